@@ -0,0 +1,119 @@
+// Micro-benchmarks for the core hot paths. Inputs are generated from a fixed
+// seed so numbers are comparable between runs.
+//
+// Baseline numbers (median) from `cargo bench --bench core` on a single core
+// x86-64 VM, so the parallel variants only show the task overhead:
+//
+// evaluate/serial/1000    177 µs
+// evaluate/par/1000       209 µs
+// evaluate/serial/10000   2.84 ms
+// evaluate/par/10000      3.05 ms
+// dist_cache              2.02 ms
+// next_gen                2.46 ms
+// lgp_run/10              54.1 ns
+// lgp_run/100             620 ns
+// perm_crossover/pmx      2.73 µs
+// perm_crossover/order    2.60 µs
+// perm_crossover/cycle    4.77 µs
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use memega::evaluators::lgp::cfg::LgpEvaluatorCfg;
+use memega::evaluators::lgp::vm::cfg::LgpVmCfg;
+use memega::evaluators::lgp::vm::lgpvm::LgpVm;
+use memega::evolve::cfg::EvolveCfg;
+use memega::gen::species::DistCache;
+use memega::gen::unevaluated::UnevaluatedGen;
+use memega::ops::crossover::{crossover_cycle, crossover_order_single, crossover_pmx_single};
+use memega::util::bench_utils::{bench_rng, rand_lgp_ops, rand_perm, CountEvaluator};
+
+fn initial(pop: usize, cfg: &EvolveCfg) -> UnevaluatedGen<usize> {
+    UnevaluatedGen::initial::<CountEvaluator>(rand_perm(pop, &mut bench_rng()), cfg)
+}
+
+fn evaluate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("evaluate");
+    for pop in [1000, 10000] {
+        for par in [false, true] {
+            let cfg = EvolveCfg::new(pop).set_par_fitness(par);
+            let gen = initial(pop, &cfg);
+            let name = if par { "par" } else { "serial" };
+            group.bench_with_input(BenchmarkId::new(name, pop), &cfg, |b, cfg| {
+                b.iter_batched(
+                    || gen.clone(),
+                    |mut gen| gen.evaluate(&[()], cfg, &CountEvaluator).unwrap(),
+                    BatchSize::LargeInput,
+                );
+            });
+        }
+    }
+    group.finish();
+}
+
+fn dist_cache(c: &mut Criterion) {
+    const POP: usize = 500;
+    let cfg = EvolveCfg::new(POP);
+    let gen = initial(POP, &cfg);
+    c.bench_function("dist_cache", |b| {
+        b.iter(|| {
+            let mut dists = DistCache::new();
+            dists.ensure(&gen.mems, false, cfg.par_chunk_size(POP * POP), &CountEvaluator).unwrap();
+            dists
+        });
+    });
+}
+
+fn next_gen(c: &mut Criterion) {
+    const POP: usize = 1000;
+    let cfg = EvolveCfg::new(POP);
+    let evaluated = initial(POP, &cfg).evaluate(&[()], &cfg, &CountEvaluator).unwrap();
+    c.bench_function("next_gen", |b| {
+        b.iter(|| evaluated.next_gen(&mut || 0, false, 0, &[], &cfg, &CountEvaluator).unwrap());
+    });
+}
+
+fn lgp_run(c: &mut Criterion) {
+    let (lgpcfg, layout) = LgpEvaluatorCfg::for_problem(2, 1);
+    let regs = layout.regs();
+    let mut r = bench_rng();
+    let mut group = c.benchmark_group("lgp_run");
+    for len in [10, 100] {
+        let ops = rand_lgp_ops(&lgpcfg, len, &mut r);
+        let vmcfg = LgpVmCfg::new()
+            .set_code(&ops)
+            .set_regs(&regs)
+            .set_constants(&layout.constants(&[1.0, 2.0]));
+        let mut vm = LgpVm::borrowed(&vmcfg);
+        group.bench_function(BenchmarkId::from_parameter(len), |b| {
+            b.iter(|| {
+                vm.reset(&regs);
+                vm.run();
+                vm.mem(0)
+            });
+        });
+    }
+    group.finish();
+}
+
+fn perm_crossover(c: &mut Criterion) {
+    const LEN: usize = 100;
+    let mut r = bench_rng();
+    let s1 = rand_perm(LEN, &mut r);
+    let s2 = rand_perm(LEN, &mut r);
+    let mut group = c.benchmark_group("perm_crossover");
+    group.bench_function("pmx", |b| b.iter(|| crossover_pmx_single(&s1, &s2, 25, 75)));
+    group.bench_function("order", |b| b.iter(|| crossover_order_single(&s1, &s2, 25, 75)));
+    group.bench_function("cycle", |b| {
+        b.iter_batched(
+            || (s1.clone(), s2.clone()),
+            |(mut s1, mut s2)| {
+                crossover_cycle(&mut s1, &mut s2);
+                (s1, s2)
+            },
+            BatchSize::SmallInput,
+        );
+    });
+    group.finish();
+}
+
+criterion_group!(benches, evaluate, dist_cache, next_gen, lgp_run, perm_crossover);
+criterion_main!(benches);
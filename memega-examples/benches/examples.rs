@@ -1,5 +1,10 @@
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use memega::evaluators::lgp::cfg::LgpEvaluatorCfg;
+use memega::evaluators::lgp::eval::LgpState;
+use memega::evaluators::lgp::vm::lgpvm::LgpVm;
 use memega::evolve::cfg::{Crossover, EvolveCfg, Mutation, Niching, Selection, Species, Survival};
+use memega::gen::unevaluated::UnevaluatedGen;
+use memega::util::bench_utils::CountEvaluator;
 use memega_examples::examples::ackley::ackley_evolver;
 use memega_examples::examples::griewank::griewank_evolver;
 use memega_examples::examples::knapsack::knapsack_evolver;
@@ -53,5 +58,77 @@ fn target_string(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, rastrigin, griewank, ackley, knapsack, target_string);
+fn par_fitness(c: &mut Criterion) {
+    const POP: usize = 100000;
+    let mut group = c.benchmark_group("par_fitness");
+    for chunk_size in [Some(1), None] {
+        let cfg = EvolveCfg::new(POP).set_par_fitness(true).set_fitness_chunk_size(chunk_size);
+        let gen = UnevaluatedGen::initial::<CountEvaluator>((0..POP).collect(), &cfg);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{chunk_size:?}")),
+            &cfg,
+            |b, cfg| {
+                b.iter(|| gen.clone().evaluate(&[()], cfg, &CountEvaluator).unwrap());
+            },
+        );
+    }
+    group.finish();
+}
+
+// Running each program over many data points, either building a new vm per
+// point or building one vm per program and resetting it.
+fn lgp_vm(c: &mut Criterion) {
+    const POP: usize = 100;
+    const POINTS: usize = 100;
+    let (lgpcfg, layout) = LgpEvaluatorCfg::for_problem(2, 1);
+    let pop = (0..POP)
+        .map(|_| {
+            let ops = (0..20).map(|_| lgpcfg.rand_op()).collect();
+            LgpState::new(ops, lgpcfg.num_reg(), lgpcfg.num_const(), lgpcfg.output_regs())
+        })
+        .collect::<Vec<_>>();
+    let inputs = |i: usize| [i as f64, 1.0];
+    let regs = layout.regs();
+    let mut group = c.benchmark_group("lgp_vm");
+    group.bench_function("new", |b| {
+        b.iter(|| {
+            let mut total = 0.0;
+            for s in &pop {
+                for i in 0..POINTS {
+                    let mut vm = LgpVm::new(&s.lgpvmcfg(&regs, &inputs(i)));
+                    vm.run();
+                    total += vm.mem(0);
+                }
+            }
+            total
+        });
+    });
+    group.bench_function("reset", |b| {
+        b.iter(|| {
+            let mut total = 0.0;
+            for s in &pop {
+                let cfg = s.lgpvmcfg(&regs, &inputs(0));
+                let mut vm = LgpVm::borrowed(&cfg);
+                for i in 0..POINTS {
+                    vm.reset_with_constants(&regs, &inputs(i));
+                    vm.run();
+                    total += vm.mem(0);
+                }
+            }
+            total
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    rastrigin,
+    griewank,
+    ackley,
+    knapsack,
+    target_string,
+    par_fitness,
+    lgp_vm
+);
 criterion_main!(benches);
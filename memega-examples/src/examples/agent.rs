@@ -0,0 +1,350 @@
+use eyre::Result;
+use memega::evaluators::lgp::classify::{interpret_outputs, OutputInterp};
+use memega::evaluators::lgp::vm::lgpvm::LgpVm;
+use memega::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Rounds in each episode.
+pub const ROUNDS: usize = 50;
+/// Probability the opponent plays the opposite of what tit-for-tat says.
+pub const NOISE: f64 = 0.1;
+/// Episodes played for each data seed.
+pub const EPISODES: usize = 4;
+/// Largest payoff for a single round, used to scale fitness to [0, 1].
+pub const MAX_PAYOFF: f64 = 5.0;
+
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Move {
+    Cooperate,
+    Defect,
+}
+
+impl Move {
+    pub fn flip(self) -> Self {
+        match self {
+            Move::Cooperate => Move::Defect,
+            Move::Defect => Move::Cooperate,
+        }
+    }
+
+    // Observation encoding: 1 to cooperate, -1 to defect, 0 if there is no
+    // move yet.
+    fn encode(v: Option<Self>) -> f64 {
+        match v {
+            Some(Move::Cooperate) => 1.0,
+            Some(Move::Defect) => -1.0,
+            None => 0.0,
+        }
+    }
+}
+
+/// Payoff to the player making |me| against |opp|, for the standard
+/// prisoner's dilemma values.
+#[must_use]
+pub fn payoff(me: Move, opp: Move) -> f64 {
+    match (me, opp) {
+        (Move::Cooperate, Move::Cooperate) => 3.0,
+        (Move::Cooperate, Move::Defect) => 0.0,
+        (Move::Defect, Move::Cooperate) => MAX_PAYOFF,
+        (Move::Defect, Move::Defect) => 1.0,
+    }
+}
+
+/// One episode of the iterated prisoner's dilemma against a noisy tit-for-tat
+/// opponent. The opponent cooperates first, then copies the agent's previous
+/// move, except it plays the opposite with probability `NOISE`. The noise
+/// only depends on the seed, so episodes are deterministic given the seed and
+/// the agent's moves.
+#[must_use]
+#[derive(Debug, Clone)]
+pub struct Game {
+    r: StdRng,
+    round: usize,
+    my_last: Option<Move>,
+    opp_last: Option<Move>,
+    score: f64,
+}
+
+impl Game {
+    pub fn new(seed: u64) -> Self {
+        Self { r: StdRng::seed_from_u64(seed), round: 0, my_last: None, opp_last: None, score: 0.0 }
+    }
+
+    /// What the agent sees before moving: its own and the opponent's last
+    /// moves, and the fraction of rounds left.
+    #[must_use]
+    pub fn observation(&self) -> [f64; 3] {
+        [
+            Move::encode(self.my_last),
+            Move::encode(self.opp_last),
+            (ROUNDS - self.round) as f64 / ROUNDS as f64,
+        ]
+    }
+
+    /// Plays a round and returns the agent's payoff for it.
+    pub fn step(&mut self, me: Move) -> f64 {
+        assert!(!self.done(), "episode is over");
+        let tft = self.my_last.unwrap_or(Move::Cooperate);
+        let opp = if self.r.gen::<f64>() < NOISE { tft.flip() } else { tft };
+        let v = payoff(me, opp);
+        self.round += 1;
+        self.my_last = Some(me);
+        self.opp_last = Some(opp);
+        self.score += v;
+        v
+    }
+
+    #[must_use]
+    pub fn done(&self) -> bool {
+        self.round >= ROUNDS
+    }
+
+    /// Mean payoff per round so far.
+    #[must_use]
+    pub fn score(&self) -> f64 {
+        if self.round == 0 {
+            0.0
+        } else {
+            self.score / self.round as f64
+        }
+    }
+}
+
+/// Plays a whole episode from |seed| with |policy| and returns the mean payoff
+/// per round, scaled to [0, 1].
+#[must_use]
+pub fn play(seed: u64, mut policy: impl FnMut(&[f64; 3]) -> Move) -> f64 {
+    let mut game = Game::new(seed);
+    while !game.done() {
+        let me = policy(&game.observation());
+        let _ = game.step(me);
+    }
+    game.score() / MAX_PAYOFF
+}
+
+/// Seeds of the episodes played for a data seed.
+pub fn episode_seeds(seed: u64) -> impl Iterator<Item = u64> {
+    (0..EPISODES as u64).map(move |i| seed * EPISODES as u64 + i)
+}
+
+/// Registers: output r0, which chooses to cooperate if positive, then
+/// scratch, then the observation as constants.
+pub fn agent_layout() -> LgpRegisterLayout {
+    LgpRegisterLayout::new(3, 1)
+}
+
+/// Mean scaled payoff of |s| over the episodes for |seed|.
+#[must_use]
+pub fn agent_fitness(s: &LgpState, seed: u64) -> f64 {
+    let layout = agent_layout();
+    let regs = layout.regs();
+    let cfg = s.lgpvmcfg(&regs, &[0.0; 3]);
+    let mut exec = LgpVm::borrowed(&cfg);
+    let total = episode_seeds(seed)
+        .map(|seed| {
+            play(seed, |obs| {
+                exec.reset_with_constants(&regs, obs);
+                exec.run();
+                match interpret_outputs(&exec, &OutputInterp::Sign).class {
+                    1 => Move::Cooperate,
+                    _ => Move::Defect,
+                }
+            })
+        })
+        .sum::<f64>();
+    total / EPISODES as f64
+}
+
+/// Mean scaled payoff of picking moves uniformly at random over the episodes
+/// for |seeds|, as a baseline.
+#[must_use]
+pub fn random_fitness(seeds: &[u64], rng_seed: u64) -> f64 {
+    let mut r = StdRng::seed_from_u64(rng_seed);
+    let total = seeds
+        .iter()
+        .flat_map(|&seed| episode_seeds(seed))
+        .map(|seed| play(seed, |_| if r.gen::<bool>() { Move::Cooperate } else { Move::Defect }))
+        .sum::<f64>();
+    total / (seeds.len() * EPISODES) as f64
+}
+
+/// Episode seeds for each split. Training seeds change every generation so
+/// agents can't overfit to particular noise, while validation and test seeds
+/// are fixed and disjoint from them.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AgentDataSampler {
+    seeds_per_gen: usize,
+}
+
+impl AgentDataSampler {
+    const VALID_BASE: u64 = 0;
+    const TEST_BASE: u64 = 1 << 20;
+    const TRAIN_BASE: u64 = 1 << 32;
+
+    pub fn new(seeds_per_gen: usize) -> Self {
+        Self { seeds_per_gen }
+    }
+
+    fn seeds(self, base: u64, start: usize) -> Vec<u64> {
+        (start..start + self.seeds_per_gen).map(|i| base + i as u64).collect()
+    }
+}
+
+impl DataSampler<u64> for AgentDataSampler {
+    fn train(&self, gen: usize) -> Vec<u64> {
+        self.seeds(Self::TRAIN_BASE, gen * self.seeds_per_gen)
+    }
+
+    fn valid(&self, _gen: usize) -> Vec<u64> {
+        self.seeds(Self::VALID_BASE, 0)
+    }
+
+    fn test(&self, _gen: usize) -> Vec<u64> {
+        self.seeds(Self::TEST_BASE, 0)
+    }
+
+    fn train_epoch(&self, gen: usize) -> DataEpoch {
+        gen as DataEpoch
+    }
+}
+
+pub fn agent_evolver(
+    lgpcfg: LgpEvaluatorCfg,
+    cfg: EvolveCfg,
+) -> Evolver<impl Evaluator<State = LgpState, Data = u64>> {
+    lgp_fitness_evolver(
+        lgpcfg.set_layout(&agent_layout()),
+        cfg,
+        |s: &'_ LgpState, seed: &'_ u64| -> Result<f64> { Ok(agent_fitness(s, *seed)) },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use memega::evaluators::lgp::vm::asm::lgp_asm;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    // Moves the opponent makes in an episode from |seed| against |policy|.
+    fn opponent_moves(seed: u64, mut policy: impl FnMut(usize) -> Move) -> Vec<(Move, Move)> {
+        let mut game = Game::new(seed);
+        let mut moves = vec![];
+        for round in 0..ROUNDS {
+            let me = policy(round);
+            let _ = game.step(me);
+            moves.push((me, game.opp_last.unwrap()));
+        }
+        moves
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn payoffs() {
+        assert_eq!(payoff(Move::Cooperate, Move::Cooperate), 3.0);
+        assert_eq!(payoff(Move::Cooperate, Move::Defect), 0.0);
+        assert_eq!(payoff(Move::Defect, Move::Cooperate), 5.0);
+        assert_eq!(payoff(Move::Defect, Move::Defect), 1.0);
+    }
+
+    #[test]
+    fn opponent_is_noisy_tit_for_tat() {
+        // Alternate moves so tit-for-tat is different every round.
+        let alternate = |round: usize| [Move::Cooperate, Move::Defect][round % 2];
+        let mut flips = 0;
+        for seed in 0..100 {
+            let moves = opponent_moves(seed, alternate);
+            let mut prev = Move::Cooperate;
+            for (me, opp) in moves {
+                if opp != prev {
+                    flips += 1;
+                }
+                prev = me;
+            }
+        }
+        let freq = flips as f64 / (100 * ROUNDS) as f64;
+        assert!((freq - NOISE).abs() < 0.02, "{freq}");
+    }
+
+    #[test]
+    fn deterministic() {
+        let always = |_| Move::Cooperate;
+        assert_eq!(opponent_moves(7, always), opponent_moves(7, always));
+        assert!((0..10).any(|seed| opponent_moves(seed, always) != opponent_moves(7, always)));
+        // Noise only depends on the seed, so it hits the same rounds whatever
+        // the agent does.
+        let noisy = |moves: Vec<(Move, Move)>| {
+            let mut prev = Move::Cooperate;
+            let mut rounds = vec![];
+            for (round, (me, opp)) in moves.into_iter().enumerate() {
+                if opp != prev {
+                    rounds.push(round);
+                }
+                prev = me;
+            }
+            rounds
+        };
+        assert_eq!(noisy(opponent_moves(3, always)), noisy(opponent_moves(3, |_| Move::Defect)));
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn observations_and_score() {
+        let mut game = Game::new(0);
+        assert_eq!(game.observation(), [0.0, 0.0, 1.0]);
+        assert_eq!(game.score(), 0.0);
+        let v = game.step(Move::Defect);
+        let opp = game.opp_last.unwrap();
+        assert_eq!(v, payoff(Move::Defect, opp));
+        assert_eq!(game.observation(), [-1.0, Move::encode(Some(opp)), 0.98]);
+        assert_eq!(game.score(), v);
+        while !game.done() {
+            let _ = game.step(Move::Cooperate);
+        }
+        assert_eq!(game.observation()[2], 0.0);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn fixed_policies() -> Result<()> {
+        // Always cooperating scores 3 unless the noise makes the opponent
+        // defect, and always defecting scores 1 unless it makes it cooperate.
+        let seeds = (0..50).collect::<Vec<_>>();
+        let mean = |policy: fn(&[f64; 3]) -> Move| {
+            seeds.iter().map(|&seed| play(seed, policy)).sum::<f64>() / seeds.len() as f64
+        };
+        let cooperate = mean(|_| Move::Cooperate) * MAX_PAYOFF;
+        let defect = mean(|_| Move::Defect) * MAX_PAYOFF;
+        assert!((cooperate - 3.0 * (1.0 - NOISE)).abs() < 0.1, "{cooperate}");
+        assert!((defect - (1.0 + 4.0 * NOISE)).abs() < 0.1, "{defect}");
+        // Random moves average all four payoffs.
+        let random = random_fitness(&seeds, 0) * MAX_PAYOFF;
+        assert!((random - 2.25).abs() < 0.1, "{random}");
+
+        // A program which always loads a positive value always cooperates.
+        let s = LgpState::new(lgp_asm("load r0, 1\n")?, 8, 3, &[0]);
+        let expected = episode_seeds(5).map(|seed| play(seed, |_| Move::Cooperate)).sum::<f64>();
+        assert_eq!(agent_fitness(&s, 5), expected / EPISODES as f64);
+        Ok(())
+    }
+
+    #[test]
+    fn sampler_seeds() {
+        let sampler = AgentDataSampler::new(3);
+        assert_eq!(sampler.train(0).len(), 3);
+        assert_ne!(sampler.train(0), sampler.train(1));
+        assert_eq!(sampler.valid(0), sampler.valid(5));
+        let mut all = [sampler.train(0), sampler.train(1), sampler.valid(0), sampler.test(0)]
+            .concat()
+            .into_iter()
+            .flat_map(episode_seeds)
+            .collect::<Vec<_>>();
+        let len = all.len();
+        all.sort_unstable();
+        all.dedup();
+        assert_eq!(all.len(), len);
+    }
+}
@@ -0,0 +1,190 @@
+use eyre::Result;
+use memega::evaluators::lgp::classify::{
+    accuracy, interpret_outputs, smooth_score, OutputInterp, Prediction,
+};
+use memega::evaluators::lgp::vm::lgpvm::LgpVm;
+use memega::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Synthetic classification problems on points in [-1, 1]^2.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ClassifyProblem {
+    // Whether the point is inside a circle around the origin.
+    Circle,
+    // Which third of the plane around the origin the point is in.
+    Sectors,
+}
+
+impl ClassifyProblem {
+    #[must_use]
+    pub fn num_classes(self) -> usize {
+        match self {
+            ClassifyProblem::Circle => 2,
+            ClassifyProblem::Sectors => 3,
+        }
+    }
+
+    #[must_use]
+    pub fn class(self, x: f64, y: f64) -> usize {
+        const RADIUS: f64 = 0.7;
+        match self {
+            ClassifyProblem::Circle => usize::from(x.hypot(y) < RADIUS),
+            ClassifyProblem::Sectors => {
+                let angle = y.atan2(x) + std::f64::consts::PI;
+                ((angle / std::f64::consts::TAU * 3.0) as usize).min(2)
+            }
+        }
+    }
+
+    pub fn layout(self) -> LgpRegisterLayout {
+        let outputs = match self {
+            ClassifyProblem::Circle => 1,
+            ClassifyProblem::Sectors => 3,
+        };
+        LgpRegisterLayout::new(2, outputs)
+    }
+
+    pub fn interp(self) -> OutputInterp {
+        match self {
+            ClassifyProblem::Circle => OutputInterp::Sign,
+            ClassifyProblem::Sectors => OutputInterp::ArgmaxOf(self.layout().output_indices),
+        }
+    }
+}
+
+/// A point and its class.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub struct Sample {
+    pub x: f64,
+    pub y: f64,
+    pub class: usize,
+}
+
+/// Predictions of |s| for each of |samples|, reusing one vm.
+#[must_use]
+pub fn classify_predictions(
+    s: &LgpState,
+    problem: ClassifyProblem,
+    samples: &[Sample],
+) -> Vec<Prediction> {
+    let layout = problem.layout();
+    let interp = problem.interp();
+    let regs = layout.regs();
+    let cfg = s.lgpvmcfg(&regs, &[0.0, 0.0]);
+    let mut exec = LgpVm::borrowed(&cfg);
+    samples
+        .iter()
+        .map(|v| {
+            exec.reset_with_constants(&regs, &[v.x, v.y]);
+            exec.run();
+            interpret_outputs(&exec, &interp)
+        })
+        .collect()
+}
+
+/// Smooth score of |s| on |samples|, in [0, 1].
+#[must_use]
+pub fn classify_fitness(s: &LgpState, problem: ClassifyProblem, samples: &[Sample]) -> f64 {
+    let targets = samples.iter().map(|v| v.class).collect::<Vec<_>>();
+    smooth_score(&classify_predictions(s, problem, samples), &targets)
+}
+
+/// Fraction of |samples| which |s| classifies correctly.
+#[must_use]
+pub fn classify_accuracy(s: &LgpState, problem: ClassifyProblem, samples: &[Sample]) -> f64 {
+    let targets = samples.iter().map(|v| v.class).collect::<Vec<_>>();
+    accuracy(&classify_predictions(s, problem, samples), &targets)
+}
+
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct ClassifyDataSampler {
+    train: Vec<Sample>,
+    valid: Vec<Sample>,
+    test: Vec<Sample>,
+}
+
+impl ClassifyDataSampler {
+    /// Random samples of |problem| generated from |seed|.
+    pub fn new(problem: ClassifyProblem, seed: u64) -> Self {
+        const TRAIN: usize = 200;
+        const VALID: usize = 100;
+        const TEST: usize = 100;
+        let mut r = StdRng::seed_from_u64(seed);
+        let mut gen = |n: usize| {
+            (0..n)
+                .map(|_| {
+                    let (x, y) = (r.gen_range(-1.0..=1.0), r.gen_range(-1.0..=1.0));
+                    Sample { x, y, class: problem.class(x, y) }
+                })
+                .collect::<Vec<_>>()
+        };
+        Self { train: gen(TRAIN), valid: gen(VALID), test: gen(TEST) }
+    }
+}
+
+// Each call returns all the samples as a single batch, so fitness functions
+// can reuse one vm for all of them.
+impl DataSampler<Vec<Sample>> for ClassifyDataSampler {
+    fn train(&self, _gen: usize) -> Vec<Vec<Sample>> {
+        vec![self.train.clone()]
+    }
+
+    fn valid(&self, _gen: usize) -> Vec<Vec<Sample>> {
+        vec![self.valid.clone()]
+    }
+
+    fn test(&self, _gen: usize) -> Vec<Vec<Sample>> {
+        vec![self.test.clone()]
+    }
+}
+
+/// Fitness is the smooth score rather than accuracy, so it keeps rising as
+/// the correct class gets more confident.
+pub fn classify_evolver(
+    problem: ClassifyProblem,
+    lgpcfg: LgpEvaluatorCfg,
+    cfg: EvolveCfg,
+) -> Evolver<impl Evaluator<State = LgpState, Data = Vec<Sample>>> {
+    lgp_fitness_evolver(
+        lgpcfg.set_layout(&problem.layout()),
+        cfg,
+        move |s: &'_ LgpState, samples: &'_ Vec<Sample>| -> Result<f64> {
+            Ok(classify_fitness(s, problem, samples))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use memega::evaluators::lgp::vm::asm::lgp_asm;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn classes() {
+        assert_eq!(ClassifyProblem::Circle.class(0.1, 0.1), 1);
+        assert_eq!(ClassifyProblem::Circle.class(0.9, 0.9), 0);
+        let sectors = [(-1.0, -0.1), (0.5, -0.5), (0.0, 1.0)]
+            .map(|(x, y)| ClassifyProblem::Sectors.class(x, y));
+        assert_eq!(sectors, [0, 1, 2]);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn fitness_of_hand_written_program() -> Result<()> {
+        // Registers: r0 output, r1-r6 scratch, r7 = x, r8 = y. Computes
+        // 0.49 - x^2 - y^2, which is positive inside the circle.
+        let code =
+            "mul r1, r7, r7\nmul r2, r8, r8\nload r0, 0.49\nsub r0, r0, r1\nsub r0, r0, r2\n";
+        let s = LgpState::new(lgp_asm(code)?, 7, 2, &[0]);
+        let samples = ClassifyDataSampler::new(ClassifyProblem::Circle, 0).train;
+        assert_eq!(classify_accuracy(&s, ClassifyProblem::Circle, &samples), 1.0);
+        assert!(classify_fitness(&s, ClassifyProblem::Circle, &samples) > 0.5);
+        Ok(())
+    }
+}
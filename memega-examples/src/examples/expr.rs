@@ -1,43 +1,131 @@
+use std::fmt;
+use std::str::FromStr;
+
 use eyre::{eyre, Result};
-use memega::eval::Evaluator;
-use memega::evaluators::lgp::builder::lgp_fitness_evolver;
-use memega::evaluators::lgp::cfg::LgpEvaluatorCfg;
-use memega::evaluators::lgp::eval::LgpState;
+use log::warn;
+use memega::evaluators::lgp::ensemble::LgpEnsemble;
+use memega::evaluators::lgp::vm::cfg::PowPolicy;
 use memega::evaluators::lgp::vm::lgpvm::LgpVm;
-use memega::evolve::cfg::EvolveCfg;
-use memega::evolve::evolver::Evolver;
-use memega::train::sampler::DataSampler;
+use memega::prelude::*;
 use num_traits::ToPrimitive;
 use savage_core::expression::{Expression, Rational};
 
-const NUM_REG: usize = 2;
-const NUM_CONST: usize = 4;
-const OUTPUT_REG: u8 = 0;
+// Inputs are 0, -1, 1 and x. The constants are useful for building expressions.
+const NUM_INPUTS: usize = 4;
 
-pub fn expr_fitness(s: &LgpState, x: f64, target: &str) -> Result<f64> {
+#[must_use]
+pub fn expr_inputs(x: f64) -> [f64; NUM_INPUTS] {
+    [0.0, -1.0, 1.0, x]
+}
+
+pub fn expr_layout() -> LgpRegisterLayout {
+    LgpRegisterLayout::new(NUM_INPUTS, 1)
+}
+
+/// Value of the target expression at |x|.
+pub fn expr_target(x: f64, target: &str) -> Result<f64> {
     let expr: Expression = target.parse().map_err(|_| eyre!("failed to parse expression"))?;
 
     let mut expr_ctx = std::collections::HashMap::default();
     let x_expr = Expression::from(Rational::from_float(x).ok_or_else(|| eyre!("invalid x"))?);
     expr_ctx.insert("x".to_string(), x_expr);
     let ans = expr.evaluate(expr_ctx).map_err(|_| eyre!("failed to evaluate expression"))?;
-    let ans = match ans {
+    Ok(match ans {
         Expression::Integer(integer) => integer.to_f64().ok_or_else(|| eyre!("invalid y"))?,
         Expression::Rational(ratio, _) => ratio.to_f64().ok_or_else(|| eyre!("invalid y"))?,
-        _ => panic!("should be number output: {ans}"),
-    };
+        _ => return Err(eyre!("non-numeric value {ans}")),
+    })
+}
+
+/// Mean fitness of |s| over the points |xs|. Builds a single vm and resets it
+/// for each point, which avoids allocating per point.
+pub fn expr_fitness(
+    s: &LgpState,
+    layout: &LgpRegisterLayout,
+    xs: &[f64],
+    target: &str,
+) -> Result<f64> {
+    let regs = layout.regs();
+    let out = layout.output_indices[0];
+    // Sign preserving pow tends to work better for symbolic regression.
+    let cfg = s.lgpvmcfg(&regs, &expr_inputs(0.0)).set_pow_policy(PowPolicy::SignPreserving);
+    let mut exec = LgpVm::borrowed(&cfg);
+    let mut total = 0.0;
+    for &x in xs {
+        let ans = expr_target(x, target)?;
+        exec.reset_with_constants(&regs, &expr_inputs(x));
+        exec.run();
+        total += 1.0 / (1.0 + (ans - exec.mem(out)).abs());
+    }
+    Ok(total / xs.len() as f64)
+}
+
+/// Ensemble of up to |k| behaviourally distinct programs from |candidates|,
+/// which are paired with their fitness.
+pub fn expr_ensemble(candidates: &[(LgpState, f64)], k: usize, xs: &[f64]) -> LgpEnsemble {
+    const MIN_DIST: f64 = 1e-3;
+    let probes = xs.iter().map(|&x| expr_inputs(x).to_vec()).collect::<Vec<_>>();
+    LgpEnsemble::select_diverse(candidates, expr_layout(), k, MIN_DIST, &probes)
+        .set_pow_policy(PowPolicy::SignPreserving)
+}
+
+/// Mean fitness of the ensemble's predictions at |xs|.
+pub fn expr_ensemble_fitness(ensemble: &LgpEnsemble, xs: &[f64], target: &str) -> Result<f64> {
+    let mut total = 0.0;
+    for &x in xs {
+        let ans = expr_target(x, target)?;
+        total += 1.0 / (1.0 + (ans - ensemble.predict(&expr_inputs(x))).abs());
+    }
+    Ok(total / xs.len() as f64)
+}
+
+/// Range of x the target expression is sampled over, written as `lo:hi`.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub struct ExprRange {
+    pub lo: f64,
+    pub hi: f64,
+}
+
+impl ExprRange {
+    pub fn new(lo: f64, hi: f64) -> Result<Self> {
+        if !lo.is_finite() || !hi.is_finite() || lo >= hi {
+            return Err(eyre!("invalid range {lo}:{hi}, need finite lo < hi"));
+        }
+        Ok(Self { lo, hi })
+    }
+
+    // |n| evenly spaced points starting at lo and stopping short of hi.
+    fn grid(&self, n: usize) -> Vec<f64> {
+        (0..n).map(|x| x as f64 / n as f64 * (self.hi - self.lo) + self.lo).collect()
+    }
+}
+
+impl Default for ExprRange {
+    fn default() -> Self {
+        Self { lo: -100.0, hi: 100.0 }
+    }
+}
 
-    let regs: [f64; NUM_REG] = [0.0, 0.0];
-    let constants: [f64; NUM_CONST] = [0.0, -1.0, 1.0, x];
-    let cfg = s.lgpvmcfg(&regs, &constants);
-    let mut exec = LgpVm::new(&cfg);
-    exec.run();
+impl fmt::Display for ExprRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.lo, self.hi)
+    }
+}
 
-    Ok(1.0 / (1.0 + (ans - exec.mem(OUTPUT_REG)).abs()))
+impl FromStr for ExprRange {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (lo, hi) = s.split_once(':').ok_or_else(|| eyre!("expected lo:hi, got {s}"))?;
+        Self::new(lo.trim().parse()?, hi.trim().parse()?)
+    }
 }
 
 #[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct ExprDataSampler {
+    range: ExprRange,
     train: Vec<f64>,
     valid: Vec<f64>,
 }
@@ -49,28 +137,92 @@ impl Default for ExprDataSampler {
 }
 
 impl ExprDataSampler {
+    // Strange numbers to give more diversity in decimal representation.
+    pub const DEFAULT_POINTS: usize = 99;
+
     pub fn new() -> Self {
-        const START: f64 = -100.0;
-        const END: f64 = 100.0;
-        // Strange numbers to give more diversity in decimal representation.
-        const TRAIN: usize = 99;
-        const VALID: usize = 9;
-        let train = (0..TRAIN).map(|x| x as f64 / TRAIN as f64 * (END - START) + START).collect();
-        let valid = (0..VALID).map(|x| x as f64 / VALID as f64 * (END - START) + START).collect();
-        Self { train, valid }
+        Self::from_range(ExprRange::default(), Self::DEFAULT_POINTS)
+    }
+
+    /// Samples |points| training points from |range|, and a validation point
+    /// for every 11 of those. The range isn't checked, see
+    /// `expr_validate_range`.
+    pub fn from_range(range: ExprRange, points: usize) -> Self {
+        let points = points.max(1);
+        Self { range, train: range.grid(points), valid: range.grid((points / 11).max(1)) }
     }
+
+    /// Samples the range |target| can be evaluated over. See
+    /// `expr_validate_range`.
+    pub fn validated(target: &str, range: ExprRange, points: usize) -> Result<Self> {
+        let range = expr_validate_range(|x| expr_target(x, target), range, points)?;
+        Ok(Self::from_range(range, points))
+    }
+
+    pub fn range(&self) -> ExprRange {
+        self.range
+    }
+
+    // Every point sampled, and hi, in order.
+    fn probes(&self) -> Vec<f64> {
+        let mut xs = [&self.train[..], &self.valid[..], &[self.range.hi]].concat();
+        xs.sort_by(f64::total_cmp);
+        xs.dedup();
+        xs
+    }
+}
+
+/// Checks |f| has a finite value at every point `ExprDataSampler` would
+/// sample from |range| with |points| points, and at hi. If it doesn't, the
+/// range is shrunk, with a warning, to the longest run of points where it
+/// does. Fails if there's no such run of at least two points, or if the
+/// shrunk range still has failing points, e.g. because the run crosses a
+/// singularity the coarser grid missed.
+pub fn expr_validate_range(
+    f: impl Fn(f64) -> Result<f64>,
+    range: ExprRange,
+    points: usize,
+) -> Result<ExprRange> {
+    let probes = ExprDataSampler::from_range(range, points).probes();
+    let ok = probes.iter().map(|&x| f(x).is_ok_and(f64::is_finite)).collect::<Vec<_>>();
+    let Some(first) = ok.iter().position(|&v| !v).map(|i| probes[i]) else {
+        return Ok(range);
+    };
+    // Longest run of points where |f| is fine, first on ties.
+    let (mut best, mut st) = ((0, 0), 0);
+    for (i, &v) in ok.iter().enumerate() {
+        if !v {
+            st = i + 1;
+        } else if i + 1 - st > best.1 - best.0 {
+            best = (st, i + 1);
+        }
+    }
+    if best.1 - best.0 < 2 {
+        return Err(eyre!("target fails at x = {first} and has no valid subrange in {range}"));
+    }
+    let shrunk = ExprRange::new(probes[best.0], probes[best.1 - 1])?;
+    let probes = ExprDataSampler::from_range(shrunk, points).probes();
+    if let Some(&x) = probes.iter().find(|&&x| !f(x).is_ok_and(f64::is_finite)) {
+        return Err(eyre!(
+            "target fails at x = {first} in {range}, and at x = {x} in the shrunk range {shrunk}"
+        ));
+    }
+    warn!("target fails at x = {first} in {range}, using {shrunk} instead");
+    Ok(shrunk)
 }
 
-impl DataSampler<f64> for ExprDataSampler {
-    fn train(&self, _gen: usize) -> Vec<f64> {
-        self.train.clone()
+// Each call returns all the points as a single batch, so fitness functions
+// can reuse one vm for all of them.
+impl DataSampler<Vec<f64>> for ExprDataSampler {
+    fn train(&self, _gen: usize) -> Vec<Vec<f64>> {
+        vec![self.train.clone()]
     }
 
-    fn valid(&self, _gen: usize) -> Vec<f64> {
-        self.valid.clone()
+    fn valid(&self, _gen: usize) -> Vec<Vec<f64>> {
+        vec![self.valid.clone()]
     }
 
-    fn test(&self, _gen: usize) -> Vec<f64> {
+    fn test(&self, _gen: usize) -> Vec<Vec<f64>> {
         vec![]
     }
 }
@@ -79,10 +231,106 @@ pub fn expr_evolver(
     target: String,
     lgpcfg: LgpEvaluatorCfg,
     cfg: EvolveCfg,
-) -> Evolver<impl Evaluator<Data = f64>> {
+) -> Evolver<impl Evaluator<State = LgpState, Data = Vec<f64>>> {
+    let layout = expr_layout();
     lgp_fitness_evolver(
-        lgpcfg.set_num_reg(NUM_REG).set_num_const(NUM_CONST).set_output_regs(&[OUTPUT_REG]),
+        lgpcfg.set_layout(&layout),
+        cfg,
+        move |s: &'_ LgpState, xs: &'_ Vec<f64>| expr_fitness(s, &layout, xs, &target),
+    )
+}
+
+/// Like `expr_evolver`, but seeded with |seed| so runs are reproducible.
+pub fn expr_reproducible_evolver(
+    target: String,
+    lgpcfg: LgpEvaluatorCfg,
+    cfg: EvolveCfg,
+    seed: u64,
+) -> Evolver<impl Evaluator<State = LgpState, Data = Vec<f64>>> {
+    let layout = expr_layout();
+    lgp_fitness_reproducible_evolver(
+        lgpcfg.set_layout(&layout),
         cfg,
-        move |s: &'_ LgpState, data: &'_ f64| expr_fitness(s, *data, &target),
+        move |s: &'_ LgpState, xs: &'_ Vec<f64>| expr_fitness(s, &layout, xs, &target),
+        seed,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn validate(f: fn(f64) -> f64, range: &str, points: usize) -> Result<ExprRange> {
+        expr_validate_range(|x| Ok(f(x)), range.parse()?, points)
+    }
+
+    #[test]
+    fn parse_range() -> Result<()> {
+        assert_eq!("-5:5.5".parse::<ExprRange>()?, ExprRange { lo: -5.0, hi: 5.5 });
+        assert_eq!(ExprRange::default().to_string(), "-100:100");
+        for bad in ["5", "5:5", "2:1", "a:1", "inf:1"] {
+            assert!(bad.parse::<ExprRange>().is_err(), "{bad}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn validate_ln() -> Result<()> {
+        assert_eq!(validate(f64::ln, "1:10", 10)?, ExprRange { lo: 1.0, hi: 10.0 });
+        // ln(0) is -inf, so lo moves to the next point.
+        assert_eq!(validate(f64::ln, "0:10", 10)?, ExprRange { lo: 1.0, hi: 10.0 });
+        let e = validate(f64::ln, "-10:0", 10).unwrap_err();
+        assert!(e.to_string().contains("x = -10 "), "{e}");
+        Ok(())
+    }
+
+    #[test]
+    fn validate_reciprocal() -> Result<()> {
+        // 0 is a probe, and the halves either side are equally long, so the
+        // first is kept.
+        assert_eq!(validate(|x| 1.0 / x, "-10:10", 10)?, ExprRange { lo: -10.0, hi: -2.0 });
+        assert_eq!(validate(|x| 1.0 / x, "0:10", 10)?, ExprRange { lo: 1.0, hi: 10.0 });
+        // Only hi fails, which leaves a single valid point.
+        let e = validate(|x| 1.0 / x, "-1:0", 1).unwrap_err();
+        assert!(e.to_string().contains("x = 0 "), "{e}");
+        Ok(())
+    }
+
+    #[test]
+    fn validate_sqrt() -> Result<()> {
+        let f = |x: f64| (x - 5.0).sqrt();
+        assert_eq!(validate(f, "0:10", 10)?, ExprRange { lo: 5.0, hi: 10.0 });
+        assert_eq!(validate(f, "5:6", 99)?, ExprRange { lo: 5.0, hi: 6.0 });
+        let e = validate(f, "-10:4", 10).unwrap_err();
+        assert!(e.to_string().contains("x = -10 "), "{e}");
+        Ok(())
+    }
+
+    #[test]
+    fn validate_missed_singularity() {
+        // Fails between the probes of -10:10, but not of the shrunk range.
+        let f = |x: f64| {
+            if x.abs() < 1e-9 || (-7.0..-6.5).contains(&x) {
+                Err(eyre!("pole"))
+            } else {
+                Ok(x)
+            }
+        };
+        let e = expr_validate_range(f, ExprRange { lo: -10.0, hi: 10.0 }, 10).unwrap_err();
+        assert!(e.to_string().contains("shrunk range -10:-2"), "{e}");
+    }
+
+    #[test]
+    fn validated_sampler() -> Result<()> {
+        let sampler = ExprDataSampler::validated("x^2", "-1:1".parse()?, 22)?;
+        assert_eq!(sampler.range(), ExprRange { lo: -1.0, hi: 1.0 });
+        assert_eq!(sampler.train(0)[0].len(), 22);
+        assert_eq!(sampler.valid(0)[0].len(), 2);
+        let sampler = ExprDataSampler::validated("1/x", "0:10".parse()?, 10)?;
+        assert_eq!(sampler.range(), ExprRange { lo: 1.0, hi: 10.0 });
+        assert!(sampler.train(0)[0].iter().all(|&x| x >= 1.0));
+        Ok(())
+    }
+}
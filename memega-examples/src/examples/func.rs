@@ -1,51 +1,104 @@
 use derive_more::{Deref, DerefMut, Display};
 use eyre::Result;
-use memega::eval::{Evaluator, FitnessFn};
-use memega::evolve::cfg::EvolveCfg;
-use memega::evolve::evolver::Evolver;
-use memega::ops::crossover::crossover_arith;
+use memega::ops::crossover::{
+    crossover_arith_rng, crossover_differential_rng, crossover_swap_k_rng, AsF64Slice,
+};
 use memega::ops::distance::dist2;
-use memega::ops::mutation::{mutate_normal, mutate_rate, mutate_uniform};
+use memega::ops::mutation::{
+    mutate_normal_rng, mutate_rate_rng, mutate_uniform_in, mutate_uniform_in_rng, Bounds,
+};
 use memega::ops::util::rand_vec;
+use memega::prelude::*;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
 
 #[must_use]
 #[derive(Debug, Display, Deref, DerefMut, Clone, PartialEq, PartialOrd)]
 #[display(fmt = "{_0:?}")]
 pub struct FuncState(pub Vec<f64>);
 
+impl AsF64Slice for FuncState {
+    fn as_f64_slice(&self) -> &[f64] {
+        &self.0
+    }
+
+    fn as_f64_slice_mut(&mut self) -> &mut [f64] {
+        &mut self.0
+    }
+}
+
 #[must_use]
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct FuncEvaluator<F: FitnessFn<FuncState>> {
     dim: usize,
     st: f64,
     en: f64,
+    bounds: Bounds,
     f: F,
 }
 
 impl<F: FitnessFn<FuncState>> FuncEvaluator<F> {
-    fn new(dim: usize, st: f64, en: f64, f: F) -> Self {
-        Self { dim, st, en, f }
+    fn new(dim: usize, st: f64, en: f64, bounds: Bounds, f: F) -> Self {
+        Self { dim, st, en, bounds, f }
     }
 }
 
 impl<F: FitnessFn<FuncState>> Evaluator for FuncEvaluator<F> {
     type State = FuncState;
+    const NUM_CROSSOVER: usize = 3;
 
     fn crossover(&self, s1: &mut Self::State, s2: &mut Self::State, idx: usize) {
+        self.crossover_rng(s1, s2, idx, &mut rand::thread_rng());
+    }
+
+    fn mutate(&self, s: &mut Self::State, rate: f64, idx: usize) {
+        self.mutate_rng(s, rate, idx, &mut rand::thread_rng());
+    }
+
+    fn crossover_rng(
+        &self,
+        s1: &mut Self::State,
+        s2: &mut Self::State,
+        idx: usize,
+        r: &mut dyn RngCore,
+    ) {
         match idx {
             0 => {}
-            1 => crossover_arith(s1, s2),
+            1 => crossover_arith_rng(s1, s2, r),
+            2 => crossover_swap_k_rng(s1, s2, 1, r),
             _ => panic!("bug"),
         };
     }
 
-    fn mutate(&self, s: &mut Self::State, rate: f64, idx: usize) {
+    fn mutate_rng(&self, s: &mut Self::State, rate: f64, idx: usize, r: &mut dyn RngCore) {
         match idx {
-            0 => mutate_rate(s, 1.0, |v| mutate_normal(v, rate).clamp(self.st, self.en)),
+            0 => {
+                mutate_rate_rng(
+                    s,
+                    1.0,
+                    |v, r| self.bounds.apply(mutate_normal_rng(v, rate, r), self.st, self.en),
+                    r,
+                );
+            }
             _ => panic!("bug"),
         };
     }
 
+    fn differential_rng(
+        &self,
+        target: &mut Self::State,
+        donors: [&Self::State; 3],
+        f: f64,
+        cr: f64,
+        rng: &mut dyn RngCore,
+    ) -> Result<()> {
+        crossover_differential_rng(target, donors, f, cr, rng);
+        for v in target.iter_mut() {
+            *v = self.bounds.apply(*v, self.st, self.en);
+        }
+        Ok(())
+    }
+
     fn fitness(&self, s: &Self::State, data: &Self::Data) -> Result<f64> {
         (self.f)(s, data)
     }
@@ -55,6 +108,8 @@ impl<F: FitnessFn<FuncState>> Evaluator for FuncEvaluator<F> {
     }
 }
 
+/// Evolver maximising |f| over [st, en]^dim. Mutated values are reflected back
+/// into bounds, see `func_evolver_with_bounds` to clamp them instead.
 pub fn func_evolver<F: FitnessFn<FuncState>>(
     dim: usize,
     st: f64,
@@ -62,7 +117,33 @@ pub fn func_evolver<F: FitnessFn<FuncState>>(
     f: F,
     cfg: EvolveCfg,
 ) -> Evolver<impl Evaluator<Data = ()>> {
-    Evolver::new(FuncEvaluator::new(dim, st, en, f), cfg, move || {
-        FuncState(rand_vec(dim, || mutate_uniform(st, en)))
+    func_evolver_with_bounds(dim, st, en, Bounds::Reflect, f, cfg)
+}
+
+pub fn func_evolver_with_bounds<F: FitnessFn<FuncState>>(
+    dim: usize,
+    st: f64,
+    en: f64,
+    bounds: Bounds,
+    f: F,
+    cfg: EvolveCfg,
+) -> Evolver<impl Evaluator<Data = ()>> {
+    Evolver::new(FuncEvaluator::new(dim, st, en, bounds, f), cfg, move || {
+        FuncState(rand_vec(dim, || mutate_uniform_in(st, en)))
     })
 }
+
+/// Like `func_evolver`, but every random choice, including the initial
+/// population, is drawn from |seed|, so runs are reproducible.
+pub fn func_reproducible_evolver<F: FitnessFn<FuncState>>(
+    dim: usize,
+    st: f64,
+    en: f64,
+    f: F,
+    cfg: EvolveCfg,
+    seed: u64,
+) -> Evolver<impl Evaluator<Data = ()>> {
+    let mut r = StdRng::seed_from_u64(seed);
+    let rand_state = move || FuncState(rand_vec(dim, || mutate_uniform_in_rng(st, en, &mut r)));
+    Evolver::new_seeded(FuncEvaluator::new(dim, st, en, Bounds::Reflect, f), cfg, rand_state, seed)
+}
@@ -1,9 +1,11 @@
-use memega::eval::Evaluator;
-use memega::evolve::cfg::EvolveCfg;
-use memega::evolve::evolver::Evolver;
+use memega::prelude::*;
 
 use crate::examples::func::{func_evolver, FuncState};
 
+/// Minimises the Griewank function. The search space is large, so with
+/// `example_cfg(100)` in 2 dimensions convergence varies a lot between runs:
+/// about two thirds reach error below 1 (fitness above 0.5) within 1000
+/// generations.
 pub fn griewank_evolver(dim: usize, cfg: EvolveCfg) -> Evolver<impl Evaluator<Data = ()>> {
     func_evolver(
         dim,
@@ -17,7 +19,8 @@ pub fn griewank_evolver(dim: usize, cfg: EvolveCfg) -> Evolver<impl Evaluator<Da
                 mul *= (x / (i as f64 + 1.0).sqrt()).cos();
             }
             let v = 1.0 + add / 4000.0 - mul;
-            // Convert to a maximisation problem
+            // Convert to a maximisation problem with fitness in (0, 1], which
+            // is 1 at the global minimum.
             Ok(1.0 / (1.0 + v))
         },
         cfg,
@@ -2,8 +2,7 @@ use std::time::Duration;
 
 use memega::evaluators::hyper::builder::HyperBuilder;
 use memega::evaluators::hyper::eval::HyperEvaluator;
-use memega::evolve::cfg::EvolveCfg;
-use memega::evolve::evolver::Evolver;
+use memega::prelude::*;
 
 use crate::examples::ackley::ackley_evolver;
 use crate::examples::griewank::griewank_evolver;
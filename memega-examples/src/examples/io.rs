@@ -0,0 +1,147 @@
+use std::fs;
+use std::path::Path;
+
+use eyre::{eyre, Result, WrapErr};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// A 0-1 knapsack problem instance.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct KnapsackInstance {
+    pub capacity: f64,
+    pub items: Vec<(f64, f64)>, // weight and value
+    pub optimum: Option<f64>,   // Best achievable value, if known.
+}
+
+impl KnapsackInstance {
+    /// Parses the standard knapsack instance format: a line with the number
+    /// of items and the capacity, followed by a "value weight" line for each
+    /// item. An optional final line may give the optimal value.
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut lines = s.lines().map(str::trim).filter(|l| !l.is_empty());
+        let header = parse_nums(lines.next().ok_or_else(|| eyre!("missing header"))?)?;
+        let [n, capacity] = header[..] else {
+            return Err(eyre!("header must be item count and capacity: {:?}", header));
+        };
+        let mut items = Vec::new();
+        for _ in 0..n as usize {
+            let line = lines.next().ok_or_else(|| eyre!("expected {} items", n))?;
+            let [v, w] = parse_nums(line)?[..] else {
+                return Err(eyre!("item line must be value and weight: {}", line));
+            };
+            items.push((w, v));
+        }
+        let optimum = match lines.next() {
+            Some(line) => {
+                let [opt] = parse_nums(line)?[..] else {
+                    return Err(eyre!("optimum line must be a single value: {}", line));
+                };
+                Some(opt)
+            }
+            None => None,
+        };
+        if let Some(line) = lines.next() {
+            return Err(eyre!("unexpected trailing line: {}", line));
+        }
+        Ok(Self { capacity, items, optimum })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path).wrap_err_with(|| format!("reading {}", path.display()))?;
+        Self::parse(&s).wrap_err_with(|| format!("parsing {}", path.display()))
+    }
+
+    /// Upper bound on the achievable value from the fractional relaxation:
+    /// greedily take items by value density, with a fraction of the first item
+    /// which doesn't fit.
+    #[must_use]
+    pub fn upper_bound(&self) -> f64 {
+        let mut items = self.items.clone();
+        items.sort_by(|a, b| (b.1 / b.0).total_cmp(&(a.1 / a.0)));
+        let mut remaining = self.capacity;
+        let mut bound = 0.0;
+        for (w, v) in items {
+            if w <= remaining {
+                remaining -= w;
+                bound += v;
+            } else {
+                bound += v * remaining / w;
+                break;
+            }
+        }
+        bound
+    }
+
+    /// Randomly generates an instance, deterministically for a given |seed|.
+    pub fn generate(num_items: usize, capacity: f64, seed: u64) -> Self {
+        let mut r = StdRng::seed_from_u64(seed);
+        let items = (0..num_items)
+            .map(|_| {
+                let w = r.gen_range(0.0..capacity);
+                let v = r.gen_range(0.1..10.0) * w;
+                (w, v)
+            })
+            .collect();
+        Self { capacity, items, optimum: None }
+    }
+}
+
+fn parse_nums(line: &str) -> Result<Vec<f64>> {
+    line.split_whitespace()
+        .map(|v| v.parse::<f64>().wrap_err_with(|| format!("invalid number {v:?}")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use memega::eval::Evaluator;
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    use super::*;
+    use crate::examples::knapsack::{KnapsackEvaluator, KnapsackState};
+
+    const KNAPSACK_12: &str = include_str!("../../data/knapsack_12.txt");
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn parse_knapsack() -> Result<()> {
+        let instance = KnapsackInstance::parse(KNAPSACK_12)?;
+        assert_eq!(instance.items.len(), 12);
+        assert_eq!(instance.capacity, 100.0);
+        assert_eq!(instance.items[0], (10.0, 25.0));
+        assert_eq!(instance.optimum, Some(255.0));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_knapsack_errors() {
+        assert!(KnapsackInstance::parse("").is_err());
+        assert!(KnapsackInstance::parse("2 10\n1 1\n").is_err());
+        assert!(KnapsackInstance::parse("1 10\n1 x\n").is_err());
+        assert!(KnapsackInstance::parse("1 10\n1 1\n5\n6\n").is_err());
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn knapsack_optimum_fitness() -> Result<()> {
+        let instance = KnapsackInstance::parse(KNAPSACK_12)?;
+        let bound = instance.upper_bound();
+        assert!(bound >= 255.0);
+        let optimum = instance.optimum.map(|v| v / bound);
+        let eval = KnapsackEvaluator::from_instance(instance);
+        let kept = [0, 1, 4, 5, 7, 10, 11];
+        let s = KnapsackState((0..12).map(|i| kept.contains(&i)).collect());
+        assert_eq!(Some(eval.fitness(&s, &())?), optimum);
+        assert_eq!(eval.optimum(), optimum);
+        Ok(())
+    }
+
+    #[test]
+    fn generate_deterministic() {
+        let instance = KnapsackInstance::generate(10, 100.0, 1);
+        assert_eq!(instance, KnapsackInstance::generate(10, 100.0, 1));
+        assert_ne!(instance, KnapsackInstance::generate(10, 100.0, 2));
+    }
+}
@@ -1,14 +1,32 @@
-use memega::evolve::cfg::{Crossover, EvolveCfg, Mutation, Niching, Species, Survival};
+use memega::prelude::*;
 
 pub mod ackley;
+pub mod agent;
+pub mod classify;
 pub mod expr;
 pub mod func;
 pub mod griewank;
 pub mod hyper;
+pub mod io;
 pub mod knapsack;
 pub mod rastrigin;
 pub mod target_string;
 
+/// Config the examples are run with. Example fitnesses are all in [0, 1], so
+/// the stagnation epsilon is an absolute improvement on that scale.
+pub fn example_cfg(pop_size: usize) -> EvolveCfg {
+    EvolveCfg::new(pop_size)
+        .set_mutation(Mutation::Adaptive)
+        .set_crossover(Crossover::Adaptive)
+        .set_survival(Survival::TopProportion(0.1))
+        .set_species(Species::None)
+        .set_niching(Niching::None)
+        .set_stagnation(Stagnation::ContinuousAfter(100))
+        .set_stagnation_condition(StagnationCondition::Epsilon(1e-3))
+        .set_replacement(Replacement::ReplaceChildren(0.1))
+        .set_par_fitness(true)
+}
+
 pub fn all_cfg() -> EvolveCfg {
     EvolveCfg::new(100)
         .set_mutation(Mutation::Adaptive)
@@ -1,25 +1,32 @@
 use std::f64::consts::PI;
 
-use memega::eval::Evaluator;
-use memega::evolve::cfg::EvolveCfg;
-use memega::evolve::evolver::Evolver;
+use memega::prelude::*;
 
-use crate::examples::func::{func_evolver, FuncState};
+use crate::examples::func::{func_evolver, func_reproducible_evolver, FuncState};
 
+/// Minimises the Rastrigin function. With `example_cfg(100)` in 2 dimensions,
+/// usually reaches error below 1 (fitness above 0.5) within 200 generations.
 pub fn rastrigin_evolver(dim: usize, cfg: EvolveCfg) -> Evolver<impl Evaluator<Data = ()>> {
-    func_evolver(
-        dim,
-        -5.12,
-        5.12,
-        |s: &'_ FuncState, _: &'_ _| {
-            const A: f64 = 10.0;
-            let mut v = 0.0;
-            for &x in s.iter() {
-                v += A + x * x - A * (2.0 * PI * x).cos();
-            }
-            // Convert to a maximisation problem
-            Ok(1.0 / (1.0 + v))
-        },
-        cfg,
-    )
+    func_evolver(dim, -5.12, 5.12, |s: &'_ FuncState, _: &'_ _| Ok(rastrigin(s)), cfg)
+}
+
+/// Like `rastrigin_evolver`, but seeded with |seed| so runs are reproducible.
+pub fn rastrigin_reproducible_evolver(
+    dim: usize,
+    cfg: EvolveCfg,
+    seed: u64,
+) -> Evolver<impl Evaluator<Data = ()>> {
+    let f = |s: &'_ FuncState, _: &'_ _| Ok(rastrigin(s));
+    func_reproducible_evolver(dim, -5.12, 5.12, f, cfg, seed)
+}
+
+fn rastrigin(s: &FuncState) -> f64 {
+    const A: f64 = 10.0;
+    let mut v = 0.0;
+    for &x in s.iter() {
+        v += A + x * x - A * (2.0 * PI * x).cos();
+    }
+    // Convert to a maximisation problem with fitness in (0, 1], which is 1 at
+    // the global minimum.
+    1.0 / (1.0 + v)
 }
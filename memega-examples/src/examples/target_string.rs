@@ -1,12 +1,10 @@
 use derive_more::{Deref, DerefMut, Display};
 use eyre::Result;
-use memega::eval::Evaluator;
-use memega::evolve::cfg::EvolveCfg;
-use memega::evolve::evolver::Evolver;
 use memega::ops::crossover::crossover_kpx;
 use memega::ops::distance::count_different;
 use memega::ops::mutation::mutate_rate;
 use memega::ops::util::{rand_vec, str_to_vec};
+use memega::prelude::*;
 use memega::util::distributions::PrintableAscii;
 use rand::Rng;
 
@@ -46,8 +44,10 @@ impl Evaluator for TargetStringEvaluator {
         };
     }
 
+    // Fraction of characters which match the target, in [0, 1].
     fn fitness(&self, s: &Self::State, _data: &Self::Data) -> Result<f64> {
-        Ok((self.target.len() - count_different(s, &self.target)) as f64 + 1.0)
+        let len = self.target.len();
+        Ok((len - count_different(s, &self.target)) as f64 / len as f64)
     }
 
     fn distance(&self, s1: &Self::State, s2: &Self::State) -> Result<f64> {
@@ -55,6 +55,8 @@ impl Evaluator for TargetStringEvaluator {
     }
 }
 
+/// With `example_cfg(100)`, usually finds the target string within 500
+/// generations.
 pub fn target_string_evolver(cfg: EvolveCfg) -> Evolver<TargetStringEvaluator> {
     const TARGET: &str = "Hello world!";
     Evolver::new(TargetStringEvaluator::new(TARGET), cfg, move || {
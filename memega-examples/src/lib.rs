@@ -38,3 +38,4 @@
 
 pub mod examples;
 pub mod op;
+pub mod sweep;
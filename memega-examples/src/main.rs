@@ -3,10 +3,15 @@ use eyre::Result;
 use memega_examples::op::Args;
 
 fn main() -> Result<()> {
-    pretty_env_logger::init_timed();
+    // Without RUST_LOG, the trainer prints progress to stdout instead.
+    if std::env::var_os("RUST_LOG").is_some() {
+        pretty_env_logger::init_timed();
+    }
     color_eyre::install()?;
 
-    Args::parse().run()?;
+    let args = Args::parse();
+    let outcome = args.run_returning()?;
+    args.print_outcome(&outcome)?;
 
     Ok(())
 }
@@ -1,24 +1,37 @@
+use std::any::Any;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
 use clap::{Parser, ValueEnum};
-use eyre::Result;
-use memega::eval::{Data, Evaluator};
-use memega::evaluators::lgp::cfg::LgpEvaluatorCfg;
-use memega::evolve::cfg::{
-    Crossover, EvolveCfg, Mutation, Niching, Replacement, Species, Stagnation, StagnationCondition,
-    Survival,
-};
+use eyre::{eyre, Result};
+use memega::evaluators::hyper::builder::HyperBuilder;
+use memega::evaluators::lgp::minimize::LgpMinimizer;
+use memega::evaluators::lgp::vm::disasm::lgp_disasm;
 use memega::evolve::evolver::CreateEvolverFn;
-use memega::evolve::result::Stats;
-use memega::train::cfg::{Termination, TrainerCfg};
-use memega::train::sampler::{DataSampler, EmptyDataSampler};
-use memega::train::trainer::Trainer;
+use memega::prelude::*;
+use memega::train::sampler::EmptyDataSampler;
+use memega::tuning::experiments::{compare_cfgs, ComparisonReport};
+use memega::tuning::search::{grid_search, CfgSearchSpace, SearchBudget, SearchResult};
+use memega::util::fmt::{fmt_duration, fmt_fitness};
 use textwrap::indent;
 
 use crate::examples::ackley::ackley_evolver;
-use crate::examples::expr::{expr_evolver, ExprDataSampler};
+use crate::examples::agent::{agent_evolver, agent_fitness, AgentDataSampler};
+use crate::examples::classify::{
+    classify_evolver, classify_fitness, ClassifyDataSampler, ClassifyProblem, Sample,
+};
+use crate::examples::example_cfg;
+use crate::examples::expr::{
+    expr_ensemble, expr_ensemble_fitness, expr_evolver, expr_fitness, expr_layout, ExprDataSampler,
+    ExprRange,
+};
 use crate::examples::griewank::griewank_evolver;
-use crate::examples::knapsack::knapsack_evolver;
+use crate::examples::io::KnapsackInstance;
+use crate::examples::knapsack::{knapsack_seeded_evolver, KNAPSACK_ITEMS, KNAPSACK_MAX_W};
 use crate::examples::rastrigin::rastrigin_evolver;
 use crate::examples::target_string::target_string_evolver;
+use crate::sweep::{SweepReport, SweepRow};
 
 #[must_use]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, ValueEnum)]
@@ -29,23 +42,68 @@ pub enum Example {
     Rastringin,
     TargetString,
     Lgp,
+    BinaryClassify,
+    TernaryClassify,
+    Agent,
 }
 
 #[must_use]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, ValueEnum)]
 pub enum Op {
     Run,
+    Tune,
+    Compare,
+    Hyper,
+    Sweep,
+}
+
+/// Result of training an example once, or of evolving configs for it.
+#[must_use]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunOutcome {
+    pub stats: Stats,         // Stats of the final generation.
+    pub best_display: String, // Best member of the final generation, formatted.
+    pub elapsed: Duration,
+    pub artifacts: Vec<PathBuf>, // Files written by the op.
+    pub optimum: Option<f64>,    // Best possible fitness, if the example knows it.
+    pub notes: Vec<String>,      // Any other results, one per line.
+}
+
+impl RunOutcome {
+    fn new<S: State>(r: &EvolveResult<S>, start: Instant) -> Self {
+        Self {
+            stats: Stats::from_result(r),
+            best_display: r.nth(0).state.to_string(),
+            elapsed: start.elapsed(),
+            artifacts: vec![],
+            optimum: None,
+            notes: vec![],
+        }
+    }
+}
+
+/// Result of running an op, for printing by the cli or use by other tools.
+#[must_use]
+#[derive(Debug, Clone, PartialEq)]
+// Only one is made per op, so the size doesn't matter.
+#[allow(clippy::large_enum_variant)]
+pub enum Outcome {
+    Run(RunOutcome),         // From `Op::Run` and `Op::Hyper`.
+    Tune(Vec<SearchResult>), // Best first.
+    Compare { a: Replacement, b: Replacement, report: ComparisonReport },
+    Sweep(SweepReport),
+    Empty, // From `Op::Hyper` with no generations to evolve configs for.
 }
 
 #[must_use]
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser)]
 #[clap(name = "memega cli", about = "memega cli")]
 pub struct Args {
     #[clap(value_enum, help = "which operation to run")]
     pub op: Op,
 
-    #[clap(value_enum, help = "which example problem to solve")]
-    pub example: Example,
+    #[clap(value_enum, help = "which example problem to solve, not needed for sweep")]
+    pub example: Option<Example>,
 
     #[clap(
         long,
@@ -61,6 +119,48 @@ pub struct Args {
     )]
     pub lgp_target: String,
 
+    #[clap(
+        long,
+        default_value = "-100:100",
+        allow_hyphen_values = true,
+        help = "range of x to sample the lgp target over, as lo:hi"
+    )]
+    pub lgp_range: ExprRange,
+
+    #[clap(long, default_value = "99", help = "number of points to train the lgp target on")]
+    pub lgp_points: usize,
+
+    #[clap(
+        long,
+        default_value = "0.0",
+        help = "probability lgp mutations target code which affects the output"
+    )]
+    pub lgp_effective_bias: f64,
+
+    #[clap(long, help = "problem instance file to load, for examples that support it")]
+    pub instance: Option<PathBuf>,
+
+    #[clap(long, default_value = "0", help = "seed for generating an instance if none is loaded")]
+    pub instance_seed: u64,
+
+    #[clap(
+        long,
+        default_value = "0.0",
+        help = "fraction of the initial population to build with heuristics, for examples that \
+                support it"
+    )]
+    pub seed_fraction: f64,
+
+    #[clap(
+        long,
+        help = "for mathematical function examples, use differential evolution with this \
+                differential weight instead of the evaluator's crossover operators"
+    )]
+    pub de_f: Option<f64>,
+
+    #[clap(long, default_value = "0.9", help = "crossover rate for differential evolution")]
+    pub de_cr: f64,
+
     #[clap(long, default_value = "2000", help = "population size")]
     pub pop_size: usize,
 
@@ -69,77 +169,446 @@ pub struct Args {
 
     #[clap(long, help = "how often to report to tensorboard")]
     pub report_gen: Option<usize>,
+
+    #[clap(long, help = "don't print progress while training")]
+    pub quiet: bool,
+
+    #[clap(long, help = "print one line of key=value stats per generation instead of summaries")]
+    pub compact: bool,
+
+    #[clap(long, default_value = "3", help = "number of runs per config when tuning")]
+    pub tune_repeats: usize,
+
+    #[clap(long, default_value = "10", help = "number of runs per config when comparing")]
+    pub compare_repeats: usize,
+
+    #[clap(long, help = "for lgp, also report validation fitness of an ensemble of this size")]
+    pub ensemble: Option<usize>,
+
+    #[clap(long, help = "for lgp, shrink the final best program and report the difference")]
+    pub minimize: bool,
+
+    #[clap(long, default_value = "10", help = "generations per inner run when evolving configs")]
+    pub inner_gens: usize,
+
+    #[clap(
+        long,
+        default_value = "50",
+        help = "population size of inner runs when evolving configs"
+    )]
+    pub inner_pop: usize,
+
+    #[clap(
+        long,
+        default_value = "hyper_cfg.toml",
+        help = "file to write the best config to when evolving configs"
+    )]
+    pub hyper_out: PathBuf,
+
+    #[clap(long, default_value = "20", help = "number of generations per example when sweeping")]
+    pub sweep_gens: usize,
+
+    #[clap(long, default_value = "1", help = "number of runs per example when sweeping")]
+    pub sweep_repeats: usize,
+
+    #[clap(
+        long,
+        default_value = "sweep_report.csv",
+        help = "file to write the report to when sweeping"
+    )]
+    pub sweep_out: PathBuf,
 }
 
 impl Args {
-    fn cfg(&self) -> EvolveCfg {
-        EvolveCfg::new(self.pop_size)
-            .set_mutation(Mutation::Adaptive)
-            .set_crossover(Crossover::Adaptive)
-            .set_survival(Survival::TopProportion(0.1))
-            .set_species(Species::None)
-            .set_niching(Niching::None)
-            .set_stagnation(Stagnation::ContinuousAfter(100))
-            .set_stagnation_condition(StagnationCondition::Epsilon(0.5))
-            .set_replacement(Replacement::ReplaceChildren(0.1))
-            .set_par_fitness(true)
-    }
-
-    fn trainer_cfg(&self) -> TrainerCfg {
-        let mut cfg = TrainerCfg::new("example")
-            .set_termination(Termination::FixedGenerations(self.num_gen))
-            .set_print_gen(10)
-            .set_print_summary(10)
-            .set_print_samples(100)
-            .set_print_valid(10);
+    pub fn cfg(&self) -> EvolveCfg {
+        example_cfg(self.pop_size)
+    }
+
+    /// Config for the mathematical function examples, which support
+    /// differential evolution if `de_f` is set.
+    pub fn func_cfg(&self, cfg: EvolveCfg) -> EvolveCfg {
+        match self.de_f {
+            Some(f) => cfg.set_crossover(Crossover::Differential { f, cr: self.de_cr }),
+            None => cfg,
+        }
+    }
+
+    pub fn trainer_cfg(&self) -> TrainerCfg {
+        let mut cfg =
+            TrainerCfg::new("example").set_termination(Termination::FixedGenerations(self.num_gen));
+        if self.compact && !self.quiet {
+            cfg = cfg.set_print_compact(Some(1)).set_stdout(true);
+        } else if !self.quiet {
+            cfg = cfg
+                .set_print_gen(10)
+                .set_print_summary(10)
+                .set_print_samples(100)
+                .set_print_valid(10)
+                .set_stdout(true);
+        }
         cfg.report_gen = self.report_gen;
         cfg
     }
 
+    /// Sampler for `lgp_target` over `lgp_range`, shrunk to where the target
+    /// can be evaluated. See `expr_validate_range`.
+    pub fn expr_sampler(&self) -> Result<ExprDataSampler> {
+        ExprDataSampler::validated(&self.lgp_target, self.lgp_range, self.lgp_points)
+    }
+
+    /// Runs the op and prints its outcome.
+    #[deprecated(note = "use `run_returning` and `print_outcome`")]
     pub fn run(&self) -> Result<()> {
+        let outcome = self.run_returning()?;
+        self.print_outcome(&outcome)
+    }
+
+    /// Runs the op without printing anything but progress, which `quiet`
+    /// turns off.
+    pub fn run_returning(&self) -> Result<Outcome> {
+        if self.op == Op::Sweep {
+            return self.sweep().map(Outcome::Sweep);
+        }
+        let example = self.example.ok_or_else(|| eyre!("{:?} needs an example", self.op))?;
+        self.run_example(example)
+    }
+
+    /// Prints |outcome| as the cli does. Fails if any sweep runs failed.
+    pub fn print_outcome(&self, outcome: &Outcome) -> Result<()> {
+        match outcome {
+            Outcome::Run(v) => print_run(v),
+            Outcome::Tune(results) => {
+                println!("Results (best first):");
+                for r in results {
+                    let s = r.stats;
+                    println!(
+                        "  fitness {:.5} +- {:.5} (min {:.5}, max {:.5}): {:?} {:?} {:?}",
+                        s.mean, s.std, s.min, s.max, r.cfg.survival, r.cfg.selection, r.cfg.niching
+                    );
+                }
+            }
+            Outcome::Compare { a, b, report } => {
+                println!("a: {a:?}, b: {b:?}");
+                println!("{report}");
+            }
+            Outcome::Sweep(report) => {
+                println!("{report}");
+                println!("Wrote report to {}", self.sweep_out.display());
+                if report.failures() > 0 {
+                    return Err(eyre!(
+                        "{} of {} runs failed",
+                        report.failures(),
+                        report.rows.len()
+                    ));
+                }
+            }
+            Outcome::Empty => {}
+        }
+        Ok(())
+    }
+
+    /// Runs every example for `sweep_gens` generations, `sweep_repeats` times
+    /// each, and writes the final stats to `sweep_out` as CSV. A failing or
+    /// panicking example is recorded in the report instead of stopping the
+    /// sweep.
+    pub fn sweep(&self) -> Result<SweepReport> {
+        let args = Self {
+            op: Op::Run,
+            num_gen: self.sweep_gens,
+            ensemble: None,
+            minimize: false,
+            quiet: true,
+            ..self.clone()
+        };
+        let mut report = SweepReport::default();
+        for &example in Example::value_variants() {
+            for repeat in 0..self.sweep_repeats {
+                if !self.quiet {
+                    println!("Sweeping {example:?}, run {}/{}", repeat + 1, self.sweep_repeats);
+                }
+                let start = Instant::now();
+                let stats = match catch_unwind(AssertUnwindSafe(|| args.run_example(example))) {
+                    Ok(Ok(Outcome::Run(v))) => Ok(v.stats),
+                    Ok(Ok(_)) => Err("no stats".to_owned()),
+                    Ok(Err(e)) => Err(format!("{e:#}")),
+                    Err(e) => Err(format!("panicked: {}", panic_message(&*e))),
+                };
+                report.rows.push(SweepRow { example, repeat, stats, time: start.elapsed() });
+            }
+        }
+        std::fs::write(&self.sweep_out, report.to_csv())?;
+        Ok(report)
+    }
+
+    /// Runs the op on |example|. Sweeps aren't supported, see `sweep`.
+    pub fn run_example(&self, example: Example) -> Result<Outcome> {
         let func_dim = self.func_dim;
         let lgp_target = self.lgp_target.clone();
-        let lgpcfg = LgpEvaluatorCfg::new();
-        match self.example {
+        let lgpcfg = LgpEvaluatorCfg::new().set_effective_mutation_bias(self.lgp_effective_bias);
+        match example {
             Example::Ackley => {
-                self.dispatch(move |cfg| ackley_evolver(func_dim, cfg), &EmptyDataSampler {})
+                let args = self.clone();
+                self.dispatch(
+                    move |cfg| ackley_evolver(func_dim, args.func_cfg(cfg)),
+                    EmptyDataSampler {},
+                )
             }
             Example::Griewank => {
-                self.dispatch(move |cfg| griewank_evolver(func_dim, cfg), &EmptyDataSampler {})
+                let args = self.clone();
+                self.dispatch(
+                    move |cfg| griewank_evolver(func_dim, args.func_cfg(cfg)),
+                    EmptyDataSampler {},
+                )
+            }
+            Example::Knapsack => {
+                let instance = match &self.instance {
+                    Some(path) => KnapsackInstance::load(path)?,
+                    None => KnapsackInstance::generate(
+                        KNAPSACK_ITEMS,
+                        KNAPSACK_MAX_W,
+                        self.instance_seed,
+                    ),
+                };
+                let seed_fraction = self.seed_fraction;
+                self.dispatch(
+                    move |cfg| knapsack_seeded_evolver(instance.clone(), seed_fraction, cfg),
+                    EmptyDataSampler {},
+                )
             }
-            Example::Knapsack => self.dispatch(knapsack_evolver, &EmptyDataSampler {}),
             Example::Rastringin => {
-                self.dispatch(move |cfg| rastrigin_evolver(func_dim, cfg), &EmptyDataSampler {})
+                let args = self.clone();
+                self.dispatch(
+                    move |cfg| rastrigin_evolver(func_dim, args.func_cfg(cfg)),
+                    EmptyDataSampler {},
+                )
             }
-            Example::TargetString => self.dispatch(target_string_evolver, &EmptyDataSampler {}),
-            Example::Lgp => self.dispatch(
-                move |cfg| expr_evolver(lgp_target.clone(), lgpcfg.clone(), cfg),
-                &ExprDataSampler::new(),
-            ),
+            Example::TargetString => self.dispatch(target_string_evolver, EmptyDataSampler {}),
+            Example::Lgp => {
+                if let (Op::Run, Some(k)) = (self.op, self.ensemble) {
+                    return self.ensemble_op(k).map(Outcome::Run);
+                }
+                if let (Op::Run, true) = (self.op, self.minimize) {
+                    let layout = expr_layout();
+                    let target = lgp_target.clone();
+                    return self
+                        .minimize_op(
+                            expr_evolver(lgp_target, lgpcfg, self.cfg()),
+                            &self.expr_sampler()?,
+                            move |s: &'_ LgpState, xs: &'_ Vec<f64>| {
+                                expr_fitness(s, &layout, xs, &target)
+                            },
+                        )
+                        .map(Outcome::Run);
+                }
+                self.dispatch(
+                    move |cfg| expr_evolver(lgp_target.clone(), lgpcfg.clone(), cfg),
+                    self.expr_sampler()?,
+                )
+            }
+            Example::BinaryClassify => self.classify(ClassifyProblem::Circle, lgpcfg),
+            Example::TernaryClassify => self.classify(ClassifyProblem::Sectors, lgpcfg),
+            Example::Agent => self.agent(lgpcfg),
+        }
+    }
+
+    fn agent(&self, lgpcfg: LgpEvaluatorCfg) -> Result<Outcome> {
+        const SEEDS_PER_GEN: usize = 4;
+        let sampler = AgentDataSampler::new(SEEDS_PER_GEN);
+        if let (Op::Run, true) = (self.op, self.minimize) {
+            return self
+                .minimize_op(
+                    agent_evolver(lgpcfg, self.cfg()),
+                    &sampler,
+                    |s: &'_ LgpState, seed: &'_ u64| Ok(agent_fitness(s, *seed)),
+                )
+                .map(Outcome::Run);
         }
+        self.dispatch(move |cfg| agent_evolver(lgpcfg.clone(), cfg), sampler)
     }
 
-    fn dispatch<D: Data, E: Evaluator<Data = D>>(
+    fn classify(&self, problem: ClassifyProblem, lgpcfg: LgpEvaluatorCfg) -> Result<Outcome> {
+        if let (Op::Run, true) = (self.op, self.minimize) {
+            return self
+                .minimize_op(
+                    classify_evolver(problem, lgpcfg, self.cfg()),
+                    &ClassifyDataSampler::new(problem, self.instance_seed),
+                    move |s: &'_ LgpState, samples: &'_ Vec<Sample>| {
+                        Ok(classify_fitness(s, problem, samples))
+                    },
+                )
+                .map(Outcome::Run);
+        }
+        self.dispatch(
+            move |cfg| classify_evolver(problem, lgpcfg.clone(), cfg),
+            ClassifyDataSampler::new(problem, self.instance_seed),
+        )
+    }
+
+    /// Runs the op on the evolvers |create_fn| makes, with data from
+    /// |sampler|. Examples use this, and other problems can too.
+    pub fn dispatch<D: Data, E: Evaluator<Data = D>>(
         &self,
         create_fn: impl CreateEvolverFn<E>,
-        sampler: &impl DataSampler<E::Data>,
-    ) -> Result<()> {
+        sampler: impl DataSampler<E::Data> + Send + Sync + 'static,
+    ) -> Result<Outcome> {
         match self.op {
-            Op::Run => self.run_op(create_fn, sampler)?,
+            Op::Run => self.run_op(create_fn, &sampler).map(Outcome::Run),
+            Op::Tune => self.tune_op(create_fn, &sampler).map(Outcome::Tune),
+            Op::Compare => self.compare_op(create_fn, &sampler),
+            Op::Hyper => self.hyper_op(create_fn, sampler),
+            Op::Sweep => Err(eyre!("sweep runs every example")),
         }
-        Ok(())
     }
 
     fn run_op<E: Evaluator>(
         &self,
         create_fn: impl CreateEvolverFn<E>,
         sampler: &impl DataSampler<E::Data>,
-    ) -> Result<()> {
+    ) -> Result<RunOutcome> {
+        let start = Instant::now();
         let evolver = create_fn(self.cfg());
+        let optimum = evolver.eval().optimum();
         let mut trainer = Trainer::new(self.trainer_cfg());
-        let mut r = trainer.train(evolver, sampler)?;
-        println!("Stats:");
-        println!("{}", indent(&format!("{}", Stats::from_result(&mut r)), "  "));
-        Ok(())
+        let r = trainer.train(evolver, sampler)?;
+        Ok(RunOutcome { optimum, ..RunOutcome::new(&r, start) })
+    }
+
+    fn tune_op<E: Evaluator>(
+        &self,
+        create_fn: impl CreateEvolverFn<E>,
+        sampler: &(impl DataSampler<E::Data> + Sync),
+    ) -> Result<Vec<SearchResult>> {
+        let space = CfgSearchSpace::new(self.cfg().set_par_fitness(false))
+            .set_survival(&[Survival::TopProportion(0.1), Survival::TopProportion(0.25)])
+            .set_selection(&[Selection::Sus, Selection::Roulette])
+            .set_niching(&[Niching::None, Niching::SharedFitness(2.0)]);
+        let budget = SearchBudget::new(self.num_gen, self.tune_repeats).set_par(true);
+        grid_search(&space, &create_fn, sampler, budget, &|s| s.best_fitness)
+    }
+
+    fn compare_op<E: Evaluator>(
+        &self,
+        create_fn: impl CreateEvolverFn<E>,
+        sampler: &(impl DataSampler<E::Data> + Sync),
+    ) -> Result<Outcome> {
+        // Compare the default stagnation replacement with replacing the worst.
+        let cfg_a = self.cfg().set_par_fitness(false);
+        let cfg_b = cfg_a.clone().set_replacement(Replacement::ReplaceWorst(0.1));
+        let budget = SearchBudget::new(self.num_gen, self.compare_repeats).set_par(true);
+        let report =
+            compare_cfgs(&create_fn, &cfg_a, &cfg_b, sampler, budget, &|s| s.best_fitness)?;
+        Ok(Outcome::Compare { a: cfg_a.replacement, b: cfg_b.replacement, report })
+    }
+
+    // Evolves configs, writing the best to `hyper_out`, which is shown as the
+    // best member. Nothing is written if there are no generations.
+    fn hyper_op<E: Evaluator>(
+        &self,
+        create_fn: impl CreateEvolverFn<E>,
+        sampler: impl DataSampler<E::Data> + Send + Sync + 'static,
+    ) -> Result<Outcome> {
+        let start = Instant::now();
+        // Normalise inner fitness by the optimum, if the example knows it.
+        let inner_cfg = self.cfg().set_pop_size(self.inner_pop).set_par_fitness(false);
+        let max_fitness = create_fn(inner_cfg).eval().optimum().unwrap_or(1.0);
+        let mut builder = HyperBuilder::new(self.inner_pop, Duration::ZERO);
+        builder.add_sampled(max_fitness, self.inner_gens, create_fn, sampler);
+        let mut evolver = builder.build(self.cfg());
+        let mut r = None;
+        for i in 0..self.num_gen {
+            let res = evolver.run()?;
+            if !self.quiet {
+                println!("Generation {i}: {}", res.nth(0).fitness);
+            }
+            r = Some(res);
+        }
+        let Some(r) = r else { return Ok(Outcome::Empty) };
+        let best = r.nth(0).state.cfg().to_toml()?;
+        std::fs::write(&self.hyper_out, &best)?;
+        Ok(Outcome::Run(RunOutcome {
+            best_display: best,
+            artifacts: vec![self.hyper_out.clone()],
+            ..RunOutcome::new(&r, start)
+        }))
+    }
+
+    fn minimize_op<D: Data>(
+        &self,
+        evolver: Evolver<impl Evaluator<State = LgpState, Data = D>>,
+        sampler: &impl DataSampler<D>,
+        f: impl FitnessFn<LgpState, D>,
+    ) -> Result<RunOutcome> {
+        let start = Instant::now();
+        let mut trainer = Trainer::new(self.trainer_cfg());
+        let r = trainer.train(evolver, sampler)?;
+
+        // Keep the exact fitness on the training data.
+        let data = sampler.train(0);
+        let best = &r.nth(0).state;
+        let min = LgpMinimizer::new(0.0).set_rewrite(true).minimize(best, &data, &f)?;
+        let note = format!(
+            "minimized length: {} -> {}, effective length: {} -> {}, fitness: {} -> {}",
+            best.ops_unopt().len(),
+            min.ops_unopt().len(),
+            best.ops_opt().len(),
+            min.ops_opt().len(),
+            fmt_fitness(LgpMinimizer::fitness(best, &data, &f)?),
+            fmt_fitness(LgpMinimizer::fitness(&min, &data, &f)?),
+        );
+        // The minimised program is shown as the best member.
+        Ok(RunOutcome {
+            best_display: lgp_disasm(min.ops_unopt()),
+            notes: vec![note],
+            ..RunOutcome::new(&r, start)
+        })
+    }
+
+    fn ensemble_op(&self, k: usize) -> Result<RunOutcome> {
+        let start = Instant::now();
+        let sampler = self.expr_sampler()?;
+        let lgpcfg = LgpEvaluatorCfg::new().set_effective_mutation_bias(self.lgp_effective_bias);
+        let evolver = expr_evolver(self.lgp_target.clone(), lgpcfg, self.cfg());
+        let mut trainer = Trainer::new(self.trainer_cfg());
+        let r = trainer.train(evolver, &sampler)?;
+
+        let valid = sampler.valid(0).concat();
+        let layout = expr_layout();
+        let mut candidates = Vec::new();
+        for mem in &r.gen.mems {
+            let fitness = expr_fitness(&mem.state, &layout, &valid, &self.lgp_target)?;
+            candidates.push(((*mem.state).clone(), fitness));
+        }
+        let best = expr_ensemble(&candidates, 1, &valid);
+        let ensemble = expr_ensemble(&candidates, k, &valid);
+        let note = format!(
+            "valid single best: {}, ensemble of {}: {}",
+            fmt_fitness(expr_ensemble_fitness(&best, &valid, &self.lgp_target)?),
+            ensemble.members().len(),
+            fmt_fitness(expr_ensemble_fitness(&ensemble, &valid, &self.lgp_target)?),
+        );
+        Ok(RunOutcome { notes: vec![note], ..RunOutcome::new(&r, start) })
     }
 }
+
+fn print_run(v: &RunOutcome) {
+    for note in &v.notes {
+        println!("{note}");
+    }
+    println!("Best:");
+    println!("{}", indent(&v.best_display, "  "));
+    println!("Stats:");
+    println!("{}", indent(&format!("{}", v.stats), "  "));
+    if let Some(optimum) = v.optimum {
+        let gap = (optimum - v.stats.best_fitness) / optimum * 100.0;
+        println!("Optimum: {}, gap: {gap:.2}%", fmt_fitness(optimum));
+    }
+    println!("Took {}", fmt_duration(v.elapsed));
+    for path in &v.artifacts {
+        println!("Wrote {}", path.display());
+    }
+}
+
+fn panic_message(e: &(dyn Any + Send)) -> &str {
+    let msg = e.downcast_ref::<&str>().copied();
+    msg.or_else(|| e.downcast_ref::<String>().map(String::as_str)).unwrap_or("unknown")
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+use std::fmt::Write;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use memega::evolve::result::Stats;
+use memega::util::fmt::{fmt_csv_str, fmt_duration, fmt_fitness};
+
+use crate::op::Example;
+
+/// One run of an example in a sweep.
+#[must_use]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepRow {
+    pub example: Example,
+    pub repeat: usize,
+    // Stats of the final generation, or why the run failed.
+    pub stats: Result<Stats, String>,
+    pub time: Duration,
+}
+
+/// Final fitness of every example, so regressions stand out. Runs which
+/// failed are kept as rows with their error.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SweepReport {
+    pub rows: Vec<SweepRow>,
+}
+
+impl SweepReport {
+    #[must_use]
+    pub fn failures(&self) -> usize {
+        self.rows.iter().filter(|v| v.stats.is_err()).count()
+    }
+
+    /// One line per run, with a header. The stats columns are those of
+    /// `Stats::csv_header`, and are empty for runs which failed.
+    #[must_use]
+    pub fn to_csv(&self) -> String {
+        let mut s = format!("example,repeat,{},secs,error\n", Stats::csv_header());
+        for row in &self.rows {
+            let (stats, error) = match &row.stats {
+                Ok(v) => (v.to_csv_row(), String::new()),
+                Err(e) => (",".repeat(Stats::KV_KEYS.len() - 1), fmt_csv_str(e)),
+            };
+            let _ = writeln!(
+                s,
+                "{},{},{stats},{:.3},{error}",
+                name(row.example),
+                row.repeat,
+                row.time.as_secs_f64()
+            );
+        }
+        s
+    }
+}
+
+impl fmt::Display for SweepReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "| example | repeat | best fitness | mean fitness | time | error |")?;
+        write!(f, "|---|---|---|---|---|---|")?;
+        for row in &self.rows {
+            write!(f, "\n| {} | {} | ", name(row.example), row.repeat)?;
+            let time = fmt_duration(row.time);
+            match &row.stats {
+                Ok(v) => {
+                    let (best, mean) = (fmt_fitness(v.best_fitness), fmt_fitness(v.mean_fitness));
+                    write!(f, "{best} | {mean} | {time} | |")?;
+                }
+                Err(e) => {
+                    let e = e.replace('\n', " ").replace('|', "\\|");
+                    write!(f, " | | {time} | {e} |")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Name of |example| as given on the command line.
+fn name(example: Example) -> String {
+    example.to_possible_value().map_or_else(|| format!("{example:?}"), |v| v.get_name().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+
+    use super::*;
+
+    #[test]
+    fn csv_columns() -> Result<()> {
+        let stats = Stats::from_kv_line(
+            "best=2 mean=1.5 pop=10 dup=0 removed=0 evals=20 stag=0 injected=0 species=1 \
+             target=0 dist=0.25 age=1 max_age=1 takeover=0 trend=0 approx=0",
+        )?;
+        let row = |stats| SweepRow {
+            example: Example::Ackley,
+            repeat: 0,
+            stats,
+            time: Duration::from_millis(1500),
+        };
+        let report = SweepReport { rows: vec![row(Ok(stats)), row(Err("bad \"cfg\"".to_owned()))] };
+        let csv = report.to_csv();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], format!("example,repeat,{},secs,error", Stats::csv_header()));
+        assert_eq!(lines[1], format!("ackley,0,{},1.500,", stats.to_csv_row()));
+        assert!(lines[2].starts_with("ackley,0,,"), "{}", lines[2]);
+        assert!(lines[2].ends_with(",1.500,\"bad \"\"cfg\"\"\""), "{}", lines[2]);
+        for line in &lines {
+            assert_eq!(line.matches(',').count(), Stats::KV_KEYS.len() + 3, "{line}");
+        }
+        Ok(())
+    }
+}
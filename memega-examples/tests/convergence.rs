@@ -0,0 +1,285 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use eyre::Result;
+use memega::prelude::*;
+use memega::train::sampler::EmptyDataSampler;
+use memega_examples::examples::ackley::ackley_evolver;
+use memega_examples::examples::agent::{
+    agent_evolver, agent_fitness, random_fitness, AgentDataSampler,
+};
+use memega_examples::examples::example_cfg;
+use memega_examples::examples::expr::{expr_reproducible_evolver, ExprDataSampler, ExprRange};
+use memega_examples::examples::func::{func_reproducible_evolver, FuncState};
+use memega_examples::examples::griewank::griewank_evolver;
+use memega_examples::examples::io::KnapsackInstance;
+use memega_examples::examples::knapsack::{
+    knapsack_evolver, knapsack_instance_evolver, knapsack_reproducible_evolver,
+    knapsack_seeded_evolver, KnapsackState, KNAPSACK_ITEMS, KNAPSACK_MAX_W,
+};
+use memega_examples::examples::rastrigin::{rastrigin_evolver, rastrigin_reproducible_evolver};
+use memega_examples::examples::target_string::target_string_evolver;
+
+// Loose convergence checks with the example config, so regressions in the
+// core algorithms show up as failures. Fitnesses are all in [0, 1]. Each
+// check takes the best of a few runs so an unlucky run doesn't fail it.
+const POP: usize = 100;
+const RUNS: usize = 3;
+
+fn best_of<E: Evaluator<Data = ()>>(create: impl Fn() -> Evolver<E>, gens: usize) -> Result<f64> {
+    let mut best = 0.0_f64;
+    for _ in 0..RUNS {
+        let mut evolver = create();
+        for _ in 0..gens {
+            best = best.max(evolver.run()?.nth(0).fitness);
+        }
+    }
+    Ok(best)
+}
+
+#[test]
+fn rastrigin_converges() -> Result<()> {
+    // Fitness above 0.5 means error below 1.
+    let best = best_of(|| rastrigin_evolver(2, example_cfg(POP)), 200)?;
+    assert!(best > 0.5, "best fitness {best}");
+    Ok(())
+}
+
+#[test]
+fn rastrigin_rank_converges() -> Result<()> {
+    // Mean over seeded runs of the best fitness within 20 generations, while
+    // selection still matters.
+    let mean_best = |selection| -> Result<f64> {
+        let mut total = 0.0;
+        for seed in 0..RUNS as u64 {
+            let cfg = example_cfg(POP).set_selection(selection);
+            let mut evolver = rastrigin_reproducible_evolver(2, cfg, seed);
+            let mut best = 0.0_f64;
+            for _ in 0..20 {
+                best = best.max(evolver.run()?.nth(0).fitness);
+            }
+            total += best;
+        }
+        Ok(total / RUNS as f64)
+    };
+    // Rank selection should do no worse than the default SUS on raw fitness,
+    // and much better than with no pressure, which selects uniformly.
+    let sus = mean_best(Selection::Sus)?;
+    let rank = mean_best(Selection::Rank(RankPressure::new(1.8)?))?;
+    let uniform = mean_best(Selection::Rank(RankPressure::new(1.0)?))?;
+    assert!(rank > sus - 0.05, "rank {rank}, sus {sus}");
+    assert!(rank > uniform + 0.1, "rank {rank}, uniform {uniform}");
+    Ok(())
+}
+
+#[test]
+fn ackley_converges() -> Result<()> {
+    let best = best_of(|| ackley_evolver(2, example_cfg(POP)), 200)?;
+    assert!(best > 0.9, "best fitness {best}");
+    Ok(())
+}
+
+#[test]
+fn griewank_converges() -> Result<()> {
+    // The search space is huge, so only check it gets within an error of 2.
+    let best = best_of(|| griewank_evolver(2, example_cfg(POP)), 1000)?;
+    assert!(best > 1.0 / 3.0, "best fitness {best}");
+    Ok(())
+}
+
+// First generation whose best fitness on the sphere reaches 0.99, i.e. a
+// squared distance from the optimum of about 0.01, if any within |gens|.
+fn sphere_solved_gen(crossover: Crossover, gens: usize) -> Result<Option<usize>> {
+    const DIM: usize = 5;
+    // Without mutation, only crossover can move the population.
+    let cfg = example_cfg(POP).set_crossover(crossover).set_mutation(Mutation::Fixed(vec![0.0]));
+    let sphere = |s: &FuncState, (): &()| Ok(1.0 / (1.0 + s.iter().map(|v| v * v).sum::<f64>()));
+    let mut evolver = func_reproducible_evolver(DIM, -5.12, 5.12, sphere, cfg, 1234);
+    for gen in 0..gens {
+        if evolver.run()?.nth(0).fitness >= 0.99 {
+            return Ok(Some(gen));
+        }
+    }
+    Ok(None)
+}
+
+#[test]
+fn differential_solves_sphere_faster() -> Result<()> {
+    const GENS: usize = 300;
+    let de = sphere_solved_gen(Crossover::Differential { f: 0.5, cr: 0.9 }, GENS)?;
+    let blend = sphere_solved_gen(Crossover::Fixed(vec![0.0, 1.0, 0.0]), GENS)?;
+    let de = de.expect("differential evolution solves the sphere");
+    assert!(blend.is_none_or(|blend| de < blend), "de {de}, blend {blend:?}");
+    Ok(())
+}
+
+#[test]
+fn knapsack_converges() -> Result<()> {
+    // Within 10% of the fractional relaxation bound.
+    let best = best_of(|| knapsack_evolver(example_cfg(POP)), 200)?;
+    assert!(best >= 0.9, "best fitness {best}");
+    Ok(())
+}
+
+#[test]
+fn knapsack_fitness_histograms() -> Result<()> {
+    const BINS: usize = 10;
+    let snapshots = Arc::new(Mutex::new(Vec::new()));
+    let out = Arc::clone(&snapshots);
+    let cfg = TrainerCfg::new("knapsack")
+        .set_termination(Termination::FixedGenerations(50))
+        .set_snapshot_gen(10);
+    let mut trainer =
+        Trainer::new(cfg).set_snapshot_fn(move |gen, snapshot: &PopulationSnapshot<'_>| {
+            let fitnesses = snapshot.fitnesses();
+            let mean = fitnesses.iter().sum::<f64>() / fitnesses.len() as f64;
+            let histogram = snapshot.fitness_histogram(BINS);
+            out.lock().unwrap().push((gen, fitnesses.len(), histogram, mean));
+        });
+    let _ = trainer.train(knapsack_evolver(example_cfg(POP)), &EmptyDataSampler {})?;
+    let snapshots = snapshots.lock().unwrap();
+    assert_eq!(snapshots.iter().map(|v| v.0).collect::<Vec<_>>(), [0, 10, 20, 30, 40]);
+    for (_, len, histogram, _) in snapshots.iter() {
+        assert_eq!(histogram.len(), BINS);
+        assert_eq!(histogram.iter().sum::<usize>(), *len);
+    }
+    // The population as a whole gets fitter, not just the best member.
+    let (first, last) = (snapshots[0].3, snapshots[4].3);
+    assert!(last > first, "mean fitness {first} then {last}");
+    Ok(())
+}
+
+#[test]
+fn knapsack_seeds_improve_initial_gen() -> Result<()> {
+    let instance = KnapsackInstance::generate(KNAPSACK_ITEMS, KNAPSACK_MAX_W, 0);
+    let unseeded = knapsack_instance_evolver(instance.clone(), example_cfg(POP)).run()?;
+    let seeded = knapsack_seeded_evolver(instance, 0.1, example_cfg(POP)).run()?;
+    let (unseeded, seeded) = (unseeded.nth(0).fitness, seeded.nth(0).fitness);
+    assert!(seeded > unseeded, "seeded {seeded}, unseeded {unseeded}");
+    Ok(())
+}
+
+#[test]
+fn knapsack_seeded_runs_match() -> Result<()> {
+    const GENS: usize = 50;
+    let bests = |seed| -> Result<Vec<KnapsackState>> {
+        let mut evolver =
+            knapsack_reproducible_evolver(example_cfg(POP).set_par_fitness(false), seed);
+        (0..GENS).map(|_| Ok((*evolver.run()?.nth(0).state).clone())).collect()
+    };
+    let first = bests(1234)?;
+    assert_eq!(first, bests(1234)?);
+    assert_ne!(first, bests(1235)?);
+    Ok(())
+}
+
+#[test]
+fn target_string_converges() -> Result<()> {
+    #[allow(clippy::float_cmp)]
+    let found = best_of(|| target_string_evolver(example_cfg(POP)), 500)? == 1.0;
+    assert!(found);
+    Ok(())
+}
+
+#[test]
+#[allow(clippy::float_cmp)]
+fn target_string_stops_at_target() -> Result<()> {
+    // The whole string matching is fitness 1, the best possible.
+    let cfg = TrainerCfg::new("test")
+        .set_termination(Termination::TargetFitness(1.0))
+        .add_termination(Termination::FixedGenerations(500))
+        .add_termination(Termination::Timeout(Duration::from_secs(60)));
+    let mut found = false;
+    for _ in 0..RUNS {
+        let evolver = target_string_evolver(example_cfg(POP));
+        let r = Trainer::new(cfg.clone()).train(evolver, &EmptyDataSampler {})?;
+        if r.termination == Some(Termination::TargetFitness(1.0)) {
+            assert_eq!(r.nth(0).fitness, 1.0);
+            assert_eq!(r.nth(0).state.to_string(), "Hello world!");
+            found = true;
+            break;
+        }
+        assert_ne!(r.termination, None);
+    }
+    assert!(found);
+    Ok(())
+}
+
+#[test]
+fn lgp_effective_mutation_grows_effective_code() -> Result<()> {
+    const TOP: usize = 10;
+    // The fraction varies a lot between runs, so average over more of them,
+    // each seeded so the margin below holds every time.
+    const EFFECTIVE_RUNS: u64 = 8;
+    let sampler = ExprDataSampler::from_range(ExprRange::default(), 11);
+    // Mean fraction of code which can affect the output in the top programs.
+    let effective_fraction = |rates: &[f64]| -> Result<f64> {
+        let mut total = 0.0;
+        for seed in 0..EFFECTIVE_RUNS {
+            let cfg = example_cfg(POP).set_mutation(Mutation::Fixed(rates.to_vec()));
+            let mut evolver =
+                expr_reproducible_evolver("x^2 + x".to_owned(), LgpEvaluatorCfg::new(), cfg, seed);
+            let mut r = evolver.run_data(&sampler.train(0))?;
+            for i in 1..50 {
+                r = evolver.run_data(&sampler.train(i))?;
+            }
+            for i in 0..TOP {
+                let s = &r.nth(i).state;
+                total += s.effective_indices().len() as f64 / s.ops_unopt().len().max(1) as f64;
+            }
+        }
+        Ok(total / (EFFECTIVE_RUNS as usize * TOP) as f64)
+    };
+    // Blind insertion and removal, against effective mutation in their place.
+    let baseline = effective_fraction(&[0.1, 0.1, 0.1, 0.1, 0.5, 0.5, 0.5, 0.0, 0.0])?;
+    let effective = effective_fraction(&[0.1, 0.1, 0.1, 0.1, 0.0, 0.0, 0.5, 1.0, 0.0])?;
+    assert!(effective > baseline + 0.1, "effective {effective}, baseline {baseline}");
+    Ok(())
+}
+
+#[test]
+fn lgp_effective_mutation_bias_no_regression() -> Result<()> {
+    const BIAS_RUNS: u64 = 4;
+    let sampler = ExprDataSampler::from_range(ExprRange::default(), 11);
+    // Mean best fitness on the expr example, from the same seeds for both.
+    let mean_best = |bias: f64| -> Result<f64> {
+        let mut total = 0.0;
+        for seed in 0..BIAS_RUNS {
+            let lgpcfg = LgpEvaluatorCfg::new().set_effective_mutation_bias(bias);
+            let mut evolver =
+                expr_reproducible_evolver("x^2 + x".to_owned(), lgpcfg, example_cfg(POP), seed);
+            let mut best = 0.0_f64;
+            for i in 0..30 {
+                best = best.max(evolver.run_data(&sampler.train(i))?.nth(0).fitness);
+            }
+            total += best;
+        }
+        Ok(total / BIAS_RUNS as f64)
+    };
+    let baseline = mean_best(0.0)?;
+    let biased = mean_best(1.0)?;
+    assert!(biased >= baseline * 0.95, "biased {biased}, baseline {baseline}");
+    Ok(())
+}
+
+#[test]
+fn agent_beats_random() -> Result<()> {
+    // Mostly cooperating with the noisy tit-for-tat opponent scores about 0.54
+    // against 0.45 for random moves.
+    let sampler = AgentDataSampler::new(4);
+    let test = sampler.test(0);
+    let mut best = 0.0_f64;
+    for _ in 0..RUNS {
+        let mut evolver = agent_evolver(LgpEvaluatorCfg::new(), example_cfg(POP));
+        let mut r = evolver.run_data(&sampler.train(0))?;
+        for i in 1..50 {
+            r = evolver.run_data(&sampler.train(i))?;
+        }
+        let s = &r.nth(0).state;
+        let fitness = test.iter().map(|&seed| agent_fitness(s, seed)).sum::<f64>();
+        best = best.max(fitness / test.len() as f64);
+    }
+    let random = random_fitness(&test, 0);
+    assert!(best > random + 0.05, "best fitness {best}, random {random}");
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::fs;
+
+use eyre::{eyre, Result};
+use memega::prelude::*;
+use memega_examples::examples::example_cfg;
+use memega_examples::examples::expr::{
+    expr_evolver, expr_fitness, expr_layout, ExprDataSampler, ExprRange,
+};
+use pretty_assertions::assert_eq;
+
+// The workflow a user follows, through public APIs only: train with
+// checkpoints, export the best program and the config, reload them into new
+// evolver and vm instances, then resume training from the checkpoint with an
+// evolver built from the reloaded config. Budgets are small since this checks
+// the plumbing, not convergence.
+const TARGET: &str = "x^2 + x";
+const GENS: usize = 10;
+
+fn evolve_cfg() -> EvolveCfg {
+    example_cfg(30).set_par_fitness(false)
+}
+
+fn trainer_cfg(gens: usize) -> TrainerCfg {
+    // A validation sample as large as the data records the full validation
+    // fitness of the best member.
+    TrainerCfg::new("end_to_end")
+        .set_termination(Termination::FixedGenerations(gens))
+        .set_valid_sample(Some(usize::MAX))
+}
+
+fn evolver(cfg: EvolveCfg) -> Evolver<impl Evaluator<State = LgpState, Data = Vec<f64>>> {
+    expr_evolver(TARGET.to_owned(), LgpEvaluatorCfg::new(), cfg)
+}
+
+#[test]
+fn train_export_reload_evaluate() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("memega-end-to-end-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let checkpoint = dir.join("checkpoint.json");
+    let sampler = ExprDataSampler::from_range(ExprRange::new(-5.0, 5.0)?, 33);
+    let r = Trainer::new(trainer_cfg(GENS)).train_resumable(
+        || evolver(evolve_cfg()),
+        &sampler,
+        &checkpoint,
+        4,
+    )?;
+    let recorded = r.valid_fitness.ok_or_else(|| eyre!("no validation fitness recorded"))?;
+
+    // Export the best program as assembly and the config as TOML.
+    let best = LgpProgram::from(&*r.nth(0).state);
+    fs::write(dir.join("best.asm"), best.disasm())?;
+    fs::write(dir.join("cfg.toml"), evolve_cfg().to_toml()?)?;
+    let program_text = fs::read_to_string(dir.join("best.asm"))?;
+    let cfg = EvolveCfg::from_toml(&fs::read_to_string(dir.join("cfg.toml"))?)?;
+    assert_eq!(cfg, evolve_cfg());
+
+    // Reload the program and evaluate it with a new evolver, and directly on
+    // a new vm. Both must match the recorded fitness exactly.
+    let program = LgpProgram::asm(&program_text, best.num_reg, best.num_const, &best.output_regs)?;
+    assert_eq!(program, best);
+    let s = LgpState::try_from(program)?;
+    let valid = sampler.valid(GENS - 1);
+    let fresh = evolver(cfg.clone());
+    let fitness = fresh.eval().multi_fitness(&s, &valid, fresh.cfg().fitness_reduction)?;
+    assert_eq!(fitness.to_bits(), recorded.to_bits(), "{fitness} != {recorded}");
+    let direct = expr_fitness(&s, &expr_layout(), &valid[0], TARGET)?;
+    assert_eq!(direct.to_bits(), recorded.to_bits(), "{direct} != {recorded}");
+
+    // Resume from the checkpoint, continuing the generation count.
+    let resumed = Trainer::new(trainer_cfg(GENS + 3)).train_resumable(
+        || evolver(cfg),
+        &sampler,
+        &checkpoint,
+        4,
+    )?;
+    assert_eq!(resumed.unevaluated.gen_idx, GENS + 2);
+    // The checkpointed best survives, so training can't get worse.
+    assert!(resumed.nth(0).fitness >= r.nth(0).fitness);
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use clap::Parser;
+use eyre::{eyre, Result};
+use memega::evolve::cfg::EvolveCfg;
+use memega_examples::op::{Args, Outcome};
+
+#[test]
+fn hyper_knapsack() -> Result<()> {
+    let out = std::env::temp_dir().join(format!("memega-hyper-{}.toml", std::process::id()));
+    let out_arg = out.to_str().ok_or_else(|| eyre!("non utf-8 temp dir"))?;
+    let args = Args::parse_from([
+        "memega",
+        "hyper",
+        "knapsack",
+        "--pop-size",
+        "4",
+        "--num-gen",
+        "2",
+        "--inner-pop",
+        "6",
+        "--inner-gens",
+        "2",
+        "--hyper-out",
+        out_arg,
+    ]);
+    let Outcome::Run(outcome) = args.run_returning()? else {
+        return Err(eyre!("hyper should return a run outcome"));
+    };
+    assert_eq!(outcome.artifacts, std::slice::from_ref(&out));
+    let text = std::fs::read_to_string(&out)?;
+    std::fs::remove_file(&out)?;
+
+    let cfg = EvolveCfg::from_toml(&text)?;
+    assert_eq!(cfg.pop_size, 6);
+    assert_eq!(cfg.to_toml()?, text);
+    assert_eq!(outcome.best_display, text);
+    Ok(())
+}
+
+#[test]
+fn hyper_no_generations() -> Result<()> {
+    let out = std::env::temp_dir().join(format!("memega-hyper-empty-{}.toml", std::process::id()));
+    let out_arg = out.to_str().ok_or_else(|| eyre!("non utf-8 temp dir"))?;
+    let args = Args::parse_from([
+        "memega",
+        "hyper",
+        "knapsack",
+        "--pop-size",
+        "4",
+        "--num-gen",
+        "0",
+        "--hyper-out",
+        out_arg,
+    ]);
+    assert_eq!(args.run_returning()?, Outcome::Empty);
+    assert!(!out.exists());
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use clap::Parser;
+use eyre::{eyre, Result};
+use memega_examples::examples::io::KnapsackInstance;
+use memega_examples::op::{Args, Example, Outcome, RunOutcome};
+
+fn run(extra: &[&str]) -> Result<RunOutcome> {
+    let args = ["memega", "run", "--pop-size", "20", "--num-gen", "5"];
+    let args = Args::parse_from(args.iter().chain(extra));
+    let args = Args { quiet: true, ..args };
+    match args.run_returning()? {
+        Outcome::Run(v) => Ok(v),
+        v => Err(eyre!("expected a run outcome, got {v:?}")),
+    }
+}
+
+#[test]
+#[allow(clippy::float_cmp)]
+fn run_outcomes() -> Result<()> {
+    let ackley = run(&["ackley"])?;
+    let knapsack = run(&["knapsack", "--instance", "data/knapsack_12.txt"])?;
+    for v in [&ackley, &knapsack] {
+        assert!(v.stats.pop_size > 0, "{v:?}");
+        assert!(!v.best_display.is_empty(), "{v:?}");
+        assert!(v.elapsed.as_nanos() > 0, "{v:?}");
+        assert!(v.artifacts.is_empty(), "{v:?}");
+    }
+    assert_eq!(ackley.optimum, None);
+    // Knapsack fitness is normalised by the instance's upper bound.
+    let bound = KnapsackInstance::load("data/knapsack_12.txt")?.upper_bound();
+    assert_eq!(knapsack.optimum, Some(255.0 / bound));
+    Ok(())
+}
+
+#[test]
+fn run_example_matches_cli() -> Result<()> {
+    let args = Args { quiet: true, ..Args::parse_from(["memega", "run", "--num-gen", "2"]) };
+    assert!(args.run_returning().is_err(), "run without an example should fail");
+    assert!(matches!(args.run_example(Example::Rastringin)?, Outcome::Run(_)));
+    Ok(())
+}
+
+#[test]
+#[allow(clippy::float_cmp)]
+fn lgp_validated_range() -> Result<()> {
+    let extra = ["lgp", "--lgp-target", "1/x", "--lgp-range", "-2:0", "--lgp-points", "20"];
+    let args = Args::parse_from(["memega", "run"].iter().chain(&extra));
+    let range = args.expr_sampler()?.range();
+    assert!(range.lo == -2.0 && range.hi < 0.0, "{range}");
+    let v = run(&extra)?;
+    assert!(v.stats.best_fitness.is_finite() && v.stats.best_fitness > 0.0, "{v:?}");
+
+    let e = run(&["lgp", "--lgp-target", "1/(x - x)", "--lgp-points", "20"]).unwrap_err();
+    assert!(e.to_string().contains("x = -100 "), "{e}");
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use clap::{Parser, ValueEnum};
+use eyre::{eyre, Result};
+use memega_examples::op::{Args, Example};
+
+#[test]
+fn sweep_all_examples() -> Result<()> {
+    let out = std::env::temp_dir().join(format!("memega-sweep-{}.csv", std::process::id()));
+    let out_arg = out.to_str().ok_or_else(|| eyre!("non utf-8 temp dir"))?;
+    let args = Args::parse_from([
+        "memega",
+        "sweep",
+        "--pop-size",
+        "20",
+        "--sweep-gens",
+        "2",
+        "--sweep-out",
+        out_arg,
+    ]);
+    let report = args.sweep()?;
+    let csv = std::fs::read_to_string(&out)?;
+    std::fs::remove_file(&out)?;
+
+    assert_eq!(report.failures(), 0, "{report}");
+    for &example in Example::value_variants() {
+        assert!(report.rows.iter().any(|v| v.example == example), "missing {example:?}");
+        let name = example.to_possible_value().unwrap();
+        let prefix = format!("{},0,", name.get_name());
+        assert!(csv.lines().any(|l| l.starts_with(&prefix)), "missing {prefix} in:\n{csv}");
+    }
+    assert_eq!(csv.lines().count(), Example::value_variants().len() + 1);
+    Ok(())
+}
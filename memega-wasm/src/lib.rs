@@ -0,0 +1,95 @@
+#![warn(
+    clippy::all,
+    clippy::pedantic,
+    future_incompatible,
+    macro_use_extern_crate,
+    meta_variable_misuse,
+    missing_abi,
+    nonstandard_style,
+    noop_method_call,
+    rust_2018_compatibility,
+    rust_2018_idioms,
+    rust_2021_compatibility,
+    trivial_casts,
+    unreachable_pub,
+    unsafe_code,
+    unsafe_op_in_unsafe_fn,
+    unused_import_braces,
+    unused_lifetimes,
+    unused_qualifications,
+    unused,
+    variant_size_differences
+)]
+#![allow(clippy::missing_errors_doc, clippy::module_name_repetitions)]
+
+use memega::evaluators::lgp::cfg::LgpEvaluatorCfg;
+use memega::evolve::cfg::{EvolveCfg, Survival};
+use memega::train::sampler::DataSampler;
+use memega_examples::examples::expr::{expr_evolver, ExprDataSampler};
+use memega_examples::examples::io::KnapsackInstance;
+use memega_examples::examples::knapsack::{
+    knapsack_instance_evolver, KNAPSACK_ITEMS, KNAPSACK_MAX_W,
+};
+use wasm_bindgen::prelude::*;
+
+fn cfg(pop_size: usize) -> EvolveCfg {
+    EvolveCfg::new(pop_size).set_survival(Survival::TopProportion(0.1))
+}
+
+fn js_err(e: &eyre::Report) -> JsError {
+    JsError::new(&format!("{e:?}"))
+}
+
+/// Evolves an LGP program to fit a function of x, one generation at a time so
+/// the page can update in between.
+#[wasm_bindgen]
+pub struct ExprDemo {
+    // The evaluator type is unnameable, so keep the evolver inside a closure
+    // which runs a generation and returns the best fitness and program.
+    step: Box<dyn FnMut() -> eyre::Result<(f64, String)>>,
+    best: String,
+}
+
+#[wasm_bindgen]
+impl ExprDemo {
+    /// |target| is an expression in x, e.g. "x^2 + x + 1".
+    #[wasm_bindgen(constructor)]
+    #[must_use]
+    pub fn new(target: String, pop_size: usize) -> ExprDemo {
+        let mut evolver = expr_evolver(target, LgpEvaluatorCfg::new(), cfg(pop_size));
+        let sampler = ExprDataSampler::new();
+        let mut gen = 0;
+        let step = move || {
+            let r = evolver.run_data(&sampler.train(gen))?;
+            gen += 1;
+            Ok((r.nth(0).fitness, r.nth(0).state.to_string()))
+        };
+        ExprDemo { step: Box::new(step), best: String::new() }
+    }
+
+    /// Runs one generation and returns the best fitness.
+    pub fn step(&mut self) -> Result<f64, JsError> {
+        let (fitness, best) = (self.step)().map_err(|e| js_err(&e))?;
+        self.best = best;
+        Ok(fitness)
+    }
+
+    /// Disassembly of the best program from the last generation.
+    #[must_use]
+    pub fn best(&self) -> String {
+        self.best.clone()
+    }
+}
+
+/// Runs the knapsack example on a generated instance and returns the best
+/// fitness.
+#[wasm_bindgen]
+pub fn knapsack_demo(generations: usize, pop_size: usize, seed: u64) -> Result<f64, JsError> {
+    let instance = KnapsackInstance::generate(KNAPSACK_ITEMS, KNAPSACK_MAX_W, seed);
+    let mut evolver = knapsack_instance_evolver(instance, cfg(pop_size));
+    let mut best = 0.0;
+    for _ in 0..generations {
+        best = evolver.run().map_err(|e| js_err(&e))?.nth(0).fitness;
+    }
+    Ok(best)
+}
@@ -0,0 +1,23 @@
+#![cfg(target_arch = "wasm32")]
+
+use memega_wasm::{knapsack_demo, ExprDemo};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+fn expr_demo_runs() {
+    let mut demo = ExprDemo::new("x^2 + x + 1".to_string(), 50);
+    let first = demo.step().unwrap();
+    let mut last = first;
+    for _ in 0..5 {
+        last = demo.step().unwrap();
+    }
+    assert!(last >= first);
+    assert!(!demo.best().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn knapsack_demo_runs() {
+    assert!(knapsack_demo(5, 50, 0).unwrap() > 0.0);
+}
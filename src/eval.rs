@@ -1,7 +1,13 @@
-use std::fmt;
+#[cfg(feature = "cache")]
 use std::hash::Hash;
+#[cfg(feature = "cache")]
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{cmp, fmt};
 
-use eyre::Result;
+use eyre::{eyre, Result};
+use rand::RngCore;
+#[cfg(feature = "cache")]
 use stretto::Cache;
 
 use crate::evolve::cfg::FitnessReduction;
@@ -10,6 +16,37 @@ pub trait State = Clone + Send + Sync + PartialOrd + PartialEq + fmt::Display;
 pub trait Data = Clone + Send + Sync;
 pub trait FitnessFn<S: State, D: Data = ()> = Fn(&S, &D) -> Result<f64> + Sync + Send + Clone;
 
+/// Identifies a version of the training data. Caches of values computed from
+/// data are only valid within an epoch.
+pub type DataEpoch = u64;
+
+/// Fitness function for evaluations made outside of evaluating a generation,
+/// e.g. by `Evaluator::local_search`, which counts the evaluations made
+/// through it so they are included in `Stats`.
+#[must_use]
+pub struct FitnessEvals<'a, S, D> {
+    fitness: &'a (dyn Fn(&S, &D) -> Result<f64> + Sync),
+    count: AtomicUsize,
+}
+
+impl<'a, S, D> FitnessEvals<'a, S, D> {
+    pub fn new(fitness: &'a (dyn Fn(&S, &D) -> Result<f64> + Sync)) -> Self {
+        Self { fitness, count: AtomicUsize::new(0) }
+    }
+
+    /// Fitness of |s| on |data|, counted as one evaluation.
+    pub fn fitness(&self, s: &S, data: &D) -> Result<f64> {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        (self.fitness)(s, data)
+    }
+
+    /// Number of evaluations made so far.
+    #[must_use]
+    pub fn get(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
 /// Evaluates, mutates, etc a State.
 pub trait Evaluator: Send + Sync {
     type State: State;
@@ -17,33 +54,112 @@ pub trait Evaluator: Send + Sync {
     /// training on a subset of data e.g. to improve overfitting or because
     /// the fitness function is not the exact goal.
     type Data: Data = ();
-    /// Specify the number of crossover operators.
+    /// Specify the number of crossover operators. Mutation-only evaluators
+    /// can use 0, or 1 for just the no-op, in which case crossover is skipped
+    /// entirely and crossover weights are ignored.
     const NUM_CROSSOVER: usize = 2;
-    /// Specify the number of mutation operators.
+    /// Whether crossover index 0 is the no-op. If so, choosing it skips
+    /// crossover without calling `crossover`, and reports label it "none" and
+    /// leave it out of comparisons between operators. Evaluators where every
+    /// index is a real operator should set this to false.
+    const CROSSOVER_HAS_NOOP: bool = true;
+    /// Specify the number of mutation operators. With 0, children only differ
+    /// from their parents through crossover.
     const NUM_MUTATION: usize = 1;
 
-    /// |idx| specifies which crossover function to use. 0 is conventionally do nothing,
-    /// with actual crossover starting from index 1.
+    /// |idx| specifies which crossover function to use. Unless
+    /// `CROSSOVER_HAS_NOOP` is false, index 0 is do nothing and this is never
+    /// called with it, with actual crossover starting from index 1. How often
+    /// crossover happens depends on `EvolveCfg::crossover_probability`:
+    ///
+    /// - If None, the crossover weights (fixed or adaptive) choose between all
+    ///   operators including 0, so the weight of the no-op relative to the
+    ///   rest acts as the probability of skipping crossover.
+    /// - If Some(p), crossover is skipped with probability 1 - p and otherwise
+    ///   the weights choose only between the real operators, so the weight of
+    ///   the no-op is ignored.
     fn crossover(&self, s1: &mut Self::State, s2: &mut Self::State, idx: usize);
 
     /// Unlike crossover, mutation is called for every mutation operator. No need for a nop operator.
     fn mutate(&self, s: &mut Self::State, rate: f64, idx: usize);
 
+    /// Like `crossover`, but draws random numbers from |rng|, which the
+    /// evolver seeds if made with `Evolver::new_seeded`. Evaluators should
+    /// override this and `mutate_rng`, e.g. with the `_rng` operators in
+    /// `ops`, for seeded runs to be reproducible. By default ignores |rng|.
+    fn crossover_rng(
+        &self,
+        s1: &mut Self::State,
+        s2: &mut Self::State,
+        idx: usize,
+        _rng: &mut dyn RngCore,
+    ) {
+        self.crossover(s1, s2, idx);
+    }
+
+    /// Like `mutate`, but draws random numbers from |rng|. See
+    /// `crossover_rng`. By default ignores |rng|.
+    fn mutate_rng(&self, s: &mut Self::State, rate: f64, idx: usize, _rng: &mut dyn RngCore) {
+        self.mutate(s, rate, idx);
+    }
+
+    /// Differential evolution variation, used instead of `crossover_rng` with
+    /// `Crossover::Differential`. Moves |target| towards donors[0] plus |f|
+    /// times the difference of donors[1] and donors[2], in each component with
+    /// probability |cr|. The donors are distinct members of the population
+    /// other than |target|. States which are real vectors can implement
+    /// `AsF64Slice` and call `crossover_differential_rng`. By default fails.
+    fn differential_rng(
+        &self,
+        _target: &mut Self::State,
+        _donors: [&Self::State; 3],
+        _f: f64,
+        _cr: f64,
+        _rng: &mut dyn RngCore,
+    ) -> Result<()> {
+        Err(eyre!("differential crossover is not supported by this evaluator"))
+    }
+
     fn fitness(&self, s: &Self::State, data: &Self::Data) -> Result<f64>;
 
+    /// Fitness for evaluators which need random numbers. If
+    /// `EvolveCfg::fitness_seed` is set, |rng| is seeded per member so fitness
+    /// is reproducible, including with `par_fitness`. Copies of a state share
+    /// one sample, see `EvolveCfg::share_duplicate_fitness`. By default
+    /// ignores |rng|.
+    fn fitness_rng(
+        &self,
+        s: &Self::State,
+        data: &Self::Data,
+        _rng: &mut dyn RngCore,
+    ) -> Result<f64> {
+        self.fitness(s, data)
+    }
+
     /// Computes fitness over multiple inputs with the given reduction.
     fn multi_fitness(
         &self,
         s: &Self::State,
         inputs: &[Self::Data],
         reduction: FitnessReduction,
+    ) -> Result<f64> {
+        self.multi_fitness_rng(s, inputs, reduction, &mut rand::thread_rng())
+    }
+
+    /// Like `multi_fitness` but uses `fitness_rng` with the given rng.
+    fn multi_fitness_rng(
+        &self,
+        s: &Self::State,
+        inputs: &[Self::Data],
+        reduction: FitnessReduction,
+        rng: &mut dyn RngCore,
     ) -> Result<f64> {
         let mut cumulative = match reduction {
             FitnessReduction::ArithmeticMean => 0.0,
             FitnessReduction::GeometricMean => 1.0,
         };
         for data in inputs {
-            let fitness = self.fitness(s, data)?;
+            let fitness = self.fitness_rng(s, data, rng)?;
             match reduction {
                 FitnessReduction::ArithmeticMean => cumulative += fitness,
                 FitnessReduction::GeometricMean => cumulative *= fitness,
@@ -57,9 +173,107 @@ pub trait Evaluator: Send + Sync {
     }
 
     fn distance(&self, s1: &Self::State, s2: &Self::State) -> Result<f64>;
+
+    /// Total amount by which |s| violates the problem's constraints, with 0
+    /// meaning feasible. Only computed if `EvolveCfg::constraint_mode` or a
+    /// `FitnessStage::Penalty` uses it, in which case it's the maximum over
+    /// all inputs. By default every state is feasible.
+    fn violation(&self, _s: &Self::State, _data: &Self::Data) -> Result<f64> {
+        Ok(0.0)
+    }
+
+    /// Compares |a| against |b| on |data|, with `Greater` meaning |a| wins.
+    /// Only called if `EvolveCfg::comparison` is set, in which case it replaces
+    /// `fitness`. Needn't be transitive or consistent between calls.
+    fn compare(
+        &self,
+        _a: &Self::State,
+        _b: &Self::State,
+        _data: &Self::Data,
+    ) -> Result<cmp::Ordering> {
+        Err(eyre!("comparative fitness needs Evaluator::compare"))
+    }
+
+    /// Positions of |s| which crossover and mutation must leave unchanged,
+    /// for states which are sequences, e.g. a hand-written preamble. Operators
+    /// enforce it themselves, e.g. with the adapters in `ops::frozen`. By
+    /// default nothing is frozen.
+    fn frozen_mask(&self, _s: &Self::State) -> Option<Vec<bool>> {
+        None
+    }
+
+    /// Checks |s| satisfies the evaluator's invariants. Called on every state
+    /// from the evolver's `RandState` generator, which is retried until it
+    /// produces a valid state, see `RAND_STATE_ATTEMPTS`. By default every
+    /// state is valid.
+    fn validate_state(&self, _s: &Self::State) -> Result<()> {
+        Ok(())
+    }
+
+    /// Adjusts |child| after crossover and mutation, given the |parents| it
+    /// was bred from, e.g. to limit how much longer it can get. Not called on
+    /// children which are unchanged copies of a parent. By default does
+    /// nothing.
+    fn repair_child(&self, _child: &mut Self::State, _parents: [&Self::State; 2]) {}
+
+    /// Length of |s|, and of the part of it which can affect fitness, e.g.
+    /// lgp code with and without instructions which can't affect the
+    /// outputs. Reported in `Stats::lengths` so bloat is visible. By default
+    /// states have no length.
+    fn lengths(&self, _s: &Self::State) -> Option<(usize, usize)> {
+        None
+    }
+
+    /// Approximate memory used by |s| in bytes, for `EvolveCfg::low_memory`.
+    /// By default only the state's own size, so states which own heap memory
+    /// should count it too.
+    fn state_bytes(&self, s: &Self::State) -> usize {
+        size_of_val(s)
+    }
+
+    /// Hash of |inputs|, recorded as `EvolveResult::data_fingerprint` so
+    /// generations can be grouped by the data they were evaluated on.
+    /// Evaluators of hashable data can return `Some(data_fingerprint(inputs))`.
+    /// By default None, in which case `Evolver::run_data_batch` records its
+    /// batch id instead.
+    fn data_fingerprint(&self, _inputs: &[Self::Data]) -> Option<u64> {
+        None
+    }
+
+    /// Behaviour descriptor of |s|, placing it in the grid of a
+    /// `MapElitesArchive`. Only computed if the evolver has one, in which case
+    /// it's averaged over all inputs. By default there is no descriptor.
+    fn descriptor(&self, _s: &Self::State, _data: &Self::Data) -> Result<Vec<f64>> {
+        Ok(vec![])
+    }
+
+    /// Improves |s| in place with at most |budget| iterations of local search
+    /// on |data|, for `EvolveCfg::local_search`. Fitness should be evaluated
+    /// through |evals|, which counts the evaluations. By default does nothing.
+    fn local_search(
+        &self,
+        _s: &mut Self::State,
+        _data: &[Self::Data],
+        _budget: usize,
+        _evals: &FitnessEvals<'_, Self::State, Self::Data>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called before evaluating a generation with data from |epoch|, by
+    /// `Evolver::run_data_epoch`. Evaluators which cache anything computed
+    /// from data should not reuse it across epochs. By default does nothing.
+    fn set_data_epoch(&self, _epoch: DataEpoch) {}
+
+    /// Best achievable fitness, if known. Used to report how far a run is from
+    /// the optimum.
+    fn optimum(&self) -> Option<f64> {
+        None
+    }
 }
 
 /// Evaluator which uses an LRU cache to cache fitness and distance values.
+#[cfg(feature = "cache")]
 #[must_use]
 pub struct CachedEvaluator<E: Evaluator>
 where
@@ -67,19 +281,26 @@ where
     E::Data: Hash + Eq,
 {
     eval: E,
-    fitness_cache: Cache<(E::State, E::Data), f64>,
+    fitness_cache: Cache<(DataEpoch, E::State, E::Data), f64>,
+    epoch: AtomicU64,
 }
 
+#[cfg(feature = "cache")]
 impl<E: Evaluator> CachedEvaluator<E>
 where
     E::State: Hash + Eq + 'static,
     E::Data: Hash + Eq + 'static,
 {
     pub fn new(eval: E, cap: usize) -> Self {
-        Self { eval, fitness_cache: Cache::new(cap * 10, cap as i64).unwrap() }
+        Self {
+            eval,
+            fitness_cache: Cache::new(cap * 10, cap as i64).unwrap(),
+            epoch: AtomicU64::new(0),
+        }
     }
 }
 
+#[cfg(feature = "cache")]
 impl<E: Evaluator> Evaluator for CachedEvaluator<E>
 where
     E::State: Hash + Eq + 'static,
@@ -88,6 +309,7 @@ where
     type State = E::State;
     type Data = E::Data;
     const NUM_CROSSOVER: usize = E::NUM_CROSSOVER;
+    const CROSSOVER_HAS_NOOP: bool = E::CROSSOVER_HAS_NOOP;
     const NUM_MUTATION: usize = E::NUM_MUTATION;
 
     fn crossover(&self, s1: &mut Self::State, s2: &mut Self::State, idx: usize) {
@@ -98,12 +320,47 @@ where
         self.eval.mutate(s, rate, idx);
     }
 
+    fn crossover_rng(
+        &self,
+        s1: &mut Self::State,
+        s2: &mut Self::State,
+        idx: usize,
+        rng: &mut dyn RngCore,
+    ) {
+        self.eval.crossover_rng(s1, s2, idx, rng);
+    }
+
+    fn mutate_rng(&self, s: &mut Self::State, rate: f64, idx: usize, rng: &mut dyn RngCore) {
+        self.eval.mutate_rng(s, rate, idx, rng);
+    }
+
+    fn differential_rng(
+        &self,
+        target: &mut Self::State,
+        donors: [&Self::State; 3],
+        f: f64,
+        cr: f64,
+        rng: &mut dyn RngCore,
+    ) -> Result<()> {
+        self.eval.differential_rng(target, donors, f, cr, rng)
+    }
+
     fn fitness(&self, s: &Self::State, data: &Self::Data) -> Result<f64> {
-        let key = (Self::State::clone(s), Self::Data::clone(data));
+        self.fitness_rng(s, data, &mut rand::thread_rng())
+    }
+
+    fn fitness_rng(
+        &self,
+        s: &Self::State,
+        data: &Self::Data,
+        rng: &mut dyn RngCore,
+    ) -> Result<f64> {
+        let epoch = self.epoch.load(Ordering::Relaxed);
+        let key = (epoch, Self::State::clone(s), Self::Data::clone(data));
         if let Some(value) = self.fitness_cache.get(&key) {
             Ok(*value.value())
         } else {
-            let value = self.eval.fitness(s, data)?;
+            let value = self.eval.fitness_rng(s, data, rng)?;
             self.fitness_cache.insert(key, value, 1);
             Ok(value)
         }
@@ -112,4 +369,91 @@ where
     fn distance(&self, s1: &Self::State, s2: &Self::State) -> Result<f64> {
         self.eval.distance(s1, s2)
     }
+
+    fn violation(&self, s: &Self::State, data: &Self::Data) -> Result<f64> {
+        self.eval.violation(s, data)
+    }
+
+    fn compare(
+        &self,
+        a: &Self::State,
+        b: &Self::State,
+        data: &Self::Data,
+    ) -> Result<cmp::Ordering> {
+        self.eval.compare(a, b, data)
+    }
+
+    fn frozen_mask(&self, s: &Self::State) -> Option<Vec<bool>> {
+        self.eval.frozen_mask(s)
+    }
+
+    fn validate_state(&self, s: &Self::State) -> Result<()> {
+        self.eval.validate_state(s)
+    }
+
+    fn repair_child(&self, child: &mut Self::State, parents: [&Self::State; 2]) {
+        self.eval.repair_child(child, parents);
+    }
+
+    fn lengths(&self, s: &Self::State) -> Option<(usize, usize)> {
+        self.eval.lengths(s)
+    }
+
+    fn state_bytes(&self, s: &Self::State) -> usize {
+        self.eval.state_bytes(s)
+    }
+
+    fn data_fingerprint(&self, inputs: &[Self::Data]) -> Option<u64> {
+        self.eval.data_fingerprint(inputs)
+    }
+
+    fn descriptor(&self, s: &Self::State, data: &Self::Data) -> Result<Vec<f64>> {
+        self.eval.descriptor(s, data)
+    }
+
+    fn local_search(
+        &self,
+        s: &mut Self::State,
+        data: &[Self::Data],
+        budget: usize,
+        evals: &FitnessEvals<'_, Self::State, Self::Data>,
+    ) -> Result<()> {
+        self.eval.local_search(s, data, budget, evals)
+    }
+
+    fn set_data_epoch(&self, epoch: DataEpoch) {
+        // Entries from old epochs are never looked up again, so they get
+        // evicted as the cache fills.
+        self.epoch.store(epoch, Ordering::Relaxed);
+        self.eval.set_data_epoch(epoch);
+    }
+
+    fn optimum(&self) -> Option<f64> {
+        self.eval.optimum()
+    }
+}
+
+#[cfg(all(test, feature = "cache"))]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::util::test_utils::MockEvaluator;
+
+    #[test]
+    fn cache_invalidated_by_epoch() -> Result<()> {
+        let eval = CachedEvaluator::new(MockEvaluator::new(), 100);
+        let calls = |eval: &CachedEvaluator<MockEvaluator>| -> Result<usize> {
+            let _ = eval.fitness(&1, &())?;
+            // Inserts are buffered, so wait for them to land.
+            eval.fitness_cache.wait()?;
+            Ok(eval.eval.fitness_calls.load(Ordering::Relaxed))
+        };
+        assert_eq!(calls(&eval)?, 1);
+        assert_eq!(calls(&eval)?, 1);
+        eval.set_data_epoch(1);
+        assert_eq!(calls(&eval)?, 2);
+        assert_eq!(calls(&eval)?, 2);
+        Ok(())
+    }
 }
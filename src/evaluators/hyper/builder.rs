@@ -6,6 +6,8 @@ use crate::evaluators::hyper::eval::{HyperEvaluator, HyperState, StatFn};
 use crate::evolve::cfg::EvolveCfg;
 use crate::evolve::evolver::{CreateEvolverFn, Evolver};
 use crate::evolve::result::Stats;
+use crate::train::sampler::DataSampler;
+use crate::tuning::search::run_cfg;
 
 #[must_use]
 pub struct HyperBuilder {
@@ -40,8 +42,8 @@ impl HyperBuilder {
             }
 
             // Get the last run that ran in time.
-            if let Some(mut r) = r1 {
-                let mut stats = Stats::from_result(&mut r);
+            if let Some(r) = r1 {
+                let mut stats = Stats::from_result(&r);
                 stats.best_fitness /= max_fitness;
                 stats.mean_fitness /= max_fitness;
                 Ok(Some(stats))
@@ -51,6 +53,26 @@ impl HyperBuilder {
         }));
     }
 
+    /// Like `add`, but runs the evolver for a fixed number of generations on
+    /// data from |sampler| instead of for the sample duration.
+    pub fn add_sampled<F: CreateEvolverFn<E>, E: Evaluator>(
+        &mut self,
+        max_fitness: f64,
+        generations: usize,
+        f: F,
+        sampler: impl DataSampler<E::Data> + Send + Sync + 'static,
+    ) {
+        assert!(generations > 0, "must run at least one generation");
+        self.num_crossover = self.num_crossover.max(E::NUM_CROSSOVER);
+        self.num_mutation = self.num_mutation.max(E::NUM_MUTATION);
+        self.stat_fns.push(Box::new(move |cfg| {
+            let mut stats = run_cfg(&cfg, &f, &sampler, generations)?;
+            stats.best_fitness /= max_fitness;
+            stats.mean_fitness /= max_fitness;
+            Ok(Some(stats))
+        }));
+    }
+
     pub fn build(self, cfg: EvolveCfg) -> Evolver<HyperEvaluator> {
         let pop_size = self.pop_size;
         let num_crossover = self.num_crossover;
@@ -8,7 +8,7 @@ use crate::eval::Evaluator;
 use crate::evolve::cfg::{Crossover, EvolveCfg, Mutation};
 use crate::evolve::evolver::CreateEvolverFn;
 use crate::evolve::result::Stats;
-use crate::ops::crossover::crossover_blx;
+use crate::ops::crossover::{crossover_blx, crossover_swap_k};
 use crate::ops::distance::dist2;
 use crate::ops::mutation::{mutate_normal, mutate_rate};
 use crate::ops::util::rand_vec;
@@ -34,8 +34,14 @@ impl HyperState {
         cfg.selection = r.gen();
         cfg.niching = r.gen();
         cfg.species = r.gen();
+        cfg.params_crossover = r.gen();
         HyperState { cfg, crossover, mutation }
     }
+
+    /// The config this state evaluates with.
+    pub fn cfg(&self) -> &EvolveCfg {
+        &self.cfg
+    }
 }
 
 #[must_use]
@@ -51,8 +57,8 @@ impl HyperEvaluator {
 
 impl Evaluator for HyperEvaluator {
     type State = HyperState;
-    const NUM_CROSSOVER: usize = 4;
-    const NUM_MUTATION: usize = 10;
+    const NUM_CROSSOVER: usize = 5;
+    const NUM_MUTATION: usize = 11;
 
     fn crossover(&self, s1: &mut Self::State, s2: &mut Self::State, idx: usize) {
         let mut r = rand::thread_rng();
@@ -84,9 +90,17 @@ impl Evaluator for HyperEvaluator {
                 if r.gen::<bool>() {
                     swap(&mut s1.cfg.duplicates, &mut s2.cfg.duplicates);
                 }
+                if r.gen::<bool>() {
+                    swap(&mut s1.cfg.params_crossover, &mut s2.cfg.params_crossover);
+                }
             }
             2 => crossover_blx(&mut s1.crossover, &mut s2.crossover, 0.5),
             3 => crossover_blx(&mut s1.mutation, &mut s2.mutation, 0.5),
+            4 => {
+                // Exchange a single weight of each.
+                crossover_swap_k(&mut s1.crossover, &mut s2.crossover, 1);
+                crossover_swap_k(&mut s1.mutation, &mut s2.mutation, 1);
+            }
             _ => panic!("bug"),
         }
     }
@@ -105,6 +119,8 @@ impl Evaluator for HyperEvaluator {
                         Crossover::Adaptive => {
                             s.cfg.crossover = Crossover::Fixed(s.crossover.clone());
                         }
+                        // Not a weighting of operators, so left alone.
+                        Crossover::Differential { .. } => {}
                     }
                 }
             }
@@ -117,6 +133,7 @@ impl Evaluator for HyperEvaluator {
                     Crossover::Adaptive => {
                         mutate_rate(&mut s.crossover, 1.0, |v| mutate_normal(v, rate).max(0.0));
                     }
+                    Crossover::Differential { .. } => {}
                 }
             }
             2 => {
@@ -174,6 +191,11 @@ impl Evaluator for HyperEvaluator {
                     s.cfg.duplicates = r.gen();
                 }
             }
+            10 => {
+                if r.gen_bool(rate) {
+                    s.cfg.params_crossover = r.gen();
+                }
+            }
             _ => panic!("bug"),
         }
     }
@@ -0,0 +1,321 @@
+use std::fmt;
+use std::mem::swap;
+
+use enumset::EnumSet;
+use eyre::Result;
+use rand::prelude::IteratorRandom;
+use rand::Rng;
+use strum::IntoEnumIterator;
+
+use crate::eval::Evaluator;
+use crate::evaluators::lgp::cfg::LgpEvaluatorCfg;
+use crate::evaluators::lgp::vm::opcode::Opcode;
+use crate::evolve::cfg::EvolveCfg;
+use crate::evolve::evolver::Evolver;
+use crate::ops::mutation::{mutate_lognorm, mutate_normal, reflect_into};
+use crate::train::sampler::DataSampler;
+use crate::tuning::search::{run_cfg, SearchBudget};
+
+pub trait CreateLgpEvolverFn<E: Evaluator> =
+    Fn(LgpEvaluatorCfg, EvolveCfg) -> Evolver<E> + Sync + Send + Clone + 'static;
+pub trait LgpStatFn = Fn(LgpEvaluatorCfg) -> Result<f64> + Send + Sync;
+
+/// Immediate values are searched within plus or minus this.
+pub const IMM_LIMIT: f64 = 1e4;
+/// Largest code size searched.
+pub const MAX_CODE_LIMIT: usize = 1000;
+
+/// Lgp config being searched over. Only the opcodes, immediate range, code
+/// size and number of registers are evolved, everything else comes from the
+/// base config.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct LgpHyperState {
+    cfg: LgpEvaluatorCfg,
+}
+
+impl fmt::Display for LgpHyperState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (lo, hi) = self.cfg.imm_range();
+        write!(
+            f,
+            "opcodes: {:?}, imm: [{lo:.3}, {hi:.3}], max code: {}, regs: {}",
+            self.cfg.opcodes().iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+            self.cfg.max_code(),
+            self.cfg.num_reg()
+        )
+    }
+}
+
+impl LgpHyperState {
+    pub fn new(cfg: LgpEvaluatorCfg) -> Self {
+        let mut s = Self { cfg };
+        s.repair();
+        s
+    }
+
+    /// Random config based on |base|.
+    pub fn rand(base: &LgpEvaluatorCfg, reg_range: (usize, usize)) -> Self {
+        let mut r = rand::thread_rng();
+        let opcodes = Opcode::iter().filter(|_| r.gen::<bool>()).collect();
+        let imm = r.gen_range(1.0..=IMM_LIMIT);
+        let cfg = base
+            .clone()
+            .set_opcodes(opcodes)
+            .set_imm_range((-imm, imm))
+            .set_max_code(r.gen_range(1..=MAX_CODE_LIMIT))
+            .set_num_reg(r.gen_range(reg_range.0..=reg_range.1));
+        Self::new(cfg)
+    }
+
+    /// The config this state evaluates with.
+    pub fn cfg(&self) -> &LgpEvaluatorCfg {
+        &self.cfg
+    }
+
+    // Programs need at least one arithmetic opcode to compute anything, and
+    // Load to get constants.
+    fn repair(&mut self) {
+        let mut opcodes = self.cfg.opcodes() | Opcode::Load;
+        if !opcodes.iter().any(|v| v.is_arithmetic()) {
+            let mut r = rand::thread_rng();
+            opcodes |= Opcode::iter().filter(Opcode::is_arithmetic).choose(&mut r).unwrap();
+        }
+        self.cfg = self.cfg.clone().set_opcodes(opcodes);
+    }
+}
+
+/// Searches over lgp configs, scoring each by the best fitness of a short
+/// inner run of the user's problem.
+///
+/// Fitness functions of the inner problem must size registers using
+/// `LgpState::num_reg`, since the number of registers is evolved.
+#[must_use]
+pub struct LgpHyperEvaluator {
+    base: LgpEvaluatorCfg,
+    reg_range: (usize, usize),
+    stat_fn: Box<dyn LgpStatFn>,
+}
+
+impl LgpHyperEvaluator {
+    /// |f| creates the inner evolver for a given lgp config, which is run with
+    /// |cfg| on data from |sampler| for the given budget. Fitness is the best
+    /// fitness of the final generation, averaged over the repeats.
+    pub fn new<F: CreateLgpEvolverFn<E>, E: Evaluator>(
+        base: LgpEvaluatorCfg,
+        cfg: EvolveCfg,
+        budget: SearchBudget,
+        f: F,
+        sampler: impl DataSampler<E::Data> + Send + Sync + 'static,
+    ) -> Self {
+        assert!(budget.generations > 0 && budget.repeats > 0, "budget must be non-empty");
+        // Outputs must stay in registers and memory is addressed by u8.
+        let min_reg = base.output_regs().iter().map(|&v| v as usize + 1).max().unwrap_or(1);
+        let max_reg = (base.num_reg() * 2).clamp(min_reg, 256 - base.num_const());
+        let stat_fn = move |lgpcfg: LgpEvaluatorCfg| {
+            let f = f.clone();
+            let create = move |cfg| f(lgpcfg.clone(), cfg);
+            let mut fitness = 0.0;
+            for _ in 0..budget.repeats {
+                fitness += run_cfg(&cfg, &create, &sampler, budget.generations)?.best_fitness;
+            }
+            Ok(fitness / budget.repeats as f64)
+        };
+        Self { base, reg_range: (min_reg, max_reg), stat_fn: Box::new(stat_fn) }
+    }
+
+    pub fn rand_state(&self) -> LgpHyperState {
+        LgpHyperState::rand(&self.base, self.reg_range)
+    }
+}
+
+pub fn lgp_hyper_evolver(eval: LgpHyperEvaluator, cfg: EvolveCfg) -> Evolver<LgpHyperEvaluator> {
+    let (base, reg_range) = (eval.base.clone(), eval.reg_range);
+    Evolver::new(eval, cfg, move || LgpHyperState::rand(&base, reg_range))
+}
+
+impl Evaluator for LgpHyperEvaluator {
+    type State = LgpHyperState;
+    const NUM_CROSSOVER: usize = 2;
+    const NUM_MUTATION: usize = 4;
+
+    fn crossover(&self, s1: &mut Self::State, s2: &mut Self::State, idx: usize) {
+        let mut r = rand::thread_rng();
+        match idx {
+            0 => {}
+            1 => {
+                // Uniform crossover, including each bit of the opcode set.
+                let (mut c1, mut c2) = (s1.cfg.clone(), s2.cfg.clone());
+                let mask = Opcode::iter().filter(|_| r.gen::<bool>()).collect::<EnumSet<_>>();
+                let (o1, o2) = (c1.opcodes(), c2.opcodes());
+                c1 = c1.set_opcodes((o1 - mask) | (o2 & mask));
+                c2 = c2.set_opcodes((o2 - mask) | (o1 & mask));
+                if r.gen::<bool>() {
+                    let (i1, i2) = (c1.imm_range(), c2.imm_range());
+                    c1 = c1.set_imm_range(i2);
+                    c2 = c2.set_imm_range(i1);
+                }
+                if r.gen::<bool>() {
+                    let (m1, m2) = (c1.max_code(), c2.max_code());
+                    c1 = c1.set_max_code(m2);
+                    c2 = c2.set_max_code(m1);
+                }
+                if r.gen::<bool>() {
+                    let (n1, n2) = (c1.num_reg(), c2.num_reg());
+                    c1 = c1.set_num_reg(n2);
+                    c2 = c2.set_num_reg(n1);
+                }
+                swap(&mut s1.cfg, &mut c1);
+                swap(&mut s2.cfg, &mut c2);
+                s1.repair();
+                s2.repair();
+            }
+            _ => panic!("bug"),
+        }
+    }
+
+    fn mutate(&self, s: &mut Self::State, rate: f64, idx: usize) {
+        let mut r = rand::thread_rng();
+        let cfg = s.cfg.clone();
+        s.cfg = match idx {
+            0 => {
+                // Toggle each opcode with the given rate.
+                let toggle = Opcode::iter().filter(|_| r.gen_bool(rate)).collect::<EnumSet<_>>();
+                let opcodes = cfg.opcodes() ^ toggle;
+                cfg.set_opcodes(opcodes)
+            }
+            1 => {
+                let (lo, hi) = cfg.imm_range();
+                let std = rate * (hi - lo).max(1.0);
+                let lo = reflect_into(mutate_normal(lo, std), -IMM_LIMIT, IMM_LIMIT);
+                let hi = reflect_into(mutate_normal(hi, std), -IMM_LIMIT, IMM_LIMIT);
+                cfg.set_imm_range((lo.min(hi), lo.max(hi)))
+            }
+            2 => {
+                let max_code = mutate_lognorm(cfg.max_code() as f64, rate);
+                let max_code = reflect_into(max_code, 1.0, MAX_CODE_LIMIT as f64).round();
+                cfg.set_max_code(max_code as usize)
+            }
+            3 => {
+                if r.gen_bool(rate) {
+                    let (min, max) = self.reg_range;
+                    let num_reg =
+                        if r.gen::<bool>() { cfg.num_reg() + 1 } else { cfg.num_reg() - 1 };
+                    cfg.set_num_reg(num_reg.clamp(min, max))
+                } else {
+                    cfg
+                }
+            }
+            _ => panic!("bug"),
+        };
+        s.repair();
+    }
+
+    fn fitness(&self, s: &Self::State, _data: &Self::Data) -> Result<f64> {
+        (self.stat_fn)(s.cfg.clone())
+    }
+
+    fn distance(&self, s1: &Self::State, s2: &Self::State) -> Result<f64> {
+        let (c1, c2) = (&s1.cfg, &s2.cfg);
+        // Differing opcodes count for one each, numeric parameters for at most
+        // one each.
+        let mut dist = (c1.opcodes() ^ c2.opcodes()).len() as f64;
+        let ((lo1, hi1), (lo2, hi2)) = (c1.imm_range(), c2.imm_range());
+        dist += ((lo1 - lo2).abs() + (hi1 - hi2).abs()) / (4.0 * IMM_LIMIT);
+        dist += c1.max_code().abs_diff(c2.max_code()) as f64 / MAX_CODE_LIMIT as f64;
+        let reg_span = (self.reg_range.1 - self.reg_range.0).max(1);
+        dist += c1.num_reg().abs_diff(c2.num_reg()) as f64 / reg_span as f64;
+        Ok(dist)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::evaluators::lgp::builder::lgp_fitness_evolver;
+    use crate::evaluators::lgp::eval::LgpState;
+    use crate::evaluators::lgp::vm::lgpvm::LgpVm;
+    use crate::train::sampler::EmptyDataSampler;
+
+    // Inner problem: output 5.
+    fn constant_fitness(s: &LgpState) -> f64 {
+        let vmcfg = s.lgpvmcfg(&vec![0.0; s.num_reg()], &[]);
+        let mut vm = LgpVm::new(&vmcfg);
+        vm.run();
+        1.0 / (1.0 + (vm.mem(0) - 5.0).abs())
+    }
+
+    fn eval(generations: usize) -> LgpHyperEvaluator {
+        LgpHyperEvaluator::new(
+            LgpEvaluatorCfg::new(),
+            EvolveCfg::new(10),
+            SearchBudget::new(generations, 1),
+            |lgpcfg, cfg| {
+                lgp_fitness_evolver(lgpcfg, cfg, |s: &LgpState, (): &()| Ok(constant_fitness(s)))
+            },
+            EmptyDataSampler {},
+        )
+    }
+
+    fn valid(s: &LgpHyperState, eval: &LgpHyperEvaluator) -> bool {
+        let cfg = s.cfg();
+        let (lo, hi) = cfg.imm_range();
+        cfg.opcodes().contains(Opcode::Load)
+            && cfg.opcodes().iter().any(|v| v.is_arithmetic())
+            && -IMM_LIMIT <= lo
+            && lo <= hi
+            && hi <= IMM_LIMIT
+            && (1..=MAX_CODE_LIMIT).contains(&cfg.max_code())
+            && (eval.reg_range.0..=eval.reg_range.1).contains(&cfg.num_reg())
+    }
+
+    #[test]
+    fn operators_keep_constraints() {
+        let eval = eval(1);
+        let mut r = rand::thread_rng();
+        let mut states = (0..10).map(|_| eval.rand_state()).collect::<Vec<_>>();
+        for _ in 0..5000 {
+            let i = r.gen_range(0..states.len());
+            let j = r.gen_range(0..states.len());
+            let (mut s1, mut s2) = (states[i].clone(), states[j].clone());
+            eval.crossover(&mut s1, &mut s2, r.gen_range(0..LgpHyperEvaluator::NUM_CROSSOVER));
+            eval.mutate(&mut s1, r.gen(), r.gen_range(0..LgpHyperEvaluator::NUM_MUTATION));
+            assert!(valid(&s1, &eval), "{s1}");
+            assert!(valid(&s2, &eval), "{s2}");
+            states[i] = s1;
+            states[j] = s2;
+        }
+
+        // Turning off every opcode still leaves Load and an arithmetic opcode.
+        let s = LgpHyperState::new(LgpEvaluatorCfg::new().set_opcodes(EnumSet::empty()));
+        assert!(valid(&s, &eval));
+        assert_eq!(s.cfg().opcodes().len(), 2);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn distance() -> Result<()> {
+        let eval = eval(1);
+        let base = LgpEvaluatorCfg::new();
+        let s1 = LgpHyperState::new(base.clone());
+        assert_eq!(eval.distance(&s1, &s1)?, 0.0);
+        let s2 = LgpHyperState::new(base.clone().set_opcodes(base.opcodes() - Opcode::Sin));
+        assert_eq!(eval.distance(&s1, &s2)?, 1.0);
+        let s3 = LgpHyperState::new(base.clone().set_max_code(base.max_code() + 100));
+        assert_eq!(eval.distance(&s1, &s3)?, 0.1);
+        Ok(())
+    }
+
+    #[test]
+    fn smoke() -> Result<()> {
+        let mut evolver = lgp_hyper_evolver(eval(2), EvolveCfg::new(4));
+        for _ in 0..2 {
+            let r = evolver.run()?;
+            assert!(r.nth(0).fitness > 0.0);
+            assert!(valid(&r.nth(0).state, evolver.eval()));
+        }
+        Ok(())
+    }
+}
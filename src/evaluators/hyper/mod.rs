@@ -1,2 +1,4 @@
 pub mod builder;
 pub mod eval;
+#[cfg(feature = "lgp")]
+pub mod lgp;
@@ -0,0 +1,152 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use eyre::Result;
+use rand::RngCore;
+
+use crate::eval::{Data, Evaluator};
+use crate::evolve::cfg::EvolveCfg;
+use crate::evolve::evolver::Evolver;
+use crate::ops::crossover::crossover_kpx_rng;
+use crate::ops::distance::count_different;
+use crate::ops::encoding::IntEncoding;
+
+pub trait IntFitnessFn<D: Data> = Fn(&[u64], &D) -> Result<f64> + Sync + Send + Clone;
+
+/// Bit vector genome encoding a vector of integers.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Hash)]
+pub struct IntVecState(pub Vec<bool>);
+
+impl fmt::Display for IntVecState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &b in &self.0 {
+            write!(f, "{}", u8::from(b))?;
+        }
+        Ok(())
+    }
+}
+
+/// Evolves integers packed into bits by an `IntEncoding`. The fitness
+/// function sees the decoded integers.
+#[must_use]
+pub struct IntVecEvaluator<D: Data, F: IntFitnessFn<D>> {
+    enc: IntEncoding,
+    f: F,
+    _u: PhantomData<D>,
+}
+
+impl<D: Data, F: IntFitnessFn<D>> IntVecEvaluator<D, F> {
+    pub fn new(enc: IntEncoding, f: F) -> Self {
+        Self { enc, f, _u: PhantomData }
+    }
+
+    pub fn enc(&self) -> &IntEncoding {
+        &self.enc
+    }
+
+    #[must_use]
+    pub fn decode(&self, s: &IntVecState) -> Vec<u64> {
+        self.enc.decode(&s.0)
+    }
+}
+
+impl<D: Data, F: IntFitnessFn<D>> Evaluator for IntVecEvaluator<D, F> {
+    type State = IntVecState;
+    type Data = D;
+    const NUM_CROSSOVER: usize = 3;
+
+    fn crossover(&self, s1: &mut Self::State, s2: &mut Self::State, idx: usize) {
+        self.crossover_rng(s1, s2, idx, &mut rand::thread_rng());
+    }
+
+    fn mutate(&self, s: &mut Self::State, rate: f64, idx: usize) {
+        self.mutate_rng(s, rate, idx, &mut rand::thread_rng());
+    }
+
+    fn crossover_rng(
+        &self,
+        s1: &mut Self::State,
+        s2: &mut Self::State,
+        idx: usize,
+        r: &mut dyn RngCore,
+    ) {
+        match idx {
+            0 => {}
+            1 => crossover_kpx_rng(&mut s1.0, &mut s2.0, 2, r),
+            2 => self.enc.crossover_ux_rng(&mut s1.0, &mut s2.0, r),
+            _ => panic!("unknown crossover strategy"),
+        }
+    }
+
+    fn mutate_rng(&self, s: &mut Self::State, rate: f64, idx: usize, r: &mut dyn RngCore) {
+        match idx {
+            0 => self.enc.mutate_rng(&mut s.0, rate, r),
+            _ => panic!("unknown mutation strategy"),
+        }
+    }
+
+    fn fitness(&self, s: &Self::State, data: &Self::Data) -> Result<f64> {
+        (self.f)(&self.decode(s), data)
+    }
+
+    fn distance(&self, s1: &Self::State, s2: &Self::State) -> Result<f64> {
+        Ok(count_different(&s1.0, &s2.0) as f64)
+    }
+}
+
+pub fn int_vec_evolver<D: Data, F: IntFitnessFn<D>>(
+    enc: IntEncoding,
+    cfg: EvolveCfg,
+    f: F,
+) -> Evolver<IntVecEvaluator<D, F>> {
+    let init = enc.clone();
+    Evolver::new(IntVecEvaluator::new(enc, f), cfg, move || IntVecState(init.rand()))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::evolve::cfg::{Crossover, Mutation};
+    use crate::ops::encoding::IntCoding;
+
+    // Generations taken to find the minimum of |x - 37| + |y - 11|, capped at
+    // |max_gen|, for the run seeded with |seed|.
+    fn gens_to_solve(coding: IntCoding, max_gen: usize, seed: u64) -> Result<usize> {
+        let enc = IntEncoding::new(&[64, 64], coding);
+        // Mutation only, so the comparison is between the mutation landscapes.
+        let cfg = EvolveCfg::new(20)
+            .set_crossover(Crossover::Fixed(vec![1.0, 0.0, 0.0]))
+            .set_mutation(Mutation::Fixed(vec![1.0 / 12.0]));
+        let eval = IntVecEvaluator::new(enc.clone(), |v: &[u64], _data: &()| {
+            Ok(1.0 / (1.0 + v[0].abs_diff(37) as f64 + v[1].abs_diff(11) as f64))
+        });
+        let mut r = StdRng::seed_from_u64(seed);
+        let mut evolver =
+            Evolver::new_seeded(eval, cfg, move || IntVecState(enc.rand_rng(&mut r)), seed);
+        for gen in 0..max_gen {
+            if evolver.run()?.nth(0).fitness >= 1.0 {
+                return Ok(gen);
+            }
+        }
+        Ok(max_gen)
+    }
+
+    #[test]
+    fn gray_beats_binary() -> Result<()> {
+        // Sum over a few seeds, since single runs vary a lot.
+        const RUNS: u64 = 20;
+        const MAX_GEN: usize = 300;
+        let mut binary = 0;
+        let mut gray = 0;
+        for seed in 0..RUNS {
+            binary += gens_to_solve(IntCoding::Binary, MAX_GEN, seed)?;
+            gray += gens_to_solve(IntCoding::Gray, MAX_GEN, seed)?;
+        }
+        assert!(gray < binary, "gray {gray} vs binary {binary}");
+        Ok(())
+    }
+}
@@ -1,29 +1,47 @@
-use eyre::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use eyre::{eyre, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
 
 use crate::eval::{Data, Evaluator, FitnessFn};
 use crate::evaluators::lgp::cfg::LgpEvaluatorCfg;
 use crate::evaluators::lgp::eval::{LgpEvaluator, LgpState};
 use crate::evolve::cfg::EvolveCfg;
 use crate::evolve::evolver::Evolver;
-use crate::ops::mutation::mutate_normal;
+use crate::ops::mutation::mutate_normal_rng;
 use crate::ops::util::rand_vec;
 
+/// Fitness function for `LgpFitnessFnEvaluator`. Also given the evaluator's
+/// cancellation flag, which vms it runs should pass to `LgpVm::run_with_cancel`.
+pub trait LgpFitnessFn<D: Data = ()> =
+    Fn(&LgpState, &D, &AtomicBool) -> Result<f64> + Sync + Send + Clone;
+
 #[must_use]
-pub struct LgpFitnessFnEvaluator<D: Data, F: FitnessFn<LgpState, D>> {
+pub struct LgpFitnessFnEvaluator<D: Data, F: LgpFitnessFn<D>> {
     evaluator: LgpEvaluator<D>,
     f: F,
+    cancel: Option<Arc<AtomicBool>>,
 }
 
-impl<D: Data, F: FitnessFn<LgpState, D>> LgpFitnessFnEvaluator<D, F> {
+impl<D: Data, F: LgpFitnessFn<D>> LgpFitnessFnEvaluator<D, F> {
     pub fn new(evaluator: LgpEvaluator<D>, f: F) -> Self {
-        Self { evaluator, f }
+        Self { evaluator, f, cancel: None }
+    }
+
+    /// Passes |cancel| to the fitness function, so vms it runs stop when it's
+    /// set, e.g. by an evaluation timeout, and fitness then fails.
+    pub fn set_cancel(self, cancel: Option<Arc<AtomicBool>>) -> Self {
+        Self { cancel, ..self }
     }
 }
 
-impl<D: Data, F: FitnessFn<LgpState, D>> Evaluator for LgpFitnessFnEvaluator<D, F> {
+impl<D: Data, F: LgpFitnessFn<D>> Evaluator for LgpFitnessFnEvaluator<D, F> {
     type State = <LgpEvaluator<D> as Evaluator>::State;
     type Data = <LgpEvaluator<D> as Evaluator>::Data;
     const NUM_CROSSOVER: usize = LgpEvaluator::<D>::NUM_CROSSOVER;
+    const CROSSOVER_HAS_NOOP: bool = LgpEvaluator::<D>::CROSSOVER_HAS_NOOP;
     const NUM_MUTATION: usize = LgpEvaluator::<D>::NUM_MUTATION;
 
     fn crossover(&self, s1: &mut Self::State, s2: &mut Self::State, idx: usize) {
@@ -34,13 +52,64 @@ impl<D: Data, F: FitnessFn<LgpState, D>> Evaluator for LgpFitnessFnEvaluator<D,
         self.evaluator.mutate(s, rate, idx);
     }
 
+    fn crossover_rng(
+        &self,
+        s1: &mut Self::State,
+        s2: &mut Self::State,
+        idx: usize,
+        rng: &mut dyn RngCore,
+    ) {
+        self.evaluator.crossover_rng(s1, s2, idx, rng);
+    }
+
+    fn mutate_rng(&self, s: &mut Self::State, rate: f64, idx: usize, rng: &mut dyn RngCore) {
+        self.evaluator.mutate_rng(s, rate, idx, rng);
+    }
+
+    fn differential_rng(
+        &self,
+        target: &mut Self::State,
+        donors: [&Self::State; 3],
+        f: f64,
+        cr: f64,
+        rng: &mut dyn RngCore,
+    ) -> Result<()> {
+        self.evaluator.differential_rng(target, donors, f, cr, rng)
+    }
+
     fn fitness(&self, s: &Self::State, data: &Self::Data) -> Result<f64> {
-        (self.f)(s, data)
+        // Without a flag, vms are given one which is never set.
+        let never = AtomicBool::new(false);
+        let cancel = self.cancel.as_deref().unwrap_or(&never);
+        let fitness = (self.f)(s, data, cancel)?;
+        if cancel.load(Ordering::Relaxed) {
+            return Err(eyre!("fitness evaluation cancelled"));
+        }
+        match self.evaluator.cfg().no_output_penalty() {
+            Some(penalty) if !s.writes_outputs() => Ok(fitness * penalty),
+            _ => Ok(fitness),
+        }
     }
 
     fn distance(&self, s1: &Self::State, s2: &Self::State) -> Result<f64> {
         self.evaluator.distance(s1, s2)
     }
+
+    fn frozen_mask(&self, s: &Self::State) -> Option<Vec<bool>> {
+        self.evaluator.frozen_mask(s)
+    }
+
+    fn validate_state(&self, s: &Self::State) -> Result<()> {
+        self.evaluator.validate_state(s)
+    }
+
+    fn repair_child(&self, child: &mut Self::State, parents: [&Self::State; 2]) {
+        self.evaluator.repair_child(child, parents);
+    }
+
+    fn lengths(&self, s: &Self::State) -> Option<(usize, usize)> {
+        self.evaluator.lengths(s)
+    }
 }
 
 pub fn lgp_create_evolver<
@@ -52,23 +121,126 @@ pub fn lgp_create_evolver<
     cfg: EvolveCfg,
     f: F,
 ) -> Evolver<E> {
+    let eval = f(LgpEvaluator::new(lgpcfg.clone()));
+    Evolver::new(eval, cfg, move || lgp_rand_state(&lgpcfg, &mut rand::thread_rng()))
+}
+
+// Random initial state for |lgpcfg|.
+fn lgp_rand_state<R: Rng + ?Sized>(lgpcfg: &LgpEvaluatorCfg, r: &mut R) -> LgpState {
     const INITIAL_LENGTH_MEAN: f64 = 10.0;
     const INITIAL_LENGTH_STD: f64 = 2.0;
 
-    Evolver::new(f(LgpEvaluator::new(lgpcfg.clone())), cfg, move || {
-        // Better to start with small-ish programs, even if the max code
-        // length is high.
-        let length = mutate_normal(INITIAL_LENGTH_MEAN, INITIAL_LENGTH_STD).round() as usize;
-        let length = length.clamp(1, lgpcfg.max_code());
-        let ops = rand_vec(length, || lgpcfg.rand_op());
-        LgpState::new(ops, lgpcfg.num_reg(), lgpcfg.num_const(), lgpcfg.output_regs())
-    })
+    // Better to start with small-ish programs, even if the max code
+    // length is high. Random code goes after the preamble.
+    let length = mutate_normal_rng(INITIAL_LENGTH_MEAN, INITIAL_LENGTH_STD, r).round() as usize;
+    let preamble = lgpcfg.preamble();
+    let length = length.clamp(1, lgpcfg.max_code().saturating_sub(preamble.len()).max(1));
+    let ops = [preamble, &rand_vec(length, || lgpcfg.rand_op_rng(r))].concat();
+    let mut s = LgpState::new(ops, lgpcfg.num_reg(), lgpcfg.num_const(), lgpcfg.output_regs());
+    if lgpcfg.ensure_output_writes() && !s.writes_outputs() {
+        let op = lgpcfg.rand_output_write_rng(r);
+        *s.ops_unopt_mut().last_mut().unwrap() = op;
+    }
+    s
 }
 
 pub fn lgp_fitness_evolver<D: Data, F: FitnessFn<LgpState, D>>(
     lgpcfg: LgpEvaluatorCfg,
     cfg: EvolveCfg,
     f: F,
-) -> Evolver<impl Evaluator<Data = D>> {
-    lgp_create_evolver(lgpcfg, cfg, |evaluator| LgpFitnessFnEvaluator::new(evaluator, f))
+) -> Evolver<impl Evaluator<State = LgpState, Data = D>> {
+    lgp_create_evolver(lgpcfg, cfg, |evaluator| {
+        LgpFitnessFnEvaluator::new(evaluator, move |s: &LgpState, data: &D, _: &AtomicBool| {
+            f(s, data)
+        })
+    })
+}
+
+/// Like `lgp_fitness_evolver`, but the evolver and the initial programs are
+/// seeded with |seed|, see `Evolver::new_seeded`, so runs are reproducible.
+pub fn lgp_fitness_reproducible_evolver<D: Data, F: FitnessFn<LgpState, D>>(
+    lgpcfg: LgpEvaluatorCfg,
+    cfg: EvolveCfg,
+    f: F,
+    seed: u64,
+) -> Evolver<impl Evaluator<State = LgpState, Data = D>> {
+    let eval = LgpFitnessFnEvaluator::new(
+        LgpEvaluator::new(lgpcfg.clone()),
+        move |s: &LgpState, data: &D, _: &AtomicBool| f(s, data),
+    );
+    let mut r = StdRng::seed_from_u64(seed);
+    Evolver::new_seeded(eval, cfg, move || lgp_rand_state(&lgpcfg, &mut r), seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluators::lgp::vm::asm::lgp_asm;
+    use crate::evaluators::lgp::vm::lgpvm::{LgpVm, RunStatus};
+
+    // Many registers, so most random programs never write to r0.
+    fn lgpcfg() -> LgpEvaluatorCfg {
+        LgpEvaluatorCfg::new().set_num_reg(32)
+    }
+
+    fn initial_states(lgpcfg: LgpEvaluatorCfg) -> Result<Vec<LgpState>> {
+        let mut evolver =
+            lgp_fitness_evolver(lgpcfg, EvolveCfg::new(100), |_: &LgpState, (): &()| Ok(1.0));
+        let r = evolver.run()?;
+        Ok(r.gen.mems.iter().map(|v| (*v.state).clone()).collect())
+    }
+
+    #[test]
+    fn ensure_output_writes() -> Result<()> {
+        assert!(initial_states(lgpcfg())?.iter().any(|s| !s.writes_outputs()));
+        let states = initial_states(lgpcfg().set_ensure_output_writes(true))?;
+        assert!(states.iter().all(LgpState::writes_outputs));
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn no_output_penalty() -> Result<()> {
+        // Both programs leave r0 at zero, but only one writes it.
+        let silent = LgpState::new(lgp_asm("add r1, r2, r3\n")?, 4, 0, &[0]);
+        let writes = LgpState::new(lgp_asm("add r0, r2, r3\n")?, 4, 0, &[0]);
+        assert!(!silent.writes_outputs());
+        assert!(writes.writes_outputs());
+
+        let f = |s: &LgpState, (): &()| {
+            let mut vm = LgpVm::new(&s.lgpvmcfg(&[0.0; 4], &[]));
+            vm.run();
+            Ok(1.0 / (1.0 + vm.mem(0).abs()))
+        };
+        let evolver = lgp_fitness_evolver(lgpcfg(), EvolveCfg::new(1), f);
+        assert_eq!(evolver.eval().fitness(&silent, &())?, evolver.eval().fitness(&writes, &())?);
+
+        let lgpcfg = lgpcfg().set_no_output_penalty(Some(0.5));
+        let evolver = lgp_fitness_evolver(lgpcfg, EvolveCfg::new(1), f);
+        let (silent, writes) =
+            (evolver.eval().fitness(&silent, &())?, evolver.eval().fitness(&writes, &())?);
+        assert!(silent < writes, "{silent} >= {writes}");
+        assert_eq!(silent, 0.5);
+        Ok(())
+    }
+
+    #[test]
+    fn cancel_stops_fitness() -> Result<()> {
+        let s = LgpState::new(lgp_asm("add r0, r1, r2\n")?, 4, 0, &[0]);
+        let f = |s: &LgpState, (): &(), cancel: &AtomicBool| {
+            let mut vm = LgpVm::new(&s.lgpvmcfg(&[0.0; 4], &[]));
+            match vm.run_with_cancel(cancel) {
+                RunStatus::Completed => Ok(1.0),
+                status => Err(eyre!("vm stopped: {status:?}")),
+            }
+        };
+        let cancel = Arc::new(AtomicBool::new(false));
+        let eval = LgpFitnessFnEvaluator::new(LgpEvaluator::new(lgpcfg()), f)
+            .set_cancel(Some(Arc::clone(&cancel)));
+        assert!(eval.fitness(&s, &()).is_ok());
+        cancel.store(true, Ordering::Relaxed);
+        let err = eval.fitness(&s, &()).unwrap_err();
+        assert!(err.to_string().contains("Cancelled"), "{err}");
+        Ok(())
+    }
 }
@@ -1,12 +1,79 @@
+use std::ops::Range;
+
 use enumset::EnumSet;
-use rand::prelude::IteratorRandom;
+use eyre::{eyre, Result};
+use rand::prelude::{IteratorRandom, SliceRandom};
 use rand::Rng;
 use smallvec::{smallvec, SmallVec};
 use strum::IntoEnumIterator;
 
+use crate::evaluators::lgp::vm::lgpvm::LgpVm;
 use crate::evaluators::lgp::vm::op::Op;
-use crate::evaluators::lgp::vm::opcode::{Opcode, Operands};
-use crate::ops::mutation::mutate_normal;
+use crate::evaluators::lgp::vm::opcode::{Opcode, Operands, RegId};
+use crate::ops::mutation::{mutate_normal_rng, reflect_into};
+
+/// Memory layout for a problem with a given number of inputs and outputs.
+///
+/// Registers come first in memory: outputs, then scratch registers. Inputs
+/// are passed in as read only constants after the registers, so programs
+/// can't overwrite them.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LgpRegisterLayout {
+    pub input_indices: Vec<u8>,
+    pub output_indices: Vec<u8>,
+    pub scratch_range: Range<u8>,
+}
+
+impl LgpRegisterLayout {
+    /// Number of scratch registers to give programs in addition to one per
+    /// input.
+    pub const EXTRA_SCRATCH: usize = 4;
+
+    pub fn new(num_inputs: usize, num_outputs: usize) -> Self {
+        let num_reg = num_outputs + num_inputs + Self::EXTRA_SCRATCH;
+        // Indices are u8, so the end of the input range must fit in one too.
+        assert!(num_reg + num_inputs < 256, "cannot use more than 255 memory locations");
+        let num_reg = num_reg as u8;
+        let num_outputs = num_outputs as u8;
+        Self {
+            input_indices: (num_reg..num_reg + num_inputs as u8).collect(),
+            output_indices: (0..num_outputs).collect(),
+            scratch_range: num_outputs..num_reg,
+        }
+    }
+
+    /// Number of read-write registers.
+    #[must_use]
+    pub fn num_reg(&self) -> usize {
+        self.scratch_range.end as usize
+    }
+
+    /// Number of read only constants.
+    #[must_use]
+    pub fn num_const(&self) -> usize {
+        self.input_indices.len()
+    }
+
+    /// Initial values for the registers.
+    #[must_use]
+    pub fn regs(&self) -> Vec<f64> {
+        vec![0.0; self.num_reg()]
+    }
+
+    /// Constants for the given inputs.
+    #[must_use]
+    pub fn constants(&self, inputs: &[f64]) -> Vec<f64> {
+        assert_eq!(inputs.len(), self.input_indices.len(), "inputs length mismatch");
+        inputs.to_vec()
+    }
+
+    /// Reads the outputs after running a program.
+    #[must_use]
+    pub fn outputs(&self, vm: &LgpVm<'_>) -> Vec<f64> {
+        self.output_indices.iter().map(|&idx| vm.mem(idx)).collect()
+    }
+}
 
 #[must_use]
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
@@ -21,6 +88,23 @@ pub struct LgpEvaluatorCfg {
     /// Range randomly generated floating point numbers can be in.
     imm_range: (f64, f64),
     opcodes: EnumSet<Opcode>,
+    /// Probability that a mutation targets the effective code (instructions
+    /// which can affect the outputs) rather than any instruction.
+    effective_mutation_bias: f64,
+    /// Whether randomly generated initial programs which don't write to any
+    /// output register get their last instruction replaced with one that does.
+    ensure_output_writes: bool,
+    /// If set, fitness of programs which don't write to any output register
+    /// is multiplied by this, which should be less than 1. Their outputs are
+    /// just the initial register values, which can look spuriously good.
+    no_output_penalty: Option<f64>,
+    /// Hand-written code every program starts with. It is never mutated,
+    /// removed or crossed over, and counts towards the maximum code length.
+    preamble: Vec<Op>,
+    /// If set, how many instructions longer a child may be than its longer
+    /// parent. Longer children have instructions which can't affect the
+    /// outputs removed first, then are truncated. Limits bloat.
+    growth_limit: Option<usize>,
 }
 
 impl LgpEvaluatorCfg {
@@ -33,86 +117,214 @@ impl LgpEvaluatorCfg {
             imm_sf: 2,
             imm_range: (-100.0, 100.0),
             opcodes: Opcode::iter().collect(),
+            effective_mutation_bias: 0.0,
+            ensure_output_writes: false,
+            no_output_penalty: None,
+            preamble: Vec::new(),
+            growth_limit: None,
         }
     }
 
-    pub fn rand_op(&self) -> Op {
-        let mut r = rand::thread_rng();
-        let mut op = Op::from_code(self.opcodes.iter().choose(&mut r).unwrap());
+    /// Config sized for a problem with the given number of inputs and
+    /// outputs, along with the memory layout fitness functions should use.
+    pub fn for_problem(num_inputs: usize, num_outputs: usize) -> (Self, LgpRegisterLayout) {
+        let layout = LgpRegisterLayout::new(num_inputs, num_outputs);
+        (Self::new().set_layout(&layout), layout)
+    }
 
+    /// Register |r|, checked to be a register rather than a constant, so
+    /// instructions can write to it.
+    pub fn writable_reg(&self, r: u8) -> Result<RegId> {
+        let reg = self.readable_reg(r)?;
+        if r as usize >= self.num_reg {
+            return Err(eyre!("r{r} is a read only constant, num_reg is {}", self.num_reg));
+        }
+        Ok(reg)
+    }
+
+    /// Register or constant |r|, checked to be in memory.
+    pub fn readable_reg(&self, r: u8) -> Result<RegId> {
         let mem_size = self.num_reg + self.num_const;
+        if r as usize >= mem_size {
+            return Err(eyre!("r{r} is out of range, memory size is {mem_size}"));
+        }
+        Ok(RegId::raw(r))
+    }
+
+    /// Instruction with the given operands, checked to be the kind |code|
+    /// takes and to only use registers in memory, writing none of the
+    /// constants.
+    pub fn op(&self, code: Opcode, operands: Operands) -> Result<Op> {
+        let op = Op::try_new(code, operands)?;
+        self.check_op(&op)?;
+        Ok(op)
+    }
+
+    /// Checks that every register |op| writes is writable and every register
+    /// it reads is readable.
+    pub fn check_op(&self, op: &Op) -> Result<()> {
+        let operands = op.operands();
+        for reg in operands.output_regs() {
+            let _ = self.writable_reg(reg.idx()).map_err(|e| eyre!("'{op}': {e}"))?;
+        }
+        for reg in operands.input_regs() {
+            let _ = self.readable_reg(reg.idx()).map_err(|e| eyre!("'{op}': {e}"))?;
+        }
+        Ok(())
+    }
+
+    pub fn rand_op(&self) -> Op {
+        self.rand_op_rng(&mut rand::thread_rng())
+    }
+
+    pub fn rand_op_rng<R: Rng + ?Sized>(&self, r: &mut R) -> Op {
+        self.rand_op_code_rng(self.opcodes.iter().choose(r).unwrap(), r)
+    }
+
+    /// Random instruction which writes to one of the output registers.
+    pub fn rand_output_write_rng<R: Rng + ?Sized>(&self, r: &mut R) -> Op {
+        let out = *self.output_regs.choose(r).expect("no output registers");
+        let out = self.writable_reg(out).expect("output register is not writable");
+        self.rand_write_rng(out, r)
+    }
+
+    /// Random instruction, not a branch, which writes to |out|.
+    pub fn rand_write_rng<R: Rng + ?Sized>(&self, out: RegId, r: &mut R) -> Op {
+        let code = self.opcodes.iter().filter(|v| !v.is_branch()).choose(r);
+        let mut op = self.rand_op_code_rng(code.expect("no opcodes which write registers"), r);
+        match op.operands_mut() {
+            Operands::Reg2Assign { ri, .. }
+            | Operands::Reg3Assign { ri, .. }
+            | Operands::ImmAssign { ri, .. } => *ri = out,
+            Operands::Reg2Cmp { .. } => unreachable!(),
+        }
+        op
+    }
+
+    // Random register which instructions can write to.
+    fn rand_writable<R: Rng + ?Sized>(&self, r: &mut R) -> RegId {
+        RegId::raw(r.gen_range(0..self.num_reg) as u8)
+    }
+
+    // Random register or constant.
+    fn rand_readable<R: Rng + ?Sized>(&self, r: &mut R) -> RegId {
+        RegId::raw(r.gen_range(0..self.num_reg + self.num_const) as u8)
+    }
+
+    // Random instruction with the given opcode.
+    fn rand_op_code_rng<R: Rng + ?Sized>(&self, code: Opcode, r: &mut R) -> Op {
+        let mut op = Op::from_code(code);
+
         match op.operands_mut() {
             Operands::Reg2Cmp { ra, rb } => {
-                *ra = r.gen_range(0..mem_size) as u8;
-                *rb = r.gen_range(0..mem_size) as u8;
+                *ra = self.rand_readable(r);
+                *rb = self.rand_readable(r);
             }
             Operands::Reg2Assign { ri, ra } => {
-                *ri = r.gen_range(0..self.num_reg) as u8;
-                *ra = r.gen_range(0..mem_size) as u8;
+                *ri = self.rand_writable(r);
+                *ra = self.rand_readable(r);
             }
             Operands::Reg3Assign { ri, ra, rb } => {
-                *ri = r.gen_range(0..self.num_reg) as u8;
-                *ra = r.gen_range(0..mem_size) as u8;
-                *rb = r.gen_range(0..mem_size) as u8;
+                *ri = self.rand_writable(r);
+                *ra = self.rand_readable(r);
+                *rb = self.rand_readable(r);
             }
             Operands::ImmAssign { ri, imm } => {
-                *ri = r.gen_range(0..self.num_reg) as u8;
+                *ri = self.rand_writable(r);
                 let v = r.gen_range(self.imm_range.0..=self.imm_range.1);
-                *imm = Self::round_sf(v, self.imm_sf()) as f32;
+                *imm = self.to_imm(v);
             }
         }
         op
     }
 
+    // Reflects |v| into |imm_range|, so out of range values don't pile up on
+    // the bounds, then rounds it to |imm_sf| significant figures. Rounding can
+    // step just past a bound, so the result is clamped too.
+    fn to_imm(&self, v: f64) -> f32 {
+        let (lo, hi) = self.imm_range;
+        Self::round_sf(reflect_into(v, lo, hi), self.imm_sf).clamp(lo, hi) as f32
+    }
+
+    // Rounds |v| to |sf| significant figures, at least 1 and at most as many
+    // as an f64 holds. Non-finite values are returned as is.
     fn round_sf(v: f64, sf: usize) -> f64 {
-        let digits = v.abs().log10().ceil() as i32;
-        let power = 10f64.powi(digits - sf as i32);
-        (v / power).round() * power
+        const MAX_SF: usize = 17;
+        if v == 0.0 {
+            return 0.0;
+        }
+        if !v.is_finite() {
+            return v;
+        }
+        // Figures before the decimal point, or minus the number of zeros
+        // after it.
+        let digits = v.abs().log10().floor() as i32 + 1;
+        // Scale so the figures to keep are before the decimal point. Small
+        // powers of ten are exact, unlike their inverses, so only multiply or
+        // divide by them.
+        let exp = sf.clamp(1, MAX_SF) as i32 - digits;
+        let rounded = if exp < 0 {
+            let power = 10f64.powi(-exp);
+            (v / power).round() * power
+        } else if exp <= f64::MAX_10_EXP {
+            let power = 10f64.powi(exp);
+            (v * power).round() / power
+        } else {
+            // Subnormal |v|. Scale by two halves so each power is finite.
+            let (a, b) = (10f64.powi(exp / 2), 10f64.powi(exp - exp / 2));
+            (v * a * b).round() / b / a
+        };
+        // Rounding up can overflow, e.g. for f64::MAX.
+        if rounded.is_finite() {
+            rounded
+        } else {
+            v
+        }
     }
 
     // Micro-mutation of the instruction without changing the opcode.
     pub fn mutate(&self, op: &mut Op) {
-        let mut r = rand::thread_rng();
+        self.mutate_rng(op, &mut rand::thread_rng());
+    }
 
-        let mem_size = self.num_reg + self.num_const;
+    pub fn mutate_rng<R: Rng + ?Sized>(&self, op: &mut Op, r: &mut R) {
         match op.operands_mut() {
             Operands::Reg2Cmp { ra, rb } => {
                 if r.gen::<bool>() {
-                    *ra = r.gen_range(0..mem_size) as u8;
+                    *ra = self.rand_readable(r);
                 } else {
-                    *rb = r.gen_range(0..mem_size) as u8;
+                    *rb = self.rand_readable(r);
                 }
             }
             Operands::Reg2Assign { ri, ra } => {
                 if r.gen::<bool>() {
-                    *ri = r.gen_range(0..self.num_reg) as u8;
+                    *ri = self.rand_writable(r);
                 } else {
-                    *ra = r.gen_range(0..mem_size) as u8;
+                    *ra = self.rand_readable(r);
                 }
             }
             Operands::Reg3Assign { ri, ra, rb } => {
                 match r.gen_range(0..3) {
                     0 => {
-                        *ri = r.gen_range(0..self.num_reg) as u8;
+                        *ri = self.rand_writable(r);
                     }
                     1 => {
-                        *ra = r.gen_range(0..mem_size) as u8;
+                        *ra = self.rand_readable(r);
                     }
                     2 => {
-                        *rb = r.gen_range(0..mem_size) as u8;
+                        *rb = self.rand_readable(r);
                     }
                     _ => unreachable!(),
                 };
             }
             Operands::ImmAssign { ri, imm } => {
                 if r.gen::<bool>() {
-                    *ri = r.gen_range(0..self.num_reg) as u8;
+                    *ri = self.rand_writable(r);
                 } else {
                     // Large/small mutation.
                     let range = self.imm_range.1 - self.imm_range.0;
                     let stddev = if r.gen::<bool>() { range.sqrt() } else { range.log10() };
-                    let v = mutate_normal(*imm as f64, stddev);
-                    *imm = Self::round_sf(v, self.imm_sf) as f32;
+                    *imm = self.to_imm(mutate_normal_rng(*imm as f64, stddev, r));
                 }
             }
         }
@@ -133,6 +345,12 @@ impl LgpEvaluatorCfg {
         self
     }
 
+    pub fn set_layout(self, layout: &LgpRegisterLayout) -> Self {
+        self.set_num_reg(layout.num_reg())
+            .set_num_const(layout.num_const())
+            .set_output_regs(&layout.output_indices)
+    }
+
     pub fn set_max_code(mut self, max_code: usize) -> Self {
         self.max_code = max_code;
         self
@@ -153,6 +371,31 @@ impl LgpEvaluatorCfg {
         self
     }
 
+    pub fn set_effective_mutation_bias(mut self, effective_mutation_bias: f64) -> Self {
+        self.effective_mutation_bias = effective_mutation_bias;
+        self
+    }
+
+    pub fn set_ensure_output_writes(mut self, ensure_output_writes: bool) -> Self {
+        self.ensure_output_writes = ensure_output_writes;
+        self
+    }
+
+    pub fn set_no_output_penalty(mut self, no_output_penalty: Option<f64>) -> Self {
+        self.no_output_penalty = no_output_penalty;
+        self
+    }
+
+    pub fn set_preamble(mut self, preamble: &[Op]) -> Self {
+        self.preamble = preamble.to_vec();
+        self
+    }
+
+    pub fn set_growth_limit(mut self, growth_limit: usize) -> Self {
+        self.growth_limit = Some(growth_limit);
+        self
+    }
+
     #[must_use]
     pub fn num_reg(&self) -> usize {
         self.num_reg
@@ -187,6 +430,31 @@ impl LgpEvaluatorCfg {
     pub fn opcodes(&self) -> EnumSet<Opcode> {
         self.opcodes
     }
+
+    #[must_use]
+    pub fn effective_mutation_bias(&self) -> f64 {
+        self.effective_mutation_bias
+    }
+
+    #[must_use]
+    pub fn ensure_output_writes(&self) -> bool {
+        self.ensure_output_writes
+    }
+
+    #[must_use]
+    pub fn no_output_penalty(&self) -> Option<f64> {
+        self.no_output_penalty
+    }
+
+    #[must_use]
+    pub fn preamble(&self) -> &[Op] {
+        &self.preamble
+    }
+
+    #[must_use]
+    pub fn growth_limit(&self) -> Option<usize> {
+        self.growth_limit
+    }
 }
 
 impl Default for LgpEvaluatorCfg {
@@ -194,3 +462,173 @@ impl Default for LgpEvaluatorCfg {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::evaluators::lgp::eval::LgpState;
+    use crate::evaluators::lgp::vm::asm::lgp_asm;
+    use crate::evaluators::lgp::vm::disasm::lgp_disasm;
+    use crate::ops::util::rand_vec;
+
+    #[test]
+    fn layout_disjoint_and_in_range() {
+        for num_inputs in 0..10 {
+            for num_outputs in 1..10 {
+                let (cfg, layout) = LgpEvaluatorCfg::for_problem(num_inputs, num_outputs);
+                let mem_size = cfg.num_reg() + cfg.num_const();
+                let mut used = vec![false; mem_size];
+                let scratch = layout.scratch_range.clone().collect::<Vec<_>>();
+                for &idx in
+                    layout.input_indices.iter().chain(&layout.output_indices).chain(&scratch)
+                {
+                    assert!((idx as usize) < mem_size);
+                    assert!(!used[idx as usize], "index {idx} used twice");
+                    used[idx as usize] = true;
+                }
+                assert!(used.iter().all(|&v| v));
+                // Inputs are read only, outputs and scratch are writable.
+                assert!(layout.input_indices.iter().all(|&idx| idx as usize >= cfg.num_reg()));
+                assert!(layout.output_indices.iter().all(|&idx| (idx as usize) < cfg.num_reg()));
+                assert_eq!(cfg.output_regs(), layout.output_indices);
+            }
+        }
+    }
+
+    #[test]
+    fn layout_memory_limit() {
+        // 125 inputs, an output and 4 scratch registers, then the inputs.
+        let layout = LgpRegisterLayout::new(125, 1);
+        assert_eq!(layout.input_indices.last(), Some(&254));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot use more than 255 memory locations")]
+    fn layout_memory_limit_exceeded() {
+        let _ = LgpRegisterLayout::new(126, 0);
+    }
+
+    #[test]
+    fn layout_stable() {
+        let (cfg, layout) = LgpEvaluatorCfg::for_problem(2, 3);
+        assert_eq!(
+            layout,
+            LgpRegisterLayout {
+                input_indices: vec![9, 10],
+                output_indices: vec![0, 1, 2],
+                scratch_range: 3..9,
+            }
+        );
+        assert_eq!((cfg.num_reg(), cfg.num_const()), (9, 2));
+    }
+
+    #[test]
+    fn fitness_against_layout() {
+        let (cfg, layout) = LgpEvaluatorCfg::for_problem(3, 2);
+        for _ in 0..100 {
+            let ops = rand_vec(20, || cfg.rand_op());
+            let s = LgpState::new(ops, cfg.num_reg(), cfg.num_const(), cfg.output_regs());
+            let vmcfg = s.lgpvmcfg(&layout.regs(), &layout.constants(&[1.0, 2.0, 3.0]));
+            let mut vm = LgpVm::new(&vmcfg);
+            vm.run();
+            let outputs = layout.outputs(&vm);
+            assert_eq!(outputs.len(), 2);
+            // Inputs can't be overwritten.
+            let inputs = layout.input_indices.iter().map(|&idx| vm.mem(idx)).collect::<Vec<_>>();
+            assert_eq!(inputs, [1.0, 2.0, 3.0]);
+        }
+    }
+
+    #[test]
+    fn checked_regs() -> Result<()> {
+        // Registers r0 to r3, then constants r4 and r5.
+        let cfg = LgpEvaluatorCfg::new().set_num_reg(4).set_num_const(2);
+        assert_eq!(cfg.writable_reg(3)?, RegId::raw(3));
+        assert!(cfg.writable_reg(4).is_err());
+        assert_eq!(cfg.readable_reg(5)?, RegId::raw(5));
+        assert!(cfg.readable_reg(6).is_err());
+
+        let (r0, r4) = (cfg.writable_reg(0)?, cfg.readable_reg(4)?);
+        let add = Operands::Reg3Assign { ri: r0, ra: r4, rb: r4 };
+        assert!(cfg.op(Opcode::Add, add).is_ok());
+        // Writing to a constant, or operands of the wrong kind.
+        let add = Operands::Reg3Assign { ri: r4, ra: r0, rb: r0 };
+        assert!(cfg.op(Opcode::Add, add).is_err());
+        assert!(cfg.op(Opcode::Load, Operands::ImmAssign { ri: r4, imm: 1.0 }).is_err());
+        assert!(cfg.op(Opcode::Abs, Operands::Reg2Cmp { ra: r0, rb: r4 }).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn random_programs_valid() -> Result<()> {
+        let (cfg, layout) = LgpEvaluatorCfg::for_problem(3, 2);
+        let header = format!(".layout {} {}\n", layout.num_reg(), layout.num_const());
+        for _ in 0..100 {
+            let mut ops = rand_vec(20, || cfg.rand_op());
+            ops.push(cfg.rand_output_write_rng(&mut rand::thread_rng()));
+            for op in &mut ops {
+                cfg.mutate(op);
+                cfg.check_op(op)?;
+            }
+            assert_eq!(lgp_asm(&(header.clone() + &lgp_disasm(&ops)))?, ops);
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn round_sf() {
+        let round = |v| (1..=4).map(|sf| LgpEvaluatorCfg::round_sf(v, sf)).collect::<Vec<_>>();
+        assert_eq!(round(0.0), [0.0; 4]);
+        assert_eq!(round(0.049), [0.05, 0.049, 0.049, 0.049]);
+        assert_eq!(round(-0.049), [-0.05, -0.049, -0.049, -0.049]);
+        assert_eq!(round(0.05), [0.05; 4]);
+        assert_eq!(round(-0.05), [-0.05; 4]);
+        assert_eq!(round(123.456), [100.0, 120.0, 123.0, 123.5]);
+        assert_eq!(round(-123.456), [-100.0, -120.0, -123.0, -123.5]);
+        // Powers of ten keep all their figures.
+        assert_eq!(round(0.1), [0.1; 4]);
+        assert_eq!(round(99.96), [100.0, 100.0, 100.0, 99.96]);
+        // The default immediate range.
+        let (lo, hi) = LgpEvaluatorCfg::new().imm_range();
+        assert_eq!(round(lo), [lo; 4]);
+        assert_eq!(round(hi), [hi; 4]);
+
+        // Subnormal values keep their sign, and overflowing values are kept.
+        assert_eq!(round(-1e-310), [-1e-310; 4]);
+        assert_eq!(round(f64::MAX), [f64::MAX; 4]);
+        assert!(LgpEvaluatorCfg::round_sf(f64::NAN, 2).is_nan());
+        assert_eq!(LgpEvaluatorCfg::round_sf(123.456, 0), 100.0);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn imms_reflected_into_range() {
+        const N: usize = 10000;
+        let cfg = LgpEvaluatorCfg::new().set_imm_range((-1.5, 1.5)).set_imm_sf(3);
+        assert_eq!(cfg.to_imm(2.0), 1.0);
+        assert_eq!(cfg.to_imm(-1.75), -1.25);
+        assert_eq!(cfg.to_imm(1.5), 1.5);
+        // Clamping would put about half of these exactly on the bound.
+        let mut r = StdRng::seed_from_u64(0);
+        let on_bound =
+            (0..N).filter(|_| cfg.to_imm(mutate_normal_rng(1.5, 0.5, &mut r)) == 1.5).count();
+        assert!(on_bound < N / 50, "{on_bound}");
+    }
+
+    #[test]
+    fn mutated_imms_in_range() {
+        let cfg = LgpEvaluatorCfg::new().set_imm_range((-1.5, 1.5)).set_imm_sf(1);
+        let r0 = cfg.writable_reg(0).unwrap();
+        let mut op = cfg.op(Opcode::Load, Operands::ImmAssign { ri: r0, imm: 1.0 }).unwrap();
+        for _ in 0..1000 {
+            cfg.mutate(&mut op);
+            let Operands::ImmAssign { imm, .. } = op.operands() else { unreachable!() };
+            assert!((-1.5..=1.5).contains(&imm), "{imm}");
+        }
+    }
+}
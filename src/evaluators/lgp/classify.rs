@@ -0,0 +1,149 @@
+use crate::evaluators::lgp::vm::lgpvm::LgpVm;
+
+/// Register values are clamped to this magnitude before scoring, so infinite
+/// or NaN outputs still give finite scores.
+const MAX_LOGIT: f64 = 1e6;
+
+/// How to read a class decision out of a program's output registers.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub enum OutputInterp {
+    // Binary: class 1 if register 0 is positive, otherwise class 0.
+    Sign,
+    // Binary: class 1 if register 0 is above the given value, otherwise class 0.
+    Threshold(f64),
+    // Multi-class: the class is the index of the largest of the given registers.
+    ArgmaxOf(Vec<u8>),
+}
+
+impl OutputInterp {
+    #[must_use]
+    pub fn num_classes(&self) -> usize {
+        match self {
+            OutputInterp::Sign | OutputInterp::Threshold(_) => 2,
+            OutputInterp::ArgmaxOf(regs) => regs.len(),
+        }
+    }
+}
+
+/// Class decision read from a program's outputs, with a logit per class for
+/// smooth scoring.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Prediction {
+    pub class: usize,
+    pub logits: Vec<f64>,
+}
+
+impl Prediction {
+    /// Predicts the class with the largest logit, the first if tied.
+    pub fn from_logits(logits: Vec<f64>) -> Self {
+        let logits = logits
+            .into_iter()
+            .map(|v| if v.is_nan() { -MAX_LOGIT } else { v.clamp(-MAX_LOGIT, MAX_LOGIT) })
+            .collect::<Vec<_>>();
+        let mut class = 0;
+        for (i, &v) in logits.iter().enumerate() {
+            if v > logits[class] {
+                class = i;
+            }
+        }
+        Self { class, logits }
+    }
+
+    /// Natural log of the softmax probability of |class|.
+    #[must_use]
+    pub fn ln_prob(&self, class: usize) -> f64 {
+        let max = self.logits.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let sum = self.logits.iter().map(|&v| (v - max).exp()).sum::<f64>();
+        self.logits[class] - max - sum.ln()
+    }
+}
+
+/// Reads the prediction from |vm|'s registers after running a program.
+pub fn interpret_outputs(vm: &LgpVm<'_>, interp: &OutputInterp) -> Prediction {
+    match interp {
+        // Binary logits are [0, v] so the softmax is the logistic function.
+        OutputInterp::Sign => Prediction::from_logits(vec![0.0, vm.mem(0)]),
+        OutputInterp::Threshold(t) => Prediction::from_logits(vec![0.0, vm.mem(0) - t]),
+        OutputInterp::ArgmaxOf(regs) => {
+            Prediction::from_logits(regs.iter().map(|&r| vm.mem(r)).collect())
+        }
+    }
+}
+
+/// Fraction of |preds| which match |targets|.
+#[must_use]
+pub fn accuracy(preds: &[Prediction], targets: &[usize]) -> f64 {
+    assert_eq!(preds.len(), targets.len(), "predictions and targets length mismatch");
+    let correct = preds.iter().zip(targets).filter(|&(p, &t)| p.class == t).count();
+    correct as f64 / preds.len() as f64
+}
+
+/// Smooth alternative to `accuracy` in [0, 1]: the geometric mean of the
+/// softmax probability of each target class, i.e. e^-(cross entropy). Unlike
+/// accuracy, it improves whenever a correct logit increases relative to the
+/// others, so fitness isn't a step function.
+#[must_use]
+pub fn smooth_score(preds: &[Prediction], targets: &[usize]) -> f64 {
+    assert_eq!(preds.len(), targets.len(), "predictions and targets length mismatch");
+    let ln_sum = preds.iter().zip(targets).map(|(p, &t)| p.ln_prob(t)).sum::<f64>();
+    (ln_sum / preds.len() as f64).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::evaluators::lgp::vm::cfg::LgpVmCfg;
+
+    fn predict(regs: &[f64], interp: &OutputInterp) -> Prediction {
+        let cfg = LgpVmCfg::new().set_regs(regs);
+        interpret_outputs(&LgpVm::new(&cfg), interp)
+    }
+
+    #[test]
+    fn interpret() {
+        assert_eq!(predict(&[0.5, 0.0], &OutputInterp::Sign).class, 1);
+        assert_eq!(predict(&[-0.5, 0.0], &OutputInterp::Sign).class, 0);
+        assert_eq!(predict(&[0.0, 0.0], &OutputInterp::Sign).class, 0);
+        assert_eq!(predict(&[2.5, 0.0], &OutputInterp::Threshold(3.0)).class, 0);
+        assert_eq!(predict(&[3.5, 0.0], &OutputInterp::Threshold(3.0)).class, 1);
+
+        let argmax = OutputInterp::ArgmaxOf(vec![1, 2, 3]);
+        assert_eq!(argmax.num_classes(), 3);
+        assert_eq!(predict(&[9.0, 1.0, 3.0, 2.0], &argmax).class, 1);
+        assert_eq!(predict(&[9.0, 1.0, 1.0, 1.0], &argmax).class, 0);
+        assert_eq!(predict(&[0.0, f64::NAN, -5.0, f64::INFINITY], &argmax).class, 2);
+    }
+
+    #[test]
+    fn scores() {
+        let preds =
+            [Prediction::from_logits(vec![0.0, 1.0]), Prediction::from_logits(vec![2.0, 0.0])];
+        assert!((accuracy(&preds, &[1, 1]) - 0.5).abs() < 1e-12);
+        assert!((accuracy(&preds, &[1, 0]) - 1.0).abs() < 1e-12);
+        // Equal logits give probability 1/n for each class.
+        let uniform = [Prediction::from_logits(vec![3.0; 4])];
+        assert!((smooth_score(&uniform, &[2]) - 0.25).abs() < 1e-12);
+        let score = smooth_score(&preds, &[1, 0]);
+        assert!(score > 0.5 && score < 1.0);
+    }
+
+    #[test]
+    fn smooth_score_monotonic() {
+        let targets = [2, 0];
+        let mut prev = 0.0;
+        for i in -20..=20 {
+            let v = f64::from(i) * 0.5;
+            let preds = [
+                Prediction::from_logits(vec![1.0, -1.0, v]),
+                Prediction::from_logits(vec![0.5, 0.2, 0.1]),
+            ];
+            let score = smooth_score(&preds, &targets);
+            assert!(score > prev, "{v}: {score} <= {prev}");
+            prev = score;
+        }
+    }
+}
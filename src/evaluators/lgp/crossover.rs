@@ -0,0 +1,297 @@
+use crate::evaluators::lgp::eval::LgpState;
+use crate::evaluators::lgp::vm::op::Op;
+use crate::evaluators::lgp::vm::opcode::{Opcode, Operands, RegId};
+use crate::evaluators::lgp::vm::optimize::effective_indices;
+
+const NUM_REGS: usize = u8::MAX as usize + 1;
+
+/// Exchanges the code computing an output register between |s1| and |s2|.
+/// The instructions which can affect the output in each parent, in order,
+/// replace those in the other, so each child computes the other parent's
+/// function for that output while keeping its own code for the rest.
+///
+/// If a parent has no effective code for the output, the other child just
+/// loses its code for it. Registers used by both the donated code and the
+/// code it is spliced into are moved to spare registers where possible. If
+/// a child would be longer than the maximum code length, the start of the
+/// donated code is dropped. A child which would have no code at all is left
+/// as it was.
+pub fn crossover_effective_subprogram(
+    s1: &mut LgpState,
+    s2: &mut LgpState,
+    output_reg: u8,
+    max_code: usize,
+) {
+    let slice1 = effective_slice(s1, output_reg);
+    let slice2 = effective_slice(s2, output_reg);
+    let code1 = splice(s1, &slice2, output_reg, max_code);
+    let code2 = splice(s2, &slice1, output_reg, max_code);
+    for (s, code) in [(s1, code1), (s2, code2)] {
+        if !code.is_empty() {
+            *s.ops_unopt_mut() = code;
+        }
+    }
+}
+
+fn effective_slice(s: &LgpState, output_reg: u8) -> Vec<Op> {
+    effective_indices(s.ops_unopt(), &[output_reg])
+        .into_iter()
+        .map(|idx| s.ops_unopt()[idx])
+        .collect()
+}
+
+// Code of |s| with its code for |output_reg| replaced by |donor|.
+fn splice(s: &LgpState, donor: &[Op], output_reg: u8, max_code: usize) -> Vec<Op> {
+    let rest = without_output(s, output_reg);
+    // Keep the end of the donor, which has the final writes to the output.
+    let mut skip = (rest.len() + donor.len()).saturating_sub(max_code).min(donor.len());
+    loop {
+        let [copies, moved, restores] = relocate(s, &rest, &donor[skip..], output_reg);
+        if copies.len() + rest.len() + moved.len() + restores.len() <= max_code {
+            return [copies, rest, moved, restores].concat();
+        }
+        if skip == donor.len() {
+            return rest;
+        }
+        skip += 1;
+    }
+}
+
+// Code of |s| without the instructions which only affect |output_reg|, so
+// the other outputs are computed as before. Branches which would be left
+// guarding a different instruction are removed too.
+fn without_output(s: &LgpState, output_reg: u8) -> Vec<Op> {
+    let code = s.ops_unopt();
+    let others = s.output_regs().iter().copied().filter(|&v| v != output_reg).collect::<Vec<_>>();
+    let mut removed = vec![false; code.len()];
+    for idx in effective_indices(code, &[output_reg]) {
+        removed[idx] = true;
+    }
+    for idx in effective_indices(code, &others) {
+        removed[idx] = false;
+    }
+    for idx in (1..code.len()).rev() {
+        if removed[idx] && code[idx - 1].code().is_branch() {
+            removed[idx - 1] = true;
+        }
+    }
+    code.iter().zip(removed).filter(|(_, removed)| !removed).map(|(op, _)| *op).collect()
+}
+
+// Renames registers in |donor| so it computes the same |output_reg| when run
+// after |rest| as it does from the initial registers. A register is moved to
+// a spare one if |rest| writes it before the donor reads it, or the donor
+// would overwrite another output. Returns copies of initial values to put
+// at the start, the renamed donor, and copies back into |output_reg| to put
+// at the end. Registers are left alone once there are no spare ones.
+fn relocate(s: &LgpState, rest: &[Op], donor: &[Op], output_reg: u8) -> [Vec<Op>; 3] {
+    let mut used = [false; NUM_REGS];
+    let mut rest_writes = [false; NUM_REGS];
+    for op in rest {
+        for reg in op.operands().input_regs() {
+            used[reg.idx() as usize] = true;
+        }
+        for reg in op.operands().output_regs() {
+            used[reg.idx() as usize] = true;
+            rest_writes[reg.idx() as usize] = true;
+        }
+    }
+    let mut donor_uses = [false; NUM_REGS];
+    let mut donor_writes = [false; NUM_REGS];
+    for op in donor {
+        for reg in op.operands().input_regs() {
+            donor_uses[reg.idx() as usize] = true;
+        }
+        for reg in op.operands().output_regs() {
+            donor_uses[reg.idx() as usize] = true;
+            donor_writes[reg.idx() as usize] = true;
+        }
+    }
+    let mut outputs = [false; NUM_REGS];
+    for &reg in s.output_regs().iter().chain([&output_reg]) {
+        outputs[reg as usize] = true;
+    }
+    let needs_initial = reads_before_write(donor);
+
+    let mut spares = (0..s.num_reg())
+        .filter(|&v| !used[v] && !donor_uses[v] && !outputs[v])
+        .map(|v| RegId::raw(v as u8));
+    let mut map = (0..NUM_REGS).map(|v| RegId::raw(v as u8)).collect::<Vec<_>>();
+    let (mut copies, mut restores) = (vec![], vec![]);
+    for reg in 0..s.num_reg() {
+        let is_output = reg == output_reg as usize;
+        // The output always starts from its initial value, since the donor
+        // may only write it conditionally, or not at all.
+        let initial = needs_initial[reg] || is_output;
+        let clobbered = rest_writes[reg] && initial;
+        let clobbers = !is_output && outputs[reg] && donor_writes[reg];
+        if !((donor_uses[reg] || is_output) && (clobbered || clobbers)) {
+            continue;
+        }
+        let Some(spare) = spares.next() else { break };
+        map[reg] = spare;
+        if initial {
+            copies.push(copy(spare, RegId::raw(reg as u8)));
+        }
+        if is_output {
+            restores.push(copy(RegId::raw(reg as u8), spare));
+        }
+    }
+    [copies, donor.iter().map(|&op| rename(op, &map)).collect(), restores]
+}
+
+// Registers which |code| reads before it definitely writes them.
+fn reads_before_write(code: &[Op]) -> [bool; NUM_REGS] {
+    let mut written = [false; NUM_REGS];
+    let mut needs = [false; NUM_REGS];
+    for (idx, op) in code.iter().enumerate() {
+        for reg in op.operands().input_regs() {
+            needs[reg.idx() as usize] |= !written[reg.idx() as usize];
+        }
+        if idx == 0 || !code[idx - 1].code().is_branch() {
+            for reg in op.operands().output_regs() {
+                written[reg.idx() as usize] = true;
+            }
+        }
+    }
+    needs
+}
+
+fn rename(mut op: Op, map: &[RegId]) -> Op {
+    let rename = |reg: &mut RegId| *reg = map[reg.idx() as usize];
+    match op.operands_mut() {
+        Operands::Reg2Cmp { ra, rb } => {
+            rename(ra);
+            rename(rb);
+        }
+        Operands::Reg2Assign { ri, ra } => {
+            rename(ri);
+            rename(ra);
+        }
+        Operands::Reg3Assign { ri, ra, rb } => {
+            rename(ri);
+            rename(ra);
+            rename(rb);
+        }
+        Operands::ImmAssign { ri, .. } => rename(ri),
+    }
+    op
+}
+
+fn copy(ri: RegId, ra: RegId) -> Op {
+    Op::new(Opcode::Copy, Operands::Reg2Assign { ri, ra })
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::evaluators::lgp::vm::asm::lgp_asm;
+    use crate::evaluators::lgp::vm::disasm::lgp_disasm;
+    use crate::evaluators::lgp::vm::lgpvm::LgpVm;
+
+    // Registers r0 to r5, with r2 starting as y, then constants r6 = x and
+    // r7 = 1. Outputs are r0 and r1.
+    fn state(code: &str) -> Result<LgpState> {
+        Ok(LgpState::new(lgp_asm(code)?, 6, 2, &[0, 1]))
+    }
+
+    // Values of |reg| after running |s| on a few inputs.
+    fn outputs(s: &LgpState, reg: u8) -> Vec<f64> {
+        let mut v = vec![];
+        for x in [-2.0, 0.5, 3.0] {
+            for y in [-1.0, 4.0] {
+                let mut vm = LgpVm::new(&s.lgpvmcfg(&[10.0, 20.0, y, 30.0, 40.0, 50.0], &[x, 1.0]));
+                vm.run();
+                v.push(vm.mem(reg));
+            }
+        }
+        v
+    }
+
+    // Crosses over output r0 and checks each child computes the other
+    // parent's r0 and its own r1.
+    fn check(a: &str, b: &str) -> Result<(LgpState, LgpState)> {
+        let (a, b) = (state(a)?, state(b)?);
+        let (mut c1, mut c2) = (a.clone(), b.clone());
+        crossover_effective_subprogram(&mut c1, &mut c2, 0, 100);
+        assert_eq!(outputs(&c1, 0), outputs(&b, 0), "{}", lgp_disasm(c1.ops_unopt()));
+        assert_eq!(outputs(&c1, 1), outputs(&a, 1), "{}", lgp_disasm(c1.ops_unopt()));
+        assert_eq!(outputs(&c2, 0), outputs(&a, 0), "{}", lgp_disasm(c2.ops_unopt()));
+        assert_eq!(outputs(&c2, 1), outputs(&b, 1), "{}", lgp_disasm(c2.ops_unopt()));
+        Ok((c1, c2))
+    }
+
+    #[test]
+    fn exchanges_output_code() -> Result<()> {
+        // Both use r3 as a temporary, for different outputs.
+        let (c1, c2) = check(
+            "mul r3, r6, r6\nadd r0, r3, r7\nadd r1, r6, r7\n",
+            "add r3, r6, r6\nmul r1, r3, r3\nsub r0, r6, r7\n",
+        )?;
+        assert_eq!(lgp_disasm(c1.ops_unopt()), "add r1, r6, r7\nsub r0, r6, r7\n");
+        assert_eq!(
+            lgp_disasm(c2.ops_unopt()),
+            "add r3, r6, r6\nmul r1, r3, r3\nmul r3, r6, r6\nadd r0, r3, r7\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn shared_code_kept() -> Result<()> {
+        // r3 feeds both outputs, so stays for r1.
+        let _ = check(
+            "mul r3, r6, r6\nadd r0, r3, r7\nsub r1, r3, r6\n",
+            "add r3, r6, r7\nmul r0, r3, r3\ncopy r1, r3\n",
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn overlapping_registers() -> Result<()> {
+        // The donor for r0 reads y from r2, which the other parent
+        // overwrites, and uses r1 as a temporary, which is the other output.
+        let _ = check(
+            "copy r1, r2\nmul r0, r1, r1\nadd r1, r6, r6\n",
+            "load r2, 3\nadd r1, r2, r6\nadd r0, r6, r6\n",
+        )?;
+        // The donor only writes r0 conditionally, and the other parent writes
+        // r0 to compute r1.
+        let _ = check(
+            "iflt r6, r7\nload r0, 7\nadd r1, r6, r7\n",
+            "load r0, 2\nadd r1, r0, r6\nadd r0, r6, r6\n",
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn insertion_and_deletion() -> Result<()> {
+        // The first parent leaves r0 alone, so the second loses its code for
+        // it, and the first gains the second's.
+        let (c1, c2) = check("add r1, r6, r7\n", "load r0, 2\nadd r1, r0, r6\nmul r0, r6, r6\n")?;
+        assert_eq!(c1.ops_unopt().len(), 2);
+        assert_eq!(outputs(&c2, 0), vec![10.0; 6]);
+
+        // Code is never left empty.
+        let a = state("add r1, r6, r7\n")?;
+        let b = state("mul r0, r6, r6\n")?;
+        let (mut c1, mut c2) = (a.clone(), b.clone());
+        crossover_effective_subprogram(&mut c1, &mut c2, 0, 100);
+        assert_eq!(lgp_disasm(c1.ops_unopt()), "add r1, r6, r7\nmul r0, r6, r6\n");
+        assert_eq!(c2, b);
+        Ok(())
+    }
+
+    #[test]
+    fn max_code() -> Result<()> {
+        let mut a = state("add r3, r6, r7\nmul r3, r3, r3\nsub r3, r3, r6\nadd r0, r3, r7\n")?;
+        let mut b = state("add r1, r6, r6\nsub r0, r6, r7\n")?;
+        crossover_effective_subprogram(&mut a, &mut b, 0, 3);
+        // Drops the start of the donated code.
+        assert_eq!(lgp_disasm(b.ops_unopt()), "add r1, r6, r6\nsub r3, r3, r6\nadd r0, r3, r7\n");
+        assert_eq!(lgp_disasm(a.ops_unopt()), "sub r0, r6, r7\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+use std::fmt::Write;
+
+use eyre::{eyre, Result};
+
+use crate::evaluators::lgp::cfg::LgpRegisterLayout;
+use crate::evaluators::lgp::eval::LgpState;
+use crate::evaluators::lgp::vm::asm::lgp_asm;
+use crate::evaluators::lgp::vm::cfg::PowPolicy;
+use crate::evaluators::lgp::vm::disasm::lgp_disasm;
+use crate::evaluators::lgp::vm::lgpvm::LgpVm;
+
+#[must_use]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd)]
+pub enum Aggregation {
+    Mean,   // Mean of member outputs.
+    Median, // Median of member outputs. More robust to a single bad member.
+}
+
+impl Aggregation {
+    #[must_use]
+    pub fn apply(self, v: &[f64]) -> f64 {
+        if v.is_empty() {
+            return f64::NAN;
+        }
+        match self {
+            Aggregation::Mean => v.iter().sum::<f64>() / v.len() as f64,
+            Aggregation::Median => {
+                let mut v = v.to_vec();
+                v.sort_by(f64::total_cmp);
+                let mid = v.len() / 2;
+                if v.len() % 2 == 0 {
+                    (v[mid - 1] + v[mid]) / 2.0
+                } else {
+                    v[mid]
+                }
+            }
+        }
+    }
+}
+
+/// Predictor which aggregates the first output of several lgp programs. All
+/// programs are run with the same register layout.
+#[must_use]
+#[derive(Debug, Clone, PartialEq)]
+pub struct LgpEnsemble {
+    members: Vec<LgpState>,
+    layout: LgpRegisterLayout,
+    aggregation: Aggregation,
+    pow_policy: PowPolicy,
+}
+
+impl LgpEnsemble {
+    pub fn new(members: Vec<LgpState>, layout: LgpRegisterLayout) -> Self {
+        Self { members, layout, aggregation: Aggregation::Mean, pow_policy: PowPolicy::default() }
+    }
+
+    /// Picks up to `k` programs from `candidates`, best fitness first,
+    /// skipping any whose behavioural distance to an already picked program
+    /// is below `min_dist`. Behavioural distance is the mean absolute
+    /// difference in output over the `probes` inputs.
+    pub fn select_diverse(
+        candidates: &[(LgpState, f64)],
+        layout: LgpRegisterLayout,
+        k: usize,
+        min_dist: f64,
+        probes: &[Vec<f64>],
+    ) -> Self {
+        let mut sorted = candidates.iter().collect::<Vec<_>>();
+        sorted.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let mut ensemble = Self::new(Vec::new(), layout);
+        let mut behaviours: Vec<Vec<f64>> = Vec::new();
+        for (s, _) in sorted {
+            if ensemble.members.len() >= k {
+                break;
+            }
+            let behaviour = probes.iter().map(|p| ensemble.run(s, p)).collect::<Vec<_>>();
+            if behaviours.iter().all(|b| behaviour_dist(b, &behaviour) >= min_dist) {
+                ensemble.members.push(s.clone());
+                behaviours.push(behaviour);
+            }
+        }
+        ensemble
+    }
+
+    pub fn set_aggregation(self, aggregation: Aggregation) -> Self {
+        Self { aggregation, ..self }
+    }
+
+    pub fn set_pow_policy(self, pow_policy: PowPolicy) -> Self {
+        Self { pow_policy, ..self }
+    }
+
+    pub fn members(&self) -> &[LgpState] {
+        &self.members
+    }
+
+    pub fn layout(&self) -> &LgpRegisterLayout {
+        &self.layout
+    }
+
+    fn run(&self, s: &LgpState, inputs: &[f64]) -> f64 {
+        let cfg = s
+            .lgpvmcfg(&self.layout.regs(), &self.layout.constants(inputs))
+            .set_pow_policy(self.pow_policy);
+        let mut vm = LgpVm::new(&cfg);
+        vm.run();
+        self.layout.outputs(&vm)[0]
+    }
+
+    /// Output of each member for the given inputs.
+    #[must_use]
+    pub fn member_outputs(&self, inputs: &[f64]) -> Vec<f64> {
+        self.members.iter().map(|s| self.run(s, inputs)).collect()
+    }
+
+    #[must_use]
+    pub fn predict(&self, inputs: &[f64]) -> f64 {
+        self.aggregation.apply(&self.member_outputs(inputs))
+    }
+
+    /// Text form of the ensemble, readable by `from_text`. Programs are
+    /// written in lgp assembly.
+    #[must_use]
+    pub fn to_text(&self) -> String {
+        let aggregation = match self.aggregation {
+            Aggregation::Mean => "mean",
+            Aggregation::Median => "median",
+        };
+        let pow = match self.pow_policy {
+            PowPolicy::SkipNonFinite => "skip",
+            PowPolicy::AbsBase => "abs",
+            PowPolicy::SignPreserving => "sign",
+        };
+        let mut s = String::new();
+        let _ = writeln!(
+            s,
+            "layout {} {}",
+            self.layout.input_indices.len(),
+            self.layout.output_indices.len()
+        );
+        let _ = writeln!(s, "aggregation {aggregation}");
+        let _ = writeln!(s, "pow {pow}");
+        for m in &self.members {
+            s += "program\n";
+            s += &lgp_disasm(m.ops_unopt());
+        }
+        s
+    }
+
+    pub fn from_text(text: &str) -> Result<Self> {
+        let mut lines = text.lines();
+        let mut header = |key: &str| -> Result<Vec<&str>> {
+            let line = lines.next().ok_or_else(|| eyre!("missing {key}"))?;
+            match line.split_whitespace().collect::<Vec<_>>().split_first() {
+                Some((&k, rest)) if k == key => Ok(rest.to_vec()),
+                _ => Err(eyre!("expected {key}, got '{line}'")),
+            }
+        };
+        let layout = match header("layout")?[..] {
+            [inputs, outputs] => LgpRegisterLayout::new(inputs.parse()?, outputs.parse()?),
+            _ => return Err(eyre!("invalid layout")),
+        };
+        let aggregation = match header("aggregation")?[..] {
+            ["mean"] => Aggregation::Mean,
+            ["median"] => Aggregation::Median,
+            _ => return Err(eyre!("invalid aggregation")),
+        };
+        let pow_policy = match header("pow")?[..] {
+            ["skip"] => PowPolicy::SkipNonFinite,
+            ["abs"] => PowPolicy::AbsBase,
+            ["sign"] => PowPolicy::SignPreserving,
+            _ => return Err(eyre!("invalid pow policy")),
+        };
+
+        let rest = lines.collect::<Vec<_>>().join("\n");
+        let mut members = Vec::new();
+        for prog in rest.split("program\n").skip(1) {
+            members.push(LgpState::new(
+                lgp_asm(prog)?,
+                layout.num_reg(),
+                layout.num_const(),
+                &layout.output_indices,
+            ));
+        }
+        Ok(Self::new(members, layout).set_aggregation(aggregation).set_pow_policy(pow_policy))
+    }
+}
+
+fn behaviour_dist(a: &[f64], b: &[f64]) -> f64 {
+    if a.is_empty() {
+        return 0.0;
+    }
+    a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum::<f64>() / a.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    // Program which sets the output to input 0 plus |v|, for a layout with
+    // one input and one output.
+    fn add_const(v: f64) -> Result<LgpState> {
+        let layout = LgpRegisterLayout::new(1, 1);
+        let input = layout.input_indices[0];
+        let scratch = layout.scratch_range.start;
+        let code = lgp_asm(&format!("load r{scratch}, {v}\nadd r0, r{input}, r{scratch}\n"))?;
+        Ok(LgpState::new(code, layout.num_reg(), layout.num_const(), &layout.output_indices))
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn aggregation() {
+        assert_eq!(Aggregation::Mean.apply(&[1.0, 2.0, 6.0]), 3.0);
+        assert_eq!(Aggregation::Median.apply(&[1.0, 6.0, 2.0]), 2.0);
+        assert_eq!(Aggregation::Median.apply(&[4.0, 1.0, 6.0, 2.0]), 3.0);
+        assert!(Aggregation::Mean.apply(&[]).is_nan());
+    }
+
+    #[test]
+    fn diverse_selection() -> Result<()> {
+        let layout = LgpRegisterLayout::new(1, 1);
+        let candidates = vec![
+            (add_const(1.0)?, 10.0),
+            (add_const(1.5)?, 9.0), // Too close to the best.
+            (add_const(5.0)?, 8.0),
+            (add_const(9.0)?, 1.0),
+            (add_const(20.0)?, 7.0),
+        ];
+        let probes = vec![vec![0.0], vec![10.0]];
+        let ensemble = LgpEnsemble::select_diverse(&candidates, layout, 3, 1.0, &probes);
+        let outputs = ensemble.member_outputs(&[0.0]);
+        assert_eq!(outputs, [1.0, 5.0, 20.0]);
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn predict_matches_member_vms() -> Result<()> {
+        let layout = LgpRegisterLayout::new(1, 1);
+        let members = vec![add_const(1.0)?, add_const(2.0)?, add_const(6.0)?];
+        let ensemble = LgpEnsemble::new(members.clone(), layout.clone());
+        for x in [-3.0, 0.0, 2.5] {
+            let mut sum = 0.0;
+            for s in &members {
+                let mut vm = LgpVm::new(&s.lgpvmcfg(&layout.regs(), &[x]));
+                vm.run();
+                sum += vm.mem(0);
+            }
+            assert_eq!(ensemble.predict(&[x]), sum / 3.0);
+            assert_eq!(ensemble.predict(&[x]), x + 3.0);
+        }
+        let ensemble = ensemble.set_aggregation(Aggregation::Median);
+        assert_eq!(ensemble.predict(&[0.0]), 2.0);
+        Ok(())
+    }
+
+    #[test]
+    fn text_round_trip() -> Result<()> {
+        let layout = LgpRegisterLayout::new(1, 1);
+        let ensemble = LgpEnsemble::new(vec![add_const(1.0)?, add_const(2.0)?], layout)
+            .set_aggregation(Aggregation::Median)
+            .set_pow_policy(PowPolicy::SignPreserving);
+        assert_eq!(LgpEnsemble::from_text(&ensemble.to_text())?, ensemble);
+        Ok(())
+    }
+}
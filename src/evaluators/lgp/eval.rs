@@ -1,28 +1,66 @@
+use std::cmp::Ordering;
 use std::fmt;
 use std::marker::PhantomData;
+use std::sync::OnceLock;
 
-use eyre::Result;
+use eyre::{eyre, Result};
 use rand::prelude::SliceRandom;
-use rand::Rng;
+use rand::{Rng, RngCore};
 use smallvec::SmallVec;
 
 use crate::eval::{Data, Evaluator};
 use crate::evaluators::lgp::cfg::LgpEvaluatorCfg;
+use crate::evaluators::lgp::crossover::crossover_effective_subprogram;
+use crate::evaluators::lgp::mutation::mutate_reorder_independent_rng;
 use crate::evaluators::lgp::vm::cfg::LgpVmCfg;
 use crate::evaluators::lgp::vm::disasm::lgp_disasm;
 use crate::evaluators::lgp::vm::op::Op;
-use crate::evaluators::lgp::vm::optimize::LgpOptimizer;
-use crate::ops::crossover::crossover_kpx;
+use crate::evaluators::lgp::vm::optimize::{Effectiveness, LgpOptimizer};
+use crate::evaluators::lgp::vm::program::LgpProgram;
+use crate::ops::crossover::crossover_kpx_rng;
 use crate::ops::distance::dist_fn;
-use crate::ops::mutation::{mutate_insert, mutate_reset, mutate_scramble, mutate_swap};
+use crate::ops::frozen::prefix_mask;
+use crate::ops::mutation::{mutate_insert_rng, mutate_scramble_rng, mutate_swap_rng};
 
+#[cfg(test)]
+thread_local! {
+    // Number of times optimised code has been computed on this thread.
+    static OPTIMIZE_CALLS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+#[cfg(test)]
+pub(crate) fn optimize_calls() -> usize {
+    OPTIMIZE_CALLS.with(std::cell::Cell::get)
+}
+
+/// With the `serde` feature, states are written as their `LgpProgram`, and
+/// validated when read.
 #[must_use]
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "LgpProgram", try_from = "LgpProgram"))]
 pub struct LgpState {
     ops_unopt: Vec<Op>, // Contains program code for linear genetic programming.
     num_reg: usize,
     num_const: usize,
     output_regs: SmallVec<[u8; 8]>,
+    // Optimised code, computed on first use and reset whenever the code is
+    // mutably borrowed. Clones share the work done so far.
+    ops_opt: OnceLock<Vec<Op>>,
+}
+
+// The cached optimised code is derived from the other fields, so it is
+// ignored for comparisons.
+impl PartialEq for LgpState {
+    fn eq(&self, o: &Self) -> bool {
+        self.key() == o.key()
+    }
+}
+
+impl PartialOrd for LgpState {
+    fn partial_cmp(&self, o: &Self) -> Option<Ordering> {
+        self.key().partial_cmp(&o.key())
+    }
 }
 
 impl fmt::Display for LgpState {
@@ -35,19 +73,25 @@ impl fmt::Display for LgpState {
             ops_opt.len(),
             self.ops_unopt.len() - ops_opt.len()
         )?;
-        write!(f, "{}", lgp_disasm(&ops_opt))
+        write!(f, "{}", lgp_disasm(ops_opt))
     }
 }
 
 impl LgpState {
     pub fn new(ops_unopt: Vec<Op>, num_reg: usize, num_const: usize, output_regs: &[u8]) -> Self {
-        Self { ops_unopt, num_reg, num_const, output_regs: output_regs.into() }
+        Self {
+            ops_unopt,
+            num_reg,
+            num_const,
+            output_regs: output_regs.into(),
+            ops_opt: OnceLock::new(),
+        }
     }
 
     pub fn lgpvmcfg(&self, regs: &[f64], constants: &[f64]) -> LgpVmCfg {
         assert!(regs.len() == self.num_reg, "regs length mismatch");
         assert!(constants.len() == self.num_const, "constants length mismatch");
-        LgpVmCfg::new().set_code(&self.ops_opt()).set_regs(regs).set_constants(constants)
+        LgpVmCfg::new().set_code(self.ops_opt()).set_regs(regs).set_constants(constants)
     }
 
     #[must_use]
@@ -60,19 +104,75 @@ impl LgpState {
         self.num_const
     }
 
+    pub fn output_regs(&self) -> &[u8] {
+        &self.output_regs
+    }
+
     pub fn ops_unopt(&self) -> &[Op] {
         &self.ops_unopt
     }
 
     #[must_use]
     pub fn ops_unopt_mut(&mut self) -> &mut Vec<Op> {
+        self.ops_opt = OnceLock::new();
         &mut self.ops_unopt
     }
 
+    /// Code optimised for the purposes of running it. Computed once and
+    /// cached until the code is next modified.
+    pub fn ops_opt(&self) -> &[Op] {
+        self.ops_opt.get_or_init(|| {
+            #[cfg(test)]
+            OPTIMIZE_CALLS.with(|v| v.set(v.get() + 1));
+            LgpOptimizer::new(self.ops_unopt(), &self.output_regs).optimize()
+        })
+    }
+
+    /// Whether the code ever writes to an output register. Programs which
+    /// don't just leave the outputs at their initial values.
+    #[must_use]
+    pub fn writes_outputs(&self) -> bool {
+        // Optimised code always keeps the last write to each output.
+        !self.ops_opt().is_empty()
+    }
+
+    /// Indices into the unoptimised code of instructions which can affect
+    /// the outputs.
     #[must_use]
-    pub fn ops_opt(&self) -> Vec<Op> {
-        // Optimise code operations for the purposes of running the code.
-        LgpOptimizer::new(self.ops_unopt(), &self.output_regs).optimize()
+    pub fn effective_indices(&self) -> Vec<usize> {
+        LgpOptimizer::new(self.ops_unopt(), &self.output_regs).effective_indices()
+    }
+
+    /// Which instructions of the unoptimised code, and which registers at
+    /// each position in it, can affect the outputs.
+    pub fn effectiveness(&self) -> Effectiveness {
+        LgpOptimizer::new(self.ops_unopt(), &self.output_regs).effectiveness()
+    }
+
+    fn key(&self) -> (&[Op], usize, usize, &[u8]) {
+        (&self.ops_unopt, self.num_reg, self.num_const, &self.output_regs)
+    }
+}
+
+impl From<&LgpState> for LgpProgram {
+    fn from(s: &LgpState) -> Self {
+        LgpProgram::new(s.ops_unopt.clone(), s.num_reg, s.num_const, &s.output_regs)
+    }
+}
+
+impl From<LgpState> for LgpProgram {
+    fn from(s: LgpState) -> Self {
+        LgpProgram::new(s.ops_unopt, s.num_reg, s.num_const, &s.output_regs)
+    }
+}
+
+// Fails if |program| isn't valid.
+impl TryFrom<LgpProgram> for LgpState {
+    type Error = eyre::Report;
+
+    fn try_from(program: LgpProgram) -> Result<Self> {
+        program.validate()?;
+        Ok(Self::new(program.ops, program.num_reg, program.num_const, &program.output_regs))
     }
 }
 
@@ -86,52 +186,205 @@ impl<D> LgpEvaluator<D> {
     pub fn new(cfg: LgpEvaluatorCfg) -> Self {
         Self { cfg, _u: PhantomData }
     }
+
+    pub fn cfg(&self) -> &LgpEvaluatorCfg {
+        &self.cfg
+    }
+
+    // Number of instructions at the start of |s| which are preamble, and so
+    // frozen.
+    fn preamble_len(&self, s: &LgpState) -> usize {
+        self.cfg.preamble().len().min(s.ops_unopt().len())
+    }
+
+    // Code of |s| after the preamble, as a state of its own.
+    fn body(&self, s: &LgpState) -> LgpState {
+        let ops = s.ops_unopt()[self.preamble_len(s)..].to_vec();
+        LgpState::new(ops, s.num_reg(), s.num_const(), s.output_regs())
+    }
+
+    // Picks an instruction after the preamble to mutate, if there are any.
+    // Effective instructions are picked with probability
+    // `effective_mutation_bias`, if there are any.
+    fn target_idx<R: Rng + ?Sized>(&self, s: &LgpState, r: &mut R) -> Option<usize> {
+        let (start, len) = (self.preamble_len(s), s.ops_unopt().len());
+        if start == len {
+            return None;
+        }
+        let bias = self.cfg.effective_mutation_bias();
+        if bias > 0.0 && r.gen::<f64>() < bias {
+            let effective: Vec<_> =
+                s.effective_indices().into_iter().filter(|&idx| idx >= start).collect();
+            if let Some(&idx) = effective.choose(r) {
+                return Some(idx);
+            }
+        }
+        Some(r.gen_range(start..len))
+    }
+
+    // Shortens |s| to at most |max_len| instructions. Instructions after the
+    // preamble which can't affect the outputs are removed first, from the
+    // end, then the code is truncated. Branches guarding a removed
+    // instruction are removed with it, so they don't guard a different one.
+    fn limit_len(&self, s: &mut LgpState, max_len: usize) {
+        let (start, len) = (self.preamble_len(s), s.ops_unopt().len());
+        if len <= max_len {
+            return;
+        }
+        let effective = s.effectiveness().effective;
+        let ops = s.ops_unopt();
+        let mut keep = vec![true; len];
+        let (mut excess, mut idx) = (len - max_len, len);
+        while excess > 0 && idx > start {
+            idx -= 1;
+            if effective[idx] {
+                continue;
+            }
+            // Branches guarding an ineffective instruction are ineffective.
+            let mut first = idx;
+            while first > start && ops[first - 1].code().is_branch() {
+                first -= 1;
+            }
+            // A branch ending the preamble can't be removed.
+            if first == start && start > 0 && ops[start - 1].code().is_branch() {
+                idx = first;
+                continue;
+            }
+            keep[first..=idx].fill(false);
+            excess = excess.saturating_sub(idx + 1 - first);
+            idx = first;
+        }
+        let mut keep = keep.into_iter();
+        s.ops_unopt_mut().retain(|_| keep.next().unwrap_or(true));
+        s.ops_unopt_mut().truncate(max_len.max(start));
+    }
 }
 
 impl<D: Data> Evaluator for LgpEvaluator<D> {
     type State = LgpState;
     type Data = D;
-    const NUM_CROSSOVER: usize = 2;
-    const NUM_MUTATION: usize = 7;
+    const NUM_CROSSOVER: usize = 3;
+    const NUM_MUTATION: usize = 9;
 
     fn crossover(&self, s1: &mut LgpState, s2: &mut LgpState, idx: usize) {
+        self.crossover_rng(s1, s2, idx, &mut rand::thread_rng());
+    }
+
+    fn mutate(&self, s: &mut LgpState, rate: f64, idx: usize) {
+        self.mutate_rng(s, rate, idx, &mut rand::thread_rng());
+    }
+
+    fn crossover_rng(&self, s1: &mut LgpState, s2: &mut LgpState, idx: usize, r: &mut dyn RngCore) {
         match idx {
             0 => {} // Do nothing.
             1 => {
-                // Two point crossover.
-                crossover_kpx(s1.ops_unopt_mut(), s2.ops_unopt_mut(), 2);
+                // Two point crossover, of the code after the preamble.
+                let (start1, start2) = (self.preamble_len(s1), self.preamble_len(s2));
+                crossover_kpx_rng(
+                    &mut s1.ops_unopt_mut()[start1..],
+                    &mut s2.ops_unopt_mut()[start2..],
+                    2,
+                    r,
+                );
+            }
+            2 => {
+                // Exchange the code computing a random output. The preamble
+                // is left out and put back afterwards.
+                let reg = s1.output_regs().choose(r).copied();
+                if let Some(reg) = reg {
+                    let (mut b1, mut b2) = (self.body(s1), self.body(s2));
+                    let max_code = self.cfg.max_code().saturating_sub(self.cfg.preamble().len());
+                    crossover_effective_subprogram(&mut b1, &mut b2, reg, max_code);
+                    for (s, b) in [(s1, b1), (s2, b2)] {
+                        let start = self.preamble_len(s);
+                        let _ = s.ops_unopt_mut().splice(start.., b.ops_unopt().iter().copied());
+                    }
+                }
             }
             _ => panic!("unknown crossover strategy"),
         };
     }
 
-    fn mutate(&self, s: &mut LgpState, rate: f64, idx: usize) {
-        let mut r = rand::thread_rng();
+    fn mutate_rng(&self, s: &mut LgpState, rate: f64, idx: usize, r: &mut dyn RngCore) {
         if r.gen::<f64>() > rate {
             return;
         }
         let code_size = s.ops_unopt().len();
-        let op = self.cfg.rand_op();
+        // The preamble is frozen, so only the code after it is mutated.
+        let start = self.preamble_len(s);
+        let op = self.cfg.rand_op_rng(r);
         match idx {
-            0 => mutate_swap(s.ops_unopt_mut()),
-            1 => mutate_insert(s.ops_unopt_mut()),
-            2 => mutate_reset(s.ops_unopt_mut(), op),
-            3 => mutate_scramble(s.ops_unopt_mut()),
+            0 | 1 | 3 if start == code_size => {}
+            0 => mutate_swap_rng(&mut s.ops_unopt_mut()[start..], r),
+            1 => mutate_insert_rng(&mut s.ops_unopt_mut()[start..], r),
+            2 => {
+                if let Some(idx) = self.target_idx(s, r) {
+                    s.ops_unopt_mut()[idx] = op;
+                }
+            }
+            3 => mutate_scramble_rng(&mut s.ops_unopt_mut()[start..], r),
             4 => {
-                // Add new random instruction.
+                // Add new random instruction. If targeting effective code,
+                // put it next to the chosen instruction.
                 if code_size < self.cfg.max_code() {
-                    s.ops_unopt_mut().insert(r.gen_range(0..code_size), op);
+                    let idx = match self.target_idx(s, r) {
+                        Some(idx) if self.cfg.effective_mutation_bias() > 0.0 => {
+                            idx + r.gen_range(0..=1)
+                        }
+                        Some(idx) => idx,
+                        None => code_size,
+                    };
+                    s.ops_unopt_mut().insert(idx, op);
                 }
             }
             5 => {
                 // Remove random instruction.
-                if code_size > 1 {
-                    let _ = s.ops_unopt_mut().remove(r.gen_range(0..code_size));
+                if code_size > 1 && let Some(idx) = self.target_idx(s, r) {
+                    let _ = s.ops_unopt_mut().remove(idx);
                 }
             }
             6 => {
                 // Micro-mutation
-                self.cfg.mutate(s.ops_unopt_mut().choose_mut(&mut r).unwrap());
+                if let Some(idx) = self.target_idx(s, r) {
+                    self.cfg.mutate_rng(&mut s.ops_unopt_mut()[idx], r);
+                }
+            }
+            7 => {
+                // Effective mutation: insert an instruction which writes a
+                // register that can affect the outputs, or remove an
+                // instruction which can.
+                let eff = s.effectiveness();
+                let effective: Vec<_> =
+                    eff.indices().into_iter().filter(|&idx| idx >= start).collect();
+                let can_remove = code_size > 1 && !effective.is_empty();
+                if code_size < self.cfg.max_code() && (!can_remove || r.gen::<bool>()) {
+                    let idx = r.gen_range(start..=code_size);
+                    let regs: Vec<_> = eff.eff_regs[idx]
+                        .iter()
+                        .filter_map(|&reg| self.cfg.writable_reg(reg).ok())
+                        .collect();
+                    if let Some(&reg) = regs.choose(r) {
+                        let op = self.cfg.rand_write_rng(reg, r);
+                        s.ops_unopt_mut().insert(idx, op);
+                    }
+                } else if can_remove && let Some(&idx) = effective.choose(r) {
+                    let _ = s.ops_unopt_mut().remove(idx);
+                }
+            }
+            8 => {
+                // Swap two independent instructions, which doesn't change the
+                // behaviour. An instruction guarded by a branch at the end
+                // of the preamble stays put.
+                let guarded = start > 0 && s.ops_unopt()[start - 1].code().is_branch();
+                let first = if guarded { start + 1 } else { start };
+                let output_regs = s.output_regs().to_vec();
+                if first < code_size {
+                    mutate_reorder_independent_rng(
+                        &mut s.ops_unopt_mut()[first..],
+                        &output_regs,
+                        r,
+                    );
+                }
             }
             _ => panic!("unknown mutation strategy"),
         }
@@ -144,6 +397,256 @@ impl<D: Data> Evaluator for LgpEvaluator<D> {
     fn distance(&self, s1: &Self::State, s2: &Self::State) -> Result<f64> {
         // Use optimised operations for distance calculation, since
         // otherwise things can be trivially very different.
-        Ok(dist_fn(&s1.ops_opt(), &s2.ops_opt(), 1.0, Op::dist))
+        Ok(dist_fn(s1.ops_opt(), s2.ops_opt(), 1.0, Op::dist))
+    }
+
+    fn frozen_mask(&self, s: &Self::State) -> Option<Vec<bool>> {
+        let k = self.cfg.preamble().len();
+        (k > 0).then(|| prefix_mask(s.ops_unopt().len(), k))
+    }
+
+    fn validate_state(&self, s: &Self::State) -> Result<()> {
+        if s.ops_unopt().is_empty() {
+            return Err(eyre!("program has no code"));
+        }
+        if !s.ops_unopt().starts_with(self.cfg.preamble()) {
+            return Err(eyre!("code doesn't start with the preamble"));
+        }
+        Ok(())
+    }
+
+    fn repair_child(&self, child: &mut Self::State, parents: [&Self::State; 2]) {
+        if let Some(limit) = self.cfg.growth_limit() {
+            let longest = parents.iter().map(|s| s.ops_unopt().len()).max().unwrap_or(0);
+            self.limit_len(child, longest + limit);
+        }
+    }
+
+    fn lengths(&self, s: &Self::State) -> Option<(usize, usize)> {
+        // Counts effective instructions rather than optimising, so stats
+        // don't optimise programs which fitness never runs.
+        Some((s.ops_unopt().len(), s.effective_indices().len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::evaluators::lgp::builder::lgp_fitness_evolver;
+    use crate::evaluators::lgp::vm::asm::lgp_asm;
+    use crate::evaluators::lgp::vm::lgpvm::LgpVm;
+    use crate::evolve::cfg::EvolveCfg;
+
+    // Only instruction 5 of 10 affects the output.
+    fn mostly_dead() -> Result<LgpState> {
+        let mut code = String::new();
+        for i in 0..10 {
+            code += if i == 5 { "add r0, r1, r2\n" } else { "add r3, r1, r2\n" };
+        }
+        Ok(LgpState::new(lgp_asm(&code)?, 4, 0, &[0]))
+    }
+
+    #[test]
+    fn effective_mutation_bias() -> Result<()> {
+        let s = mostly_dead()?;
+        assert_eq!(s.effective_indices(), [5]);
+        let eval = LgpEvaluator::<()>::new(
+            LgpEvaluatorCfg::new().set_num_reg(4).set_effective_mutation_bias(1.0),
+        );
+        let mut r = rand::thread_rng();
+        for _ in 0..1000 {
+            assert_eq!(eval.target_idx(&s, &mut r), Some(5));
+        }
+        // Reset and micro-mutation only change the effective instruction.
+        for idx in [2, 6] {
+            for _ in 0..100 {
+                let mut m = s.clone();
+                eval.mutate(&mut m, 1.0, idx);
+                for (i, (a, b)) in s.ops_unopt().iter().zip(m.ops_unopt()).enumerate() {
+                    assert!(i == 5 || a == b, "mutated dead instruction {i}");
+                }
+            }
+        }
+
+        // Without the bias, dead code gets picked too.
+        let eval = LgpEvaluator::<()>::new(LgpEvaluatorCfg::new().set_num_reg(4));
+        assert!((0..1000).any(|_| eval.target_idx(&s, &mut r) != Some(5)));
+        Ok(())
+    }
+
+    #[test]
+    fn effective_mutation() -> Result<()> {
+        let s = mostly_dead()?;
+        let eval = LgpEvaluator::<()>::new(LgpEvaluatorCfg::new().set_num_reg(4));
+        let (mut inserted, mut removed) = (0, 0);
+        for _ in 0..1000 {
+            let mut m = s.clone();
+            eval.mutate(&mut m, 1.0, 7);
+            let (old, new) = (s.ops_unopt(), m.ops_unopt());
+            if new.len() > old.len() {
+                // The new instruction is effective.
+                let idx = (0..old.len()).find(|&i| old[i] != new[i]).unwrap_or(old.len());
+                assert!(m.effective_indices().contains(&idx), "dead insert at {idx}:\n{m}");
+                inserted += 1;
+            } else {
+                // Only the effective instruction is removed.
+                let mut expected = old.to_vec();
+                let _ = expected.remove(5);
+                assert_eq!(new, expected);
+                removed += 1;
+            }
+        }
+        assert!(inserted > 0 && removed > 0, "inserted {inserted}, removed {removed}");
+        Ok(())
+    }
+
+    #[test]
+    fn growth_limit() {
+        let lgpcfg = LgpEvaluatorCfg::new().set_num_reg(4).set_max_code(200).set_growth_limit(2);
+        let eval = LgpEvaluator::<()>::new(lgpcfg.clone());
+        let rand_state = |r: &mut rand::rngs::ThreadRng| {
+            let ops = (0..r.gen_range(1..30)).map(|_| lgpcfg.rand_op_rng(r)).collect::<Vec<_>>();
+            LgpState::new(ops, 4, 0, &[0])
+        };
+        let mut r = rand::thread_rng();
+        let mut trimmed = 0;
+        for _ in 0..2000 {
+            let parents = [rand_state(&mut r), rand_state(&mut r)];
+            let longest = parents.iter().map(|s| s.ops_unopt().len()).max().unwrap();
+            let [mut c1, mut c2] = parents.clone();
+            eval.crossover(&mut c1, &mut c2, r.gen_range(0..LgpEvaluator::<()>::NUM_CROSSOVER));
+            for c in [&mut c1, &mut c2] {
+                for idx in 0..LgpEvaluator::<()>::NUM_MUTATION {
+                    eval.mutate(c, 0.5, idx);
+                }
+                let len = c.ops_unopt().len();
+                eval.repair_child(c, [&parents[0], &parents[1]]);
+                let trimmed_len = c.ops_unopt().len();
+                assert!(trimmed_len <= longest + 2, "{trimmed_len} > {longest} + 2");
+                trimmed += usize::from(trimmed_len < len);
+            }
+        }
+        assert!(trimmed > 0);
+    }
+
+    #[test]
+    fn limit_len_drops_dead_code_first() -> Result<()> {
+        let eval = LgpEvaluator::<()>::new(LgpEvaluatorCfg::new().set_num_reg(4));
+        let s = mostly_dead()?;
+        let mut m = s.clone();
+        eval.limit_len(&mut m, 7);
+        assert_eq!(m.ops_unopt(), &s.ops_unopt()[..7]);
+        eval.limit_len(&mut m, 1);
+        assert_eq!(m.ops_unopt(), s.ops_opt());
+
+        // The branch guarding dead code goes with it.
+        let code = "add r3, r1, r2\niflt r1, r2\nadd r3, r1, r1\nadd r0, r1, r2\n";
+        let mut m = LgpState::new(lgp_asm(code)?, 4, 0, &[0]);
+        eval.limit_len(&mut m, 3);
+        assert_eq!(m.ops_unopt(), lgp_asm("add r3, r1, r2\nadd r0, r1, r2\n")?);
+
+        // Effective code is truncated if there isn't enough dead code.
+        let code = "add r3, r1, r2\nadd r0, r1, r2\nadd r0, r0, r1\nadd r0, r0, r1\n";
+        let mut m = LgpState::new(lgp_asm(code)?, 4, 0, &[0]);
+        eval.limit_len(&mut m, 2);
+        assert_eq!(m.ops_unopt(), lgp_asm("add r0, r1, r2\nadd r0, r0, r1\n")?);
+        Ok(())
+    }
+
+    #[test]
+    fn preamble_frozen() -> Result<()> {
+        let preamble = lgp_asm("load r1, 2\nload r2, 3\nmul r3, r1, r2\n")?;
+        let lgpcfg = LgpEvaluatorCfg::new()
+            .set_num_reg(4)
+            .set_max_code(20)
+            .set_preamble(&preamble)
+            .set_effective_mutation_bias(0.5);
+        let eval = LgpEvaluator::<()>::new(lgpcfg);
+        let with_preamble = |s: &LgpState| {
+            let code = [&preamble[..], s.ops_unopt()].concat();
+            LgpState::new(code, s.num_reg(), s.num_const(), s.output_regs())
+        };
+        let (mut s1, mut s2) = (with_preamble(&mostly_dead()?), with_preamble(&mostly_dead()?));
+        let mask = eval.frozen_mask(&s1).unwrap();
+        assert_eq!(mask.iter().filter(|&&v| v).count(), preamble.len());
+
+        let mut r = rand::thread_rng();
+        for _ in 0..10000 {
+            if r.gen::<bool>() {
+                eval.crossover(&mut s1, &mut s2, r.gen_range(0..LgpEvaluator::<()>::NUM_CROSSOVER));
+            } else {
+                eval.mutate(&mut s1, 1.0, r.gen_range(0..LgpEvaluator::<()>::NUM_MUTATION));
+            }
+            for s in [&s1, &s2] {
+                assert!(s.ops_unopt().starts_with(&preamble), "lost preamble:\n{s}");
+                assert!(s.ops_unopt().len() <= 20);
+            }
+        }
+
+        // Random states must keep the preamble too.
+        assert!(eval.validate_state(&s1).is_ok());
+        assert!(eval.validate_state(&mostly_dead()?).is_err());
+        let empty = LgpState::new(vec![], 4, 0, &[0]);
+        let eval = LgpEvaluator::<()>::new(LgpEvaluatorCfg::new().set_num_reg(4));
+        assert!(eval.validate_state(&empty).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn program_round_trip() -> Result<()> {
+        let s = mostly_dead()?;
+        let program = LgpProgram::from(&s);
+        assert_eq!(program.effective_indices(), s.effective_indices());
+        assert_eq!(program.optimize().ops, s.ops_opt());
+        let s2 = LgpState::try_from(program.clone())?;
+        assert_eq!(s2, s);
+        assert_eq!(LgpProgram::from(&s2), program);
+
+        let mut invalid = program;
+        invalid.output_regs = vec![4];
+        assert!(LgpState::try_from(invalid).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn ops_opt_cached() -> Result<()> {
+        let mut s = mostly_dead()?;
+        let calls = optimize_calls();
+        assert_eq!(s.ops_opt().len(), 1);
+        let _ = s.clone().ops_opt();
+        assert_eq!(optimize_calls(), calls + 1);
+
+        // Modifying the code invalidates the cache.
+        s.ops_unopt_mut().clear();
+        assert!(s.ops_opt().is_empty());
+        assert_eq!(optimize_calls(), calls + 2);
+        Ok(())
+    }
+
+    #[test]
+    fn summaries_reuse_ops_opt() -> Result<()> {
+        const POP_SIZE: usize = 20;
+        let lgpcfg = LgpEvaluatorCfg::new().set_num_reg(4);
+        let mut evolver =
+            lgp_fitness_evolver(lgpcfg, EvolveCfg::new(POP_SIZE), |s: &LgpState, (): &()| {
+                let cfg = s.lgpvmcfg(&[0.0; 4], &[]);
+                let mut exec = LgpVm::new(&cfg);
+                exec.run();
+                Ok(1.0 / (1.0 + exec.mem(0).abs()))
+            });
+        for _ in 0..5 {
+            let calls = optimize_calls();
+            let r = evolver.run()?;
+            // Fitness and distances need each new program optimised once.
+            let after_run = optimize_calls();
+            assert!(after_run - calls <= POP_SIZE, "optimized {} times", after_run - calls);
+            for _ in 0..3 {
+                let _ = evolver.summary_sample(&r, 5);
+            }
+            assert_eq!(optimize_calls(), after_run);
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,270 @@
+use eyre::{eyre, Result};
+
+use crate::eval::{Data, FitnessFn};
+use crate::evaluators::lgp::eval::LgpState;
+use crate::evaluators::lgp::vm::op::Op;
+use crate::evaluators::lgp::vm::opcode::{Opcode, Operands, RegId};
+use crate::evaluators::lgp::vm::optimize::LgpOptimizer;
+
+/// Shrinks a program while keeping its fitness on some data. Unlike
+/// `LgpOptimizer`, which only removes code that can't affect the outputs,
+/// this also removes live code which turns out not to matter, by trying
+/// removals and keeping those which don't lose more than |tolerance| fitness.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub struct LgpMinimizer {
+    tolerance: f64,
+    rewrite: bool,
+}
+
+impl LgpMinimizer {
+    pub fn new(tolerance: f64) -> Self {
+        Self { tolerance, rewrite: false }
+    }
+
+    /// Also try forwarding copied registers to their readers, and reusing
+    /// registers which were already loaded with the same value.
+    pub fn set_rewrite(self, rewrite: bool) -> Self {
+        Self { rewrite, ..self }
+    }
+
+    /// Mean fitness of |s| over |data|.
+    pub fn fitness<D: Data>(
+        s: &LgpState,
+        data: &[D],
+        f: &impl FitnessFn<LgpState, D>,
+    ) -> Result<f64> {
+        if data.is_empty() {
+            return Err(eyre!("no data to minimize on"));
+        }
+        let mut total = 0.0;
+        for v in data {
+            total += f(s, v)?;
+        }
+        Ok(total / data.len() as f64)
+    }
+
+    /// Smallest program found with fitness on |data| at least that of |s|
+    /// minus the tolerance. Removes single instructions, then increasingly
+    /// large contiguous chunks, then tries rewrites if enabled, repeating
+    /// until nothing more can be removed.
+    pub fn minimize<D: Data>(
+        &self,
+        s: &LgpState,
+        data: &[D],
+        f: &impl FitnessFn<LgpState, D>,
+    ) -> Result<LgpState> {
+        let min_fitness = Self::fitness(s, data, f)? - self.tolerance;
+        let accepts = |ops: &[Op]| -> Result<bool> {
+            Ok(Self::fitness(&Self::with_ops(s, ops.to_vec()), data, f)? >= min_fitness)
+        };
+
+        let mut ops = s.ops_opt().to_vec();
+        loop {
+            let len = ops.len();
+            let mut chunk = 1;
+            while chunk <= ops.len() {
+                // Try later code first, since it is more likely to overwrite
+                // earlier results.
+                let mut end = ops.len();
+                while end >= chunk {
+                    let mut cand = ops[..end - chunk].to_vec();
+                    cand.extend_from_slice(&ops[end..]);
+                    let cand = Self::dead_code(s, &cand);
+                    if accepts(&cand)? {
+                        end = (end - chunk).min(cand.len());
+                        ops = cand;
+                    } else {
+                        end -= 1;
+                    }
+                }
+                chunk *= 2;
+            }
+            if self.rewrite {
+                for cand in Self::rewrites(&ops) {
+                    let cand = Self::dead_code(s, &cand);
+                    if cand.len() < ops.len() && accepts(&cand)? {
+                        ops = cand;
+                        break;
+                    }
+                }
+            }
+            if ops.len() == len {
+                return Ok(Self::with_ops(s, ops));
+            }
+        }
+    }
+
+    fn with_ops(s: &LgpState, ops: Vec<Op>) -> LgpState {
+        LgpState::new(ops, s.num_reg(), s.num_const(), s.output_regs())
+    }
+
+    fn dead_code(s: &LgpState, ops: &[Op]) -> Vec<Op> {
+        LgpOptimizer::new(ops, s.output_regs()).optimize()
+    }
+
+    // Candidate programs which remove a copy, or a load of a value already
+    // loaded into another register, by making later instructions read the
+    // original register. Conditional instructions are left alone.
+    fn rewrites(ops: &[Op]) -> Vec<Vec<Op>> {
+        let conditional = |i: usize| i > 0 && ops[i - 1].code().is_branch();
+        let mut cands = Vec::new();
+        for (i, op) in ops.iter().enumerate() {
+            if conditional(i) {
+                continue;
+            }
+            match op.operands() {
+                Operands::Reg2Assign { ri, ra } if op.code() == Opcode::Copy => {
+                    cands.push(Self::forward(ops, i, ri, ra));
+                }
+                Operands::ImmAssign { ri, imm } => {
+                    // Find the last write to any other register with the same
+                    // value, which is still there.
+                    for j in (0..i).rev() {
+                        let Some(&rj) = ops[j].operands().output_regs().first() else { continue };
+                        let same = matches!(ops[j].operands(),
+                            Operands::ImmAssign { imm: v, .. } if v.to_bits() == imm.to_bits());
+                        if same && rj != ri && !conditional(j) {
+                            let overwritten = ops[j + 1..i]
+                                .iter()
+                                .any(|v| v.operands().output_regs().contains(&rj));
+                            if !overwritten {
+                                cands.push(Self::forward(ops, i, ri, rj));
+                                break;
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        cands
+    }
+
+    // Removes the instruction at |at|, which sets |from| to the value of
+    // |to|, and makes the following instructions read |to| instead of |from|
+    // until either register is written again.
+    fn forward(ops: &[Op], at: usize, from: RegId, to: RegId) -> Vec<Op> {
+        let mut cand = ops.to_vec();
+        for op in &mut cand[at + 1..] {
+            match op.operands_mut() {
+                Operands::Reg2Cmp { ra, rb } | Operands::Reg3Assign { ra, rb, .. } => {
+                    for r in [ra, rb] {
+                        if *r == from {
+                            *r = to;
+                        }
+                    }
+                }
+                Operands::Reg2Assign { ra, .. } => {
+                    if *ra == from {
+                        *ra = to;
+                    }
+                }
+                Operands::ImmAssign { .. } => {}
+            }
+            let outputs = op.operands().output_regs();
+            if outputs.contains(&from) || outputs.contains(&to) {
+                break;
+            }
+        }
+        let _ = cand.remove(at);
+        cand
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::evaluators::lgp::vm::asm::lgp_asm;
+    use crate::evaluators::lgp::vm::disasm::lgp_disasm;
+    use crate::evaluators::lgp::vm::lgpvm::LgpVm;
+
+    // Registers r0 to r3, then constants r4 = x and r5 = 1. Fitness is
+    // closeness to x^2 + x + 1 over the given xs.
+    fn state(code: &str) -> Result<LgpState> {
+        Ok(LgpState::new(lgp_asm(code)?, 4, 2, &[0]))
+    }
+
+    fn fitness() -> impl FitnessFn<LgpState, Vec<f64>> {
+        |s: &LgpState, xs: &Vec<f64>| -> Result<f64> {
+            let mut total = 0.0;
+            for &x in xs {
+                let mut vm = LgpVm::new(&s.lgpvmcfg(&[0.0; 4], &[x, 1.0]));
+                vm.run();
+                total += 1.0 / (1.0 + (vm.mem(0) - (x * x + x + 1.0)).abs());
+            }
+            Ok(total / xs.len() as f64)
+        }
+    }
+
+    fn data() -> Vec<Vec<f64>> {
+        vec![vec![-3.0, -0.5, 0.0, 2.0], vec![7.0, 10.0]]
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn removes_live_redundant_code() -> Result<()> {
+        let core = "mul r1, r4, r4\nadd r1, r1, r4\nadd r0, r1, r5\n";
+        // Every instruction affects r0, but only through adding zero,
+        // multiplying or dividing by one, or copying.
+        let padded = state(
+            "load r3, 0\n\
+            copy r2, r4\n\
+            add r2, r2, r3\n\
+            mul r1, r2, r2\n\
+            mul r3, r3, r1\n\
+            add r1, r1, r3\n\
+            div r1, r1, r5\n\
+            add r1, r1, r4\n\
+            copy r2, r5\n\
+            mul r2, r2, r5\n\
+            add r0, r1, r2\n",
+        )?;
+        assert_eq!(padded.ops_opt().len(), 11);
+        let before = LgpMinimizer::fitness(&padded, &data(), &fitness())?;
+        assert_eq!(before, LgpMinimizer::fitness(&state(core)?, &data(), &fitness())?);
+
+        let min =
+            LgpMinimizer::new(0.0).set_rewrite(true).minimize(&padded, &data(), &fitness())?;
+        assert_eq!(LgpMinimizer::fitness(&min, &data(), &fitness())?, before);
+        assert!(min.ops_unopt().len() <= 4, "{}", lgp_disasm(min.ops_unopt()));
+
+        // Without rewrites the copies have to stay.
+        let min = LgpMinimizer::new(0.0).minimize(&padded, &data(), &fitness())?;
+        assert_eq!(LgpMinimizer::fitness(&min, &data(), &fitness())?, before);
+        assert!(min.ops_unopt().len() < 11, "{}", lgp_disasm(min.ops_unopt()));
+        Ok(())
+    }
+
+    #[test]
+    fn collapses_loads() -> Result<()> {
+        // Computes x^2 + x + 1 with 1 loaded twice.
+        let s = state(
+            "load r1, 1\n\
+            load r2, 1\n\
+            mul r3, r4, r4\n\
+            add r3, r3, r4\n\
+            add r3, r3, r1\n\
+            mul r0, r3, r2\n",
+        )?;
+        let min = LgpMinimizer::new(0.0).set_rewrite(true).minimize(&s, &data(), &fitness())?;
+        assert_eq!(
+            lgp_disasm(min.ops_unopt()),
+            "load r1, 1\nmul r3, r4, r4\nadd r3, r3, r4\nadd r3, r3, r1\nmul r0, r3, r1\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tolerance() -> Result<()> {
+        // Dropping the + 1 loses some fitness, so needs a loose tolerance.
+        let s = state("mul r1, r4, r4\nadd r1, r1, r4\nadd r0, r1, r5\n")?;
+        let min = LgpMinimizer::new(0.0).minimize(&s, &data(), &fitness())?;
+        assert_eq!(min.ops_unopt().len(), 3);
+        let min = LgpMinimizer::new(0.7).minimize(&s, &data(), &fitness())?;
+        assert!(min.ops_unopt().len() < 3, "{}", lgp_disasm(min.ops_unopt()));
+        Ok(())
+    }
+}
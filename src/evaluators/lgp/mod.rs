@@ -1,4 +1,9 @@
 pub mod builder;
 pub mod cfg;
+pub mod classify;
+pub mod crossover;
+pub mod ensemble;
 pub mod eval;
+pub mod minimize;
+pub mod mutation;
 pub mod vm;
@@ -0,0 +1,138 @@
+use rand::prelude::SliceRandom;
+use rand::Rng;
+
+use crate::evaluators::lgp::vm::op::Op;
+use crate::evaluators::lgp::vm::optimize::effective_indices;
+
+/// Swaps a random adjacent pair of instructions in |ops| which are
+/// independent, so the code computes exactly the same thing afterwards.
+/// Pairs which are both effective for |output_regs| are preferred, since
+/// they reorder the optimised code too. Does nothing if no pair is
+/// independent.
+pub fn mutate_reorder_independent(ops: &mut [Op], output_regs: &[u8]) {
+    let mut r = rand::thread_rng();
+    mutate_reorder_independent_rng(ops, output_regs, &mut r);
+}
+
+pub fn mutate_reorder_independent_rng<R: Rng + ?Sized>(
+    ops: &mut [Op],
+    output_regs: &[u8],
+    r: &mut R,
+) {
+    let pairs = independent_pairs(ops);
+    let effective = effective_indices(ops, output_regs);
+    let both_effective = pairs
+        .iter()
+        .copied()
+        .filter(|idx| effective.binary_search(idx).is_ok())
+        .filter(|idx| effective.binary_search(&(idx + 1)).is_ok())
+        .collect::<Vec<_>>();
+    let candidates = if both_effective.is_empty() { pairs } else { both_effective };
+    if let Some(&idx) = candidates.choose(r) {
+        ops.swap(idx, idx + 1);
+    }
+}
+
+/// Indices i where instructions i and i + 1 of |ops| can be swapped without
+/// changing what the code computes. Neither may be a branch or guarded by
+/// one, and neither may read or write a register the other writes.
+#[must_use]
+pub fn independent_pairs(ops: &[Op]) -> Vec<usize> {
+    (0..ops.len().saturating_sub(1))
+        .filter(|&idx| idx == 0 || !ops[idx - 1].code().is_branch())
+        .filter(|&idx| independent(&ops[idx], &ops[idx + 1]))
+        .collect()
+}
+
+fn independent(a: &Op, b: &Op) -> bool {
+    if a.code().is_branch() || b.code().is_branch() {
+        return false;
+    }
+    let (a, b) = (a.operands(), b.operands());
+    let (a_in, a_out, b_in, b_out) =
+        (a.input_regs(), a.output_regs(), b.input_regs(), b.output_regs());
+    !a_out.iter().any(|reg| b_in.contains(reg) || b_out.contains(reg))
+        && !b_out.iter().any(|reg| a_in.contains(reg))
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+    use pretty_assertions::assert_eq;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::evaluators::lgp::cfg::LgpEvaluatorCfg;
+    use crate::evaluators::lgp::vm::asm::lgp_asm;
+    use crate::evaluators::lgp::vm::cfg::LgpVmCfg;
+    use crate::evaluators::lgp::vm::disasm::lgp_disasm;
+    use crate::evaluators::lgp::vm::lgpvm::LgpVm;
+
+    // Every register and constant after running |ops| on a few inputs, as
+    // bits so NaNs compare equal.
+    fn probe(ops: &[Op]) -> Vec<u64> {
+        let mut v = vec![];
+        for x in [-2.5, 0.0, 1.0, 7.0] {
+            let cfg = LgpVmCfg::new()
+                .set_code(ops)
+                .set_regs(&[x, x * x, 1.0 - x, 0.5])
+                .set_constants(&[3.0, -1.0]);
+            let mut vm = LgpVm::new(&cfg);
+            let _ = vm.run();
+            v.extend(vm.mem_slice().iter().map(|v| v.to_bits()));
+        }
+        v
+    }
+
+    #[test]
+    fn preserves_behaviour() {
+        let cfg = LgpEvaluatorCfg::new().set_num_reg(4).set_num_const(2);
+        let mut r = StdRng::seed_from_u64(0);
+        let mut swapped = 0;
+        for _ in 0..500 {
+            let ops = (0..r.gen_range(1..20)).map(|_| cfg.rand_op_rng(&mut r)).collect::<Vec<_>>();
+            let mut mutated = ops.clone();
+            for _ in 0..5 {
+                mutate_reorder_independent_rng(&mut mutated, &[0], &mut r);
+            }
+            swapped += usize::from(mutated != ops);
+            assert_eq!(probe(&mutated), probe(&ops), "{}", lgp_disasm(&ops));
+        }
+        assert!(swapped > 0);
+    }
+
+    #[test]
+    fn dependent_code_unchanged() -> Result<()> {
+        // Each instruction reads the one before, or is guarded by a branch.
+        let ops = lgp_asm(
+            "add r1, r0, r0\n\
+            mul r2, r1, r1\n\
+            iflt r2, r1\n\
+            sub r3, r2, r1\n\
+            abs r0, r3\n",
+        )?;
+        assert_eq!(independent_pairs(&ops), Vec::<usize>::new());
+        let mut mutated = ops.clone();
+        mutate_reorder_independent(&mut mutated, &[0]);
+        assert_eq!(mutated, ops);
+        mutate_reorder_independent(&mut [], &[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn prefers_effective_pairs() -> Result<()> {
+        let ops = lgp_asm(
+            "load r2, 1\n\
+            load r3, 2\n\
+            load r1, 3\n\
+            add r0, r1, r3\n",
+        )?;
+        assert_eq!(independent_pairs(&ops), [0, 1]);
+        // Only r3 and r1 can affect r0, so they are swapped.
+        let mut mutated = ops.clone();
+        mutate_reorder_independent(&mut mutated, &[0]);
+        assert_eq!(mutated, [ops[0], ops[2], ops[1], ops[3]]);
+        Ok(())
+    }
+}
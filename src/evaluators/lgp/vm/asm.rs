@@ -1,67 +1,439 @@
-use eyre::{eyre, Result};
+use std::fmt;
+use std::iter::once;
 
+use eyre::Result;
+use strum::IntoEnumIterator;
+
+use crate::evaluators::lgp::cfg::LgpEvaluatorCfg;
 use crate::evaluators::lgp::vm::op::Op;
-use crate::evaluators::lgp::vm::opcode::{Opcode, Operands};
-
-fn lgp_asm_op(s: &str) -> Result<Op> {
-    let mut tokens = s.split_whitespace();
-    let op = match tokens.next().ok_or_else(|| eyre!("missing token"))? {
-        "add" => Opcode::Add,
-        "sub" => Opcode::Sub,
-        "mul" => Opcode::Mul,
-        "div" => Opcode::Div,
-        "abs" => Opcode::Abs,
-        "neg" => Opcode::Neg,
-        "pow" => Opcode::Pow,
-        "ln" => Opcode::Ln,
-        "sin" => Opcode::Sin,
-        "cos" => Opcode::Cos,
-        "load" => Opcode::Load,
-        "copy" => Opcode::Copy,
-        "iflt" => Opcode::IfLt,
-        _ => return Err(eyre!("unknown instruction format")),
+use crate::evaluators::lgp::vm::opcode::{Opcode, Operands, RegId};
+
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd)]
+pub enum AsmErrorKind {
+    UnknownOpcode,    // First token isn't an instruction mnemonic.
+    MissingOperand,   // Line ended before all operands were given.
+    InvalidRegister,  // Operand should be a register like r3.
+    InvalidImmediate, // Operand should be a floating point value.
+    NonFinite,        // Immediate is NaN, infinite or too large for an f32.
+    ExtraOperand,     // Tokens after the last operand.
+    InvalidLayout,    // Malformed or misplaced layout header.
+    OutOfRange,       // Register is past the end of memory given by the layout.
+    ReadOnly,         // Instruction writes to a constant given by the layout.
+}
+
+/// Error assembling a single line of lgp assembly.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd)]
+pub struct AsmError {
+    pub kind: AsmErrorKind,
+    pub line: usize, // 1-based line number.
+    pub col: usize,  // 1-based column of |token|, or one past the end of the line if missing.
+    pub token: String,
+    /// Expected form of the instruction, e.g. "add ri, ra, rb".
+    pub expected: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.kind {
+            AsmErrorKind::UnknownOpcode => "unknown instruction",
+            AsmErrorKind::MissingOperand => "missing operand",
+            AsmErrorKind::InvalidRegister => "invalid register",
+            AsmErrorKind::InvalidImmediate => "invalid immediate",
+            AsmErrorKind::NonFinite => "non-finite immediate",
+            AsmErrorKind::ExtraOperand => "extra operand",
+            AsmErrorKind::InvalidLayout => "invalid layout",
+            AsmErrorKind::OutOfRange => "register out of range",
+            AsmErrorKind::ReadOnly => "write to constant",
+        };
+        write!(f, "line {}, col {}: {what}", self.line, self.col)?;
+        if !self.token.is_empty() {
+            write!(f, " '{}'", self.token)?;
+        }
+        write!(f, ", expected {}", self.expected)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// Splits |line| into tokens separated by whitespace and/or commas, with the
+// 1-based column each token starts at.
+fn tokenize(line: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    let mut st = None;
+    for (i, c) in line.char_indices().chain(once((line.len(), ' '))) {
+        let sep = c.is_whitespace() || c == ',';
+        match st {
+            None if !sep => st = Some(i),
+            Some(s) if sep => {
+                tokens.push((s + 1, &line[s..i]));
+                st = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+struct LineParser<'a> {
+    line: usize,
+    end: usize,
+    tokens: std::vec::IntoIter<(usize, &'a str)>,
+    expected: String,
+    // Registers are checked against this, if there was a layout header.
+    cfg: Option<&'a LgpEvaluatorCfg>,
+}
+
+impl<'a> LineParser<'a> {
+    fn err(&self, kind: AsmErrorKind, col: usize, token: &str) -> AsmError {
+        AsmError {
+            kind,
+            line: self.line,
+            col,
+            token: token.to_owned(),
+            expected: self.expected.clone(),
+        }
+    }
+
+    fn next(&mut self) -> Result<(usize, &'a str), AsmError> {
+        self.tokens.next().ok_or_else(|| self.err(AsmErrorKind::MissingOperand, self.end, ""))
+    }
+
+    fn reg(&mut self, write: bool) -> Result<RegId, AsmError> {
+        let (col, tok) = self.next()?;
+        let r = tok
+            .strip_prefix(['r', 'R'])
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| self.err(AsmErrorKind::InvalidRegister, col, tok))?;
+        let Some(cfg) = self.cfg else { return Ok(RegId::raw(r)) };
+        let reg = if write { cfg.writable_reg(r) } else { cfg.readable_reg(r) };
+        reg.map_err(|_| {
+            let kind = if cfg.readable_reg(r).is_ok() {
+                AsmErrorKind::ReadOnly
+            } else {
+                AsmErrorKind::OutOfRange
+            };
+            self.err(kind, col, tok)
+        })
+    }
+
+    // Register the instruction reads.
+    fn reg_in(&mut self) -> Result<RegId, AsmError> {
+        self.reg(false)
+    }
+
+    // Register the instruction writes.
+    fn reg_out(&mut self) -> Result<RegId, AsmError> {
+        self.reg(true)
+    }
+
+    fn count(&mut self) -> Result<usize, AsmError> {
+        let (col, tok) = self.next()?;
+        tok.parse().map_err(|_| self.err(AsmErrorKind::InvalidLayout, col, tok))
+    }
+
+    // Values which overflow to infinity are rejected rather than clamped, as
+    // are NaN and infinity themselves.
+    fn imm(&mut self) -> Result<f32, AsmError> {
+        let (col, tok) = self.next()?;
+        let imm: f32 =
+            tok.parse().map_err(|_| self.err(AsmErrorKind::InvalidImmediate, col, tok))?;
+        if !imm.is_finite() {
+            return Err(self.err(AsmErrorKind::NonFinite, col, tok));
+        }
+        Ok(imm)
+    }
+
+    fn finish(mut self) -> Result<(), AsmError> {
+        match self.tokens.next() {
+            Some((col, tok)) => Err(self.err(AsmErrorKind::ExtraOperand, col, tok)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parser<'a>(
+    line_no: usize,
+    s: &'a str,
+    expected: &str,
+    cfg: Option<&'a LgpEvaluatorCfg>,
+) -> LineParser<'a> {
+    LineParser {
+        line: line_no,
+        end: s.trim_end().len() + 1,
+        tokens: tokenize(s).into_iter(),
+        expected: expected.to_owned(),
+        cfg,
+    }
+}
+
+// Parses a `.layout num_reg num_const` header, giving a config to check
+// registers against.
+fn lgp_asm_layout(line_no: usize, s: &str) -> Result<LgpEvaluatorCfg, AsmError> {
+    let mut p = parser(line_no, s, ".layout num_reg num_const", None);
+    let (col, directive) = p.next()?;
+    if directive != ".layout" {
+        return Err(p.err(AsmErrorKind::InvalidLayout, col, directive));
+    }
+    let num_reg = p.count()?;
+    let num_const = p.count()?;
+    if num_reg + num_const > 256 {
+        return Err(p.err(AsmErrorKind::InvalidLayout, col, directive));
+    }
+    p.finish()?;
+    Ok(LgpEvaluatorCfg::new().set_num_reg(num_reg).set_num_const(num_const))
+}
+
+// Assembles a non-empty line, checking registers against |cfg| if given.
+// |line_no| is only used for errors.
+fn lgp_asm_op(line_no: usize, s: &str, cfg: Option<&LgpEvaluatorCfg>) -> Result<Op, AsmError> {
+    let mut p = parser(line_no, s, "instruction", cfg);
+    let (col, mnemonic) = p.tokens.next().unwrap_or((1, ""));
+    let code = Opcode::iter()
+        .find(|code| code.to_string().eq_ignore_ascii_case(mnemonic))
+        .ok_or_else(|| p.err(AsmErrorKind::UnknownOpcode, col, mnemonic))?;
+    let mut op = Op::from_code(code);
+    let shape = match op.operands() {
+        Operands::Reg2Cmp { .. } => "ra, rb",
+        Operands::Reg2Assign { .. } => "ri, ra",
+        Operands::Reg3Assign { .. } => "ri, ra, rb",
+        Operands::ImmAssign { .. } => "ri, imm",
     };
-    let mut op = Op::from_code(op);
+    p.expected = format!("{} {shape}", code.to_string().to_lowercase());
     match op.operands_mut() {
         Operands::Reg2Cmp { ra, rb } => {
-            let tok = tokens.next().ok_or_else(|| eyre!("missing register"))?;
-            *ra = tok.replace(',', "")[1..].parse()?;
-
-            let tok = tokens.next().ok_or_else(|| eyre!("missing register"))?;
-            *rb = tok.replace(',', "")[1..].parse()?;
+            *ra = p.reg_in()?;
+            *rb = p.reg_in()?;
         }
         Operands::Reg2Assign { ri, ra } => {
-            let tok = tokens.next().ok_or_else(|| eyre!("missing register"))?;
-            *ri = tok.replace(',', "")[1..].parse()?;
-
-            let tok = tokens.next().ok_or_else(|| eyre!("missing register"))?;
-            *ra = tok.replace(',', "")[1..].parse()?;
+            *ri = p.reg_out()?;
+            *ra = p.reg_in()?;
         }
         Operands::Reg3Assign { ri, ra, rb } => {
-            let tok = tokens.next().ok_or_else(|| eyre!("missing register"))?;
-            *ri = tok.replace(',', "")[1..].parse()?;
-
-            let tok = tokens.next().ok_or_else(|| eyre!("missing register"))?;
-            *ra = tok.replace(',', "")[1..].parse()?;
-
-            let tok = tokens.next().ok_or_else(|| eyre!("missing register"))?;
-            *rb = tok.replace(',', "")[1..].parse()?;
+            *ri = p.reg_out()?;
+            *ra = p.reg_in()?;
+            *rb = p.reg_in()?;
         }
         Operands::ImmAssign { ri, imm } => {
-            let tok = tokens.next().ok_or_else(|| eyre!("missing register"))?;
-            *ri = tok.replace(',', "")[1..].parse()?;
-
-            let tok = tokens.next().ok_or_else(|| eyre!("missing register"))?;
-            *imm = tok.parse::<f32>()?;
+            *ri = p.reg_out()?;
+            *imm = p.imm()?;
         }
     }
+    p.finish()?;
     Ok(op)
 }
 
+// Assembles each non-blank line of |s|. A `.layout` header is only allowed
+// before the first instruction.
+fn lgp_asm_lines(s: &str) -> Vec<Result<Op, AsmError>> {
+    let mut cfg = None;
+    let mut lines = Vec::new();
+    for (i, line) in s.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !line.trim_start().starts_with('.') {
+            lines.push(lgp_asm_op(i + 1, line, cfg.as_ref()));
+        } else if lines.is_empty() && cfg.is_none() {
+            match lgp_asm_layout(i + 1, line) {
+                Ok(layout) => cfg = Some(layout),
+                Err(e) => lines.push(Err(e)),
+            }
+        } else {
+            let mut p = parser(i + 1, line, "layout before any instructions", None);
+            let (col, tok) = p.next().unwrap_or((1, ""));
+            lines.push(Err(p.err(AsmErrorKind::InvalidLayout, col, tok)));
+        }
+    }
+    lines
+}
+
+/// Assembles |s|, one instruction per line. Mnemonics and register prefixes
+/// are case insensitive and operands may be separated by whitespace, commas,
+/// or both. Blank lines are skipped. Fails with an `AsmError` for the first
+/// bad line.
+///
+/// An optional first line `.layout num_reg num_const` gives the memory the
+/// program runs with. Registers are then checked to be in memory, and written
+/// registers to not be constants.
 pub fn lgp_asm(s: &str) -> Result<Vec<Op>> {
+    Ok(lgp_asm_lines(s).into_iter().collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Like `lgp_asm`, but skips bad lines instead of failing. Returns the
+/// assembled instructions and an error for each skipped line.
+#[must_use]
+pub fn lgp_asm_lossy(s: &str) -> (Vec<Op>, Vec<AsmError>) {
     let mut ops = Vec::new();
-    for line in s.lines() {
-        ops.push(lgp_asm_op(line)?);
+    let mut errs = Vec::new();
+    for line in lgp_asm_lines(s) {
+        match line {
+            Ok(op) => ops.push(op),
+            Err(e) => errs.push(e),
+        }
+    }
+    (ops, errs)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::evaluators::lgp::vm::disasm::lgp_disasm;
+
+    fn asm_err(s: &str) -> AsmError {
+        let err = lgp_asm(s).unwrap_err();
+        err.downcast_ref::<AsmError>().unwrap().clone()
+    }
+
+    #[test]
+    fn error_lines() {
+        let err = asm_err("add r0 r1\nsub r1, r2, r3\n");
+        assert_eq!(
+            err,
+            AsmError {
+                kind: AsmErrorKind::MissingOperand,
+                line: 1,
+                col: 10,
+                token: String::new(),
+                expected: "add ri, ra, rb".to_owned(),
+            }
+        );
+        assert_eq!(err.to_string(), "line 1, col 10: missing operand, expected add ri, ra, rb");
+
+        let err = asm_err("add r0, r1, r2\nabs r1, r0\nload r2, x1\nneg r1, r2\n");
+        assert_eq!((err.kind, err.line, err.col), (AsmErrorKind::InvalidImmediate, 3, 10));
+        assert_eq!((err.token.as_str(), err.expected.as_str()), ("x1", "load ri, imm"));
+
+        // Last line, without a trailing newline.
+        let err = asm_err("add r0, r1, r2\nabs r1, r0\nsub r0, r1, r2\nneg r1, r2\nmod r1, r2");
+        assert_eq!((err.kind, err.line, err.col), (AsmErrorKind::UnknownOpcode, 5, 1));
+        assert_eq!(err.token, "mod");
+
+        let err = asm_err("iflt r0, q1");
+        assert_eq!((err.kind, err.token.as_str()), (AsmErrorKind::InvalidRegister, "q1"));
+        let err = asm_err("copy r0, r1, r2");
+        assert_eq!((err.kind, err.col), (AsmErrorKind::ExtraOperand, 14));
+    }
+
+    #[test]
+    fn lossy_recovers_valid_lines() -> Result<()> {
+        let text = "add r0, r1, r2\nbad\nsub r1 r2 r0\nload r0,\n\nneg r0, r0\ncos r256, r1\n";
+        let (ops, errs) = lgp_asm_lossy(text);
+        assert_eq!(ops, lgp_asm("add r0, r1, r2\nsub r1, r2, r0\nneg r0, r0\n")?);
+        let lines = errs.iter().map(|e| (e.line, e.kind)).collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                (2, AsmErrorKind::UnknownOpcode),
+                (4, AsmErrorKind::MissingOperand),
+                (7, AsmErrorKind::InvalidRegister)
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tolerant_syntax() -> Result<()> {
+        let canonical = "add r0, r1, r2\nIfLt r3, r4\nload r5, -1.5\ncopy r6, r7\n";
+        let expected = lgp_asm(canonical)?;
+        for text in [
+            "add r0,r1,r2\niflt r3,r4\nload r5,-1.5\ncopy r6,r7",
+            "ADD R0 R1 R2  \n  iflt r3 ,r4\nLoad r5 , -1.5\t\n\ncopy r6, r7,\n",
+        ] {
+            assert_eq!(lgp_asm(text)?, expected);
+        }
+        assert_eq!(lgp_asm(&lgp_disasm(&expected))?, expected);
+        assert_eq!(lgp_disasm(&expected), canonical.to_lowercase());
+        Ok(())
+    }
+
+    // Disassembles and reassembles a load of |imm|, checking the bits match.
+    fn imm_round_trip(imm: f32) -> Result<String> {
+        let op = Op::new(Opcode::Load, Operands::ImmAssign { ri: RegId::raw(0), imm });
+        let text = lgp_disasm(&[op]);
+        let ops = lgp_asm(&text)?;
+        let Operands::ImmAssign { imm: parsed, .. } = ops[0].operands() else {
+            panic!("{text} isn't a load");
+        };
+        assert_eq!(parsed.to_bits(), imm.to_bits(), "{text}");
+        Ok(text)
+    }
+
+    #[test]
+    fn imm_round_trips() -> Result<()> {
+        let cfg = LgpEvaluatorCfg::new().set_imm_sf(5).set_imm_range((-1e6, 1e6));
+        let mut r = StdRng::seed_from_u64(0);
+        let mut loads = 0;
+        while loads < 5000 {
+            let op = cfg.rand_op_rng(&mut r);
+            if let Operands::ImmAssign { imm, .. } = op.operands() {
+                let _ = imm_round_trip(imm)?;
+                loads += 1;
+            }
+        }
+        // Any finite value round trips, not just the configured ones.
+        for _ in 0..5000 {
+            let imm = f32::from_bits(r.gen());
+            if imm.is_finite() {
+                let _ = imm_round_trip(imm)?;
+            }
+        }
+        let cases = [
+            (0.3, "load r0, 0.3\n"),
+            (-0.0, "load r0, -0\n"),
+            (-2.5e-7, "load r0, -2.5e-7\n"),
+            (1e30, "load r0, 1e30\n"),
+            (f32::MAX, "load r0, 3.4028235e38\n"),
+            (f32::MIN_POSITIVE, "load r0, 1.1754944e-38\n"),
+        ];
+        for (imm, expected) in cases {
+            assert_eq!(imm_round_trip(imm)?, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn non_finite_imm() {
+        for tok in ["NaN", "nan", "inf", "-inf", "infinity", "1e40", "-3.5e38"] {
+            let err = asm_err(&format!("load r0, {tok}\n"));
+            assert_eq!((err.kind, err.token.as_str()), (AsmErrorKind::NonFinite, tok));
+        }
+        let err = asm_err("load r0, 1e\n");
+        assert_eq!(err.kind, AsmErrorKind::InvalidImmediate);
+        assert_eq!(
+            asm_err("load r0, NaN").to_string(),
+            "line 1, col 10: non-finite immediate 'NaN', expected load ri, imm"
+        );
+    }
+
+    #[test]
+    fn layout_header() -> Result<()> {
+        // Registers r0 to r3, then constants r4 and r5.
+        let ops = lgp_asm(".layout 4 2\nadd r0, r4, r5\niflt r5, r3\n")?;
+        assert_eq!(ops, lgp_asm("add r0, r4, r5\niflt r5, r3\n")?);
+
+        let err = asm_err(".layout 4 2\nadd r0, r1, r2\nload r4, 1\n");
+        assert_eq!((err.kind, err.line, err.col), (AsmErrorKind::ReadOnly, 3, 6));
+        let err = asm_err(".layout 4 2\ncopy r1, r6\n");
+        assert_eq!((err.kind, err.token.as_str()), (AsmErrorKind::OutOfRange, "r6"));
+        // Without a header nothing is checked.
+        assert_eq!(lgp_asm("load r4, 1\ncopy r1, r6\n")?.len(), 2);
+
+        let err = asm_err("add r0, r1, r2\n.layout 4 2\n");
+        assert_eq!((err.kind, err.line), (AsmErrorKind::InvalidLayout, 2));
+        let err = asm_err(".layout 4\n");
+        assert_eq!(err.kind, AsmErrorKind::MissingOperand);
+        let err = asm_err(".layout 4 x\n");
+        assert_eq!((err.kind, err.token.as_str()), (AsmErrorKind::InvalidLayout, "x"));
+        let (ops, errs) = lgp_asm_lossy(".layout 2 1\nneg r0, r2\nneg r2, r0\nneg r1, r3\n");
+        assert_eq!(ops.len(), 1);
+        let kinds = errs.iter().map(|e| e.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [AsmErrorKind::ReadOnly, AsmErrorKind::OutOfRange]);
+        Ok(())
     }
-    Ok(ops)
 }
@@ -1,5 +1,41 @@
 use crate::evaluators::lgp::vm::op::Op;
 
+/// How `pow` handles negative bases. `powf` with a negative base and a
+/// non-integer exponent is NaN, so without a policy those writes are skipped.
+/// In all policies non-finite results (e.g. overflow, 0^negative) are not
+/// written, same as the other arithmetic instructions.
+#[must_use]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Default)]
+pub enum PowPolicy {
+    /// ri = ra ^ rb, skipping the write if the result is not finite.
+    #[default]
+    SkipNonFinite,
+    /// ri = |ra| ^ rb
+    AbsBase,
+    /// ri = sign(ra) * |ra| ^ rb. Zero is treated as positive, so 0^0 = 1.
+    SignPreserving,
+}
+
+impl PowPolicy {
+    /// Computes a ^ b under this policy. May return a non-finite value, which
+    /// callers should ignore.
+    #[must_use]
+    pub fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            PowPolicy::SkipNonFinite => a.powf(b),
+            PowPolicy::AbsBase => a.abs().powf(b),
+            PowPolicy::SignPreserving => {
+                let v = a.abs().powf(b);
+                if a < 0.0 {
+                    -v
+                } else {
+                    v
+                }
+            }
+        }
+    }
+}
+
 /// Virtual machine for lgp code.
 #[must_use]
 #[derive(Debug, Clone)]
@@ -10,6 +46,10 @@ pub struct LgpVmCfg {
     constants: Vec<f64>,
     /// Code to execute.
     code: Vec<Op>,
+    /// Semantics of the pow instruction.
+    pow_policy: PowPolicy,
+    /// Maximum number of instructions to execute per run, if any.
+    max_steps: Option<usize>,
 }
 
 impl Default for LgpVmCfg {
@@ -20,7 +60,13 @@ impl Default for LgpVmCfg {
 
 impl LgpVmCfg {
     pub fn new() -> Self {
-        Self { regs: vec![], constants: vec![], code: vec![] }
+        Self {
+            regs: vec![],
+            constants: vec![],
+            code: vec![],
+            pow_policy: PowPolicy::default(),
+            max_steps: None,
+        }
     }
 
     pub fn set_regs(mut self, regs: &[f64]) -> Self {
@@ -46,6 +92,16 @@ impl LgpVmCfg {
         self
     }
 
+    pub fn set_pow_policy(mut self, pow_policy: PowPolicy) -> Self {
+        self.pow_policy = pow_policy;
+        self
+    }
+
+    pub fn set_max_steps(mut self, max_steps: Option<usize>) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
     #[must_use]
     pub fn regs(&self) -> &[f64] {
         &self.regs
@@ -59,4 +115,13 @@ impl LgpVmCfg {
     pub fn code(&self) -> &[Op] {
         &self.code
     }
+
+    pub fn pow_policy(&self) -> PowPolicy {
+        self.pow_policy
+    }
+
+    #[must_use]
+    pub fn max_steps(&self) -> Option<usize> {
+        self.max_steps
+    }
 }
@@ -14,21 +14,43 @@ pub fn lgp_disasm(code: &[Op]) -> String {
 mod tests {
     use eyre::Result;
     use pretty_assertions::assert_eq;
+    use strum::IntoEnumIterator;
 
     use super::*;
     use crate::evaluators::lgp::vm::asm::lgp_asm;
     use crate::evaluators::lgp::vm::op::Op;
-    use crate::evaluators::lgp::vm::opcode::{Opcode, Operands};
+    use crate::evaluators::lgp::vm::opcode::{Opcode, Operands, RegId};
 
     #[test]
     fn basic_disasm() -> Result<()> {
+        let [r0, r1, r2] = [0, 1, 2].map(RegId::raw);
         let code = vec![
-            Op::new(Opcode::Add, Operands::Reg3Assign { ri: 0, ra: 1, rb: 2 }),
-            Op::new(Opcode::Sub, Operands::Reg3Assign { ri: 2, ra: 1, rb: 0 }),
+            Op::new(Opcode::Add, Operands::Reg3Assign { ri: r0, ra: r1, rb: r2 }),
+            Op::new(Opcode::Sub, Operands::Reg3Assign { ri: r2, ra: r1, rb: r0 }),
         ];
         let text = "add r0, r1, r2\nsub r2, r1, r0\n";
         assert_eq!(text, lgp_disasm(&code));
         assert_eq!(code, lgp_asm(text)?);
         Ok(())
     }
+
+    #[test]
+    fn every_opcode_round_trips() -> Result<()> {
+        let code: Vec<Op> = Opcode::iter()
+            .map(|code| {
+                let mut op = Op::from_code(code);
+                match op.operands_mut() {
+                    Operands::Reg2Cmp { ra, rb } => (*ra, *rb) = (RegId::raw(1), RegId::raw(2)),
+                    Operands::Reg2Assign { ri, ra } => (*ri, *ra) = (RegId::raw(3), RegId::raw(4)),
+                    Operands::Reg3Assign { ri, ra, rb } => {
+                        (*ri, *ra, *rb) = (RegId::raw(5), RegId::raw(6), RegId::raw(7));
+                    }
+                    Operands::ImmAssign { ri, imm } => (*ri, *imm) = (RegId::raw(8), -1.5),
+                }
+                op
+            })
+            .collect();
+        assert_eq!(code, lgp_asm(&lgp_disasm(&code))?);
+        Ok(())
+    }
 }
@@ -1,31 +1,103 @@
-use crate::evaluators::lgp::vm::cfg::LgpVmCfg;
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::evaluators::lgp::vm::cfg::{LgpVmCfg, PowPolicy};
 use crate::evaluators::lgp::vm::op::Op;
-use crate::evaluators::lgp::vm::opcode::{Opcode, Operands};
+use crate::evaluators::lgp::vm::opcode::{Opcode, Operands, RegId};
+
+/// How often a cancellation flag is checked, in executed instructions.
+pub const CANCEL_CHECK_STEPS: usize = 1024;
+
+/// How a run of an `LgpVm` ended.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Hash)]
+pub enum RunStatus {
+    /// Ran to the end of the program.
+    Completed,
+    /// Stopped after `LgpVmCfg::max_steps` instructions.
+    StepLimit,
+    /// Stopped because the cancellation flag was set.
+    Cancelled,
+}
 
 /// Virtual machine for lgp code. Programs should not be able to run forever,
-/// and have acyclic control flow graphs.
+/// and have acyclic control flow graphs. Runs can still be bounded with
+/// `LgpVmCfg::max_steps` or stopped early with `run_with_cancel`.
+///
+/// Numeric edge cases: arithmetic never writes a non-finite value. If an
+/// instruction would produce NaN or infinity (division by zero, ln of a
+/// non-positive number, overflow), the destination register is left
+/// unchanged. Writes to constants are also ignored. Negative bases for pow are
+/// handled according to the configured `PowPolicy`.
+///
+/// To run the same program on many inputs, create the vm once with
+/// `borrowed` and call `reset` before each run. This avoids allocating.
 #[must_use]
 #[derive(Debug, Clone)]
-pub struct LgpVm {
+pub struct LgpVm<'a> {
     pc: usize,
     mem: Vec<f64>,
-    code: Vec<Op>,
+    code: Cow<'a, [Op]>,
     /// Number of non-constant memory locations.
     num_reg: usize,
+    pow_policy: PowPolicy,
+    /// Instructions executed since the last reset.
+    steps: usize,
+    max_steps: Option<usize>,
 }
 
-impl LgpVm {
+impl<'a> LgpVm<'a> {
+    /// Creates a vm with its own copy of the code in |cfg|.
     pub fn new(cfg: &LgpVmCfg) -> Self {
+        Self::with_code(cfg, Cow::Owned(cfg.code().to_vec()))
+    }
+
+    /// Creates a vm which runs the code in |cfg| without copying it.
+    pub fn borrowed(cfg: &'a LgpVmCfg) -> Self {
+        Self::with_code(cfg, Cow::Borrowed(cfg.code()))
+    }
+
+    fn with_code(cfg: &LgpVmCfg, code: Cow<'a, [Op]>) -> Self {
         let num_reg = cfg.regs().len();
         let mem_size = cfg.regs().len() + cfg.constants().len();
         let mut mem = vec![0.0; mem_size];
         mem[..num_reg].copy_from_slice(cfg.regs());
         mem[num_reg..].copy_from_slice(cfg.constants());
-        Self { pc: 0, mem, code: cfg.code().to_vec(), num_reg }
+        Self {
+            pc: 0,
+            mem,
+            code,
+            num_reg,
+            pow_policy: cfg.pow_policy(),
+            steps: 0,
+            max_steps: cfg.max_steps(),
+        }
+    }
+
+    /// Rewinds to the start of the program and sets the registers to |regs|.
+    /// Constants are read only so keep their values.
+    pub fn reset(&mut self, regs: &[f64]) {
+        assert_eq!(regs.len(), self.num_reg, "regs length mismatch");
+        self.pc = 0;
+        self.steps = 0;
+        self.mem[..self.num_reg].copy_from_slice(regs);
     }
 
-    fn is_constant(&self, idx: u8) -> bool {
-        idx as usize >= self.num_reg
+    /// Like `reset`, but also replaces the constants, e.g. to run on new
+    /// inputs.
+    pub fn reset_with_constants(&mut self, regs: &[f64], constants: &[f64]) {
+        assert_eq!(constants.len(), self.mem.len() - self.num_reg, "constants length mismatch");
+        self.reset(regs);
+        self.mem[self.num_reg..].copy_from_slice(constants);
+    }
+
+    fn is_constant(&self, reg: RegId) -> bool {
+        reg.idx() as usize >= self.num_reg
+    }
+
+    /// Number of instructions executed since the vm was created or reset.
+    #[must_use]
+    pub fn steps(&self) -> usize {
+        self.steps
     }
 
     #[must_use]
@@ -38,8 +110,12 @@ impl LgpVm {
         self.mem[idx as usize]
     }
 
-    fn set_mem(&mut self, idx: u8, v: f64) {
-        self.mem[idx as usize] = v;
+    fn load(&self, reg: RegId) -> f64 {
+        self.mem(reg.idx())
+    }
+
+    fn store(&mut self, reg: RegId, v: f64) {
+        self.mem[reg.idx() as usize] = v;
     }
 
     fn peek(&mut self) -> Option<Op> {
@@ -64,75 +140,75 @@ impl LgpVm {
         if let Some(op) = self.fetch() {
             match (op.code(), op.operands()) {
                 (Opcode::Add, Operands::Reg3Assign { ri, ra, rb }) => {
-                    let v = self.mem(ra) + self.mem(rb);
+                    let v = self.load(ra) + self.load(rb);
                     if v.is_finite() && !self.is_constant(ri) {
-                        self.set_mem(ri, v);
+                        self.store(ri, v);
                     }
                 }
                 (Opcode::Sub, Operands::Reg3Assign { ri, ra, rb }) => {
-                    let v = self.mem(ra) - self.mem(rb);
+                    let v = self.load(ra) - self.load(rb);
                     if v.is_finite() && !self.is_constant(ri) {
-                        self.set_mem(ri, v);
+                        self.store(ri, v);
                     }
                 }
                 (Opcode::Mul, Operands::Reg3Assign { ri, ra, rb }) => {
-                    let v = self.mem(ra) * self.mem(rb);
+                    let v = self.load(ra) * self.load(rb);
                     if v.is_finite() && !self.is_constant(ri) {
-                        self.set_mem(ri, v);
+                        self.store(ri, v);
                     }
                 }
                 (Opcode::Div, Operands::Reg3Assign { ri, ra, rb }) => {
-                    let v = self.mem(ra) / self.mem(rb);
+                    let v = self.load(ra) / self.load(rb);
                     if v.is_finite() && !self.is_constant(ri) {
-                        self.set_mem(ri, v);
+                        self.store(ri, v);
                     }
                 }
                 (Opcode::Pow, Operands::Reg3Assign { ri, ra, rb }) => {
-                    let v = self.mem(ra).powf(self.mem(rb));
+                    let v = self.pow_policy.apply(self.load(ra), self.load(rb));
                     if v.is_finite() && !self.is_constant(ri) {
-                        self.set_mem(ri, v);
+                        self.store(ri, v);
                     }
                 }
                 (Opcode::Abs, Operands::Reg2Assign { ri, ra }) => {
                     if !self.is_constant(ri) {
-                        self.set_mem(ri, self.mem(ra).abs());
+                        self.store(ri, self.load(ra).abs());
                     }
                 }
                 (Opcode::Neg, Operands::Reg2Assign { ri, ra }) => {
                     if !self.is_constant(ri) {
-                        self.set_mem(ri, -self.mem(ra));
+                        self.store(ri, -self.load(ra));
                     }
                 }
                 (Opcode::Ln, Operands::Reg2Assign { ri, ra }) => {
-                    let v = self.mem(ra).ln();
+                    let v = self.load(ra).ln();
                     if v.is_finite() && !self.is_constant(ri) {
-                        self.set_mem(ri, v);
+                        self.store(ri, v);
                     }
                 }
                 (Opcode::Sin, Operands::Reg2Assign { ri, ra }) => {
-                    let v = self.mem(ra).sin();
+                    let v = self.load(ra).sin();
                     if v.is_finite() && !self.is_constant(ri) {
-                        self.set_mem(ri, v);
+                        self.store(ri, v);
                     }
                 }
                 (Opcode::Cos, Operands::Reg2Assign { ri, ra }) => {
-                    let v = self.mem(ra).cos();
+                    let v = self.load(ra).cos();
                     if v.is_finite() && !self.is_constant(ri) {
-                        self.set_mem(ri, v);
+                        self.store(ri, v);
                     }
                 }
                 (Opcode::Load, Operands::ImmAssign { ri, imm }) => {
                     if !self.is_constant(ri) {
-                        self.set_mem(ri, imm as f64);
+                        self.store(ri, imm as f64);
                     }
                 }
                 (Opcode::Copy, Operands::Reg2Assign { ri, ra }) => {
                     if !self.is_constant(ri) {
-                        self.set_mem(ri, self.mem(ra));
+                        self.store(ri, self.load(ra));
                     }
                 }
                 (Opcode::IfLt, Operands::Reg2Cmp { ra, rb }) => {
-                    if self.mem(ra) >= self.mem(rb) {
+                    if self.load(ra) >= self.load(rb) {
                         // Find first non if instruction and skip it (last fetch will skip).
                         while let Some(op) = self.fetch() && op.code().is_branch() {}
                     }
@@ -145,7 +221,173 @@ impl LgpVm {
         }
     }
 
-    pub fn run(&mut self) {
-        while !self.step() {}
+    /// Runs to the end of the program or the step limit.
+    pub fn run(&mut self) -> RunStatus {
+        self.run_checked(None)
+    }
+
+    /// Like `run`, but stops when |cancel| is set, e.g. from another thread.
+    /// The flag is checked every `CANCEL_CHECK_STEPS` instructions.
+    pub fn run_with_cancel(&mut self, cancel: &AtomicBool) -> RunStatus {
+        self.run_checked(Some(cancel))
+    }
+
+    fn run_checked(&mut self, cancel: Option<&AtomicBool>) -> RunStatus {
+        loop {
+            // Finishing takes priority, so a run never ends as cancelled or
+            // limited once it has nothing left to execute.
+            if self.pc >= self.code.len() {
+                return RunStatus::Completed;
+            }
+            if let Some(cancel) = cancel
+                && self.steps % CANCEL_CHECK_STEPS == 0
+                && cancel.load(Ordering::Relaxed)
+            {
+                return RunStatus::Cancelled;
+            }
+            if matches!(self.max_steps, Some(max) if self.steps >= max) {
+                return RunStatus::StepLimit;
+            }
+            self.step();
+            self.steps += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::evaluators::lgp::vm::op::Op;
+
+    fn run_pow(policy: PowPolicy, a: f64, b: f64) -> f64 {
+        const INITIAL: f64 = 42.0;
+        let (ri, ra, rb) = (RegId::raw(0), RegId::raw(1), RegId::raw(2));
+        let code = [Op::new(Opcode::Pow, Operands::Reg3Assign { ri, ra, rb })];
+        let cfg = LgpVmCfg::new().set_code(&code).set_regs(&[INITIAL, a, b]).set_pow_policy(policy);
+        let mut vm = LgpVm::new(&cfg);
+        vm.run();
+        vm.mem(0)
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn pow_policies() {
+        use PowPolicy::{AbsBase, SignPreserving, SkipNonFinite};
+        const SKIP: f64 = 42.0; // Initial value of the output register.
+        let cases = [
+            // (a, b, skip, abs, sign preserving)
+            (2.0, 3.0, 8.0, 8.0, 8.0),
+            (-2.0, 3.0, -8.0, 8.0, -8.0),
+            (-2.0, 2.0, 4.0, 4.0, -4.0),
+            (-4.0, 0.5, SKIP, 2.0, -2.0),
+            (0.0, 0.0, 1.0, 1.0, 1.0),
+            (0.0, -1.0, SKIP, SKIP, SKIP),
+            (10.0, 400.0, SKIP, SKIP, SKIP),
+            (-10.0, 401.0, SKIP, SKIP, SKIP),
+        ];
+        for (a, b, skip, abs, sign) in cases {
+            assert_eq!(run_pow(SkipNonFinite, a, b), skip, "skip {a}^{b}");
+            assert_eq!(run_pow(AbsBase, a, b), abs, "abs {a}^{b}");
+            assert_eq!(run_pow(SignPreserving, a, b), sign, "sign preserving {a}^{b}");
+        }
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn reset_reuses_vm() {
+        // r0 = r1 * c0 + r2, with c0 a constant after the three registers.
+        let [r0, r1, r2, r3] = [0, 1, 2, 3].map(RegId::raw);
+        let code = [
+            Op::new(Opcode::Mul, Operands::Reg3Assign { ri: r0, ra: r1, rb: r3 }),
+            Op::new(Opcode::Add, Operands::Reg3Assign { ri: r0, ra: r0, rb: r2 }),
+        ];
+        let cfg = LgpVmCfg::new().set_code(&code).set_regs(&[0.0, 2.0, 1.0]).set_constants(&[3.0]);
+        let mut vm = LgpVm::borrowed(&cfg);
+        vm.run();
+        assert_eq!(vm.mem(0), 7.0);
+
+        vm.reset(&[0.0, 4.0, 1.0]);
+        vm.run();
+        assert_eq!(vm.mem(0), 13.0);
+
+        vm.reset_with_constants(&[0.0, 4.0, 1.0], &[-1.0]);
+        vm.run();
+        assert_eq!(vm.mem(0), -3.0);
+
+        let mut fresh = LgpVm::new(&cfg.clone().set_regs(&[0.0, 4.0, 1.0]).set_constants(&[-1.0]));
+        fresh.run();
+        assert_eq!(fresh.mem_slice(), vm.mem_slice());
+    }
+
+    // r0 += r1, |n| times.
+    fn counter(n: usize) -> LgpVmCfg {
+        let (r0, r1) = (RegId::raw(0), RegId::raw(1));
+        let code = vec![Op::new(Opcode::Add, Operands::Reg3Assign { ri: r0, ra: r0, rb: r1 }); n];
+        LgpVmCfg::new().set_code(&code).set_regs(&[0.0, 1.0])
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn run_status() {
+        let mut vm = LgpVm::new(&counter(3));
+        assert_eq!(vm.run(), RunStatus::Completed);
+        assert_eq!((vm.steps(), vm.mem(0)), (3, 3.0));
+
+        let mut vm = LgpVm::new(&counter(3).set_max_steps(Some(2)));
+        assert_eq!(vm.run(), RunStatus::StepLimit);
+        assert_eq!((vm.steps(), vm.mem(0)), (2, 2.0));
+        vm.reset(&[0.0, 1.0]);
+        assert_eq!(vm.steps(), 0);
+
+        // Reaching the limit on the last instruction still completes.
+        let mut vm = LgpVm::new(&counter(3).set_max_steps(Some(3)));
+        assert_eq!(vm.run(), RunStatus::Completed);
+
+        let cancel = AtomicBool::new(true);
+        let mut vm = LgpVm::new(&counter(3));
+        assert_eq!(vm.run_with_cancel(&cancel), RunStatus::Cancelled);
+        assert_eq!((vm.steps(), vm.mem(0)), (0, 0.0));
+
+        // Finishing exactly on a check still completes.
+        let mut vm = LgpVm::new(&counter(CANCEL_CHECK_STEPS));
+        assert_eq!(vm.run(), RunStatus::Completed);
+        assert_eq!(vm.run_with_cancel(&cancel), RunStatus::Completed);
+    }
+
+    #[test]
+    fn unset_cancel_matches_run() {
+        let cfg = counter(10 * CANCEL_CHECK_STEPS + 7);
+        let mut plain = LgpVm::new(&cfg);
+        assert_eq!(plain.run(), RunStatus::Completed);
+        let mut vm = LgpVm::new(&cfg);
+        assert_eq!(vm.run_with_cancel(&AtomicBool::new(false)), RunStatus::Completed);
+        assert_eq!(vm.steps(), plain.steps());
+        assert_eq!(vm.steps(), cfg.code().len());
+        assert_eq!(vm.mem_slice(), plain.mem_slice());
+    }
+
+    #[test]
+    fn cancel_from_other_thread() {
+        let cfg = counter(100 * CANCEL_CHECK_STEPS);
+        let cancel = AtomicBool::new(false);
+        let steps = std::thread::scope(|s| {
+            let worker = s.spawn(|| {
+                let mut vm = LgpVm::borrowed(&cfg);
+                loop {
+                    vm.reset(&[0.0, 1.0]);
+                    if vm.run_with_cancel(&cancel) == RunStatus::Cancelled {
+                        return vm.steps();
+                    }
+                }
+            });
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            cancel.store(true, Ordering::Relaxed);
+            worker.join().unwrap()
+        });
+        // Stops at the first check after the flag is set.
+        assert_eq!(steps % CANCEL_CHECK_STEPS, 0);
+        assert!(steps < cfg.code().len());
     }
 }
@@ -5,3 +5,4 @@ pub mod lgpvm;
 pub mod op;
 pub mod opcode;
 pub mod optimize;
+pub mod program;
@@ -1,6 +1,7 @@
 use std::fmt;
 use std::mem::discriminant;
 
+use eyre::{eyre, Result};
 use rand::prelude::IteratorRandom;
 use rand::Rng;
 use rand_distr::{Distribution, Standard};
@@ -10,6 +11,7 @@ use crate::evaluators::lgp::vm::opcode::{Opcode, Operands};
 
 #[must_use]
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Op {
     code: Opcode,
     operands: Operands,
@@ -33,15 +35,26 @@ impl fmt::Display for Op {
             Opcode::IfLt => "iflt",
         };
         let operands = match self.operands {
-            Operands::Reg2Cmp { ra, rb } => format!("r{ra}, r{rb}"),
-            Operands::Reg2Assign { ri, ra } => format!("r{ri}, r{ra}"),
-            Operands::Reg3Assign { ri, ra, rb } => format!("r{ri}, r{ra}, r{rb}"),
-            Operands::ImmAssign { ri, imm } => format!("r{ri}, {imm}"),
+            Operands::Reg2Cmp { ra, rb } => format!("{ra}, {rb}"),
+            Operands::Reg2Assign { ri, ra } => format!("{ri}, {ra}"),
+            Operands::Reg3Assign { ri, ra, rb } => format!("{ri}, {ra}, {rb}"),
+            Operands::ImmAssign { ri, imm } => format!("{ri}, {}", fmt_imm(imm)),
         };
         write!(f, "{mnemonic} {operands}")
     }
 }
 
+// Shortest text `lgp_asm` parses back to exactly |imm|. Very small and large
+// magnitudes use exponent notation, so lines stay short.
+fn fmt_imm(imm: f32) -> String {
+    let abs = imm.abs();
+    if abs == 0.0 || (1e-4..1e9).contains(&abs) {
+        imm.to_string()
+    } else {
+        format!("{imm:e}")
+    }
+}
+
 impl Distribution<Opcode> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Opcode {
         Opcode::iter().choose(rng).unwrap()
@@ -49,9 +62,16 @@ impl Distribution<Opcode> for Standard {
 }
 
 impl Op {
+    /// Panics if |operands| aren't the kind |code| takes.
     pub fn new(code: Opcode, operands: Operands) -> Self {
-        assert!(discriminant(&code.operands()) == discriminant(&operands), "invalid operands");
-        Self { code, operands }
+        Self::try_new(code, operands).expect("invalid operands")
+    }
+
+    pub fn try_new(code: Opcode, operands: Operands) -> Result<Self> {
+        if discriminant(&code.operands()) != discriminant(&operands) {
+            return Err(eyre!("invalid operands {operands:?} for {code}"));
+        }
+        Ok(Self { code, operands })
     }
 
     pub fn from_code(code: Opcode) -> Self {
@@ -1,23 +1,53 @@
+use std::fmt;
+
 use enumset::EnumSetType;
 use smallvec::{smallvec, SmallVec};
 use strum_macros::{Display, EnumIter};
 
+/// Index of a memory location, either a register or a constant. Use
+/// `LgpEvaluatorCfg::writable_reg` and `LgpEvaluatorCfg::readable_reg` to
+/// make ones checked against a memory layout. `RegId::raw` skips the checks,
+/// for vm internals and code which already knows the index is valid.
+#[must_use]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct RegId(u8);
+
+impl RegId {
+    pub const fn raw(idx: u8) -> Self {
+        Self(idx)
+    }
+
+    #[must_use]
+    pub const fn idx(self) -> u8 {
+        self.0
+    }
+}
+
+impl fmt::Display for RegId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "r{}", self.0)
+    }
+}
+
 #[must_use]
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Operands {
     /// Compare two registers.
-    Reg2Cmp { ra: u8, rb: u8 },
+    Reg2Cmp { ra: RegId, rb: RegId },
     /// Assign function of register to another.
-    Reg2Assign { ri: u8, ra: u8 },
+    Reg2Assign { ri: RegId, ra: RegId },
     /// Assign function of two registers to another.
-    Reg3Assign { ri: u8, ra: u8, rb: u8 },
+    Reg3Assign { ri: RegId, ra: RegId, rb: RegId },
     /// Assign immediate value to register.
-    ImmAssign { ri: u8, imm: f32 },
+    ImmAssign { ri: RegId, imm: f32 },
 }
 
 impl Operands {
     #[must_use]
-    pub fn input_regs(&self) -> SmallVec<[u8; 2]> {
+    pub fn input_regs(&self) -> SmallVec<[RegId; 2]> {
         match *self {
             Operands::Reg2Assign { ra, .. } => smallvec![ra],
             Operands::Reg3Assign { ra, rb, .. } | Operands::Reg2Cmp { ra, rb } => smallvec![ra, rb],
@@ -26,7 +56,7 @@ impl Operands {
     }
 
     #[must_use]
-    pub fn output_regs(&self) -> SmallVec<[u8; 1]> {
+    pub fn output_regs(&self) -> SmallVec<[RegId; 1]> {
         match *self {
             Operands::Reg2Cmp { .. } => smallvec![],
             Operands::Reg2Assign { ri, .. }
@@ -40,13 +70,14 @@ impl Operands {
 /// Opcodes are 8 bit and have variable number of operands.
 #[must_use]
 #[derive(EnumSetType, Debug, Display, PartialOrd, EnumIter)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Opcode {
     // Arithmetic - three register assignments:
     Add, // add ri, ra, rb: rx = rx + ry
     Sub, // sub ri, ra, rb: ri = ra - rb
     Mul, // mul ri, ra, rb: ri = ra * rb
     Div, // div ri, ra, rb: ri = ra / rb - Div by zero is ignored.
-    Pow, // pow ri, ra, rb: ri = ra ^ rb - Infinite value is ignored.
+    Pow, // pow ri, ra, rb: ri = ra ^ rb - Negative bases depend on PowPolicy.
 
     // Arithmetic - two register assignments:
     Abs, // abs ri, ra: ri = |ra|
@@ -68,16 +99,16 @@ impl Opcode {
         match self {
             // Three reg assign
             Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Pow => {
-                Operands::Reg3Assign { ri: 0, ra: 0, rb: 0 }
+                Operands::Reg3Assign { ri: RegId(0), ra: RegId(0), rb: RegId(0) }
             }
             // Two reg assign:
             Opcode::Abs | Opcode::Neg | Opcode::Ln | Opcode::Sin | Opcode::Cos | Opcode::Copy => {
-                Operands::Reg2Assign { ri: 0, ra: 0 }
+                Operands::Reg2Assign { ri: RegId(0), ra: RegId(0) }
             }
             // Immediate assign
-            Opcode::Load => Operands::ImmAssign { ri: 0, imm: 0.0 },
+            Opcode::Load => Operands::ImmAssign { ri: RegId(0), imm: 0.0 },
             // Two reg compare:
-            Opcode::IfLt => Operands::Reg2Cmp { ra: 0, rb: 0 },
+            Opcode::IfLt => Operands::Reg2Cmp { ra: RegId(0), rb: RegId(0) },
         }
     }
 
@@ -85,4 +116,21 @@ impl Opcode {
     pub fn is_branch(&self) -> bool {
         matches!(self, Opcode::IfLt)
     }
+
+    #[must_use]
+    pub fn is_arithmetic(&self) -> bool {
+        matches!(
+            self,
+            Opcode::Add
+                | Opcode::Sub
+                | Opcode::Mul
+                | Opcode::Div
+                | Opcode::Pow
+                | Opcode::Abs
+                | Opcode::Neg
+                | Opcode::Ln
+                | Opcode::Sin
+                | Opcode::Cos
+        )
+    }
 }
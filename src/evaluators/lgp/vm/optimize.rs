@@ -1,6 +1,7 @@
 use smallvec::{smallvec, SmallVec};
 
 use crate::evaluators::lgp::vm::op::Op;
+use crate::evaluators::lgp::vm::opcode::RegId;
 
 /// Virtual machine for lgp code. Programs should not be able to run forever,
 /// and have acyclic control flow graphs.
@@ -16,57 +17,132 @@ impl LgpOptimizer {
         Self { code: code.to_vec(), output_regs: output_regs.into() }
     }
 
+    /// Code which can affect the output registers. Empty if no output
+    /// register is ever written, see `writes_outputs`.
     #[must_use]
     pub fn optimize(&self) -> Vec<Op> {
-        let mut eff_regs = [false; u8::MAX as usize];
-        for reg in &self.output_regs {
-            eff_regs[*reg as usize] = true;
-        }
+        self.effective_indices().into_iter().map(|idx| self.code[idx]).collect()
+    }
 
-        let mut eff_code = vec![];
-        let mut next_effective = false;
-        let mut next_output_regs: SmallVec<[u8; 1]> = smallvec![];
-        for op in self.code.iter().rev() {
-            // Check to see if this op affects an effective register.
-            let mut effective = false;
-            for output in op.operands().output_regs() {
-                if eff_regs[output as usize] {
-                    effective = true;
-                    // Changes to this register earlier in the program no longer
-                    // affect the final output registers (unless it is part of a
-                    // branch).
-                    eff_regs[output as usize] = false;
-                }
-            }
+    /// Whether any instruction writes to an output register, possibly
+    /// conditionally. If not, the outputs are just their initial values.
+    #[must_use]
+    pub fn writes_outputs(&self) -> bool {
+        self.code.iter().any(|op| {
+            op.operands().output_regs().iter().any(|r| self.output_regs.contains(&r.idx()))
+        })
+    }
+
+    /// Indices of the instructions which can affect the output registers, in
+    /// increasing order.
+    #[must_use]
+    pub fn effective_indices(&self) -> Vec<usize> {
+        effective_indices(&self.code, &self.output_regs)
+    }
+
+    /// Which instructions and registers can affect the output registers, at
+    /// each position in the code. See `Effectiveness`.
+    pub fn effectiveness(&self) -> Effectiveness {
+        effectiveness(&self.code, &self.output_regs)
+    }
+}
+
+/// Which parts of some code can affect the output registers.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Effectiveness {
+    /// Whether each instruction can affect the output registers.
+    pub effective: Vec<bool>,
+    /// Registers, including constants, whose value just before each
+    /// instruction can affect the output registers, in increasing order.
+    /// Has a final entry for the end of the code. An instruction inserted at
+    /// index i is effective if it writes one of `eff_regs[i]`.
+    pub eff_regs: Vec<SmallVec<[u8; 8]>>,
+}
 
-            // If this op is a branch, and the next instruction in the program
-            // is effective, then this op is also effective.
-            // Also, re-add the next ops output registers to the effective
-            // register set, since the branch might not execute, and those
-            // output registers that we previously removed could still be
-            // effective.
-            if next_effective && op.code().is_branch() {
+impl Effectiveness {
+    /// Indices of the effective instructions, in increasing order.
+    #[must_use]
+    pub fn indices(&self) -> Vec<usize> {
+        self.effective.iter().enumerate().filter_map(|(idx, &v)| v.then_some(idx)).collect()
+    }
+}
+
+/// Indices of the instructions in |code| which can affect the given output
+/// registers, in increasing order. Works backwards from the outputs, keeping instructions
+/// which write a register that is read later, and branches guarding them.
+#[must_use]
+pub fn effective_indices(code: &[Op], output_regs: &[u8]) -> Vec<usize> {
+    let effective = scan(code, output_regs, |_, _| {});
+    effective.iter().enumerate().filter_map(|(idx, &v)| v.then_some(idx)).collect()
+}
+
+/// Like `effective_indices`, but also works out which registers can affect
+/// the outputs at each position.
+pub fn effectiveness(code: &[Op], output_regs: &[u8]) -> Effectiveness {
+    let mut eff_regs = vec![SmallVec::new(); code.len() + 1];
+    let effective = scan(code, output_regs, |idx, regs| {
+        eff_regs[idx] = (0..=u8::MAX).filter(|&r| regs[r as usize]).collect();
+    });
+    Effectiveness { effective, eff_regs }
+}
+
+// Whether each instruction in |code| can affect the given output registers.
+// Calls |on_regs| with each index, from the end of the code backwards, and the
+// registers which can affect the outputs just before it.
+fn scan(
+    code: &[Op],
+    output_regs: &[u8],
+    mut on_regs: impl FnMut(usize, &[bool; u8::MAX as usize + 1]),
+) -> Vec<bool> {
+    let mut eff_regs = [false; u8::MAX as usize + 1];
+    for reg in output_regs {
+        eff_regs[*reg as usize] = true;
+    }
+    on_regs(code.len(), &eff_regs);
+
+    let mut eff = vec![false; code.len()];
+    let mut next_effective = false;
+    let mut next_output_regs: SmallVec<[RegId; 1]> = smallvec![];
+    for (idx, op) in code.iter().enumerate().rev() {
+        // Check to see if this op affects an effective register.
+        let mut effective = false;
+        for output in op.operands().output_regs() {
+            if eff_regs[output.idx() as usize] {
                 effective = true;
-                for reg in next_output_regs {
-                    eff_regs[reg as usize] = true;
-                }
+                // Changes to this register earlier in the program no longer
+                // affect the final output registers (unless it is part of a
+                // branch).
+                eff_regs[output.idx() as usize] = false;
             }
+        }
 
-            // If this op is reachable, add it to the reachable code and append
-            // its inputs to the reachable registers.
-            if effective {
-                eff_code.push(*op);
-                for input in op.operands().input_regs() {
-                    eff_regs[input as usize] = true;
-                }
+        // If this op is a branch, and the next instruction in the program
+        // is effective, then this op is also effective.
+        // Also, re-add the next ops output registers to the effective
+        // register set, since the branch might not execute, and those
+        // output registers that we previously removed could still be
+        // effective.
+        if next_effective && op.code().is_branch() {
+            effective = true;
+            for reg in next_output_regs {
+                eff_regs[reg.idx() as usize] = true;
             }
-            next_effective = effective;
-            next_output_regs = op.operands().output_regs();
         }
 
-        eff_code.reverse();
-        eff_code
+        // If this op is reachable, mark it as effective and append its
+        // inputs to the reachable registers.
+        if effective {
+            eff[idx] = true;
+            for input in op.operands().input_regs() {
+                eff_regs[input.idx() as usize] = true;
+            }
+        }
+        on_regs(idx, &eff_regs);
+        next_effective = effective;
+        next_output_regs = op.operands().output_regs();
     }
+    eff
 }
 
 #[cfg(test)]
@@ -190,6 +266,44 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn effective_indices() -> Result<()> {
+        let code = lgp_asm(
+            "add r4, r1, r2\n\
+            add r3, r1, r2\n\
+            add r1, r1, r2\n\
+            iflt r2, r3\n\
+            mul r1, r2, r3\n\
+            add r0, r1, r1\n",
+        )?;
+        assert_eq!(LgpOptimizer::new(&code, &[0]).effective_indices(), [1, 2, 3, 4, 5]);
+        assert_eq!(LgpOptimizer::new(&code, &[4]).effective_indices(), [0]);
+        Ok(())
+    }
+
+    #[test]
+    fn effectiveness() -> Result<()> {
+        let code = lgp_asm(
+            "add r3, r1, r2\n\
+            add r1, r1, r2\n\
+            iflt r2, r3\n\
+            mul r1, r2, r3\n\
+            add r0, r1, r1\n",
+        )?;
+        let opt = LgpOptimizer::new(&code, &[0]);
+        let eff = opt.effectiveness();
+        assert_eq!(eff.effective, [true, true, true, true, true]);
+        assert_eq!(eff.indices(), opt.effective_indices());
+        let regs = eff.eff_regs.iter().map(|v| v.to_vec()).collect::<Vec<_>>();
+        // The branch might skip the mul, so r1 can matter before it.
+        assert_eq!(regs, [vec![1, 2], vec![1, 2, 3], vec![1, 2, 3], vec![2, 3], vec![1], vec![0]]);
+
+        let eff = LgpOptimizer::new(&code, &[4]).effectiveness();
+        assert!(eff.indices().is_empty());
+        assert!(eff.eff_regs.iter().all(|v| v.as_slice() == [4]));
+        Ok(())
+    }
+
     #[test]
     fn optimize_keep_last_branch() -> Result<()> {
         let code = lgp_asm(
@@ -201,4 +315,18 @@ mod tests {
         assert_eq!(expected, lgp_disasm(&LgpOptimizer::new(&code, &[0]).optimize()));
         Ok(())
     }
+
+    #[test]
+    fn writes_outputs() -> Result<()> {
+        let code = lgp_asm("add r1, r2, r3\nload r2, 1\niflt r1, r2\n")?;
+        let opt = LgpOptimizer::new(&code, &[0]);
+        assert!(!opt.writes_outputs());
+        assert!(opt.optimize().is_empty());
+        assert!(LgpOptimizer::new(&code, &[0, 2]).writes_outputs());
+
+        // Conditional writes count.
+        let code = lgp_asm("iflt r1, r2\ncopy r0, r1\n")?;
+        assert!(LgpOptimizer::new(&code, &[0]).writes_outputs());
+        Ok(())
+    }
 }
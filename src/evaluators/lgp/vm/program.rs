@@ -0,0 +1,152 @@
+use eyre::{eyre, Result};
+
+use crate::evaluators::lgp::vm::asm::lgp_asm;
+use crate::evaluators::lgp::vm::disasm::lgp_disasm;
+use crate::evaluators::lgp::vm::op::Op;
+use crate::evaluators::lgp::vm::optimize::LgpOptimizer;
+
+/// An lgp program along with the memory it expects, for use outside of the
+/// evaluator, e.g. by external tools. Memory is `num_reg` read-write registers
+/// followed by `num_const` read only constants.
+///
+/// With the `serde` feature, deserialized programs are validated.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "UncheckedLgpProgram"))]
+pub struct LgpProgram {
+    pub ops: Vec<Op>,
+    pub num_reg: usize,
+    pub num_const: usize,
+    pub output_regs: Vec<u8>,
+}
+
+impl LgpProgram {
+    pub fn new(ops: Vec<Op>, num_reg: usize, num_const: usize, output_regs: &[u8]) -> Self {
+        Self { ops, num_reg, num_const, output_regs: output_regs.to_vec() }
+    }
+
+    /// Assembles |s| into a program with the given memory and checks it is
+    /// valid.
+    pub fn asm(s: &str, num_reg: usize, num_const: usize, output_regs: &[u8]) -> Result<Self> {
+        let program = Self::new(lgp_asm(s)?, num_reg, num_const, output_regs);
+        program.validate()?;
+        Ok(program)
+    }
+
+    #[must_use]
+    pub fn disasm(&self) -> String {
+        lgp_disasm(&self.ops)
+    }
+
+    fn optimizer(&self) -> LgpOptimizer {
+        LgpOptimizer::new(&self.ops, &self.output_regs)
+    }
+
+    /// The program with instructions which can't affect the outputs removed.
+    pub fn optimize(&self) -> Self {
+        Self { ops: self.optimizer().optimize(), ..self.clone() }
+    }
+
+    /// Indices of the instructions which can affect the outputs.
+    #[must_use]
+    pub fn effective_indices(&self) -> Vec<usize> {
+        self.optimizer().effective_indices()
+    }
+
+    /// Checks that memory fits in the vm and that every register is in
+    /// bounds. Instructions and outputs may only write to registers, not
+    /// constants.
+    pub fn validate(&self) -> Result<()> {
+        let mem_size = self.num_reg + self.num_const;
+        if mem_size > 256 {
+            return Err(eyre!("cannot use more than 256 memory locations, got {mem_size}"));
+        }
+        if let Some(reg) = self.output_regs.iter().find(|&&reg| reg as usize >= self.num_reg) {
+            return Err(eyre!("output register r{reg} out of range, num_reg is {}", self.num_reg));
+        }
+        for (idx, op) in self.ops.iter().enumerate() {
+            let operands = op.operands();
+            if let Some(reg) =
+                operands.output_regs().into_iter().find(|r| r.idx() as usize >= self.num_reg)
+            {
+                return Err(eyre!(
+                    "instruction {idx} '{op}' writes {reg}, num_reg is {}",
+                    self.num_reg
+                ));
+            }
+            if let Some(reg) =
+                operands.input_regs().into_iter().find(|r| r.idx() as usize >= mem_size)
+            {
+                return Err(eyre!(
+                    "instruction {idx} '{op}' reads {reg}, memory size is {mem_size}"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct UncheckedLgpProgram {
+    ops: Vec<Op>,
+    num_reg: usize,
+    num_const: usize,
+    output_regs: Vec<u8>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<UncheckedLgpProgram> for LgpProgram {
+    type Error = String;
+
+    fn try_from(p: UncheckedLgpProgram) -> Result<Self, Self::Error> {
+        let program = Self::new(p.ops, p.num_reg, p.num_const, &p.output_regs);
+        program.validate().map_err(|e| e.to_string())?;
+        Ok(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::evaluators::lgp::cfg::LgpEvaluatorCfg;
+
+    #[test]
+    fn validation() -> Result<()> {
+        let valid = "load r1, 2\nadd r0, r1, r2\niflt r0, r2\nneg r0, r0\n";
+        let _ = LgpProgram::asm(valid, 2, 1, &[0])?;
+        // Reading past the constants.
+        assert!(LgpProgram::asm("add r0, r1, r3", 2, 1, &[0]).is_err());
+        assert!(LgpProgram::asm("iflt r3, r0", 2, 1, &[0]).is_err());
+        // Writing to a constant.
+        assert!(LgpProgram::asm("copy r2, r0", 2, 1, &[0]).is_err());
+        assert!(LgpProgram::asm("load r2, 1.0", 2, 1, &[0]).is_err());
+        // Outputs must be registers.
+        assert!(LgpProgram::asm("copy r0, r2", 2, 1, &[2]).is_err());
+        // Too much memory.
+        assert!(LgpProgram::new(vec![], 200, 57, &[0]).validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn matches_slice_functions() {
+        let (lgpcfg, layout) = LgpEvaluatorCfg::for_problem(3, 2);
+        for _ in 0..100 {
+            let ops = (0..30).map(|_| lgpcfg.rand_op()).collect::<Vec<_>>();
+            let program = LgpProgram::new(
+                ops.clone(),
+                layout.num_reg(),
+                layout.num_const(),
+                &layout.output_indices,
+            );
+            assert!(program.validate().is_ok());
+            let optimizer = LgpOptimizer::new(&ops, &layout.output_indices);
+            assert_eq!(program.optimize().ops, optimizer.optimize());
+            assert_eq!(program.effective_indices(), optimizer.effective_indices());
+            assert_eq!(program.disasm(), lgp_disasm(&ops));
+        }
+    }
+}
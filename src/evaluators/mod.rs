@@ -1,2 +1,4 @@
 pub mod hyper;
+pub mod intvec;
+#[cfg(feature = "lgp")]
 pub mod lgp;
@@ -0,0 +1,151 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::eval::State;
+use crate::gen::member::Member;
+
+#[derive(Debug)]
+struct Inner<S: State> {
+    capacity: usize,
+    // Sorted by fitness, highest first. States are unique.
+    mems: RwLock<Vec<Member<S>>>,
+    // Bits of the best fitness, so it can be polled without locking.
+    best_fitness: AtomicU64,
+}
+
+/// Best members seen so far, shared between any number of evolvers, possibly
+/// on different threads. Cloning gives another handle to the same archive.
+#[must_use]
+#[derive(Debug, Clone)]
+pub struct SharedArchive<S: State> {
+    inner: Arc<Inner<S>>,
+}
+
+impl<S: State> SharedArchive<S> {
+    /// Archive keeping the top |capacity| members.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "archive must hold at least one member");
+        Self {
+            inner: Arc::new(Inner {
+                capacity,
+                mems: RwLock::new(Vec::with_capacity(capacity + 1)),
+                best_fitness: AtomicU64::new(f64::NEG_INFINITY.to_bits()),
+            }),
+        }
+    }
+
+    /// Adds |mem| if it is fit enough and no member with an equal state is
+    /// already in the archive. Returns whether it was added.
+    #[must_use]
+    pub fn insert(&self, mem: &Member<S>) -> bool {
+        if mem.fitness.is_nan() {
+            return false;
+        }
+        let mut mems = self.inner.mems.write().unwrap();
+        if mems.len() >= self.inner.capacity && mems.last().unwrap().fitness >= mem.fitness {
+            return false;
+        }
+        if mems.iter().any(|v| v.state == mem.state) {
+            return false;
+        }
+        let idx = mems.partition_point(|v| v.fitness >= mem.fitness);
+        mems.insert(idx, mem.clone());
+        mems.truncate(self.inner.capacity);
+        // Only written under the lock, so it can't go backwards.
+        self.inner.best_fitness.store(mems[0].fitness.to_bits(), Ordering::Release);
+        true
+    }
+
+    /// Fitness of the best member, or negative infinity if the archive is
+    /// empty. Doesn't take the lock, so it is cheap enough to poll.
+    #[must_use]
+    pub fn best_fitness(&self) -> f64 {
+        f64::from_bits(self.inner.best_fitness.load(Ordering::Acquire))
+    }
+
+    #[must_use]
+    pub fn best(&self) -> Option<Member<S>> {
+        self.inner.mems.read().unwrap().first().cloned()
+    }
+
+    /// Members in the archive, fittest first.
+    #[must_use]
+    pub fn mems(&self) -> Vec<Member<S>> {
+        self.inner.mems.read().unwrap().clone()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.inner.mems.read().unwrap().len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::evolve::cfg::EvolveCfg;
+    use crate::util::bench_utils::CountEvaluator;
+
+    fn mem(state: usize, fitness: f64) -> Member<usize> {
+        let mut mem = Member::new::<CountEvaluator>(state, &EvolveCfg::new(1));
+        mem.fitness = fitness;
+        mem
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn insert_keeps_top_unique() {
+        let archive = SharedArchive::new(3);
+        assert_eq!(archive.best_fitness(), f64::NEG_INFINITY);
+        assert!(archive.insert(&mem(1, 1.0)));
+        assert!(!archive.insert(&mem(1, 1.0)));
+        assert!(archive.insert(&mem(2, 3.0)));
+        assert!(archive.insert(&mem(3, 2.0)));
+        assert!(!archive.insert(&mem(4, 0.5)));
+        assert!(archive.insert(&mem(5, 4.0)));
+        assert!(!archive.insert(&mem(6, f64::NAN)));
+        let states = archive.mems().iter().map(|v| *v.state).collect::<Vec<_>>();
+        assert_eq!(states, [5, 2, 3]);
+        assert_eq!(archive.best_fitness(), 4.0);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn concurrent_insert() {
+        const THREADS: usize = 8;
+        const PER_THREAD: usize = 2000;
+        const CAPACITY: usize = 50;
+        let archive = SharedArchive::new(CAPACITY);
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let archive = archive.clone();
+                s.spawn(move || {
+                    // Threads insert overlapping states in different orders.
+                    for i in 0..PER_THREAD {
+                        let state = (i * (t + 1)) % PER_THREAD;
+                        let _ = archive.insert(&mem(state, state as f64));
+                        assert!(archive.best_fitness() >= state as f64);
+                    }
+                });
+            }
+        });
+        let states = archive.mems().iter().map(|v| *v.state).collect::<Vec<_>>();
+        let expected = (PER_THREAD - CAPACITY..PER_THREAD).rev().collect::<Vec<_>>();
+        assert_eq!(states, expected);
+        assert_eq!(archive.best_fitness(), (PER_THREAD - 1) as f64);
+    }
+}
@@ -1,20 +1,37 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::time::Duration;
+
+use enumset::EnumSetType;
+#[cfg(feature = "serde")]
+use eyre::WrapErr;
+use eyre::{eyre, Result};
 use rand::Rng;
 use rand_distr::{Distribution, Standard};
 
 use crate::gen::species::SpeciesId;
+use crate::util::par::num_threads;
 
 #[must_use]
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 // Only one crossover function will be applied at a time.
 pub enum Crossover {
     // Fixed with given rate. Specify the weights for each crossover function.
     Fixed(Vec<f64>),
     // Adaptive - uses 1/sqrt(pop size) as learning rate.
     Adaptive,
+    // Differential evolution (DE/rand/1/bin), see `Evaluator::differential_rng`.
+    // Each child is its parent moved by |f| times the difference of two other
+    // members, in each component with probability |cr|, and replaces the
+    // parent only if at least as fit. That costs an extra fitness evaluation
+    // per child. Crossover weights are unused. Needs at least four members.
+    Differential { f: f64, cr: f64 },
 }
 
 #[must_use]
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 // Each mutation function will be applied with the given rate. This is different to crossover,
 // which is only applied once.
 pub enum Mutation {
@@ -26,11 +43,47 @@ pub enum Mutation {
 
 #[must_use]
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Survival {
     TopProportion(f64),
     SpeciesTopProportion(f64), // Top proportion for each species.
     Youngest,                  // Only the youngest members survive. Age based replacement.
     Tournament(usize),         // Tournament selection. Tournament size is given.
+    // Like |Tournament|, but members are ranked by how many of their
+    // opponents they beat rather than by how many beat them.
+    TournamentWins(usize),
+    // Samples the given proportion of members without replacement, each with
+    // weight exp(fitness / T). T starts at |initial_temp| and is multiplied by
+    // |decay| every generation, so survival gets more elitist over time.
+    Boltzmann { initial_temp: f64, decay: f64, prop: f64 },
+}
+
+impl Survival {
+    /// `Boltzmann` survival keeping the same proportion of members as the
+    /// default `TopProportion(0.2)`.
+    pub fn boltzmann(initial_temp: f64, decay: f64) -> Self {
+        Self::Boltzmann { initial_temp, decay, prop: 0.2 }
+    }
+
+    /// Temperature at the given generation for `Boltzmann` survival.
+    #[must_use]
+    pub fn temperature(&self, gen_idx: usize) -> Option<f64> {
+        match *self {
+            Self::Boltzmann { initial_temp, decay, .. } => {
+                Some(initial_temp * decay.powf(gen_idx as f64))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Which fitness survivor selection compares members by.
+#[must_use]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SurvivalFitness {
+    Base,   // Plain fitness, so the fittest members are kept.
+    Shared, // Selection fitness, so survival also respects niching and other fitness stages.
 }
 
 impl Distribution<Survival> for Standard {
@@ -44,56 +97,168 @@ impl Distribution<Survival> for Standard {
 
 #[must_use]
 #[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Selection {
     Sus,
     Roulette,
+    // SUS on linear rank weights rather than selection fitness, so selection
+    // doesn't depend on how fitnesses are scaled. Equal fitnesses get the
+    // average of their ranks.
+    Rank(RankPressure),
+    // Each parent is the member with the best selection fitness out of this
+    // many sampled uniformly. Clamped to the population size, and a size of 1
+    // selects uniformly at random.
+    Tournament(usize),
 }
 
 impl Distribution<Selection> for Standard {
     fn sample<R: Rng + ?Sized>(&self, r: &mut R) -> Selection {
-        match r.gen_range(0..2) {
+        match r.gen_range(0..4) {
             0 => Selection::Sus,
-            _ => Selection::Roulette,
+            1 => Selection::Roulette,
+            2 => Selection::Rank(RankPressure(r.gen_range(1.0..=2.0))),
+            _ => Selection::Tournament(r.gen_range(2..8)),
         }
     }
 }
 
+/// Selection pressure of `Selection::Rank`: the expected number of picks of
+/// the best member per pick of an average one. In [1, 2], where 1 selects
+/// uniformly.
 #[must_use]
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "f64", into = "f64"))]
+pub struct RankPressure(f64);
+
+// Never NaN, since it's checked to be in [1, 2].
+impl Eq for RankPressure {}
+
+impl RankPressure {
+    pub fn new(pressure: f64) -> Result<Self> {
+        if !(1.0..=2.0).contains(&pressure) {
+            return Err(eyre!("rank selection pressure must be in [1, 2]: {pressure}"));
+        }
+        Ok(Self(pressure))
+    }
+
+    #[must_use]
+    pub fn get(self) -> f64 {
+        self.0
+    }
+}
+
+impl TryFrom<f64> for RankPressure {
+    type Error = eyre::Report;
+
+    fn try_from(pressure: f64) -> Result<Self> {
+        Self::new(pressure)
+    }
+}
+
+impl From<RankPressure> for f64 {
+    fn from(pressure: RankPressure) -> Self {
+        pressure.get()
+    }
+}
+
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Niching {
     None,
     SharedFitness(f64),   // Takes a distance for fitness sharing
     SpeciesSharedFitness, // Derives sharing distance from species information.
+    // Picks the sharing distance each generation so that on average a member
+    // shares fitness with |target_fraction| of the population.
+    SharedFitnessAuto { target_fraction: f64 },
 }
 
 impl Distribution<Niching> for Standard {
     fn sample<R: Rng + ?Sized>(&self, r: &mut R) -> Niching {
-        match r.gen_range(0..3) {
+        match r.gen_range(0..4) {
             0 => Niching::None,
             1 => Niching::SharedFitness(r.gen_range(0.0..100.0)), // TODO: Hardcoded.
+            2 => Niching::SharedFitnessAuto { target_fraction: r.gen_range(0.01..0.5) },
             _ => Niching::SpeciesSharedFitness,
         }
     }
 }
 
+/// Fraction of the population, in (0, 1].
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "f64", into = "f64"))]
+pub struct PopFraction(f64);
+
+// Never NaN, since it's checked to be in (0, 1].
+impl Eq for PopFraction {}
+
+impl PopFraction {
+    pub fn new(frac: f64) -> Result<Self> {
+        if !(frac > 0.0 && frac <= 1.0) {
+            return Err(eyre!("population fraction must be in (0, 1]: {frac}"));
+        }
+        Ok(Self(frac))
+    }
+
+    #[must_use]
+    pub fn get(self) -> f64 {
+        self.0
+    }
+}
+
+impl TryFrom<f64> for PopFraction {
+    type Error = eyre::Report;
+
+    fn try_from(frac: f64) -> Result<Self> {
+        Self::new(frac)
+    }
+}
+
+impl From<PopFraction> for f64 {
+    fn from(frac: PopFraction) -> Self {
+        frac.get()
+    }
+}
+
 #[must_use]
 #[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Species {
     None,
-    TargetNumber(SpeciesId), // Target number of species.
+    // Target number of species, counted as `Stats` reports them. Each
+    // generation searches for the species radius which gives this many.
+    TargetNumber(SpeciesId),
+    // Adjust the target number of species between generations, within
+    // [min, max], based on the observed species sizes. Needs 1 <= min <= max.
+    AutoTarget { min: SpeciesId, max: SpeciesId },
+    // Like TargetNumber, but species smaller than |min_size| are merged into
+    // their nearest neighbour and species are capped at |max_frac| of the
+    // population, with the excess moved to the nearest species with room.
+    TargetNumberBounded { target: SpeciesId, min_size: usize, max_frac: PopFraction },
 }
 
 impl Distribution<Species> for Standard {
     fn sample<R: Rng + ?Sized>(&self, r: &mut R) -> Species {
-        match r.gen_range(0..2) {
+        match r.gen_range(0..4) {
             0 => Species::None,
-            _ => Species::TargetNumber(r.gen_range(1..10)), // TODO: Hardcoded.
+            1 => Species::TargetNumber(r.gen_range(1..10)), // TODO: Hardcoded.
+            2 => Species::AutoTarget { min: 1, max: r.gen_range(2..20) }, // TODO: Hardcoded.
+            // TODO: Hardcoded.
+            _ => Species::TargetNumberBounded {
+                target: r.gen_range(1..10),
+                min_size: r.gen_range(1..5),
+                max_frac: PopFraction(r.gen_range(0.2..1.0)),
+            },
         }
     }
 }
 
 #[must_use]
 #[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Stagnation {
     None,
     // After N generations of the same best fitness, trigger stagnation once.
@@ -114,6 +279,7 @@ impl Distribution<Stagnation> for Standard {
 
 #[must_use]
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StagnationCondition {
     // Use default epsilon and relative comparison to determine stagnation.
     Default,
@@ -130,24 +296,91 @@ impl Distribution<StagnationCondition> for Standard {
     }
 }
 
+#[must_use]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StagnationSignal {
+    // Best training fitness of each generation.
+    TrainBest,
+    // Best validation fitness, which is computed every N generations and fed
+    // in via `Evolver::report_external_fitness`. Useful if training fitness is
+    // noisy, e.g. due to sampling a different batch of data each generation.
+    ValidationBest { every: usize },
+    // Moving average of the best training fitness over the last N generations.
+    TrainBestSmoothed { window: usize },
+}
+
 #[must_use]
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Replacement {
     // During stagnation make a proportion of the children with random individuals.
+    // Does nothing if survivors already fill the population.
     ReplaceChildren(f64),
+    // During stagnation replace the worst proportion of the whole population
+    // with random individuals.
+    ReplaceWorst(f64),
+    // During stagnation cross the best members of |pairs| randomly chosen
+    // pairs of distinct species, and add the mutated children. With fewer than
+    // two species, adds as many random individuals instead, as with
+    // `ReplaceChildren`.
+    HybridizeSpecies { pairs: usize },
 }
 
 impl Distribution<Replacement> for Standard {
     fn sample<R: Rng + ?Sized>(&self, r: &mut R) -> Replacement {
-        Replacement::ReplaceChildren(r.gen())
+        if r.gen::<bool>() {
+            Replacement::ReplaceChildren(r.gen())
+        } else {
+            Replacement::ReplaceWorst(r.gen())
+        }
     }
 }
 
+/// Which random individuals injected during stagnation get into the next
+/// generation. Random individuals often score far below the survivors and
+/// are dropped straight away, wasting their evaluation.
+#[must_use]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ReplacementFilter {
+    None, // Inject every random individual.
+    // Evaluate up to |attempts| random individuals for each injected slot,
+    // and inject the first which beats the worst survivor, feasibility first
+    // and then fitness, or the best one if none do. They're evaluated along
+    // with the generation they're injected into, so once only. Evaluations
+    // count towards `fitness_evals`. Not supported with comparative fitness.
+    BeatWorst { attempts: usize },
+}
+
 #[must_use]
 #[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ParamsCrossover {
+    Inherit,  // Children keep the params of the parent they were cloned from.
+    Average,  // Both children get the average of the parents' params.
+    SwapHalf, // Uniform crossover on the params.
+}
+
+impl Distribution<ParamsCrossover> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, r: &mut R) -> ParamsCrossover {
+        match r.gen_range(0..3) {
+            0 => ParamsCrossover::Inherit,
+            1 => ParamsCrossover::Average,
+            _ => ParamsCrossover::SwapHalf,
+        }
+    }
+}
+
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Duplicates {
     DisallowDuplicates, // Don't allow duplicate states in the population.
     AllowDuplicates,
+    // Treat states closer than the given distance as duplicates, keeping the
+    // fitter one.
+    DisallowWithinDistance(f64),
 }
 
 impl Distribution<Duplicates> for Standard {
@@ -163,6 +396,7 @@ impl Distribution<Duplicates> for Standard {
 /// given (`Evaluator::Data`)
 #[must_use]
 #[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FitnessReduction {
     ArithmeticMean,
     GeometricMean,
@@ -177,27 +411,305 @@ impl Distribution<FitnessReduction> for Standard {
     }
 }
 
+/// How constraint violations, from `Evaluator::violation`, affect the order
+/// of members.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConstraintMode {
+    None, // Members are ordered by fitness.
+    // Stochastic ranking (Runarsson & Yao). Neighbouring members are compared
+    // by fitness if both are feasible or with probability |pf|, and by
+    // violation otherwise. By default selection fitness is then linear in
+    // rank, in place of any niching. See `FitnessStage::Rank`.
+    StochasticRanking { pf: f64 },
+}
+
+/// How member fitness is computed. Comparative modes set fitness to the win
+/// rate, in [0, 1], from `Evaluator::compare` against other members, with
+/// ties counting as half a win. They go through the fitness pipeline like any
+/// other fitness.
+#[must_use]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Comparison {
+    None,       // Fitness from `Evaluator::fitness`.
+    RoundRobin, // Each member plays every other member.
+    // Each member plays |opponents| other members sampled without
+    // replacement, or every other member if there aren't that many.
+    Sampled { opponents: usize },
+}
+
+/// Optional work in a generation, which is skipped if it would go over
+/// `EvolveCfg::generation_time_budget`. Listed in the order it gets skipped.
+#[derive(EnumSetType, Debug, PartialOrd)]
+pub enum OptionalPhase {
+    Distances, // Distances between members, and so speciation and niching.
+    Reporting, // Species snapshots.
+}
+
+/// Discounts the selection fitness of old members so long-lived elites don't
+/// dominate selection forever. Base fitness is left alone, so survival still
+/// keeps the best members.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AgeDecay {
+    pub start_age: usize, // Members older than this have their selection fitness decayed.
+    pub rate: f64,        // How much to decay per generation past |start_age|.
+}
+
+impl AgeDecay {
+    pub fn new(start_age: usize, rate: f64) -> Self {
+        Self { start_age, rate }
+    }
+
+    #[must_use]
+    pub fn apply(&self, selection_fitness: f64, age: usize) -> f64 {
+        if age <= self.start_age {
+            return selection_fitness;
+        }
+        selection_fitness / (1.0 + self.rate * (age - self.start_age) as f64)
+    }
+}
+
+/// Different breeding settings for the first generations of a run, e.g. to
+/// explore with mutation alone while crossover between random members would
+/// mostly produce garbage. See `EvolveCfg::breeding_cfg`.
+#[must_use]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Warmup {
+    // Number of generations whose children are bred with these settings.
+    pub generations: usize,
+    // If false, children are bred by mutation alone.
+    pub crossover_enabled: bool,
+    // Used in place of `EvolveCfg::selection`.
+    pub selection: Selection,
+}
+
+impl Warmup {
+    pub fn new(generations: usize, crossover_enabled: bool, selection: Selection) -> Self {
+        Self { generations, crossover_enabled, selection }
+    }
+}
+
+/// Which children `LocalSearchCfg` applies local search to.
+#[must_use]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LocalSearchPolicy {
+    BestParents, // Children whose better parent is fittest.
+    Random,      // Children chosen uniformly at random.
+}
+
+/// Memetic local search on children after reproduction. See
+/// `Evaluator::local_search`.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LocalSearchCfg {
+    pub fraction: f64,    // Fraction of children to search, rounded to nearest.
+    pub max_iters: usize, // Budget passed to `Evaluator::local_search`.
+    pub policy: LocalSearchPolicy,
+}
+
+impl LocalSearchCfg {
+    pub fn new(fraction: f64, max_iters: usize, policy: LocalSearchPolicy) -> Self {
+        Self { fraction, max_iters, policy }
+    }
+}
+
+/// A step in turning fitness into selection fitness. The stages in
+/// `EvolveCfg::fitness_pipeline` run in order, each on the selection fitness
+/// left by the one before, starting from plain fitness. Order matters, e.g.
+/// scaling before sharing scales each member's fitness before dividing it
+/// between its neighbours, while scaling after sharing also scales the
+/// division.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FitnessStage {
+    // Shares selection fitness between nearby members. Needs distances, so
+    // does nothing if they were skipped to meet the generation time budget.
+    Niching(Niching),
+    // Selection fitness linear in rank, from the number of members for the
+    // best down to 1, replacing whatever came before. Ranks come from
+    // stochastic ranking with `ConstraintMode::StochasticRanking`, and from
+    // fitness otherwise.
+    Rank,
+    // Discounts the selection fitness of old members.
+    AgeDecay(AgeDecay),
+    // Raises selection fitness to this power. Above 1 increases selection
+    // pressure, below 1 decreases it.
+    Power(f64),
+    // Subtracts this multiple of the constraint violation, down to zero.
+    // Violations are computed if the pipeline has a penalty.
+    Penalty(f64),
+}
+
+#[must_use]
+#[derive(Debug, Copy, Clone)]
+pub enum Termination {
+    FixedGenerations(usize), // Once the evolver reaches the given generation.
+    // Once the generations run make at least the given number of fitness
+    // evaluations in total.
+    FitnessEvaluations(usize),
+    // Once the best fitness reaches the given target, compared with
+    // `TrainerCfg::target_condition`. See `TrainerCfg::target_signal`.
+    TargetFitness(f64),
+    Timeout(Duration), // Once training has run for the given wall-clock time.
+}
+
+impl Termination {
+    // Position of the variant, for ordering different kinds of condition.
+    fn kind(&self) -> usize {
+        match self {
+            Self::FixedGenerations(_) => 0,
+            Self::FitnessEvaluations(_) => 1,
+            Self::TargetFitness(_) => 2,
+            Self::Timeout(_) => 3,
+        }
+    }
+}
+
+// Targets are compared with `f64::total_cmp`, so even NaN equals itself and
+// `Termination` can be `Eq`.
+impl Ord for Termination {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::FixedGenerations(a), Self::FixedGenerations(b))
+            | (Self::FitnessEvaluations(a), Self::FitnessEvaluations(b)) => a.cmp(b),
+            (Self::TargetFitness(a), Self::TargetFitness(b)) => a.total_cmp(b),
+            (Self::Timeout(a), Self::Timeout(b)) => a.cmp(b),
+            _ => self.kind().cmp(&other.kind()),
+        }
+    }
+}
+
+impl PartialOrd for Termination {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Termination {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Termination {}
+
 #[must_use]
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EvolveCfg {
     pub pop_size: usize,
     pub crossover: Crossover,
+    /// Probability of applying crossover to a pair of parents, independent of
+    /// which operator gets chosen. If set, crossover operator 0 is no longer
+    /// treated as the no-op. See `Evaluator::crossover`.
+    pub crossover_probability: Option<f64>,
     pub mutation: Mutation, // Mutation rate per bit / basic block.
+    pub params_crossover: ParamsCrossover,
     pub survival: Survival,
+    pub survival_fitness: SurvivalFitness,
+    /// Number of the fittest members, by base fitness, copied unchanged into
+    /// the next generation whatever |survival| and |replacement| do. Capped
+    /// at |pop_size|.
+    pub elitism: usize,
     pub selection: Selection,
     pub niching: Niching,
     pub species: Species,
     pub stagnation: Stagnation,
     pub stagnation_condition: StagnationCondition,
+    pub stagnation_signal: StagnationSignal,
     pub replacement: Replacement,
+    pub replacement_filter: ReplacementFilter,
     pub duplicates: Duplicates,
     pub fitness_reduction: FitnessReduction,
+    pub age_decay: Option<AgeDecay>,
+    pub constraint_mode: ConstraintMode,
+    pub comparison: Comparison,
+
+    /// Stages turning fitness into selection fitness, in order. If None,
+    /// applies |niching|, then ranking for `ConstraintMode::StochasticRanking`,
+    /// then |age_decay|. See `fitness_stages`.
+    pub fitness_pipeline: Option<Vec<FitnessStage>>,
+
+    /// Breed the children of the first generations with different settings.
+    pub warmup: Option<Warmup>,
+
+    /// Apply local search to some of the children bred each generation.
+    pub local_search: Option<LocalSearchCfg>,
+
+    /// Evaluate members with equal states once per generation and give them
+    /// all the same fitness. With noisy fitness, copies then share one
+    /// sample, rather than each getting its own. Doesn't apply to comparative
+    /// fitness. On by default.
+    pub share_duplicate_fitness: bool,
 
     /// Run fitness computations in parallel
     pub par_fitness: bool,
 
     /// Run distance computations in parallel
     pub par_dist: bool,
+
+    /// Number of items each parallel task evaluates, for both fitness and
+    /// distance computations. If None, picks one based on the number of items
+    /// and threads.
+    pub fitness_chunk_size: Option<usize>,
+
+    /// Master seed for the rng passed to `Evaluator::fitness_rng`. Each
+    /// member gets its own rng derived from the seed, the generation index and
+    /// the member's index, so fitness doesn't depend on evaluation order. If
+    /// None, fitness uses the thread rng.
+    pub fitness_seed: Option<u64>,
+
+    /// Memory threshold in bytes for low-memory mode. Once the states of an
+    /// evaluated generation take more than this, as measured by
+    /// `Evaluator::state_bytes`, only members which survive into the next
+    /// generation are kept in the returned `EvolveResult`, so the rest can be
+    /// freed while the result is held, e.g. by a trainer. Statistics computed from the
+    /// result, like `num_dup` and summaries, then only cover the survivors.
+    /// If None, every member is kept.
+    pub low_memory: Option<usize>,
+
+    /// Estimate `num_dup` and mean distance for `Stats` from this many
+    /// sampled members and pairs, instead of computing them exactly. For very
+    /// large populations, where sorting the population or computing every
+    /// pairwise distance is too slow.
+    pub approx_stats: Option<usize>,
+
+    /// Record how each child was bred in `UnevaluatedGen::trace`, for
+    /// debugging what happens in a generation.
+    pub trace: bool,
+
+    /// Record a `SpeciesSnapshot` in each `EvolveResult`. Only has an effect
+    /// if speciation is enabled.
+    pub species_snapshots: bool,
+
+    /// Members within this distance of the best member count towards the
+    /// takeover fraction. Distances are only used if they were computed for
+    /// the generation, otherwise members must be equal to the best.
+    pub takeover_epsilon: f64,
+
+    /// Log a warning when the takeover fraction goes above this.
+    pub takeover_warning: Option<f64>,
+
+    /// Check the evaluator's distance is zero from a member to itself,
+    /// non-negative and symmetric, on a sample of pairs the first time a run
+    /// computes distances. On by default in debug builds.
+    pub validate_distance: bool,
+
+    /// Time each generation should take. Fitness of every member and
+    /// reproduction always happen, but `OptionalPhase`s are skipped if the
+    /// budget is used up. Time is reserved for reproduction based on how long
+    /// it took in the previous generation. Without distances, selection uses
+    /// plain fitness as with `Niching::None`.
+    pub generation_time_budget: Option<Duration>,
 }
 
 impl EvolveCfg {
@@ -205,18 +717,41 @@ impl EvolveCfg {
         Self {
             pop_size,
             crossover: Crossover::Adaptive,
+            crossover_probability: None,
             mutation: Mutation::Adaptive,
+            params_crossover: ParamsCrossover::Inherit,
             survival: Survival::TopProportion(0.2),
+            survival_fitness: SurvivalFitness::Base,
+            elitism: 0,
             selection: Selection::Sus,
             niching: Niching::None,
             species: Species::None,
             stagnation: Stagnation::None,
             stagnation_condition: StagnationCondition::Default,
+            stagnation_signal: StagnationSignal::TrainBest,
             replacement: Replacement::ReplaceChildren(0.2),
+            replacement_filter: ReplacementFilter::None,
             duplicates: Duplicates::DisallowDuplicates,
             fitness_reduction: FitnessReduction::ArithmeticMean,
+            age_decay: None,
+            constraint_mode: ConstraintMode::None,
+            comparison: Comparison::None,
+            fitness_pipeline: None,
+            warmup: None,
+            local_search: None,
+            share_duplicate_fitness: true,
             par_fitness: false,
             par_dist: false,
+            fitness_chunk_size: None,
+            fitness_seed: None,
+            low_memory: None,
+            approx_stats: None,
+            trace: false,
+            species_snapshots: false,
+            takeover_epsilon: 0.0,
+            takeover_warning: None,
+            validate_distance: cfg!(debug_assertions),
+            generation_time_budget: None,
         }
     }
 
@@ -228,14 +763,30 @@ impl EvolveCfg {
         Self { crossover, ..self }
     }
 
+    pub fn set_crossover_probability(self, crossover_probability: Option<f64>) -> Self {
+        Self { crossover_probability, ..self }
+    }
+
     pub fn set_mutation(self, mutation: Mutation) -> Self {
         Self { mutation, ..self }
     }
 
+    pub fn set_params_crossover(self, params_crossover: ParamsCrossover) -> Self {
+        Self { params_crossover, ..self }
+    }
+
     pub fn set_survival(self, survival: Survival) -> Self {
         Self { survival, ..self }
     }
 
+    pub fn set_survival_fitness(self, survival_fitness: SurvivalFitness) -> Self {
+        Self { survival_fitness, ..self }
+    }
+
+    pub fn set_elitism(self, elitism: usize) -> Self {
+        Self { elitism, ..self }
+    }
+
     pub fn set_selection(self, selection: Selection) -> Self {
         Self { selection, ..self }
     }
@@ -245,6 +796,9 @@ impl EvolveCfg {
     }
 
     pub fn set_species(self, species: Species) -> Self {
+        if let Species::AutoTarget { min, max } = species {
+            assert!(1 <= min && min <= max, "invalid species target range [{min}, {max}]");
+        }
         Self { species, ..self }
     }
 
@@ -256,10 +810,18 @@ impl EvolveCfg {
         Self { stagnation_condition, ..self }
     }
 
+    pub fn set_stagnation_signal(self, stagnation_signal: StagnationSignal) -> Self {
+        Self { stagnation_signal, ..self }
+    }
+
     pub fn set_replacement(self, replacement: Replacement) -> Self {
         Self { replacement, ..self }
     }
 
+    pub fn set_replacement_filter(self, replacement_filter: ReplacementFilter) -> Self {
+        Self { replacement_filter, ..self }
+    }
+
     pub fn set_duplicates(self, duplicates: Duplicates) -> Self {
         Self { duplicates, ..self }
     }
@@ -268,6 +830,34 @@ impl EvolveCfg {
         Self { fitness_reduction, ..self }
     }
 
+    pub fn set_age_decay(self, age_decay: Option<AgeDecay>) -> Self {
+        Self { age_decay, ..self }
+    }
+
+    pub fn set_constraint_mode(self, constraint_mode: ConstraintMode) -> Self {
+        Self { constraint_mode, ..self }
+    }
+
+    pub fn set_comparison(self, comparison: Comparison) -> Self {
+        Self { comparison, ..self }
+    }
+
+    pub fn set_fitness_pipeline(self, fitness_pipeline: Vec<FitnessStage>) -> Self {
+        Self { fitness_pipeline: Some(fitness_pipeline), ..self }
+    }
+
+    pub fn set_warmup(self, warmup: Option<Warmup>) -> Self {
+        Self { warmup, ..self }
+    }
+
+    pub fn set_local_search(self, local_search: Option<LocalSearchCfg>) -> Self {
+        Self { local_search, ..self }
+    }
+
+    pub fn set_share_duplicate_fitness(self, share_duplicate_fitness: bool) -> Self {
+        Self { share_duplicate_fitness, ..self }
+    }
+
     pub fn set_par_fitness(self, par_fitness: bool) -> Self {
         Self { par_fitness, ..self }
     }
@@ -275,4 +865,113 @@ impl EvolveCfg {
     pub fn set_par_dist(self, par_dist: bool) -> Self {
         Self { par_dist, ..self }
     }
+
+    pub fn set_fitness_chunk_size(self, fitness_chunk_size: Option<usize>) -> Self {
+        Self { fitness_chunk_size, ..self }
+    }
+
+    pub fn set_fitness_seed(self, fitness_seed: Option<u64>) -> Self {
+        Self { fitness_seed, ..self }
+    }
+
+    pub fn set_low_memory(self, low_memory: Option<usize>) -> Self {
+        Self { low_memory, ..self }
+    }
+
+    pub fn set_approx_stats(self, approx_stats: Option<usize>) -> Self {
+        Self { approx_stats, ..self }
+    }
+
+    pub fn set_trace(self, trace: bool) -> Self {
+        Self { trace, ..self }
+    }
+
+    pub fn set_species_snapshots(self, species_snapshots: bool) -> Self {
+        Self { species_snapshots, ..self }
+    }
+
+    pub fn set_takeover_epsilon(self, takeover_epsilon: f64) -> Self {
+        Self { takeover_epsilon, ..self }
+    }
+
+    pub fn set_takeover_warning(self, takeover_warning: Option<f64>) -> Self {
+        Self { takeover_warning, ..self }
+    }
+
+    pub fn set_validate_distance(self, validate_distance: bool) -> Self {
+        Self { validate_distance, ..self }
+    }
+
+    pub fn set_generation_time_budget(self, generation_time_budget: Option<Duration>) -> Self {
+        Self { generation_time_budget, ..self }
+    }
+
+    /// Chunk size to use when splitting |n| items across parallel tasks.
+    #[must_use]
+    pub fn par_chunk_size(&self, n: usize) -> usize {
+        // Aim for a few tasks per thread so expensive items don't leave
+        // threads idle, without paying per-item task overhead for cheap ones.
+        const TASKS_PER_THREAD: usize = 4;
+        const MAX_CHUNK_SIZE: usize = 1024;
+        self.fitness_chunk_size
+            .unwrap_or_else(|| (n / (num_threads() * TASKS_PER_THREAD)).clamp(1, MAX_CHUNK_SIZE))
+            .max(1)
+    }
+
+    /// Stages turning fitness into selection fitness: |fitness_pipeline| if
+    /// set, otherwise the stages given by |niching|, |constraint_mode| and
+    /// |age_decay|.
+    #[must_use]
+    pub fn fitness_stages(&self) -> Vec<FitnessStage> {
+        if let Some(pipeline) = &self.fitness_pipeline {
+            return pipeline.clone();
+        }
+        let mut stages = vec![FitnessStage::Niching(self.niching)];
+        if let ConstraintMode::StochasticRanking { .. } = self.constraint_mode {
+            stages.push(FitnessStage::Rank);
+        }
+        if let Some(age_decay) = self.age_decay {
+            stages.push(FitnessStage::AgeDecay(age_decay));
+        }
+        stages
+    }
+
+    /// Config to breed the children of generation |gen_idx| with. While
+    /// |warmup| lasts, this uses its selection, and a crossover probability
+    /// of zero if it disables crossover.
+    #[must_use]
+    pub fn breeding_cfg(&self, gen_idx: usize) -> Cow<'_, Self> {
+        match self.warmup {
+            Some(warmup) if gen_idx < warmup.generations => {
+                let mut cfg = self.clone().set_selection(warmup.selection);
+                if !warmup.crossover_enabled {
+                    cfg.crossover_probability = Some(0.0);
+                }
+                Cow::Owned(cfg)
+            }
+            _ => Cow::Borrowed(self),
+        }
+    }
+
+    /// Whether constraint violations need to be computed, for stochastic
+    /// ranking or a penalty stage.
+    #[must_use]
+    pub fn needs_violation(&self) -> bool {
+        self.constraint_mode != ConstraintMode::None
+            || self.fitness_stages().iter().any(|v| matches!(v, FitnessStage::Penalty(_)))
+    }
+
+    /// The config as TOML, e.g. to save the result of a hyperparameter
+    /// search. Unset options are left out. Fails for values TOML can't hold,
+    /// like a |fitness_seed| above `i64::MAX`.
+    #[cfg(feature = "serde")]
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).wrap_err("writing config as TOML")
+    }
+
+    /// Reads a config written by `to_toml`.
+    #[cfg(feature = "serde")]
+    pub fn from_toml(text: &str) -> Result<Self> {
+        toml::from_str(text).wrap_err("parsing config TOML")
+    }
 }
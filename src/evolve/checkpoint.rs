@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use rand_chacha::ChaCha12Rng;
+
+use crate::eval::State;
+use crate::gen::member::{Member, MemberId};
+use crate::gen::params::Params;
+use crate::gen::species::SpeciesId;
+use crate::gen::unevaluated::Contest;
+
+/// A member of a checkpoint. Unlike `Member`, owns its state.
+#[must_use]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemberCheckpoint<S> {
+    pub state: S,
+    // Ids are only unique within a process, so a restored member gets a new
+    // one. Members sharing an id still share one once restored.
+    pub id: MemberId,
+    pub params: Params,
+    pub species: SpeciesId,
+    pub fitness: f64,
+    pub selection_fitness: f64,
+    pub violation: f64,
+    pub age: usize,
+}
+
+impl<S: State> MemberCheckpoint<S> {
+    pub fn new(mem: &Member<S>) -> Self {
+        Self {
+            state: (*mem.state).clone(),
+            id: mem.id,
+            params: mem.params.clone(),
+            species: mem.species,
+            fitness: mem.fitness,
+            selection_fitness: mem.selection_fitness,
+            violation: mem.violation,
+            age: mem.age,
+        }
+    }
+
+    /// The member this checkpoint was made from, with id |id|.
+    pub fn into_member(self, id: MemberId) -> Member<S> {
+        Member {
+            state: Arc::new(self.state),
+            id,
+            params: self.params,
+            species: self.species,
+            fitness: self.fitness,
+            selection_fitness: self.selection_fitness,
+            violation: self.violation,
+            age: self.age,
+        }
+    }
+}
+
+/// Everything an `Evolver` carries from one generation to the next, from
+/// `Evolver::checkpoint`. Restoring it with `Evolver::restore` continues the
+/// run as if it was never interrupted, provided the evolver is made with the
+/// same evaluator and config. The evaluator, random state generator, archives
+/// and breeding traces aren't included.
+#[must_use]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EvolverCheckpoint<S> {
+    pub gen: usize,                     // Index of the next generation to run.
+    pub mems: Vec<MemberCheckpoint<S>>, // The next generation, not yet evaluated.
+    pub contests: Vec<Contest<MemberCheckpoint<S>>>, // Its contests for places.
+    pub stagnation_count: usize,
+    pub last_fitness: f64,
+    pub species_target: SpeciesId,
+    pub species_history: Vec<SpeciesId>,
+    pub signal_gens: usize,
+    pub external_fitness: Option<f64>,
+    pub fitness_window: VecDeque<f64>,
+    pub warned_no_injection: bool,
+    pub takeover_history: VecDeque<f64>,
+    pub dists_validated: bool,
+    pub rng: ChaCha12Rng,
+}
@@ -1,20 +1,63 @@
+use std::collections::VecDeque;
 use std::fmt::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use ahash::{HashMap, HashSet};
 use approx::{abs_diff_eq, relative_eq};
-use eyre::Result;
+use eyre::{eyre, Report, Result, WrapErr};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+#[cfg(feature = "pretty")]
 use textwrap::indent;
 
-use crate::eval::{Evaluator, State};
-use crate::evolve::cfg::{Crossover, EvolveCfg, Mutation, Stagnation, StagnationCondition};
-use crate::evolve::result::{EvolveResult, Stats};
-use crate::gen::member::Member;
-use crate::gen::unevaluated::UnevaluatedGen;
-use crate::ops::util::rand_vec;
+use crate::eval::{DataEpoch, Evaluator, State};
+use crate::evolve::archive::SharedArchive;
+use crate::evolve::cfg::{
+    Crossover, EvolveCfg, Mutation, OptionalPhase, Species, Stagnation, StagnationCondition,
+    StagnationSignal,
+};
+use crate::evolve::checkpoint::{EvolverCheckpoint, MemberCheckpoint};
+use crate::evolve::map_elites::MapElitesArchive;
+use crate::evolve::result::{ApproxStats, EvolveResult, LengthStats, Stats};
+use crate::gen::member::{next_member_id, Member};
+use crate::gen::params::Params;
+use crate::gen::snapshot::SpeciesSnapshot;
+use crate::gen::species::{auto_species_target, SpeciesId, NO_SPECIES};
+use crate::gen::unevaluated::{Contest, UnevaluatedGen};
+use crate::util::fmt::fmt_fitness;
 
 pub trait CreateEvolverFn<E: Evaluator> =
     Fn(EvolveCfg) -> Evolver<E> + Sync + Send + Clone + 'static;
 pub trait RandState<S: State> = FnMut() -> S + Send;
 
+/// Attempts a `RandState` generator gets to produce a state which passes
+/// `Evaluator::validate_state`.
+pub const RAND_STATE_ATTEMPTS: usize = 100;
+
+/// State from |genfn| which passes `Evaluator::validate_state`, trying up to
+/// `RAND_STATE_ATTEMPTS` times. |source| says what the state is for, e.g.
+/// "initial", for the error if none pass.
+pub(crate) fn rand_valid_state<E: Evaluator>(
+    eval: &E,
+    genfn: &mut (dyn RandState<E::State> + '_),
+    source: &str,
+) -> Result<E::State> {
+    let mut attempts = 1;
+    loop {
+        let s = genfn();
+        match eval.validate_state(&s) {
+            Ok(()) => return Ok(s),
+            Err(e) if attempts >= RAND_STATE_ATTEMPTS => {
+                return Err(e.wrap_err(format!(
+                    "random state generator made no valid {source} state in {attempts} attempts"
+                )));
+            }
+            Err(_) => attempts += 1,
+        }
+    }
+}
+
 /// Runs iterations of GA w.r.t. the given evaluator.
 #[must_use]
 pub struct Evolver<E: Evaluator> {
@@ -25,6 +68,34 @@ pub struct Evolver<E: Evaluator> {
     gen_count: usize,
     stagnation_count: usize,
     last_fitness: f64,
+    species_target: SpeciesId,
+    species_history: Vec<SpeciesId>,
+    // Generations since the stagnation signal was last updated.
+    signal_gens: usize,
+    // Externally reported fitness not yet used for stagnation.
+    external_fitness: Option<f64>,
+    // Recent best fitnesses for `StagnationSignal::TrainBestSmoothed`.
+    fitness_window: VecDeque<f64>,
+    // Whether we have warned about stagnation not injecting anything.
+    warned_no_injection: bool,
+    // Recent takeover fractions, oldest first.
+    takeover_history: VecDeque<f64>,
+    // Archive the best member of each generation is added to.
+    archive: Option<SharedArchive<E::State>>,
+    // Quality-diversity archive every evaluated member is offered to.
+    map_elites: Option<MapElitesArchive<E::State>>,
+    // How long creating the next generation took last time, reserved out of
+    // the generation time budget.
+    reproduction_time: Duration,
+    // Whether a generation has computed distances, so the metric was checked
+    // if `EvolveCfg::validate_distance` is set.
+    dists_validated: bool,
+    // Why the initial population couldn't be filled, returned by the next run.
+    init_error: Option<Report>,
+    // Source of every random choice made by the evolver itself, seeded by
+    // `new_seeded`. The generator `StdRng` currently uses, named so its
+    // position can be checkpointed.
+    rng: ChaCha12Rng,
 }
 
 /// Default runner for no data.
@@ -38,16 +109,89 @@ impl<E: Evaluator> Evolver<E> {
     pub fn from_initial(
         eval: E,
         cfg: EvolveCfg,
-        mut gen: Vec<E::State>,
+        gen: Vec<E::State>,
+        rand_state: impl RandState<E::State> + 'static,
+    ) -> Self {
+        Self::from_initial_rng(eval, cfg, gen, rand_state, ChaCha12Rng::from_entropy())
+    }
+
+    fn from_initial_rng(
+        eval: E,
+        cfg: EvolveCfg,
+        gen: Vec<E::State>,
+        rand_state: impl RandState<E::State> + 'static,
+        mut rng: ChaCha12Rng,
+    ) -> Self {
+        let gen = gen.into_iter().map(|s| Member::new_rng::<E, _>(s, &cfg, &mut rng)).collect();
+        Self::from_members(eval, cfg, gen, rand_state, rng)
+    }
+
+    /// Like `from_initial`, but each initial state can come with the `Params`
+    /// it starts with, e.g. to favour operators known to help seeded states.
+    /// States without params get random ones as usual. Fails if any params
+    /// don't match the evaluator's operators.
+    pub fn from_initial_with_params(
+        eval: E,
+        cfg: EvolveCfg,
+        gen: Vec<(E::State, Option<Params>)>,
+        rand_state: impl RandState<E::State> + 'static,
+    ) -> Result<Self> {
+        let mut rng = ChaCha12Rng::from_entropy();
+        let mems = Self::members_with_params(gen, &cfg, &mut rng)?;
+        Ok(Self::from_members(eval, cfg, mems, rand_state, rng))
+    }
+
+    // Members for |gen|, with random params where none are given.
+    fn members_with_params(
+        gen: Vec<(E::State, Option<Params>)>,
+        cfg: &EvolveCfg,
+        rng: &mut ChaCha12Rng,
+    ) -> Result<Vec<Member<E::State>>> {
+        let mut mems = Vec::with_capacity(gen.len());
+        for (i, (s, params)) in gen.into_iter().enumerate() {
+            let mut mem = Member::new_rng::<E, _>(s, cfg, rng);
+            if let Some(params) = params {
+                params.validate::<E>().wrap_err_with(|| format!("params of initial state {i}"))?;
+                mem.params = params;
+            }
+            mems.push(mem);
+        }
+        Ok(mems)
+    }
+
+    fn from_members(
+        eval: E,
+        cfg: EvolveCfg,
+        mut gen: Vec<Member<E::State>>,
         mut rand_state: impl RandState<E::State> + 'static,
+        mut rng: ChaCha12Rng,
     ) -> Self {
         // Fill out the rest of |gen| if it's smaller than pop_size.
         // If speciation is on, this lets more random species be generated at
         // the beginning.
+        let mut init_error = None;
         while gen.len() < cfg.pop_size {
-            gen.push(rand_state());
+            match rand_valid_state(&eval, &mut rand_state, "initial") {
+                Ok(s) => gen.push(Member::new_rng::<E, _>(s, &cfg, &mut rng)),
+                Err(e) => {
+                    init_error = Some(e);
+                    break;
+                }
+            }
         }
-        let gen = UnevaluatedGen::initial::<E>(gen, &cfg);
+        // A generation can't be empty, so without any members the evolver
+        // only returns errors.
+        let gen = if gen.is_empty() {
+            let _ = init_error.get_or_insert_with(|| eyre!("no members in the initial population"));
+            UnevaluatedGen::empty()
+        } else {
+            UnevaluatedGen::new(gen)
+        };
+        let species_target = match cfg.species {
+            Species::None => NO_SPECIES,
+            Species::TargetNumber(target) | Species::TargetNumberBounded { target, .. } => target,
+            Species::AutoTarget { min, max } => min + (max - min) / 2,
+        };
         Self {
             cfg,
             eval,
@@ -56,44 +200,151 @@ impl<E: Evaluator> Evolver<E> {
             gen_count: 0,
             stagnation_count: 0,
             last_fitness: 0.0,
+            species_target,
+            species_history: Vec::new(),
+            signal_gens: 0,
+            external_fitness: None,
+            fitness_window: VecDeque::new(),
+            warned_no_injection: false,
+            takeover_history: VecDeque::new(),
+            archive: None,
+            map_elites: None,
+            reproduction_time: Duration::ZERO,
+            dists_validated: false,
+            init_error,
+            rng,
         }
     }
 
-    pub fn new(
+    pub fn new(eval: E, cfg: EvolveCfg, rand_state: impl RandState<E::State> + 'static) -> Self {
+        Self::from_initial(eval, cfg, Vec::new(), rand_state)
+    }
+
+    /// Like `new`, but every random choice the evolver makes comes from an
+    /// rng seeded with |seed|. Two evolvers made with the same seed produce
+    /// the same populations if |rand_state| is deterministic, the evaluator
+    /// draws from the rng it is given, see `Evaluator::crossover_rng`, and
+    /// fitness doesn't depend on evaluation order. Set
+    /// `EvolveCfg::fitness_seed` too if fitness is random.
+    pub fn new_seeded(
         eval: E,
         cfg: EvolveCfg,
-        mut rand_state: impl RandState<E::State> + 'static,
+        rand_state: impl RandState<E::State> + 'static,
+        seed: u64,
     ) -> Self {
-        #[allow(clippy::redundant_closure)] // This closure is actually necessary.
-        let gen = UnevaluatedGen::initial::<E>(rand_vec(cfg.pop_size, || rand_state()), &cfg);
-        Self {
-            eval,
-            cfg,
-            gen,
-            rand_state: Box::new(rand_state),
-            gen_count: 0,
-            stagnation_count: 0,
-            last_fitness: 0.0,
+        Self::from_initial_rng(eval, cfg, Vec::new(), rand_state, ChaCha12Rng::seed_from_u64(seed))
+    }
+
+    /// Replaces |fraction| of the initial population with states from the
+    /// given function, which is passed the number of states wanted. This lets runs
+    /// start from domain specific heuristic solutions; the rest of the
+    /// population stays random to keep diversity.
+    pub fn set_seed_fraction(
+        mut self,
+        fraction: f64,
+        seed_fn: impl FnOnce(usize) -> Vec<E::State>,
+    ) -> Self {
+        assert!((0.0..=1.0).contains(&fraction), "seed fraction must be in [0, 1]");
+        assert!(self.gen_count == 0, "can only seed the initial population");
+        let count = (fraction * self.gen.mems.len() as f64).round() as usize;
+        for (mem, state) in self.gen.mems.iter_mut().zip(seed_fn(count).into_iter().take(count)) {
+            *mem = Member::new_rng::<E, _>(state, &self.cfg, &mut self.rng);
         }
+        self
+    }
+
+    /// Adds the best member of each generation to |archive|, which can be
+    /// shared with other evolvers.
+    pub fn set_archive(mut self, archive: SharedArchive<E::State>) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    /// Offers every evaluated member to |map_elites| each generation. Members
+    /// are placed by `Evaluator::descriptor`, which the evaluator must
+    /// implement with as many dimensions as the archive's grid.
+    pub fn set_map_elites(mut self, map_elites: MapElitesArchive<E::State>) -> Self {
+        self.map_elites = Some(map_elites);
+        self
+    }
+
+    /// Like `run_data`, but first tells the evaluator which data epoch
+    /// |inputs| belong to. Caching layers treat data from different epochs as
+    /// different even if it compares equal, so callers control when data is
+    /// considered changed. See `Evaluator::set_data_epoch`.
+    pub fn run_data_epoch(
+        &mut self,
+        inputs: &[E::Data],
+        epoch: DataEpoch,
+    ) -> Result<EvolveResult<E::State>> {
+        self.eval.set_data_epoch(epoch);
+        self.run_data(inputs)
+    }
+
+    /// Like `run_data`, but records |batch_id| as the result's
+    /// `data_fingerprint`, e.g. from `DataSampler::batch_id`, if the evaluator
+    /// doesn't fingerprint |inputs| itself with `Evaluator::data_fingerprint`.
+    pub fn run_data_batch(
+        &mut self,
+        inputs: &[E::Data],
+        batch_id: u64,
+    ) -> Result<EvolveResult<E::State>> {
+        let mut r = self.run_data(inputs)?;
+        r.data_fingerprint = r.data_fingerprint.or(Some(batch_id));
+        Ok(r)
     }
 
     pub fn run_data(&mut self, inputs: &[E::Data]) -> Result<EvolveResult<E::State>> {
-        let gen = self.gen.evaluate(inputs, &self.cfg, &self.eval)?;
-        let stagnant = match self.cfg.stagnation_condition {
-            StagnationCondition::Default => {
-                relative_eq!(gen.mems[0].fitness, self.last_fitness)
-            }
-            StagnationCondition::Epsilon(ep) => {
-                abs_diff_eq!(gen.mems[0].fitness, self.last_fitness, epsilon = ep)
+        if let Some(e) = self.init_error.take() {
+            return Err(e);
+        }
+        if self.gen.mems.is_empty() && self.gen.contests.is_empty() {
+            return Err(eyre!("evolver has no members, the initial population couldn't be made"));
+        }
+        self.gen.species_target = self.species_target;
+        self.gen.gen_idx = self.gen_count;
+        self.gen.validate_dists = self.cfg.validate_distance && !self.dists_validated;
+        let start = Instant::now();
+        self.gen.deadline = self
+            .cfg
+            .generation_time_budget
+            .map(|budget| start + budget.saturating_sub(self.reproduction_time));
+        let mut gen = self.gen.evaluate_rng(inputs, &self.cfg, &self.eval, &mut self.rng)?;
+        self.dists_validated |= !self.gen.dists.is_empty();
+        let approx_stats = match self.cfg.approx_stats {
+            Some(size) => {
+                Some(ApproxStats::sample_rng(&gen.mems, size, &self.eval, &mut self.rng)?)
             }
+            None => None,
         };
+        if matches!(self.gen.deadline, Some(d) if Instant::now() >= d) {
+            self.gen.skipped |= OptionalPhase::Reporting;
+        }
+        if self.cfg.species != Species::None {
+            self.species_history.push(self.gen.species.num);
+        }
+        if let Species::AutoTarget { min, max } = self.cfg.species {
+            self.species_target = auto_species_target(&self.gen.mems, &self.gen.species, min, max);
+        }
+        let species_snapshot = (self.cfg.species_snapshots
+            && self.cfg.species != Species::None
+            && !self.gen.skipped.contains(OptionalPhase::Reporting))
+        .then(|| SpeciesSnapshot::new(self.gen_count, &self.gen.species, &gen.mems));
         self.gen_count += 1;
-        if stagnant {
-            self.stagnation_count += 1;
-        } else {
-            self.stagnation_count = 0;
+        if let Some(archive) = &self.archive {
+            let _ = archive.insert(&gen.mems[0]);
         }
-        self.last_fitness = gen.mems[0].fitness;
+        let map_elites = match &mut self.map_elites {
+            Some(map_elites) => {
+                map_elites.update(&self.eval, &gen.mems, inputs)?;
+                Some(map_elites.stats())
+            }
+            None => None,
+        };
+        let lengths = LengthStats::new(&gen.mems, &self.eval);
+        self.update_stagnation_count(gen.mems[0].fitness);
+        let takeover_fraction = self.gen.takeover_fraction(self.cfg.takeover_epsilon);
+        let takeover_trend = self.update_takeover(takeover_fraction);
 
         let stagnant = match self.cfg.stagnation {
             Stagnation::None => false,
@@ -108,9 +359,211 @@ impl<E: Evaluator> Evolver<E> {
             Stagnation::ContinuousAfter(count) => self.stagnation_count >= count,
         };
 
-        let mut next = gen.next_gen(self.rand_state.as_mut(), stagnant, &self.cfg, &self.eval)?;
+        let reproduction_start = Instant::now();
+        let gen_idx = self.gen.gen_idx;
+        let warmup = self.cfg.warmup.is_some_and(|w| gen_idx < w.generations);
+        if self.cfg.warmup.is_some_and(|w| w.generations > 0 && gen_idx == w.generations) {
+            log::info!("warm-up over after {gen_idx} generations, breeding with the main config");
+        }
+        let breeding_cfg = self.cfg.breeding_cfg(gen_idx);
+        let rand_state = self.rand_state.as_mut();
+        let mut next = gen.next_gen_rng(
+            rand_state,
+            stagnant,
+            gen_idx,
+            inputs,
+            &breeding_cfg,
+            &self.eval,
+            &mut self.rng,
+        )?;
+        self.reproduction_time = reproduction_start.elapsed();
+        let (injected, hybrids, dups_removed) = (next.injected, next.hybrids, next.dups_removed);
+        let (filtered, unqualified) = (self.gen.filtered, self.gen.unqualified);
+        let fitness_evals = self.gen.fitness_evals
+            + self.gen.filter_evals
+            + self.gen.trial_evals
+            + next.local_search_evals;
+        let local_searched = next.local_searched;
+        if stagnant && injected + hybrids == 0 && !self.warned_no_injection {
+            log::warn!(
+                "stagnation injected no individuals, survivors fill the population: {:?}",
+                self.cfg.replacement
+            );
+            self.warned_no_injection = true;
+        }
+        if let Some(limit) = self.cfg.low_memory &&
+                gen.mems.iter().map(|v| self.eval.state_bytes(&v.state)).sum::<usize>() > limit {
+            // Drop references to members that didn't survive so they can be
+            // freed before the next generation is evaluated. Always keep the
+            // best member.
+            let mut keep = next.mems.iter().map(|v| Arc::as_ptr(&v.state)).collect::<HashSet<_>>();
+            keep.insert(Arc::as_ptr(&gen.mems[0].state));
+            gen.mems.retain(|v| keep.contains(&Arc::as_ptr(&v.state)));
+            self.gen.mems.retain(|v| keep.contains(&Arc::as_ptr(&v.state)));
+        }
         std::mem::swap(&mut next, &mut self.gen);
-        Ok(EvolveResult { unevaluated: next, gen, stagnant })
+        Ok(EvolveResult {
+            unevaluated: next,
+            gen,
+            stagnant,
+            injected,
+            filtered,
+            unqualified,
+            hybrids,
+            dups_removed,
+            fitness_evals,
+            local_searched,
+            data_len: inputs.len(),
+            data_fingerprint: self.eval.data_fingerprint(inputs),
+            takeover_fraction,
+            takeover_trend,
+            warmup,
+            test_fitness: None,
+            valid_fitness: None,
+            cv_best: None,
+            dropped_metrics: 0,
+            termination: None,
+            throughput: None,
+            species_snapshot,
+            approx_stats,
+            map_elites,
+            lengths,
+        })
+    }
+
+    /// Reports a fitness computed outside of the evolver, e.g. validation
+    /// fitness. Used for `StagnationSignal::ValidationBest` in the next
+    /// generation.
+    pub fn report_external_fitness(&mut self, fitness: f64) {
+        self.external_fitness = Some(fitness);
+    }
+
+    fn update_stagnation_count(&mut self, best_fitness: f64) {
+        self.signal_gens += 1;
+        let signal = match self.cfg.stagnation_signal {
+            StagnationSignal::TrainBest => Some(best_fitness),
+            StagnationSignal::ValidationBest { .. } => self.external_fitness.take(),
+            StagnationSignal::TrainBestSmoothed { window } => {
+                self.fitness_window.push_back(best_fitness);
+                while self.fitness_window.len() > window.max(1) {
+                    self.fitness_window.pop_front();
+                }
+                Some(self.fitness_window.iter().sum::<f64>() / self.fitness_window.len() as f64)
+            }
+        };
+        // Keep the current stagnation count until there is a new signal.
+        let Some(signal) = signal else { return };
+        let stagnant = match self.cfg.stagnation_condition {
+            StagnationCondition::Default => relative_eq!(signal, self.last_fitness),
+            StagnationCondition::Epsilon(ep) => {
+                abs_diff_eq!(signal, self.last_fitness, epsilon = ep)
+            }
+        };
+        if stagnant {
+            self.stagnation_count += self.signal_gens;
+        } else {
+            self.stagnation_count = 0;
+        }
+        self.last_fitness = signal;
+        self.signal_gens = 0;
+    }
+
+    // Records the takeover fraction of the latest generation. Returns the
+    // number of generations it has been growing for, up to the history length.
+    fn update_takeover(&mut self, fraction: f64) -> usize {
+        const HISTORY: usize = 16;
+        if let Some(threshold) = self.cfg.takeover_warning && fraction > threshold &&
+                self.takeover_history.back().copied().unwrap_or(f64::NEG_INFINITY) <= threshold {
+            log::warn!(
+                "possible premature convergence: {:.1}% of the population are copies of the best",
+                fraction * 100.0
+            );
+        }
+        self.takeover_history.push_back(fraction);
+        while self.takeover_history.len() > HISTORY {
+            self.takeover_history.pop_front();
+        }
+        let recent = self.takeover_history.iter().rev();
+        recent.clone().zip(recent.skip(1)).take_while(|(cur, prev)| cur > prev).count()
+    }
+
+    /// Index of the next generation to be evaluated, which is the number of
+    /// generations run so far unless it was changed with `set_generation`.
+    #[must_use]
+    pub fn generation(&self) -> usize {
+        self.gen_count
+    }
+
+    /// Sets the index of the next generation, e.g. when restoring from a
+    /// checkpoint, so generation numbers continue from where they left off.
+    pub fn set_generation(&mut self, generation: usize) {
+        self.gen_count = generation;
+    }
+
+    /// The population to evaluate next and everything else carried between
+    /// generations, to continue the run later with `restore`.
+    pub fn checkpoint(&self) -> EvolverCheckpoint<E::State> {
+        let contest = |v: &Contest<Member<E::State>>| Contest {
+            kind: v.kind,
+            mems: v.mems.iter().map(MemberCheckpoint::new).collect(),
+        };
+        EvolverCheckpoint {
+            gen: self.gen_count,
+            mems: self.gen.mems.iter().map(MemberCheckpoint::new).collect(),
+            contests: self.gen.contests.iter().map(contest).collect(),
+            stagnation_count: self.stagnation_count,
+            last_fitness: self.last_fitness,
+            species_target: self.species_target,
+            species_history: self.species_history.clone(),
+            signal_gens: self.signal_gens,
+            external_fitness: self.external_fitness,
+            fitness_window: self.fitness_window.clone(),
+            warned_no_injection: self.warned_no_injection,
+            takeover_history: self.takeover_history.clone(),
+            dists_validated: self.dists_validated,
+            rng: self.rng.clone(),
+        }
+    }
+
+    /// Continues the run |checkpoint| was made from, replacing the population
+    /// and generation count. The evolver must have the same evaluator and
+    /// config as the one checkpointed. Fails if the checkpoint has no members
+    /// or any params don't match the evaluator's operators.
+    pub fn restore(mut self, checkpoint: EvolverCheckpoint<E::State>) -> Result<Self> {
+        if checkpoint.mems.is_empty() && checkpoint.contests.is_empty() {
+            return Err(eyre!("checkpoint has no members"));
+        }
+        let mut ids = HashMap::default();
+        let mut restore = |mems: Vec<MemberCheckpoint<E::State>>| -> Result<Vec<_>> {
+            let mut restored = Vec::with_capacity(mems.len());
+            for (i, mem) in mems.into_iter().enumerate() {
+                mem.params.validate::<E>().wrap_err_with(|| format!("params of member {i}"))?;
+                let id = *ids.entry(mem.id).or_insert_with(next_member_id);
+                restored.push(mem.into_member(id));
+            }
+            Ok(restored)
+        };
+        let mems = restore(checkpoint.mems)?;
+        let mut contests = Vec::with_capacity(checkpoint.contests.len());
+        for v in checkpoint.contests {
+            contests.push(Contest { kind: v.kind, mems: restore(v.mems)? });
+        }
+        self.gen = UnevaluatedGen { mems, contests, ..UnevaluatedGen::empty() };
+        self.gen_count = checkpoint.gen;
+        self.stagnation_count = checkpoint.stagnation_count;
+        self.last_fitness = checkpoint.last_fitness;
+        self.species_target = checkpoint.species_target;
+        self.species_history = checkpoint.species_history;
+        self.signal_gens = checkpoint.signal_gens;
+        self.external_fitness = checkpoint.external_fitness;
+        self.fitness_window = checkpoint.fitness_window;
+        self.warned_no_injection = checkpoint.warned_no_injection;
+        self.takeover_history = checkpoint.takeover_history;
+        self.dists_validated = checkpoint.dists_validated;
+        self.rng = checkpoint.rng;
+        // The checkpointed population replaces one which couldn't be filled.
+        self.init_error = None;
+        Ok(self)
     }
 
     pub fn cfg(&self) -> &EvolveCfg {
@@ -121,23 +574,68 @@ impl<E: Evaluator> Evolver<E> {
         &self.eval
     }
 
-    pub fn summary(&self, r: &mut EvolveResult<E::State>) -> String {
+    pub fn map_elites(&self) -> Option<&MapElitesArchive<E::State>> {
+        self.map_elites.as_ref()
+    }
+
+    pub fn archive(&self) -> Option<&SharedArchive<E::State>> {
+        self.archive.as_ref()
+    }
+
+    /// Takeover fractions of recent generations, oldest first.
+    #[must_use]
+    pub fn takeover_history(&self) -> &VecDeque<f64> {
+        &self.takeover_history
+    }
+
+    /// Number of species found in each generation so far, if speciation is on.
+    #[must_use]
+    pub fn species_history(&self) -> &[SpeciesId] {
+        &self.species_history
+    }
+
+    pub fn summary(&self, r: &EvolveResult<E::State>) -> String {
         let mut s = String::new();
         let _ = writeln!(s, "{}", Stats::from_result(r));
-        if self.cfg.mutation == Mutation::Adaptive {
+        if self.cfg.mutation == Mutation::Adaptive && E::NUM_MUTATION > 0 {
             s += "mutation:  ";
             for &v in &r.nth(0).params.mutation {
                 let _ = write!(s, "{v:5.5}, ");
             }
             s += "\n";
         }
-        if self.cfg.crossover == Crossover::Adaptive {
+        let has_noop = E::CROSSOVER_HAS_NOOP;
+        if self.cfg.crossover == Crossover::Adaptive && E::NUM_CROSSOVER > usize::from(has_noop) {
+            let weights = &r.nth(0).params.crossover;
             s += "crossover: ";
-            for &v in &r.nth(0).params.crossover {
-                let _ = write!(s, "{v:5.5}, ");
+            for (idx, &v) in weights.iter().enumerate() {
+                let _ = write!(s, "{} {v:5.5}, ", Params::crossover_label(idx, has_noop));
+            }
+            // Compare the real operators without the weight of skipping.
+            s += "\ncrossover share: ";
+            for (idx, v) in Params::crossover_shares(weights, has_noop) {
+                let _ = write!(s, "{idx} {:.1}%, ", v * 100.0);
+            }
+            s += "\n";
+        }
+        // Only worth breaking down if more than one operator was used.
+        if let Some(improvement) = &r.unevaluated.improvement &&
+                improvement.by_crossover.len() > 1 {
+            s += "improved by crossover: ";
+            for (&idx, count) in &improvement.by_crossover {
+                let label =
+                    idx.map_or_else(|| "none".to_owned(), |v| Params::crossover_label(v, has_noop));
+                let rate = count.rate().unwrap_or(0.0) * 100.0;
+                let _ = write!(s, "{label} {rate:.1}% of {}, ", count.children);
             }
             s += "\n";
         }
+        if let Some(improvement) = &r.unevaluated.improvement &&
+                improvement.mutation_only.children > 0 {
+            let count = improvement.mutation_only;
+            let rate = count.rate().unwrap_or(0.0) * 100.0;
+            let _ = writeln!(s, "improved by mutation only: {rate:.1}% of {}", count.children);
+        }
         s
     }
 
@@ -145,8 +643,30 @@ impl<E: Evaluator> Evolver<E> {
     // top n / # species for each species. If n isn't divisble by number of
     // species, the remainder will go to print the top n % # out of the #
     // species.
+    pub fn summary_sample(&self, r: &EvolveResult<E::State>, n: usize) -> String {
+        self.summary_sample_with(r, n, ToString::to_string)
+    }
+
+    #[deprecated(note = "summaries only read the result, use `summary`")]
+    pub fn summary_mut(&self, r: &mut EvolveResult<E::State>) -> String {
+        self.summary(r)
+    }
+
+    #[deprecated(note = "summaries only read the result, use `summary_sample`")]
+    pub fn summary_sample_mut(&self, r: &mut EvolveResult<E::State>, n: usize) -> String {
+        self.summary_sample(r, n)
+    }
+
+    /// Like `summary_sample`, but formats each state with |f|. Formatting
+    /// states can be expensive, so |f| can return an empty string to only
+    /// print fitnesses.
     #[allow(clippy::unused_self)]
-    pub fn summary_sample(&self, r: &mut EvolveResult<E::State>, n: usize) -> String {
+    pub fn summary_sample_with(
+        &self,
+        r: &EvolveResult<E::State>,
+        n: usize,
+        f: impl Fn(&E::State) -> String,
+    ) -> String {
         let mut s = String::new();
         let species = r.gen.species();
         let mut by_species: Vec<(usize, Vec<Member<E::State>>)> = Vec::new();
@@ -188,8 +708,11 @@ impl<E: Evaluator> Evolver<E> {
             if *count > 0 {
                 let _ = writeln!(s, "Species {} top {count}:", mems[0].species);
                 for mem in mems.iter().take(*count) {
-                    let state_str = indent(&format!("{}", mem.state), "  ");
-                    let _ = writeln!(s, "fitness: {:5.5}\n{state_str}", mem.fitness);
+                    let _ = writeln!(s, "fitness: {}", fmt_fitness(mem.fitness));
+                    let state_str = f(&mem.state);
+                    if !state_str.is_empty() {
+                        let _ = writeln!(s, "{}", indent(&state_str, "  "));
+                    }
                 }
                 s += "\n";
             }
@@ -198,3 +721,645 @@ impl<E: Evaluator> Evolver<E> {
         s
     }
 }
+
+// Same as `textwrap::indent`, for builds without the `pretty` feature:
+// prefixes each line that isn't only whitespace.
+#[cfg(not(feature = "pretty"))]
+fn indent(s: &str, prefix: &str) -> String {
+    s.split_inclusive('\n')
+        .map(|line| if line.trim().is_empty() { line.to_owned() } else { prefix.to_owned() + line })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::sync::Mutex;
+
+    use pretty_assertions::assert_eq;
+    use rand::Rng;
+
+    use super::*;
+    use crate::evolve::cfg::{
+        Comparison, Duplicates, Niching, RankPressure, Replacement, ReplacementFilter, Selection,
+        Survival, Warmup,
+    };
+    use crate::gen::species::MetricError;
+    use crate::train::sampler::data_fingerprint;
+    use crate::util::bench_utils::CountEvaluator;
+    use crate::util::test_utils::MockEvaluator;
+
+    // Best fitness of each generation is whatever data is passed in.
+    type ScriptedEvaluator = MockEvaluator<f64, f64>;
+
+    fn scripted() -> ScriptedEvaluator {
+        MockEvaluator::with(|_, data| Ok(*data), |_, _| Ok(0.0))
+    }
+
+    fn scripted_evolver(signal: StagnationSignal) -> Evolver<ScriptedEvaluator> {
+        let cfg = EvolveCfg::new(4)
+            .set_duplicates(Duplicates::AllowDuplicates)
+            .set_stagnation(Stagnation::ContinuousAfter(3))
+            .set_stagnation_signal(signal);
+        Evolver::new(scripted(), cfg, || 0.0)
+    }
+
+    // Returns the stagnation count and whether stagnation was triggered for
+    // each generation.
+    fn run(
+        evolver: &mut Evolver<ScriptedEvaluator>,
+        train: &[f64],
+        valid: &[Option<f64>],
+    ) -> Result<(Vec<usize>, Vec<bool>)> {
+        let mut counts = Vec::new();
+        let mut stagnant = Vec::new();
+        for (i, &fitness) in train.iter().enumerate() {
+            stagnant.push(evolver.run_data(&[fitness])?.stagnant);
+            counts.push(evolver.stagnation_count);
+            if let Some(&Some(v)) = valid.get(i) {
+                evolver.report_external_fitness(v);
+            }
+        }
+        Ok((counts, stagnant))
+    }
+
+    #[test]
+    fn stagnation_train_best() -> Result<()> {
+        let mut evolver = scripted_evolver(StagnationSignal::TrainBest);
+        let (counts, stagnant) = run(&mut evolver, &[1.0, 1.0, 1.0, 1.0, 1.0, 2.0], &[])?;
+        assert_eq!(counts, [0, 1, 2, 3, 4, 0]);
+        assert_eq!(stagnant, [false, false, false, true, true, false]);
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn improvement_rate() -> Result<()> {
+        // Children are as fit as their parents, so never improve.
+        let cfg = EvolveCfg::new(4).set_duplicates(Duplicates::AllowDuplicates).set_trace(true);
+        let mut evolver = Evolver::new(scripted(), cfg, || 0.0);
+        let r = evolver.run_data(&[1.0])?;
+        // The initial generation wasn't bred.
+        assert_eq!(Stats::from_result(&r).improvement_rate, None);
+        let r = evolver.run_data(&[1.0])?;
+        assert_eq!(Stats::from_result(&r).improvement_rate, Some(0.0));
+
+        let mut evolver = scripted_evolver(StagnationSignal::TrainBest);
+        for _ in 0..2 {
+            let r = evolver.run_data(&[1.0])?;
+            assert_eq!(Stats::from_result(&r).improvement_rate, None);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn stagnation_train_best_smoothed() -> Result<()> {
+        let train = [1.0, 3.0, 1.0, 3.0, 1.0, 3.0];
+        let mut evolver = scripted_evolver(StagnationSignal::TrainBest);
+        let (counts, _) = run(&mut evolver, &train, &[])?;
+        assert_eq!(counts, [0; 6]);
+
+        // Moving average over two generations is flat after the first two.
+        let mut evolver = scripted_evolver(StagnationSignal::TrainBestSmoothed { window: 2 });
+        let (counts, stagnant) = run(&mut evolver, &train, &[])?;
+        assert_eq!(counts, [0, 0, 1, 2, 3, 4]);
+        assert_eq!(stagnant, [false, false, false, false, true, true]);
+        Ok(())
+    }
+
+    #[test]
+    fn stagnation_validation_best() -> Result<()> {
+        // Training fitness alternates, but validation is flat.
+        let train = [1.0, 3.0, 1.0, 3.0, 1.0, 3.0, 1.0, 3.0];
+        let valid = [None, Some(5.0), None, Some(5.0), None, Some(5.0), None, Some(5.0)];
+        let mut evolver = scripted_evolver(StagnationSignal::ValidationBest { every: 2 });
+        let (counts, stagnant) = run(&mut evolver, &train, &valid)?;
+        assert_eq!(counts, [0, 0, 0, 0, 2, 2, 4, 4]);
+        assert_eq!(stagnant, [false, false, false, false, false, false, true, true]);
+        Ok(())
+    }
+
+    fn injected(replacement: Replacement) -> Result<(usize, usize)> {
+        let cfg = EvolveCfg::new(10)
+            .set_duplicates(Duplicates::AllowDuplicates)
+            .set_survival(Survival::TopProportion(0.95))
+            .set_stagnation(Stagnation::ContinuousAfter(0))
+            .set_replacement(replacement);
+        let mut evolver = Evolver::new(scripted(), cfg, || 0.0);
+        let r = evolver.run_data(&[1.0])?;
+        let stats = Stats::from_result(&r);
+        assert!(stats.stagnant);
+        Ok((stats.injected, evolver.gen.mems.len()))
+    }
+
+    #[test]
+    fn stagnation_replacement_injected() -> Result<()> {
+        // Survivors fill the whole population so there are no children to replace.
+        assert_eq!(injected(Replacement::ReplaceChildren(0.5))?, (0, 10));
+        assert_eq!(injected(Replacement::ReplaceWorst(0.5))?, (5, 10));
+        Ok(())
+    }
+
+    // Fitness is the state itself.
+    fn identity() -> MockEvaluator<f64> {
+        MockEvaluator::with(|s, ()| Ok(*s), |_, _| Ok(0.0))
+            .set_compare(|a, b, ()| Ok(a.total_cmp(b)))
+    }
+
+    // Runs a stagnant generation of [1, 2, 3, 4] replacing the worst half,
+    // with random individuals taken in order from |candidates|, then 0. Runs
+    // the next generation too, which settles any contests for the replaced
+    // places, and returns its stats and states.
+    fn filtered(filter: ReplacementFilter, candidates: Vec<f64>) -> Result<(Stats, Vec<f64>)> {
+        let cfg = EvolveCfg::new(4)
+            .set_duplicates(Duplicates::AllowDuplicates)
+            .set_survival(Survival::TopProportion(1.0))
+            .set_stagnation(Stagnation::ContinuousAfter(0))
+            .set_replacement(Replacement::ReplaceWorst(0.5))
+            .set_replacement_filter(filter);
+        let mut candidates = candidates.into_iter();
+        let rand_state = move || candidates.next().unwrap_or(0.0);
+        let initial = vec![1.0, 2.0, 3.0, 4.0];
+        let mut evolver = Evolver::from_initial(identity(), cfg, initial, rand_state);
+        let _ = evolver.run()?;
+        let r = evolver.run()?;
+        let stats = Stats::from_result(&r);
+        let mut states = r.gen.mems.iter().map(|v| *v.state).collect::<Vec<_>>();
+        states.sort_by(f64::total_cmp);
+        Ok((stats, states))
+    }
+
+    #[test]
+    fn replacement_filter() -> Result<()> {
+        // Survivors are 3 and 4, so candidates must beat 3.
+        let filter = ReplacementFilter::BeatWorst { attempts: 3 };
+        let (stats, states) = filtered(filter, vec![1.0, 5.0, 0.0, 2.0, 3.0])?;
+        // The first slot rejects 1 for 5. No candidate for the second beats 3,
+        // so the best of them, 3, is injected anyway.
+        assert_eq!(states, [3.0, 3.0, 4.0, 5.0]);
+        assert_eq!((stats.injected, stats.filtered, stats.unqualified), (2, 3, 1));
+        // The survivors, then every candidate including rejected ones. The
+        // injected candidates aren't evaluated again.
+        assert_eq!(stats.fitness_evals, 2 + 5);
+
+        let (stats, states) = filtered(ReplacementFilter::None, vec![1.0, 5.0])?;
+        assert_eq!(states, [1.0, 3.0, 4.0, 5.0]);
+        assert_eq!((stats.injected, stats.filtered, stats.unqualified), (2, 0, 0));
+        assert_eq!(stats.fitness_evals, 4);
+
+        // Comparative fitness can't be compared with the survivors.
+        let cfg = EvolveCfg::new(4)
+            .set_stagnation(Stagnation::ContinuousAfter(0))
+            .set_comparison(Comparison::RoundRobin)
+            .set_replacement_filter(filter);
+        let err = Evolver::new(identity(), cfg, || 1.0).run().err().unwrap();
+        assert!(err.to_string().contains("replacement filter"), "{err}");
+        Ok(())
+    }
+
+    // Whether the two fittest members of each of several stagnant generations
+    // are carried over to the next unchanged.
+    fn elites_kept(cfg: EvolveCfg) -> Result<bool> {
+        let cfg = cfg.set_stagnation(Stagnation::ContinuousAfter(0));
+        let mut evolver = Evolver::new(CountEvaluator, cfg, || 0);
+        for _ in 0..10 {
+            let r = evolver.run()?;
+            assert!(r.stagnant);
+            for top in &r.gen.mems[..2] {
+                let next = &evolver.gen.mems;
+                if !next.iter().any(|v| v.id == top.id && v.state == top.state) {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    #[test]
+    fn elitism() -> Result<()> {
+        let cfgs = [
+            EvolveCfg::new(10)
+                .set_survival(Survival::Youngest)
+                .set_replacement(Replacement::ReplaceChildren(0.9)),
+            EvolveCfg::new(10)
+                .set_survival(Survival::TopProportion(0.0))
+                .set_replacement(Replacement::ReplaceWorst(1.0)),
+            EvolveCfg::new(10)
+                .set_duplicates(Duplicates::DisallowWithinDistance(0.5))
+                .set_replacement(Replacement::ReplaceWorst(0.9)),
+        ];
+        for cfg in cfgs {
+            assert!(elites_kept(cfg.clone().set_elitism(2))?, "{cfg:?}");
+        }
+        // Replacing everyone loses the best members without elitism.
+        let cfg = EvolveCfg::new(10)
+            .set_survival(Survival::TopProportion(0.0))
+            .set_replacement(Replacement::ReplaceWorst(1.0));
+        assert!(!elites_kept(cfg)?);
+
+        // Elitism past the population size keeps only as many as fit, even
+        // if the initial population is bigger.
+        let cfg = EvolveCfg::new(4).set_elitism(10).set_stagnation(Stagnation::ContinuousAfter(0));
+        let mut evolver = Evolver::from_initial(CountEvaluator, cfg, (0..10).collect(), || 0);
+        assert_eq!(evolver.run()?.gen.mems.len(), 10);
+        for _ in 0..5 {
+            assert_eq!(evolver.run()?.gen.mems.len(), 4);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rank_selection_pressure() -> Result<()> {
+        let cases = [(1.0, true), (2.0, true), (0.5, false), (2.5, false), (f64::NAN, false)];
+        for (pressure, ok) in cases {
+            assert_eq!(RankPressure::new(pressure).is_ok(), ok, "pressure {pressure}");
+        }
+        // Warmup selection is used in place of the configured selection.
+        let rank = Selection::Rank(RankPressure::new(2.0)?);
+        let cfg = EvolveCfg::new(4).set_warmup(Some(Warmup::new(1, true, rank)));
+        assert_eq!(cfg.breeding_cfg(0).selection, rank);
+        let mut evolver = Evolver::new(identity(), cfg, || 1.0);
+        for _ in 0..3 {
+            let _ = evolver.run()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn takeover_trend() {
+        let mut evolver = scripted_evolver(StagnationSignal::TrainBest);
+        let fractions = [0.1, 0.2, 0.2, 0.3, 0.5, 0.9, 0.4, 0.5];
+        let trends = fractions.map(|v| evolver.update_takeover(v));
+        assert_eq!(trends, [0, 1, 0, 1, 2, 3, 0, 1]);
+        assert_eq!(evolver.takeover_history().len(), fractions.len());
+    }
+
+    fn snapshots(cfg: EvolveCfg) -> Result<Vec<Option<SpeciesSnapshot>>> {
+        let initial = vec![0, 0, 0, 10, 10, 20];
+        let mut evolver = Evolver::from_initial(CountEvaluator, cfg, initial, || 0);
+        (0..3).map(|_| Ok(evolver.run()?.species_snapshot)).collect()
+    }
+
+    #[test]
+    fn species_snapshots() -> Result<()> {
+        let cfg = EvolveCfg::new(6).set_duplicates(Duplicates::AllowDuplicates);
+        assert_eq!(snapshots(cfg.clone().set_species_snapshots(true))?, [None, None, None]);
+        let cfg = cfg.set_species(Species::TargetNumber(3));
+        assert_eq!(snapshots(cfg.clone())?, [None, None, None]);
+
+        let snapshots = snapshots(cfg.set_species_snapshots(true))?;
+        for (i, snapshot) in snapshots.iter().enumerate() {
+            let snapshot = snapshot.as_ref().unwrap();
+            assert_eq!(snapshot.gen, i);
+            assert_eq!(snapshot.species.iter().map(|v| v.size).sum::<usize>(), 6);
+        }
+        // Members start at distance 10 apart, so the radius search finds the
+        // three groups in the initial generation. The fittest group is first.
+        let first = snapshots[0].as_ref().unwrap();
+        assert_eq!(first.species.iter().map(|v| v.size).collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!(first.species[0].representative, "20");
+        Ok(())
+    }
+
+    #[test]
+    fn seed_fraction() -> Result<()> {
+        let cfg = EvolveCfg::new(8).set_duplicates(Duplicates::AllowDuplicates);
+        let mut evolver = Evolver::new(CountEvaluator, cfg, || 1)
+            .set_seed_fraction(0.3, |count| (0..count).map(|i| 10 + i).collect());
+        let r = evolver.run()?;
+        let mut states = r.gen.mems.iter().map(|v| *v.state).collect::<Vec<_>>();
+        states.sort_unstable();
+        assert_eq!(states, [1, 1, 1, 1, 1, 1, 10, 11]);
+        Ok(())
+    }
+
+    // Like `CountEvaluator`, but each distance takes a millisecond.
+    fn slow_dist() -> MockEvaluator {
+        MockEvaluator::new().counting().set_distance(|s1, s2| {
+            std::thread::sleep(Duration::from_millis(1));
+            Ok(s1.abs_diff(*s2) as f64)
+        })
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn generation_time_budget() -> Result<()> {
+        // 400 distances take at least 400ms, so a 50ms budget skips them.
+        let cfg = EvolveCfg::new(20)
+            .set_duplicates(Duplicates::AllowDuplicates)
+            .set_par_dist(false)
+            .set_niching(Niching::SharedFitness(1.0))
+            .set_species_snapshots(true)
+            .set_generation_time_budget(Some(Duration::from_millis(50)));
+        let mut evolver = Evolver::new(slow_dist(), cfg, || 1);
+        for _ in 0..2 {
+            let start = Instant::now();
+            let r = evolver.run()?;
+            assert!(start.elapsed() < Duration::from_millis(200), "{:?}", start.elapsed());
+            assert!(r.unevaluated.skipped.contains(OptionalPhase::Distances));
+            assert!(r.gen.mems.iter().all(|v| v.selection_fitness == v.fitness));
+            assert!(Stats::from_result(&r).skipped.contains(OptionalPhase::Distances));
+        }
+
+        // Nothing is skipped without a budget.
+        let cfg = EvolveCfg::new(4)
+            .set_niching(Niching::SharedFitness(1.0))
+            .set_generation_time_budget(None);
+        let r = Evolver::new(slow_dist(), cfg, || 1).run()?;
+        assert!(r.unevaluated.skipped.is_empty());
+        assert!(r.mean_distance().is_finite());
+        Ok(())
+    }
+
+    // Two mutation operators, with fitness the state plus one.
+    type RateEvaluator = MockEvaluator<usize, (), 2, 2>;
+
+    #[test]
+    fn initial_params() -> Result<()> {
+        const POP: usize = 100;
+        // Records the rates passed to each mutation operator.
+        let rates = Arc::new(Mutex::new(vec![]));
+        let eval = || {
+            let rates = Arc::clone(&rates);
+            RateEvaluator::default()
+                .set_fitness(|s, ()| Ok(*s as f64 + 1.0))
+                .set_mutate(move |_, rate, idx| rates.lock().unwrap().push((idx, rate)))
+        };
+        let cfg = EvolveCfg::new(POP)
+            .set_mutation(Mutation::Adaptive)
+            .set_duplicates(Duplicates::AllowDuplicates);
+        // Seeded states should mostly get the first mutation. Random params
+        // would average 0.5 for both.
+        let params = Params::biased::<RateEvaluator>(&[0.9, 0.01], &[1.0, 1.0])?;
+        let initial = (0..POP).map(|i| (i, Some(params.clone()))).collect();
+        let mut evolver = Evolver::from_initial_with_params(eval(), cfg.clone(), initial, || 0)?;
+        let _ = evolver.run()?;
+        let rates = rates.lock().unwrap().clone();
+        let mean = |idx| {
+            let v = rates.iter().filter(|(i, _)| *i == idx).map(|(_, r)| r).collect::<Vec<_>>();
+            v.iter().copied().sum::<f64>() / v.len() as f64
+        };
+        assert!(mean(0) > 0.8 && mean(1) < 0.02, "{} {}", mean(0), mean(1));
+
+        // Wrong lengths are rejected straight away.
+        assert!(Params::biased::<RateEvaluator>(&[0.9], &[1.0, 1.0]).is_err());
+        assert!(Params::biased::<RateEvaluator>(&[0.9, -1.0], &[1.0, 1.0]).is_err());
+        let bad = Params { mutation: vec![1.0; 3], ..Params::uniform::<RateEvaluator>() };
+        let initial = vec![(0, None), (1, Some(bad))];
+        assert!(Evolver::from_initial_with_params(eval(), cfg, initial, || 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoint_restore() -> Result<()> {
+        let cfg = EvolveCfg::new(8).set_mutation(Mutation::Adaptive).set_par_fitness(false);
+        let mut evolver = Evolver::new_seeded(CountEvaluator, cfg.clone(), || 0, 1);
+        for _ in 0..3 {
+            let _ = evolver.run()?;
+        }
+        // A restored evolver carries on exactly like the one checkpointed,
+        // whatever it was made with.
+        let checkpoint = evolver.checkpoint();
+        let mut restored = Evolver::new(CountEvaluator, cfg.clone(), || 5).restore(checkpoint)?;
+        assert_eq!(restored.generation(), 3);
+        let mems = |r: &EvolveResult<usize>| {
+            r.gen.mems.iter().map(|v| (*v.state, v.params.clone(), v.age)).collect::<Vec<_>>()
+        };
+        for _ in 0..3 {
+            assert_eq!(mems(&evolver.run()?), mems(&restored.run()?));
+        }
+
+        let mut empty = evolver.checkpoint();
+        empty.mems.clear();
+        assert!(Evolver::new(CountEvaluator, cfg, || 0).restore(empty).is_err());
+        Ok(())
+    }
+
+    // Evaluator with three crossover operators, where index 0 is the no-op if
+    // |NOOP|.
+    type NoopEvaluator<const NOOP: bool> = MockEvaluator<usize, (), 3, 1, NOOP>;
+
+    fn crossover_summary<const NOOP: bool>() -> Result<String> {
+        let cfg = EvolveCfg::new(4).set_duplicates(Duplicates::AllowDuplicates);
+        let params = Params::biased::<NoopEvaluator<NOOP>>(&[1.0], &[2.0, 1.0, 3.0])?;
+        let initial = (0..4).map(|i| (i, Some(params.clone()))).collect();
+        let mut evolver = Evolver::from_initial_with_params(
+            NoopEvaluator::<NOOP>::default(),
+            cfg,
+            initial,
+            || 0,
+        )?;
+        let r = evolver.run()?;
+        Ok(evolver.summary(&r))
+    }
+
+    #[test]
+    fn summary_labels_noop() -> Result<()> {
+        let summary = crossover_summary::<true>()?;
+        assert!(
+            summary.contains(
+                "crossover: none 2.00000, 1 1.00000, 2 3.00000, \n\
+                crossover share: 1 25.0%, 2 75.0%, \n"
+            ),
+            "{summary}"
+        );
+        let summary = crossover_summary::<false>()?;
+        assert!(
+            summary.contains(
+                "crossover: 0 2.00000, 1 1.00000, 2 3.00000, \n\
+                crossover share: 0 33.3%, 1 16.7%, 2 50.0%, \n"
+            ),
+            "{summary}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn warmup() -> Result<()> {
+        // Always applies the real crossover operator once warm-up is over.
+        let cfg = EvolveCfg::new(10)
+            .set_duplicates(Duplicates::AllowDuplicates)
+            .set_crossover(Crossover::Fixed(vec![0.0, 1.0]))
+            .set_warmup(Some(Warmup::new(3, false, Selection::Roulette)));
+        let selections = (0..5).map(|i| cfg.breeding_cfg(i).selection).collect::<Vec<_>>();
+        let (roulette, sus) = (Selection::Roulette, Selection::Sus);
+        assert_eq!(selections, [roulette, roulette, roulette, sus, sus]);
+        assert!(cfg.breeding_cfg(5).crossover_probability.is_none());
+
+        let mut evolver = Evolver::new(MockEvaluator::new().counting(), cfg, || 0);
+        let mut calls = vec![];
+        let mut warmups = vec![];
+        for _ in 0..5 {
+            let r = evolver.run()?;
+            warmups.push(Stats::from_result(&r).warmup);
+            calls.push(evolver.eval().crossover_calls.swap(0, Ordering::Relaxed));
+        }
+        assert_eq!(warmups, [true, true, true, false, false]);
+        assert_eq!(&calls[..3], &[0, 0, 0]);
+        assert!(calls[3..].iter().all(|&v| v > 0), "{calls:?}");
+
+        // The warm-up selection is the one parents are picked with: a
+        // tournament over everyone only picks the best, and a tournament of
+        // one picks uniformly.
+        let cfg = EvolveCfg::new(10)
+            .set_duplicates(Duplicates::AllowDuplicates)
+            .set_selection(Selection::Tournament(1))
+            .set_warmup(Some(Warmup::new(1, true, Selection::Tournament(10))))
+            .set_trace(true);
+        let states = (0..10).collect();
+        let rng = ChaCha12Rng::seed_from_u64(0);
+        let mut evolver = Evolver::from_initial_rng(MockEvaluator::new(), cfg, states, || 0, rng);
+        // Each result traces how its generation was bred from the last one.
+        let mut best = evolver.run()?.gen.mems[0].fitness;
+        let mut only_best = vec![];
+        for _ in 0..2 {
+            let r = evolver.run()?;
+            let trace = r.unevaluated.trace.unwrap();
+            assert!(!trace.is_empty());
+            only_best.push(trace.iter().all(|ev| ev.parent_fitness == [best, best]));
+            best = r.gen.mems[0].fitness;
+        }
+        assert_eq!(only_best, [true, false]);
+        Ok(())
+    }
+
+    #[test]
+    fn validate_distance() -> Result<()> {
+        let cfg = EvolveCfg::new(6)
+            .set_duplicates(Duplicates::AllowDuplicates)
+            .set_niching(Niching::SharedFitness(1.0))
+            .set_validate_distance(true);
+        let initial = vec![0, 1, 2, 3, 4, 5];
+        let evolver = |lopsided, cfg| {
+            // Distances from smaller to larger states are doubled if |lopsided|.
+            let eval = MockEvaluator::new().counting().set_distance(move |s1, s2| {
+                let d = s1.abs_diff(*s2) as f64;
+                Ok(if lopsided && s1 < s2 { 2.0 * d } else { d })
+            });
+            Evolver::from_initial(eval, cfg, initial.clone(), || 0)
+        };
+
+        // Checking the metric uses the cached distances.
+        let mut valid = evolver(false, cfg.clone());
+        let _ = valid.run()?;
+        assert_eq!(valid.eval().distance_calls.load(Ordering::Relaxed), 36);
+
+        let Err(err) = evolver(true, cfg.clone()).run() else {
+            panic!("asymmetric distance accepted");
+        };
+        let metric_err = err.downcast_ref::<MetricError>();
+        assert!(matches!(metric_err, Some(MetricError::Asymmetric { .. })), "{err}");
+        assert!(evolver(true, cfg.set_validate_distance(false)).run().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn data_recorded() -> Result<()> {
+        let mut evolver = Evolver::new(CountEvaluator, EvolveCfg::new(4), || 0);
+        let r = evolver.run_data(&[(), ()])?;
+        assert_eq!((r.data_len, r.data_fingerprint), (2, None));
+        let stats = Stats::from_result(&r);
+        assert_eq!((stats.data_len, stats.data_fingerprint), (2, None));
+
+        let r = evolver.run_data_batch(&[(); 3], 42)?;
+        assert_eq!((r.data_len, r.data_fingerprint), (3, Some(42)));
+        let stats = Stats::from_result(&r);
+        assert_eq!((stats.data_len, stats.data_fingerprint), (3, Some(42)));
+
+        // Evaluators which hash their inputs tell batches apart by contents.
+        let eval: MockEvaluator<usize, u64> =
+            MockEvaluator::default().set_data_fingerprint(|inputs| Some(data_fingerprint(inputs)));
+        let mut evolver = Evolver::new(eval.counting(), EvolveCfg::new(4), || 1);
+        let a = evolver.run_data_batch(&[1, 2], 42)?.data_fingerprint;
+        let b = evolver.run_data_batch(&[1, 3], 42)?.data_fingerprint;
+        assert_ne!(a, b);
+        assert_eq!(a, Some(data_fingerprint(&[1u64, 2])));
+        assert_eq!(evolver.run_data(&[1, 2])?.data_fingerprint, a);
+        Ok(())
+    }
+
+    #[test]
+    fn summaries_share_result() -> Result<()> {
+        let cfg = EvolveCfg::new(20).set_species(Species::TargetNumber(2));
+        let mut evolver = Evolver::new(CountEvaluator, cfg, || 0);
+        let r = evolver.run()?;
+        let expected = Stats::from_result(&r);
+        // Other threads compute stats while the evolver summarises the same
+        // result, all through shared borrows.
+        std::thread::scope(|s| {
+            let handles = (0..4).map(|_| s.spawn(|| Stats::from_result(&r))).collect::<Vec<_>>();
+            let summary = evolver.summary(&r);
+            let sample = evolver.summary_sample(&r, 5);
+            assert!(summary.starts_with(&expected.to_string()), "{summary}");
+            assert!(sample.starts_with("Species"), "{sample}");
+            for h in handles {
+                assert_eq!(h.join().unwrap(), expected);
+            }
+        });
+
+        #[allow(deprecated)]
+        let (summary, stats) = {
+            let mut r = r.clone();
+            (evolver.summary_mut(&mut r), Stats::from_result_mut(&mut r))
+        };
+        assert_eq!(summary, evolver.summary(&r));
+        assert_eq!(stats, expected);
+        Ok(())
+    }
+
+    // Only odd states are valid.
+    fn odd() -> MockEvaluator {
+        MockEvaluator::new().set_validate(|s| {
+            if s % 2 == 1 {
+                Ok(())
+            } else {
+                Err(eyre::eyre!("{s} is even"))
+            }
+        })
+    }
+
+    fn replacing_cfg(pop_size: usize) -> EvolveCfg {
+        EvolveCfg::new(pop_size)
+            .set_duplicates(Duplicates::AllowDuplicates)
+            .set_stagnation(Stagnation::ContinuousAfter(0))
+            .set_replacement(Replacement::ReplaceWorst(0.5))
+    }
+
+    #[test]
+    fn rand_states_validated() -> Result<()> {
+        // Half the generated states are invalid, so generation is retried.
+        let genfn = || rand::thread_rng().gen_range(0..1000);
+        let mut evolver = Evolver::new(odd(), replacing_cfg(20), genfn);
+        assert_eq!(evolver.gen.mems.len(), 20);
+        for _ in 0..5 {
+            assert!(evolver.gen.mems.iter().all(|v| *v.state % 2 == 1));
+            let r = evolver.run()?;
+            assert_eq!(Stats::from_result(&r).injected, 10);
+        }
+
+        // A generator which never makes a valid state fails, saying what the
+        // state was for, rather than retrying forever.
+        let mut evolver = Evolver::new(odd(), EvolveCfg::new(4), || 2);
+        let Err(e) = evolver.run() else { panic!("invalid initial states accepted") };
+        assert_eq!(
+            format!("{e:#}"),
+            "random state generator made no valid initial state in 100 attempts: 2 is even"
+        );
+        assert!(evolver.run().is_err());
+
+        let mut calls = 0;
+        let genfn = move || {
+            calls += 1;
+            if calls <= 4 {
+                1
+            } else {
+                2
+            }
+        };
+        let mut evolver = Evolver::new(odd(), replacing_cfg(4), genfn);
+        let Err(e) = evolver.run() else { panic!("invalid replacement states accepted") };
+        assert!(format!("{e:#}").contains("no valid replacement state"), "{e:#}");
+        Ok(())
+    }
+}
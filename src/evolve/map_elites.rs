@@ -0,0 +1,325 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use eyre::{eyre, Result, WrapErr};
+
+use crate::eval::{Evaluator, State};
+use crate::gen::member::Member;
+use crate::util::fmt::fmt_csv_str;
+
+/// One dimension of the behaviour descriptor grid: values from |min| to |max|
+/// are split into |bins| equal cells. Values outside the range go in the
+/// nearest edge cell.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub struct GridDim {
+    pub min: f64,
+    pub max: f64,
+    pub bins: usize,
+}
+
+impl GridDim {
+    pub fn new(min: f64, max: f64, bins: usize) -> Result<Self> {
+        let dim = Self { min, max, bins };
+        dim.validate()?;
+        Ok(dim)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !self.min.is_finite() || !self.max.is_finite() || self.min >= self.max {
+            return Err(eyre!("grid dimension must have finite min < max: {self:?}"));
+        }
+        if self.bins == 0 {
+            return Err(eyre!("grid dimension must have at least one bin"));
+        }
+        Ok(())
+    }
+
+    /// Cell |v| falls in, or None if it is NaN.
+    #[must_use]
+    pub fn bin(&self, v: f64) -> Option<usize> {
+        if v.is_nan() {
+            return None;
+        }
+        let frac = (v - self.min) / (self.max - self.min);
+        Some(((frac * self.bins as f64).floor().max(0.0) as usize).min(self.bins - 1))
+    }
+}
+
+/// Coverage and quality of a `MapElitesArchive`.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub struct MapElitesStats {
+    pub filled: usize,
+    pub cells: usize,
+    // Sum of the fitness of the member in each filled cell.
+    pub qd_score: f64,
+}
+
+impl MapElitesStats {
+    /// Fraction of cells which hold a member.
+    #[must_use]
+    pub fn coverage(&self) -> f64 {
+        self.filled as f64 / self.cells as f64
+    }
+}
+
+/// Quality-diversity archive in the MAP-Elites style. Members are placed in a
+/// grid cell by their behaviour descriptor, from `Evaluator::descriptor`, and
+/// each cell keeps the fittest member it has seen. Maintained alongside the
+/// population by an `Evolver` with `Evolver::set_map_elites`, and doesn't
+/// affect evolution.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct MapElitesArchive<S: State> {
+    dims: Vec<GridDim>,
+    // Row major, with the first dimension varying slowest.
+    cells: Vec<Option<Member<S>>>,
+}
+
+impl<S: State> MapElitesArchive<S> {
+    /// Fails if there are no dimensions, or any has no bins or an empty range.
+    pub fn new(dims: Vec<GridDim>) -> Result<Self> {
+        if dims.is_empty() {
+            return Err(eyre!("grid must have at least one dimension"));
+        }
+        for dim in &dims {
+            dim.validate()?;
+        }
+        let cells = dims.iter().map(|v| v.bins).product();
+        Ok(Self { dims, cells: vec![None; cells] })
+    }
+
+    pub fn dims(&self) -> &[GridDim] {
+        &self.dims
+    }
+
+    /// Coordinates of the cell |descriptor| falls in, or None if any value is
+    /// NaN. Fails if it has the wrong number of dimensions.
+    pub fn cell(&self, descriptor: &[f64]) -> Result<Option<Vec<usize>>> {
+        if descriptor.len() != self.dims.len() {
+            return Err(eyre!(
+                "descriptor has {} dimensions, grid has {}",
+                descriptor.len(),
+                self.dims.len()
+            ));
+        }
+        Ok(self.dims.iter().zip(descriptor).map(|(dim, &v)| dim.bin(v)).collect())
+    }
+
+    fn index(&self, coords: &[usize]) -> usize {
+        self.dims.iter().zip(coords).fold(0, |idx, (dim, &c)| idx * dim.bins + c)
+    }
+
+    fn coords(&self, mut idx: usize) -> Vec<usize> {
+        let mut coords = vec![0; self.dims.len()];
+        for (c, dim) in coords.iter_mut().zip(&self.dims).rev() {
+            *c = idx % dim.bins;
+            idx /= dim.bins;
+        }
+        coords
+    }
+
+    /// Puts |mem| in the cell for |descriptor| if the cell is empty or |mem|
+    /// is fitter than its member. Returns whether it was added.
+    pub fn insert(&mut self, mem: &Member<S>, descriptor: &[f64]) -> Result<bool> {
+        let Some(coords) = self.cell(descriptor)? else { return Ok(false) };
+        let idx = self.index(&coords);
+        if mem.fitness.is_nan() || matches!(&self.cells[idx], Some(v) if v.fitness >= mem.fitness) {
+            return Ok(false);
+        }
+        self.cells[idx] = Some(mem.clone());
+        Ok(true)
+    }
+
+    /// Inserts each of |mems| with its descriptor averaged over |inputs|.
+    /// Fails if there are no inputs to average over.
+    pub fn update<E: Evaluator<State = S>>(
+        &mut self,
+        eval: &E,
+        mems: &[Member<S>],
+        inputs: &[E::Data],
+    ) -> Result<()> {
+        if inputs.is_empty() && !mems.is_empty() {
+            return Err(eyre!("map-elites descriptors need at least one input"));
+        }
+        for mem in mems {
+            let mut descriptor = vec![0.0; self.dims.len()];
+            for data in inputs {
+                let v = eval.descriptor(&mem.state, data)?;
+                if v.len() != descriptor.len() {
+                    return Err(eyre!(
+                        "descriptor has {} dimensions, grid has {}",
+                        v.len(),
+                        descriptor.len()
+                    ));
+                }
+                for (acc, v) in descriptor.iter_mut().zip(v) {
+                    *acc += v / inputs.len() as f64;
+                }
+            }
+            let _ = self.insert(mem, &descriptor)?;
+        }
+        Ok(())
+    }
+
+    /// Member in the cell at |coords|, if any.
+    #[must_use]
+    pub fn get(&self, coords: &[usize]) -> Option<&Member<S>> {
+        self.cells[self.index(coords)].as_ref()
+    }
+
+    /// Filled cells with their coordinates, in row major order.
+    pub fn elites(&self) -> impl Iterator<Item = (Vec<usize>, &Member<S>)> + '_ {
+        self.cells
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, v)| v.as_ref().map(|mem| (self.coords(idx), mem)))
+    }
+
+    pub fn stats(&self) -> MapElitesStats {
+        let filled = self.cells.iter().flatten();
+        MapElitesStats {
+            filled: filled.clone().count(),
+            cells: self.cells.len(),
+            qd_score: filled.map(|v| v.fitness).sum(),
+        }
+    }
+
+    /// Filled cells as CSV, with a column per cell coordinate, then fitness
+    /// and the member's state.
+    #[must_use]
+    pub fn to_csv(&self) -> String {
+        let mut s = String::new();
+        for i in 0..self.dims.len() {
+            let _ = write!(s, "cell_{i},");
+        }
+        s.push_str("fitness,state\n");
+        for (coords, mem) in self.elites() {
+            for c in coords {
+                let _ = write!(s, "{c},");
+            }
+            let _ = writeln!(s, "{},{}", mem.fitness, fmt_csv_str(&mem.state.to_string()));
+        }
+        s
+    }
+
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_csv())
+            .wrap_err_with(|| format!("writing map-elites archive {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::evolve::cfg::{Duplicates, EvolveCfg};
+    use crate::evolve::evolver::Evolver;
+    use crate::util::test_utils::MockEvaluator;
+
+    // States are points (x, y) encoded as x * 10 + y, with fitness x + y and
+    // descriptor (x, y).
+    fn grid() -> MockEvaluator {
+        MockEvaluator::new()
+            .set_fitness(|s, ()| Ok((s / 10 + s % 10) as f64))
+            .set_descriptor(|s, ()| Ok(vec![(s / 10) as f64, (s % 10) as f64]))
+    }
+
+    // 2x3 grid over [0, 10) x [0, 9).
+    fn archive() -> MapElitesArchive<usize> {
+        MapElitesArchive::new(vec![
+            GridDim::new(0.0, 10.0, 2).unwrap(),
+            GridDim::new(0.0, 9.0, 3).unwrap(),
+        ])
+        .unwrap()
+    }
+
+    fn mem(state: usize, fitness: f64) -> Member<usize> {
+        let mut mem = Member::new::<MockEvaluator>(state, &EvolveCfg::new(1));
+        mem.fitness = fitness;
+        mem
+    }
+
+    #[test]
+    fn cell_assignment() -> Result<()> {
+        let archive = archive();
+        assert_eq!(archive.cell(&[0.0, 0.0])?, Some(vec![0, 0]));
+        assert_eq!(archive.cell(&[4.9, 3.0])?, Some(vec![0, 1]));
+        assert_eq!(archive.cell(&[5.0, 8.9])?, Some(vec![1, 2]));
+        // Out of range values go in the edge cells.
+        assert_eq!(archive.cell(&[-1.0, 100.0])?, Some(vec![0, 2]));
+        assert_eq!(archive.cell(&[f64::NAN, 1.0])?, None);
+        assert!(archive.cell(&[1.0]).is_err());
+        for idx in 0..6 {
+            assert_eq!(archive.index(&archive.coords(idx)), idx);
+        }
+        assert_eq!(archive.coords(5), [1, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_grid() {
+        assert!(GridDim::new(0.0, 1.0, 0).is_err());
+        assert!(GridDim::new(1.0, 1.0, 2).is_err());
+        assert!(GridDim::new(0.0, f64::NAN, 2).is_err());
+        assert!(MapElitesArchive::<usize>::new(vec![]).is_err());
+        // Dimensions built directly are checked too.
+        let dim = GridDim { min: 0.0, max: 1.0, bins: 0 };
+        assert!(MapElitesArchive::<usize>::new(vec![dim]).is_err());
+    }
+
+    #[test]
+    fn update_needs_inputs() -> Result<()> {
+        let mut archive = archive();
+        assert!(archive.update(&grid(), &[mem(11, 2.0)], &[]).is_err());
+        assert_eq!(archive.stats().filled, 0);
+        archive.update(&grid(), &[mem(11, 2.0)], &[()])?;
+        assert_eq!(archive.get(&[0, 0]).map(|v| *v.state), Some(11));
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn keeps_best_per_cell() -> Result<()> {
+        let mut archive = archive();
+        assert!(archive.insert(&mem(1, 2.0), &[1.0, 1.0])?);
+        assert!(!archive.insert(&mem(2, 1.0), &[2.0, 2.0])?);
+        assert!(!archive.insert(&mem(3, 2.0), &[3.0, 0.0])?);
+        assert!(archive.insert(&mem(4, 3.0), &[4.0, 2.0])?);
+        assert!(archive.insert(&mem(5, 1.0), &[9.0, 8.0])?);
+        assert!(!archive.insert(&mem(6, 9.0), &[f64::NAN, 8.0])?);
+        assert_eq!(archive.get(&[0, 0]).map(|v| *v.state), Some(4));
+        assert_eq!(archive.get(&[1, 2]).map(|v| *v.state), Some(5));
+        assert_eq!(archive.get(&[0, 1]), None);
+
+        let stats = archive.stats();
+        assert_eq!(stats, MapElitesStats { filled: 2, cells: 6, qd_score: 4.0 });
+        assert_eq!(stats.coverage(), 2.0 / 6.0);
+        assert_eq!(archive.to_csv(), "cell_0,cell_1,fitness,state\n0,0,3,4\n1,2,1,5\n");
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn evolver_updates_archive() -> Result<()> {
+        // (1, 1), (2, 5), (7, 0), (7, 2) and (8, 8). The last two share a cell.
+        let states = vec![11, 25, 70, 72, 88];
+        let cfg = EvolveCfg::new(states.len()).set_duplicates(Duplicates::AllowDuplicates);
+        let mut evolver =
+            Evolver::from_initial(grid(), cfg, states, || 0).set_map_elites(archive());
+        let r = evolver.run()?;
+        let archive = evolver.map_elites().unwrap();
+        let elites = archive.elites().map(|(c, v)| (c, *v.state)).collect::<Vec<_>>();
+        assert_eq!(
+            elites,
+            [(vec![0, 0], 11), (vec![0, 1], 25), (vec![1, 0], 72), (vec![1, 2], 88)]
+        );
+        let stats = r.map_elites.unwrap();
+        assert_eq!(stats.filled, 4);
+        assert_eq!(stats.qd_score, 2.0 + 7.0 + 9.0 + 16.0);
+        Ok(())
+    }
+}
@@ -1,3 +1,6 @@
+pub mod archive;
 pub mod cfg;
+pub mod checkpoint;
 pub mod evolver;
+pub mod map_elites;
 pub mod result;
@@ -1,10 +1,22 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
 use derive_more::Display;
+use enumset::EnumSet;
+use eyre::{eyre, Result};
+use rand::Rng;
 
-use crate::eval::State;
+use crate::eval::{Evaluator, State};
+use crate::evolve::cfg::{OptionalPhase, Termination};
+use crate::evolve::map_elites::MapElitesStats;
 use crate::gen::evaluated::EvaluatedGen;
 use crate::gen::member::Member;
-use crate::gen::species::SpeciesInfo;
+use crate::gen::snapshot::{PopulationSnapshot, SpeciesSnapshot};
+use crate::gen::species::{SpeciesId, SpeciesInfo, NO_SPECIES};
+use crate::gen::trace::format_trace;
 use crate::gen::unevaluated::UnevaluatedGen;
+use crate::util::fmt::{fmt_count, fmt_duration, fmt_fitness};
 
 #[must_use]
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -13,36 +25,419 @@ pub struct Stats {
     pub mean_fitness: f64,
     pub pop_size: usize,
     pub num_dup: usize,
+    pub dups_removed: usize,
+    // Fitness evaluations, including by local search. See `EvolveResult`.
+    pub fitness_evals: usize,
+    // Children local search was applied to. See `EvolveResult`.
+    pub local_searched: usize,
+    // Training data evaluated on. See `EvolveResult`.
+    pub data_len: usize,
+    pub data_fingerprint: Option<u64>,
     pub mean_distance: f64,
     pub stagnant: bool,
+    pub injected: usize,
+    // Random individuals rejected, and injected without beating the worst
+    // survivor, by `EvolveCfg::replacement_filter`. See `EvolveResult`.
+    pub filtered: usize,
+    pub unqualified: usize,
+    pub hybrids: usize,
     pub species: SpeciesInfo,
+    pub species_target: SpeciesId,
+    pub mean_age: f64,
+    pub max_age: usize,
+    pub takeover_fraction: f64,
+    pub takeover_trend: usize,
+    // Whether the next generation was bred with `EvolveCfg::warmup`.
+    pub warmup: bool,
+    // Fraction of children fitter than their better parent, if
+    // `EvolveCfg::trace` is set and any children were bred.
+    pub improvement_rate: Option<f64>,
+    // Coverage and QD-score of the evolver's `MapElitesArchive`, if it has one.
+    pub map_elites: Option<MapElitesStats>,
+    // Optional work skipped to stay within the generation time budget.
+    pub skipped: EnumSet<OptionalPhase>,
+    // Whether `num_dup` and `mean_distance` are estimates, from
+    // `EvolveCfg::approx_stats`.
+    pub approx: bool,
+    // Lengths of the states, if the evaluator reports them.
+    pub lengths: Option<LengthStats>,
 }
 
 impl std::fmt::Display for Stats {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "best: {:5.5}, mean: {:5.5}\npop: {:>5}, dupes: {:>5}, stagnant: {}",
-            self.best_fitness, self.mean_fitness, self.pop_size, self.num_dup, self.stagnant
+            "best: {}, mean: {}\npop: {:>5}, dupes: {}{:>5}, removed: {:>5}, stagnant: {}",
+            fmt_fitness(self.best_fitness),
+            fmt_fitness(self.mean_fitness),
+            self.pop_size,
+            if self.approx { "~" } else { "" },
+            self.num_dup,
+            self.dups_removed,
+            self.stagnant
+        )?;
+        if self.stagnant {
+            write!(f, ", injected: {}", self.injected)?;
+            if self.filtered + self.unqualified > 0 {
+                write!(f, ", filtered: {}, unqualified: {}", self.filtered, self.unqualified)?;
+            }
+            if self.hybrids > 0 {
+                write!(f, ", hybrids: {}", self.hybrids)?;
+            }
+        }
+        if self.warmup {
+            write!(f, ", warmup")?;
+        }
+        write!(f, "\nevals: {}", fmt_count(self.fitness_evals))?;
+        if self.local_searched > 0 {
+            write!(f, ", local searched: {}", self.local_searched)?;
+        }
+        write!(f, "\nage: mean {:.1}, max {}", self.mean_age, self.max_age)?;
+        write!(
+            f,
+            "\ntakeover: {:.3}, growing for {}",
+            self.takeover_fraction, self.takeover_trend
         )?;
         if self.mean_distance.is_finite() {
-            write!(f, "dist: {:5.5}, {}", self.mean_distance, self.species)?;
+            let approx = if self.approx { "~" } else { "" };
+            write!(f, "\ndist: {approx}{:5.5}, {}", self.mean_distance, self.species)?;
+            if self.species_target != NO_SPECIES {
+                write!(f, ", target: {:>3}", self.species_target)?;
+            }
+        }
+        if let Some(rate) = self.improvement_rate {
+            write!(f, "\nimproved: {:.1}%", rate * 100.0)?;
+        }
+        if let Some(map_elites) = self.map_elites {
+            write!(
+                f,
+                "\nmap-elites: coverage {:.1}%, qd score {}",
+                map_elites.coverage() * 100.0,
+                fmt_fitness(map_elites.qd_score)
+            )?;
+        }
+        if let Some(lengths) = self.lengths {
+            write!(f, "\nlength: {lengths}")?;
+        }
+        if !self.skipped.is_empty() {
+            write!(f, "\nskipped: {:?}", self.skipped)?;
         }
         Ok(())
     }
 }
 
 impl Stats {
-    pub fn from_result<S: State>(r: &mut EvolveResult<S>) -> Self {
+    pub fn from_result<S: State>(r: &EvolveResult<S>) -> Self {
+        let (num_dup, mean_distance) = match r.approx_stats {
+            Some(approx) => (approx.num_dup, approx.mean_distance),
+            None => (r.num_dup(), r.mean_distance()),
+        };
         Self {
             best_fitness: r.nth(0).fitness,
             mean_fitness: r.mean_fitness(),
             pop_size: r.size(),
-            num_dup: r.num_dup(),
-            mean_distance: r.mean_distance(),
+            num_dup,
+            dups_removed: r.dups_removed,
+            fitness_evals: r.fitness_evals,
+            local_searched: r.local_searched,
+            data_len: r.data_len,
+            data_fingerprint: r.data_fingerprint,
+            mean_distance,
             stagnant: r.stagnant,
+            injected: r.injected,
+            filtered: r.filtered,
+            unqualified: r.unqualified,
+            hybrids: r.hybrids,
             species: r.unevaluated.species,
+            species_target: r.unevaluated.species_target,
+            mean_age: r.mean_age(),
+            max_age: r.max_age(),
+            takeover_fraction: r.takeover_fraction,
+            takeover_trend: r.takeover_trend,
+            warmup: r.warmup,
+            improvement_rate: r.unevaluated.improvement.as_ref().and_then(|v| v.all.rate()),
+            map_elites: r.map_elites,
+            skipped: r.unevaluated.skipped,
+            approx: r.approx_stats.is_some(),
+            lengths: r.lengths,
+        }
+    }
+
+    /// Keys of `to_kv_line`, in order, which are also the columns of
+    /// `to_csv_row`. Keys never contain spaces.
+    pub const KV_KEYS: [&'static str; 16] = [
+        "best", "mean", "pop", "dup", "removed", "evals", "stag", "injected", "species", "target",
+        "dist", "age", "max_age", "takeover", "trend", "approx",
+    ];
+
+    /// Keys `to_kv_line` adds after `KV_KEYS` if `lengths` is set. They
+    /// aren't columns of `to_csv_row`, which are the same for every row.
+    pub const LENGTH_KEYS: [&'static str; 6] =
+        ["len_min", "len_mean", "len_max", "eff_min", "eff_mean", "eff_max"];
+
+    // Values for `KV_KEYS`. Flags are 0 or 1, and fractional values are
+    // written with |frac|, so nothing contains spaces or commas.
+    fn kv_values(&self, frac: fn(f64) -> String) -> [String; 16] {
+        [
+            frac(self.best_fitness),
+            frac(self.mean_fitness),
+            self.pop_size.to_string(),
+            self.num_dup.to_string(),
+            self.dups_removed.to_string(),
+            self.fitness_evals.to_string(),
+            u8::from(self.stagnant).to_string(),
+            self.injected.to_string(),
+            self.species.num.to_string(),
+            self.species_target.to_string(),
+            frac(self.mean_distance),
+            frac(self.mean_age),
+            self.max_age.to_string(),
+            frac(self.takeover_fraction),
+            self.takeover_trend.to_string(),
+            u8::from(self.approx).to_string(),
+        ]
+    }
+
+    /// Single line of space separated key=value pairs, e.g. for piping logs
+    /// through shell tools. See `KV_KEYS`, `LENGTH_KEYS` and `from_kv_line`.
+    #[must_use]
+    pub fn to_kv_line(&self) -> String {
+        let mut pairs = Self::KV_KEYS.iter().zip(self.kv_values(fmt_fitness)).collect::<Vec<_>>();
+        if let Some(lengths) = self.lengths {
+            pairs.extend(Self::LENGTH_KEYS.iter().zip(lengths.kv_values()));
+        }
+        pairs.into_iter().map(|(k, v)| format!("{k}={v}")).collect::<Vec<_>>().join(" ")
+    }
+
+    /// CSV header for `to_csv_row`, with the same names as `to_kv_line`.
+    #[must_use]
+    pub fn csv_header() -> String {
+        Self::KV_KEYS.join(",")
+    }
+
+    /// Values of `KV_KEYS`, separated by commas. Unlike `to_kv_line`,
+    /// fractional values keep full precision.
+    #[must_use]
+    pub fn to_csv_row(&self) -> String {
+        self.kv_values(|v| format!("{v:?}")).join(",")
+    }
+
+    /// Parses a line from `to_kv_line`. Keys not in `KV_KEYS` or
+    /// `LENGTH_KEYS`, like the generation and time the `Trainer` adds, are
+    /// ignored. Fields without a key are zero, false or None, and fractional
+    /// values only have the precision they were printed with.
+    pub fn from_kv_line(line: &str) -> Result<Self> {
+        let mut values = HashMap::new();
+        for pair in line.split_whitespace() {
+            let (k, v) = pair.split_once('=').ok_or_else(|| eyre!("expected key=value: {pair}"))?;
+            let _ = values.insert(k, v);
+        }
+        Ok(Self {
+            best_fitness: kv_value(&values, "best")?,
+            mean_fitness: kv_value(&values, "mean")?,
+            pop_size: kv_value(&values, "pop")?,
+            num_dup: kv_value(&values, "dup")?,
+            dups_removed: kv_value(&values, "removed")?,
+            fitness_evals: kv_value(&values, "evals")?,
+            local_searched: 0,
+            data_len: 0,
+            data_fingerprint: None,
+            mean_distance: kv_value(&values, "dist")?,
+            stagnant: kv_flag(&values, "stag")?,
+            injected: kv_value(&values, "injected")?,
+            filtered: 0,
+            unqualified: 0,
+            hybrids: 0,
+            species: SpeciesInfo { num: kv_value(&values, "species")?, ..SpeciesInfo::new() },
+            species_target: kv_value(&values, "target")?,
+            mean_age: kv_value(&values, "age")?,
+            max_age: kv_value(&values, "max_age")?,
+            takeover_fraction: kv_value(&values, "takeover")?,
+            takeover_trend: kv_value(&values, "trend")?,
+            warmup: false,
+            improvement_rate: None,
+            map_elites: None,
+            skipped: EnumSet::new(),
+            approx: kv_flag(&values, "approx")?,
+            lengths: kv_lengths(&values)?,
+        })
+    }
+
+    #[deprecated(note = "stats only read the result, use `from_result`")]
+    pub fn from_result_mut<S: State>(r: &mut EvolveResult<S>) -> Self {
+        Self::from_result(r)
+    }
+}
+
+// Value of |key| in a line parsed by `Stats::from_kv_line`.
+fn kv_value<T: FromStr>(values: &HashMap<&str, &str>, key: &str) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    let v = values.get(key).ok_or_else(|| eyre!("missing key {key}"))?;
+    v.parse().map_err(|e| eyre!("invalid {key}={v}: {e}"))
+}
+
+// Flag written as 0 or 1 by `Stats::to_kv_line`.
+fn kv_flag(values: &HashMap<&str, &str>, key: &str) -> Result<bool> {
+    match kv_value::<u8>(values, key)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        v => Err(eyre!("invalid {key}={v}, expected 0 or 1")),
+    }
+}
+
+// `LengthStats` from `Stats::LENGTH_KEYS`, if the line has any of them.
+fn kv_lengths(values: &HashMap<&str, &str>) -> Result<Option<LengthStats>> {
+    if !Stats::LENGTH_KEYS.iter().any(|k| values.contains_key(k)) {
+        return Ok(None);
+    }
+    let [min, mean, max, effective_min, effective_mean, effective_max] = Stats::LENGTH_KEYS;
+    Ok(Some(LengthStats {
+        min: kv_value(values, min)?,
+        mean: kv_value(values, mean)?,
+        max: kv_value(values, max)?,
+        effective_min: kv_value(values, effective_min)?,
+        effective_mean: kv_value(values, effective_mean)?,
+        effective_max: kv_value(values, effective_max)?,
+    }))
+}
+
+/// Minimum, mean and maximum length of the states in a population, and of
+/// the parts of them which can affect fitness, from `Evaluator::lengths`.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LengthStats {
+    pub min: usize,
+    pub mean: f64,
+    pub max: usize,
+    pub effective_min: usize,
+    pub effective_mean: f64,
+    pub effective_max: usize,
+}
+
+impl LengthStats {
+    /// None if |mems| is empty or the evaluator doesn't report lengths.
+    #[must_use]
+    pub fn new<E: Evaluator>(mems: &[Member<E::State>], eval: &E) -> Option<Self> {
+        let lens = mems.iter().map(|v| eval.lengths(&v.state)).collect::<Option<Vec<_>>>()?;
+        let (full, effective): (Vec<_>, Vec<_>) = lens.into_iter().unzip();
+        let mean = |v: &[usize]| v.iter().sum::<usize>() as f64 / v.len() as f64;
+        Some(Self {
+            min: *full.iter().min()?,
+            mean: mean(&full),
+            max: *full.iter().max()?,
+            effective_min: *effective.iter().min()?,
+            effective_mean: mean(&effective),
+            effective_max: *effective.iter().max()?,
+        })
+    }
+
+    // Values for `Stats::LENGTH_KEYS`, like `Stats::kv_values`.
+    fn kv_values(&self) -> [String; 6] {
+        [
+            self.min.to_string(),
+            fmt_fitness(self.mean),
+            self.max.to_string(),
+            self.effective_min.to_string(),
+            fmt_fitness(self.effective_mean),
+            self.effective_max.to_string(),
+        ]
+    }
+}
+
+impl std::fmt::Display for LengthStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "min {}, mean {:.1}, max {}, effective: min {}, mean {:.1}, max {}",
+            self.min,
+            self.mean,
+            self.max,
+            self.effective_min,
+            self.effective_mean,
+            self.effective_max
+        )
+    }
+}
+
+/// Estimates of statistics which are expensive to compute exactly for large
+/// populations. See `EvolveCfg::approx_stats`.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ApproxStats {
+    pub num_dup: usize,
+    pub mean_distance: f64,
+}
+
+impl ApproxStats {
+    /// Estimates from |size| samples. Duplicates are estimated by checking
+    /// whether sampled members equal any earlier member, which is what
+    /// `EvolveResult::num_dup` counts. This takes one pass over the
+    /// population, hashing states by their `Display` text, and only keeps
+    /// the sampled states. Mean distance is estimated by averaging the
+    /// distance between sampled pairs, which like the exact mean includes
+    /// each member paired with itself.
+    pub fn sample_rng<E: Evaluator, R: Rng + ?Sized>(
+        mems: &[Member<E::State>],
+        size: usize,
+        eval: &E,
+        r: &mut R,
+    ) -> Result<Self> {
+        let n = mems.len();
+        if n == 0 || size == 0 {
+            return Ok(Self { num_dup: 0, mean_distance: f64::NAN });
+        }
+        let mut dist = 0.0;
+        let mut sampled = Vec::with_capacity(size);
+        for _ in 0..size {
+            let (i, j) = (r.gen_range(0..n), r.gen_range(0..n));
+            dist += eval.distance(&mems[i].state, &mems[j].state)?;
+            sampled.push(i);
+        }
+        // First member of each distinct state with the same text as a sampled
+        // state. States with the same text needn't be equal, so compare them.
+        let mut firsts: HashMap<String, Vec<usize>> =
+            sampled.iter().map(|&i| (mems[i].state.to_string(), Vec::new())).collect();
+        for (i, mem) in mems.iter().enumerate() {
+            if let Some(v) = firsts.get_mut(&mem.state.to_string()) &&
+                    !v.iter().any(|&k| mems[k].state == mem.state) {
+                v.push(i);
+            }
+        }
+        let dups = sampled
+            .iter()
+            .filter(|&&i| {
+                let v = &firsts[&mems[i].state.to_string()];
+                v.iter().any(|&k| k < i && mems[k].state == mems[i].state)
+            })
+            .count();
+        let num_dup = (dups as f64 / size as f64 * n as f64).round() as usize;
+        Ok(Self { num_dup, mean_distance: dist / size as f64 })
+    }
+}
+
+/// Rates of recent generations, and the estimated time until training
+/// terminates.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Throughput {
+    pub gens_per_sec: f64,
+    pub evals_per_sec: f64,
+    /// Time left until the first termination criterion is met. None if it
+    /// can't be estimated, e.g. if no fitness evaluations have been made to
+    /// estimate an evaluation budget from, or if the only criterion is a
+    /// target fitness.
+    pub eta: Option<Duration>,
+}
+
+impl std::fmt::Display for Throughput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.2} gens/s, {:.1} evals/s", self.gens_per_sec, self.evals_per_sec)?;
+        if let Some(eta) = self.eta {
+            write!(f, ", eta {}", fmt_duration(eta))?;
         }
+        Ok(())
     }
 }
 
@@ -53,6 +448,71 @@ pub struct EvolveResult<S: State> {
     pub unevaluated: UnevaluatedGen<S>,
     pub gen: EvaluatedGen<S>,
     pub stagnant: bool,
+    // Random individuals injected into the next generation due to stagnation.
+    pub injected: usize,
+    // Random individuals evaluated and rejected by
+    // `EvolveCfg::replacement_filter` for places in this generation.
+    pub filtered: usize,
+    // Random individuals injected into this generation which didn't beat the
+    // worst survivor, since no candidate for their place did.
+    pub unqualified: usize,
+    // Children of different species bred into the next generation due to
+    // stagnation.
+    pub hybrids: usize,
+    // Duplicates removed when creating the next generation.
+    pub dups_removed: usize,
+    // Fitness evaluations of this generation, including of the random
+    // individuals considered by `EvolveCfg::replacement_filter`, plus those
+    // made by local search on the children bred for the next generation.
+    pub fitness_evals: usize,
+    // Children bred for the next generation which local search was applied
+    // to, whether or not it improved them.
+    pub local_searched: usize,
+    // Number of inputs this generation was evaluated on.
+    pub data_len: usize,
+    // Identifies the inputs this generation was evaluated on: their hash from
+    // `Evaluator::data_fingerprint`, or else the batch id if run with
+    // `Evolver::run_data_batch`, as the `Trainer` does.
+    pub data_fingerprint: Option<u64>,
+    // Fraction of the population which are (near) copies of the best member.
+    pub takeover_fraction: f64,
+    // Number of generations the takeover fraction has been growing for.
+    pub takeover_trend: usize,
+    // Whether the next generation was bred with `EvolveCfg::warmup`. The
+    // first result without it is the generation the main config took over.
+    pub warmup: bool,
+    // Fitness of the best member on the test data. Only set by `Trainer` on
+    // the final result, and None if there is no test data.
+    pub test_fitness: Option<f64>,
+    // Fitness of the selected member on all the validation data. Only set by
+    // `Trainer` on the final result if `TrainerCfg::valid_sample` is set, since
+    // validation reporting during training then only uses a sample.
+    pub valid_fitness: Option<f64>,
+    // Member with the best cross validated fitness seen during training, and
+    // that fitness. Only set by `Trainer` on the final result, if
+    // `TrainerCfg::cv_valid` is set.
+    pub cv_best: Option<(Member<S>, f64)>,
+    // Metrics the `Trainer` dropped during training since its metric queues
+    // were full. Only set by `Trainer` on the final result.
+    pub dropped_metrics: usize,
+    // Termination condition which stopped training, the first of
+    // `TrainerCfg::terminations` met. Only set by `Trainer` on the final
+    // result.
+    pub termination: Option<Termination>,
+    // Generations and fitness evaluations per second, smoothed over recent
+    // generations, and the time left. Only set by `Trainer`.
+    pub throughput: Option<Throughput>,
+    // Species in this generation, if `EvolveCfg::species_snapshots` is set and
+    // speciation is enabled.
+    pub species_snapshot: Option<SpeciesSnapshot>,
+    // Estimated statistics, if `EvolveCfg::approx_stats` is set.
+    pub approx_stats: Option<ApproxStats>,
+    // Archive stats after this generation, if the evolver has a
+    // `MapElitesArchive`.
+    pub map_elites: Option<MapElitesStats>,
+    // Lengths of the states in this generation, if the evaluator reports
+    // them with `Evaluator::lengths`.
+    pub lengths: Option<LengthStats>,
 }
 
 impl<S: State> EvolveResult<S> {
@@ -65,16 +525,39 @@ impl<S: State> EvolveResult<S> {
         &self.gen.mems[n]
     }
 
+    /// Metadata of every member, without copying states. See
+    /// `PopulationSnapshot`.
+    pub fn snapshot(&self) -> PopulationSnapshot<'_> {
+        PopulationSnapshot::new(&self.gen.mems)
+    }
+
     #[must_use]
     pub fn mean_fitness(&self) -> f64 {
         self.gen.mems.iter().map(|v| v.fitness).sum::<f64>() / self.gen.mems.len() as f64
     }
 
+    #[must_use]
+    pub fn mean_age(&self) -> f64 {
+        self.gen.mems.iter().map(|v| v.age as f64).sum::<f64>() / self.gen.mems.len() as f64
+    }
+
+    #[must_use]
+    pub fn max_age(&self) -> usize {
+        self.gen.mems.iter().map(|v| v.age).max().unwrap_or(0)
+    }
+
     #[must_use]
     pub fn mean_distance(&self) -> f64 {
         self.unevaluated.dists.mean()
     }
 
+    /// How the top |k| members were bred, if `EvolveCfg::trace` is set.
+    #[must_use]
+    pub fn trace_summary(&self, k: usize) -> Option<String> {
+        let trace = self.unevaluated.trace.as_ref()?;
+        Some(format_trace(trace, &self.gen.mems, k))
+    }
+
     #[must_use]
     pub fn num_dup(&self) -> usize {
         let mut states = self.gen.mems.iter().map(|v| &v.state).cloned().collect::<Vec<_>>();
@@ -83,3 +566,173 @@ impl<S: State> EvolveResult<S> {
         self.gen.mems.len() - states.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::evolve::cfg::EvolveCfg;
+    use crate::evolve::evolver::Evolver;
+    use crate::util::bench_utils::CountEvaluator;
+
+    #[test]
+    fn approx_stats_converge() -> Result<()> {
+        // 700 distinct states, then 300 copies of the first 100.
+        let cfg = EvolveCfg::new(1000);
+        let mems = (0..1000)
+            .map(|i: usize| Member::new::<CountEvaluator>(if i < 700 { i } else { i % 100 }, &cfg))
+            .collect::<Vec<_>>();
+        let n = mems.len() as f64;
+        let mean_distance = mems
+            .iter()
+            .flat_map(|a| mems.iter().map(move |b| a.state.abs_diff(*b.state) as f64))
+            .sum::<f64>()
+            / (n * n);
+
+        let mut r = StdRng::seed_from_u64(0);
+        let mut errs = Vec::new();
+        for size in [100, 1000, 100_000] {
+            let approx = ApproxStats::sample_rng(&mems, size, &CountEvaluator, &mut r)?;
+            let dup_err = approx.num_dup.abs_diff(300) as f64 / n;
+            let dist_err = (approx.mean_distance - mean_distance).abs() / mean_distance;
+            errs.push((dup_err, dist_err));
+        }
+        // Standard errors for 100 samples are about 0.05 for the duplicate
+        // fraction and 0.08 for relative distance.
+        assert!(errs[0].0 < 0.2 && errs[0].1 < 0.3, "{errs:?}");
+        assert!(errs[2].0 < 0.01 && errs[2].1 < 0.01, "{errs:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn approx_stats_flagged() -> Result<()> {
+        let mut evolver = Evolver::new(CountEvaluator, EvolveCfg::new(20), || 0);
+        let stats = Stats::from_result(&evolver.run()?);
+        assert!(!stats.approx);
+
+        let cfg = EvolveCfg::new(20).set_approx_stats(Some(50));
+        let mut evolver = Evolver::new(CountEvaluator, cfg, || 0);
+        let r = evolver.run()?;
+        assert!(r.approx_stats.is_some());
+        let stats = Stats::from_result(&r);
+        assert!(stats.approx);
+        assert!(stats.to_string().contains("dupes: ~"), "{stats}");
+        Ok(())
+    }
+
+    fn example_stats() -> Stats {
+        Stats {
+            best_fitness: 1234.5678,
+            mean_fitness: 1e-7,
+            pop_size: 100,
+            num_dup: 3,
+            dups_removed: 1,
+            fitness_evals: 1_234_567,
+            local_searched: 0,
+            data_len: 0,
+            data_fingerprint: None,
+            mean_distance: f64::NAN,
+            stagnant: false,
+            injected: 0,
+            filtered: 0,
+            unqualified: 0,
+            hybrids: 0,
+            species: SpeciesInfo::new(),
+            species_target: NO_SPECIES,
+            mean_age: 2.5,
+            max_age: 7,
+            takeover_fraction: 0.5,
+            takeover_trend: 2,
+            warmup: false,
+            improvement_rate: None,
+            map_elites: Some(MapElitesStats { filled: 5, cells: 20, qd_score: -0.0 }),
+            skipped: EnumSet::new(),
+            approx: false,
+            lengths: Some(LengthStats {
+                min: 3,
+                mean: 7.4,
+                max: 12,
+                effective_min: 1,
+                effective_mean: 3.5,
+                effective_max: 6,
+            }),
+        }
+    }
+
+    #[test]
+    fn stats_display() {
+        assert_eq!(
+            example_stats().to_string(),
+            "best: 1234.57, mean: 1.00000e-7\n\
+             pop:   100, dupes:     3, removed:     1, stagnant: false\n\
+             evals: 1,234,567\n\
+             age: mean 2.5, max 7\n\
+             takeover: 0.500, growing for 2\n\
+             map-elites: coverage 25.0%, qd score 0.00000\n\
+             length: min 3, mean 7.4, max 12, effective: min 1, mean 3.5, max 6"
+        );
+    }
+
+    #[test]
+    fn stats_kv_line() -> Result<()> {
+        let stats = example_stats();
+        let line = "best=1234.57 mean=1.00000e-7 pop=100 dup=3 removed=1 evals=1234567 stag=0 \
+                    injected=0 species=1 target=0 dist=NaN age=2.50000 max_age=7 \
+                    takeover=0.500000 trend=2 approx=0 len_min=3 len_mean=7.40000 len_max=12 \
+                    eff_min=1 eff_mean=3.50000 eff_max=6";
+        assert_eq!(stats.to_kv_line(), line);
+        assert_eq!(
+            Stats::csv_header(),
+            "best,mean,pop,dup,removed,evals,stag,injected,species,target,dist,age,max_age,takeover,\
+             trend,approx"
+        );
+        assert_eq!(stats.to_csv_row(), "1234.5678,1e-7,100,3,1,1234567,0,0,1,0,NaN,2.5,7,0.5,2,0");
+        assert_eq!(Stats::from_kv_line(line)?.to_kv_line(), line);
+        Ok(())
+    }
+
+    #[test]
+    fn stats_kv_round_trip() -> Result<()> {
+        // Values exact at the printed precision, and fields the line has.
+        let stats = Stats {
+            best_fitness: 0.75,
+            mean_fitness: 0.5,
+            mean_distance: 0.25,
+            stagnant: true,
+            injected: 5,
+            species: SpeciesInfo { num: 3, ..SpeciesInfo::new() },
+            species_target: 4,
+            approx: true,
+            map_elites: None,
+            lengths: None,
+            ..example_stats()
+        };
+        let line = stats.to_kv_line();
+        assert_eq!(Stats::from_kv_line(&line)?, stats);
+        // Keys the trainer adds are ignored.
+        assert_eq!(Stats::from_kv_line(&format!("gen=3 {line} time_ms=41"))?, stats);
+
+        assert!(Stats::from_kv_line(&line.replace("stag=1", "stag=2")).is_err());
+        assert!(Stats::from_kv_line(&line.replace("pop=100", "pop=x")).is_err());
+        assert!(Stats::from_kv_line(&line.replace("pop=100", "pop")).is_err());
+        assert!(Stats::from_kv_line(&line.replace("pop=100", "")).is_err());
+
+        // Lengths are kept if set, and need all their keys.
+        let lengths = LengthStats {
+            min: 2,
+            mean: 4.5,
+            max: 9,
+            effective_min: 0,
+            effective_mean: 1.25,
+            effective_max: 3,
+        };
+        let stats = Stats { lengths: Some(lengths), ..stats };
+        let line = stats.to_kv_line();
+        assert!(line.ends_with(" eff_max=3"), "{line}");
+        assert_eq!(Stats::from_kv_line(&line)?, stats);
+        assert!(Stats::from_kv_line(&line.replace("len_max=9", "")).is_err());
+        Ok(())
+    }
+}
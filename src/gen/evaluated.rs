@@ -1,17 +1,28 @@
+use std::sync::Arc;
+
+use ahash::HashMap;
 use derive_more::Display;
 use eyre::{eyre, Result};
 use rand::prelude::SliceRandom;
+use rand::{Rng, RngCore};
 
-use crate::eval::{Evaluator, State};
+use crate::eval::{Evaluator, FitnessEvals, State};
 use crate::evolve::cfg::{
-    Crossover, Duplicates, EvolveCfg, Mutation, Replacement, Selection, Survival,
+    Comparison, Crossover, Duplicates, EvolveCfg, LocalSearchCfg, LocalSearchPolicy, Mutation,
+    Replacement, ReplacementFilter, Selection, Survival, SurvivalFitness,
+};
+use crate::evolve::evolver::{rand_valid_state, RandState};
+use crate::gen::member::{next_member_id, Member, MemberId};
+use crate::gen::params::Params;
+use crate::gen::species::{SpeciesId, NO_SPECIES};
+use crate::gen::trace::BreedingEvent;
+use crate::gen::unevaluated::{Contest, ContestKind, UnevaluatedGen};
+use crate::ops::mutation::{mutate_lognorm_rng, mutate_normal_rng, mutate_rate_rng};
+use crate::ops::sampling::{
+    linear_rank_weights, multi_rws_rng, multi_wswor_rng, rws_rng, sample_others_rng, sus_rng,
+    tournament_rng,
 };
-use crate::evolve::evolver::RandState;
-use crate::gen::member::Member;
-use crate::gen::species::SpeciesId;
-use crate::gen::unevaluated::UnevaluatedGen;
-use crate::ops::mutation::{mutate_lognorm, mutate_normal, mutate_rate};
-use crate::ops::sampling::{multi_rws, rws, sus};
+use crate::util::par::try_any;
 
 #[must_use]
 #[derive(Display, Clone, PartialOrd, PartialEq)]
@@ -23,12 +34,18 @@ pub struct EvaluatedGen<S: State> {
 impl<S: State> EvaluatedGen<S> {
     pub fn new(mut mems: Vec<Member<S>>) -> Self {
         // Sort by base fitness. Selection should happen using selection
-        // fitness. Generate survivors using base fitness, to make sure we keep
-        // the top individuals.
+        // fitness. Survivors are generated using base fitness by default, to
+        // make sure we keep the top individuals. See `SurvivalFitness`.
         mems.sort_unstable_by(|a, b| b.fitness.partial_cmp(&a.fitness).unwrap());
         Self { mems }
     }
 
+    /// Generation from |mems| which are already ordered best first, e.g. by
+    /// stochastic ranking, so survivors are taken in that order.
+    pub fn from_ranked(mems: Vec<Member<S>>) -> Self {
+        Self { mems }
+    }
+
     pub fn mems(&self) -> &[Member<S>] {
         &self.mems
     }
@@ -48,7 +65,29 @@ impl<S: State> EvaluatedGen<S> {
         species
     }
 
-    fn survivors(&self, survival: Survival, cfg: &EvolveCfg) -> Vec<Member<S>> {
+    // Members in the order survivors are taken from, best first by
+    // |cfg.survival_fitness|. Base fitness keeps the generation's order, which
+    // may come from stochastic ranking.
+    fn survival_order(&self, cfg: &EvolveCfg) -> Vec<&Member<S>> {
+        let mut order = self.mems.iter().collect::<Vec<_>>();
+        if cfg.survival_fitness == SurvivalFitness::Shared {
+            order.sort_by(|a, b| b.selection_fitness.partial_cmp(&a.selection_fitness).unwrap());
+        }
+        order
+    }
+
+    fn survivors(
+        &self,
+        survival: Survival,
+        gen_idx: usize,
+        cfg: &EvolveCfg,
+        r: &mut dyn RngCore,
+    ) -> Vec<Member<S>> {
+        let fitness = |v: &Member<S>| match cfg.survival_fitness {
+            SurvivalFitness::Base => v.fitness,
+            SurvivalFitness::Shared => v.selection_fitness,
+        };
+        let order = self.survival_order(cfg);
         let mut mems = match survival {
             Survival::TopProportion(prop) => {
                 // Ceiling so we don't miss keeping things for small sizes.
@@ -57,35 +96,56 @@ impl<S: State> EvaluatedGen<S> {
                 // number of survivors selected from them. This is useful for
                 // with a small number of individuals.
                 let num = (cfg.pop_size as f64 * prop).ceil() as usize;
-                self.mems.iter().take(num).cloned().collect()
+                order.into_iter().take(num).cloned().collect()
             }
             Survival::SpeciesTopProportion(prop) => {
                 let mut survivors = Vec::new();
                 let species = self.species();
                 let num = (cfg.pop_size as f64 * prop / species.len() as f64).ceil() as usize;
                 for id in species {
-                    survivors.extend(self.species_mems(id).into_iter().take(num));
+                    let mems = order.iter().filter(|v| v.species == id);
+                    survivors.extend(mems.take(num).map(|&v| v.clone()));
                 }
                 survivors
             }
             Survival::Youngest => {
-                let mut survivors = self.mems.clone();
-                survivors.sort_unstable_by_key(|mem| mem.age);
+                let mut survivors = order.into_iter().cloned().collect::<Vec<_>>();
+                // Stable, so members of the same age stay in order of fitness.
+                survivors.sort_by_key(|mem| mem.age);
                 // Drop oldest until we reach the population size.
                 survivors.truncate(cfg.pop_size);
                 survivors
             }
-            Survival::Tournament(q) => {
+            Survival::Tournament(q) | Survival::TournamentWins(q) => {
+                let count_wins = matches!(survival, Survival::TournamentWins(_));
                 let mut survivors = Vec::new();
-                let mut rng = rand::thread_rng();
                 for mem in &self.mems {
-                    let opponents = self.mems.choose_multiple(&mut rng, q);
-                    let wins = opponents.filter(|opp| opp.fitness > mem.fitness).count();
+                    let opponents = self.mems.choose_multiple(r, q);
+                    let wins = opponents
+                        .filter(|opp| {
+                            if count_wins {
+                                fitness(mem) > fitness(opp)
+                            } else {
+                                fitness(opp) > fitness(mem)
+                            }
+                        })
+                        .count();
                     survivors.push((wins, mem));
                 }
                 survivors.sort_unstable_by_key(|(wins, _)| -(*wins as i64));
                 survivors.into_iter().map(|(_, mem)| mem.clone()).collect()
             }
+            Survival::Boltzmann { prop, .. } => {
+                let num = (cfg.pop_size as f64 * prop).ceil() as usize;
+                let temp = survival.temperature(gen_idx).unwrap().max(f64::MIN_POSITIVE);
+                // Subtract the max fitness so exp can't overflow. The best
+                // member has weight 1, and at low temperatures the rest can
+                // underflow to 0, which leaves them in order of fitness.
+                let max = order.iter().map(|&v| fitness(v)).fold(f64::NEG_INFINITY, f64::max);
+                let w = order.iter().map(|&v| ((fitness(v) - max) / temp).exp());
+                let idxs = multi_wswor_rng(&w.collect::<Vec<_>>(), num, r);
+                idxs.into_iter().map(|idx| order[idx].clone()).collect()
+            }
         };
         // Bump ages.
         for mem in &mut mems {
@@ -94,13 +154,35 @@ impl<S: State> EvaluatedGen<S> {
         mems
     }
 
-    fn selection(&self, selection: Selection) -> [Member<S>; 2] {
+    // The |n| best members, aged like survivors. Members compare by
+    // feasibility first, then base fitness, so infeasible members are only
+    // elites if there aren't enough feasible ones.
+    fn elites(&self, n: usize) -> Vec<Member<S>> {
+        let mut order = self.mems.iter().collect::<Vec<_>>();
+        order.sort_by(|a, b| {
+            a.violation.total_cmp(&b.violation).then(b.fitness.total_cmp(&a.fitness))
+        });
+        let mut elites = order.into_iter().take(n).cloned().collect::<Vec<_>>();
+        for mem in &mut elites {
+            mem.age += 1;
+        }
+        elites
+    }
+
+    fn selection_idxs(&self, selection: Selection, r: &mut dyn RngCore) -> [usize; 2] {
         let fitnesses = self.mems.iter().map(|v| v.selection_fitness).collect::<Vec<_>>();
         let idxs = match selection {
-            Selection::Sus => sus(&fitnesses, 2),
-            Selection::Roulette => multi_rws(&fitnesses, 2),
+            Selection::Sus => sus_rng(&fitnesses, 2, r),
+            Selection::Roulette => multi_rws_rng(&fitnesses, 2, r),
+            Selection::Rank(pressure) => {
+                sus_rng(&linear_rank_weights(&fitnesses, pressure.get()), 2, r)
+            }
+            // Independent tournaments, so both parents can be the same member.
+            Selection::Tournament(k) => {
+                (0..2).map(|_| tournament_rng(&fitnesses, k, r).unwrap()).collect()
+            }
         };
-        [self.mems[idxs[0]].clone(), self.mems[idxs[1]].clone()]
+        [idxs[0], idxs[1]]
     }
 
     fn check_weights(weights: &[f64], l: usize) -> Result<()> {
@@ -115,29 +197,80 @@ impl<S: State> EvaluatedGen<S> {
         Ok(())
     }
 
+    // Varies |s|, a copy of the member at |idx|, with differential evolution
+    // using three other members as donors.
+    fn differential<E: Evaluator<State = S>>(
+        &self,
+        f: f64,
+        cr: f64,
+        eval: &E,
+        s: &mut Member<S>,
+        idx: usize,
+        r: &mut dyn RngCore,
+    ) -> Result<()> {
+        let Some(donors) = sample_others_rng(self.mems.len(), idx, 3, r) else {
+            return Err(eyre!("differential crossover needs at least 4 members"));
+        };
+        let donors = [0, 1, 2].map(|i| &*self.mems[donors[i]].state);
+        eval.differential_rng(Arc::make_mut(&mut s.state), donors, f, cr, r)
+    }
+
+    // Returns the crossover operator applied, if any. Differential evolution
+    // isn't one of the evaluator's operators, so is never reported.
     fn crossover<E: Evaluator<State = S>>(
         &self,
-        crossover: &Crossover,
+        cfg: &EvolveCfg,
         eval: &E,
         s1: &mut Member<S>,
         s2: &mut Member<S>,
-    ) -> Result<()> {
-        match crossover {
+        parent_idxs: [usize; 2],
+        r: &mut dyn RngCore,
+    ) -> Result<Option<usize>> {
+        // Recombine params before self-adapting them.
+        Params::crossover_rng(&mut s1.params, &mut s2.params, cfg.params_crossover, r);
+        let first = usize::from(E::CROSSOVER_HAS_NOOP);
+        match &cfg.crossover {
+            &Crossover::Differential { f, cr } => {
+                for (s, idx) in [(s1, parent_idxs[0]), (s2, parent_idxs[1])] {
+                    if cfg.crossover_probability.is_none_or(|p| r.gen_bool(p.clamp(0.0, 1.0))) {
+                        self.differential(f, cr, eval, s, idx, r)?;
+                    }
+                }
+                return Ok(None);
+            }
+            // No real operators to choose between, so leave the weights alone.
+            _ if E::NUM_CROSSOVER <= first => return Ok(None),
             Crossover::Fixed(rates) => {
                 s1.params.crossover = rates.clone();
                 s2.params.crossover = rates.clone();
             }
             Crossover::Adaptive => {
                 let lrate = 1.0 / (self.mems.len() as f64).sqrt();
-                mutate_rate(&mut s1.params.crossover, 1.0, |v| mutate_normal(v, lrate).max(0.0));
-                mutate_rate(&mut s2.params.crossover, 1.0, |v| mutate_normal(v, lrate).max(0.0));
+                let f = |v, r: &mut _| mutate_normal_rng(v, lrate, r).max(0.0);
+                mutate_rate_rng(&mut s1.params.crossover, 1.0, f, r);
+                mutate_rate_rng(&mut s2.params.crossover, 1.0, f, r);
             }
         };
         Self::check_weights(&s1.params.crossover, E::NUM_CROSSOVER)?;
         Self::check_weights(&s2.params.crossover, E::NUM_CROSSOVER)?;
-        let idx = rws(&s1.params.crossover).unwrap();
-        eval.crossover(&mut s1.state, &mut s2.state, idx);
-        Ok(())
+        let idx = if let Some(p) = cfg.crossover_probability {
+            // Weights only choose between the real operators.
+            if !r.gen_bool(p.clamp(0.0, 1.0)) {
+                return Ok(None);
+            }
+            let Some(idx) = rws_rng(&s1.params.crossover[first..], r) else { return Ok(None) };
+            idx + first
+        } else {
+            let Some(idx) = rws_rng(&s1.params.crossover, r) else { return Ok(None) };
+            idx
+        };
+        // Choosing the no-op is the same as skipping crossover, so it isn't
+        // recorded as an operator being applied.
+        if idx < first {
+            return Ok(None);
+        }
+        eval.crossover_rng(Arc::make_mut(&mut s1.state), Arc::make_mut(&mut s2.state), idx, r);
+        Ok(Some(idx))
     }
 
     fn mutation<E: Evaluator<State = S>>(
@@ -145,7 +278,11 @@ impl<S: State> EvaluatedGen<S> {
         mutation: &Mutation,
         eval: &E,
         s: &mut Member<S>,
+        r: &mut dyn RngCore,
     ) -> Result<()> {
+        if E::NUM_MUTATION == 0 {
+            return Ok(());
+        }
         match mutation {
             Mutation::Fixed(rates) => {
                 s.params.mutation = rates.clone();
@@ -154,63 +291,982 @@ impl<S: State> EvaluatedGen<S> {
                 // Apply every mutation with the given rate.
                 // c' = c * e^(learning rate * N(0, 1))
                 let lrate = 1.0 / (self.mems.len() as f64).sqrt();
-                mutate_rate(&mut s.params.mutation, 1.0, |v| {
-                    mutate_lognorm(v, lrate).clamp(0.0, 1.0)
-                });
+                let f = |v, r: &mut _| mutate_lognorm_rng(v, lrate, r).clamp(0.0, 1.0);
+                mutate_rate_rng(&mut s.params.mutation, 1.0, f, r);
             }
         };
         Self::check_weights(&s.params.mutation, E::NUM_MUTATION)?;
         for (idx, &rate) in s.params.mutation.iter().enumerate() {
-            eval.mutate(&mut s.state, rate, idx);
+            eval.mutate_rng(Arc::make_mut(&mut s.state), rate, idx, r);
         }
         Ok(())
     }
 
+    // Breeds two children from the members at |parent_idxs|, and records them
+    // in |trace| and |bred| if they are set.
+    fn breed<E: Evaluator<State = S>>(
+        &self,
+        cfg: &EvolveCfg,
+        eval: &E,
+        parent_idxs: [usize; 2],
+        trace: &mut Option<Vec<BreedingEvent>>,
+        bred: &mut Option<Vec<(MemberId, f64)>>,
+        r: &mut dyn RngCore,
+    ) -> Result<[Member<S>; 2]> {
+        let [mut s1, mut s2] = parent_idxs.map(|idx| self.mems[idx].clone());
+        let crossover = self.crossover(cfg, eval, &mut s1, &mut s2, parent_idxs, r)?;
+        self.mutation(&cfg.mutation, eval, &mut s1, r)?;
+        self.mutation(&cfg.mutation, eval, &mut s2, r)?;
+        let parent_states = parent_idxs.map(|idx| &self.mems[idx].state);
+        for child in [&mut s1, &mut s2] {
+            // Children still sharing a parent's state are unchanged copies.
+            if !parent_states.iter().any(|v| Arc::ptr_eq(v, &child.state)) {
+                eval.repair_child(Arc::make_mut(&mut child.state), parent_states.map(|v| &**v));
+            }
+        }
+        let parents = [s1.id, s2.id];
+        s1.id = next_member_id();
+        s2.id = next_member_id();
+        let parent_fitness = parent_idxs.map(|idx| self.mems[idx].fitness);
+        let better = parent_fitness[0].max(parent_fitness[1]);
+        if let Some(bred) = bred {
+            bred.extend([(s1.id, better), (s2.id, better)]);
+        }
+        if let Some(trace) = trace {
+            trace.push(BreedingEvent {
+                parents,
+                parent_idxs,
+                parent_fitness,
+                crossover,
+                crossover_weights: s1.params.crossover.clone(),
+                crossover_has_noop: E::CROSSOVER_HAS_NOOP,
+                mutation_rates: [s1.params.mutation.clone(), s2.params.mutation.clone()],
+                children: [s1.id, s2.id],
+            });
+        }
+        Ok([s1, s2])
+    }
+
+    // Index of the best member of each species, in order of species id.
+    fn species_bests(&self) -> Vec<usize> {
+        self.species()
+            .into_iter()
+            .filter(|&id| id != NO_SPECIES)
+            .filter_map(|id| self.mems.iter().position(|v| v.species == id))
+            .collect()
+    }
+
+    /// Breeds the next generation. |inputs| are only used for
+    /// `EvolveCfg::local_search`.
     pub fn next_gen<E: Evaluator<State = S>>(
         &self,
         genfn: &mut (dyn RandState<S> + '_),
         stagnant: bool,
+        gen_idx: usize,
+        inputs: &[E::Data],
+        cfg: &EvolveCfg,
+        eval: &E,
+    ) -> Result<UnevaluatedGen<S>> {
+        self.next_gen_rng(genfn, stagnant, gen_idx, inputs, cfg, eval, &mut rand::thread_rng())
+    }
+
+    /// Like `next_gen`, but draws every random choice made while breeding
+    /// from |r|, including the `Evaluator::crossover_rng` and
+    /// `Evaluator::mutate_rng` operators.
+    #[allow(clippy::too_many_arguments)]
+    pub fn next_gen_rng<E: Evaluator<State = S>>(
+        &self,
+        genfn: &mut (dyn RandState<S> + '_),
+        stagnant: bool,
+        gen_idx: usize,
+        inputs: &[E::Data],
         cfg: &EvolveCfg,
         eval: &E,
+        r: &mut dyn RngCore,
     ) -> Result<UnevaluatedGen<S>> {
-        // Pick survivors:
-        let mut new_mems = self.survivors(cfg.survival, cfg);
+        // Pick elites, then survivors which aren't elites. Elites come first,
+        // and are kept by replacement and duplicate removal. There are never
+        // more elites than the population holds.
+        let mut new_mems = self.elites(cfg.elitism.min(cfg.pop_size));
+        let elite_ids = new_mems.iter().map(|v| v.id).collect::<Vec<_>>();
+        let is_elite = |mem: &Member<S>| elite_ids.contains(&mem.id);
+        let survivors = self.survivors(cfg.survival, gen_idx, cfg, r);
+        new_mems.extend(survivors.into_iter().filter(|v| !is_elite(v)));
+        if !elite_ids.is_empty() {
+            // Elites take the place of the last survivors if there isn't room.
+            new_mems.truncate(cfg.pop_size);
+        }
         // Min here to avoid underflow - can happen if we produce too many parents.
         new_mems.reserve(cfg.pop_size);
 
-        // If stagnant, fill with random individuals.
+        // If stagnant, fill with random individuals or hybrids.
+        let mut injected = 0;
+        let mut contests = Vec::new();
+        let mut hybrids = 0;
+        let mut trace = cfg.trace.then(Vec::new);
+        // Children bred since local search last ran, with the fitness of their
+        // better parent. Only kept for local search.
+        let mut bred = cfg.local_search.is_some().then(Vec::new);
         if stagnant {
-            let num = match cfg.replacement {
+            injected = match cfg.replacement {
                 Replacement::ReplaceChildren(prop) => {
                     let remaining = cfg.pop_size as f64 - new_mems.len() as f64;
                     (prop * remaining).ceil().max(0.0) as usize
                 }
+                Replacement::ReplaceWorst(prop) => {
+                    let room = cfg.pop_size.saturating_sub(elite_ids.len());
+                    let num = ((prop * cfg.pop_size as f64).ceil() as usize).min(room);
+                    // Make room by dropping the worst survivors. Stable, so
+                    // elites stay ahead of survivors with equal fitness.
+                    new_mems.sort_by(|a, b| b.fitness.partial_cmp(&a.fitness).unwrap());
+                    new_mems.truncate(cfg.pop_size - num);
+                    num
+                }
+                Replacement::HybridizeSpecies { pairs } => {
+                    let bests = self.species_bests();
+                    let remaining = cfg.pop_size.saturating_sub(new_mems.len());
+                    if bests.len() < 2 {
+                        (2 * pairs).min(remaining)
+                    } else {
+                        for _ in 0..pairs {
+                            if new_mems.len() >= cfg.pop_size {
+                                break;
+                            }
+                            // Two distinct species.
+                            let a = r.gen_range(0..bests.len());
+                            let b = (a + r.gen_range(1..bests.len())) % bests.len();
+                            let parent_idxs = [bests[a], bests[b]];
+                            let children =
+                                self.breed(cfg, eval, parent_idxs, &mut trace, &mut bred, r)?;
+                            new_mems.extend(children);
+                            hybrids += 2;
+                        }
+                        0
+                    }
+                }
             };
-            for _ in 0..num {
-                new_mems.push(Member::new::<E>((*genfn)(), cfg));
-            }
+            Self::immigrants(genfn, injected, cfg, eval, &mut new_mems, &mut contests, r)?;
         }
 
-        // If DisallowDuplicates on, try up to NUM_TRIES times
-        // to fill the population up.
+        // If duplicates are disallowed, try up to NUM_TRIES times to fill the
+        // population up, stopping once a pass removes nothing. Allowing
+        // duplicates never compares states.
         const NUM_TRIES: usize = 3;
+        let mut dups_removed = 0;
+        // Differential evolution targets members in turn from the first slot
+        // left to fill, so with top proportion survival each member either
+        // survives or has exactly one trial.
+        let mut target = new_mems.len();
+        let fitness = |s: &S, data: &E::Data| eval.fitness(s, data);
+        let evals = FitnessEvals::new(&fitness);
+        let mut local_searched = 0;
         for _ in 0..NUM_TRIES {
             // Reproduce.
-            while new_mems.len() < cfg.pop_size {
-                let [mut s1, mut s2] = self.selection(cfg.selection);
-                self.crossover(&cfg.crossover, eval, &mut s1, &mut s2).unwrap();
-                self.mutation(&cfg.mutation, eval, &mut s1).unwrap();
-                self.mutation(&cfg.mutation, eval, &mut s2).unwrap();
-                new_mems.push(s1);
-                new_mems.push(s2);
+            while new_mems.len() + contests.len() < cfg.pop_size {
+                if let Crossover::Differential { f, cr } = cfg.crossover {
+                    let idx = target % self.mems.len();
+                    target += 1;
+                    contests.push(self.trial(f, cr, idx, cfg, eval, r)?);
+                    continue;
+                }
+                let parent_idxs = self.selection_idxs(cfg.selection, r);
+                new_mems.extend(self.breed(cfg, eval, parent_idxs, &mut trace, &mut bred, r)?);
+            }
+
+            // Search before removing duplicates, so duplicates local search
+            // creates are removed too.
+            if let (Some(ls), Some(bred)) = (&cfg.local_search, &mut bred) {
+                local_searched +=
+                    Self::local_search(ls, &mut new_mems, bred, inputs, eval, &evals, r)?;
+                bred.clear();
             }
 
             // Remove duplicates if we need to.
-            if cfg.duplicates == Duplicates::DisallowDuplicates {
-                new_mems.sort_unstable_by(|a, b| a.state.partial_cmp(&b.state).unwrap());
-                new_mems.dedup_by(|a, b| a.state.eq(&b.state));
+            let before = new_mems.len();
+            match cfg.duplicates {
+                Duplicates::AllowDuplicates => break,
+                Duplicates::DisallowDuplicates => {
+                    // Elites first among equal states, so they're never removed.
+                    new_mems.sort_unstable_by(|a, b| {
+                        a.state
+                            .partial_cmp(&b.state)
+                            .unwrap()
+                            .then_with(|| is_elite(b).cmp(&is_elite(a)))
+                    });
+                    new_mems.dedup_by(|a, b| a.state.eq(&b.state) && !is_elite(a));
+                }
+                Duplicates::DisallowWithinDistance(radius) => {
+                    new_mems =
+                        Self::dedup_within(new_mems, &elite_ids, radius, cfg.par_dist, eval)?;
+                }
+            }
+            dups_removed += before - new_mems.len();
+            if new_mems.len() == before {
+                break;
+            }
+        }
+        let mut gen = UnevaluatedGen { mems: new_mems, contests, ..UnevaluatedGen::empty() };
+        gen.local_searched = local_searched;
+        gen.local_search_evals = evals.get();
+        gen.injected = injected;
+        gen.hybrids = hybrids;
+        gen.dups_removed = dups_removed;
+        gen.trace = trace;
+        Ok(gen)
+    }
+
+    // Differential evolution trial for the member at |idx|, as a contest which
+    // the trial wins only if at least as good once evaluated. Otherwise the
+    // member itself is kept, aged like a survivor.
+    fn trial<E: Evaluator<State = S>>(
+        &self,
+        f: f64,
+        cr: f64,
+        idx: usize,
+        cfg: &EvolveCfg,
+        eval: &E,
+        r: &mut dyn RngCore,
+    ) -> Result<Contest<Member<S>>> {
+        if cfg.comparison != Comparison::None {
+            return Err(eyre!("differential evolution doesn't support comparative fitness"));
+        }
+        let parent = &self.mems[idx];
+        let mut trial = parent.clone();
+        self.differential(f, cr, eval, &mut trial, idx, r)?;
+        self.mutation(&cfg.mutation, eval, &mut trial, r)?;
+        eval.repair_child(Arc::make_mut(&mut trial.state), [&parent.state, &parent.state]);
+        trial.id = next_member_id();
+        let mut kept = parent.clone();
+        kept.age += 1;
+        Ok(Contest { kind: ContestKind::Trial, mems: vec![kept, trial] })
+    }
+
+    // Adds |num| random individuals to inject to |mems|. With
+    // |cfg.replacement_filter| they're added to |contests| instead, as contests
+    // between candidates which are settled once evaluated.
+    fn immigrants<E: Evaluator<State = S>>(
+        genfn: &mut (dyn RandState<S> + '_),
+        num: usize,
+        cfg: &EvolveCfg,
+        eval: &E,
+        mems: &mut Vec<Member<S>>,
+        contests: &mut Vec<Contest<Member<S>>>,
+        r: &mut dyn RngCore,
+    ) -> Result<()> {
+        let mut rand_mem = || -> Result<Member<S>> {
+            let s = rand_valid_state(eval, genfn, "replacement")?;
+            Ok(Member::new_rng::<E, _>(s, cfg, r))
+        };
+        let ReplacementFilter::BeatWorst { attempts } = cfg.replacement_filter else {
+            for _ in 0..num {
+                mems.push(rand_mem()?);
+            }
+            return Ok(());
+        };
+        if cfg.comparison != Comparison::None {
+            return Err(eyre!("replacement filter doesn't support comparative fitness"));
+        }
+        for _ in 0..num {
+            let mems = (0..attempts.max(1)).map(|_| rand_mem()).collect::<Result<_>>()?;
+            contests.push(Contest { kind: ContestKind::BeatWorst, mems });
+        }
+        Ok(())
+    }
+
+    // Applies local search to |ls.fraction| of the children in |mems|, which
+    // are the members in |bred|. Returns the number of children searched.
+    fn local_search<E: Evaluator<State = S>>(
+        ls: &LocalSearchCfg,
+        mems: &mut [Member<S>],
+        bred: &[(MemberId, f64)],
+        inputs: &[E::Data],
+        eval: &E,
+        evals: &FitnessEvals<'_, S, E::Data>,
+        r: &mut dyn RngCore,
+    ) -> Result<usize> {
+        let parent_fitness = bred.iter().copied().collect::<HashMap<_, _>>();
+        let mut children = mems
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| parent_fitness.get(&v.id).map(|&f| (i, f)))
+            .collect::<Vec<_>>();
+        let num = (ls.fraction.clamp(0.0, 1.0) * children.len() as f64).round() as usize;
+        match ls.policy {
+            LocalSearchPolicy::BestParents => children.sort_by(|a, b| b.1.total_cmp(&a.1)),
+            LocalSearchPolicy::Random => children.shuffle(r),
+        }
+        for &(i, _) in &children[..num] {
+            eval.local_search(Arc::make_mut(&mut mems[i].state), inputs, ls.max_iters, evals)?;
+        }
+        Ok(num)
+    }
+
+    // Greedily keeps members, fittest first, which are at least |radius| away
+    // from every member kept so far. Members in |elite_ids| are always kept.
+    fn dedup_within<E: Evaluator<State = S>>(
+        mut mems: Vec<Member<S>>,
+        elite_ids: &[MemberId],
+        radius: f64,
+        par: bool,
+        eval: &E,
+    ) -> Result<Vec<Member<S>>> {
+        // Stable sort so survivors stay ahead of children with equal fitness.
+        mems.sort_by(|a, b| b.fitness.partial_cmp(&a.fitness).unwrap());
+        let mut kept: Vec<Member<S>> = Vec::with_capacity(mems.len());
+        for mem in mems {
+            let close = try_any(&kept, par, |k| Ok(eval.distance(&k.state, &mem.state)? < radius))?;
+            if !close || elite_ids.contains(&mem.id) {
+                kept.push(mem);
+            }
+        }
+        Ok(kept)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::evolve::cfg::{Species, Stagnation};
+    use crate::evolve::evolver::Evolver;
+    use crate::evolve::result::Stats;
+    use crate::gen::trace::format_trace;
+    use crate::util::bench_utils::CountEvaluator;
+    use crate::util::test_utils::MockEvaluator;
+
+    // Real valued genome where mutation only nudges the value slightly.
+    fn nudge() -> MockEvaluator<f64> {
+        MockEvaluator::with(|s, ()| Ok(*s), |s1: &f64, s2| Ok((s1 - s2).abs()))
+            .set_mutate(|s, _, _| *s += 1e-9)
+    }
+
+    // Runs a few generations and returns the final population and the
+    // number of duplicates removed in the last generation.
+    fn run(duplicates: Duplicates) -> Result<(Vec<f64>, usize)> {
+        let cfg = EvolveCfg::new(20).set_duplicates(duplicates);
+        let mut evolver = Evolver::new(nudge(), cfg, || rand::thread_rng().gen::<f64>());
+        let mut removed = 0;
+        for _ in 0..5 {
+            removed = Stats::from_result(&evolver.run()?).dups_removed;
+        }
+        let r = evolver.run()?;
+        Ok((r.gen.mems.iter().map(|m| *m.state).collect(), removed))
+    }
+
+    fn min_dist(v: &[f64]) -> f64 {
+        let mut min = f64::INFINITY;
+        for i in 0..v.len() {
+            for j in (i + 1)..v.len() {
+                min = min.min((v[i] - v[j]).abs());
             }
         }
-        Ok(UnevaluatedGen::new(new_mems))
+        min
+    }
+
+    // Base fitness orders members 0 to 5 best first, and selection fitness
+    // the other way round. Members 0-2 are species 0 and 3-5 species 1, and
+    // ages are 0, 0, 1, 1, 2, 2.
+    fn divergent_gen() -> EvaluatedGen<usize> {
+        let mems = (0..6_usize)
+            .map(|i| {
+                let mut mem = Member::new::<CountEvaluator>(i, &EvolveCfg::new(1));
+                mem.fitness = (6 - i) as f64;
+                mem.selection_fitness = (i + 1) as f64;
+                mem.species = (i / 3) as SpeciesId;
+                mem.age = i / 2;
+                mem
+            })
+            .collect();
+        EvaluatedGen::new(mems)
+    }
+
+    #[test]
+    fn survival_fitness() {
+        let gen = divergent_gen();
+        let survivors = |survival, pop_size, survival_fitness| {
+            let cfg = EvolveCfg::new(pop_size).set_survival_fitness(survival_fitness);
+            let survivors = gen.survivors(survival, 0, &cfg, &mut rand::thread_rng());
+            survivors.iter().map(|v| *v.state).collect::<Vec<_>>()
+        };
+        let boltzmann = Survival::Boltzmann { initial_temp: 1e-9, decay: 1.0, prop: 0.5 };
+        let cases: [(Survival, usize, &[usize], &[usize]); 4] = [
+            (Survival::TopProportion(0.5), 6, &[0, 1, 2], &[5, 4, 3]),
+            // Two of each species.
+            (Survival::SpeciesTopProportion(0.5), 6, &[0, 1, 3, 4], &[2, 1, 5, 4]),
+            // Members of the same age are kept in order of fitness.
+            (Survival::Youngest, 3, &[0, 1, 2], &[1, 0, 3]),
+            // Low enough temperature to keep members in order of fitness.
+            (boltzmann, 6, &[0, 1, 2], &[5, 4, 3]),
+        ];
+        for (survival, pop_size, base, shared) in cases {
+            assert_eq!(survivors(survival, pop_size, SurvivalFitness::Base), base, "{survival:?}");
+            assert_eq!(
+                survivors(survival, pop_size, SurvivalFitness::Shared),
+                shared,
+                "{survival:?}"
+            );
+        }
+
+        // With everyone in every tournament, members are ordered by losses, or
+        // by wins when counting them.
+        let tournament = Survival::Tournament(6);
+        assert_eq!(survivors(tournament, 6, SurvivalFitness::Base), [5, 4, 3, 2, 1, 0]);
+        assert_eq!(survivors(tournament, 6, SurvivalFitness::Shared), [0, 1, 2, 3, 4, 5]);
+        let tournament = Survival::TournamentWins(6);
+        assert_eq!(survivors(tournament, 6, SurvivalFitness::Base), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(survivors(tournament, 6, SurvivalFitness::Shared), [5, 4, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn tournament_selection() {
+        let gen = divergent_gen();
+        let mut r = rand::thread_rng();
+        let fitness = |idx: usize| gen.mems[idx].selection_fitness;
+        let best = (0..gen.mems.len()).max_by(|&a, &b| fitness(a).total_cmp(&fitness(b))).unwrap();
+        // Tournaments larger than the population hold everyone, so the member
+        // with the best selection fitness always wins.
+        for _ in 0..100 {
+            assert_eq!(gen.selection_idxs(Selection::Tournament(100), &mut r), [best, best]);
+        }
+        // Size 1 is uniform, so eventually everyone is picked.
+        let mut picked = vec![false; gen.mems.len()];
+        for _ in 0..1000 {
+            for idx in gen.selection_idxs(Selection::Tournament(1), &mut r) {
+                picked[idx] = true;
+            }
+        }
+        assert!(picked.iter().all(|&v| v));
+    }
+
+    #[test]
+    fn near_duplicates() -> Result<()> {
+        // Children differ from their parents by 1e-9, so exact dedup keeps them.
+        let (exact, _) = run(Duplicates::DisallowDuplicates)?;
+        assert!(min_dist(&exact) < 1e-6);
+
+        let (near, removed) = run(Duplicates::DisallowWithinDistance(1e-6))?;
+        assert!(min_dist(&near) >= 1e-6);
+        assert!(removed > 0);
+        Ok(())
+    }
+
+    // Three crossover operators, with index 0 being the no-op if |NOOP|.
+    type SpyEvaluator<const NOOP: bool> = MockEvaluator<usize, (), 3, 1, NOOP>;
+
+    // Fraction of |n| crossovers which applied each operator.
+    fn crossover_freqs<const NOOP: bool>(cfg: &EvolveCfg, n: usize) -> Result<Vec<f64>> {
+        let applied = Arc::new(Mutex::new(vec![0; 3]));
+        let spy = Arc::clone(&applied);
+        let eval = SpyEvaluator::<NOOP>::default()
+            .set_crossover(move |_, _, idx| spy.lock().unwrap()[idx] += 1);
+        let gen = EvaluatedGen::new(vec![Member::new::<SpyEvaluator<NOOP>>(0, cfg); 10]);
+        for _ in 0..n {
+            let idxs = gen.selection_idxs(cfg.selection, &mut rand::thread_rng());
+            let [mut s1, mut s2] = idxs.map(|idx| gen.mems[idx].clone());
+            let _ = gen.crossover(cfg, &eval, &mut s1, &mut s2, idxs, &mut rand::thread_rng())?;
+        }
+        let applied = applied.lock().unwrap().clone();
+        Ok(applied.into_iter().map(|v| v as f64 / n as f64).collect())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn crossover_probability() -> Result<()> {
+        const N: usize = 20000;
+        const TOL: f64 = 0.02;
+        // Without a probability, index 0 is chosen by weight like any other,
+        // but the no-op is never passed to the evaluator.
+        let cfg = EvolveCfg::new(10).set_crossover(Crossover::Fixed(vec![1.0, 1.0, 2.0]));
+        let freqs = crossover_freqs::<true>(&cfg, N)?;
+        for (freq, expected) in freqs.iter().zip([0.0, 0.25, 0.5]) {
+            assert!((freq - expected).abs() < TOL, "{freqs:?}");
+        }
+        let freqs = crossover_freqs::<false>(&cfg, N)?;
+        for (freq, expected) in freqs.iter().zip([0.25, 0.25, 0.5]) {
+            assert!((freq - expected).abs() < TOL, "{freqs:?}");
+        }
+
+        // With a probability, the weight of the no-op is ignored.
+        for p in [0.0, 0.3, 0.7, 1.0] {
+            for crossover in
+                [Crossover::Fixed(vec![5.0, 1.0, 3.0]), Crossover::Fixed(vec![0.0, 1.0, 3.0])]
+            {
+                let cfg =
+                    EvolveCfg::new(10).set_crossover(crossover).set_crossover_probability(Some(p));
+                let freqs = crossover_freqs::<true>(&cfg, N)?;
+                assert!(freqs[0] == 0.0, "{freqs:?}");
+                assert!((freqs[1] + freqs[2] - p).abs() < TOL, "{freqs:?}");
+                assert!((freqs[1] - p * 0.25).abs() < TOL, "{freqs:?}");
+            }
+        }
+
+        // Without a no-op, index 0 is a real operator chosen by weight.
+        let cfg = EvolveCfg::new(10)
+            .set_crossover(Crossover::Fixed(vec![2.0, 1.0, 1.0]))
+            .set_crossover_probability(Some(0.5));
+        let freqs = crossover_freqs::<false>(&cfg, N)?;
+        for (freq, expected) in freqs.iter().zip([0.25, 0.125, 0.125]) {
+            assert!((freq - expected).abs() < TOL, "{freqs:?}");
+        }
+        Ok(())
+    }
+
+    // Evaluator whose differential evolution replaces the target with the
+    // digits of its donors, so they can be inspected.
+    fn donor() -> MockEvaluator {
+        MockEvaluator::new()
+            .set_crossover(|_, _, _| panic!("crossover called with differential evolution"))
+            .set_differential(|target, donors, _, _| {
+                *target = donors.iter().fold(0, |acc, &&v| acc * 10 + v);
+                Ok(())
+            })
+    }
+
+    #[test]
+    fn differential_donors() -> Result<()> {
+        let cfg = EvolveCfg::new(6).set_crossover(Crossover::Differential { f: 0.5, cr: 0.9 });
+        let mems = (0..6).map(|v| Member::new::<MockEvaluator>(v, &cfg));
+        let gen = EvaluatedGen::from_ranked(mems.collect());
+        let mut r = StdRng::seed_from_u64(0);
+        let eval = donor();
+        for _ in 0..200 {
+            let idxs = [r.gen_range(0..6), r.gen_range(0..6)];
+            let [mut s1, mut s2] = idxs.map(|idx| gen.mems[idx].clone());
+            assert_eq!(gen.crossover(&cfg, &eval, &mut s1, &mut s2, idxs, &mut r)?, None);
+            for (s, idx) in [(s1, idxs[0]), (s2, idxs[1])] {
+                let mut donors = [*s.state / 100, *s.state / 10 % 10, *s.state % 10];
+                assert!(!donors.contains(&idx), "{donors:?} contains parent {idx}");
+                donors.sort_unstable();
+                assert!(donors[0] < donors[1] && donors[1] < donors[2], "{donors:?}");
+            }
+        }
+
+        // Too few members to pick donors from.
+        let small = EvaluatedGen::from_ranked(gen.mems[..3].to_vec());
+        let [mut s1, mut s2] = [small.mems[0].clone(), small.mems[1].clone()];
+        assert!(small.crossover(&cfg, &eval, &mut s1, &mut s2, [0, 1], &mut r).is_err());
+        // Evaluators without differential evolution fail.
+        let eval = MockEvaluator::new();
+        let gen = EvaluatedGen::new(vec![Member::new::<MockEvaluator>(0, &cfg); 6]);
+        let [mut s1, mut s2] = [gen.mems[0].clone(), gen.mems[1].clone()];
+        assert!(gen.crossover(&cfg, &eval, &mut s1, &mut s2, [0, 1], &mut r).is_err());
+        Ok(())
+    }
+
+    // Evaluator whose differential evolution trials are always fitter than
+    // their parent if |better|, and never otherwise. Fitness is the state.
+    fn trial(better: bool) -> MockEvaluator {
+        MockEvaluator::new().set_differential(move |target, _, _, _| {
+            *target = if better { 1000 } else { 0 };
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn elites_prefer_feasible() {
+        let cfg = EvolveCfg::new(4);
+        let mems = [(4.0, 1.0), (3.0, 0.0), (2.0, 2.0), (1.0, 0.0)].map(|(fitness, violation)| {
+            let mut mem = Member::new::<MockEvaluator>(0, &cfg);
+            mem.fitness = fitness;
+            mem.violation = violation;
+            mem
+        });
+        let gen = EvaluatedGen::new(mems.to_vec());
+        let fitness = |n| gen.elites(n).iter().map(|v| v.fitness).collect::<Vec<_>>();
+        // The fittest member is infeasible, so is passed over.
+        assert_eq!(fitness(2), [3.0, 1.0]);
+        // Less infeasible members come first once feasible ones run out.
+        assert_eq!(fitness(4), [3.0, 1.0, 4.0, 2.0]);
+    }
+
+    #[test]
+    fn differential_greedy_replacement() -> Result<()> {
+        let cfg = EvolveCfg::new(10)
+            .set_crossover(Crossover::Differential { f: 0.5, cr: 0.9 })
+            .set_duplicates(Duplicates::AllowDuplicates);
+        let mems = (1..=10).map(|v| {
+            let mut mem = Member::new::<MockEvaluator>(v, &cfg);
+            mem.fitness = v as f64;
+            mem
+        });
+        let gen = EvaluatedGen::new(mems.collect());
+        let mut r = StdRng::seed_from_u64(0);
+
+        // The two survivors, then every other member kept over its worse trial.
+        // Trials and their members are both evaluated once, when settled.
+        let eval = trial(false);
+        let mut next = gen.next_gen_rng(&mut || 1, false, 0, &[()], &cfg, &eval, &mut r)?;
+        assert_eq!((next.mems.len(), next.contests.len()), (2, 8));
+        let evaluated = next.evaluate(&[()], &cfg, &eval)?;
+        let mut states = evaluated.mems.iter().map(|v| *v.state).collect::<Vec<_>>();
+        states.sort_unstable();
+        assert_eq!(states, (1..=10).collect::<Vec<_>>());
+        assert_eq!((next.fitness_evals, next.trial_evals), (2, 16));
+
+        // Fitter trials replace their parent.
+        let eval = trial(true);
+        let mut next = gen.next_gen_rng(&mut || 1, false, 0, &[()], &cfg, &eval, &mut r)?;
+        let evaluated = next.evaluate(&[()], &cfg, &eval)?;
+        let states = evaluated.mems.iter().map(|v| *v.state).collect::<Vec<_>>();
+        assert_eq!(states, [1000, 1000, 1000, 1000, 1000, 1000, 1000, 1000, 10, 9]);
+        Ok(())
+    }
+
+    // Evaluator with |C| crossover and |M| mutation operators, which panics
+    // if crossover is called.
+    type FewOpsEvaluator<const C: usize, const M: usize> = MockEvaluator<usize, (), C, M>;
+
+    fn few_ops<const C: usize, const M: usize>() -> FewOpsEvaluator<C, M> {
+        FewOpsEvaluator::default()
+            .set_crossover(|_, _, _| panic!("crossover called with {C} operators"))
+            .set_mutate(|s, _, idx| {
+                assert!(idx < M, "mutation {idx} out of range");
+                *s += 1;
+            })
+    }
+
+    fn skips_crossover<const C: usize>(cfg: &EvolveCfg) -> Result<()> {
+        let eval = few_ops::<C, 1>();
+        let gen = EvaluatedGen::new(vec![Member::new::<FewOpsEvaluator<C, 1>>(0, cfg); 10]);
+        let [mut s1, mut s2] = [gen.mems[0].clone(), gen.mems[1].clone()];
+        let weights = s1.params.crossover.clone();
+        assert_eq!(weights.len(), C);
+        for _ in 0..100 {
+            let r = &mut rand::thread_rng();
+            assert_eq!(gen.crossover(cfg, &eval, &mut s1, &mut s2, [0, 1], r)?, None);
+        }
+        assert_eq!(s1.params.crossover, weights);
+        Ok(())
+    }
+
+    #[test]
+    fn few_crossover_operators() -> Result<()> {
+        let cfg = EvolveCfg::new(10).set_crossover(Crossover::Adaptive);
+        for cfg in [cfg.clone(), cfg.set_crossover_probability(Some(1.0))] {
+            skips_crossover::<0>(&cfg)?;
+            skips_crossover::<1>(&cfg)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn no_mutation_operators() -> Result<()> {
+        let cfg = EvolveCfg::new(10).set_mutation(Mutation::Adaptive);
+        let gen = EvaluatedGen::new(vec![Member::new::<FewOpsEvaluator<0, 0>>(0, &cfg); 10]);
+        let mut s = gen.mems[0].clone();
+        assert!(s.params.mutation.is_empty());
+        gen.mutation(&cfg.mutation, &few_ops::<0, 0>(), &mut s, &mut rand::thread_rng())?;
+        assert!(s.params.mutation.is_empty());
+        assert_eq!(*s.state, 0);
+        Ok(())
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Call {
+        Crossover([usize; 2], usize),
+        Mutate(usize, f64, usize),
+    }
+
+    // Three crossover and two mutation operators.
+    type LogEvaluator = MockEvaluator<usize, (), 3, 2>;
+
+    // Records every operator call, in order, in the returned log.
+    fn log_eval() -> (LogEvaluator, Arc<Mutex<Vec<Call>>>) {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let (crossovers, mutations) = (Arc::clone(&calls), Arc::clone(&calls));
+        let eval = LogEvaluator::default()
+            .set_crossover(move |s1, s2, idx| {
+                crossovers.lock().unwrap().push(Call::Crossover([*s1, *s2], idx));
+            })
+            .set_mutate(move |s, rate, idx| {
+                mutations.lock().unwrap().push(Call::Mutate(*s, rate, idx));
+            });
+        (eval, calls)
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn trace_matches_operator_calls() -> Result<()> {
+        const POP: usize = 20;
+        let cfg = EvolveCfg::new(POP)
+            .set_crossover(Crossover::Adaptive)
+            .set_crossover_probability(Some(0.5))
+            .set_mutation(Mutation::Adaptive)
+            .set_duplicates(Duplicates::AllowDuplicates)
+            .set_trace(true);
+        let (eval, calls) = log_eval();
+        let gen = EvaluatedGen::new(
+            (0..POP)
+                .map(|v| {
+                    let mut mem = Member::new::<LogEvaluator>(v, &cfg);
+                    mem.fitness = v as f64;
+                    mem.selection_fitness = v as f64;
+                    mem
+                })
+                .collect(),
+        );
+        let next = gen.next_gen(&mut || 0, false, 0, &[()], &cfg, &eval)?;
+        let trace = next.trace.as_ref().unwrap();
+        assert!(!trace.is_empty());
+
+        // Replay the trace and check it against what the evaluator saw.
+        let mut expected = vec![];
+        for ev in trace {
+            let parents = ev.parent_idxs.map(|idx| &gen.mems[idx]);
+            assert_eq!(ev.parents, parents.map(|m| m.id));
+            assert_eq!(ev.parent_fitness, parents.map(|m| m.fitness));
+            let states = parents.map(|m| *m.state);
+            if let Some(idx) = ev.crossover {
+                expected.push(Call::Crossover(states, idx));
+            }
+            for (state, rates) in states.iter().zip(&ev.mutation_rates) {
+                for (idx, &rate) in rates.iter().enumerate() {
+                    expected.push(Call::Mutate(*state, rate, idx));
+                }
+            }
+        }
+        assert_eq!(*calls.lock().unwrap(), expected);
+
+        // Every child made it into the new generation under its recorded id.
+        for ev in trace {
+            for id in ev.children {
+                assert!(next.mems.iter().any(|m| m.id == id));
+            }
+        }
+
+        // Children are pushed after survivors, so the last member was bred.
+        let summary = format_trace(trace, &next.mems[POP - 1..], 1);
+        assert!(summary.contains("parents"), "{summary}");
+        assert!(summary.contains("mutation rates"), "{summary}");
+
+        // The no-op is never recorded as applied.
+        assert!(trace.iter().all(|ev| ev.crossover != Some(0)));
+
+        // Without trace mode nothing is recorded.
+        let (eval, _) = log_eval();
+        let next =
+            gen.next_gen(&mut || 0, false, 0, &[()], &cfg.clone().set_trace(false), &eval)?;
+        assert_eq!(next.trace, None);
+        Ok(())
+    }
+
+    #[test]
+    fn trace_labels_noop() {
+        let cfg = EvolveCfg::new(2);
+        let mems = vec![Member::new::<LogEvaluator>(0, &cfg), Member::new::<LogEvaluator>(1, &cfg)];
+        let event = |crossover_has_noop| BreedingEvent {
+            parents: [mems[0].id, mems[1].id],
+            parent_idxs: [0, 1],
+            parent_fitness: [0.0, 0.0],
+            crossover: Some(1),
+            crossover_weights: vec![0.5, 0.25, 0.25],
+            crossover_has_noop,
+            mutation_rates: [vec![], vec![]],
+            children: [mems[0].id, mems[1].id],
+        };
+        let summary = format_trace(&[event(true)], &mems, 1);
+        assert!(
+            summary.contains("crossover 1 of weights none 0.500, 1 0.250, 2 0.250\n"),
+            "{summary}"
+        );
+        let summary = format_trace(&[event(false)], &mems, 1);
+        assert!(
+            summary.contains("crossover 1 of weights 0 0.500, 1 0.250, 2 0.250\n"),
+            "{summary}"
+        );
+    }
+
+    // State which counts how many times it is compared.
+    #[derive(Debug, Clone, derive_more::Display)]
+    struct CountedState(usize);
+
+    static COMPARISONS: AtomicUsize = AtomicUsize::new(0);
+
+    impl PartialEq for CountedState {
+        fn eq(&self, other: &Self) -> bool {
+            let _ = COMPARISONS.fetch_add(1, Ordering::Relaxed);
+            self.0 == other.0
+        }
+    }
+
+    impl PartialOrd for CountedState {
+        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            let _ = COMPARISONS.fetch_add(1, Ordering::Relaxed);
+            self.0.partial_cmp(&other.0)
+        }
+    }
+
+    // Children are copies of their parents, so most are duplicates.
+    type CopyEvaluator = MockEvaluator<CountedState>;
+
+    fn copy_eval() -> CopyEvaluator {
+        MockEvaluator::with(
+            |s: &CountedState, ()| Ok(s.0 as f64 + 1.0),
+            |s1, s2| Ok(s1.0.abs_diff(s2.0) as f64),
+        )
+    }
+
+    #[test]
+    fn allow_duplicates_never_compares() -> Result<()> {
+        const POP: usize = 64;
+        let cfg = EvolveCfg::new(POP).set_trace(true);
+        let gen = EvaluatedGen::new(
+            (0..POP)
+                .rev()
+                .map(|v| {
+                    let mut mem = Member::new::<CopyEvaluator>(CountedState(v), &cfg);
+                    mem.fitness = v as f64 + 1.0;
+                    mem.selection_fitness = mem.fitness;
+                    mem
+                })
+                .collect(),
+        );
+        let parents = gen.mems.iter().map(|v| v.state.0).collect::<Vec<_>>();
+        let next_gen = |duplicates| -> Result<(UnevaluatedGen<CountedState>, usize)> {
+            let cfg = cfg.clone().set_duplicates(duplicates);
+            COMPARISONS.store(0, Ordering::Relaxed);
+            let next =
+                gen.next_gen(&mut || CountedState(0), false, 0, &[()], &cfg, &copy_eval())?;
+            Ok((next, COMPARISONS.load(Ordering::Relaxed)))
+        };
+
+        // Survivors then every bred child, in order.
+        let (next, comparisons) = next_gen(Duplicates::AllowDuplicates)?;
+        assert_eq!(comparisons, 0);
+        let trace = next.trace.as_ref().unwrap();
+        let children = trace.iter().flat_map(|ev| ev.children).collect::<Vec<_>>();
+        // Children come in pairs, so can go one over.
+        assert!(next.mems.len() == POP || next.mems.len() == POP + 1);
+        let survivors = next.mems.len() - children.len();
+        assert_eq!(next.mems[survivors..].iter().map(|v| v.id).collect::<Vec<_>>(), children);
+        for (mem, parent) in next.mems[..survivors].iter().zip(&gen.mems) {
+            assert_eq!(mem.id, parent.id);
+        }
+        assert!(next.mems.iter().all(|v| parents.contains(&v.state.0)));
+
+        // Duplicates are removed with a sort per pass, of which there are at
+        // most three.
+        let (next, comparisons) = next_gen(Duplicates::DisallowDuplicates)?;
+        let bound = 3 * 2 * (POP + 2) * (POP + 2).ilog2() as usize;
+        assert!(comparisons > 0 && comparisons <= bound, "{comparisons} > {bound}");
+        let mut states = next.mems.iter().map(|v| v.state.0).collect::<Vec<_>>();
+        assert!(states.iter().all(|v| parents.contains(v)));
+        let len = states.len();
+        states.dedup();
+        assert_eq!(states.len(), len);
+        // Nothing is lost other than the removed duplicates.
+        let bred = next.trace.as_ref().unwrap().len() * 2;
+        assert_eq!(len + next.dups_removed, survivors + bred);
+        Ok(())
+    }
+
+    // Runs a stagnant generation of two clusters of members, 0 to 9 and 1000
+    // to 1009, which are far enough apart to be separate species.
+    fn hybridize(species: Species) -> Result<(Stats, Vec<Call>)> {
+        // Never choose the no-op, so every pair is crossed over.
+        let cfg = EvolveCfg::new(20)
+            .set_crossover(Crossover::Fixed(vec![0.0, 1.0, 1.0]))
+            .set_duplicates(Duplicates::AllowDuplicates)
+            .set_survival(Survival::TopProportion(0.5))
+            .set_stagnation(Stagnation::ContinuousAfter(0))
+            .set_replacement(Replacement::HybridizeSpecies { pairs: 3 })
+            .set_species(species);
+        let (eval, calls) = log_eval();
+        let mut i = 0;
+        let mut evolver = Evolver::new(eval, cfg, move || {
+            i += 1;
+            if i <= 10 {
+                i - 1
+            } else {
+                990 + i - 1
+            }
+        });
+        let stats = Stats::from_result(&evolver.run()?);
+        assert!(stats.stagnant);
+        let calls = calls.lock().unwrap().clone();
+        Ok((stats, calls))
+    }
+
+    #[test]
+    fn hybridize_species() -> Result<()> {
+        let (stats, calls) = hybridize(Species::TargetNumber(2))?;
+        assert_eq!(stats.species.num, 2);
+        assert_eq!((stats.hybrids, stats.injected), (6, 0));
+        // Hybrids are bred before the rest of the children, from the best
+        // member of each species.
+        let crossovers = calls.iter().filter_map(|v| match v {
+            Call::Crossover(states, _) => Some(*states),
+            Call::Mutate(..) => None,
+        });
+        for states in crossovers.take(3) {
+            assert!(states == [9, 1009] || states == [1009, 9], "{states:?}");
+        }
+
+        // Without species, random individuals are injected instead.
+        let (stats, _) = hybridize(Species::None)?;
+        assert_eq!((stats.hybrids, stats.injected), (0, 6));
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn boltzmann_survival() {
+        const N: usize = 10000;
+        // Halves the temperature every generation, so it is 1 at generation 10.
+        let survival = Survival::Boltzmann { initial_temp: 1024.0, decay: 0.5, prop: 0.2 };
+        let cfg = EvolveCfg::new(5).set_survival(survival);
+        assert_eq!(survival.temperature(0), Some(1024.0));
+        assert_eq!(survival.temperature(10), Some(1.0));
+        assert_eq!(survival.temperature(11), Some(0.5));
+        assert_eq!(Survival::TopProportion(0.2).temperature(0), None);
+        assert_eq!(
+            Survival::boltzmann(2.0, 0.9),
+            Survival::Boltzmann { initial_temp: 2.0, decay: 0.9, prop: 0.2 }
+        );
+
+        // Fitness is the state, from 0 to 4. One member survives.
+        let gen = EvaluatedGen::new(
+            (0..5)
+                .map(|v| {
+                    let mut mem = Member::new::<CountEvaluator>(v, &cfg);
+                    mem.fitness = v as f64;
+                    mem
+                })
+                .collect(),
+        );
+        let freqs = |gen_idx: usize| {
+            let mut counts = [0; 5];
+            for _ in 0..N {
+                let survivors = gen.survivors(survival, gen_idx, &cfg, &mut rand::thread_rng());
+                assert_eq!(survivors.len(), 1);
+                counts[*survivors[0].state] += 1;
+            }
+            counts.map(|v| v as f64 / N as f64)
+        };
+
+        // Nearly uniform at a high temperature.
+        for freq in freqs(0) {
+            assert!((freq - 0.2).abs() < 0.03, "{freq}");
+        }
+        // Proportional to exp(fitness) at a temperature of 1.
+        let total = (0..5).map(|v| f64::from(v).exp()).sum::<f64>();
+        for (v, freq) in freqs(10).into_iter().enumerate() {
+            let expected = (v as f64).exp() / total;
+            assert!((freq - expected).abs() < 0.03, "{v}: {freq} vs {expected}");
+        }
+        // Only the best survives once the temperature is low.
+        assert_eq!(freqs(30), [0.0, 0.0, 0.0, 0.0, 1.0]);
+
+        // Survivors are distinct, and the order is by fitness once every
+        // weight but the best underflows.
+        let survival = Survival::Boltzmann { initial_temp: 1.0, decay: 0.01, prop: 0.6 };
+        let survivors = gen.survivors(survival, 300, &cfg, &mut rand::thread_rng());
+        assert_eq!(survivors.iter().map(|v| *v.state).collect::<Vec<_>>(), [4, 3, 2]);
+        assert!(survivors.iter().all(|v| v.age == 1));
     }
 }
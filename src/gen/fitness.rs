@@ -0,0 +1,93 @@
+use crate::eval::State;
+use crate::evolve::cfg::AgeDecay;
+use crate::gen::member::Member;
+
+/// Sets selection fitness linear in rank, where |order| lists the indices of
+/// |mems| best first. The best gets the number of members and the worst 1.
+pub fn rank<S: State>(mems: &mut [Member<S>], order: &[usize]) {
+    let n = mems.len();
+    for (i, &idx) in order.iter().enumerate() {
+        mems[idx].selection_fitness = (n - i) as f64;
+    }
+}
+
+pub fn age_decay<S: State>(mems: &mut [Member<S>], decay: AgeDecay) {
+    for v in mems {
+        v.selection_fitness = decay.apply(v.selection_fitness, v.age);
+    }
+}
+
+pub fn power<S: State>(mems: &mut [Member<S>], exponent: f64) {
+    for v in mems {
+        v.selection_fitness = v.selection_fitness.powf(exponent);
+    }
+}
+
+/// Subtracts |weight| times the violation, keeping selection fitness
+/// non-negative.
+pub fn penalty<S: State>(mems: &mut [Member<S>], weight: f64) {
+    for v in mems {
+        v.selection_fitness = (v.selection_fitness - weight * v.violation).max(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::evolve::cfg::EvolveCfg;
+    use crate::util::bench_utils::CountEvaluator;
+
+    // Members with the given selection fitness, age and violation.
+    fn mems(v: &[(f64, usize, f64)]) -> Vec<Member<usize>> {
+        v.iter()
+            .enumerate()
+            .map(|(i, &(selection_fitness, age, violation))| {
+                let mut mem = Member::new::<CountEvaluator>(i, &EvolveCfg::new(1));
+                mem.selection_fitness = selection_fitness;
+                mem.age = age;
+                mem.violation = violation;
+                mem
+            })
+            .collect()
+    }
+
+    fn selection(mems: &[Member<usize>]) -> Vec<f64> {
+        mems.iter().map(|v| v.selection_fitness).collect()
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn rank_replaces_fitness() {
+        let mut mems = mems(&[(0.5, 0, 0.0), (9.0, 0, 0.0), (3.0, 0, 0.0), (1.0, 0, 0.0)]);
+        rank(&mut mems, &[2, 0, 3, 1]);
+        assert_eq!(selection(&mems), [3.0, 1.0, 4.0, 2.0]);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn age_decay_discounts_old() {
+        let mut mems = mems(&[(6.0, 6, 0.0), (10.0, 0, 0.0), (3.0, 3, 0.0), (2.0, 2, 0.0)]);
+        age_decay(&mut mems, AgeDecay::new(2, 0.5));
+        assert_eq!(selection(&mems), [2.0, 10.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn power_scales() {
+        let mut mems = mems(&[(3.0, 0, 0.0), (0.5, 0, 0.0), (0.0, 0, 0.0)]);
+        power(&mut mems, 2.0);
+        assert_eq!(selection(&mems), [9.0, 0.25, 0.0]);
+        power(&mut mems, 0.5);
+        assert_eq!(selection(&mems), [3.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn penalty_floors_at_zero() {
+        let mut mems = mems(&[(5.0, 0, 0.0), (5.0, 0, 1.5), (2.0, 0, 3.0)]);
+        penalty(&mut mems, 2.0);
+        assert_eq!(selection(&mems), [5.0, 2.0, 0.0]);
+    }
+}
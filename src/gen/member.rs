@@ -1,31 +1,101 @@
+use std::cmp;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
 use derive_more::Display;
+use rand::Rng;
 
 use crate::eval::{Evaluator, State};
 use crate::evolve::cfg::EvolveCfg;
 use crate::gen::params::Params;
 use crate::gen::species::{SpeciesId, NO_SPECIES};
+use crate::util::fmt::fmt_fitness;
+
+/// Unique id of a member, so members can be followed between generations.
+/// Copies of a member which survive keep its id, and children get new ids.
+pub type MemberId = u64;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+#[must_use]
+pub fn next_member_id() -> MemberId {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
 
 #[must_use]
-#[derive(Clone, PartialOrd, PartialEq, Debug, Display)]
-#[display(fmt = "fitness {fitness:5.5} species {species:>3}")]
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "fitness {} species {species:>3}", "fmt_fitness(*fitness)")]
 pub struct Member<S: State> {
-    pub state: S,               // Actual state.
+    pub state: Arc<S>,          // Actual state. Shared between copies until modified.
+    pub id: MemberId,           // Unique id, kept by survivors.
     pub params: Params,         // Adaptively evolved parameters
     pub species: SpeciesId,     // Species index
     pub fitness: f64,           // Original fitness, generated by Evaluator fitness function.
     pub selection_fitness: f64, // Potentially adjusted fitness, for selection.
+    pub violation: f64,         // Constraint violation, 0 if feasible or not computed.
     pub age: usize,             // Age of the member in generations.
 }
 
 impl<S: State> Member<S> {
     pub fn new<E: Evaluator>(state: S, cfg: &EvolveCfg) -> Self {
+        Self::new_rng::<E, _>(state, cfg, &mut rand::thread_rng())
+    }
+
+    /// Like `new`, but draws the random initial `Params` from |r|.
+    pub fn new_rng<E: Evaluator, R: Rng + ?Sized>(state: S, cfg: &EvolveCfg, r: &mut R) -> Self {
         Self {
-            state,
-            params: Params::new::<E>(cfg),
+            state: Arc::new(state),
+            id: next_member_id(),
+            params: Params::new_rng::<E, _>(cfg, r),
             species: NO_SPECIES,
             fitness: 0.0,
             selection_fitness: 0.0,
+            violation: 0.0,
             age: 0,
         }
     }
+
+    // Everything but the id, which only tells copies apart for tracing.
+    fn key(&self) -> (&S, &Params, SpeciesId, f64, f64, f64, usize) {
+        (
+            &self.state,
+            &self.params,
+            self.species,
+            self.fitness,
+            self.selection_fitness,
+            self.violation,
+            self.age,
+        )
+    }
+}
+
+// Ids are left out, so members with the same contents compare equal.
+impl<S: State> PartialEq for Member<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl<S: State> PartialOrd for Member<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        self.key().partial_cmp(&other.key())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_utils::MockEvaluator;
+
+    #[test]
+    fn equality_ignores_id() {
+        let cfg = EvolveCfg::new(1);
+        let mem = Member::new::<MockEvaluator>(1, &cfg);
+        let mut copy = mem.clone();
+        copy.id = next_member_id();
+        assert_eq!(copy, mem);
+        assert_eq!(copy.partial_cmp(&mem), Some(cmp::Ordering::Equal));
+        copy.age += 1;
+        assert_ne!(copy, mem);
+    }
 }
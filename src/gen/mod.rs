@@ -1,5 +1,9 @@
 pub mod evaluated;
+pub mod fitness;
 pub mod member;
 pub mod params;
+pub mod ranking;
+pub mod snapshot;
 pub mod species;
+pub mod trace;
 pub mod unevaluated;
@@ -1,12 +1,15 @@
+use eyre::{eyre, Result};
 use rand::Rng;
 
 use crate::eval::Evaluator;
-use crate::evolve::cfg::{Crossover, EvolveCfg, Mutation};
+use crate::evolve::cfg::{Crossover, EvolveCfg, Mutation, ParamsCrossover};
+use crate::ops::crossover::{crossover_arith_alpha, crossover_ux_rng};
 use crate::ops::util::rand_vec;
 
 /// Potentially self-adaptive parameters per state.
 #[must_use]
 #[derive(Debug, Clone, PartialOrd, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Params {
     // Conventionally, the first element will be the weight of doing no mutation or crossover.
     pub mutation: Vec<f64>,
@@ -15,7 +18,10 @@ pub struct Params {
 
 impl Params {
     pub fn new<E: Evaluator>(cfg: &EvolveCfg) -> Self {
-        let mut r = rand::thread_rng();
+        Self::new_rng::<E, _>(cfg, &mut rand::thread_rng())
+    }
+
+    pub fn new_rng<E: Evaluator, R: Rng + ?Sized>(cfg: &EvolveCfg, r: &mut R) -> Self {
         let mutation = if let Mutation::Fixed(v) = &cfg.mutation {
             v.clone()
         } else {
@@ -30,4 +36,140 @@ impl Params {
 
         Self { mutation, crossover }
     }
+
+    /// Equal weights for every operator.
+    pub fn uniform<E: Evaluator>() -> Self {
+        Self { mutation: vec![1.0; E::NUM_MUTATION], crossover: vec![1.0; E::NUM_CROSSOVER] }
+    }
+
+    /// Given weights, e.g. to favour operators known to help seeded states.
+    /// Fails unless there is a non-negative weight for each operator.
+    pub fn biased<E: Evaluator>(mutation_bias: &[f64], crossover_bias: &[f64]) -> Result<Self> {
+        let params = Self { mutation: mutation_bias.to_vec(), crossover: crossover_bias.to_vec() };
+        params.validate::<E>()?;
+        Ok(params)
+    }
+
+    /// Checks there is a non-negative weight for each of the operators of |E|.
+    pub fn validate<E: Evaluator>(&self) -> Result<()> {
+        let check = |kind: &str, weights: &[f64], len: usize| {
+            if weights.len() != len {
+                return Err(eyre!("{} {kind} weights given, expected {len}", weights.len()));
+            }
+            if let Some(v) = weights.iter().find(|v| !(**v >= 0.0 && v.is_finite())) {
+                return Err(eyre!("{kind} weights must be non-negative and finite: {v}"));
+            }
+            Ok(())
+        };
+        check("mutation", &self.mutation, E::NUM_MUTATION)?;
+        check("crossover", &self.crossover, E::NUM_CROSSOVER)
+    }
+
+    /// Label of crossover operator |idx| in reports: "none" for index 0 if it
+    /// is the no-op, as with `Evaluator::CROSSOVER_HAS_NOOP`, otherwise the
+    /// index.
+    #[must_use]
+    pub fn crossover_label(idx: usize, has_noop: bool) -> String {
+        if has_noop && idx == 0 {
+            "none".to_string()
+        } else {
+            idx.to_string()
+        }
+    }
+
+    /// Index and share of the total weight of each real crossover operator,
+    /// leaving out index 0 if it is the no-op. Shares are all 0 if the real
+    /// operators have no weight.
+    #[must_use]
+    pub fn crossover_shares(weights: &[f64], has_noop: bool) -> Vec<(usize, f64)> {
+        let first = usize::from(has_noop).min(weights.len());
+        let total: f64 = weights[first..].iter().sum();
+        let share = |v: f64| if total > 0.0 { v / total } else { 0.0 };
+        weights.iter().enumerate().skip(first).map(|(idx, &v)| (idx, share(v))).collect()
+    }
+
+    /// Recombines the params of two children produced by crossover.
+    pub fn crossover(p1: &mut Params, p2: &mut Params, mode: ParamsCrossover) {
+        let mut r = rand::thread_rng();
+        Self::crossover_rng(p1, p2, mode, &mut r);
+    }
+
+    pub fn crossover_rng<R: Rng + ?Sized>(
+        p1: &mut Params,
+        p2: &mut Params,
+        mode: ParamsCrossover,
+        r: &mut R,
+    ) {
+        match mode {
+            ParamsCrossover::Inherit => {}
+            ParamsCrossover::Average => {
+                crossover_arith_alpha(&mut p1.mutation, &mut p2.mutation, 0.5);
+                crossover_arith_alpha(&mut p1.crossover, &mut p2.crossover, 0.5);
+            }
+            ParamsCrossover::SwapHalf => {
+                crossover_ux_rng(&mut p1.mutation, &mut p2.mutation, r);
+                crossover_ux_rng(&mut p1.crossover, &mut p2.crossover, r);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rand::rngs::mock::StepRng;
+
+    use super::*;
+
+    fn parents() -> (Params, Params) {
+        (
+            Params { mutation: vec![0.5, 1.0], crossover: vec![1.0, 2.0, 4.0] },
+            Params { mutation: vec![0.25, 0.0], crossover: vec![3.0, 0.0, 8.0] },
+        )
+    }
+
+    fn crossover(mode: ParamsCrossover) -> (Params, Params) {
+        let (mut p1, mut p2) = parents();
+        // Alternates not swapping and swapping.
+        let mut r = StepRng::new(0, 1 << 31);
+        Params::crossover_rng(&mut p1, &mut p2, mode, &mut r);
+        (p1, p2)
+    }
+
+    #[test]
+    fn params_crossover_inherit() {
+        assert_eq!(crossover(ParamsCrossover::Inherit), parents());
+    }
+
+    #[test]
+    fn params_crossover_average() {
+        let avg = Params { mutation: vec![0.375, 0.5], crossover: vec![2.0, 1.0, 6.0] };
+        assert_eq!(crossover(ParamsCrossover::Average), (avg.clone(), avg));
+    }
+
+    #[test]
+    fn params_crossover_swap_half() {
+        assert_eq!(
+            crossover(ParamsCrossover::SwapHalf),
+            (
+                Params { mutation: vec![0.5, 0.0], crossover: vec![1.0, 0.0, 4.0] },
+                Params { mutation: vec![0.25, 1.0], crossover: vec![3.0, 2.0, 8.0] },
+            )
+        );
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn crossover_shares() {
+        let weights = [2.0, 1.0, 3.0];
+        assert_eq!(Params::crossover_shares(&weights, true), vec![(1, 0.25), (2, 0.75)]);
+        assert_eq!(
+            Params::crossover_shares(&weights, false),
+            vec![(0, 2.0 / 6.0), (1, 1.0 / 6.0), (2, 3.0 / 6.0)]
+        );
+        assert_eq!(Params::crossover_shares(&[1.0, 0.0], true), vec![(1, 0.0)]);
+        assert_eq!(Params::crossover_shares(&[], true), vec![]);
+        assert_eq!(Params::crossover_label(0, true), "none");
+        assert_eq!(Params::crossover_label(0, false), "0");
+    }
 }
@@ -0,0 +1,113 @@
+use rand::Rng;
+
+use crate::eval::State;
+use crate::gen::member::Member;
+
+/// Orders |mems| best first by stochastic ranking (Runarsson & Yao, 2000).
+/// Each sweep compares neighbouring members: by fitness, higher first, if both
+/// are feasible or with probability |pf|, otherwise by violation, lower first.
+/// Stops after a sweep with no swaps, or after as many sweeps as members.
+pub fn stochastic_rank<S: State, R: Rng + ?Sized>(mems: &mut [Member<S>], pf: f64, r: &mut R) {
+    let ranked =
+        stochastic_order(mems, pf, r).into_iter().map(|i| mems[i].clone()).collect::<Vec<_>>();
+    mems.clone_from_slice(&ranked);
+}
+
+/// Like `stochastic_rank`, but leaves |mems| alone and returns their indices
+/// in ranked order.
+pub fn stochastic_order<S: State, R: Rng + ?Sized>(
+    mems: &[Member<S>],
+    pf: f64,
+    r: &mut R,
+) -> Vec<usize> {
+    let mut order = (0..mems.len()).collect::<Vec<_>>();
+    for _ in 0..order.len() {
+        let mut swapped = false;
+        for j in 1..order.len() {
+            let (a, b) = (&mems[order[j - 1]], &mems[order[j]]);
+            // Always draw, so the rng sequence doesn't depend on feasibility.
+            let u = r.gen::<f64>();
+            let by_fitness = (a.violation <= 0.0 && b.violation <= 0.0) || u < pf;
+            let swap = if by_fitness { a.fitness < b.fitness } else { a.violation > b.violation };
+            if swap {
+                order.swap(j - 1, j);
+                swapped = true;
+            }
+        }
+        if !swapped {
+            break;
+        }
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::evolve::cfg::EvolveCfg;
+    use crate::util::bench_utils::CountEvaluator;
+
+    // Members with the given fitness and violation, with states numbering
+    // them in order.
+    fn mems(v: &[(f64, f64)]) -> Vec<Member<usize>> {
+        v.iter()
+            .enumerate()
+            .map(|(i, &(fitness, violation))| {
+                let mut mem = Member::new::<CountEvaluator>(i, &EvolveCfg::new(1));
+                mem.fitness = fitness;
+                mem.violation = violation;
+                mem
+            })
+            .collect()
+    }
+
+    fn rank(v: &[(f64, f64)], pf: f64, seed: u64) -> Vec<usize> {
+        let mut mems = mems(v);
+        stochastic_rank(&mut mems, pf, &mut StdRng::seed_from_u64(seed));
+        mems.iter().map(|v| *v.state).collect()
+    }
+
+    // Fitness and violation pairs mixing feasible and infeasible members.
+    const POP: [(f64, f64); 6] =
+        [(5.0, 2.0), (1.0, 0.0), (9.0, 0.5), (3.0, 0.0), (7.0, 2.0), (2.0, 0.0)];
+
+    #[test]
+    fn pure_feasibility() {
+        // Feasible members first by fitness, then infeasible ones by
+        // violation. Ties in violation keep their original order.
+        for seed in 0..10 {
+            assert_eq!(rank(&POP, 0.0, seed), [3, 5, 1, 2, 0, 4]);
+        }
+    }
+
+    #[test]
+    fn pure_fitness() {
+        for seed in 0..10 {
+            assert_eq!(rank(&POP, 1.0, seed), [2, 4, 0, 3, 5, 1]);
+        }
+    }
+
+    #[test]
+    fn mixed() {
+        // With a feasible member ahead of a fitter infeasible one, the first
+        // sweep swaps them with probability pf. If swapped, the second and
+        // last sweep keeps them with probability pf, so the infeasible member
+        // ends up first with probability pf^2.
+        const N: u64 = 20000;
+        const PF: f64 = 0.45;
+        let pair = [(1.0, 0.0), (2.0, 1.0)];
+        let first = (0..N).filter(|&seed| rank(&pair, PF, seed)[0] == 1).count();
+        let freq = first as f64 / N as f64;
+        assert!((freq - PF * PF).abs() < 0.02, "{freq}");
+
+        // Between the extremes, some but not all orderings put an infeasible
+        // member first.
+        let firsts = (0..100).map(|seed| rank(&POP, PF, seed)[0]).collect::<Vec<_>>();
+        assert!(firsts.contains(&3));
+        assert!(firsts.iter().any(|&v| POP[v].1 > 0.0));
+    }
+}
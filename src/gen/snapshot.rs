@@ -0,0 +1,237 @@
+#[cfg(feature = "serde")]
+use eyre::{Result, WrapErr};
+
+use crate::eval::State;
+use crate::gen::member::{Member, MemberId};
+use crate::gen::params::Params;
+use crate::gen::species::{SpeciesId, SpeciesInfo};
+
+/// Summary of a single species in a generation.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpeciesSummary {
+    pub id: SpeciesId,
+    pub size: usize,
+    pub best_fitness: f64,
+    pub mean_fitness: f64,
+    // Display string of the fittest member of the species.
+    pub representative: String,
+}
+
+/// Machine readable dump of the species in a generation, recorded when
+/// `EvolveCfg::species_snapshots` is set. Species ids are assigned in order of
+/// the fitness of their fittest member each generation, so they are only
+/// comparable between generations by rank.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpeciesSnapshot {
+    pub gen: usize,
+    pub radius: f64,
+    // Sorted by species id.
+    pub species: Vec<SpeciesSummary>,
+}
+
+impl SpeciesSnapshot {
+    /// Builds a snapshot from speciated |mems|, which must be sorted by
+    /// fitness, as in an evaluated generation.
+    pub fn new<S: State>(gen: usize, info: &SpeciesInfo, mems: &[Member<S>]) -> Self {
+        let mut species: Vec<SpeciesSummary> = Vec::new();
+        for mem in mems {
+            if let Some(summary) = species.iter_mut().find(|v| v.id == mem.species) {
+                summary.size += 1;
+                summary.mean_fitness += mem.fitness;
+            } else {
+                // Members are sorted by fitness, so the first member seen is
+                // the fittest.
+                species.push(SpeciesSummary {
+                    id: mem.species,
+                    size: 1,
+                    best_fitness: mem.fitness,
+                    mean_fitness: mem.fitness,
+                    representative: mem.state.to_string(),
+                });
+            }
+        }
+        for summary in &mut species {
+            summary.mean_fitness /= summary.size as f64;
+        }
+        species.sort_unstable_by_key(|v| v.id);
+        Self { gen, radius: info.radius, species }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl SpeciesSnapshot {
+    /// Renders the snapshot as a single line of JSON, for JSONL output.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).wrap_err("serializing species snapshot")
+    }
+}
+
+/// Metadata of a member without its state. See `PopulationSnapshot`.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct MemberSnapshot<'a> {
+    pub id: MemberId,
+    pub fitness: f64,
+    pub selection_fitness: f64,
+    pub species: SpeciesId,
+    pub age: usize,
+    pub params: &'a Params, // Borrowed from the member, so snapshots are cheap.
+}
+
+/// Metadata of every member of an evaluated generation, for analysing
+/// population dynamics such as fitness distributions and species sizes over
+/// time. States aren't copied: members are in the same order as in the
+/// generation, fittest first, so e.g. `EvolveResult::nth` gives their states.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd, Default)]
+pub struct PopulationSnapshot<'a> {
+    pub members: Vec<MemberSnapshot<'a>>,
+}
+
+impl<'a> PopulationSnapshot<'a> {
+    pub fn new<S: State>(mems: &'a [Member<S>]) -> Self {
+        let members = mems
+            .iter()
+            .map(|v| MemberSnapshot {
+                id: v.id,
+                fitness: v.fitness,
+                selection_fitness: v.selection_fitness,
+                species: v.species,
+                age: v.age,
+                params: &v.params,
+            })
+            .collect();
+        Self { members }
+    }
+
+    #[must_use]
+    pub fn fitnesses(&self) -> Vec<f64> {
+        self.members.iter().map(|v| v.fitness).collect()
+    }
+
+    /// Number of members in each species, sorted by species id.
+    #[must_use]
+    pub fn species_sizes(&self) -> Vec<(SpeciesId, usize)> {
+        let mut sizes: Vec<(SpeciesId, usize)> = Vec::new();
+        for mem in &self.members {
+            match sizes.iter_mut().find(|v| v.0 == mem.species) {
+                Some(v) => v.1 += 1,
+                None => sizes.push((mem.species, 1)),
+            }
+        }
+        sizes.sort_unstable();
+        sizes
+    }
+
+    /// Counts of fitnesses in |bins| equal width bins from the lowest to the
+    /// highest fitness, with the highest in the last bin. If every fitness is
+    /// the same, they're all in the first bin. Empty if |bins| is 0.
+    #[must_use]
+    pub fn fitness_histogram(&self, bins: usize) -> Vec<usize> {
+        let mut counts = vec![0; bins];
+        if bins == 0 {
+            return counts;
+        }
+        let fitnesses = self.fitnesses();
+        let lo = fitnesses.iter().copied().fold(f64::INFINITY, f64::min);
+        let hi = fitnesses.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let width = (hi - lo) / bins as f64;
+        for v in fitnesses {
+            let bin = if width > 0.0 { ((v - lo) / width) as usize } else { 0 };
+            counts[bin.min(bins - 1)] += 1;
+        }
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::evolve::cfg::EvolveCfg;
+    use crate::util::bench_utils::CountEvaluator;
+
+    fn mem(state: usize, fitness: f64, species: SpeciesId) -> Member<usize> {
+        let mut mem = Member::new::<CountEvaluator>(state, &EvolveCfg::new(1));
+        mem.fitness = fitness;
+        mem.species = species;
+        mem
+    }
+
+    #[test]
+    fn snapshot_contents() {
+        let mems = [mem(7, 4.0, 1), mem(3, 3.0, 2), mem(6, 2.0, 1), mem(2, 1.0, 2), mem(5, 0.0, 2)];
+        let info = SpeciesInfo { num: 2, radius: 1.5, ..SpeciesInfo::new() };
+        let snapshot = SpeciesSnapshot::new(4, &info, &mems);
+        assert_eq!(
+            snapshot,
+            SpeciesSnapshot {
+                gen: 4,
+                radius: 1.5,
+                species: vec![
+                    SpeciesSummary {
+                        id: 1,
+                        size: 2,
+                        best_fitness: 4.0,
+                        mean_fitness: 3.0,
+                        representative: "7".to_string(),
+                    },
+                    SpeciesSummary {
+                        id: 2,
+                        size: 3,
+                        best_fitness: 3.0,
+                        mean_fitness: 4.0 / 3.0,
+                        representative: "3".to_string(),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn snapshot_json() -> Result<()> {
+        let species = SpeciesSummary {
+            id: 1,
+            size: 2,
+            best_fitness: f64::NAN,
+            mean_fitness: 3.0,
+            representative: "a\"b\nc".to_string(),
+        };
+        let snapshot = SpeciesSnapshot { gen: 4, radius: 1.5, species: vec![species] };
+        // JSON has no non-finite numbers, so they're written as null.
+        assert_eq!(
+            snapshot.to_json()?,
+            concat!(
+                r#"{"gen":4,"radius":1.5,"species":[{"id":1,"size":2,"best_fitness":null,"#,
+                r#""mean_fitness":3.0,"representative":"a\"b\nc"}]}"#,
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn population_snapshot() {
+        let mut mems = [mem(7, 4.0, 2), mem(3, 3.0, 1), mem(6, 2.5, 2), mem(5, 0.0, 2)];
+        mems[1].age = 3;
+        mems[1].selection_fitness = 1.5;
+        let snapshot = PopulationSnapshot::new(&mems);
+        let m = &snapshot.members[1];
+        assert_eq!((m.id, m.fitness, m.selection_fitness, m.age), (mems[1].id, 3.0, 1.5, 3));
+        assert!(std::ptr::eq(m.params, &mems[1].params));
+        assert_eq!(snapshot.fitnesses(), [4.0, 3.0, 2.5, 0.0]);
+        assert_eq!(snapshot.species_sizes(), [(1, 1), (2, 3)]);
+        assert_eq!(snapshot.fitness_histogram(4), [1, 0, 1, 2]);
+        assert_eq!(snapshot.fitness_histogram(0), Vec::<usize>::new());
+
+        let same = [mem(1, 2.0, 1), mem(2, 2.0, 1)];
+        let same = PopulationSnapshot::new(&same);
+        assert_eq!(same.fitness_histogram(3), [2, 0, 0]);
+        assert_eq!(PopulationSnapshot::default().fitness_histogram(2), [0, 0]);
+    }
+}
@@ -1,27 +1,51 @@
 use std::collections::VecDeque;
 use std::ops::Index;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
-use derive_more::Display;
-use eyre::Result;
-use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use eyre::{Result, WrapErr};
+use rand::seq::index::sample;
+use rand::Rng;
 
 use crate::eval::{Evaluator, State};
 use crate::gen::member::Member;
+use crate::util::par::try_for_each_chunk_mut;
 
 pub type SpeciesId = u64;
 pub const NO_SPECIES: SpeciesId = 0;
 
 #[must_use]
-#[derive(Copy, Clone, PartialOrd, PartialEq, Debug, Display)]
-#[display(fmt = "species: {num:>3}, radius: {radius:5.5}")]
+#[derive(Copy, Clone, PartialOrd, PartialEq, Debug)]
 pub struct SpeciesInfo {
+    // Number of species found.
     pub num: u64,
     pub radius: f64,
+    // Radius used for fitness sharing, if niching uses it.
+    pub share_radius: Option<f64>,
+    // Species merged into a neighbour for being too small, for
+    // `Species::TargetNumberBounded`.
+    pub merged: usize,
+    // Members moved out of species which were too large, for
+    // `Species::TargetNumberBounded`.
+    pub reassigned: usize,
 }
 
 impl SpeciesInfo {
     pub fn new() -> Self {
-        Self { num: 1, radius: 1.0 }
+        Self { num: 1, radius: 1.0, share_radius: None, merged: 0, reassigned: 0 }
+    }
+}
+
+impl std::fmt::Display for SpeciesInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "species: {:>3}, radius: {:5.5}", self.num, self.radius)?;
+        if let Some(share_radius) = self.share_radius {
+            write!(f, ", share radius: {share_radius:5.5}")?;
+        }
+        if self.merged > 0 || self.reassigned > 0 {
+            write!(f, ", merged: {}, reassigned: {}", self.merged, self.reassigned)?;
+        }
+        Ok(())
     }
 }
 
@@ -31,6 +55,99 @@ impl Default for SpeciesInfo {
     }
 }
 
+/// Error computing the distance between members |i| and |j| of a
+/// generation. Wraps the evaluator's error, so it can be found with
+/// `downcast_ref` on the returned report.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd)]
+pub struct DistanceError {
+    pub i: usize,
+    pub j: usize,
+}
+
+impl std::fmt::Display for DistanceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to compute distance between members {} and {}", self.i, self.j)
+    }
+}
+
+/// Distance metric property broken by an evaluator, found by
+/// `validate_distance_metric` or, with `EvolveCfg::validate_distance`, when
+/// distances are first computed. Indices are of the checked states.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub enum MetricError {
+    NonZeroSelf { i: usize, d: f64 },
+    Negative { i: usize, j: usize, d: f64 },
+    Asymmetric { i: usize, j: usize, ij: f64, ji: f64 },
+}
+
+impl std::fmt::Display for MetricError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::NonZeroSelf { i, d } => {
+                write!(f, "distance from state {i} to itself is {d}, not 0")
+            }
+            Self::Negative { i, j, d } => {
+                write!(f, "distance from state {i} to {j} is {d}, not non-negative")
+            }
+            Self::Asymmetric { i, j, ij, ji } => {
+                write!(f, "distance from state {i} to {j} is {ij}, but from {j} to {i} is {ji}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MetricError {}
+
+// Checks |samples| random pairs of distinct members out of |len|, and each
+// pair's first member against itself, with |dist| giving the distance
+// between members.
+fn check_metric(
+    len: usize,
+    samples: usize,
+    mut dist: impl FnMut(usize, usize) -> Result<f64>,
+) -> Result<()> {
+    const EPSILON: f64 = 1.0e-9;
+    if len == 0 {
+        return Ok(());
+    }
+    let mut rng = rand::thread_rng();
+    for _ in 0..samples {
+        let first = rng.gen_range(0..len);
+        let second = if len > 1 { (first + rng.gen_range(1..len)) % len } else { first };
+        // Deliberately the same member twice: distance to itself must be zero.
+        let to_self = dist(first, first)?;
+        if to_self.is_nan() || to_self.abs() > EPSILON {
+            return Err(MetricError::NonZeroSelf { i: first, d: to_self }.into());
+        }
+        let (forward, backward) = (dist(first, second)?, dist(second, first)?);
+        for (i, j, d) in [(first, second, forward), (second, first, backward)] {
+            if d.is_nan() || d < 0.0 {
+                return Err(MetricError::Negative { i, j, d }.into());
+            }
+        }
+        if (forward - backward).abs() > EPSILON * forward.max(backward).max(1.0) {
+            let (i, j, ij, ji) = (first, second, forward, backward);
+            return Err(MetricError::Asymmetric { i, j, ij, ji }.into());
+        }
+    }
+    Ok(())
+}
+
+/// Checks |eval|'s distance is a sensible metric on |samples| random pairs of
+/// |states|: zero from a state to itself, non-negative and symmetric.
+/// Speciation, niching and deduplication by distance all assume this. Fails
+/// with a `MetricError` naming the offending pair, which can be found with
+/// `downcast_ref`. For evaluator unit tests.
+pub fn validate_distance_metric<E: Evaluator>(
+    eval: &E,
+    states: &[E::State],
+    samples: usize,
+) -> Result<()> {
+    check_metric(states.len(), samples, |i, j| eval.distance(&states[i], &states[j]))
+}
+
 #[must_use]
 #[derive(Debug, Clone, PartialOrd, PartialEq)]
 pub struct DistCache {
@@ -49,38 +166,56 @@ impl DistCache {
         &mut self,
         s: &[Member<E::State>],
         par: bool,
+        chunk_size: usize,
         eval: &E,
     ) -> Result<()> {
+        let _ = self.ensure_until(s, par, chunk_size, eval, None)?;
+        Ok(())
+    }
+
+    /// Like `ensure`, but stops computing distances once |deadline| has
+    /// passed, leaving the cache empty. Returns whether the cache is filled.
+    pub fn ensure_until<E: Evaluator>(
+        &mut self,
+        s: &[Member<E::State>],
+        par: bool,
+        chunk_size: usize,
+        eval: &E,
+        deadline: Option<Instant>,
+    ) -> Result<bool> {
         if self.is_empty() {
-            self.n = s.len();
-            self.cache = if par {
-                let cache = (0..self.n * self.n)
-                    .into_par_iter()
-                    .map(|v| {
-                        let i = v / self.n;
-                        let j = v % self.n;
-                        eval.distance(&s[i].state, &s[j].state)
-                    })
-                    .collect::<Result<Vec<f64>>>()?;
-                (self.max, self.sum) = cache
-                    .par_iter()
-                    .fold(|| (0.0, 0.0), |(m, s): (f64, f64), &v| (m.max(v), s + v))
-                    .reduce(|| (0.0, 0.0), |(m0, s0), (m1, s1)| (m0.max(m1), s0 + s1));
-                cache
-            } else {
-                let mut cache = vec![0.0; self.n * self.n];
-                for i in 0..self.n {
-                    for j in 0..self.n {
-                        let dist = eval.distance(&s[i].state, &s[j].state)?;
-                        cache[i * self.n + j] = dist;
-                        self.max = self.max.max(dist);
-                        self.sum += dist;
+            // Fill a separate buffer so the cache stays empty if any distance
+            // fails, and can be computed again later.
+            let n = s.len();
+            let mut cache = vec![0.0; n * n];
+            let timed_out = AtomicBool::new(false);
+            try_for_each_chunk_mut(&mut cache, par, chunk_size, |chunk, dists| {
+                for (k, dist) in dists.iter_mut().enumerate() {
+                    if matches!(deadline, Some(d) if Instant::now() >= d) {
+                        timed_out.store(true, Ordering::Relaxed);
+                        return Ok(());
                     }
+                    let v = chunk * chunk_size + k;
+                    let pair = DistanceError { i: v / n, j: v % n };
+                    *dist = eval.distance(&s[pair.i].state, &s[pair.j].state).wrap_err(pair)?;
                 }
-                cache
-            };
+                Ok(())
+            })?;
+            if timed_out.into_inner() {
+                return Ok(false);
+            }
+            self.n = n;
+            self.cache = cache;
+            (self.max, self.sum) =
+                self.cache.iter().fold((0.0, 0.0), |(m, s): (f64, f64), &v| (m.max(v), s + v));
         }
-        Ok(())
+        Ok(true)
+    }
+
+    /// Like `validate_distance_metric`, but checks the cached distances
+    /// between members, so computes no new ones. The cache must be computed.
+    pub fn validate(&self, samples: usize) -> Result<()> {
+        check_metric(self.n, samples, |i, j| Ok(self[(i, j)]))
     }
 
     pub fn speciate<S: State>(
@@ -109,12 +244,89 @@ impl DistCache {
             num += 1;
         }
 
-        // Assign species to ones not assigned yet.
-        (ids, SpeciesInfo { num, radius })
+        // |num| is one past the last assigned species id.
+        (ids, SpeciesInfo { num: num - 1, radius, ..SpeciesInfo::new() })
+    }
+
+    /// Enforces size bounds on species |ids| from `speciate`. Species with
+    /// fewer than the minimum size are merged, smallest first, into the
+    /// species with the nearest representative. Then members of a species
+    /// beyond the first `max_frac` of the population are moved to the species
+    /// with the representative nearest to them which still has room. A
+    /// species' representative is its first, i.e. fittest, member. Surviving
+    /// species keep their order and are renumbered to stay contiguous.
+    pub fn bound_species(
+        &self,
+        ids: &mut [SpeciesId],
+        info: &mut SpeciesInfo,
+        min_size: usize,
+        max_frac: f64,
+    ) {
+        if ids.is_empty() || ids.contains(&NO_SPECIES) {
+            return;
+        }
+        let num = *ids.iter().max().unwrap() as usize;
+        let mut reps = vec![0; num + 1];
+        let mut sizes = vec![0; num + 1];
+        for (i, &id) in ids.iter().enumerate().rev() {
+            reps[id as usize] = i;
+            sizes[id as usize] += 1;
+        }
+        // Species other than |skip| with fewer than |room| members whose
+        // representative is nearest to member |from|.
+        let nearest = |sizes: &[usize], from: usize, skip: usize, room: usize| {
+            (1..=num)
+                .filter(|&v| v != skip && sizes[v] > 0 && sizes[v] < room)
+                .min_by(|&a, &b| self[(from, reps[a])].total_cmp(&self[(from, reps[b])]))
+        };
+
+        let mut merged = 0;
+        while let Some(src) =
+            (1..=num).filter(|&v| sizes[v] > 0 && sizes[v] < min_size).min_by_key(|&v| sizes[v])
+        {
+            let Some(dst) = nearest(&sizes, reps[src], src, usize::MAX) else { break };
+            for id in ids.iter_mut().filter(|v| **v as usize == src) {
+                *id = dst as SpeciesId;
+            }
+            sizes[dst] += sizes[src];
+            sizes[src] = 0;
+            merged += 1;
+        }
+
+        let cap = ((max_frac * ids.len() as f64).floor() as usize).max(1);
+        let mut kept = vec![0; num + 1];
+        let mut reassigned = 0;
+        for (i, id) in ids.iter_mut().enumerate() {
+            let src = *id as usize;
+            if kept[src] < cap {
+                kept[src] += 1;
+            } else if let Some(dst) = nearest(&sizes, i, src, cap) {
+                *id = dst as SpeciesId;
+                sizes[src] -= 1;
+                sizes[dst] += 1;
+                reassigned += 1;
+            }
+        }
+
+        let mut renumber = vec![NO_SPECIES; num + 1];
+        let mut next = 0;
+        for id in 1..=num {
+            if sizes[id] > 0 {
+                next += 1;
+                renumber[id] = next;
+            }
+        }
+        for id in ids.iter_mut() {
+            *id = renumber[*id as usize];
+        }
+        info.num = next;
+        info.merged = merged;
+        info.reassigned = reassigned;
     }
 
     pub fn shared_fitness<S: State>(&self, s: &mut [Member<S>], radius: f64, alpha: f64) {
-        // Compute fitness as F'(i) = F(i) / sum of 1 - (d(i, j) / species_radius) ^ alpha.
+        // Compute fitness as F'(i) = F(i) / sum of 1 - (d(i, j) / species_radius) ^ alpha,
+        // where F(i) is the selection fitness so far.
         for i in 0..s.len() {
             let mut sum = 0.0;
             for j in 0..s.len() {
@@ -123,7 +335,7 @@ impl DistCache {
                     sum += 1.0 - (d / radius).powf(alpha);
                 }
             }
-            s[i].selection_fitness = s[i].fitness / sum;
+            s[i].selection_fitness /= sum;
         }
     }
 
@@ -133,11 +345,37 @@ impl DistCache {
         self.shared_fitness(s, species.radius, alpha);
     }
 
+    /// Approximate |q| quantile of all pairwise distances, including each
+    /// member's distance to itself. Uses a random sample of the distances for
+    /// large populations. The cache must be computed.
+    #[must_use]
+    pub fn quantile(&self, q: f64) -> f64 {
+        self.quantile_rng(q, &mut rand::thread_rng())
+    }
+
+    #[must_use]
+    pub fn quantile_rng<R: Rng + ?Sized>(&self, q: f64, r: &mut R) -> f64 {
+        const MAX_SAMPLE: usize = 4096;
+        let mut dists = if self.cache.len() <= MAX_SAMPLE {
+            self.cache.clone()
+        } else {
+            sample(r, self.cache.len(), MAX_SAMPLE).into_iter().map(|i| self.cache[i]).collect()
+        };
+        let k = ((dists.len() - 1) as f64 * q.clamp(0.0, 1.0)).round() as usize;
+        *dists.select_nth_unstable_by(k, f64::total_cmp).1
+    }
+
     #[must_use]
     pub fn is_empty(&self) -> bool {
         self.cache.is_empty()
     }
 
+    /// Distance between members |i| and |j|. The cache must be computed.
+    #[must_use]
+    pub fn dist(&self, i: usize, j: usize) -> f64 {
+        self.cache[i * self.n + j]
+    }
+
     #[must_use]
     pub fn mean(&self) -> f64 {
         self.sum / ((self.n * self.n) as f64)
@@ -149,6 +387,42 @@ impl DistCache {
     }
 }
 
+/// Picks the species target for the next generation for
+/// `Species::AutoTarget`. Starts from the number of species actually found this
+/// generation, which may differ from the requested target if the radius search
+/// could not hit it. If the median species is tiny, the population is being
+/// split too finely, so decrease the target. If the largest species holds too
+/// much of the population, niching isn't doing anything, so increase it.
+#[must_use]
+pub fn auto_species_target<S: State>(
+    mems: &[Member<S>],
+    species: &SpeciesInfo,
+    min: SpeciesId,
+    max: SpeciesId,
+) -> SpeciesId {
+    const MIN_MEDIAN_SIZE: usize = 2;
+    const MAX_SPECIES_FRAC: f64 = 0.5;
+
+    let mut sizes = vec![0; species.num as usize + 1];
+    for mem in mems {
+        sizes[mem.species as usize] += 1;
+    }
+    sizes.retain(|&v| v > 0);
+    sizes.sort_unstable();
+    let target = species.num.max(1);
+    let Some(&largest) = sizes.last() else { return target.clamp(min, max) };
+    let median = sizes[sizes.len() / 2];
+
+    let target = if median < MIN_MEDIAN_SIZE {
+        target - 1
+    } else if largest as f64 > mems.len() as f64 * MAX_SPECIES_FRAC {
+        target + 1
+    } else {
+        target
+    };
+    target.clamp(min, max)
+}
+
 impl Default for DistCache {
     fn default() -> Self {
         Self::new()
@@ -162,3 +436,113 @@ impl Index<(usize, usize)> for DistCache {
         &self.cache[i.0 * self.n + i.1]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_utils::MockEvaluator;
+
+    // Distances between members at |points| on a line.
+    fn line(points: &[f64]) -> DistCache {
+        let n = points.len();
+        let cache: Vec<f64> =
+            points.iter().flat_map(|a| points.iter().map(move |b| (a - b).abs())).collect();
+        let max = cache.iter().copied().fold(0.0, f64::max);
+        let sum = cache.iter().sum();
+        DistCache { n, cache, max, sum }
+    }
+
+    fn bound(points: &[f64], ids: &[SpeciesId], min_size: usize, max_frac: f64) -> Vec<SpeciesId> {
+        let mut ids = ids.to_vec();
+        let mut info = SpeciesInfo::new();
+        line(points).bound_species(&mut ids, &mut info, min_size, max_frac);
+        // Ids are contiguous and every member is still assigned.
+        let mut seen = ids.clone();
+        seen.sort_unstable();
+        seen.dedup();
+        assert_eq!(seen, (1..=info.num).collect::<Vec<_>>());
+        ids
+    }
+
+    #[test]
+    fn bound_species_trims_oversized() {
+        let points = [0.0, 0.1, 0.2, 0.3, 19.0, 9.0, 8.0, 10.0, 10.5, 20.0];
+        let ids = [1, 1, 1, 1, 1, 1, 1, 2, 2, 3];
+        let mut out = ids.to_vec();
+        let mut info = SpeciesInfo::new();
+        line(&points).bound_species(&mut out, &mut info, 0, 0.4);
+        assert_eq!(out, [1, 1, 1, 1, 3, 2, 2, 2, 2, 3]);
+        assert_eq!((info.num, info.merged, info.reassigned), (3, 0, 3));
+
+        // Nowhere to move members to.
+        assert_eq!(bound(&[0.0, 1.0, 2.0], &[1, 1, 1], 0, 0.1), [1, 1, 1]);
+    }
+
+    #[test]
+    fn bound_species_merges_undersized() {
+        let points = [0.0, 0.0, 0.0, 10.0, 10.0, 10.0, 9.0, 1.0];
+        let ids = [1, 1, 1, 2, 2, 2, 3, 4];
+        let mut out = ids.to_vec();
+        let mut info = SpeciesInfo::new();
+        line(&points).bound_species(&mut out, &mut info, 2, 1.0);
+        assert_eq!(out, [1, 1, 1, 2, 2, 2, 2, 1]);
+        assert_eq!((info.num, info.merged, info.reassigned), (2, 2, 0));
+
+        // Merged species are renumbered to stay contiguous.
+        assert_eq!(bound(&[0.0, 5.0, 6.0, 9.0, 9.0], &[1, 2, 3, 3, 3], 2, 1.0), [1, 1, 2, 2, 2]);
+    }
+
+    #[test]
+    fn bound_species_conserves_members() {
+        let points = (0..20).map(|i| f64::from(i % 7)).collect::<Vec<_>>();
+        let ids = [1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 3, 3, 3, 4, 5, 5, 6];
+        for (min_size, max_frac) in [(0, 0.25), (2, 1.0), (2, 0.3), (3, 0.5)] {
+            let out = bound(&points, &ids, min_size, max_frac);
+            assert_eq!(out.len(), ids.len());
+            let mut sizes = vec![0; *out.iter().max().unwrap() as usize];
+            for &id in &out {
+                sizes[id as usize - 1] += 1;
+            }
+            assert_eq!(sizes.iter().sum::<usize>(), ids.len());
+            assert!(sizes.iter().all(|&v| v >= min_size), "{min_size} {max_frac} {sizes:?}");
+            let cap = (max_frac * ids.len() as f64).floor() as usize;
+            assert!(sizes.iter().all(|&v| v <= cap), "{min_size} {max_frac} {sizes:?}");
+        }
+    }
+
+    const STATES: [i64; 4] = [0, 1, 5, 9];
+
+    // Distance between integers given by |f|.
+    fn metric_err(f: impl Fn(i64, i64) -> f64 + Send + Sync + 'static) -> Option<MetricError> {
+        let eval: MockEvaluator<i64> =
+            MockEvaluator::with(|s, ()| Ok(*s as f64), move |s1, s2| Ok(f(*s1, *s2)));
+        let err = validate_distance_metric(&eval, &STATES, 16).err()?;
+        Some(*err.downcast_ref::<MetricError>().unwrap())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn broken_metrics() {
+        assert_eq!(metric_err(|a, b| (a - b).abs() as f64), None);
+        assert!(
+            matches!(metric_err(|_, _| 1.0), Some(MetricError::NonZeroSelf { d, .. }) if d == 1.0)
+        );
+        assert!(matches!(metric_err(|a, b| (a - b) as f64), Some(MetricError::Negative { .. })));
+
+        // Further from smaller to larger states than the other way.
+        let lopsided = |a: i64, b: i64| (a - b).abs() as f64 * if a < b { 2.0 } else { 1.0 };
+        let Some(MetricError::Asymmetric { i, j, ij, ji }) = metric_err(lopsided) else {
+            panic!("expected an asymmetric pair");
+        };
+        assert_ne!(i, j);
+        assert_eq!((ij, ji), (lopsided(STATES[i], STATES[j]), lopsided(STATES[j], STATES[i])));
+
+        // Cached distances are checked the same way.
+        assert!(line(&[0.0, 1.0, 5.0]).validate(16).is_ok());
+        let cache = DistCache { n: 2, cache: vec![0.0, 1.0, 3.0, 0.0], max: 3.0, sum: 4.0 };
+        let err = cache.validate(16).unwrap_err();
+        let want = [(0, 1, 1.0, 3.0), (1, 0, 3.0, 1.0)]
+            .map(|(i, j, ij, ji)| MetricError::Asymmetric { i, j, ij, ji });
+        assert!(want.contains(err.downcast_ref::<MetricError>().unwrap()), "{err}");
+    }
+}
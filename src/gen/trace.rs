@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use ahash::HashMap;
+
+use crate::eval::State;
+use crate::gen::member::{Member, MemberId};
+use crate::gen::params::Params;
+use crate::util::fmt::fmt_fitness;
+
+/// How a pair of children was bred, recorded when `EvolveCfg::trace` is set.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct BreedingEvent {
+    pub parents: [MemberId; 2],
+    /// Indices of the parents in the generation they were selected from,
+    /// which is sorted by fitness.
+    pub parent_idxs: [usize; 2],
+    pub parent_fitness: [f64; 2],
+    /// Crossover operator applied, or None if crossover was skipped.
+    pub crossover: Option<usize>,
+    /// Crossover weights the operator was chosen with.
+    pub crossover_weights: Vec<f64>,
+    /// Whether crossover index 0 is the no-op, from
+    /// `Evaluator::CROSSOVER_HAS_NOOP`.
+    pub crossover_has_noop: bool,
+    /// Mutation rates applied to each child, indexed by mutation operator.
+    pub mutation_rates: [Vec<f64>; 2],
+    pub children: [MemberId; 2],
+}
+
+/// How many children were fitter than their better parent.
+#[must_use]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ImprovementCount {
+    pub children: usize,
+    pub improved: usize,
+}
+
+impl ImprovementCount {
+    /// Fraction of children which improved, or None if there were none.
+    #[must_use]
+    pub fn rate(&self) -> Option<f64> {
+        (self.children > 0).then(|| self.improved as f64 / self.children as f64)
+    }
+
+    fn add(&mut self, improved: bool) {
+        self.children += 1;
+        self.improved += usize::from(improved);
+    }
+}
+
+/// How productive breeding was for a generation, computed from its trace
+/// once it has been evaluated. Only children count, not survivors or
+/// injected individuals. Differential evolution trials aren't bred in pairs
+/// so aren't traced, and don't count either.
+#[must_use]
+#[derive(Debug, Default, Clone, PartialEq, PartialOrd)]
+pub struct Improvement {
+    pub all: ImprovementCount,
+    /// Counts by crossover operator, with None for skipped crossover.
+    pub by_crossover: BTreeMap<Option<usize>, ImprovementCount>,
+    /// Counts for children only changed by mutation, i.e. those which skipped
+    /// crossover or had the no-op crossover operator applied.
+    pub mutation_only: ImprovementCount,
+}
+
+impl Improvement {
+    /// Compares each child in |trace| against its parents' fitness. |mems|
+    /// must have been evaluated. Children no longer in |mems|, e.g. removed as
+    /// duplicates, are skipped.
+    pub fn from_trace<S: State>(trace: &[BreedingEvent], mems: &[Member<S>]) -> Self {
+        let fitness = mems.iter().map(|v| (v.id, v.fitness)).collect::<HashMap<_, _>>();
+        let mut improvement = Self::default();
+        for ev in trace {
+            let best_parent = ev.parent_fitness[0].max(ev.parent_fitness[1]);
+            for id in ev.children {
+                let Some(&fitness) = fitness.get(&id) else { continue };
+                let improved = fitness > best_parent;
+                improvement.all.add(improved);
+                improvement.by_crossover.entry(ev.crossover).or_default().add(improved);
+                if ev.crossover.is_none() || (ev.crossover_has_noop && ev.crossover == Some(0)) {
+                    improvement.mutation_only.add(improved);
+                }
+            }
+        }
+        improvement
+    }
+}
+
+/// Renders how each of the top |k| members of |mems| was bred, using |trace|
+/// from the generation they were bred in. |mems| must be sorted by fitness,
+/// as in an evaluated generation.
+#[must_use]
+pub fn format_trace<S: State>(trace: &[BreedingEvent], mems: &[Member<S>], k: usize) -> String {
+    let mut s = String::new();
+    for (rank, mem) in mems.iter().take(k).enumerate() {
+        let _ = write!(s, "{rank:>3}: member {} fitness {}", mem.id, fmt_fitness(mem.fitness));
+        let Some((ev, child)) = trace.iter().find_map(|ev| {
+            ev.children.iter().position(|&id| id == mem.id).map(|child| (ev, child))
+        }) else {
+            let _ = writeln!(s, ", not bred this generation");
+            continue;
+        };
+        let _ = writeln!(s);
+        let _ = writeln!(
+            s,
+            "     parents {} (#{}, fitness {}) x {} (#{}, fitness {})",
+            ev.parents[0],
+            ev.parent_idxs[0],
+            fmt_fitness(ev.parent_fitness[0]),
+            ev.parents[1],
+            ev.parent_idxs[1],
+            fmt_fitness(ev.parent_fitness[1]),
+        );
+        let label = |idx| Params::crossover_label(idx, ev.crossover_has_noop);
+        match ev.crossover {
+            Some(idx) => {
+                let _ = write!(s, "     crossover {} of weights ", label(idx));
+                for (idx, v) in ev.crossover_weights.iter().enumerate() {
+                    let sep = if idx == 0 { "" } else { ", " };
+                    let _ = write!(s, "{sep}{} {v:.3}", label(idx));
+                }
+                let _ = writeln!(s);
+            }
+            None => {
+                let _ = writeln!(s, "     no crossover");
+            }
+        }
+        let _ = writeln!(s, "     mutation rates {:.3?}", ev.mutation_rates[child]);
+    }
+    s
+}
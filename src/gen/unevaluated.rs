@@ -1,21 +1,109 @@
 use std::cmp::Ordering;
+use std::mem;
+use std::time::Instant;
 
 use approx::relative_eq;
+use enumset::EnumSet;
 use eyre::{eyre, Result};
-use rayon::prelude::*;
+use rand::rngs::StdRng;
+use rand::seq::index;
+use rand::{RngCore, SeedableRng};
 
 use crate::eval::{Evaluator, State};
-use crate::evolve::cfg::{EvolveCfg, Niching, Species};
+use crate::evolve::cfg::{
+    Comparison, ConstraintMode, EvolveCfg, FitnessStage, Niching, OptionalPhase, Species,
+};
 use crate::gen::evaluated::EvaluatedGen;
+use crate::gen::fitness::{age_decay, penalty, power, rank};
 use crate::gen::member::Member;
-use crate::gen::species::{DistCache, SpeciesInfo};
+use crate::gen::ranking::stochastic_order;
+use crate::gen::species::{DistCache, SpeciesId, SpeciesInfo, NO_SPECIES};
+use crate::gen::trace::{BreedingEvent, Improvement};
+use crate::util::par::try_for_each_chunk_mut;
+
+const SHARING_ALPHA: f64 = 6.0; // Default alpha between 5 and 10.
+
+/// Members competing for one place in a generation, settled when it's
+/// evaluated by evaluating them in the same way as the rest of it.
+#[must_use]
+#[derive(Debug, Clone, PartialOrd, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Contest<M> {
+    pub kind: ContestKind,
+    pub mems: Vec<M>,
+}
+
+#[must_use]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ContestKind {
+    // Random individuals for `ReplacementFilter::BeatWorst`, evaluated in
+    // turn. The first which beats the worst survivor wins, or else the best.
+    BeatWorst,
+    // A member, aged like a survivor, then its differential evolution trial,
+    // which replaces it if at least as good.
+    Trial,
+}
 
 #[must_use]
 #[derive(Clone, PartialOrd, PartialEq)]
 pub struct UnevaluatedGen<S: State> {
     pub mems: Vec<Member<S>>,
+    /// Members competing for the remaining places. `evaluate` adds the winner
+    /// of each to |mems|.
+    pub contests: Vec<Contest<Member<S>>>,
     pub species: SpeciesInfo,
     pub dists: DistCache,
+    /// Number of species to aim for when speciating. For
+    /// `Species::AutoTarget` this is set by the `Evolver` before evaluation.
+    pub species_target: SpeciesId,
+    /// Index of this generation, set by the `Evolver` before evaluation. Used
+    /// to derive per-member fitness rngs.
+    pub gen_idx: usize,
+    /// Number of random individuals injected into this generation due to
+    /// stagnation.
+    pub injected: usize,
+    /// Number of random individuals evaluated and rejected by
+    /// `EvolveCfg::replacement_filter`, set by `evaluate`.
+    pub filtered: usize,
+    /// Number of injected individuals which didn't beat the worst survivor,
+    /// since no candidate for their slot did, set by `evaluate`.
+    pub unqualified: usize,
+    /// Fitness evaluations made by `evaluate` on the random individuals
+    /// considered for `EvolveCfg::replacement_filter`.
+    pub filter_evals: usize,
+    /// Fitness evaluations made by `evaluate` on differential evolution trials
+    /// and the members they would replace.
+    pub trial_evals: usize,
+    /// Number of children of different species bred into this generation due
+    /// to stagnation, for `Replacement::HybridizeSpecies`.
+    pub hybrids: usize,
+    /// Number of duplicate members removed when creating this generation.
+    pub dups_removed: usize,
+    /// Number of children in this generation local search was applied to,
+    /// for `EvolveCfg::local_search`, whether or not it improved them.
+    pub local_searched: usize,
+    /// Fitness evaluations made by local search on this generation.
+    pub local_search_evals: usize,
+    /// Fitness evaluations made by `evaluate`, one per member and input.
+    /// Members sharing an evaluation, for `EvolveCfg::share_duplicate_fitness`,
+    /// count once.
+    pub fitness_evals: usize,
+    /// How each child in this generation was bred, if `EvolveCfg::trace` is
+    /// set.
+    pub trace: Option<Vec<BreedingEvent>>,
+    /// How many children bred into this generation were fitter than their
+    /// better parent, set by `evaluate` if |trace| is set.
+    pub improvement: Option<Improvement>,
+    /// Time by which optional work must be done, set by the `Evolver` before
+    /// evaluation if there is a generation time budget.
+    pub deadline: Option<Instant>,
+    /// Optional work skipped for this generation to meet |deadline|.
+    pub skipped: EnumSet<OptionalPhase>,
+    /// Whether to check the distance metric once distances are computed, set
+    /// by the `Evolver` if `EvolveCfg::validate_distance` is set and no earlier
+    /// generation computed them.
+    pub validate_dists: bool,
 }
 
 impl<S: State> UnevaluatedGen<S> {
@@ -26,7 +114,35 @@ impl<S: State> UnevaluatedGen<S> {
 
     pub fn new(mems: Vec<Member<S>>) -> Self {
         assert!(!mems.is_empty(), "Generation must not be empty");
-        Self { mems, species: SpeciesInfo::new(), dists: DistCache::new() }
+        Self { mems, ..Self::empty() }
+    }
+
+    // A generation with no members, for an `Evolver` which couldn't make
+    // any. It must not be evaluated.
+    pub(crate) fn empty() -> Self {
+        Self {
+            mems: Vec::new(),
+            contests: Vec::new(),
+            species: SpeciesInfo::new(),
+            dists: DistCache::new(),
+            species_target: NO_SPECIES,
+            gen_idx: 0,
+            injected: 0,
+            filtered: 0,
+            unqualified: 0,
+            filter_evals: 0,
+            trial_evals: 0,
+            hybrids: 0,
+            dups_removed: 0,
+            local_searched: 0,
+            local_search_evals: 0,
+            fitness_evals: 0,
+            trace: None,
+            improvement: None,
+            deadline: None,
+            skipped: EnumSet::new(),
+            validate_dists: false,
+        }
     }
 
     pub fn evaluate<E: Evaluator<State = S>>(
@@ -35,67 +151,890 @@ impl<S: State> UnevaluatedGen<S> {
         cfg: &EvolveCfg,
         eval: &E,
     ) -> Result<EvaluatedGen<S>> {
-        // First compute plain fitnesses.
-        let compute = |s: &mut Member<S>| -> Result<()> {
-            s.fitness = eval.multi_fitness(&s.state, inputs, cfg.fitness_reduction)?;
+        self.evaluate_rng(inputs, cfg, eval, &mut rand::thread_rng())
+    }
+
+    /// Like `evaluate`, but draws random choices outside of fitness, e.g.
+    /// for stochastic ranking, from |r|. Fitness uses
+    /// `EvolveCfg::fitness_seed` instead.
+    pub fn evaluate_rng<E: Evaluator<State = S>>(
+        &mut self,
+        inputs: &[E::Data],
+        cfg: &EvolveCfg,
+        eval: &E,
+        r: &mut dyn RngCore,
+    ) -> Result<EvaluatedGen<S>> {
+        if cfg.comparison != Comparison::None && !self.contests.is_empty() {
+            return Err(eyre!("contests for places aren't supported with comparative fitness"));
+        }
+        // First compute plain fitnesses. Comparative fitness needs the whole
+        // population, so is computed afterwards.
+        // Only one member of each group of equal states is evaluated, with its
+        // index, fitness and violation.
+        let groups = if cfg.share_duplicate_fitness {
+            equal_groups(&self.mems)
+        } else {
+            (0..self.mems.len()).map(|i| vec![i]).collect()
+        };
+        let mems = &self.mems;
+        let mut reps = groups
+            .iter()
+            .map(|g| (g[0], mems[g[0]].fitness, mems[g[0]].violation))
+            .collect::<Vec<_>>();
+        let gen_idx = self.gen_idx;
+        let comparative = cfg.comparison != Comparison::None;
+        let compute = |(idx, fitness, violation): &mut (usize, f64, f64)| -> Result<()> {
+            let s = &*mems[*idx].state;
+            if !comparative {
+                *fitness = member_fitness(s, gen_idx, *idx, inputs, cfg, eval)?;
+            }
+            if cfg.needs_violation() {
+                *violation = max_violation(s, inputs, eval)?;
+            }
             Ok(())
         };
-        if cfg.par_fitness {
-            self.mems.par_iter_mut().try_for_each(compute)?;
+        let chunk_size = cfg.par_chunk_size(reps.len());
+        try_for_each_chunk_mut(&mut reps, cfg.par_fitness, chunk_size, |_, reps| {
+            reps.iter_mut().try_for_each(&compute)
+        })?;
+        for (group, &(_, fitness, violation)) in groups.iter().zip(&reps) {
+            for &i in group {
+                self.mems[i].fitness = fitness;
+                self.mems[i].violation = violation;
+            }
+        }
+
+        self.fitness_evals = if comparative {
+            self.win_rates(inputs, cfg, eval)?
         } else {
-            self.mems.iter_mut().try_for_each(compute)?;
+            reps.len() * inputs.len()
         };
+        self.mems.iter().try_for_each(check_values)?;
+        self.settle(inputs, cfg, eval)?;
 
-        // Check fitnesses are non-negative and finite.
-        if !self.mems.iter().map(|v| v.fitness).all(|v| v >= 0.0 && v.is_finite()) {
-            return Err(eyre!("got negative or non-finite fitness"));
+        if let Some(trace) = &self.trace {
+            self.improvement = Some(Improvement::from_trace(trace, &self.mems));
         }
 
         // Sort by fitnesses.
         self.mems.sort_unstable_by(|a, b| b.fitness.partial_cmp(&a.fitness).unwrap());
 
+        // Distances are optional work, so with a deadline compute them up
+        // front and skip everything which needs them if they don't finish.
+        let stages = cfg.fitness_stages();
+        let needs_dists = cfg.species != Species::None
+            || stages.iter().any(|v| matches!(v, FitnessStage::Niching(n) if *n != Niching::None));
+        if needs_dists && self.deadline.is_some() && !self.ensure_dists_until(cfg, eval)? {
+            self.skipped |= OptionalPhase::Distances;
+        }
+        let species = if self.skipped.contains(OptionalPhase::Distances) {
+            Species::None
+        } else {
+            cfg.species
+        };
+
         // Speciate if necessary.
-        match cfg.species {
+        match species {
             Species::None => {}
             Species::TargetNumber(target) => {
-                self.dists.ensure(&self.mems, cfg.par_dist, eval)?;
-                let mut lo = 0.0;
-                let mut hi = self.dists.max();
-                let mut ids = Vec::new();
-                while !relative_eq!(lo, hi, epsilon = 1.0e-6) {
-                    let r = (lo + hi) / 2.0;
-                    (ids, self.species) = self.dists.speciate(&self.mems, r);
-                    match self.species.num.cmp(&target) {
-                        Ordering::Less => hi = self.species.radius,
-                        Ordering::Equal => break,
-                        Ordering::Greater => lo = self.species.radius,
+                self.species_target = target;
+                self.speciate(cfg, eval)?;
+            }
+            Species::AutoTarget { min, max } => {
+                self.species_target = self.species_target.clamp(min, max);
+                self.speciate(cfg, eval)?;
+            }
+            Species::TargetNumberBounded { target, min_size, max_frac } => {
+                self.species_target = target;
+                self.speciate(cfg, eval)?;
+                let mut ids = self.mems.iter().map(|v| v.species).collect::<Vec<_>>();
+                self.dists.bound_species(&mut ids, &mut self.species, min_size, max_frac.get());
+                for (mem, id) in self.mems.iter_mut().zip(ids) {
+                    mem.species = id;
+                }
+            }
+        }
+
+        // Rank after speciating, which needs members sorted by fitness.
+        let order = match cfg.constraint_mode {
+            ConstraintMode::None => (0..self.mems.len()).collect(),
+            ConstraintMode::StochasticRanking { pf } => stochastic_order(&self.mems, pf, r),
+        };
+
+        // Transform fitness into selection fitness.
+        for v in &mut self.mems {
+            v.selection_fitness = v.fitness;
+        }
+        for stage in stages {
+            match stage {
+                FitnessStage::Niching(niching) => self.niche(niching, cfg, eval, r)?,
+                FitnessStage::Rank => rank(&mut self.mems, &order),
+                FitnessStage::AgeDecay(decay) => age_decay(&mut self.mems, decay),
+                FitnessStage::Power(exponent) => power(&mut self.mems, exponent),
+                FitnessStage::Penalty(weight) => penalty(&mut self.mems, weight),
+            }
+        }
+
+        Ok(match cfg.constraint_mode {
+            ConstraintMode::None => EvaluatedGen::new(self.mems.clone()),
+            ConstraintMode::StochasticRanking { .. } => {
+                self.mems = order.into_iter().map(|i| self.mems[i].clone()).collect();
+                EvaluatedGen::from_ranked(self.mems.clone())
+            }
+        })
+    }
+
+    /// Fraction of members which are (near) copies of the best member, i.e.
+    /// within |epsilon| distance of it. Falls back to counting members equal
+    /// to the best if distances weren't computed. Call after `evaluate`, which
+    /// puts the best member first.
+    #[must_use]
+    pub fn takeover_fraction(&self, epsilon: f64) -> f64 {
+        let copies = if self.dists.is_empty() {
+            self.mems.iter().filter(|v| v.state == self.mems[0].state).count()
+        } else {
+            (0..self.mems.len()).filter(|&i| self.dists.dist(0, i) <= epsilon).count()
+        };
+        copies as f64 / self.mems.len() as f64
+    }
+
+    // Settles |contests|, adding the winner of each to the generation. Their
+    // members are evaluated as if they were members of the generation, in the
+    // place the winner takes. Survivors are members older than 0.
+    fn settle<E: Evaluator<State = S>>(
+        &mut self,
+        inputs: &[E::Data],
+        cfg: &EvolveCfg,
+        eval: &E,
+    ) -> Result<()> {
+        // Members compare by feasibility first, then fitness.
+        let key = |v: &Member<S>| (-v.violation, v.fitness);
+        let cmp = |a: &(f64, f64), b: &(f64, f64)| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1));
+        let beats = |a: (f64, f64), b: (f64, f64)| cmp(&a, &b) == Ordering::Greater;
+        let worst = self.mems.iter().filter(|v| v.age > 0).map(key).min_by(cmp);
+        for contest in mem::take(&mut self.contests) {
+            let idx = self.mems.len();
+            let mut winner: Option<Member<S>> = None;
+            let mut evaluated = 0;
+            for mut mem in contest.mems {
+                mem.fitness = member_fitness(&*mem.state, self.gen_idx, idx, inputs, cfg, eval)?;
+                if cfg.needs_violation() {
+                    mem.violation = max_violation(&*mem.state, inputs, eval)?;
+                }
+                check_values(&mem)?;
+                evaluated += 1;
+                match contest.kind {
+                    ContestKind::BeatWorst => {
+                        let qualifies = worst.is_none_or(|v| beats(key(&mem), v));
+                        if winner.as_ref().is_none_or(|v| beats(key(&mem), key(v))) {
+                            winner = Some(mem);
+                        }
+                        if qualifies {
+                            break;
+                        }
+                    }
+                    ContestKind::Trial => {
+                        if winner.as_ref().is_none_or(|v| !beats(key(v), key(&mem))) {
+                            winner = Some(mem);
+                        }
                     }
                 }
-                // Assign species into mems if speciated.
-                for (i, &id) in ids.iter().enumerate() {
-                    self.mems[i].species = id;
+            }
+            let winner = winner.ok_or_else(|| eyre!("contest for a place has no members"))?;
+            match contest.kind {
+                ContestKind::BeatWorst => {
+                    self.filtered += evaluated - 1;
+                    self.unqualified += usize::from(!worst.is_none_or(|v| beats(key(&winner), v)));
+                    self.filter_evals += evaluated * inputs.len();
                 }
+                ContestKind::Trial => self.trial_evals += evaluated * inputs.len(),
             }
+            self.mems.push(winner);
         }
+        Ok(())
+    }
 
-        // Transform fitness if necessary.
-        match cfg.niching {
-            Niching::None => {
-                for v in &mut self.mems {
-                    v.selection_fitness = v.fitness;
+    // Sets each member's fitness to its win rate for `EvolveCfg::comparison`.
+    // A member with no games gets 0.5, as if it tied. Returns the number of
+    // comparisons made.
+    fn win_rates<E: Evaluator<State = S>>(
+        &mut self,
+        inputs: &[E::Data],
+        cfg: &EvolveCfg,
+        eval: &E,
+    ) -> Result<usize> {
+        let n = self.mems.len();
+        let opponents = match cfg.comparison {
+            Comparison::None => return Ok(0),
+            Comparison::RoundRobin => n - 1,
+            Comparison::Sampled { opponents } => opponents.min(n - 1),
+        };
+        let (gen_idx, mems) = (self.gen_idx, &self.mems);
+        let mut rates = vec![0.5; n];
+        let chunk_size = cfg.par_chunk_size(n);
+        try_for_each_chunk_mut(&mut rates, cfg.par_fitness, chunk_size, |i, rates| {
+            for (j, rate) in rates.iter_mut().enumerate() {
+                let idx = i * chunk_size + j;
+                let others = if opponents == n - 1 {
+                    (0..n - 1).collect()
+                } else {
+                    let mut r = match cfg.fitness_seed {
+                        Some(seed) => member_rng(seed, gen_idx, idx),
+                        None => StdRng::from_rng(rand::thread_rng())?,
+                    };
+                    index::sample(&mut r, n - 1, opponents).into_vec()
+                };
+                let mut wins = 0.0;
+                for other in others {
+                    // Skip over |idx| so members never play themselves.
+                    let other = if other >= idx { other + 1 } else { other };
+                    for data in inputs {
+                        wins += match eval.compare(&mems[idx].state, &mems[other].state, data)? {
+                            Ordering::Greater => 1.0,
+                            Ordering::Equal => 0.5,
+                            Ordering::Less => 0.0,
+                        };
+                    }
+                }
+                let games = opponents * inputs.len();
+                if games > 0 {
+                    *rate = wins / games as f64;
                 }
             }
+            Ok(())
+        })?;
+        for (mem, rate) in self.mems.iter_mut().zip(rates) {
+            mem.fitness = rate;
+        }
+        Ok(n * opponents * inputs.len())
+    }
+
+    // Shares selection fitness between nearby members. Does nothing if
+    // distances were skipped.
+    fn niche<E: Evaluator<State = S>>(
+        &mut self,
+        niching: Niching,
+        cfg: &EvolveCfg,
+        eval: &E,
+        r: &mut dyn RngCore,
+    ) -> Result<()> {
+        if self.skipped.contains(OptionalPhase::Distances) {
+            return Ok(());
+        }
+        match niching {
+            Niching::None => {}
             Niching::SharedFitness(radius) => {
-                const ALPHA: f64 = 6.0; // Default alpha between 5 and 10.
-                self.dists.ensure(&self.mems, cfg.par_dist, eval)?;
-                self.dists.shared_fitness(&mut self.mems, radius, ALPHA);
+                self.ensure_dists(cfg, eval)?;
+                self.dists.shared_fitness(&mut self.mems, radius, SHARING_ALPHA);
+                self.species.share_radius = Some(radius);
             }
             Niching::SpeciesSharedFitness => {
-                self.dists.ensure(&self.mems, cfg.par_dist, eval)?;
+                self.ensure_dists(cfg, eval)?;
                 self.dists.species_shared_fitness(&mut self.mems, &self.species);
+                self.species.share_radius = Some(self.species.radius);
+            }
+            Niching::SharedFitnessAuto { target_fraction } => {
+                self.ensure_dists(cfg, eval)?;
+                // Keep the radius positive so every member at least shares
+                // with itself, even if most of the population are copies.
+                let radius = self.dists.quantile_rng(target_fraction, r).max(f64::MIN_POSITIVE);
+                self.dists.shared_fitness(&mut self.mems, radius, SHARING_ALPHA);
+                self.species.share_radius = Some(radius);
+            }
+        }
+        Ok(())
+    }
+
+    fn ensure_dists<E: Evaluator<State = S>>(&mut self, cfg: &EvolveCfg, eval: &E) -> Result<()> {
+        let chunk_size = cfg.par_chunk_size(self.mems.len() * self.mems.len());
+        self.dists.ensure(&self.mems, cfg.par_dist, chunk_size, eval)?;
+        self.check_metric()
+    }
+
+    // Like `ensure_dists`, but gives up at |self.deadline|. Returns whether
+    // the distances were computed.
+    fn ensure_dists_until<E: Evaluator<State = S>>(
+        &mut self,
+        cfg: &EvolveCfg,
+        eval: &E,
+    ) -> Result<bool> {
+        let chunk_size = cfg.par_chunk_size(self.mems.len() * self.mems.len());
+        let filled =
+            self.dists.ensure_until(&self.mems, cfg.par_dist, chunk_size, eval, self.deadline)?;
+        if filled {
+            self.check_metric()?;
+        }
+        Ok(filled)
+    }
+
+    // Checks a sample of the computed distances form a metric, the first time
+    // this is called if |validate_dists| is set.
+    fn check_metric(&mut self) -> Result<()> {
+        const SAMPLES: usize = 16;
+        if mem::take(&mut self.validate_dists) {
+            self.dists.validate(SAMPLES)?;
+        }
+        Ok(())
+    }
+
+    // Binary search for a radius that gives |species_target| species.
+    fn speciate<E: Evaluator<State = S>>(&mut self, cfg: &EvolveCfg, eval: &E) -> Result<()> {
+        self.ensure_dists(cfg, eval)?;
+        let mut lo = 0.0;
+        let mut hi = self.dists.max();
+        let mut ids = Vec::new();
+        while !relative_eq!(lo, hi, epsilon = 1.0e-6) {
+            let r = (lo + hi) / 2.0;
+            (ids, self.species) = self.dists.speciate(&self.mems, r);
+            match self.species.num.cmp(&self.species_target) {
+                Ordering::Less => hi = self.species.radius,
+                Ordering::Equal => break,
+                Ordering::Greater => lo = self.species.radius,
+            }
+        }
+        // Assign species into mems if speciated.
+        for (i, &id) in ids.iter().enumerate() {
+            self.mems[i].species = id;
+        }
+        Ok(())
+    }
+}
+
+// Indices of |mems| grouped by equal state, ordered by their first index.
+// States are only compared for equality, with the first member of each group,
+// so they needn't be totally ordered. A state which isn't equal to itself,
+// e.g. containing NaN, is in a group of its own.
+fn equal_groups<S: State>(mems: &[Member<S>]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (i, mem) in mems.iter().enumerate() {
+        match groups.iter_mut().find(|g| mems[g[0]].state == mem.state) {
+            Some(g) => g.push(i),
+            None => groups.push(vec![i]),
+        }
+    }
+    groups
+}
+
+// Fitness of |s|, the member at |idx| of generation |gen_idx|, on |inputs|.
+fn member_fitness<E: Evaluator>(
+    s: &E::State,
+    gen_idx: usize,
+    idx: usize,
+    inputs: &[E::Data],
+    cfg: &EvolveCfg,
+    eval: &E,
+) -> Result<f64> {
+    match cfg.fitness_seed {
+        Some(seed) => {
+            let mut r = member_rng(seed, gen_idx, idx);
+            eval.multi_fitness_rng(s, inputs, cfg.fitness_reduction, &mut r)
+        }
+        None => eval.multi_fitness(s, inputs, cfg.fitness_reduction),
+    }
+}
+
+// Largest constraint violation of |s| over |inputs|.
+fn max_violation<E: Evaluator>(s: &E::State, inputs: &[E::Data], eval: &E) -> Result<f64> {
+    let mut violation: f64 = 0.0;
+    for data in inputs {
+        violation = violation.max(eval.violation(s, data)?);
+    }
+    Ok(violation)
+}
+
+// Checks fitness and constraint violation are non-negative and finite.
+fn check_values<S: State>(mem: &Member<S>) -> Result<()> {
+    if !(mem.fitness >= 0.0 && mem.fitness.is_finite()) {
+        return Err(eyre!("got negative or non-finite fitness"));
+    }
+    if !(mem.violation >= 0.0 && mem.violation.is_finite()) {
+        return Err(eyre!("got negative or non-finite constraint violation"));
+    }
+    Ok(())
+}
+
+// Independent rng for one member's fitness computation. The seed, generation
+// and member indices make up the ChaCha key, so each member gets its own
+// stream.
+fn member_rng(seed: u64, gen_idx: usize, member_idx: usize) -> StdRng {
+    let mut key = [0; 32];
+    key[..8].copy_from_slice(&seed.to_le_bytes());
+    key[8..16].copy_from_slice(&(gen_idx as u64).to_le_bytes());
+    key[16..24].copy_from_slice(&(member_idx as u64).to_le_bytes());
+    StdRng::from_seed(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic;
+
+    use derive_more::Display;
+    use rand::Rng;
+
+    use super::*;
+    use crate::evolve::cfg::AgeDecay;
+    use crate::evolve::evolver::Evolver;
+    use crate::gen::member::MemberId;
+    use crate::gen::species::{auto_species_target, DistanceError};
+    use crate::gen::trace::ImprovementCount;
+    use crate::util::test_utils::MockEvaluator;
+
+    #[derive(Debug, Display, Clone, PartialEq, PartialOrd)]
+    #[display(fmt = "{cluster}:{idx}")]
+    struct ClusterState {
+        cluster: usize,
+        idx: usize,
+    }
+
+    // Four equidistant clusters.
+    type ClusterEvaluator = MockEvaluator<ClusterState>;
+
+    fn clusters() -> ClusterEvaluator {
+        MockEvaluator::with(
+            |s: &ClusterState, ()| Ok(s.idx as f64),
+            |s1, s2| Ok(if s1.cluster == s2.cluster { 0.0 } else { 100.0 }),
+        )
+    }
+
+    fn run_auto_target(min: SpeciesId, max: SpeciesId, start: SpeciesId) -> Result<SpeciesId> {
+        const POP: usize = 40;
+        let cfg = EvolveCfg::new(POP).set_species(Species::AutoTarget { min, max });
+        let states = (0..POP).map(|idx| ClusterState { cluster: idx % 4, idx }).collect();
+        let initial = UnevaluatedGen::initial::<ClusterEvaluator>(states, &cfg);
+        let mut target = start;
+        for _ in 0..10 {
+            let mut gen = initial.clone();
+            gen.species_target = target;
+            let _ = gen.evaluate(&[()], &cfg, &clusters())?;
+            target = auto_species_target(&gen.mems, &gen.species, min, max);
+        }
+        Ok(target)
+    }
+
+    #[test]
+    fn target_number_counts_species() -> Result<()> {
+        const POP: usize = 40;
+        let states = (0..POP).map(|idx| ClusterState { cluster: idx % 4, idx }).collect::<Vec<_>>();
+        // The four clusters can't be merged into one species, so the count
+        // reported is the number found rather than the target.
+        for (target, found) in [(1, 4), (4, 4)] {
+            let cfg = EvolveCfg::new(POP).set_species(Species::TargetNumber(target));
+            let mut gen = UnevaluatedGen::initial::<ClusterEvaluator>(states.clone(), &cfg);
+            gen.species_target = target;
+            let evaluated = gen.evaluate(&[()], &cfg, &clusters())?;
+            assert_eq!(gen.species.num, found);
+            assert_eq!(evaluated.species().len() as SpeciesId, found);
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid species target range [5, 2]")]
+    fn auto_species_target_range() {
+        let _ = EvolveCfg::new(10).set_species(Species::AutoTarget { min: 5, max: 2 });
+    }
+
+    #[test]
+    fn auto_species_target_converges() -> Result<()> {
+        assert_eq!(run_auto_target(1, 8, 8)?, 4);
+        assert_eq!(run_auto_target(1, 8, 1)?, 4);
+        assert_eq!(run_auto_target(1, 3, 1)?, 3);
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn age_decay_selection_fitness() -> Result<()> {
+        let cfg = EvolveCfg::new(5).set_age_decay(Some(AgeDecay::new(2, 0.5)));
+        // (fitness, age) pairs.
+        let mems = [(1, 4), (6, 6), (10, 0), (2, 2), (3, 3)]
+            .into_iter()
+            .map(|(idx, age)| {
+                let mut mem =
+                    Member::new::<ClusterEvaluator>(ClusterState { cluster: 0, idx }, &cfg);
+                mem.age = age;
+                mem
+            })
+            .collect();
+        let mut gen = UnevaluatedGen::new(mems);
+        let evaluated = gen.evaluate(&[()], &cfg, &clusters())?;
+        let fitness = evaluated.mems.iter().map(|v| v.fitness).collect::<Vec<_>>();
+        let selection = evaluated.mems.iter().map(|v| v.selection_fitness).collect::<Vec<_>>();
+        assert_eq!(fitness, [10.0, 6.0, 3.0, 2.0, 1.0]);
+        assert_eq!(selection, [10.0, 2.0, 2.0, 2.0, 0.5]);
+        Ok(())
+    }
+
+    #[test]
+    fn chunked_fitness_matches_serial() -> Result<()> {
+        const POP: usize = 1000;
+        let states = (0..POP).map(|idx| ClusterState { cluster: idx % 4, idx }).collect();
+        let cfg = EvolveCfg::new(POP).set_niching(Niching::SharedFitness(1.0));
+        let initial = UnevaluatedGen::initial::<ClusterEvaluator>(states, &cfg);
+        let run = |cfg: &EvolveCfg| -> Result<(Vec<f64>, DistCache)> {
+            let mut gen = initial.clone();
+            let evaluated = gen.evaluate(&[()], cfg, &clusters())?;
+            Ok((evaluated.mems.iter().map(|v| v.fitness).collect(), gen.dists))
+        };
+        let serial = run(&cfg)?;
+        for chunk_size in [None, Some(1), Some(7), Some(POP * POP)] {
+            let cfg = cfg
+                .clone()
+                .set_par_fitness(true)
+                .set_par_dist(true)
+                .set_fitness_chunk_size(chunk_size);
+            assert_eq!(run(&cfg)?, serial);
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn takeover_fraction() -> Result<()> {
+        // 6 of 10 members are copies of the best, the rest are in other clusters.
+        let best = ClusterState { cluster: 0, idx: 9 };
+        let states = (0..10)
+            .map(|i| if i < 6 { best.clone() } else { ClusterState { cluster: i % 3 + 1, idx: i } })
+            .collect::<Vec<_>>();
+        for niching in [Niching::None, Niching::SharedFitness(1.0)] {
+            let cfg = EvolveCfg::new(10).set_niching(niching);
+            let mut gen = UnevaluatedGen::initial::<ClusterEvaluator>(states.clone(), &cfg);
+            let _ = gen.evaluate(&[()], &cfg, &clusters())?;
+            assert_eq!(gen.dists.is_empty(), niching == Niching::None);
+            assert_eq!(gen.takeover_fraction(0.0), 0.6);
+        }
+        Ok(())
+    }
+
+    // Fitness is drawn entirely from the provided rng.
+    fn rng_eval() -> MockEvaluator {
+        MockEvaluator::new().set_fitness_rng(|_, (), rng| Ok(rng.gen()))
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn seeded_fitness_reproducible() -> Result<()> {
+        const POP: usize = 100;
+        let run = |cfg: &EvolveCfg, gen_idx: usize| -> Result<Vec<(usize, f64)>> {
+            let mut gen = UnevaluatedGen::initial::<MockEvaluator>((0..POP).collect(), cfg);
+            gen.gen_idx = gen_idx;
+            let evaluated = gen.evaluate(&[(), ()], cfg, &rng_eval())?;
+            let mut fitness =
+                evaluated.mems.iter().map(|v| (*v.state, v.fitness)).collect::<Vec<_>>();
+            fitness.sort_by_key(|v| v.0);
+            Ok(fitness)
+        };
+        let cfg = EvolveCfg::new(POP).set_fitness_seed(Some(1234));
+        let serial = run(&cfg, 3)?;
+        assert_eq!(run(&cfg, 3)?, serial);
+        for chunk_size in [None, Some(1), Some(7)] {
+            let cfg = cfg.clone().set_par_fitness(true).set_fitness_chunk_size(chunk_size);
+            assert_eq!(run(&cfg, 3)?, serial);
+        }
+        // Members, generations and seeds all get different streams.
+        assert!(serial.windows(2).all(|w| w[0].1 != w[1].1));
+        assert_ne!(run(&cfg, 4)?, serial);
+        assert_ne!(run(&cfg.clone().set_fitness_seed(Some(1235)), 3)?, serial);
+        Ok(())
+    }
+
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn duplicates_evaluated_once() -> Result<()> {
+        // Half the members are copies of 0..25.
+        const POP: usize = 100;
+        let states = (0..POP).map(|i| if i < 50 { i } else { i % 25 }).collect::<Vec<_>>();
+        for par in [false, true] {
+            let shared = EvolveCfg::new(POP).set_par_fitness(par).set_fitness_chunk_size(Some(7));
+            let cfg = shared.clone().set_share_duplicate_fitness(false);
+            let eval = MockEvaluator::new();
+            let mut gen = UnevaluatedGen::initial::<MockEvaluator>(states.clone(), &shared);
+            let evaluated = gen.evaluate(&[(), ()], &shared, &eval)?;
+            assert_eq!(eval.fitness_calls.load(atomic::Ordering::Relaxed), 50 * 2);
+            assert_eq!(gen.fitness_evals, 50 * 2);
+            assert!(evaluated.mems.iter().all(|v| v.fitness == *v.state as f64));
+
+            let eval = MockEvaluator::new();
+            let mut gen = UnevaluatedGen::initial::<MockEvaluator>(states.clone(), &cfg);
+            let _ = gen.evaluate(&[(), ()], &cfg, &eval)?;
+            assert_eq!(eval.fitness_calls.load(atomic::Ordering::Relaxed), POP * 2);
+            assert_eq!(gen.fitness_evals, POP * 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn equal_groups_without_total_order() {
+        let states = vec![1.0, f64::NAN, 1.0, f64::NAN, 2.0, 1.0];
+        let gen = UnevaluatedGen::initial::<MockEvaluator>(states, &EvolveCfg::new(6));
+        assert_eq!(equal_groups(&gen.mems), [vec![0, 2, 5], vec![1], vec![3], vec![4]]);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn duplicates_share_noise() -> Result<()> {
+        const POP: usize = 40;
+        let states = (0..POP).map(|i| i % 10).collect::<Vec<_>>();
+        let fitness = |cfg: &EvolveCfg| -> Result<Vec<Vec<f64>>> {
+            let mut gen = UnevaluatedGen::initial::<MockEvaluator>(states.clone(), cfg);
+            let evaluated = gen.evaluate(&[()], cfg, &rng_eval())?;
+            let mut by_state = vec![vec![]; 10];
+            for mem in &evaluated.mems {
+                by_state[*mem.state].push(mem.fitness);
+            }
+            Ok(by_state)
+        };
+        for shared in [EvolveCfg::new(POP), EvolveCfg::new(POP).set_fitness_seed(Some(1))] {
+            let cfg = shared.clone().set_share_duplicate_fitness(false);
+            assert!(fitness(&shared)?.iter().all(|v| v.iter().all(|&f| f == v[0])));
+            assert!(fitness(&cfg)?.iter().all(|v| v.iter().any(|&f| f != v[0])));
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn shared_fitness_auto_radius() -> Result<()> {
+        // Members are points on a line, so distances are known exactly.
+        const POP: usize = 200;
+        let frac_within = |radius: f64, strict: bool| {
+            let mut count = 0;
+            for i in 0..POP {
+                for j in 0..POP {
+                    let d = i.abs_diff(j) as f64;
+                    if d < radius || (!strict && d == radius) {
+                        count += 1;
+                    }
+                }
+            }
+            count as f64 / (POP * POP) as f64
+        };
+        for target_fraction in [0.05, 0.1, 0.3] {
+            let cfg =
+                EvolveCfg::new(POP).set_niching(Niching::SharedFitnessAuto { target_fraction });
+            let mut gen = UnevaluatedGen::initial::<MockEvaluator>((0..POP).collect(), &cfg);
+            let evaluated = gen.evaluate(&[()], &cfg, &rng_eval())?;
+            let radius = gen.species.share_radius.unwrap();
+            // Distances are integers, so the target falls within the mass of
+            // the chosen distance.
+            assert!(frac_within(radius, true) <= target_fraction + 0.02, "radius {radius}");
+            assert!(frac_within(radius, false) >= target_fraction - 0.02, "radius {radius}");
+
+            for (i, mem) in evaluated.mems.iter().enumerate() {
+                let sum = evaluated
+                    .mems
+                    .iter()
+                    .map(|v| mem.state.abs_diff(*v.state) as f64)
+                    .filter(|&d| d < radius)
+                    .map(|d| 1.0 - (d / radius).powf(SHARING_ALPHA))
+                    .sum::<f64>();
+                assert!(
+                    (mem.selection_fitness - mem.fitness / sum).abs() < 1e-9,
+                    "member {i} not shared with radius {radius}"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // Distance fails between states 3 and 7 while |broken|.
+    fn broken_dist(broken: bool) -> MockEvaluator {
+        MockEvaluator::new().set_distance(move |s1, s2| {
+            if broken && (*s1, *s2) == (3, 7) {
+                return Err(eyre!("bad pair"));
+            }
+            Ok(s1.abs_diff(*s2) as f64)
+        })
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn distance_error() -> Result<()> {
+        const POP: usize = 10;
+        let cfg = EvolveCfg::new(POP).set_niching(Niching::SharedFitness(1.0));
+        for chunk_size in [None, Some(1), Some(7)] {
+            for par in [false, true] {
+                let cfg = cfg.clone().set_par_dist(par).set_fitness_chunk_size(chunk_size);
+                let mut gen = UnevaluatedGen::initial::<MockEvaluator>((0..POP).collect(), &cfg);
+                let err = gen.evaluate(&[()], &cfg, &broken_dist(true)).err().unwrap();
+                let dist_err = *err.downcast_ref::<DistanceError>().unwrap();
+                let pair = (*gen.mems[dist_err.i].state, *gen.mems[dist_err.j].state);
+                assert_eq!(pair, (3, 7));
+                assert!(err.to_string().contains(&format!("{} and {}", dist_err.i, dist_err.j)));
+                assert!(gen.dists.is_empty());
+
+                let _ = gen.evaluate(&[()], &cfg, &broken_dist(false))?;
+                assert!(!gen.dists.is_empty());
+                assert_eq!(gen.dists.dist(dist_err.i, dist_err.j), 4.0);
             }
+        }
+        Ok(())
+    }
+
+    // States above 5 are infeasible, by how far above 5 they are.
+    fn capped() -> MockEvaluator {
+        MockEvaluator::new().set_violation(|s, ()| Ok(s.saturating_sub(5) as f64))
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn stochastic_ranking() -> Result<()> {
+        let cfg =
+            EvolveCfg::new(10).set_constraint_mode(ConstraintMode::StochasticRanking { pf: 0.0 });
+        let mut gen = UnevaluatedGen::initial::<MockEvaluator>((0..10).collect(), &cfg);
+        let evaluated = gen.evaluate(&[()], &cfg, &capped())?;
+        let states = evaluated.mems.iter().map(|v| *v.state).collect::<Vec<_>>();
+        assert_eq!(states, [5, 4, 3, 2, 1, 0, 6, 7, 8, 9]);
+        assert_eq!(evaluated.mems[0].selection_fitness, 10.0);
+        assert_eq!(evaluated.mems[9].selection_fitness, 1.0);
+        assert_eq!(evaluated.mems[9].violation, 4.0);
+
+        // Speciation still sees members sorted by fitness.
+        let species_cfg = cfg.clone().set_species(Species::TargetNumber(2));
+        let mut gen = UnevaluatedGen::initial::<MockEvaluator>((0..10).collect(), &species_cfg);
+        let evaluated = gen.evaluate(&[()], &species_cfg, &capped())?;
+        assert_eq!(*evaluated.mems[0].state, 5);
+
+        // Without a constraint mode, violations aren't computed.
+        let cfg = cfg.set_constraint_mode(ConstraintMode::None);
+        let mut gen = UnevaluatedGen::initial::<MockEvaluator>((0..10).collect(), &cfg);
+        let evaluated = gen.evaluate(&[()], &cfg, &capped())?;
+        assert_eq!(*evaluated.mems[0].state, 9);
+        assert!(evaluated.mems.iter().all(|v| v.violation == 0.0));
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn improvement_rate() -> Result<()> {
+        let cfg = EvolveCfg::new(10).set_trace(true);
+        let mem = |idx| Member::new::<ClusterEvaluator>(ClusterState { cluster: 0, idx }, &cfg);
+        // A survivor and an immigrant, which are both fitter than any parent
+        // but don't count, then four pairs of children.
+        let mems = [100, 50, 6, 5, 4, 1, 9, 8, 3, 2].map(mem).to_vec();
+        let event = |parent_fitness, crossover, children: [MemberId; 2]| BreedingEvent {
+            parents: [1000, 1001],
+            parent_idxs: [0, 1],
+            parent_fitness,
+            crossover,
+            crossover_weights: vec![],
+            crossover_has_noop: false,
+            mutation_rates: [vec![], vec![]],
+            children,
+        };
+        let ids = mems.iter().map(|v| v.id).collect::<Vec<_>>();
+        let trace = vec![
+            // Equal to the better parent isn't an improvement.
+            event([3.0, 5.0], Some(1), [ids[2], ids[3]]),
+            event([2.0, 1.0], None, [ids[4], ids[5]]),
+            event([7.0, 7.0], Some(1), [ids[6], ids[7]]),
+            // The no-op operator leaves children only mutated.
+            BreedingEvent {
+                crossover_has_noop: true,
+                ..event([2.0, 2.5], Some(0), [ids[8], ids[9]])
+            },
+        ];
+
+        let mut gen = UnevaluatedGen::new(mems.clone());
+        gen.trace = Some(trace);
+        let _ = gen.evaluate(&[()], &cfg, &clusters())?;
+        let improvement = gen.improvement.unwrap();
+        assert_eq!(improvement.all, ImprovementCount { children: 8, improved: 5 });
+        assert_eq!(improvement.all.rate(), Some(5.0 / 8.0));
+        assert_eq!(improvement.mutation_only, ImprovementCount { children: 4, improved: 2 });
+        let by_crossover = improvement.by_crossover.into_iter().collect::<Vec<_>>();
+        assert_eq!(
+            by_crossover,
+            [
+                (None, ImprovementCount { children: 2, improved: 1 }),
+                (Some(0), ImprovementCount { children: 2, improved: 1 }),
+                (Some(1), ImprovementCount { children: 4, improved: 3 })
+            ]
+        );
+
+        // Nothing is computed without a trace.
+        let mut gen = UnevaluatedGen::new(mems);
+        let _ = gen.evaluate(&[()], &cfg.set_trace(false), &clusters())?;
+        assert_eq!(gen.improvement, None);
+        Ok(())
+    }
+
+    // Compares states by value, or as rock-paper-scissors on the state mod 3
+    // if |rps| is set, where each beats the one below it.
+    fn compare_eval(rps: bool) -> MockEvaluator {
+        MockEvaluator::new()
+            .set_mutate(|s, _, _| *s += 1)
+            .set_fitness(|_, ()| Err(eyre!("comparative fitness doesn't call fitness")))
+            .set_compare(move |a, b, ()| {
+                Ok(match (a % 3, b % 3) {
+                    _ if !rps => a.cmp(b),
+                    (a, b) if a == b => Ordering::Equal,
+                    (a, b) if a == (b + 1) % 3 => Ordering::Greater,
+                    _ => Ordering::Less,
+                })
+            })
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn comparative_total_order() -> Result<()> {
+        let eval = compare_eval(false);
+        let evaluate = |cfg: &EvolveCfg| {
+            let mut gen = UnevaluatedGen::initial::<MockEvaluator>((0..10).collect(), cfg);
+            let evaluated = gen.evaluate(&[()], cfg, &eval)?;
+            Ok::<_, eyre::Report>((evaluated, gen.fitness_evals))
+        };
+
+        // Playing everyone gives the exact ranking, with the best winning
+        // every game.
+        let cfg = EvolveCfg::new(10).set_comparison(Comparison::RoundRobin);
+        let sampled_all = cfg
+            .clone()
+            .set_comparison(Comparison::Sampled { opponents: 20 })
+            .set_par_fitness(true)
+            .set_fitness_chunk_size(Some(3));
+        for cfg in [cfg, sampled_all] {
+            let (evaluated, evals) = evaluate(&cfg)?;
+            let states = evaluated.mems.iter().map(|v| *v.state).collect::<Vec<_>>();
+            assert_eq!(states, [9, 8, 7, 6, 5, 4, 3, 2, 1, 0]);
+            for (i, mem) in evaluated.mems.iter().enumerate() {
+                assert_eq!(mem.fitness, (9 - i) as f64 / 9.0);
+            }
+            assert_eq!(evals, 90);
+        }
+
+        // Sampled opponents are drawn from the fitness seed.
+        let cfg = EvolveCfg::new(10)
+            .set_comparison(Comparison::Sampled { opponents: 3 })
+            .set_fitness_seed(Some(1));
+        let fitness = |gen: &EvaluatedGen<usize>| {
+            gen.mems.iter().map(|v| (*v.state, v.fitness)).collect::<Vec<_>>()
         };
+        let (first, evals) = evaluate(&cfg)?;
+        let (second, _) = evaluate(&cfg)?;
+        assert_eq!(fitness(&first), fitness(&second));
+        assert!(fitness(&first).contains(&(9, 1.0)));
+        assert!(fitness(&first).contains(&(0, 0.0)));
+        assert!(first.mems.iter().all(|v| (0.0..=1.0).contains(&v.fitness)));
+        assert_eq!(evals, 30);
+        Ok(())
+    }
 
-        Ok(EvaluatedGen::new(self.mems.clone()))
+    #[test]
+    fn comparative_non_transitive() -> Result<()> {
+        let cfg = EvolveCfg::new(12)
+            .set_comparison(Comparison::Sampled { opponents: 4 })
+            .set_par_fitness(true);
+        let mut evolver =
+            Evolver::new(compare_eval(true), cfg, || rand::thread_rng().gen_range(0..3));
+        for _ in 0..10 {
+            let r = evolver.run()?;
+            for mem in &r.gen.mems {
+                assert!((0.0..=1.0).contains(&mem.fitness), "{}", mem.fitness);
+                assert!(mem.selection_fitness.is_finite());
+            }
+        }
+        Ok(())
     }
 }
@@ -42,5 +42,7 @@ pub mod evaluators;
 pub mod evolve;
 pub mod gen;
 pub mod ops;
+pub mod prelude;
 pub mod train;
+pub mod tuning;
 pub mod util;
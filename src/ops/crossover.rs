@@ -32,6 +32,14 @@ use smallvec::SmallVec;
 // s1 and s2 must have the same length.
 pub fn crossover_pmx<T: Copy + Hash + Default + Eq>(s1: &mut [T], s2: &mut [T]) {
     let mut r = rand::thread_rng();
+    crossover_pmx_rng(s1, s2, &mut r);
+}
+
+pub fn crossover_pmx_rng<T: Copy + Hash + Default + Eq, R: Rng + ?Sized>(
+    s1: &mut [T],
+    s2: &mut [T],
+    r: &mut R,
+) {
     let st = r.gen_range(0..s1.len());
     let en = r.gen_range(st..s1.len());
     let c1 = crossover_pmx_single(s1, s2, st, en);
@@ -89,6 +97,14 @@ pub fn crossover_pmx_single<T: Copy + Hash + Default + Eq>(
 // s1 and s2 must have the same length.
 pub fn crossover_order<T: Copy + Hash + Default + Eq>(s1: &mut [T], s2: &mut [T]) {
     let mut r = rand::thread_rng();
+    crossover_order_rng(s1, s2, &mut r);
+}
+
+pub fn crossover_order_rng<T: Copy + Hash + Default + Eq, R: Rng + ?Sized>(
+    s1: &mut [T],
+    s2: &mut [T],
+    r: &mut R,
+) {
     let st = r.gen_range(0..s1.len());
     let en = r.gen_range(st..s1.len());
     let c1 = crossover_order_single(s1, s2, st, en);
@@ -187,7 +203,11 @@ pub fn crossover_cycle<T: Copy + Hash + Default + Eq>(s1: &mut [T], s2: &mut [T]
 // Random point K-point crossover. Lengths of s1 and s2 can be different.
 pub fn crossover_kpx<T>(s1: &mut [T], s2: &mut [T], k: usize) {
     let mut r = rand::thread_rng();
-    let xpoints = (0..s1.len().min(s2.len())).choose_multiple(&mut r, k);
+    crossover_kpx_rng(s1, s2, k, &mut r);
+}
+
+pub fn crossover_kpx_rng<T, R: Rng + ?Sized>(s1: &mut [T], s2: &mut [T], k: usize, r: &mut R) {
+    let xpoints = (0..s1.len().min(s2.len())).choose_multiple(r, k);
     crossover_kpx_pts(s1, s2, &xpoints);
 }
 
@@ -219,6 +239,21 @@ pub fn crossover_ux_rng<T, R: Rng + ?Sized>(s1: &mut [T], s2: &mut [T], r: &mut
     }
 }
 
+// Swaps k distinct random positions, clamped to the length of the shorter of
+// s1 and s2. Makes a much smaller change than uniform crossover, e.g. for
+// fine tuning real vectors near convergence.
+pub fn crossover_swap_k<T>(s1: &mut [T], s2: &mut [T], k: usize) {
+    let mut r = rand::thread_rng();
+    crossover_swap_k_rng(s1, s2, k, &mut r);
+}
+
+pub fn crossover_swap_k_rng<T, R: Rng + ?Sized>(s1: &mut [T], s2: &mut [T], k: usize, r: &mut R) {
+    let min = s1.len().min(s2.len());
+    for i in (0..min).choose_multiple(r, k) {
+        swap(&mut s1[i], &mut s2[i]);
+    }
+}
+
 // Real crossover operators  ////////////////////////////////////////////////
 
 // Whole arithemtic recombination. This takes the linear combination between
@@ -235,6 +270,10 @@ pub fn crossover_arith_alpha(s1: &mut [f64], s2: &mut [f64], alpha: f64) {
 // Whole arithmetic recombination with a random combination multiplier.
 pub fn crossover_arith(s1: &mut [f64], s2: &mut [f64]) {
     let mut r = rand::thread_rng();
+    crossover_arith_rng(s1, s2, &mut r);
+}
+
+pub fn crossover_arith_rng<R: Rng + ?Sized>(s1: &mut [f64], s2: &mut [f64], r: &mut R) {
     crossover_arith_alpha(s1, s2, r.gen());
 }
 
@@ -242,6 +281,10 @@ pub fn crossover_arith(s1: &mut [f64], s2: &mut [f64]) {
 // [x - |y - x| * alpha, y + |y - x| * alpha]. A good choice for alpha is 0.5.
 pub fn crossover_blx(s1: &mut [f64], s2: &mut [f64], alpha: f64) {
     let mut r = rand::thread_rng();
+    crossover_blx_rng(s1, s2, alpha, &mut r);
+}
+
+pub fn crossover_blx_rng<R: Rng + ?Sized>(s1: &mut [f64], s2: &mut [f64], alpha: f64, r: &mut R) {
     let min = s1.len().min(s2.len());
     for i in 0..min {
         let x = s1[i].min(s2[i]);
@@ -254,10 +297,75 @@ pub fn crossover_blx(s1: &mut [f64], s2: &mut [f64], alpha: f64) {
     }
 }
 
+/// States which are vectors of reals, so real operators like
+/// `crossover_differential` can be applied to them directly.
+pub trait AsF64Slice {
+    fn as_f64_slice(&self) -> &[f64];
+    fn as_f64_slice_mut(&mut self) -> &mut [f64];
+}
+
+impl AsF64Slice for [f64] {
+    fn as_f64_slice(&self) -> &[f64] {
+        self
+    }
+
+    fn as_f64_slice_mut(&mut self) -> &mut [f64] {
+        self
+    }
+}
+
+impl AsF64Slice for Vec<f64> {
+    fn as_f64_slice(&self) -> &[f64] {
+        self
+    }
+
+    fn as_f64_slice_mut(&mut self) -> &mut [f64] {
+        self
+    }
+}
+
+// Differential evolution (DE/rand/1/bin). Sets each element of |target| to
+// a + f * (b - c) for donors [a, b, c] with probability |cr|, and always at
+// least one random element, so the target always changes. Clamped to the
+// length of the shortest of the target and the donors.
+pub fn crossover_differential<T: AsF64Slice + ?Sized>(
+    target: &mut T,
+    donors: [&T; 3],
+    f: f64,
+    cr: f64,
+) {
+    let mut r = rand::thread_rng();
+    crossover_differential_rng(target, donors, f, cr, &mut r);
+}
+
+#[allow(clippy::many_single_char_names)]
+pub fn crossover_differential_rng<T: AsF64Slice + ?Sized, R: Rng + ?Sized>(
+    target: &mut T,
+    donors: [&T; 3],
+    f: f64,
+    cr: f64,
+    r: &mut R,
+) {
+    let [a, b, c] = donors.map(T::as_f64_slice);
+    let target = target.as_f64_slice_mut();
+    let min = target.len().min(a.len()).min(b.len()).min(c.len());
+    if min == 0 {
+        return;
+    }
+    let forced = r.gen_range(0..min);
+    for i in 0..min {
+        if i == forced || r.gen_bool(cr.clamp(0.0, 1.0)) {
+            target[i] = a[i] + f * (b[i] - c[i]);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use pretty_assertions::assert_eq;
     use rand::rngs::mock::StepRng;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     use super::*;
     use crate::ops::util::{str_to_vec, vec_to_str};
@@ -266,7 +374,7 @@ mod tests {
     fn test_crossover_pmx() {
         let a: [i32; 0] = [];
         let b: [i32; 0] = [];
-        assert_eq!(crossover_pmx_single(&a, &b, 0, 0), []);
+        assert_eq!(crossover_pmx_single(&a, &b, 0, 0), Vec::<i32>::new());
 
         let a = [1];
         let b = [1];
@@ -293,7 +401,7 @@ mod tests {
     fn test_crossover_order() {
         let a: [i32; 0] = [];
         let b: [i32; 0] = [];
-        assert_eq!(crossover_order_single(&a, &b, 0, 0), []);
+        assert_eq!(crossover_order_single(&a, &b, 0, 0), Vec::<i32>::new());
 
         let a = [1];
         let b = [1];
@@ -321,8 +429,8 @@ mod tests {
         let mut a: [i32; 0] = [];
         let mut b: [i32; 0] = [];
         crossover_cycle(&mut a, &mut b);
-        assert_eq!(a, []);
-        assert_eq!(b, []);
+        assert_eq!(a, [0; 0]);
+        assert_eq!(b, [0; 0]);
 
         let mut a = [1];
         let mut b = [1];
@@ -388,4 +496,65 @@ mod tests {
         assert_eq!(vec_to_str(&a), "wbyd");
         assert_eq!(vec_to_str(&b), "axcz");
     }
+
+    #[allow(clippy::float_cmp)]
+    fn swap_k(a: &[f64], b: &[f64], k: usize, seed: u64) -> (Vec<f64>, Vec<f64>, usize) {
+        let (mut s1, mut s2) = (a.to_vec(), b.to_vec());
+        crossover_swap_k_rng(&mut s1, &mut s2, k, &mut StdRng::seed_from_u64(seed));
+        // Positions either keep both values or swap them.
+        let mut swapped = 0;
+        for i in 0..a.len().min(b.len()) {
+            if (s1[i], s2[i]) == (b[i], a[i]) {
+                swapped += 1;
+            } else {
+                assert_eq!((s1[i], s2[i]), (a[i], b[i]));
+            }
+        }
+        (s1, s2, swapped)
+    }
+
+    #[test]
+    fn test_crossover_swap_k() {
+        let a = [0.0, 1.0, 2.0, 3.0, 4.0, 5.0];
+        let b = [10.0, 11.0, 12.0, 13.0, 14.0, 15.0];
+        for seed in 0..20 {
+            for k in 0..=a.len() {
+                assert_eq!(swap_k(&a, &b, k, seed).2, k);
+            }
+        }
+        assert_eq!(swap_k(&a, &b, 0, 0), (a.to_vec(), b.to_vec(), 0));
+        assert_eq!(swap_k(&a, &b, 100, 0), (b.to_vec(), a.to_vec(), 6));
+
+        // Only the common prefix can be swapped.
+        let (c, d, swapped) = swap_k(&a, &b[..3], 100, 0);
+        assert_eq!(swapped, 3);
+        assert_eq!(c, [10.0, 11.0, 12.0, 3.0, 4.0, 5.0]);
+        assert_eq!(d, [0.0, 1.0, 2.0]);
+        let (c, d, swapped) = swap_k(&a[..2], &b, 1, 0);
+        assert_eq!((c.len(), d.len(), swapped), (2, 6, 1));
+        assert_eq!(d[2..], b[2..]);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn test_crossover_differential() {
+        let (a, b, c) = (vec![1.0, 2.0, 3.0], vec![0.5, 4.0, -1.0], vec![0.25, 1.0, 1.0]);
+        let mut target = vec![9.0, 9.0, 9.0];
+        crossover_differential(&mut target, [&a, &b, &c], 0.5, 1.0);
+        assert_eq!(target, [1.125, 3.5, 2.0]);
+
+        // With no crossover rate, exactly one element still changes.
+        let mut r = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let mut target = vec![9.0, 9.0, 9.0];
+            crossover_differential_rng(&mut target, [&a, &b, &c], 0.5, 0.0, &mut r);
+            let changed = (0..3).filter(|&i| target[i] != 9.0).collect::<Vec<_>>();
+            assert_eq!(changed.len(), 1, "{target:?}");
+            assert_eq!(target[changed[0]], [1.125, 3.5, 2.0][changed[0]]);
+        }
+
+        let mut empty: Vec<f64> = vec![];
+        crossover_differential(&mut empty, [&a, &b, &c], 0.5, 1.0);
+        assert_eq!(empty, Vec::<f64>::new());
+    }
 }
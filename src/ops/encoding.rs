@@ -0,0 +1,223 @@
+use rand::Rng;
+
+use crate::ops::crossover::crossover_ux_rng;
+use crate::ops::mutation::mutate_rate_rng;
+
+// Integer encoding operators ////////////////////////////////////////////////
+
+// Reflected binary Gray code. Consecutive integers differ in exactly one bit,
+// which avoids Hamming cliffs (e.g. 0111 => 1000) under bit-flip mutation.
+#[must_use]
+pub fn to_gray(v: u64) -> u64 {
+    v ^ (v >> 1)
+}
+
+#[must_use]
+pub fn from_gray(mut g: u64) -> u64 {
+    let mut shift = 1;
+    while shift < u64::BITS {
+        g ^= g >> shift;
+        shift <<= 1;
+    }
+    g
+}
+
+// Number of bits needed to represent all integers in 0..bound.
+#[must_use]
+pub fn bits_for(bound: u64) -> usize {
+    (u64::BITS - bound.saturating_sub(1).leading_zeros()) as usize
+}
+
+#[must_use]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd)]
+pub enum IntCoding {
+    Binary, // Plain binary coding.
+    Gray,   // Gray coding. Usually better for bit-flip mutation.
+}
+
+/// Packs a vector of bounded integers into a bit vector genome. Each integer
+/// uses just enough bits for its bound, most significant bit first.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct IntEncoding {
+    bounds: Vec<u64>,
+    bits: Vec<usize>,
+    coding: IntCoding,
+}
+
+impl IntEncoding {
+    /// Integer i takes values in 0..bounds[i].
+    pub fn new(bounds: &[u64], coding: IntCoding) -> Self {
+        assert!(bounds.iter().all(|&b| b > 0), "bounds must be positive");
+        let bits = bounds.iter().map(|&b| bits_for(b)).collect();
+        Self { bounds: bounds.to_vec(), bits, coding }
+    }
+
+    #[must_use]
+    pub fn bounds(&self) -> &[u64] {
+        &self.bounds
+    }
+
+    /// Total number of bits in the genome.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.bits.iter().sum()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[must_use]
+    pub fn encode(&self, v: &[u64]) -> Vec<bool> {
+        assert_eq!(v.len(), self.bounds.len(), "wrong number of integers");
+        let mut genome = Vec::with_capacity(self.len());
+        for (&x, &bits) in v.iter().zip(&self.bits) {
+            let x = match self.coding {
+                IntCoding::Binary => x,
+                IntCoding::Gray => to_gray(x),
+            };
+            genome.extend((0..bits).rev().map(|b| (x >> b) & 1 == 1));
+        }
+        genome
+    }
+
+    /// Bit patterns past an integer's bound are clamped to the bound.
+    #[must_use]
+    pub fn decode(&self, genome: &[bool]) -> Vec<u64> {
+        assert_eq!(genome.len(), self.len(), "wrong genome length");
+        let mut v = Vec::with_capacity(self.bounds.len());
+        let mut st = 0;
+        for (&bound, &bits) in self.bounds.iter().zip(&self.bits) {
+            let x = genome[st..st + bits].iter().fold(0, |acc, &b| (acc << 1) | u64::from(b));
+            let x = match self.coding {
+                IntCoding::Binary => x,
+                IntCoding::Gray => from_gray(x),
+            };
+            v.push(x.min(bound - 1));
+            st += bits;
+        }
+        v
+    }
+
+    /// Random genome with integers uniformly distributed within their bounds.
+    #[must_use]
+    pub fn rand(&self) -> Vec<bool> {
+        self.rand_rng(&mut rand::thread_rng())
+    }
+
+    #[must_use]
+    pub fn rand_rng<R: Rng + ?Sized>(&self, r: &mut R) -> Vec<bool> {
+        let v = self.bounds.iter().map(|&b| r.gen_range(0..b)).collect::<Vec<_>>();
+        self.encode(&v)
+    }
+
+    /// Flips each bit with probability |rate|.
+    pub fn mutate(&self, genome: &mut [bool], rate: f64) {
+        self.mutate_rng(genome, rate, &mut rand::thread_rng());
+    }
+
+    pub fn mutate_rng<R: Rng + ?Sized>(&self, genome: &mut [bool], rate: f64, r: &mut R) {
+        mutate_rate_rng(genome, rate, |b, _| !b, r);
+    }
+
+    /// Uniform crossover which swaps whole integers rather than single bits.
+    pub fn crossover_ux(&self, s1: &mut [bool], s2: &mut [bool]) {
+        self.crossover_ux_rng(s1, s2, &mut rand::thread_rng());
+    }
+
+    pub fn crossover_ux_rng<R: Rng + ?Sized>(&self, s1: &mut [bool], s2: &mut [bool], r: &mut R) {
+        let mut xs1 = self.split(s1);
+        let mut xs2 = self.split(s2);
+        crossover_ux_rng(&mut xs1, &mut xs2, r);
+        s1.copy_from_slice(&xs1.concat());
+        s2.copy_from_slice(&xs2.concat());
+    }
+
+    fn split(&self, genome: &[bool]) -> Vec<Vec<bool>> {
+        let mut st = 0;
+        self.bits
+            .iter()
+            .map(|&bits| {
+                st += bits;
+                genome[st - bits..st].to_vec()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn gray_round_trip() {
+        let mut r = rand::thread_rng();
+        let edge = [0, 1, 2, 3, u64::MAX, u64::MAX - 1, 1 << 63, (1 << 63) - 1];
+        for v in edge.into_iter().chain((0..10000).map(|_| r.gen::<u64>())) {
+            assert_eq!(from_gray(to_gray(v)), v);
+            assert_eq!(to_gray(from_gray(v)), v);
+        }
+    }
+
+    #[test]
+    fn gray_adjacent_differ_by_one_bit() {
+        for v in 0..1000 {
+            assert_eq!((to_gray(v) ^ to_gray(v + 1)).count_ones(), 1);
+        }
+        assert_eq!([to_gray(0), to_gray(1), to_gray(2), to_gray(3)], [0b00, 0b01, 0b11, 0b10]);
+    }
+
+    #[test]
+    fn test_bits_for() {
+        assert_eq!(bits_for(1), 0);
+        assert_eq!(bits_for(2), 1);
+        assert_eq!(bits_for(64), 6);
+        assert_eq!(bits_for(65), 7);
+        assert_eq!(bits_for(u64::MAX), 64);
+    }
+
+    #[test]
+    fn pack_round_trip() {
+        let mut r = rand::thread_rng();
+        let bounds = [1, 2, 5, 64, 1000, u64::MAX];
+        for coding in [IntCoding::Binary, IntCoding::Gray] {
+            let enc = IntEncoding::new(&bounds, coding);
+            assert_eq!(enc.len(), 1 + 3 + 6 + 10 + 64);
+            for _ in 0..1000 {
+                let v = bounds.iter().map(|&b| r.gen_range(0..b)).collect::<Vec<_>>();
+                assert_eq!(enc.decode(&enc.encode(&v)), v);
+            }
+        }
+    }
+
+    #[test]
+    fn pack_layout() {
+        let enc = IntEncoding::new(&[8, 4], IntCoding::Binary);
+        assert_eq!(enc.encode(&[6, 1]), [true, true, false, false, true]);
+        let enc = IntEncoding::new(&[8, 4], IntCoding::Gray);
+        assert_eq!(enc.encode(&[6, 1]), [true, false, true, false, true]);
+        // Out of range patterns are clamped.
+        let enc = IntEncoding::new(&[5], IntCoding::Binary);
+        assert_eq!(enc.decode(&[true, true, true]), [4]);
+    }
+
+    #[test]
+    fn crossover_keeps_integers_whole() {
+        let enc = IntEncoding::new(&[16, 16, 16, 16], IntCoding::Gray);
+        for _ in 0..100 {
+            let mut s1 = enc.encode(&[1, 2, 3, 4]);
+            let mut s2 = enc.encode(&[11, 12, 13, 14]);
+            enc.crossover_ux(&mut s1, &mut s2);
+            let (v1, v2) = (enc.decode(&s1), enc.decode(&s2));
+            for i in 0..4 {
+                let pair = (v1[i], v2[i]);
+                let i = i as u64;
+                assert!(pair == (i + 1, i + 11) || pair == (i + 11, i + 1));
+            }
+        }
+    }
+}
@@ -0,0 +1,171 @@
+// Adapters which keep frozen positions of slice states unchanged, for use
+// with `Evaluator::frozen_mask`. A position is frozen if it's set in the mask.
+// Positions past the end of the mask are not frozen.
+
+/// Mask freezing the first |k| of |len| positions.
+#[must_use]
+pub fn prefix_mask(len: usize, k: usize) -> Vec<bool> {
+    (0..len).map(|i| i < k).collect()
+}
+
+/// Runs the mutation |op| on |s|, then restores the frozen positions. A frozen
+/// value the operator moved elsewhere is swapped back, so operators which
+/// permute |s| still leave a permutation. Values it overwrote are written back.
+pub fn with_frozen<T: Clone + PartialEq>(s: &mut [T], mask: &[bool], op: impl FnOnce(&mut [T])) {
+    let saved = frozen_values(s, mask);
+    op(s);
+    restore(s, &saved);
+}
+
+/// Like `with_frozen`, for crossover of |s1| and |s2|, each with its own mask.
+pub fn with_frozen2<T: Clone + PartialEq>(
+    s1: &mut [T],
+    mask1: &[bool],
+    s2: &mut [T],
+    mask2: &[bool],
+    op: impl FnOnce(&mut [T], &mut [T]),
+) {
+    let (saved1, saved2) = (frozen_values(s1, mask1), frozen_values(s2, mask2));
+    op(s1, s2);
+    restore(s1, &saved1);
+    restore(s2, &saved2);
+}
+
+fn frozen_values<T: Clone>(s: &[T], mask: &[bool]) -> Vec<(usize, T)> {
+    s.iter()
+        .zip(mask)
+        .enumerate()
+        .filter(|(_, (_, &f))| f)
+        .map(|(i, (v, _))| (i, v.clone()))
+        .collect()
+}
+
+// Puts each saved value back at its position, by swapping it with where it
+// moved to if that isn't a frozen position already holding its own value.
+fn restore<T: Clone + PartialEq>(s: &mut [T], saved: &[(usize, T)]) {
+    let settled = |s: &[T], j: usize| saved.iter().any(|(k, v)| *k == j && s[j] == *v);
+    for (i, v) in saved {
+        if s[*i] == *v {
+            continue;
+        }
+        match (0..s.len()).find(|&j| s[j] == *v && !settled(s, j)) {
+            Some(j) => s.swap(*i, j),
+            None => s[*i] = v.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use eyre::Result;
+    use rand::Rng;
+
+    use super::*;
+    use crate::eval::Evaluator;
+    use crate::ops::crossover::{crossover_cycle, crossover_order, crossover_pmx};
+    use crate::ops::mutation::{mutate_insert, mutate_inversion, mutate_scramble, mutate_swap};
+
+    #[derive(Debug, Clone, PartialEq, PartialOrd)]
+    struct Perm(Vec<u8>);
+
+    impl fmt::Display for Perm {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:?}", self.0)
+        }
+    }
+
+    // Permutations of 0..10 where the values at even positions are fixed. All
+    // the operators keep permutations, so states stay permutations.
+    struct EvenFrozenEvaluator;
+
+    impl Evaluator for EvenFrozenEvaluator {
+        type State = Perm;
+        const NUM_CROSSOVER: usize = 3;
+        const NUM_MUTATION: usize = 4;
+
+        fn crossover(&self, s1: &mut Perm, s2: &mut Perm, idx: usize) {
+            let (mask1, mask2) = (self.frozen_mask(s1).unwrap(), self.frozen_mask(s2).unwrap());
+            with_frozen2(&mut s1.0, &mask1, &mut s2.0, &mask2, |s1, s2| match idx {
+                0 => crossover_pmx(s1, s2),
+                1 => crossover_order(s1, s2),
+                2 => crossover_cycle(s1, s2),
+                _ => panic!("unknown crossover strategy"),
+            });
+        }
+
+        fn mutate(&self, s: &mut Perm, _: f64, idx: usize) {
+            let mask = self.frozen_mask(s).unwrap();
+            with_frozen(&mut s.0, &mask, |s| match idx {
+                0 => mutate_swap(s),
+                1 => mutate_insert(s),
+                2 => mutate_scramble(s),
+                3 => mutate_inversion(s),
+                _ => panic!("unknown mutation strategy"),
+            });
+        }
+
+        fn fitness(&self, _: &Perm, _data: &()) -> Result<f64> {
+            Ok(1.0)
+        }
+
+        fn distance(&self, _: &Perm, _: &Perm) -> Result<f64> {
+            Ok(0.0)
+        }
+
+        fn frozen_mask(&self, s: &Perm) -> Option<Vec<bool>> {
+            Some((0..s.0.len()).map(|i| i % 2 == 0).collect())
+        }
+    }
+
+    #[test]
+    fn operator_storm_keeps_frozen() {
+        let eval = EvenFrozenEvaluator;
+        let mut r = rand::thread_rng();
+        let mut s1 = Perm((0..10).collect());
+        let mut s2 = Perm((0..10).rev().collect());
+        let (frozen1, frozen2) = (s1.clone(), s2.clone());
+        for _ in 0..10000 {
+            if r.gen::<bool>() {
+                eval.crossover(
+                    &mut s1,
+                    &mut s2,
+                    r.gen_range(0..EvenFrozenEvaluator::NUM_CROSSOVER),
+                );
+            } else {
+                eval.mutate(&mut s1, 1.0, r.gen_range(0..EvenFrozenEvaluator::NUM_MUTATION));
+            }
+            for i in (0..10).step_by(2) {
+                assert_eq!((s1.0[i], s2.0[i]), (frozen1.0[i], frozen2.0[i]));
+            }
+            for s in [&s1, &s2] {
+                let mut sorted = s.0.clone();
+                sorted.sort_unstable();
+                assert_eq!(sorted, (0..10).collect::<Vec<_>>(), "not a permutation: {s}");
+            }
+        }
+    }
+
+    #[test]
+    fn short_masks() {
+        let mut s = [1, 2, 3, 4];
+        with_frozen(&mut s, &prefix_mask(2, 1), |s| s.fill(0));
+        assert_eq!(s, [1, 0, 0, 0]);
+        with_frozen(&mut s, &[], |s| s.fill(5));
+        assert_eq!(s, [5; 4]);
+        assert_eq!(prefix_mask(3, 5), [true; 3]);
+    }
+
+    #[test]
+    fn moved_values_swapped_back() {
+        // Rotating moves frozen values elsewhere, and they're swapped back.
+        let mut s = [0, 1, 2, 3, 4];
+        with_frozen(&mut s, &[true, false, true], |s| s.rotate_left(1));
+        assert_eq!(s, [0, 3, 2, 4, 1]);
+        // Both frozen values moved to each other's place.
+        let mut s = [0, 1, 2, 3];
+        with_frozen(&mut s, &[true, true], |s| s.swap(0, 1));
+        assert_eq!(s, [0, 1, 2, 3]);
+    }
+}
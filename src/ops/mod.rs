@@ -1,5 +1,7 @@
 pub mod crossover;
 pub mod distance;
+pub mod encoding;
+pub mod frozen;
 pub mod mutation;
 pub mod sampling;
 pub mod util;
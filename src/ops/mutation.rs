@@ -11,6 +11,10 @@ use rand_distr::{Distribution, Standard, StandardNormal};
 // Mutate by swapping
 pub fn mutate_swap<T: Copy>(s: &mut [T]) {
     let mut r = rand::thread_rng();
+    mutate_swap_rng(s, &mut r);
+}
+
+pub fn mutate_swap_rng<T: Copy, R: Rng + ?Sized>(s: &mut [T], r: &mut R) {
     s.swap(r.gen_range(0..s.len()), r.gen_range(0..s.len()));
 }
 
@@ -18,6 +22,10 @@ pub fn mutate_swap<T: Copy>(s: &mut [T]) {
 // elements in between. E.g. AbcdEfg => bcdAEfg
 pub fn mutate_insert<T: Copy>(s: &mut [T]) {
     let mut r = rand::thread_rng();
+    mutate_insert_rng(s, &mut r);
+}
+
+pub fn mutate_insert_rng<T: Copy, R: Rng + ?Sized>(s: &mut [T], r: &mut R) {
     let st = r.gen_range(0..s.len());
     let en = r.gen_range(st..s.len());
     for i in st..en {
@@ -28,9 +36,13 @@ pub fn mutate_insert<T: Copy>(s: &mut [T]) {
 // Mutate by scrambling a random substring of the input. e.g. aBCDefg => aCDBefg
 pub fn mutate_scramble<T: Copy>(s: &mut [T]) {
     let mut r = rand::thread_rng();
+    mutate_scramble_rng(s, &mut r);
+}
+
+pub fn mutate_scramble_rng<T: Copy, R: Rng + ?Sized>(s: &mut [T], r: &mut R) {
     let st = r.gen_range(0..s.len());
     let en = r.gen_range(st..s.len());
-    s[st..=en].shuffle(&mut r);
+    s[st..=en].shuffle(r);
 }
 
 // Mutate by inverting a random substring of the input, e.g. aBCDefg => aDCBefg.
@@ -38,6 +50,10 @@ pub fn mutate_scramble<T: Copy>(s: &mut [T]) {
 // two edges (the ends where the inversion happens).
 pub fn mutate_inversion<T: Copy>(s: &mut [T]) {
     let mut r = rand::thread_rng();
+    mutate_inversion_rng(s, &mut r);
+}
+
+pub fn mutate_inversion_rng<T: Copy, R: Rng + ?Sized>(s: &mut [T], r: &mut R) {
     let st = r.gen_range(0..s.len());
     let en = r.gen_range(st..s.len());
     s[st..=en].reverse();
@@ -52,13 +68,25 @@ where
     Standard: Distribution<T>,
 {
     let mut r = rand::thread_rng();
+    mutate_gen_rng(&mut r)
+}
+
+#[must_use]
+pub fn mutate_gen_rng<T, R: Rng + ?Sized>(r: &mut R) -> T
+where
+    Standard: Distribution<T>,
+{
     r.gen::<T>()
 }
 
 // Replaces a random value in |s| with |v|.
 pub fn mutate_reset<T>(s: &mut [T], v: T) {
     let mut r = rand::thread_rng();
-    if let Some(ov) = s.iter_mut().choose(&mut r) {
+    mutate_reset_rng(s, v, &mut r);
+}
+
+pub fn mutate_reset_rng<T, R: Rng + ?Sized>(s: &mut [T], v: T, r: &mut R) {
+    if let Some(ov) = s.iter_mut().choose(r) {
         *ov = v;
     }
 }
@@ -66,9 +94,19 @@ pub fn mutate_reset<T>(s: &mut [T], v: T) {
 // Mutates using the given function for each element, using |rate| to decide to mutate or not.
 pub fn mutate_rate<T: Copy>(s: &mut [T], rate: f64, mut f: impl FnMut(T) -> T) {
     let mut r = rand::thread_rng();
+    mutate_rate_rng(s, rate, |v, _| f(v), &mut r);
+}
+
+// Like `mutate_rate`, but |f| is also given |r|, e.g. for `mutate_normal_rng`.
+pub fn mutate_rate_rng<T: Copy, R: Rng + ?Sized>(
+    s: &mut [T],
+    rate: f64,
+    mut f: impl FnMut(T, &mut R) -> T,
+    r: &mut R,
+) {
     for v in s {
         if r.gen::<f64>() < rate {
-            *v = f(*v);
+            *v = f(*v, r);
         }
     }
 }
@@ -79,28 +117,139 @@ pub fn mutate_rate<T: Copy>(s: &mut [T], rate: f64, mut f: impl FnMut(T) -> T) {
 #[must_use]
 pub fn mutate_uniform(st: f64, en: f64) -> f64 {
     let mut r = rand::thread_rng();
+    mutate_uniform_rng(st, en, &mut r)
+}
+
+#[must_use]
+pub fn mutate_uniform_rng<R: Rng + ?Sized>(st: f64, en: f64, r: &mut R) -> f64 {
     r.gen_range(st..=en)
 }
 
 // Mutate |v| by a value from N(0, std). It's usual to use the mutation rate as |std|.
-// May want to clamp the value to a range afterwards.
+// See `mutate_normal_bounded` to keep the value in a range afterwards.
 #[must_use]
 pub fn mutate_normal(v: f64, std: f64) -> f64 {
     let mut r = rand::thread_rng();
+    mutate_normal_rng(v, std, &mut r)
+}
+
+#[must_use]
+pub fn mutate_normal_rng<R: Rng + ?Sized>(v: f64, std: f64, r: &mut R) -> f64 {
     v + std * r.sample::<f64, _>(StandardNormal)
 }
 
+// How to bring a mutated real value back into its bounds.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Bounds {
+    // Bounce back off the bounds. Keeps the distribution smooth near the bounds.
+    Reflect,
+    // Clamp to the bounds. Values outside the bounds all land exactly on them,
+    // which biases search towards the bounds.
+    Clamp,
+}
+
+impl Bounds {
+    #[must_use]
+    pub fn apply(self, v: f64, lo: f64, hi: f64) -> f64 {
+        match self {
+            Bounds::Reflect => reflect_into(v, lo, hi),
+            Bounds::Clamp => v.clamp(lo, hi),
+        }
+    }
+}
+
+// Reflects |v| back into [lo, hi] as many times as needed, e.g. for [0, 1],
+// 1.2 => 0.8 and 2.2 => 0.2. Values inside the interval are unchanged.
+// Infinite values are clamped, since there's no sensible reflection.
+#[must_use]
+pub fn reflect_into(v: f64, lo: f64, hi: f64) -> f64 {
+    debug_assert!(lo <= hi, "invalid interval [{lo}, {hi}]");
+    if (lo..=hi).contains(&v) || v.is_nan() {
+        return v;
+    }
+    let width = hi - lo;
+    let period = 2.0 * width;
+    let v = if !v.is_finite() || width <= 0.0 {
+        v
+    } else if period.is_finite() {
+        // Position in the period starting at |lo|; the second half of the
+        // period is the reflected copy of the interval.
+        let t = (v - lo).rem_euclid(period);
+        if t > hi - lo {
+            lo + (period - t)
+        } else {
+            lo + t
+        }
+    } else if v < lo {
+        // Interval is too wide for more than one bounce.
+        2.0 * lo - v
+    } else {
+        2.0 * hi - v
+    };
+    // Guard against rounding past the bounds.
+    v.clamp(lo, hi)
+}
+
+// Mutate |v| by a value from N(0, std), reflecting the result into [lo, hi].
+#[must_use]
+pub fn mutate_normal_bounded(v: f64, std: f64, lo: f64, hi: f64) -> f64 {
+    let mut r = rand::thread_rng();
+    mutate_normal_bounded_rng(v, std, lo, hi, &mut r)
+}
+
+#[must_use]
+pub fn mutate_normal_bounded_rng<R: Rng + ?Sized>(
+    v: f64,
+    std: f64,
+    lo: f64,
+    hi: f64,
+    r: &mut R,
+) -> f64 {
+    reflect_into(mutate_normal_rng(v, std, r), lo, hi)
+}
+
+// Random value taken from the uniform distribution on [lo, hi]. Unlike
+// `mutate_uniform`, allows an empty range (lo == hi).
+#[must_use]
+pub fn mutate_uniform_in(lo: f64, hi: f64) -> f64 {
+    let mut r = rand::thread_rng();
+    mutate_uniform_in_rng(lo, hi, &mut r)
+}
+
+#[must_use]
+pub fn mutate_uniform_in_rng<R: Rng + ?Sized>(lo: f64, hi: f64, r: &mut R) -> f64 {
+    debug_assert!(lo <= hi, "invalid interval [{lo}, {hi}]");
+    if hi <= lo {
+        return lo;
+    }
+    mutate_uniform_rng(lo, hi, r)
+}
+
 // Mutate s.t. v' = v * e^(std * N(0, 1)).
 // May want to clamp the value to a range afterwards.
 #[must_use]
 pub fn mutate_lognorm(v: f64, std: f64) -> f64 {
     let mut r = rand::thread_rng();
+    mutate_lognorm_rng(v, std, &mut r)
+}
+
+#[must_use]
+pub fn mutate_lognorm_rng<R: Rng + ?Sized>(v: f64, std: f64, r: &mut R) -> f64 {
     v * E.powf(std * r.sample::<f64, _>(StandardNormal))
 }
 
 // Number mutation operators:
 pub fn mutate_creep<T: Num + Saturating + SampleUniform + PartialOrd>(v: T, max_diff: T) -> T {
     let mut r = rand::thread_rng();
+    mutate_creep_rng(v, max_diff, &mut r)
+}
+
+pub fn mutate_creep_rng<T: Num + Saturating + SampleUniform + PartialOrd, R: Rng + ?Sized>(
+    v: T,
+    max_diff: T,
+    r: &mut R,
+) -> T {
     let diff = r.gen_range(T::zero()..max_diff);
     if r.gen::<bool>() {
         v.saturating_sub(diff)
@@ -108,3 +257,91 @@ pub fn mutate_creep<T: Num + Saturating + SampleUniform + PartialOrd>(v: T, max_
         v.saturating_add(diff)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn reflect_into_bounds() {
+        let mut r = StdRng::seed_from_u64(0);
+        for _ in 0..10000 {
+            let lo = r.gen_range(-100.0..100.0);
+            let hi = lo + r.gen_range(0.0..10.0);
+            let width = hi - lo;
+            // Values from inside the interval out to thousands of widths away.
+            let v = lo + width * r.gen_range(-5000.0..5000.0);
+            let reflected = reflect_into(v, lo, hi);
+            assert!((lo..=hi).contains(&reflected), "{v} => {reflected} in [{lo}, {hi}]");
+            assert_eq!(reflect_into(reflected, lo, hi), reflected);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn reflect_into_values() {
+        assert_eq!(reflect_into(0.5, 0.0, 1.0), 0.5);
+        assert_eq!(reflect_into(1.0, 0.0, 1.0), 1.0);
+        assert!((reflect_into(1.25, 0.0, 1.0) - 0.75).abs() < 1e-12);
+        assert!((reflect_into(-0.25, 0.0, 1.0) - 0.25).abs() < 1e-12);
+        assert!((reflect_into(2.25, 0.0, 1.0) - 0.25).abs() < 1e-12);
+        assert!((reflect_into(-3.25, 0.0, 1.0) - 0.75).abs() < 1e-12);
+        assert_eq!(reflect_into(5.0, 3.0, 3.0), 3.0);
+        assert_eq!(reflect_into(-1e300, 3.0, 3.0), 3.0);
+        assert!((0.0..=1.0).contains(&reflect_into(1e300, 0.0, 1.0)));
+        assert_eq!(reflect_into(f64::INFINITY, 0.0, 1.0), 1.0);
+        assert_eq!(reflect_into(f64::NEG_INFINITY, 0.0, 1.0), 0.0);
+        assert_eq!(reflect_into(-2.0, 0.0, f64::INFINITY), 2.0);
+        assert_eq!(reflect_into(f64::MAX, -f64::MAX, 0.0), -f64::MAX);
+        assert!(!reflect_into(-f64::MAX, -f64::MAX / 2.0, f64::MAX).is_nan());
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn reflect_has_no_atom_at_bound() {
+        const N: usize = 10000;
+        let (lo, hi) = (0.0, 1.0);
+        let mut clamped = 0;
+        let mut reflected = 0;
+        for _ in 0..N {
+            let v = mutate_normal(hi, 0.1);
+            clamped += usize::from(Bounds::Clamp.apply(v, lo, hi) == hi);
+            reflected += usize::from(Bounds::Reflect.apply(v, lo, hi) == hi);
+        }
+        // Clamping puts about half the samples exactly on the bound.
+        assert!(clamped > N * 2 / 5, "{clamped}");
+        assert!(reflected < N / 100, "{reflected}");
+    }
+
+    #[test]
+    fn rng_variants_reproducible() {
+        let run = |seed| {
+            let mut r = StdRng::seed_from_u64(seed);
+            let mut s = (0..20).collect::<Vec<_>>();
+            mutate_swap_rng(&mut s, &mut r);
+            mutate_insert_rng(&mut s, &mut r);
+            mutate_scramble_rng(&mut s, &mut r);
+            mutate_inversion_rng(&mut s, &mut r);
+            mutate_rate_rng(&mut s, 0.5, |v, r| mutate_creep_rng(v, 3, r), &mut r);
+            s
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn bounded_helpers() {
+        for _ in 0..1000 {
+            let v = mutate_normal_bounded(0.0, 100.0, -1.0, 1.0);
+            assert!((-1.0..=1.0).contains(&v));
+            let v = mutate_uniform_in(2.0, 3.0);
+            assert!((2.0..=3.0).contains(&v));
+        }
+        assert_eq!(mutate_uniform_in(2.0, 2.0), 2.0);
+    }
+}
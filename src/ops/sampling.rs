@@ -1,6 +1,7 @@
 use std::iter::Iterator;
 
 use rand::prelude::IteratorRandom;
+use rand::seq::index::sample;
 use rand::Rng;
 
 // Roulette wheel selection:
@@ -71,6 +72,110 @@ pub fn sus_rng<R: Rng + ?Sized>(w: &[f64], k: usize, r: &mut R) -> Vec<usize> {
     idxs
 }
 
+// Weighted sampling without replacement. Each pick is proportional to the
+// weights of the items not picked yet. Once only zero weights remain, the
+// rest are picked in order.
+#[must_use]
+pub fn multi_wswor(w: &[f64], k: usize) -> Vec<usize> {
+    let mut r = rand::thread_rng();
+    multi_wswor_rng(w, k, &mut r)
+}
+
+pub fn multi_wswor_rng<R: Rng + ?Sized>(w: &[f64], k: usize, r: &mut R) -> Vec<usize> {
+    let mut w = w.to_vec();
+    let mut picked = vec![false; w.len()];
+    let mut idxs = Vec::new();
+    for _ in 0..k.min(w.len()) {
+        let sum: f64 = w.iter().sum();
+        let idx = if sum > 0.0 {
+            let mut cursor = r.gen::<f64>() * sum;
+            // Rounding can leave the cursor past the end, so default to the
+            // last item that can be picked.
+            let mut idx = w.iter().rposition(|&v| v > 0.0).unwrap();
+            for (i, &v) in w.iter().enumerate() {
+                if v > 0.0 && cursor < v {
+                    idx = i;
+                    break;
+                }
+                cursor -= v;
+            }
+            idx
+        } else {
+            picked.iter().position(|&v| !v).unwrap()
+        };
+        w[idx] = 0.0;
+        picked[idx] = true;
+        idxs.push(idx);
+    }
+    idxs
+}
+
+// Linear ranking: weights rising linearly with rank, from 2 - |pressure| for
+// the lowest of |w| to |pressure| for the highest, so they average 1. Equal
+// values get the average of the weights of their ranks. |pressure| should be
+// in [1, 2].
+#[must_use]
+pub fn linear_rank_weights(w: &[f64], pressure: f64) -> Vec<f64> {
+    let n = w.len();
+    if n < 2 {
+        return vec![1.0; n];
+    }
+    let mut order = (0..n).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| w[a].total_cmp(&w[b]));
+    let weight = |rank: f64| 2.0 - pressure + 2.0 * (pressure - 1.0) * rank / (n - 1) as f64;
+    let mut ranked = vec![0.0; n];
+    let mut start = 0;
+    while start < n {
+        // Weights are linear in rank, so the average weight of a run of equal
+        // values is the weight of their average rank.
+        let tied = order[start..].iter().take_while(|&&i| w[i].total_cmp(&w[order[start]]).is_eq());
+        let end = start + tied.count();
+        let avg = weight((start + end - 1) as f64 / 2.0);
+        for &i in &order[start..end] {
+            ranked[i] = avg;
+        }
+        start = end;
+    }
+    ranked
+}
+
+// Tournament selection: samples |k| items uniformly without replacement and
+// picks the one with the highest weight. |k| is clamped to between 1 and the
+// number of items, so a size of 1 picks uniformly at random. Weights don't
+// need to be non-negative.
+#[must_use]
+pub fn tournament(w: &[f64], k: usize) -> Option<usize> {
+    let mut r = rand::thread_rng();
+    tournament_rng(w, k, &mut r)
+}
+
+pub fn tournament_rng<R: Rng + ?Sized>(w: &[f64], k: usize, r: &mut R) -> Option<usize> {
+    (0..w.len()).choose_multiple(r, k.max(1)).into_iter().max_by(|&a, &b| w[a].total_cmp(&w[b]))
+}
+
+// Samples |k| distinct indices uniformly from [0, n) other than |exclude|, e.g.
+// donors for differential evolution. None if there are fewer than |k| of them.
+#[must_use]
+pub fn sample_others(n: usize, exclude: usize, k: usize) -> Option<Vec<usize>> {
+    let mut r = rand::thread_rng();
+    sample_others_rng(n, exclude, k, &mut r)
+}
+
+pub fn sample_others_rng<R: Rng + ?Sized>(
+    n: usize,
+    exclude: usize,
+    k: usize,
+    r: &mut R,
+) -> Option<Vec<usize>> {
+    let others = if exclude < n { n - 1 } else { n };
+    if others < k {
+        return None;
+    }
+    // Sample from the others as if |exclude| were removed, then shift back.
+    let idxs = sample(r, others, k).into_iter();
+    Some(idxs.map(|idx| if idx >= exclude { idx + 1 } else { idx }).collect())
+}
+
 #[cfg(test)]
 mod tests {
     use pretty_assertions::assert_eq;
@@ -89,9 +194,9 @@ mod tests {
     #[test]
     fn test_multi_rws() {
         let mut r = StepRng::new(1 << 31, 1 << 31);
-        assert_eq!(multi_rws_rng(&[], 0, &mut r), []);
-        assert_eq!(multi_rws_rng(&[], 1, &mut r), []);
-        assert_eq!(multi_rws_rng(&[1.0], 0, &mut r), []);
+        assert_eq!(multi_rws_rng(&[], 0, &mut r), Vec::<usize>::new());
+        assert_eq!(multi_rws_rng(&[], 1, &mut r), Vec::<usize>::new());
+        assert_eq!(multi_rws_rng(&[1.0], 0, &mut r), Vec::<usize>::new());
         assert_eq!(multi_rws_rng(&[1.0], 1, &mut r), [0]);
         assert_eq!(multi_rws_rng(&[1.0], 1, &mut r), [0]);
         assert_eq!(multi_rws_rng(&[0.0, 1.0], 1, &mut r), [1]);
@@ -100,9 +205,9 @@ mod tests {
     #[test]
     fn test_sus() {
         let mut r = StepRng::new(1 << 31, 1 << 31);
-        assert_eq!(sus_rng(&[], 0, &mut r), []);
-        assert_eq!(sus_rng(&[], 1, &mut r), []);
-        assert_eq!(sus_rng(&[1.0], 0, &mut r), []);
+        assert_eq!(sus_rng(&[], 0, &mut r), Vec::<usize>::new());
+        assert_eq!(sus_rng(&[], 1, &mut r), Vec::<usize>::new());
+        assert_eq!(sus_rng(&[1.0], 0, &mut r), Vec::<usize>::new());
         assert_eq!(sus_rng(&[1.0], 1, &mut r), [0]);
         assert_eq!(sus_rng(&[1.0], 1, &mut r), [0]);
         assert_eq!(sus_rng(&[1.0, 1.0], 1, &mut r), [0]);
@@ -110,4 +215,85 @@ mod tests {
         assert_eq!(sus_rng(&[1.0, 1.0], 2, &mut r), [0, 1]);
         assert_eq!(sus_rng(&[1.0, 2.0], 3, &mut r), [0, 1, 1]);
     }
+
+    #[test]
+    fn test_multi_wswor() {
+        let mut r = StepRng::new(1 << 31, 1 << 31);
+        assert_eq!(multi_wswor_rng(&[], 1, &mut r), Vec::<usize>::new());
+        assert_eq!(multi_wswor_rng(&[1.0], 0, &mut r), Vec::<usize>::new());
+        assert_eq!(multi_wswor_rng(&[1.0], 2, &mut r), [0]);
+        assert_eq!(multi_wswor_rng(&[0.0, 1.0, 0.0, 3.0], 4, &mut r), [1, 3, 0, 2]);
+        assert_eq!(multi_wswor_rng(&[0.0, 0.0], 1, &mut r), [0]);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn test_linear_rank_weights() {
+        assert_eq!(linear_rank_weights(&[], 1.5), Vec::<f64>::new());
+        assert_eq!(linear_rank_weights(&[7.0], 2.0), [1.0]);
+        assert_eq!(linear_rank_weights(&[3.0, 100.0, 1.0], 2.0), [1.0, 2.0, 0.0]);
+        assert_eq!(linear_rank_weights(&[3.0, 100.0, 1.0], 1.0), [1.0; 3]);
+        assert_eq!(linear_rank_weights(&[1.0, 1e-9, 0.0], 1.5), [1.5, 1.0, 0.5]);
+        // Ties share their ranks in any order.
+        let third = 4.0 / 3.0;
+        assert_eq!(linear_rank_weights(&[5.0, 0.0, 5.0, 5.0], 2.0), [third, 0.0, third, third]);
+        assert_eq!(linear_rank_weights(&[2.0, 2.0], 1.5), [1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_tournament() {
+        let mut r = StepRng::new(1 << 31, 1 << 31);
+        assert_eq!(tournament_rng(&[], 2, &mut r), None);
+        assert_eq!(tournament_rng(&[1.0], 0, &mut r), Some(0));
+        // Sizes past the number of items hold everyone in the tournament.
+        assert_eq!(tournament_rng(&[1.0, -2.0, 3.0, 0.0], 10, &mut r), Some(2));
+        assert_eq!(tournament_rng(&[-1.0, -2.0], 2, &mut r), Some(0));
+    }
+
+    // How often the best of 10 items is picked, out of |n| tournaments.
+    fn best_wins(k: usize, n: usize) -> usize {
+        let w = (0..10).map(f64::from).collect::<Vec<_>>();
+        (0..n).filter(|_| tournament(&w, k) == Some(9)).count()
+    }
+
+    #[test]
+    fn tournament_pressure() {
+        const N: usize = 20000;
+        // The best is in a tournament of size k with probability k / 10.
+        let (uniform, two, five) = (best_wins(1, N), best_wins(2, N), best_wins(5, N));
+        assert!((uniform as f64 / N as f64 - 0.1).abs() < 0.02, "{uniform}");
+        assert!((two as f64 / N as f64 - 0.2).abs() < 0.02, "{two}");
+        assert!((five as f64 / N as f64 - 0.5).abs() < 0.02, "{five}");
+        assert!(five > two && two > uniform);
+    }
+
+    #[test]
+    fn test_sample_others() {
+        let mut r = StepRng::new(1 << 31, 1 << 31);
+        assert_eq!(sample_others_rng(3, 0, 3, &mut r), None);
+        assert_eq!(sample_others_rng(0, 0, 0, &mut r), Some(vec![]));
+        let mut v = sample_others_rng(4, 1, 3, &mut r).unwrap();
+        v.sort_unstable();
+        assert_eq!(v, [0, 2, 3]);
+        // An out of range |exclude| leaves every index available.
+        assert_eq!(sample_others_rng(1, 5, 1, &mut r), Some(vec![0]));
+    }
+
+    #[test]
+    fn sample_others_distinct() {
+        let mut counts = [0; 6];
+        for _ in 0..6000 {
+            let v = sample_others(6, 2, 3).unwrap();
+            assert_eq!(v.len(), 3);
+            assert!(v[0] != v[1] && v[0] != v[2] && v[1] != v[2], "{v:?}");
+            for idx in v {
+                counts[idx] += 1;
+            }
+        }
+        // Each of the 5 others is a donor 3/5 of the time.
+        assert_eq!(counts[2], 0);
+        for (idx, &count) in counts.iter().enumerate().filter(|&(idx, _)| idx != 2) {
+            assert!((count as f64 / 6000.0 - 0.6).abs() < 0.05, "{idx}: {count}");
+        }
+    }
 }
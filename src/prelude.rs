@@ -0,0 +1,38 @@
+//! Commonly used items, for use with `use memega::prelude::*`.
+//!
+//! The prelude is the stable way to import memega. Items here keep their
+//! names even if the modules defining them are reorganised, and are only
+//! removed or renamed in a breaking release. New items may be added at any
+//! time, so glob importing the prelude alongside other globs can need
+//! disambiguating after an upgrade. `tests/prelude.rs` uses every item, so
+//! removing one by accident fails the tests.
+
+pub use crate::eval::{Data, DataEpoch, Evaluator, FitnessEvals, FitnessFn, State};
+#[cfg(feature = "lgp")]
+pub use crate::evaluators::lgp::builder::{
+    lgp_create_evolver, lgp_fitness_evolver, lgp_fitness_reproducible_evolver, LgpFitnessFn,
+    LgpFitnessFnEvaluator,
+};
+#[cfg(feature = "lgp")]
+pub use crate::evaluators::lgp::cfg::{LgpEvaluatorCfg, LgpRegisterLayout};
+#[cfg(feature = "lgp")]
+pub use crate::evaluators::lgp::eval::LgpState;
+#[cfg(feature = "lgp")]
+pub use crate::evaluators::lgp::vm::program::LgpProgram;
+pub use crate::evolve::cfg::{
+    AgeDecay, Comparison, ConstraintMode, Crossover, Duplicates, EvolveCfg, FitnessReduction,
+    FitnessStage, LocalSearchCfg, LocalSearchPolicy, Mutation, Niching, OptionalPhase,
+    ParamsCrossover, PopFraction, RankPressure, Replacement, ReplacementFilter, Selection, Species,
+    Stagnation, StagnationCondition, StagnationSignal, Survival, SurvivalFitness, Termination,
+    Warmup,
+};
+pub use crate::evolve::evolver::Evolver;
+pub use crate::evolve::result::{EvolveResult, Stats};
+pub use crate::gen::member::Member;
+pub use crate::gen::params::Params;
+pub use crate::gen::snapshot::PopulationSnapshot;
+pub use crate::gen::species::{validate_distance_metric, MetricError};
+pub use crate::ops::{crossover, distance, encoding, frozen, mutation, sampling, util};
+pub use crate::train::cfg::{TargetSignal, TrainerCfg};
+pub use crate::train::sampler::DataSampler;
+pub use crate::train::trainer::Trainer;
@@ -1,43 +1,94 @@
 use std::path::{Path, PathBuf};
 
+use crate::evolve::cfg::{StagnationCondition, Termination};
+
 #[must_use]
 #[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd)]
-pub enum Termination {
-    FixedGenerations(usize), // After fixed number of generations.
+pub enum TargetSignal {
+    // Best training fitness of the last generation. If the evolver has a
+    // `SharedArchive`, the archive's best fitness is used instead, so every
+    // trainer sharing it stops once any of them reaches the target.
+    Train,
+    // Validation fitness of the best member of the last generation, on all
+    // the validation data.
+    Validation,
 }
 
 #[must_use]
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd)]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct TrainerCfg {
     pub name: String,
-    pub termination: Termination,
-    pub print_gen: Option<usize>, // How often to print basic generation info.
-    pub print_summary: Option<usize>, // How often to print summary info.
-    pub print_samples: Option<usize>, // How often to print samples.
-    pub print_valid: Option<usize>, // How often to print validation info.
+    // Training stops once any of these is met.
+    pub terminations: Vec<Termination>,
+    pub print_gen: Option<usize>, // How often to log basic generation info.
+    pub print_summary: Option<usize>, // How often to log summary info.
+    pub print_samples: Option<usize>, // How often to log samples, at debug level.
+    pub print_valid: Option<usize>, // How often to log validation info.
+    pub print_compact: Option<usize>, // How often to log stats as one line.
+    pub cv_valid: Option<usize>,  // How often to compute cross validated fitness.
+    pub valid_sample: Option<usize>, // Size of validation samples used for reporting.
     pub report_gen: Option<usize>, // How often to report generation info via tensorboard.
     pub report_path: Option<PathBuf>, // Where to write tensorboard reports.
+    pub species_path: Option<PathBuf>, // Where to write species snapshots as JSONL.
+    pub trace_sampling: Option<PathBuf>, // Where to append training data ids as JSONL.
+    pub metric_queue: Option<usize>, // Size of the queue for writing metrics in the background.
+    // Fitness compared for `Termination::TargetFitness`, and how.
+    pub target_signal: TargetSignal,
+    pub target_condition: StagnationCondition,
+    pub throughput_window: usize, // Generations throughput is smoothed over.
+    pub snapshot_gen: usize,      // How often to call `Trainer::set_snapshot_fn`.
+    pub fitness_digits: Option<usize>, // Significant digits fitness is printed with.
+    pub stdout: bool,             // Whether to install a logger printing to stdout.
 }
 
 impl TrainerCfg {
     pub fn new(name: &str) -> Self {
         Self {
             name: name.to_string(),
-            termination: Termination::FixedGenerations(2000),
+            terminations: vec![Termination::FixedGenerations(2000)],
             print_gen: None,
             print_summary: None,
             print_samples: None,
             print_valid: None,
+            print_compact: None,
+            cv_valid: None,
+            valid_sample: None,
             report_gen: None,
             report_path: None,
+            species_path: None,
+            trace_sampling: None,
+            metric_queue: Some(1024),
+            target_signal: TargetSignal::Train,
+            target_condition: StagnationCondition::Default,
+            throughput_window: 10,
+            snapshot_gen: 1,
+            fitness_digits: None,
+            stdout: false,
         }
     }
 
+    /// Replaces the termination conditions with just |termination|.
     pub fn set_termination(mut self, termination: Termination) -> Self {
-        self.termination = termination;
+        self.terminations = vec![termination];
+        self
+    }
+
+    /// Also stops training once |termination| is met. Conditions are checked
+    /// in the order they were added, and the first met is recorded in
+    /// `EvolveResult::termination`.
+    pub fn add_termination(mut self, termination: Termination) -> Self {
+        self.terminations.push(termination);
         self
     }
 
+    /// The first termination condition. Training now stops on any of
+    /// |terminations|, which this doesn't show.
+    #[deprecated(note = "training stops on any of `terminations`")]
+    #[must_use]
+    pub fn termination(&self) -> Option<Termination> {
+        self.terminations.first().copied()
+    }
+
     pub fn set_print_gen(mut self, print_gen: usize) -> Self {
         self.print_gen = Some(print_gen);
         self
@@ -58,6 +109,58 @@ impl TrainerCfg {
         self
     }
 
+    /// Logs a single line of key=value pairs this often: the generation, the
+    /// keys of `Stats::to_kv_line` and how long the generation took. For
+    /// piping into shell tools, unlike the multi-line summaries.
+    pub fn set_print_compact(mut self, print_compact: Option<usize>) -> Self {
+        self.print_compact = print_compact;
+        self
+    }
+
+    /// Computes the fitness of the best member averaged over every fold from
+    /// `DataSampler::valid_folds` this often. The member with the best such
+    /// fitness is then used for the final test fitness, instead of the best
+    /// member of the last generation.
+    pub fn set_cv_valid(mut self, cv_valid: usize) -> Self {
+        self.cv_valid = Some(cv_valid);
+        self
+    }
+
+    /// Prints fitness in logs and summaries with this many significant digits
+    /// instead of `DEFAULT_FITNESS_DIGITS`. Only applies while training, on
+    /// the thread training runs on.
+    pub fn set_fitness_digits(mut self, fitness_digits: usize) -> Self {
+        self.fitness_digits = Some(fitness_digits);
+        self
+    }
+
+    /// Installs a simple logger printing to stdout when the `Trainer` is
+    /// created, for binaries which don't set up logging themselves. Does
+    /// nothing if a logger is already installed.
+    pub fn set_stdout(mut self, stdout: bool) -> Self {
+        self.stdout = stdout;
+        self
+    }
+
+    /// Reports validation fitness during training on a random sample of this
+    /// many validation items, drawn afresh each time, rather than all of them.
+    /// The full validation data is then only used once, for the final result.
+    /// Validation used for `StagnationSignal::ValidationBest` isn't sampled.
+    pub fn set_valid_sample(mut self, valid_sample: Option<usize>) -> Self {
+        self.valid_sample = valid_sample;
+        self
+    }
+
+    /// Writes metrics, i.e. tensorboard reports and species snapshots, from a
+    /// background thread with a queue of up to this many metrics, so slow
+    /// storage doesn't hold up training. If the queue is full, the oldest
+    /// per-generation scalars are dropped. Final results and snapshots are
+    /// never dropped. If None, metrics are written synchronously.
+    pub fn set_metric_queue(mut self, metric_queue: Option<usize>) -> Self {
+        self.metric_queue = metric_queue;
+        self
+    }
+
     pub fn set_report_gen(mut self, report_gen: usize) -> Self {
         self.report_gen = Some(report_gen);
         self
@@ -67,4 +170,84 @@ impl TrainerCfg {
         self.report_path = Some(report_path.as_ref().into());
         self
     }
+
+    /// Also stops training once the best fitness reaches the target. Unlike
+    /// `set_termination`, the other termination conditions are kept, so this
+    /// stops training early rather than replacing the generation limit. Any
+    /// previous target is replaced. See `Termination::TargetFitness`.
+    pub fn set_target_fitness(mut self, target_fitness: f64) -> Self {
+        self.terminations.retain(|v| !matches!(v, Termination::TargetFitness(_)));
+        self.add_termination(Termination::TargetFitness(target_fitness))
+    }
+
+    pub fn set_target_signal(mut self, target_signal: TargetSignal) -> Self {
+        self.target_signal = target_signal;
+        self
+    }
+
+    /// Counts the target fitness as reached if the best fitness is within
+    /// the condition's epsilon of it, which helps with noisy fitness. The
+    /// default allows for rounding error.
+    pub fn set_target_condition(mut self, target_condition: StagnationCondition) -> Self {
+        self.target_condition = target_condition;
+        self
+    }
+
+    /// Smooths generations and fitness evaluations per second, and the ETA
+    /// computed from them, over this many of the most recent generations.
+    pub fn set_throughput_window(mut self, throughput_window: usize) -> Self {
+        self.throughput_window = throughput_window;
+        self
+    }
+
+    /// Calls the function from `Trainer::set_snapshot_fn` every this many
+    /// generations, at least one.
+    pub fn set_snapshot_gen(mut self, snapshot_gen: usize) -> Self {
+        self.snapshot_gen = snapshot_gen;
+        self
+    }
+
+    /// Writes a line of JSON per generation with the generation's species to
+    /// the given path. Requires `EvolveCfg::species_snapshots` to be set on
+    /// the evolver being trained, and training fails without the `serde`
+    /// feature.
+    pub fn set_species_path(mut self, species_path: impl AsRef<Path>) -> Self {
+        self.species_path = Some(species_path.as_ref().into());
+        self
+    }
+
+    /// Appends a line of JSON per generation with the ids of the training
+    /// data it used, from `DataSampler::train_ids`, to the given path. Load it
+    /// with `SamplingTrace::load` to replay the run. Training fails if the
+    /// sampler doesn't provide ids, or without the `serde` feature.
+    pub fn set_trace_sampling(mut self, trace_sampling: impl AsRef<Path>) -> Self {
+        self.trace_sampling = Some(trace_sampling.as_ref().into());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn target_fitness_replaces_target() {
+        let cfg = TrainerCfg::new("test")
+            .add_termination(Termination::TargetFitness(1.0))
+            .set_target_fitness(2.0)
+            .set_target_fitness(f64::NAN);
+        assert_eq!(
+            cfg.terminations,
+            [Termination::FixedGenerations(2000), Termination::TargetFitness(f64::NAN)]
+        );
+        #[allow(deprecated)]
+        let first = cfg.termination();
+        assert_eq!(first, Some(Termination::FixedGenerations(2000)));
+        assert!(Termination::TargetFitness(1.0) < Termination::Timeout(Duration::ZERO));
+        assert_ne!(Termination::TargetFitness(0.0), Termination::TargetFitness(-0.0));
+    }
 }
@@ -0,0 +1,114 @@
+#[cfg(feature = "serde")]
+use std::fs::{self, File};
+#[cfg(feature = "serde")]
+use std::io::{ErrorKind, Write};
+#[cfg(feature = "serde")]
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "serde")]
+use eyre::{Result, WrapErr};
+#[cfg(feature = "serde")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::evolve::checkpoint::{EvolverCheckpoint, MemberCheckpoint};
+
+/// What the `Trainer` carries between generations, saved alongside the
+/// evolver's checkpoint by `Trainer::train_resumable` and
+/// `Trainer::train_parallel_resumable`, one per evolver.
+#[must_use]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrainerCheckpoint<S> {
+    // Best member by cross validated fitness so far, and that fitness.
+    pub cv_best: Option<(MemberCheckpoint<S>, f64)>,
+    // Training fitness summed over generations since the last report.
+    pub fitness_sum: f64,
+    // Best training fitness so far, and the generation it was reached in.
+    pub best: Option<(f64, usize)>,
+    pub fitness_count: f64,
+    pub evals: usize, // Fitness evaluations made in total.
+    // Fitness of the last generation compared for `Termination::TargetFitness`.
+    pub target_best: Option<f64>,
+    // Lengths of the species snapshot and sampling trace files, so records
+    // written after the checkpoint can be dropped when resuming.
+    pub species_len: Option<u64>,
+    pub trace_len: Option<u64>,
+}
+
+/// An evolver and trainer checkpoint, as written by `Trainer::train_resumable`.
+#[must_use]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Checkpoint<S> {
+    pub evolver: EvolverCheckpoint<S>,
+    pub trainer: TrainerCheckpoint<S>,
+}
+
+/// Checkpoints of labelled evolvers, as written by
+/// `Trainer::train_parallel_resumable`.
+#[must_use]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParallelCheckpoint<S> {
+    pub runs: Vec<(String, Checkpoint<S>)>,
+}
+
+#[cfg(feature = "serde")]
+impl<S: Serialize + DeserializeOwned> Checkpoint<S> {
+    /// Reads the checkpoint at |path| as JSON, or None if there isn't one.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        load(path)
+    }
+
+    /// Writes the checkpoint to |path| as JSON. It's written to a temporary
+    /// file next to |path| first, which then replaces |path|, so if writing
+    /// fails part way the previous checkpoint is left as it was.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        save(self, path)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<S: Serialize + DeserializeOwned> ParallelCheckpoint<S> {
+    /// Reads the checkpoint at |path|, as `Checkpoint::load` does.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        load(path)
+    }
+
+    /// Writes the checkpoint to |path|, as `Checkpoint::save` does.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        save(self, path)
+    }
+}
+
+#[cfg(feature = "serde")]
+fn load<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    let json = match fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e).wrap_err_with(|| format!("reading checkpoint {}", path.display()));
+        }
+    };
+    let checkpoint = serde_json::from_str(&json)
+        .wrap_err_with(|| format!("parsing checkpoint {}", path.display()))?;
+    Ok(Some(checkpoint))
+}
+
+#[cfg(feature = "serde")]
+fn save<T: Serialize>(checkpoint: &T, path: &Path) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let write = || -> Result<()> {
+        let mut file = File::create(&tmp)?;
+        serde_json::to_writer(&mut file, checkpoint)?;
+        file.flush()?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    };
+    write().wrap_err_with(|| format!("writing checkpoint {}", path.display()))
+}
@@ -1,3 +1,8 @@
 pub mod cfg;
+pub mod checkpoint;
+pub mod parallel;
 pub mod sampler;
+pub mod sink;
+pub mod standardize;
+pub mod throughput;
 pub mod trainer;
@@ -0,0 +1,93 @@
+use std::fmt;
+
+use crate::eval::State;
+use crate::evolve::result::EvolveResult;
+use crate::util::fmt::fmt_fitness;
+
+/// Best fitnesses of one evolver trained by `Trainer::train_parallel`.
+#[must_use]
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelSummary {
+    pub label: String,
+    pub final_best: f64, // Best fitness in the last generation.
+    pub best: f64,       // Best fitness in any generation.
+    pub best_gen: usize, // First generation with |best|.
+}
+
+/// Comparison of the evolvers trained by `Trainer::train_parallel`, one row
+/// per evolver in the order they were given.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ParallelReport {
+    pub rows: Vec<LabelSummary>,
+}
+
+impl ParallelReport {
+    /// Row with the highest final best fitness, the first on ties.
+    #[must_use]
+    pub fn winner(&self) -> Option<&LabelSummary> {
+        self.rows.iter().rev().max_by(|a, b| a.final_best.total_cmp(&b.final_best))
+    }
+}
+
+impl fmt::Display for ParallelReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "| label | final best | best | best gen |")?;
+        write!(f, "|---|---|---|---|")?;
+        for row in &self.rows {
+            write!(
+                f,
+                "\n| {} | {} | {} | {} |",
+                row.label,
+                fmt_fitness(row.final_best),
+                fmt_fitness(row.best),
+                row.best_gen
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Final result of each evolver trained by `Trainer::train_parallel`, with
+/// its label, and a comparison of them.
+#[must_use]
+#[derive(Clone, PartialEq)]
+pub struct ParallelResult<S: State> {
+    pub results: Vec<(String, EvolveResult<S>)>,
+    pub report: ParallelReport,
+}
+
+impl<S: State> ParallelResult<S> {
+    #[must_use]
+    pub fn get(&self, label: &str) -> Option<&EvolveResult<S>> {
+        self.results.iter().find(|(v, _)| v == label).map(|(_, r)| r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn row(label: &str, final_best: f64, best: f64, best_gen: usize) -> LabelSummary {
+        LabelSummary { label: label.to_owned(), final_best, best, best_gen }
+    }
+
+    #[test]
+    fn report() {
+        let report = ParallelReport {
+            rows: vec![row("a", 0.5, 0.75, 3), row("b", 0.875, 0.875, 10), row("c", 0.875, 1.0, 2)],
+        };
+        assert_eq!(report.winner().map(|v| v.label.as_str()), Some("b"));
+        assert_eq!(
+            report.to_string(),
+            "| label | final best | best | best gen |\n\
+             |---|---|---|---|\n\
+             | a | 0.500000 | 0.750000 | 3 |\n\
+             | b | 0.875000 | 0.875000 | 10 |\n\
+             | c | 0.875000 | 1.00000 | 2 |"
+        );
+        assert_eq!(ParallelReport::default().winner(), None);
+    }
+}
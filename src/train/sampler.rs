@@ -1,14 +1,69 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
+#[cfg(feature = "serde")]
+use std::path::Path;
 
-use rand::prelude::{SliceRandom, StdRng};
-use rand::SeedableRng;
+#[cfg(feature = "serde")]
+use eyre::WrapErr;
+use eyre::{eyre, Result};
+use rand::prelude::StdRng;
+use rand::seq::index;
+use rand::{RngCore, SeedableRng};
+use rand_distr::{Distribution, StandardNormal};
 
-use crate::eval::Data;
+use crate::eval::{Data, DataEpoch};
+
+pub trait PerturbFn<D: Data> = Fn(&D, &mut dyn RngCore) -> D + Sync + Send;
 
 pub trait DataSampler<D: Data> {
     fn train(&self, gen: usize) -> Vec<D>;
     fn valid(&self, gen: usize) -> Vec<D>;
     fn test(&self, gen: usize) -> Vec<D>;
+
+    /// Version of the training data for |gen|, which changes whenever `train`
+    /// returns different data. By default the training data never changes.
+    fn train_epoch(&self, _gen: usize) -> DataEpoch {
+        0
+    }
+
+    /// Held out folds of data for cross validation at |gen|, used when
+    /// `TrainerCfg` has cross validation set. By default the validation data is one fold.
+    fn valid_folds(&self, gen: usize) -> Vec<Vec<D>> {
+        vec![self.valid(gen)]
+    }
+
+    /// Number of items `train` returns for |gen|. By default calls `train`,
+    /// so samplers which can count their training data without copying it
+    /// should override this.
+    fn train_len(&self, gen: usize) -> usize {
+        self.train(gen).len()
+    }
+
+    /// Ids of the items `train` returns for |gen|, in the same order, as
+    /// indices into the sampler's full dataset. Used to record a
+    /// `SamplingTrace` when `TrainerCfg::trace_sampling` is set. By default
+    /// there are no ids.
+    fn train_ids(&self, _gen: usize) -> Option<Vec<usize>> {
+        None
+    }
+
+    /// Id of the training batch for |gen|, recorded in each `EvolveResult` so
+    /// generations can be grouped by the data they were evaluated on. Samplers
+    /// of hashable data can use `data_fingerprint`. By default the training
+    /// data epoch.
+    fn batch_id(&self, gen: usize) -> u64 {
+        self.train_epoch(gen)
+    }
+}
+
+/// Hash of |data|, which is the same for equal data across runs of the same
+/// build. Usable as a `DataSampler::batch_id`.
+#[must_use]
+pub fn data_fingerprint<D: Hash>(data: &[D]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
 }
 
 #[must_use]
@@ -24,8 +79,13 @@ impl DataSampler<()> for EmptyDataSampler {
         vec![()]
     }
 
+    // No test data, so there is no separate test evaluation.
     fn test(&self, _: usize) -> Vec<()> {
-        vec![()]
+        vec![]
+    }
+
+    fn train_ids(&self, _: usize) -> Option<Vec<usize>> {
+        Some(vec![0])
     }
 }
 
@@ -43,15 +103,20 @@ impl<D: Data, S: DataSampler<D>> BatchDataSampler<D, S> {
     pub fn new(sampler: S, batch_size: usize) -> Self {
         BatchDataSampler { sampler, batch_size, _u: PhantomData }
     }
-}
 
-impl<D: Data, S: DataSampler<D>> DataSampler<D> for BatchDataSampler<D, S> {
-    fn train(&self, gen: usize) -> Vec<D> {
+    // Indices of the batch for |gen| in a training set of |len| items.
+    fn batch(&self, gen: usize, len: usize) -> Vec<usize> {
         // Randomly select batch_size samples from the training set,
         // randomly seeded based on the generation to keep it consistent.
         let mut r = StdRng::seed_from_u64(gen as u64);
+        index::sample(&mut r, len, self.batch_size.min(len)).into_vec()
+    }
+}
+
+impl<D: Data, S: DataSampler<D>> DataSampler<D> for BatchDataSampler<D, S> {
+    fn train(&self, gen: usize) -> Vec<D> {
         let v = self.sampler.train(gen);
-        v.choose_multiple(&mut r, self.batch_size).cloned().collect()
+        self.batch(gen, v.len()).into_iter().map(|i| v[i].clone()).collect()
     }
 
     fn valid(&self, gen: usize) -> Vec<D> {
@@ -61,4 +126,492 @@ impl<D: Data, S: DataSampler<D>> DataSampler<D> for BatchDataSampler<D, S> {
     fn test(&self, gen: usize) -> Vec<D> {
         self.sampler.test(gen)
     }
+
+    // Each generation gets a different batch.
+    fn train_epoch(&self, gen: usize) -> DataEpoch {
+        gen as DataEpoch
+    }
+
+    fn train_len(&self, gen: usize) -> usize {
+        self.batch_size.min(self.sampler.train_len(gen))
+    }
+
+    // Ids from the wrapped sampler if it has them, otherwise indices into its
+    // training data.
+    fn train_ids(&self, gen: usize) -> Option<Vec<usize>> {
+        let batch = self.batch(gen, self.sampler.train_len(gen));
+        Some(match self.sampler.train_ids(gen) {
+            Some(ids) => batch.into_iter().map(|i| ids[i]).collect(),
+            None => batch,
+        })
+    }
+}
+
+/// k-fold cross validation over a fixed dataset, where item i is in fold
+/// i % k. One fold at a time is held out for validation and the rest is
+/// trained on, moving to the next fold every `set_rotate_every` generations.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct KFoldSampler<D: Data> {
+    data: Vec<D>,
+    folds: usize,
+    rotate_every: usize,
+    test: Vec<D>,
+}
+
+impl<D: Data> KFoldSampler<D> {
+    pub fn new(data: Vec<D>, folds: usize) -> Self {
+        assert!(folds >= 2, "need at least two folds");
+        Self { data, folds, rotate_every: 1, test: vec![] }
+    }
+
+    pub fn set_rotate_every(self, rotate_every: usize) -> Self {
+        assert!(rotate_every >= 1, "must rotate every one or more generations");
+        Self { rotate_every, ..self }
+    }
+
+    /// Test data, which is kept out of every fold.
+    pub fn set_test(self, test: Vec<D>) -> Self {
+        Self { test, ..self }
+    }
+
+    /// Fold held out for validation at |gen|.
+    #[must_use]
+    pub fn active_fold(&self, gen: usize) -> usize {
+        (gen / self.rotate_every) % self.folds
+    }
+
+    #[must_use]
+    pub fn fold(&self, k: usize) -> Vec<D> {
+        self.data.iter().skip(k).step_by(self.folds).cloned().collect()
+    }
+}
+
+impl<D: Data> DataSampler<D> for KFoldSampler<D> {
+    fn train(&self, gen: usize) -> Vec<D> {
+        let k = self.active_fold(gen);
+        let rest = self.data.iter().enumerate().filter(|(i, _)| i % self.folds != k);
+        rest.map(|(_, v)| v.clone()).collect()
+    }
+
+    fn valid(&self, gen: usize) -> Vec<D> {
+        self.fold(self.active_fold(gen))
+    }
+
+    fn test(&self, _: usize) -> Vec<D> {
+        self.test.clone()
+    }
+
+    fn train_epoch(&self, gen: usize) -> DataEpoch {
+        (gen / self.rotate_every) as DataEpoch
+    }
+
+    fn valid_folds(&self, _: usize) -> Vec<Vec<D>> {
+        (0..self.folds).map(|k| self.fold(k)).collect()
+    }
+
+    // Everything but the held out fold, which has the items k, k + folds, ...
+    fn train_len(&self, gen: usize) -> usize {
+        let k = self.active_fold(gen);
+        self.data.len() - self.data.len().saturating_sub(k).div_ceil(self.folds)
+    }
+
+    fn train_ids(&self, gen: usize) -> Option<Vec<usize>> {
+        let k = self.active_fold(gen);
+        Some((0..self.data.len()).filter(|i| i % self.folds != k).collect())
+    }
+}
+
+/// Ids of the training data used in one generation.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SampledBatch {
+    pub gen: usize,
+    // From `DataSampler::batch_id`. None for traces recorded before batch ids.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub batch: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(rename = "train"))]
+    pub ids: Vec<usize>,
+}
+
+#[cfg(feature = "serde")]
+impl SampledBatch {
+    /// Renders the batch as a single line of JSON, for JSONL output.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).wrap_err("serializing sampled batch")
+    }
+
+    /// Parses a line written by `to_json`.
+    pub fn from_json(line: &str) -> Result<Self> {
+        serde_json::from_str(line).wrap_err_with(|| format!("invalid sampling trace line: {line}"))
+    }
+}
+
+/// The training data used in each generation of a run, as recorded by the
+/// `Trainer` when `TrainerCfg::trace_sampling` is set. Replay it with a
+/// `ReplaySampler`.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd)]
+pub struct SamplingTrace {
+    // In the order they were recorded. A run restored from a checkpoint
+    // appends to the trace, so later batches replace earlier ones for the
+    // same generation.
+    pub batches: Vec<SampledBatch>,
+}
+
+impl SamplingTrace {
+    #[cfg(feature = "serde")]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("reading sampling trace {}", path.display()))?;
+        Self::from_jsonl(&s)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_jsonl(s: &str) -> Result<Self> {
+        let lines = s.lines().filter(|v| !v.trim().is_empty());
+        Ok(Self { batches: lines.map(SampledBatch::from_json).collect::<Result<_>>()? })
+    }
+
+    /// Ids of the training data used at |gen|, if it was recorded.
+    #[must_use]
+    pub fn ids(&self, gen: usize) -> Option<&[usize]> {
+        self.batch(gen).map(|v| v.ids.as_slice())
+    }
+
+    fn batch(&self, gen: usize) -> Option<&SampledBatch> {
+        self.batches.iter().rev().find(|v| v.gen == gen)
+    }
+}
+
+/// Replays a `SamplingTrace`, giving the same training data each generation
+/// as the traced run. Items are looked up by id in |data|, which must be the
+/// full dataset the ids index into. Validation and test data come from the
+/// wrapped sampler. As a `DataSampler`, panics if a generation wasn't traced
+/// or its ids are out of range. Use `train_data` to handle that as an error.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct ReplaySampler<D: Data, S: DataSampler<D>> {
+    sampler: S,
+    data: Vec<D>,
+    trace: SamplingTrace,
+}
+
+impl<D: Data, S: DataSampler<D>> ReplaySampler<D, S> {
+    pub fn new(sampler: S, data: Vec<D>, trace: SamplingTrace) -> Self {
+        Self { sampler, data, trace }
+    }
+
+    /// Ids of the training data traced for |gen|. Fails if |gen| wasn't
+    /// traced, or any of its ids are out of range of the data.
+    pub fn ids(&self, gen: usize) -> Result<&[usize]> {
+        let ids =
+            self.trace.ids(gen).ok_or_else(|| eyre!("no traced training data for gen {gen}"))?;
+        if let Some(id) = ids.iter().find(|&&id| id >= self.data.len()) {
+            return Err(eyre!(
+                "traced id {id} at gen {gen} is past the {} data items",
+                self.data.len()
+            ));
+        }
+        Ok(ids)
+    }
+
+    /// Training data traced for |gen|. See `ids`.
+    pub fn train_data(&self, gen: usize) -> Result<Vec<D>> {
+        Ok(self.ids(gen)?.iter().map(|&i| self.data[i].clone()).collect())
+    }
+
+    fn ids_or_panic(&self, gen: usize) -> &[usize] {
+        self.ids(gen).unwrap_or_else(|e| panic!("failed to replay training data: {e:#}"))
+    }
+}
+
+impl<D: Data, S: DataSampler<D>> DataSampler<D> for ReplaySampler<D, S> {
+    fn train(&self, gen: usize) -> Vec<D> {
+        self.ids_or_panic(gen).iter().map(|&i| self.data[i].clone()).collect()
+    }
+
+    fn valid(&self, gen: usize) -> Vec<D> {
+        self.sampler.valid(gen)
+    }
+
+    fn test(&self, gen: usize) -> Vec<D> {
+        self.sampler.test(gen)
+    }
+
+    fn train_epoch(&self, gen: usize) -> DataEpoch {
+        gen as DataEpoch
+    }
+
+    fn valid_folds(&self, gen: usize) -> Vec<Vec<D>> {
+        self.sampler.valid_folds(gen)
+    }
+
+    fn train_len(&self, gen: usize) -> usize {
+        self.ids_or_panic(gen).len()
+    }
+
+    fn train_ids(&self, gen: usize) -> Option<Vec<usize>> {
+        self.ids(gen).ok().map(<[usize]>::to_vec)
+    }
+
+    // The recorded batch id, so replayed generations group like the traced
+    // ones.
+    fn batch_id(&self, gen: usize) -> u64 {
+        self.trace.batch(gen).and_then(|v| v.batch).unwrap_or(gen as u64)
+    }
+}
+
+/// Wraps a given `DataSampler` and perturbs each training item with |f|, e.g.
+/// jittering inputs, so evolved programs are robust to noise. Perturbations
+/// are seeded by the generation, so they are reproducible. Validation and
+/// test data are left as they are. Wrap a `BatchDataSampler` to perturb
+/// each batch.
+#[must_use]
+#[derive(Clone)]
+pub struct PerturbingSampler<D: Data, S: DataSampler<D>, F: PerturbFn<D>> {
+    sampler: S,
+    f: F,
+    seed: u64,
+    _u: PhantomData<D>,
+}
+
+impl<D: Data, S: DataSampler<D>, F: PerturbFn<D>> PerturbingSampler<D, S, F> {
+    pub fn new(sampler: S, f: F) -> Self {
+        Self { sampler, f, seed: 0, _u: PhantomData }
+    }
+
+    /// Seed mixed with the generation, for different perturbations of the
+    /// same data across runs.
+    pub fn set_seed(self, seed: u64) -> Self {
+        Self { seed, ..self }
+    }
+}
+
+impl<D: Data, S: DataSampler<D>, F: PerturbFn<D>> DataSampler<D> for PerturbingSampler<D, S, F> {
+    fn train(&self, gen: usize) -> Vec<D> {
+        let mut r = StdRng::seed_from_u64(splitmix64(self.seed ^ splitmix64(gen as u64)));
+        self.sampler.train(gen).iter().map(|v| (self.f)(v, &mut r)).collect()
+    }
+
+    fn valid(&self, gen: usize) -> Vec<D> {
+        self.sampler.valid(gen)
+    }
+
+    fn test(&self, gen: usize) -> Vec<D> {
+        self.sampler.test(gen)
+    }
+
+    // Each generation gets different perturbations.
+    fn train_epoch(&self, gen: usize) -> DataEpoch {
+        gen as DataEpoch
+    }
+
+    fn valid_folds(&self, gen: usize) -> Vec<Vec<D>> {
+        self.sampler.valid_folds(gen)
+    }
+
+    fn train_len(&self, gen: usize) -> usize {
+        self.sampler.train_len(gen)
+    }
+
+    // Perturbed items keep the ids of the items they came from.
+    fn train_ids(&self, gen: usize) -> Option<Vec<usize>> {
+        self.sampler.train_ids(gen)
+    }
+}
+
+/// Perturbation for `PerturbingSampler` adding Gaussian noise with standard
+/// deviation |sigma|. With a |sigma| of zero, values are passed through
+/// exactly.
+#[must_use]
+pub fn jitter_f64(sigma: f64) -> impl PerturbFn<f64> + Clone {
+    move |v: &f64, r: &mut dyn RngCore| jitter(*v, sigma, r)
+}
+
+/// Like `jitter_f64`, adding independent noise to each element.
+#[must_use]
+pub fn jitter_vec(sigma: f64) -> impl PerturbFn<Vec<f64>> + Clone {
+    move |v: &Vec<f64>, r: &mut dyn RngCore| v.iter().map(|&v| jitter(v, sigma, r)).collect()
+}
+
+// SplitMix64 mixing function. Unlike `DefaultHasher`, it's fixed, so seeds
+// derived with it are the same across Rust releases.
+fn splitmix64(v: u64) -> u64 {
+    let mut z = v.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn jitter(v: f64, sigma: f64, r: &mut dyn RngCore) -> f64 {
+    if sigma == 0.0 {
+        return v;
+    }
+    let noise: f64 = StandardNormal.sample(r);
+    v + sigma * noise
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn kfold_covers_data() {
+        const FOLDS: usize = 3;
+        const EVERY: usize = 2;
+        let sampler =
+            KFoldSampler::new((0..10).collect::<Vec<usize>>(), FOLDS).set_rotate_every(EVERY);
+        for cycle in 0..2 {
+            let mut seen = vec![0; 10];
+            for gen in cycle * FOLDS * EVERY..(cycle + 1) * FOLDS * EVERY {
+                let (train, valid) = (sampler.train(gen), sampler.valid(gen));
+                assert_eq!(train.len() + valid.len(), 10);
+                assert!(train.iter().all(|v| !valid.contains(v)), "{train:?} {valid:?}");
+                // Only count each fold once, when it becomes active.
+                if gen % EVERY == 0 {
+                    for v in valid {
+                        seen[v] += 1;
+                    }
+                } else {
+                    assert_eq!(sampler.valid(gen), sampler.valid(gen - 1));
+                }
+            }
+            assert_eq!(seen, [1; 10]);
+        }
+        assert_eq!(sampler.valid_folds(0), [vec![0, 3, 6, 9], vec![1, 4, 7], vec![2, 5, 8]]);
+        assert_eq!(sampler.train_epoch(3), 1);
+        assert_eq!(sampler.train_ids(2), Some(vec![0, 2, 3, 5, 6, 8, 9]));
+        for gen in 0..FOLDS * EVERY {
+            assert_eq!(sampler.train_len(gen), sampler.train(gen).len());
+        }
+    }
+
+    #[test]
+    fn batch_ids_match_train() {
+        let data = (10..30).collect::<Vec<usize>>();
+        let sampler = BatchDataSampler::new(KFoldSampler::new(data.clone(), 2), 4);
+        for gen in 0..5 {
+            let train = sampler.train(gen);
+            let ids = sampler.train_ids(gen).unwrap();
+            assert_eq!(train.len(), 4);
+            assert_eq!(sampler.train_len(gen), 4);
+            assert_eq!(ids.iter().map(|&i| data[i]).collect::<Vec<_>>(), train);
+        }
+    }
+
+    #[test]
+    fn fingerprint_follows_batch() {
+        let data = (10..30).collect::<Vec<usize>>();
+        let sampler = BatchDataSampler::new(KFoldSampler::new(data, 2), 4);
+        let fingerprint = |gen| data_fingerprint(&sampler.train(gen));
+        assert_eq!(fingerprint(0), fingerprint(0));
+        assert_ne!(fingerprint(0), fingerprint(1));
+        assert_eq!(data_fingerprint(&[1, 2]), data_fingerprint(&[1, 2]));
+        assert_ne!(data_fingerprint(&[1, 2]), data_fingerprint(&[2, 1]));
+        // Batch ids default to the data epoch.
+        assert_eq!(sampler.batch_id(3), 3);
+        assert_eq!(EmptyDataSampler {}.batch_id(3), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn trace_roundtrip() -> Result<()> {
+        let batches = vec![
+            SampledBatch { gen: 0, batch: None, ids: vec![3, 1, 4] },
+            SampledBatch { gen: 1, batch: Some(7), ids: vec![] },
+            SampledBatch { gen: 0, batch: Some(u64::MAX), ids: vec![5] },
+        ];
+        let jsonl = batches.iter().map(|v| Ok(v.to_json()? + "\n")).collect::<Result<String>>()?;
+        let expected = [
+            "{\"gen\":0,\"train\":[3,1,4]}\n",
+            "{\"gen\":1,\"batch\":7,\"train\":[]}\n",
+            "{\"gen\":0,\"batch\":18446744073709551615,\"train\":[5]}\n",
+        ];
+        assert_eq!(jsonl, expected.concat());
+        let trace = SamplingTrace::from_jsonl(&jsonl)?;
+        assert_eq!(trace.batches, batches);
+        // Later batches for a generation win, as after resuming a run.
+        assert_eq!(trace.ids(0), Some([5].as_slice()));
+        assert_eq!(trace.ids(2), None);
+        assert!(SamplingTrace::from_jsonl("{\"gen\":0,\"train\":[1,x]}").is_err());
+        assert!(SamplingTrace::from_jsonl("{\"gen\":0}").is_err());
+        assert!(SamplingTrace::from_jsonl("{\"gen\":0,\"batch\":x,\"train\":[]}").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn replay_errors() -> Result<()> {
+        let data = vec![10, 20, 30];
+        let batches = vec![
+            SampledBatch { gen: 0, batch: None, ids: vec![2, 0] },
+            SampledBatch { gen: 1, batch: None, ids: vec![3] },
+        ];
+        let inner = KFoldSampler::new(data.clone(), 2);
+        let replay = ReplaySampler::new(inner, data, SamplingTrace { batches });
+        assert_eq!(replay.train_data(0)?, [30, 10]);
+        assert_eq!(replay.train_ids(0), Some(vec![2, 0]));
+        assert!(replay.ids(1).is_err());
+        assert!(replay.train_data(2).is_err());
+        assert_eq!(replay.train_ids(2), None);
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn perturbing_keeps_valid_and_test() {
+        let data = (0..20).map(f64::from).collect::<Vec<_>>();
+        let inner = KFoldSampler::new(data, 4).set_test(vec![100.0, 200.0]);
+        let sampler = PerturbingSampler::new(inner.clone(), jitter_f64(0.5));
+        for gen in 0..4 {
+            assert_eq!(sampler.valid(gen), inner.valid(gen));
+            assert_eq!(sampler.test(gen), inner.test(gen));
+            assert_eq!(sampler.valid_folds(gen), inner.valid_folds(gen));
+            assert_eq!(sampler.train_ids(gen), inner.train_ids(gen));
+
+            let train = sampler.train(gen);
+            assert_eq!(train.len(), inner.train(gen).len());
+            assert!(train.iter().zip(inner.train(gen)).all(|(&a, b)| a != b), "{train:?}");
+            assert_eq!(train, sampler.train(gen));
+            assert_ne!(train, sampler.train(gen + 4));
+        }
+        let reseeded = PerturbingSampler::new(inner, jitter_f64(0.5)).set_seed(1);
+        assert_ne!(reseeded.train(0), sampler.train(0));
+    }
+
+    #[test]
+    fn splitmix64_fixed() {
+        // The first output of SplitMix64 seeded with 0, so perturbations don't
+        // change with the toolchain.
+        assert_eq!(splitmix64(0), 0xe220_a839_7b1d_cdaf);
+        assert_ne!(splitmix64(1), splitmix64(0));
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn perturbing_after_batching() {
+        let data = (0..20).map(|v| vec![f64::from(v), 1.0]).collect::<Vec<_>>();
+        let batched = BatchDataSampler::new(KFoldSampler::new(data.clone(), 2), 4);
+        let sampler = PerturbingSampler::new(batched.clone(), jitter_vec(0.1));
+        for gen in 0..3 {
+            let train = sampler.train(gen);
+            assert_eq!(sampler.train_ids(gen), batched.train_ids(gen));
+            for (v, id) in train.iter().zip(sampler.train_ids(gen).unwrap()) {
+                assert_eq!(v.len(), 2);
+                assert!(v.iter().zip(&data[id]).all(|(a, b)| a != b && (a - b).abs() < 1.0));
+            }
+        }
+
+        // Zero sigma is an exact pass-through, even for negative zero.
+        let inner = KFoldSampler::new(vec![vec![-0.0, 1.5], vec![f64::MAX, -3.25]], 2);
+        let sampler = PerturbingSampler::new(inner.clone(), jitter_vec(0.0));
+        let bits = |v: Vec<Vec<f64>>| v.concat().iter().map(|v| v.to_bits()).collect::<Vec<_>>();
+        for gen in 0..2 {
+            assert_eq!(bits(sampler.train(gen)), bits(inner.train(gen)));
+        }
+    }
 }
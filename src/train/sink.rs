@@ -0,0 +1,301 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use eyre::{eyre, Result};
+use log::warn;
+
+/// A metric reported by the `Trainer`.
+#[must_use]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Metric {
+    // Scalars reported each generation. These may be dropped if a queue is
+    // full, since later generations report them again.
+    Scalars { tag: String, values: HashMap<String, f32>, step: usize },
+    // A single scalar, e.g. a final result. Never dropped.
+    Scalar { tag: String, value: f32, step: usize },
+    // A line of text, e.g. a JSONL record. Never dropped.
+    Line(String),
+}
+
+impl Metric {
+    #[must_use]
+    pub fn critical(&self) -> bool {
+        !matches!(self, Metric::Scalars { .. })
+    }
+}
+
+/// Somewhere to write metrics to.
+pub trait MetricSink: Send {
+    fn write(&mut self, metric: Metric) -> Result<()>;
+
+    /// Waits until everything written so far has been written out.
+    fn flush(&mut self) -> Result<()>;
+
+    /// Number of metrics dropped so far instead of being written.
+    fn dropped(&self) -> usize {
+        0
+    }
+}
+
+/// Writes metrics to text: lines as they are, e.g. JSONL, and scalars as CSV
+/// rows of step, tag and value. Scalars reported together are written in
+/// order of their tag.
+pub struct TextSink<W: Write + Send> {
+    out: BufWriter<W>,
+}
+
+impl<W: Write + Send> TextSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out: BufWriter::new(out) }
+    }
+}
+
+impl<W: Write + Send> MetricSink for TextSink<W> {
+    fn write(&mut self, metric: Metric) -> Result<()> {
+        match metric {
+            Metric::Scalars { tag, values, step } => {
+                let mut values = values.into_iter().collect::<Vec<_>>();
+                values.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+                for (name, value) in values {
+                    writeln!(self.out, "{step},{tag}/{name},{value}")?;
+                }
+            }
+            Metric::Scalar { tag, value, step } => writeln!(self.out, "{step},{tag},{value}")?,
+            Metric::Line(line) => writeln!(self.out, "{line}")?,
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes scalars to tensorboard, flushing after each write. Lines are
+/// ignored.
+#[cfg(feature = "tensorboard")]
+pub struct TensorboardSink {
+    writer: tensorboard_rs::summary_writer::SummaryWriter,
+}
+
+#[cfg(feature = "tensorboard")]
+impl TensorboardSink {
+    pub fn new(path: impl AsRef<std::path::Path>) -> Self {
+        Self { writer: tensorboard_rs::summary_writer::SummaryWriter::new(path) }
+    }
+}
+
+#[cfg(feature = "tensorboard")]
+impl MetricSink for TensorboardSink {
+    fn write(&mut self, metric: Metric) -> Result<()> {
+        match metric {
+            Metric::Scalars { tag, values, step } => self.writer.add_scalars(&tag, &values, step),
+            Metric::Scalar { tag, value, step } => self.writer.add_scalar(&tag, value, step),
+            Metric::Line(_) => return Ok(()),
+        }
+        self.writer.flush();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush();
+        Ok(())
+    }
+}
+
+struct Queue {
+    metrics: VecDeque<Metric>,
+    flush_requests: usize,
+    flushes_done: usize,
+    closed: bool,
+    error: Option<eyre::Report>,
+}
+
+/// Writes metrics to another sink from a background thread, so a slow sink
+/// doesn't hold up training. Writes never block: if more than |capacity|
+/// metrics are waiting, the oldest non-critical one is dropped. Critical
+/// metrics are always kept, even if that goes over capacity. Errors from the
+/// wrapped sink are returned by the next `flush`.
+pub struct AsyncSink {
+    queue: Arc<(Mutex<Queue>, Condvar)>,
+    capacity: usize,
+    dropped: usize,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl AsyncSink {
+    pub fn new(sink: impl MetricSink + 'static, capacity: usize) -> Self {
+        let queue = Arc::new((
+            Mutex::new(Queue {
+                metrics: VecDeque::new(),
+                flush_requests: 0,
+                flushes_done: 0,
+                closed: false,
+                error: None,
+            }),
+            Condvar::new(),
+        ));
+        let worker = {
+            let queue = Arc::clone(&queue);
+            std::thread::spawn(move || Self::work(sink, &queue))
+        };
+        Self { queue, capacity, dropped: 0, worker: Some(worker) }
+    }
+
+    fn work(mut sink: impl MetricSink, queue: &(Mutex<Queue>, Condvar)) {
+        let (lock, cvar) = queue;
+        loop {
+            let (metrics, flush_requests, flush, closed) = {
+                let mut q = lock.lock().unwrap();
+                while q.metrics.is_empty() && q.flush_requests == q.flushes_done && !q.closed {
+                    q = cvar.wait(q).unwrap();
+                }
+                let flush = q.flush_requests > q.flushes_done;
+                (std::mem::take(&mut q.metrics), q.flush_requests, flush, q.closed)
+            };
+            let mut res = metrics.into_iter().try_for_each(|v| sink.write(v));
+            if res.is_ok() && (flush || closed) {
+                res = sink.flush();
+            }
+            let mut q = lock.lock().unwrap();
+            if let Err(e) = res && q.error.is_none() {
+                q.error = Some(e);
+            }
+            q.flushes_done = flush_requests;
+            cvar.notify_all();
+            if closed && q.metrics.is_empty() {
+                return;
+            }
+        }
+    }
+}
+
+impl MetricSink for AsyncSink {
+    fn write(&mut self, metric: Metric) -> Result<()> {
+        let (lock, cvar) = &*self.queue;
+        let mut q = lock.lock().unwrap();
+        if q.metrics.len() >= self.capacity {
+            if let Some(idx) = q.metrics.iter().position(|v| !v.critical()) {
+                let _ = q.metrics.remove(idx);
+                self.dropped += 1;
+            } else if !metric.critical() {
+                self.dropped += 1;
+                return Ok(());
+            }
+        }
+        q.metrics.push_back(metric);
+        cvar.notify_all();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let (lock, cvar) = &*self.queue;
+        let mut q = lock.lock().unwrap();
+        q.flush_requests += 1;
+        let target = q.flush_requests;
+        cvar.notify_all();
+        while q.flushes_done < target {
+            if !matches!(&self.worker, Some(worker) if !worker.is_finished()) {
+                return Err(eyre!("metric writer thread stopped"));
+            }
+            // Time out to notice if the worker panicked.
+            q = cvar.wait_timeout(q, Duration::from_millis(100)).unwrap().0;
+        }
+        q.error.take().map_or(Ok(()), Err)
+    }
+
+    fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+impl Drop for AsyncSink {
+    fn drop(&mut self) {
+        {
+            let (lock, cvar) = &*self.queue;
+            lock.lock().unwrap().closed = true;
+            cvar.notify_all();
+        }
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+        if let Ok(mut q) = self.queue.0.lock() && let Some(e) = q.error.take() {
+            warn!("failed to write metrics: {e:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::util::test_utils::MockSink;
+
+    fn scalars(step: usize) -> Metric {
+        Metric::Scalars { tag: "fitness".to_string(), values: HashMap::new(), step }
+    }
+
+    #[test]
+    fn text_sink() -> Result<()> {
+        let mut out = Vec::new();
+        let mut sink = TextSink::new(&mut out);
+        let values = HashMap::from([("valid".to_string(), 0.5), ("train".to_string(), 2.0)]);
+        sink.write(Metric::Scalars { tag: "fitness".to_string(), values, step: 3 })?;
+        sink.write(Metric::Scalar { tag: "test".to_string(), value: 1.5, step: 4 })?;
+        sink.write(Metric::Line("{\"gen\":4}".to_string()))?;
+        sink.flush()?;
+        drop(sink);
+        assert_eq!(
+            String::from_utf8(out)?,
+            "3,fitness/train,2\n3,fitness/valid,0.5\n4,test,1.5\n{\"gen\":4}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn slow_sink_drops_oldest() -> Result<()> {
+        let delay = Duration::from_millis(50);
+        let sink = MockSink::new().set_delay(delay);
+        let written = sink.written();
+        let mut sink = AsyncSink::new(sink, 4);
+        let start = Instant::now();
+        for step in 0..20 {
+            sink.write(scalars(step))?;
+        }
+        sink.write(Metric::Line("last".to_string()))?;
+        // Writing synchronously would take a second.
+        assert!(start.elapsed() < delay * 4, "writes took {:?}", start.elapsed());
+        sink.flush()?;
+
+        // The worker may have taken the first write before the queue filled,
+        // and then the newest non-critical metrics fill the rest.
+        let written = written.lock().unwrap().clone();
+        assert_eq!(written.len() + sink.dropped(), 21);
+        assert!(sink.dropped() >= 10, "dropped {}", sink.dropped());
+        assert_eq!(written.last(), Some(&Metric::Line("last".to_string())));
+        assert_eq!(written[written.len() - 2], scalars(19));
+        Ok(())
+    }
+
+    #[test]
+    fn critical_metrics_kept() -> Result<()> {
+        let slow = MockSink::new().set_delay(Duration::from_millis(20));
+        let written = slow.written();
+        let mut sink = AsyncSink::new(slow, 1);
+        for i in 0..5 {
+            sink.write(Metric::Line(i.to_string()))?;
+        }
+        sink.write(scalars(0))?;
+        sink.flush()?;
+        let lines = written.lock().unwrap().iter().filter(|v| v.critical()).count();
+        assert_eq!(lines, 5);
+        Ok(())
+    }
+}
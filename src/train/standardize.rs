@@ -0,0 +1,359 @@
+use std::fmt::Write;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+use eyre::{eyre, Result};
+
+use crate::eval::{Data, DataEpoch};
+use crate::train::sampler::DataSampler;
+
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Scaling {
+    MeanStd, // Zero mean and unit standard deviation.
+    MinMax,  // Maps the range of each feature to [0, 1].
+}
+
+/// Per-feature affine transform fitted on training data. Each feature is
+/// mapped to (v - offset) / scale. Features that are constant in the training
+/// data have a scale of 1, so they are only shifted.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Standardizer {
+    pub scaling: Scaling,
+    pub offset: Vec<f64>,
+    pub scale: Vec<f64>,
+}
+
+impl Standardizer {
+    /// Fits to the features of |data|, as given by |f|. Every item must have
+    /// the same number of features.
+    pub fn fit<D: Data>(
+        scaling: Scaling,
+        data: &[D],
+        f: impl Fn(&mut D) -> &mut [f64],
+    ) -> Result<Self> {
+        let mut rows = data.to_vec();
+        let rows = rows.iter_mut().map(f).collect::<Vec<_>>();
+        let n = rows.first().map_or(0, |v| v.len());
+        if rows.iter().any(|v| v.len() != n) {
+            return Err(eyre!("data items have different numbers of features"));
+        }
+        let (offset, spread): (Vec<_>, Vec<_>) = (0..n)
+            .map(|i| {
+                let vals = rows.iter().map(|v| v[i]);
+                match scaling {
+                    Scaling::MeanStd => {
+                        let mean = vals.clone().sum::<f64>() / rows.len() as f64;
+                        let var =
+                            vals.map(|v| (v - mean) * (v - mean)).sum::<f64>() / rows.len() as f64;
+                        (mean, var.sqrt())
+                    }
+                    Scaling::MinMax => {
+                        let min = vals.clone().fold(f64::INFINITY, f64::min);
+                        let max = vals.fold(f64::NEG_INFINITY, f64::max);
+                        (min, max - min)
+                    }
+                }
+            })
+            .unzip();
+        if offset.iter().chain(&spread).any(|v| !v.is_finite()) {
+            return Err(eyre!("non-finite feature in training data"));
+        }
+        let scale = spread.into_iter().map(|v| if v > 0.0 { v } else { 1.0 }).collect();
+        Ok(Self { scaling, offset, scale })
+    }
+
+    /// Transforms the features |v| in place. Fails if |v| doesn't have one
+    /// value per fitted feature.
+    pub fn transform(&self, v: &mut [f64]) -> Result<()> {
+        self.check_len(v)?;
+        for ((v, offset), scale) in v.iter_mut().zip(&self.offset).zip(&self.scale) {
+            *v = (*v - offset) / scale;
+        }
+        Ok(())
+    }
+
+    /// Inverse of `transform`, e.g. to map predicted features back.
+    pub fn inverse(&self, v: &mut [f64]) -> Result<()> {
+        self.check_len(v)?;
+        for ((v, offset), scale) in v.iter_mut().zip(&self.offset).zip(&self.scale) {
+            *v = *v * scale + offset;
+        }
+        Ok(())
+    }
+
+    fn check_len(&self, v: &[f64]) -> Result<()> {
+        if v.len() == self.offset.len() {
+            Ok(())
+        } else {
+            Err(eyre!("got {} features, expected {}", v.len(), self.offset.len()))
+        }
+    }
+
+    /// Checks there are as many offsets as scales, offsets are finite and
+    /// scales are finite and positive, as `fit` produces.
+    pub fn validate(&self) -> Result<()> {
+        if self.offset.len() != self.scale.len() {
+            return Err(eyre!("{} offsets but {} scales", self.offset.len(), self.scale.len()));
+        }
+        if let Some(v) = self.offset.iter().find(|v| !v.is_finite()) {
+            return Err(eyre!("non-finite offset {v}"));
+        }
+        if let Some(v) = self.scale.iter().find(|&&v| !v.is_finite() || v <= 0.0) {
+            return Err(eyre!("invalid scale {v}"));
+        }
+        Ok(())
+    }
+
+    /// Text form, readable by `from_text`. Values are written exactly, so a
+    /// round trip gives the same transform. Fails if `validate` does.
+    pub fn to_text(&self) -> Result<String> {
+        self.validate()?;
+        let scaling = match self.scaling {
+            Scaling::MeanStd => "meanstd",
+            Scaling::MinMax => "minmax",
+        };
+        let mut s = format!("scaling {scaling}\n");
+        for (offset, scale) in self.offset.iter().zip(&self.scale) {
+            let _ = writeln!(s, "{offset:?} {scale:?}");
+        }
+        Ok(s)
+    }
+
+    /// Reads the text form written by `to_text`, checked with `validate`.
+    pub fn from_text(text: &str) -> Result<Self> {
+        let mut lines = text.lines();
+        let header = lines.next().ok_or_else(|| eyre!("missing scaling"))?;
+        let scaling = match header.split_whitespace().collect::<Vec<_>>()[..] {
+            ["scaling", "meanstd"] => Scaling::MeanStd,
+            ["scaling", "minmax"] => Scaling::MinMax,
+            _ => return Err(eyre!("invalid scaling '{header}'")),
+        };
+        let (mut offset, mut scale) = (Vec::new(), Vec::new());
+        for line in lines {
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [o, s] => {
+                    offset.push(o.parse()?);
+                    scale.push(s.parse()?);
+                }
+                _ => return Err(eyre!("invalid feature '{line}'")),
+            }
+        }
+        let v = Self { scaling, offset, scale };
+        v.validate()?;
+        Ok(v)
+    }
+}
+
+/// Wraps a given `DataSampler` and standardizes the features of all splits,
+/// as given by |f|, with a `Standardizer` fitted on the training split only.
+/// It is refitted only when `DataSampler::train_epoch` changes. As a
+/// `DataSampler`, panics if the training split can't be fitted, e.g. if it has
+/// non-finite features. Use `transform` to handle that as an error.
+pub struct StandardizedSampler<D: Data, S: DataSampler<D>, F: Fn(&mut D) -> &mut [f64]> {
+    sampler: S,
+    scaling: Scaling,
+    f: F,
+    fitted: Mutex<Option<(DataEpoch, Standardizer)>>,
+    _u: PhantomData<D>,
+}
+
+impl<D: Data, S: DataSampler<D>, F: Fn(&mut D) -> &mut [f64]> StandardizedSampler<D, S, F> {
+    pub fn new(sampler: S, scaling: Scaling, f: F) -> Self {
+        Self { sampler, scaling, f, fitted: Mutex::new(None), _u: PhantomData }
+    }
+
+    /// Standardizer used for |gen|, fitted on its training split.
+    pub fn standardizer(&self, gen: usize) -> Result<Standardizer> {
+        let epoch = self.sampler.train_epoch(gen);
+        let mut fitted = self.fitted.lock().unwrap();
+        match &*fitted {
+            Some((e, v)) if *e == epoch => Ok(v.clone()),
+            _ => {
+                let v = Standardizer::fit(self.scaling, &self.sampler.train(gen), &self.f)?;
+                *fitted = Some((epoch, v.clone()));
+                Ok(v)
+            }
+        }
+    }
+
+    /// Standardizes the features of |data| for |gen|.
+    pub fn transform(&self, gen: usize, mut data: Vec<D>) -> Result<Vec<D>> {
+        let standardizer = self.standardizer(gen)?;
+        for v in &mut data {
+            standardizer.transform((self.f)(v))?;
+        }
+        Ok(data)
+    }
+
+    fn transform_or_panic(&self, gen: usize, data: Vec<D>) -> Vec<D> {
+        self.transform(gen, data)
+            .unwrap_or_else(|e| panic!("failed to standardize data for gen {gen}: {e:#}"))
+    }
+}
+
+impl<D: Data, S: DataSampler<D>, F: Fn(&mut D) -> &mut [f64]> DataSampler<D>
+    for StandardizedSampler<D, S, F>
+{
+    fn train(&self, gen: usize) -> Vec<D> {
+        self.transform_or_panic(gen, self.sampler.train(gen))
+    }
+
+    fn valid(&self, gen: usize) -> Vec<D> {
+        self.transform_or_panic(gen, self.sampler.valid(gen))
+    }
+
+    fn test(&self, gen: usize) -> Vec<D> {
+        self.transform_or_panic(gen, self.sampler.test(gen))
+    }
+
+    fn train_epoch(&self, gen: usize) -> DataEpoch {
+        self.sampler.train_epoch(gen)
+    }
+
+    fn train_len(&self, gen: usize) -> usize {
+        self.sampler.train_len(gen)
+    }
+
+    fn batch_id(&self, gen: usize) -> u64 {
+        self.sampler.batch_id(gen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    // Train data changes every |every| generations, to features [e, 2e, 7]
+    // and [e + 2, 2e + 4, 7] for epoch e. Valid and test data are fixed.
+    struct EpochSampler {
+        every: usize,
+        train_calls: Cell<usize>,
+    }
+
+    impl DataSampler<Vec<f64>> for EpochSampler {
+        fn train(&self, gen: usize) -> Vec<Vec<f64>> {
+            self.train_calls.set(self.train_calls.get() + 1);
+            let e = self.train_epoch(gen) as f64;
+            vec![vec![e, 2.0 * e, 7.0], vec![e + 2.0, 2.0 * e + 4.0, 7.0]]
+        }
+
+        fn valid(&self, _gen: usize) -> Vec<Vec<f64>> {
+            vec![vec![1.0, 2.0, 7.0]]
+        }
+
+        fn test(&self, _gen: usize) -> Vec<Vec<f64>> {
+            vec![vec![100.0, 100.0, 100.0]]
+        }
+
+        fn train_epoch(&self, gen: usize) -> DataEpoch {
+            (gen / self.every) as DataEpoch
+        }
+    }
+
+    type Features = fn(&mut Vec<f64>) -> &mut [f64];
+
+    fn sampler(every: usize) -> StandardizedSampler<Vec<f64>, EpochSampler, Features> {
+        let sampler = EpochSampler { every, train_calls: Cell::new(0) };
+        StandardizedSampler::new(sampler, Scaling::MeanStd, Vec::as_mut_slice)
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn transform_math() -> Result<()> {
+        // The last feature is constant, so it only gets shifted.
+        let data = [vec![1.0, 10.0, 5.0], vec![3.0, 30.0, 5.0]];
+        let v = Standardizer::fit(Scaling::MeanStd, &data, Vec::as_mut_slice)?;
+        assert_eq!(v.offset, [2.0, 20.0, 5.0]);
+        assert_eq!(v.scale, [1.0, 10.0, 1.0]);
+        let mut x = vec![5.0, 0.0, 6.0];
+        v.transform(&mut x)?;
+        assert_eq!(x, [3.0, -2.0, 1.0]);
+        v.inverse(&mut x)?;
+        assert_eq!(x, [5.0, 0.0, 6.0]);
+        assert!(v.transform(&mut [1.0, 2.0]).is_err());
+
+        let v = Standardizer::fit(Scaling::MinMax, &data, Vec::as_mut_slice)?;
+        assert_eq!(v.offset, [1.0, 10.0, 5.0]);
+        assert_eq!(v.scale, [2.0, 20.0, 1.0]);
+        let mut x = vec![2.0, 40.0, 5.0];
+        v.transform(&mut x)?;
+        assert_eq!(x, [0.5, 1.5, 0.0]);
+
+        let bad = [vec![1.0], vec![1.0, 2.0]];
+        assert!(Standardizer::fit(Scaling::MinMax, &bad, Vec::as_mut_slice).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn text_round_trip() -> Result<()> {
+        let data = [vec![1.0, 10.0], vec![3.0, 30.0]];
+        let v = Standardizer::fit(Scaling::MinMax, &data, Vec::as_mut_slice)?;
+        assert_eq!(Standardizer::from_text(&v.to_text()?)?, v);
+
+        let text = "scaling meanstd\n1.0 2.0\n";
+        assert!(Standardizer::from_text(text).is_ok());
+        assert!(Standardizer::from_text("scaling meanstd\nNaN 2.0\n").is_err());
+        assert!(Standardizer::from_text("scaling meanstd\n1.0 inf\n").is_err());
+        assert!(Standardizer::from_text("scaling meanstd\n1.0 0.0\n").is_err());
+        let uneven = Standardizer { scale: vec![], ..Standardizer::from_text(text)? };
+        assert!(uneven.to_text().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn non_finite_training_data() {
+        struct NanSampler;
+
+        impl DataSampler<Vec<f64>> for NanSampler {
+            fn train(&self, _gen: usize) -> Vec<Vec<f64>> {
+                vec![vec![f64::NAN]]
+            }
+
+            fn valid(&self, _gen: usize) -> Vec<Vec<f64>> {
+                vec![vec![1.0]]
+            }
+
+            fn test(&self, _gen: usize) -> Vec<Vec<f64>> {
+                vec![]
+            }
+        }
+
+        let s = StandardizedSampler::new(NanSampler, Scaling::MeanStd, Vec::as_mut_slice);
+        assert!(s.transform(0, vec![vec![1.0]]).is_err());
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn no_leakage() {
+        // Train stats for epoch 0 are mean [1, 2, 7] and std [1, 2, 1], so
+        // valid and test data use those regardless of their own values.
+        let s = sampler(10);
+        assert_eq!(s.train(0), [vec![-1.0, -1.0, 0.0], vec![1.0, 1.0, 0.0]]);
+        assert_eq!(s.valid(0), [vec![0.0, 0.0, 0.0]]);
+        assert_eq!(s.test(0), [vec![99.0, 49.0, 93.0]]);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn refit_per_epoch() -> Result<()> {
+        let s = sampler(3);
+        for gen in 0..3 {
+            assert_eq!(s.standardizer(gen)?.offset, [1.0, 2.0, 7.0]);
+            let _ = s.valid(gen);
+        }
+        assert_eq!(s.sampler.train_calls.get(), 1);
+        // Epoch 1 has mean [2, 4, 7].
+        assert_eq!(s.valid(3), [vec![-1.0, -1.0, 0.0]]);
+        assert_eq!(s.standardizer(5)?.offset, [2.0, 4.0, 7.0]);
+        assert_eq!(s.sampler.train_calls.get(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::evolve::cfg::Termination;
+use crate::evolve::result::Throughput;
+
+/// Durations and fitness evaluations of the last few generations, so
+/// throughput is smoothed over a window rather than jumping around with
+/// every generation.
+#[must_use]
+#[derive(Debug, Clone)]
+pub struct ThroughputTracker {
+    window: usize,
+    recent: VecDeque<(Duration, usize)>,
+    evals: usize,      // Fitness evaluations recorded in total.
+    elapsed: Duration, // Time recorded in total.
+}
+
+impl ThroughputTracker {
+    /// Tracks the last |window| generations, at least one.
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        Self { window, recent: VecDeque::with_capacity(window), evals: 0, elapsed: Duration::ZERO }
+    }
+
+    /// Like `new`, but counting |evals| fitness evaluations made before, e.g.
+    /// by training which was resumed from a checkpoint.
+    pub fn resumed(window: usize, evals: usize) -> Self {
+        Self { evals, ..Self::new(window) }
+    }
+
+    /// Records a generation which took |elapsed| and made |evals| fitness
+    /// evaluations, dropping the oldest once the window is full.
+    pub fn record(&mut self, elapsed: Duration, evals: usize) {
+        if self.recent.len() == self.window {
+            let _ = self.recent.pop_front();
+        }
+        self.recent.push_back((elapsed, evals));
+        self.evals += evals;
+        self.elapsed += elapsed;
+    }
+
+    /// Fitness evaluations recorded in total, including those no longer in
+    /// the window.
+    #[must_use]
+    pub fn evals(&self) -> usize {
+        self.evals
+    }
+
+    /// Throughput over the window, with the time left until the first of
+    /// |terminations| is met if the next generation to run is |next_gen|.
+    /// None until generations taking some time have been recorded.
+    #[must_use]
+    pub fn throughput(&self, terminations: &[Termination], next_gen: usize) -> Option<Throughput> {
+        let secs = self.recent.iter().map(|(d, _)| d.as_secs_f64()).sum::<f64>();
+        if secs <= 0.0 {
+            return None;
+        }
+        let gens_per_sec = self.recent.len() as f64 / secs;
+        let evals_per_sec = self.recent.iter().map(|&(_, v)| v).sum::<usize>() as f64 / secs;
+        let eta = terminations
+            .iter()
+            .filter_map(|&termination| {
+                let eta = match termination {
+                    Termination::FixedGenerations(gen) => {
+                        Some(gen.saturating_sub(next_gen) as f64 / gens_per_sec)
+                    }
+                    Termination::FitnessEvaluations(evals) if evals_per_sec > 0.0 => {
+                        Some(evals.saturating_sub(self.evals) as f64 / evals_per_sec)
+                    }
+                    Termination::Timeout(timeout) => {
+                        Some(timeout.saturating_sub(self.elapsed).as_secs_f64())
+                    }
+                    Termination::FitnessEvaluations(_) | Termination::TargetFitness(_) => None,
+                };
+                // Huge budgets, e.g. usize::MAX generations, can't be
+                // represented.
+                eta.and_then(|v| Duration::try_from_secs_f64(v).ok())
+            })
+            .min();
+        Some(Throughput { gens_per_sec, evals_per_sec, eta })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENS: &[Termination] = &[Termination::FixedGenerations(100)];
+
+    fn secs(v: u64) -> Duration {
+        Duration::from_secs(v)
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn first_generation() {
+        let mut t = ThroughputTracker::new(4);
+        assert_eq!(t.throughput(GENS, 0), None);
+        // A generation which took no time gives no rate.
+        t.record(Duration::ZERO, 10);
+        assert_eq!(t.throughput(GENS, 1), None);
+
+        let mut t = ThroughputTracker::new(4);
+        t.record(secs(2), 10);
+        let v = t.throughput(GENS, 1).unwrap();
+        assert_eq!(v.gens_per_sec, 0.5);
+        assert_eq!(v.evals_per_sec, 5.0);
+        assert_eq!(v.eta, Some(secs(198)));
+        assert_eq!(v.to_string(), "0.50 gens/s, 5.0 evals/s, eta 3m18s");
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn window_boundary() {
+        let mut t = ThroughputTracker::new(3);
+        for (d, evals) in [(10, 100), (1, 10), (2, 20), (3, 30)] {
+            t.record(secs(d), evals);
+        }
+        // The first generation has left the window, but still counts towards
+        // the total evaluations.
+        let v = t.throughput(GENS, 4).unwrap();
+        assert_eq!(v.gens_per_sec, 0.5);
+        assert_eq!(v.evals_per_sec, 10.0);
+        assert_eq!(v.eta, Some(secs(192)));
+        assert_eq!(t.evals(), 160);
+
+        // Exactly filling the window keeps every generation.
+        let mut t = ThroughputTracker::new(3);
+        for d in [1, 2, 3] {
+            t.record(secs(d), 0);
+        }
+        assert_eq!(t.throughput(GENS, 3).unwrap().gens_per_sec, 0.5);
+    }
+
+    #[test]
+    fn eta() {
+        let mut t = ThroughputTracker::new(2);
+        t.record(secs(1), 25);
+        t.record(secs(3), 75);
+        let eta = |termination: Termination, next_gen| {
+            t.throughput(&[termination], next_gen).unwrap().eta
+        };
+        assert_eq!(eta(Termination::FitnessEvaluations(1100), 2), Some(secs(40)));
+        assert_eq!(eta(Termination::FitnessEvaluations(50), 2), Some(Duration::ZERO));
+        assert_eq!(eta(Termination::FixedGenerations(2), 2), Some(Duration::ZERO));
+        assert_eq!(eta(Termination::FixedGenerations(usize::MAX), 2), None);
+
+        // Without evaluations the budget can't be estimated.
+        let mut t = ThroughputTracker::new(2);
+        t.record(secs(1), 0);
+        let v = t.throughput(&[Termination::FitnessEvaluations(10)], 1).unwrap();
+        assert_eq!(v.eta, None);
+        assert_eq!(v.to_string(), "1.00 gens/s, 0.0 evals/s");
+    }
+
+    #[test]
+    fn eta_combined() {
+        let mut t = ThroughputTracker::new(2);
+        for _ in 0..4 {
+            t.record(secs(1), 10);
+        }
+        let eta = |terminations: &[Termination]| t.throughput(terminations, 4).unwrap().eta;
+        assert_eq!(eta(&[Termination::Timeout(secs(10))]), Some(secs(6)));
+        assert_eq!(eta(&[Termination::Timeout(secs(3))]), Some(Duration::ZERO));
+        assert_eq!(eta(&[Termination::TargetFitness(1.0)]), None);
+        assert_eq!(eta(&[]), None);
+        // The first criterion to be met decides.
+        let terminations = [
+            Termination::TargetFitness(1.0),
+            Termination::FixedGenerations(100),
+            Termination::Timeout(secs(10)),
+        ];
+        assert_eq!(eta(&terminations), Some(secs(6)));
+        assert_eq!(eta(&terminations[..2]), Some(secs(96)));
+    }
+}
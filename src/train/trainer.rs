@@ -1,17 +1,119 @@
-use eyre::Result;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-use crate::eval::Evaluator;
+use approx::{abs_diff_eq, relative_eq};
+use eyre::{eyre, Result, WrapErr};
+use log::{debug, info, log_enabled, warn, Level, LevelFilter, Metadata, Record};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::eval::{Data, Evaluator};
+use crate::evolve::archive::SharedArchive;
+use crate::evolve::cfg::{StagnationCondition, StagnationSignal, Termination};
+use crate::evolve::checkpoint::MemberCheckpoint;
 use crate::evolve::evolver::Evolver;
-use crate::evolve::result::EvolveResult;
-use crate::train::cfg::{Termination, TrainerCfg};
-use crate::train::sampler::DataSampler;
+use crate::evolve::result::{EvolveResult, Stats};
+use crate::gen::member::{next_member_id, Member};
+use crate::gen::snapshot::{PopulationSnapshot, SpeciesSnapshot};
+use crate::train::cfg::{TargetSignal, TrainerCfg};
+use crate::train::checkpoint::TrainerCheckpoint;
+#[cfg(feature = "serde")]
+use crate::train::checkpoint::{Checkpoint, ParallelCheckpoint};
+use crate::train::parallel::{LabelSummary, ParallelReport, ParallelResult};
+use crate::train::sampler::{DataSampler, SampledBatch};
+use crate::train::sink::{AsyncSink, Metric, MetricSink, TextSink};
+use crate::train::throughput::ThroughputTracker;
+use crate::util::fmt::{fmt_fitness, set_fitness_digits};
+
+// Prints records to stdout: memega's own at debug level and above, so samples
+// are included, and everything else at info level and above.
+struct StdoutLogger;
+
+impl log::Log for StdoutLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= Level::Info
+            || (metadata.level() <= Level::Debug && metadata.target().starts_with("memega"))
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            Level::Error | Level::Warn => println!("{}: {}", record.level(), record.args()),
+            _ => println!("{}", record.args()),
+        }
+    }
 
-/// Runs evolution with the given parameters and prints some info.
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+    }
+}
+
+/// Runs evolution with the given parameters and logs some info. Generation
+/// info, summaries and validation fitness are logged at info level, and
+/// samples, which list members of each species, at debug level. Generation
+/// info is reported to tensorboard, or another `MetricSink`.
 #[must_use]
 pub struct Trainer {
     cfg: TrainerCfg,
-    #[cfg(feature = "tensorboard")]
-    writer: Option<tensorboard_rs::summary_writer::SummaryWriter>,
+    metrics: Option<Box<dyn MetricSink>>,
+    snapshot_fn: Option<Box<dyn SnapshotFn>>,
+}
+
+/// Called with the generation index and a snapshot of the population. See
+/// `Trainer::set_snapshot_fn`.
+pub trait SnapshotFn = FnMut(usize, &PopulationSnapshot<'_>) + Send;
+
+// Saves a checkpoint of each run's evolver and the trainer's bookkeeping for it.
+trait CheckpointFn<E: Evaluator> = FnMut(&[Run<E>], Vec<TrainerCheckpoint<E::State>>) -> Result<()>;
+
+// An evolver being trained, and the trainer's bookkeeping for it.
+struct Run<E: Evaluator> {
+    // Prefixes metric tags and logs when training several evolvers at once.
+    label: Option<String>,
+    evolver: Evolver<E>,
+    // Best member by cross validated fitness so far, and that fitness.
+    cv_best: Option<(Member<E::State>, f64)>,
+    // Training fitness summed over generations since the last report.
+    fitness_sum: f64,
+    // Best training fitness so far, and the generation it was reached in.
+    best: Option<(f64, usize)>,
+    last: Option<EvolveResult<E::State>>,
+}
+
+impl<E: Evaluator> Run<E> {
+    fn new(label: Option<String>, evolver: Evolver<E>) -> Self {
+        Self { label, evolver, cv_best: None, fitness_sum: 0.0, best: None, last: None }
+    }
+
+    // Metric tag |name|, prefixed with the label if there is one.
+    fn tag(&self, name: &str) -> String {
+        match &self.label {
+            Some(label) => format!("{label}/{name}"),
+            None => name.to_string(),
+        }
+    }
+
+    // Prefix naming the run in logs.
+    fn name(&self) -> String {
+        self.label.as_ref().map_or(String::new(), |v| format!("{v}: "))
+    }
+}
+
+// A generation, as recorded for each run by `Trainer::record_gen`.
+#[derive(Clone, Copy)]
+struct GenInfo {
+    i: usize,
+    batch: u64,
+    time: Duration,
+    // Generations training fitness is averaged over, if metrics are reported
+    // this generation.
+    report: Option<f64>,
 }
 
 impl Trainer {
@@ -20,14 +122,15 @@ impl Trainer {
         use std::mem;
 
         use chrono::Local;
-        use log::warn;
         use tempfile::Builder;
 
+        use crate::train::sink::TensorboardSink;
+
         let report_path = if let Some(report_path) = &cfg.report_path {
             Some(report_path.clone())
         } else if cfg.report_gen.is_some() {
             let path = std::env::temp_dir().join("tensorboard");
-            std::fs::create_dir_all(path.clone()).unwrap();
+            fs::create_dir_all(path.clone()).unwrap();
             let prefix = format!("{}-{}", cfg.name, Local::now().format("%Y-%m-%d-%H-%M-%S"));
             let tmp = Builder::new().prefix(&prefix).tempdir_in(path).unwrap();
             warn!("No report path specified for tensorboard, writing to {}", tmp.path().display());
@@ -37,80 +140,1369 @@ impl Trainer {
         } else {
             None
         };
-        let writer = report_path.as_ref().map(tensorboard_rs::summary_writer::SummaryWriter::new);
-        Self { cfg, writer }
+        let metrics = report_path.map(|path| Self::sink(&cfg, TensorboardSink::new(path)));
+        Self { cfg, metrics, snapshot_fn: None }
     }
 
     pub fn new(cfg: TrainerCfg) -> Self {
+        if cfg.stdout && log::set_logger(&StdoutLogger).is_ok() {
+            log::set_max_level(LevelFilter::Debug);
+        }
         #[cfg(feature = "tensorboard")]
         let s = Self::new_tensorboard(cfg);
         #[cfg(not(feature = "tensorboard"))]
-        let s = Self { cfg };
+        let s = Self { cfg, metrics: None, snapshot_fn: None };
         s
     }
 
+    /// Reports generation info to |sink| instead of tensorboard, e.g. a
+    /// `TextSink` writing CSV. Uses a background thread as for tensorboard if
+    /// `TrainerCfg::metric_queue` is set.
+    pub fn set_metric_sink(mut self, sink: impl MetricSink + 'static) -> Self {
+        self.metrics = Some(Self::sink(&self.cfg, sink));
+        self
+    }
+
+    /// Calls |f| with a snapshot of the population every
+    /// `TrainerCfg::snapshot_gen` generations, e.g. to collect fitness
+    /// histograms or species sizes over time in storage of your own.
+    pub fn set_snapshot_fn(mut self, f: impl SnapshotFn + 'static) -> Self {
+        self.snapshot_fn = Some(Box::new(f));
+        self
+    }
+
+    fn sink(cfg: &TrainerCfg, sink: impl MetricSink + 'static) -> Box<dyn MetricSink> {
+        match cfg.metric_queue {
+            Some(capacity) => Box::new(AsyncSink::new(sink, capacity)),
+            None => Box::new(sink),
+        }
+    }
+
     pub fn train<E: Evaluator>(
         &mut self,
-        mut evolver: Evolver<E>,
+        evolver: Evolver<E>,
         sampler: &impl DataSampler<E::Data>,
     ) -> Result<EvolveResult<E::State>> {
-        let mut ret = None;
-        let mut fitness_sum = 0.0;
-        let mut fitness_count = 0.0;
-        for i in 0.. {
-            match self.cfg.termination {
-                Termination::FixedGenerations(gen) => {
-                    if i >= gen {
-                        break;
+        let mut runs = [Run::new(None, evolver)];
+        Ok(self.train_runs(&mut runs, sampler, None, None)?.remove(0))
+    }
+
+    /// Like `train`, but checkpoints the evolver and the trainer's bookkeeping
+    /// to |checkpoint_path| every |every_n| generations and once training is
+    /// done. If a checkpoint is already there, training resumes from it, with
+    /// the evolver from |evolver_factory| restored to the checkpointed state,
+    /// so it must have the same evaluator and config. Checkpoints replace the
+    /// previous one only once written in full, see `Checkpoint::save`.
+    ///
+    /// On resuming, species snapshots and sampling traces are cut back to how
+    /// they were at the checkpoint, so generations run again aren't recorded
+    /// twice. Metrics are only reported once checkpointed, so they lag behind
+    /// by up to |every_n| generations. Time for `Termination::Timeout` counts
+    /// from when training resumed. A seeded evolver, see `Evolver::new_seeded`,
+    /// ends up the same as if training was never interrupted.
+    #[cfg(feature = "serde")]
+    pub fn train_resumable<E: Evaluator>(
+        &mut self,
+        evolver_factory: impl FnOnce() -> Evolver<E>,
+        sampler: &impl DataSampler<E::Data>,
+        checkpoint_path: impl AsRef<Path>,
+        every_n: usize,
+    ) -> Result<EvolveResult<E::State>>
+    where
+        E::State: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        if every_n == 0 {
+            return Err(eyre!("checkpoints must be at least one generation apart"));
+        }
+        let path = checkpoint_path.as_ref();
+        let (evolver, resume) = match Checkpoint::load(path)? {
+            Some(checkpoint) => {
+                info!("resuming from {} at gen {}", path.display(), checkpoint.evolver.gen);
+                (evolver_factory().restore(checkpoint.evolver)?, Some(vec![checkpoint.trainer]))
+            }
+            None => (evolver_factory(), None),
+        };
+        let mut save = |runs: &[Run<E>], mut trainers: Vec<TrainerCheckpoint<E::State>>| {
+            Checkpoint { evolver: runs[0].evolver.checkpoint(), trainer: trainers.remove(0) }
+                .save(path)
+        };
+        let mut runs = [Run::new(None, evolver)];
+        Ok(self.train_runs(&mut runs, sampler, resume, Some((every_n, &mut save)))?.remove(0))
+    }
+
+    /// Trains |evolvers| side by side, running a generation of each in turn
+    /// on the same training data, so they all see identical batches. Metrics
+    /// are reported with each evolver's label prefixed to their tag, and logs
+    /// name the label, so labels must be distinct, non-empty and not contain
+    /// '/'. The function from `set_snapshot_fn` is called for each evolver in
+    /// turn, in the order given. Training stops for every evolver once any
+    /// termination condition is met: fitness evaluations are counted over all
+    /// of them, and a target fitness is reached once any of them reaches it.
+    /// Species snapshots aren't supported, since they'd share one file.
+    pub fn train_parallel<E: Evaluator>(
+        &mut self,
+        evolvers: Vec<(String, Evolver<E>)>,
+        sampler: &impl DataSampler<E::Data>,
+    ) -> Result<ParallelResult<E::State>> {
+        let mut runs = Self::parallel_runs(evolvers)?;
+        let results = self.train_runs(&mut runs, sampler, None, None)?;
+        Ok(self.parallel_result(&runs, results))
+    }
+
+    /// Like `train_parallel`, but checkpoints every evolver to
+    /// |checkpoint_path|, and resumes from it, as `train_resumable` does. The
+    /// evolvers from |evolvers_factory| must have the same labels, in the same
+    /// order, as those checkpointed.
+    #[cfg(feature = "serde")]
+    pub fn train_parallel_resumable<E: Evaluator>(
+        &mut self,
+        evolvers_factory: impl FnOnce() -> Vec<(String, Evolver<E>)>,
+        sampler: &impl DataSampler<E::Data>,
+        checkpoint_path: impl AsRef<Path>,
+        every_n: usize,
+    ) -> Result<ParallelResult<E::State>>
+    where
+        E::State: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        if every_n == 0 {
+            return Err(eyre!("checkpoints must be at least one generation apart"));
+        }
+        let path = checkpoint_path.as_ref();
+        let evolvers = evolvers_factory();
+        let (evolvers, resume) = match ParallelCheckpoint::load(path)? {
+            Some(checkpoint) => {
+                if checkpoint.runs.len() != evolvers.len() {
+                    return Err(eyre!(
+                        "checkpoint has {} evolvers, not {}",
+                        checkpoint.runs.len(),
+                        evolvers.len()
+                    ));
+                }
+                let gen = checkpoint.runs.first().map_or(0, |(_, v)| v.evolver.gen);
+                info!("resuming from {} at gen {gen}", path.display());
+                let mut restored = Vec::with_capacity(evolvers.len());
+                let mut trainers = Vec::with_capacity(evolvers.len());
+                for ((label, evolver), (saved, v)) in evolvers.into_iter().zip(checkpoint.runs) {
+                    if label != saved {
+                        return Err(eyre!("checkpoint has evolver {saved} in place of {label}"));
                     }
+                    restored.push((label, evolver.restore(v.evolver)?));
+                    trainers.push(v.trainer);
                 }
+                (restored, Some(trainers))
             }
-            let mut r = evolver.run_data(&sampler.train(i))?;
+            None => (evolvers, None),
+        };
+        let mut save = |runs: &[Run<E>], trainers: Vec<TrainerCheckpoint<E::State>>| {
+            let runs = runs
+                .iter()
+                .zip(trainers)
+                .map(|(run, trainer)| {
+                    let label = run.label.clone().unwrap_or_default();
+                    (label, Checkpoint { evolver: run.evolver.checkpoint(), trainer })
+                })
+                .collect();
+            ParallelCheckpoint { runs }.save(path)
+        };
+        let mut runs = Self::parallel_runs(evolvers)?;
+        let results = self.train_runs(&mut runs, sampler, resume, Some((every_n, &mut save)))?;
+        Ok(self.parallel_result(&runs, results))
+    }
 
-            fitness_sum += r.nth(0).fitness;
+    // Runs for |evolvers|, labelled with their names, which must be distinct,
+    // non-empty and not contain '/', since they prefix metric tags.
+    fn parallel_runs<E: Evaluator>(evolvers: Vec<(String, Evolver<E>)>) -> Result<Vec<Run<E>>> {
+        if evolvers.is_empty() {
+            return Err(eyre!("no evolvers to train"));
+        }
+        for (i, (label, _)) in evolvers.iter().enumerate() {
+            if label.is_empty() || label.contains('/') {
+                return Err(eyre!("label {label:?} must be non-empty and not contain '/'"));
+            }
+            if evolvers[..i].iter().any(|(v, _)| v == label) {
+                return Err(eyre!("duplicate label {label}"));
+            }
+        }
+        Ok(evolvers.into_iter().map(|(label, evolver)| Run::new(Some(label), evolver)).collect())
+    }
+
+    // Labels |results| of |runs| and compares them.
+    fn parallel_result<E: Evaluator>(
+        &self,
+        runs: &[Run<E>],
+        results: Vec<EvolveResult<E::State>>,
+    ) -> ParallelResult<E::State> {
+        let mut report = ParallelReport::default();
+        let mut labelled = Vec::with_capacity(runs.len());
+        for (run, r) in runs.iter().zip(results) {
+            let label = run.label.clone().unwrap_or_default();
+            let final_best = r.nth(0).fitness;
+            let (best, best_gen) = run.best.unwrap_or((final_best, r.unevaluated.gen_idx));
+            report.rows.push(LabelSummary { label: label.clone(), final_best, best, best_gen });
+            labelled.push((label, r));
+        }
+        if self.cfg.print_gen.is_some() {
+            let _digits = self.cfg.fitness_digits.map(set_fitness_digits);
+            info!("{report}");
+        }
+        ParallelResult { results: labelled, report }
+    }
+
+    // Trains |runs| side by side on the same data, continuing the trainer's
+    // bookkeeping from |resume|, one checkpoint per run, if given. Calls
+    // |checkpoint| every so many generations and once training is done, after
+    // everything recorded so far is written out. Metrics are held back until
+    // then, so a run resumed from the checkpoint doesn't report generations it
+    // runs again twice. Returns the final result of each run.
+    fn train_runs<E: Evaluator>(
+        &mut self,
+        runs: &mut [Run<E>],
+        sampler: &impl DataSampler<E::Data>,
+        resume: Option<Vec<TrainerCheckpoint<E::State>>>,
+        mut checkpoint: Option<(usize, &mut dyn CheckpointFn<E>)>,
+    ) -> Result<Vec<EvolveResult<E::State>>> {
+        // The evolvers' generation is used for everything, so numbering
+        // continues from where restored evolvers left off.
+        let first_gen =
+            runs.first().ok_or_else(|| eyre!("no evolvers to train"))?.evolver.generation();
+        if runs.iter().any(|v| v.evolver.generation() != first_gen) {
+            return Err(eyre!("evolvers must all start at the same generation"));
+        }
+        if self.cfg.species_path.is_some() && runs.iter().any(|v| v.label.is_some()) {
+            return Err(eyre!("parallel training doesn't support species snapshots"));
+        }
+        let _digits = self.cfg.fitness_digits.map(set_fitness_digits);
+        // Bookkeeping shared by every run is the same in each of their
+        // checkpoints. A resumed run continues the species and trace files
+        // from where they were at the checkpoint.
+        let shared = resume.as_ref().and_then(|v| v.first());
+        let (species_len, trace_len) =
+            shared.map_or((None, None), |v| (v.species_len, v.trace_len));
+        // |target_best| is the fitness of the last generation compared for
+        // `Termination::TargetFitness`.
+        let (mut fitness_count, evals, mut target_best) =
+            shared.map_or((0.0, 0, None), |v| (v.fitness_count, v.evals, v.target_best));
+        let mut species_out = match &self.cfg.species_path {
+            Some(path) => {
+                let file = if resume.is_some() {
+                    open_append(path, species_len)
+                } else {
+                    File::create(path)
+                };
+                let file =
+                    file.wrap_err_with(|| format!("creating species file {}", path.display()))?;
+                Some(Self::sink(&self.cfg, TextSink::new(file)))
+            }
+            None => None,
+        };
+        // Appended to, so a run restored from a checkpoint continues the trace.
+        let mut trace_out = match &self.cfg.trace_sampling {
+            Some(path) => {
+                let file = open_append(path, trace_len)
+                    .wrap_err_with(|| format!("opening sampling trace {}", path.display()))?;
+                Some(Self::sink(&self.cfg, TextSink::new(file)))
+            }
+            None => None,
+        };
+        if let Some(resume) = resume {
+            for (run, v) in runs.iter_mut().zip(resume) {
+                run.cv_best =
+                    v.cv_best.map(|(mem, fitness)| (mem.into_member(next_member_id()), fitness));
+                run.fitness_sum = v.fitness_sum;
+                run.best = v.best;
+            }
+        }
+        let dropped_before = self.metrics.as_ref().map_or(0, |v| v.dropped());
+        // Metrics not yet written to the sink.
+        let mut held = Vec::new();
+        // Each generation is timed from the end of the previous one, so time
+        // spent on validation and reporting counts too.
+        let mut throughput = ThroughputTracker::resumed(self.cfg.throughput_window, evals);
+        let start = Instant::now();
+        let mut tick = start;
+        let mut termination = None;
+        let mut last = first_gen;
+        for i in first_gen.. {
+            termination = self.termination(i, throughput.evals(), start, target_best);
+            if let Some((every_n, f)) = &mut checkpoint && i > first_gen &&
+                    (termination.is_some() || i % *every_n == 0) {
+                self.write_metrics(&mut held)?;
+                let outs = [&mut species_out, &mut trace_out, &mut self.metrics];
+                for out in outs.into_iter().flatten() {
+                    out.flush()?;
+                }
+                let species_len = file_len(self.cfg.species_path.as_deref())?;
+                let trace_len = file_len(self.cfg.trace_sampling.as_deref())?;
+                let trainers = runs
+                    .iter()
+                    .map(|run| TrainerCheckpoint {
+                        cv_best: run
+                            .cv_best
+                            .as_ref()
+                            .map(|(mem, v)| (MemberCheckpoint::new(mem), *v)),
+                        fitness_sum: run.fitness_sum,
+                        best: run.best,
+                        fitness_count,
+                        evals: throughput.evals(),
+                        target_best,
+                        species_len,
+                        trace_len,
+                    })
+                    .collect();
+                f(runs, trainers)?;
+            }
+            if termination.is_some() {
+                break;
+            }
+            let batch = sampler.batch_id(i);
+            if let Some(out) = &mut trace_out {
+                let ids = sampler
+                    .train_ids(i)
+                    .ok_or_else(|| eyre!("sampler doesn't provide ids for its training data"))?;
+                let line = trace_line(&SampledBatch { gen: i, batch: Some(batch), ids })?;
+                out.write(Metric::Line(line))?;
+            }
+            let train = sampler.train(i);
+            let mut results = Vec::with_capacity(runs.len());
+            for run in runs.iter_mut() {
+                results.push(run.evolver.run_data_batch(&train, batch)?);
+            }
+            let now = Instant::now();
+            let time = now - tick;
+            throughput.record(time, results.iter().map(|r| r.fitness_evals).sum());
+            tick = now;
+            let rate = throughput.throughput(&self.cfg.terminations, i + 1);
             fitness_count += 1.0;
 
             if let Some(print_gen) = self.cfg.print_gen && i % print_gen == 0 {
-                 println!("Gen {i:>6}\ntrain best {:5.5}", r.nth(0).fitness);
+                match &rate {
+                    Some(v) => info!("Gen {i:>6} ({v})"),
+                    None => info!("Gen {i:>6}"),
+                }
             }
-
-            if let Some(print_valid) = self.cfg.print_valid && i % print_valid == 0 {
-                let valid_fitness = evolver.eval().multi_fitness(
-                    &r.nth(0).state,
-                    &sampler.valid(i),
-                    evolver.cfg().fitness_reduction,
-                )?;
-                println!("valid best: {valid_fitness:5.5}");
+            let report = self.metrics.is_some() && self.cfg.report_gen.is_some_and(|v| i % v == 0);
+            let gen = GenInfo { i, batch, time, report: report.then_some(fitness_count) };
+            let mut target = None;
+            for (run, mut r) in runs.iter_mut().zip(results) {
+                r.throughput = rate;
+                let reached =
+                    self.record_gen(run, r, &gen, sampler, &mut species_out, &mut held)?;
+                if let Some(v) = reached {
+                    target = Some(target.map_or(v, |t: f64| t.max(v)));
+                }
             }
+            if report {
+                fitness_count = 0.0;
+            }
+            if target.is_some() {
+                target_best = target;
+            }
+            if checkpoint.is_none() {
+                self.write_metrics(&mut held)?;
+            }
+            last = i;
+        }
 
-            if let Some(print_summary) = self.cfg.print_summary && i % print_summary == 0 {
-                println!("{}", evolver.summary(&mut r));
+        if let Some(out) = &mut species_out {
+            out.flush()?;
+        }
+        if let Some(out) = &mut trace_out {
+            out.flush()?;
+        }
+
+        // Evaluate on the test data only once training is done, so it can't
+        // affect any training decisions.
+        // Model selection uses the cross validated fitness if there is one.
+        // If validation was sampled for reporting, also evaluate the selected
+        // member on all of it once.
+        let mut results = Vec::with_capacity(runs.len());
+        for run in runs.iter_mut() {
+            let mut r = run
+                .last
+                .take()
+                .ok_or_else(|| eyre!("no generations run, evolver is already done"))?;
+            r.termination = termination;
+            r.cv_best = run.cv_best.take();
+            let evolver = &run.evolver;
+            let selected = r.cv_best.as_ref().map_or(r.nth(0), |(mem, _)| mem);
+            let test_fitness = Self::test_fitness(evolver, &selected.state, sampler, last)?;
+            let valid_fitness = match self.cfg.valid_sample {
+                Some(_) => {
+                    Some(Self::valid_fitness(evolver, &selected.state, &sampler.valid(last))?)
+                }
+                None => None,
+            };
+            r.test_fitness = test_fitness;
+            r.valid_fitness = valid_fitness;
+            let name = run.name();
+            if let Some(valid_fitness) = r.valid_fitness {
+                if self.cfg.print_valid.is_some() {
+                    info!("{name}valid best (full): {}", fmt_fitness(valid_fitness));
+                }
+                if self.metrics.is_some() {
+                    let tag = run.tag("valid_full");
+                    held.push(Metric::Scalar { tag, value: valid_fitness as f32, step: last });
+                }
             }
+            if let Some(test_fitness) = r.test_fitness {
+                if self.cfg.print_valid.is_some() {
+                    info!("{name}test best: {}", fmt_fitness(test_fitness));
+                }
+                if self.metrics.is_some() {
+                    let tag = run.tag("test_fitness");
+                    held.push(Metric::Scalar { tag, value: test_fitness as f32, step: last });
+                }
+            }
+            results.push(r);
+        }
+
+        // Wait for metrics to be written, so they're complete once training
+        // returns.
+        self.write_metrics(&mut held)?;
+        let mut dropped = 0;
+        if let Some(metrics) = &mut self.metrics {
+            metrics.flush()?;
+            dropped = metrics.dropped() - dropped_before;
+        }
+        if dropped > 0 {
+            warn!("dropped {dropped} metrics since the metric queue was full");
+        }
+        for r in &mut results {
+            r.dropped_metrics = dropped;
+        }
+        Ok(results)
+    }
+
+    // Records generation |gen| of |run|, which gave |r|: writes its species
+    // snapshot, logs it, reports its metrics to |held|, and feeds validation
+    // back to the evolver. Returns the fitness to compare for
+    // `Termination::TargetFitness`, if that's a termination.
+    fn record_gen<E: Evaluator>(
+        &mut self,
+        run: &mut Run<E>,
+        r: EvolveResult<E::State>,
+        gen: &GenInfo,
+        sampler: &impl DataSampler<E::Data>,
+        species_out: &mut Option<Box<dyn MetricSink>>,
+        held: &mut Vec<Metric>,
+    ) -> Result<Option<f64>> {
+        let GenInfo { i, batch, time, report } = *gen;
+        let name = run.name();
+        let evolver = &mut run.evolver;
+        if let Some(out) = species_out && let Some(snapshot) = &r.species_snapshot {
+            out.write(Metric::Line(species_line(snapshot)?))?;
+        }
+
+        if let Some(f) = &mut self.snapshot_fn && i % self.cfg.snapshot_gen.max(1) == 0 {
+            f(i, &r.snapshot());
+        }
+
+        let fitness = r.nth(0).fitness;
+        run.fitness_sum += fitness;
+        if run.best.is_none_or(|(best, _)| fitness > best) {
+            run.best = Some((fitness, i));
+        }
 
-            if let Some(print_samples) = self.cfg.print_samples && i % print_samples == 0 {
-                println!("{}", evolver.summary_sample(&mut r, 5));
+        if let Some(print_gen) = self.cfg.print_gen && i % print_gen == 0 {
+            info!("{name}train best {}", fmt_fitness(fitness));
+        }
+
+        if let Some(print_compact) = self.cfg.print_compact && i % print_compact == 0 &&
+                log_enabled!(Level::Info) {
+            let stats = Stats::from_result(&r).to_kv_line();
+            info!("{name}gen={i} {stats} time_ms={}", time.as_millis());
+        }
+
+        if let Some(print_valid) = self.cfg.print_valid && i % print_valid == 0 &&
+                log_enabled!(Level::Info) {
+            let (valid, sampled) = Self::report_valid(self.cfg.valid_sample, sampler, i);
+            let valid_fitness = Self::valid_fitness(evolver, &r.nth(0).state, &valid)?;
+            let tag = if sampled { " (sampled)" } else { "" };
+            info!("{name}valid best{tag}: {}", fmt_fitness(valid_fitness));
+        }
+
+        if let Some(cv_valid) = self.cfg.cv_valid && i % cv_valid == 0 {
+            let cv_fitness = Self::cv_fitness(evolver, &r, sampler, i)?;
+            if self.cfg.print_valid.is_some() {
+                info!("{name}cv valid best: {}", fmt_fitness(cv_fitness));
+            }
+            if !matches!(&run.cv_best, Some((_, best)) if *best >= cv_fitness) {
+                run.cv_best = Some((r.nth(0).clone(), cv_fitness));
             }
+        }
+
+        if let StagnationSignal::ValidationBest { every } = evolver.cfg().stagnation_signal &&
+                i % every.max(1) == 0 {
+            let valid_fitness = Self::valid_fitness(evolver, &r.nth(0).state, &sampler.valid(i))?;
+            evolver.report_external_fitness(valid_fitness);
+        }
 
-            #[cfg(feature = "tensorboard")]
-            if let Some(report_gen) = self.cfg.report_gen &&
-                    let Some(writer) = &mut self.writer && i % report_gen == 0 {
-                let valid_fitness = evolver.eval().multi_fitness(
-                    &r.nth(0).state,
-                    &sampler.valid(i),
-                    evolver.cfg().fitness_reduction,
-                )?;
-                let scalars = std::collections::HashMap::from([
-                    ("train".to_string(), (fitness_sum / fitness_count) as f32),
-                    ("valid".to_string(), valid_fitness as f32),
+        // Summaries are expensive to build, so skip them if they won't be
+        // logged.
+        if let Some(print_summary) = self.cfg.print_summary && i % print_summary == 0 &&
+                log_enabled!(Level::Info) {
+            info!("{name}{}", evolver.summary(&r));
+        }
+
+        if let Some(print_samples) = self.cfg.print_samples && i % print_samples == 0 &&
+                log_enabled!(Level::Debug) {
+            debug!("{name}{}", evolver.summary_sample(&r, 5));
+        }
+
+        if let Some(fitness_count) = report {
+            let (valid, sampled) = Self::report_valid(self.cfg.valid_sample, sampler, i);
+            let valid_fitness = Self::valid_fitness(evolver, &r.nth(0).state, &valid)?;
+            let tag = if sampled { "valid_sampled" } else { "valid" };
+            let values = HashMap::from([
+                ("train".to_string(), (run.fitness_sum / fitness_count) as f32),
+                (tag.to_string(), valid_fitness as f32),
+            ]);
+            held.push(Metric::Scalars { tag: run.tag("fitness"), values, step: i });
+            // Scalars are f32, so only batch ids below 2^24 are exact.
+            // The sampling trace records them exactly.
+            let values = HashMap::from([
+                ("len".to_string(), r.data_len as f32),
+                ("batch".to_string(), batch as f32),
+            ]);
+            held.push(Metric::Scalars { tag: run.tag("data"), values, step: i });
+            // Throughput is over every run, but reported for each, so all of
+            // a run's metrics share its label.
+            if let Some(v) = r.throughput {
+                let mut values = HashMap::from([
+                    ("gens_per_sec".to_string(), v.gens_per_sec as f32),
+                    ("evals_per_sec".to_string(), v.evals_per_sec as f32),
                 ]);
-                writer.add_scalars("fitness", &scalars, i);
+                if let Some(eta) = v.eta {
+                    let _ = values.insert("eta_secs".to_string(), eta.as_secs_f32());
+                }
+                held.push(Metric::Scalars { tag: run.tag("throughput"), values, step: i });
+            }
+            if let Some(map_elites) = &r.map_elites {
+                let values = HashMap::from([
+                    ("coverage".to_string(), map_elites.coverage() as f32),
+                    ("qd_score".to_string(), map_elites.qd_score as f32),
+                ]);
+                held.push(Metric::Scalars { tag: run.tag("map_elites"), values, step: i });
+            }
+            run.fitness_sum = 0.0;
+        }
 
-                writer.flush();
-                fitness_sum = 0.0;
-                fitness_count = 0.0;
+        let mut reached = None;
+        if self.cfg.terminations.iter().any(|v| matches!(v, Termination::TargetFitness(_))) {
+            reached = Some(match self.cfg.target_signal {
+                TargetSignal::Train => {
+                    run.evolver.archive().map_or(fitness, SharedArchive::best_fitness)
+                }
+                TargetSignal::Validation => {
+                    Self::valid_fitness(&run.evolver, &r.nth(0).state, &sampler.valid(i))?
+                }
+            });
+        }
+        run.last = Some(r);
+        Ok(reached)
+    }
+
+    // Writes out and clears |held| metrics.
+    fn write_metrics(&mut self, held: &mut Vec<Metric>) -> Result<()> {
+        if let Some(metrics) = &mut self.metrics {
+            for metric in held.drain(..) {
+                metrics.write(metric)?;
+            }
+        }
+        Ok(())
+    }
+
+    // First of the termination conditions met before running generation |i|,
+    // after |evals| fitness evaluations. |best| is compared with target
+    // fitnesses, and is None if no generations have run yet.
+    fn termination(
+        &self,
+        i: usize,
+        evals: usize,
+        start: Instant,
+        best: Option<f64>,
+    ) -> Option<Termination> {
+        let condition = self.cfg.target_condition;
+        self.cfg.terminations.iter().copied().find(|&termination| match termination {
+            Termination::FixedGenerations(gen) => i >= gen,
+            Termination::FitnessEvaluations(n) => evals >= n,
+            Termination::TargetFitness(target) => {
+                best.is_some_and(|best| target_reached(best, target, condition))
             }
-            ret = Some(r);
+            Termination::Timeout(timeout) => start.elapsed() >= timeout,
+        })
+    }
+
+    // Fitness of |s| on the test data, if there is any.
+    fn test_fitness<E: Evaluator>(
+        evolver: &Evolver<E>,
+        s: &E::State,
+        sampler: &impl DataSampler<E::Data>,
+        i: usize,
+    ) -> Result<Option<f64>> {
+        let test = sampler.test(i);
+        if test.is_empty() {
+            return Ok(None);
+        }
+        let fitness = evolver.eval().multi_fitness(s, &test, evolver.cfg().fitness_reduction)?;
+        Ok(Some(fitness))
+    }
+
+    // Fitness of the best member of |r| averaged over the validation folds.
+    fn cv_fitness<E: Evaluator>(
+        evolver: &Evolver<E>,
+        r: &EvolveResult<E::State>,
+        sampler: &impl DataSampler<E::Data>,
+        i: usize,
+    ) -> Result<f64> {
+        let folds = sampler.valid_folds(i);
+        if folds.is_empty() {
+            return Err(eyre!("no validation folds for cross validation"));
+        }
+        let mut total = 0.0;
+        for fold in &folds {
+            let reduction = evolver.cfg().fitness_reduction;
+            total += evolver.eval().multi_fitness(&r.nth(0).state, fold, reduction)?;
+        }
+        Ok(total / folds.len() as f64)
+    }
+
+    // Fitness of |s| on the given validation data.
+    fn valid_fitness<E: Evaluator>(
+        evolver: &Evolver<E>,
+        s: &E::State,
+        valid: &[E::Data],
+    ) -> Result<f64> {
+        evolver.eval().multi_fitness(s, valid, evolver.cfg().fitness_reduction)
+    }
+
+    // Validation data to report on at |i|, and whether it is a sample of at
+    // most |valid_sample| items. Samples are seeded by the generation, so
+    // they are reproducible.
+    fn report_valid<D: Data>(
+        valid_sample: Option<usize>,
+        sampler: &impl DataSampler<D>,
+        i: usize,
+    ) -> (Vec<D>, bool) {
+        let valid = sampler.valid(i);
+        match valid_sample {
+            Some(n) if n < valid.len() => {
+                let mut r = StdRng::seed_from_u64(i as u64);
+                (valid.choose_multiple(&mut r, n).cloned().collect(), true)
+            }
+            _ => (valid, false),
+        }
+    }
+}
+
+// Line of the sampling trace recording |batch|.
+#[cfg(feature = "serde")]
+fn trace_line(batch: &SampledBatch) -> Result<String> {
+    batch.to_json()
+}
+
+#[cfg(not(feature = "serde"))]
+fn trace_line(_: &SampledBatch) -> Result<String> {
+    Err(eyre!("sampling traces need the serde feature"))
+}
+
+// Line of the species file recording |snapshot|.
+#[cfg(feature = "serde")]
+fn species_line(snapshot: &SpeciesSnapshot) -> Result<String> {
+    snapshot.to_json()
+}
+
+#[cfg(not(feature = "serde"))]
+fn species_line(_: &SpeciesSnapshot) -> Result<String> {
+    Err(eyre!("species snapshots need the serde feature"))
+}
+
+// Opens |path| for appending, creating it if needed. If |len| is given, first
+// cuts it back to that length, e.g. to drop records written after a
+// checkpoint.
+fn open_append(path: &Path, len: Option<u64>) -> std::io::Result<File> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    if let Some(len) = len && len < file.metadata()?.len() {
+        file.set_len(len)?;
+    }
+    Ok(file)
+}
+
+// Length of the file at |path|, if there is a path.
+fn file_len(path: Option<&Path>) -> Result<Option<u64>> {
+    let len = |path: &Path| {
+        fs::metadata(path).map(|v| v.len()).wrap_err_with(|| format!("reading {}", path.display()))
+    };
+    path.map(len).transpose()
+}
+
+// Whether |best| reaches |target|, allowing for the same error as
+// stagnation does with |condition|.
+fn target_reached(best: f64, target: f64, condition: StagnationCondition) -> bool {
+    best >= target
+        || match condition {
+            StagnationCondition::Default => relative_eq!(best, target),
+            StagnationCondition::Epsilon(ep) => abs_diff_eq!(best, target, epsilon = ep),
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex, Once};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    #[cfg(feature = "lgp")]
+    use crate::evaluators::lgp::builder::lgp_fitness_evolver;
+    #[cfg(feature = "lgp")]
+    use crate::evaluators::lgp::cfg::LgpEvaluatorCfg;
+    #[cfg(feature = "lgp")]
+    use crate::evaluators::lgp::eval::{optimize_calls, LgpState};
+    use crate::evolve::cfg::{Duplicates, EvolveCfg, Species};
+    #[cfg(feature = "serde")]
+    use crate::train::sampler::{BatchDataSampler, ReplaySampler, SamplingTrace};
+    use crate::train::sampler::{EmptyDataSampler, KFoldSampler};
+    use crate::util::bench_utils::CountEvaluator;
+    use crate::util::test_utils::{tagged_scalars, MockEvaluator, MockSink};
+
+    // Fitness is the data point, so it tells which split was used.
+    fn split() -> MockEvaluator<usize, f64> {
+        MockEvaluator::default().set_fitness(|_, data| Ok(*data))
+    }
+
+    struct SplitSampler {
+        test: Vec<f64>,
+    }
+
+    impl DataSampler<f64> for SplitSampler {
+        fn train(&self, _: usize) -> Vec<f64> {
+            vec![1.0]
+        }
+
+        fn valid(&self, _: usize) -> Vec<f64> {
+            vec![2.0]
+        }
+
+        fn test(&self, _: usize) -> Vec<f64> {
+            self.test.clone()
+        }
+    }
+
+    fn train(test: Vec<f64>) -> Result<EvolveResult<usize>> {
+        let evolver = Evolver::new(split(), EvolveCfg::new(10), || 0);
+        let mut trainer =
+            Trainer::new(TrainerCfg::new("test").set_termination(Termination::FixedGenerations(3)));
+        trainer.train(evolver, &SplitSampler { test })
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn test_fitness_uses_test_split() -> Result<()> {
+        let r = train(vec![3.0, 5.0])?;
+        assert_eq!(r.nth(0).fitness, 1.0);
+        assert_eq!(r.test_fitness, Some(4.0));
+        assert_eq!(train(vec![])?.test_fitness, None);
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn cv_fitness_averages_folds() -> Result<()> {
+        // Folds are [1, 8, 64], [2, 16] and [4, 32].
+        let data = vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0];
+        let sampler = KFoldSampler::new(data, 3).set_test(vec![3.0, 5.0]);
+        let evolver = Evolver::new(split(), EvolveCfg::new(10), || 0);
+        let cfg = TrainerCfg::new("test")
+            .set_termination(Termination::FixedGenerations(4))
+            .set_cv_valid(1);
+        let r = Trainer::new(cfg).train(evolver, &sampler)?;
+        let (_, cv_fitness) = r.cv_best.as_ref().unwrap();
+        let expected = (73.0 / 3.0 + 18.0 / 2.0 + 36.0 / 2.0) / 3.0;
+        assert_eq!(*cv_fitness, expected);
+        assert_eq!(r.test_fitness, Some(4.0));
+
+        // Without cross validation there is no score.
+        let evolver = Evolver::new(split(), EvolveCfg::new(10), || 0);
+        let cfg = TrainerCfg::new("test").set_termination(Termination::FixedGenerations(2));
+        assert!(Trainer::new(cfg).train(evolver, &sampler)?.cv_best.is_none());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn species_snapshots_written() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("memega-species-{}.jsonl", std::process::id()));
+        let cfg =
+            EvolveCfg::new(6).set_species(Species::TargetNumber(2)).set_species_snapshots(true);
+        let evolver = Evolver::new(CountEvaluator, cfg, || 0);
+        let mut trainer = Trainer::new(
+            TrainerCfg::new("test")
+                .set_termination(Termination::FixedGenerations(4))
+                .set_species_path(&path),
+        );
+        let r = trainer.train(evolver, &EmptyDataSampler {})?;
+        let lines = fs::read_to_string(&path)?;
+        fs::remove_file(&path)?;
+        let lines = lines.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        for (i, line) in lines.iter().enumerate() {
+            assert!(line.starts_with(&format!("{{\"gen\":{i},")), "{line}");
+        }
+        assert_eq!(lines[3], r.species_snapshot.unwrap().to_json()?);
+        Ok(())
+    }
+
+    // Training fitness each generation, tracing sampling to |trace| if set.
+    #[cfg(feature = "serde")]
+    fn train_fitnesses(
+        sampler: &impl DataSampler<f64>,
+        trace: Option<&Path>,
+    ) -> Result<Vec<(usize, f32)>> {
+        let mut cfg = TrainerCfg::new("test")
+            .set_termination(Termination::FixedGenerations(6))
+            .set_report_gen(1)
+            .set_metric_queue(None);
+        if let Some(trace) = trace {
+            cfg = cfg.set_trace_sampling(trace);
+        }
+        let sink = MockSink::new();
+        let metrics = sink.written();
+        let mut trainer = Trainer::new(cfg).set_metric_sink(sink);
+        let _ = trainer.train(Evolver::new(split(), EvolveCfg::new(4), || 0), sampler)?;
+        let fitnesses = tagged_scalars(&metrics.lock().unwrap(), "fitness");
+        Ok(fitnesses.into_iter().map(|(step, v)| (step, v["train"])).collect())
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn sampling_trace_replays() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("memega-trace-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let data = (0..20).map(|v| f64::from(v * v)).collect::<Vec<_>>();
+        let sampler = BatchDataSampler::new(KFoldSampler::new(data.clone(), 2), 3);
+        let traced = train_fitnesses(&sampler, Some(&path))?;
+        let trace = SamplingTrace::load(&path)?;
+        fs::remove_file(&path)?;
+        assert_eq!(traced.len(), 6);
+        assert_eq!(trace.batches.len(), 6);
+        for (gen, v) in trace.batches.iter().enumerate() {
+            assert_eq!(v.gen, gen);
+            // Each generation gets a new batch.
+            assert_eq!(v.batch, Some(gen as u64));
+            assert_eq!(v.ids.len(), 3);
+            // Training data comes from the folds not held out.
+            assert!(v.ids.iter().all(|&i| i < data.len() && i % 2 != gen % 2), "{v:?}");
+        }
+
+        // Replaying the trace against the full data gives the same batches,
+        // so the same training fitness.
+        let replay = ReplaySampler::new(KFoldSampler::new(data.clone(), 2), data, trace);
+        assert_eq!(train_fitnesses(&replay, None)?, traced);
+
+        // Samplers without ids can't be traced.
+        let sampler = SplitSampler { test: vec![] };
+        assert!(train_fitnesses(&sampler, Some(&path)).is_err());
+        let _ = fs::remove_file(&path);
+        Ok(())
+    }
+
+    // Fitness is the given constant.
+    fn constant(fitness: f64) -> MockEvaluator {
+        MockEvaluator::new().set_mutate(|s, _, _| *s += 1).set_fitness(move |_, ()| Ok(fitness))
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn shared_archive_stops_all_workers() -> Result<()> {
+        const WORKERS: usize = 4;
+        let archive = SharedArchive::new(5);
+        thread::scope(|s| {
+            let handles = (0..WORKERS)
+                .map(|w| {
+                    let archive = archive.clone();
+                    s.spawn(move || {
+                        // Only the last worker can reach the target. The
+                        // others would run forever if they didn't see it.
+                        // Workers start from different states, since the
+                        // archive dedups by state.
+                        let fitness = if w == WORKERS - 1 { 1.0 } else { 0.5 };
+                        let start = w * 1_000_000;
+                        let evolver =
+                            Evolver::new(constant(fitness), EvolveCfg::new(4), move || start)
+                                .set_archive(archive);
+                        let mut trainer = Trainer::new(
+                            TrainerCfg::new("worker")
+                                .set_termination(Termination::FixedGenerations(usize::MAX))
+                                .set_target_fitness(1.0),
+                        );
+                        trainer.train(evolver, &EmptyDataSampler {}).map(|r| r.nth(0).fitness)
+                    })
+                })
+                .collect::<Vec<_>>();
+            let fitnesses =
+                handles.into_iter().map(|h| h.join().unwrap()).collect::<Result<Vec<_>>>()?;
+            assert_eq!(fitnesses, [0.5, 0.5, 0.5, 1.0]);
+            Ok::<_, eyre::Report>(())
+        })?;
+        assert_eq!(archive.best_fitness(), 1.0);
+        assert_eq!(archive.best().map(|v| v.fitness), Some(1.0));
+        Ok(())
+    }
+
+    // Records which generations training data was requested for.
+    #[derive(Default)]
+    struct RecordingSampler {
+        gens: Mutex<Vec<usize>>,
+    }
+
+    impl DataSampler<()> for RecordingSampler {
+        fn train(&self, gen: usize) -> Vec<()> {
+            self.gens.lock().unwrap().push(gen);
+            vec![()]
+        }
+
+        fn valid(&self, _: usize) -> Vec<()> {
+            vec![()]
+        }
+
+        fn test(&self, _: usize) -> Vec<()> {
+            vec![]
+        }
+    }
+
+    #[test]
+    fn resume_continues_generations() -> Result<()> {
+        let train = |evolver, gens| -> Result<(Vec<usize>, EvolveResult<usize>)> {
+            let sampler = RecordingSampler::default();
+            let mut trainer = Trainer::new(
+                TrainerCfg::new("test").set_termination(Termination::FixedGenerations(gens)),
+            );
+            let r = trainer.train(evolver, &sampler)?;
+            Ok((sampler.gens.into_inner().unwrap(), r))
+        };
+        let cfg =
+            EvolveCfg::new(4).set_species(Species::TargetNumber(2)).set_species_snapshots(true);
+        let (gens, _) = train(Evolver::new(CountEvaluator, cfg.clone(), || 0), 3)?;
+        assert_eq!(gens, [0, 1, 2]);
+
+        // Restoring from a checkpoint after generation 3 only runs the rest.
+        let mut evolver = Evolver::new(CountEvaluator, cfg.clone(), || 0);
+        evolver.set_generation(3);
+        let (gens, r) = train(evolver, 5)?;
+        assert_eq!(gens, [3, 4]);
+        assert_eq!(r.unevaluated.gen_idx, 4);
+        assert_eq!(r.species_snapshot.map(|v| v.gen), Some(4));
+
+        let mut evolver = Evolver::new(CountEvaluator, cfg, || 0);
+        evolver.set_generation(5);
+        assert!(train(evolver, 5).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn terminations() -> Result<()> {
+        // Training fitness is 1 and validation fitness 2. Returns which
+        // termination fired, and the last generation run.
+        let train = |cfg: TrainerCfg| -> Result<(Option<Termination>, usize)> {
+            let evolver = Evolver::new(split(), EvolveCfg::new(4), || 0);
+            let r = Trainer::new(cfg).train(evolver, &SplitSampler { test: vec![] })?;
+            Ok((r.termination, r.unevaluated.gen_idx))
+        };
+        let fixed = Termination::FixedGenerations(3);
+        let cfg = TrainerCfg::new("test").set_termination(fixed);
+        assert_eq!(train(cfg.clone())?, (Some(fixed), 2));
+
+        let target = Termination::TargetFitness;
+        assert_eq!(train(cfg.clone().set_target_fitness(1.0))?, (Some(target(1.0)), 0));
+        assert_eq!(train(cfg.clone().set_target_fitness(1.5))?, (Some(fixed), 2));
+        let valid = cfg.clone().set_target_signal(TargetSignal::Validation);
+        assert_eq!(train(valid.set_target_fitness(1.5))?, (Some(target(1.5)), 0));
+
+        // Rounding error is allowed by default, and more with an epsilon.
+        let noisy = 1.0 + f64::EPSILON;
+        assert_eq!(train(cfg.clone().set_target_fitness(noisy))?, (Some(target(noisy)), 0));
+        assert_eq!(train(cfg.clone().set_target_fitness(1.05))?, (Some(fixed), 2));
+        let epsilon = cfg.clone().set_target_condition(StagnationCondition::Epsilon(0.1));
+        assert_eq!(train(epsilon.set_target_fitness(1.05))?, (Some(target(1.05)), 0));
+
+        // The first condition met wins.
+        let one = Termination::FixedGenerations(1);
+        let cfg = TrainerCfg::new("test").set_termination(target(1.0)).add_termination(one);
+        assert_eq!(train(cfg)?, (Some(target(1.0)), 0));
+        let cfg = TrainerCfg::new("test").set_termination(one).add_termination(target(1.0));
+        assert_eq!(train(cfg)?, (Some(one), 0));
+
+        let timeout = Termination::Timeout(Duration::from_millis(20));
+        let cfg = TrainerCfg::new("test")
+            .set_termination(Termination::FixedGenerations(usize::MAX))
+            .add_termination(timeout);
+        assert_eq!(train(cfg)?.0, Some(timeout));
+        Ok(())
+    }
+
+    #[test]
+    fn fitness_evaluations_budget() -> Result<()> {
+        // Every member is evaluated on the single data point each generation,
+        // so the budget is used up after 10 generations.
+        let sampler = RecordingSampler::default();
+        let cfg = TrainerCfg::new("test")
+            .set_termination(Termination::FitnessEvaluations(95))
+            .set_throughput_window(3);
+        // Duplicates are kept so the population stays at 10, and each is
+        // evaluated.
+        let evolve_cfg = EvolveCfg::new(10)
+            .set_duplicates(Duplicates::AllowDuplicates)
+            .set_share_duplicate_fitness(false);
+        let evolver = Evolver::new(CountEvaluator, evolve_cfg, || 0);
+        let r = Trainer::new(cfg).train(evolver, &sampler)?;
+        assert_eq!(sampler.gens.into_inner().unwrap(), (0..10).collect::<Vec<_>>());
+        assert_eq!(r.fitness_evals, 10);
+        assert_eq!(r.throughput.unwrap().eta, Some(Duration::ZERO));
+        Ok(())
+    }
+
+    thread_local! {
+        // Records logged on this thread at or above the capture level.
+        static CAPTURED: RefCell<Vec<(Level, String)>> = const { RefCell::new(Vec::new()) };
+        static CAPTURE_LEVEL: Cell<LevelFilter> = const { Cell::new(LevelFilter::Off) };
+    }
+
+    // Captures records per thread, so tests running in parallel don't see
+    // each other's records.
+    struct CaptureLogger;
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            metadata.level() <= CAPTURE_LEVEL.with(Cell::get)
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            if self.enabled(record.metadata()) {
+                let v = (record.level(), record.args().to_string());
+                CAPTURED.with(|c| c.borrow_mut().push(v));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture<T>(level: LevelFilter, f: impl FnOnce() -> T) -> (T, Vec<(Level, String)>) {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&CaptureLogger).unwrap();
+            log::set_max_level(LevelFilter::Trace);
+        });
+        CAPTURE_LEVEL.with(|v| v.set(level));
+        let ret = f();
+        CAPTURE_LEVEL.with(|v| v.set(LevelFilter::Off));
+        (ret, CAPTURED.with(RefCell::take))
+    }
+
+    #[cfg(feature = "lgp")]
+    #[test]
+    fn log_levels() -> Result<()> {
+        // Fitness doesn't need optimised code, so only formatting samples
+        // optimises programs.
+        let train = || {
+            let evolver = lgp_fitness_evolver(
+                LgpEvaluatorCfg::new().set_num_reg(4),
+                EvolveCfg::new(10),
+                |s: &LgpState, (): &()| Ok(s.ops_unopt().len() as f64 + 1.0),
+            );
+            let cfg = TrainerCfg::new("test")
+                .set_termination(Termination::FixedGenerations(2))
+                .set_print_gen(1)
+                .set_print_summary(1)
+                .set_print_samples(1)
+                .set_print_valid(1);
+            let calls = optimize_calls();
+            let _ = Trainer::new(cfg).train(evolver, &EmptyDataSampler {})?;
+            Ok::<_, eyre::Report>(optimize_calls() - calls)
+        };
+
+        let (calls, records) = capture(LevelFilter::Info, train);
+        assert_eq!(calls?, 0);
+        let gens = records.iter().filter(|(_, v)| v.starts_with("Gen ")).count();
+        assert_eq!(gens, 2);
+        assert!(records.iter().any(|(_, v)| v.starts_with("valid best")));
+        assert!(records.iter().all(|(level, _)| *level == Level::Info), "{records:?}");
+
+        let (calls, records) = capture(LevelFilter::Debug, train);
+        assert!(calls? > 0);
+        assert_eq!(records.iter().filter(|(level, _)| *level == Level::Debug).count(), 2);
+
+        let (calls, records) = capture(LevelFilter::Warn, train);
+        assert_eq!(calls?, 0);
+        assert_eq!(records, []);
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_fn() -> Result<()> {
+        let snapshots = Arc::new(Mutex::new(Vec::new()));
+        let out = Arc::clone(&snapshots);
+        let cfg = TrainerCfg::new("test")
+            .set_termination(Termination::FixedGenerations(5))
+            .set_snapshot_gen(2);
+        let mut trainer =
+            Trainer::new(cfg).set_snapshot_fn(move |gen, snapshot: &PopulationSnapshot<'_>| {
+                out.lock().unwrap().push((gen, snapshot.members.len(), snapshot.fitnesses()));
+            });
+        let evolve_cfg = EvolveCfg::new(10).set_duplicates(Duplicates::AllowDuplicates);
+        let evolver = Evolver::new(CountEvaluator, evolve_cfg.clone(), || 0);
+        let r = trainer.train(evolver, &EmptyDataSampler {})?;
+        let snapshots = snapshots.lock().unwrap();
+        assert_eq!(snapshots.iter().map(|v| v.0).collect::<Vec<_>>(), [0, 2, 4]);
+        assert!(snapshots.iter().all(|v| v.1 == evolve_cfg.pop_size));
+        assert_eq!(snapshots[2].2, r.snapshot().fitnesses());
+        Ok(())
+    }
+
+    #[test]
+    fn train_parallel_snapshot_fn() -> Result<()> {
+        let gens = Arc::new(Mutex::new(Vec::new()));
+        let out = Arc::clone(&gens);
+        let cfg = TrainerCfg::new("test").set_termination(Termination::FixedGenerations(2));
+        let mut trainer =
+            Trainer::new(cfg).set_snapshot_fn(move |gen, snapshot: &PopulationSnapshot<'_>| {
+                out.lock().unwrap().push((gen, snapshot.members.len()));
+            });
+        let evolver = |pop_size| {
+            let cfg = EvolveCfg::new(pop_size).set_duplicates(Duplicates::AllowDuplicates);
+            Evolver::new(CountEvaluator, cfg, || 0)
+        };
+        let evolvers = vec![("a".to_owned(), evolver(10)), ("b".to_owned(), evolver(20))];
+        let _ = trainer.train_parallel(evolvers, &EmptyDataSampler {})?;
+        assert_eq!(*gens.lock().unwrap(), [(0, 10), (0, 20), (1, 10), (1, 20)]);
+        Ok(())
+    }
+
+    #[test]
+    fn fitness_digits_while_training() -> Result<()> {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let out = Arc::clone(&seen);
+        let cfg = TrainerCfg::new("test")
+            .set_termination(Termination::FixedGenerations(2))
+            .set_fitness_digits(3);
+        let mut trainer =
+            Trainer::new(cfg).set_snapshot_fn(move |_, _: &PopulationSnapshot<'_>| {
+                out.lock().unwrap().push(fmt_fitness(1.0 / 3.0));
+            });
+        let evolver = Evolver::new(CountEvaluator, EvolveCfg::new(4), || 0);
+        let _ = trainer.train(evolver, &EmptyDataSampler {})?;
+        assert_eq!(*seen.lock().unwrap(), ["0.333", "0.333"]);
+        // Restored once training finishes.
+        assert_eq!(fmt_fitness(1.0 / 3.0), "0.333333");
+        Ok(())
+    }
+
+    #[test]
+    fn print_compact() -> Result<()> {
+        let cfg = TrainerCfg::new("test")
+            .set_termination(Termination::FixedGenerations(4))
+            .set_print_compact(Some(2));
+        let evolve_cfg = EvolveCfg::new(10).set_duplicates(Duplicates::AllowDuplicates);
+        let evolver = Evolver::new(CountEvaluator, evolve_cfg.clone(), || 0);
+        let train = || Trainer::new(cfg).train(evolver, &EmptyDataSampler {});
+        let (r, records) = capture(LevelFilter::Info, train);
+        let _ = r?;
+        let lines = records.iter().map(|(_, v)| v).filter(|v| v.starts_with("gen="));
+        let lines = lines.collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "{records:?}");
+        for (line, gen) in lines.into_iter().zip([0, 2]) {
+            assert!(line.starts_with(&format!("gen={gen} best=")), "{line}");
+            assert!(!line.contains('\n'), "{line}");
+            assert_eq!(Stats::from_kv_line(line)?.pop_size, evolve_cfg.pop_size);
+            let (_, time) = line.rsplit_once(" time_ms=").unwrap();
+            let _: u128 = time.parse()?;
+        }
+        Ok(())
+    }
+
+    // Counts fitness evaluations on validation data, which is 1000 and up.
+    fn valid_count(valid_calls: Arc<AtomicUsize>) -> MockEvaluator<usize, f64> {
+        MockEvaluator::default().set_fitness(move |_, data| {
+            if *data >= 1000.0 {
+                let _ = valid_calls.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(1.0)
+        })
+    }
+
+    struct ValidSampler;
+
+    impl DataSampler<f64> for ValidSampler {
+        fn train(&self, _: usize) -> Vec<f64> {
+            vec![1.0]
+        }
+
+        fn valid(&self, _: usize) -> Vec<f64> {
+            (1000..1100).map(f64::from).collect()
+        }
+
+        fn test(&self, _: usize) -> Vec<f64> {
+            vec![]
+        }
+    }
+
+    #[test]
+    fn valid_sample() -> Result<()> {
+        let full = ValidSampler.valid(0);
+        let sample = |i| Trainer::report_valid(Some(10), &ValidSampler, i);
+        let (valid, sampled) = sample(3);
+        assert!(sampled);
+        assert_eq!(valid.len(), 10);
+        assert!(valid.iter().all(|v| full.contains(v)));
+        assert_eq!(sample(3), (valid.clone(), true));
+        assert!((0..10).any(|i| sample(i).0 != valid));
+        assert_eq!(Trainer::report_valid(Some(100), &ValidSampler, 3), (full.clone(), false));
+        assert_eq!(Trainer::report_valid(None, &ValidSampler, 3), (full, false));
+
+        // Each generation reports on a sample, then the full set is used once.
+        let valid_calls = Arc::new(AtomicUsize::new(0));
+        let (r, records) = capture(LevelFilter::Info, || {
+            let eval = valid_count(Arc::clone(&valid_calls));
+            let cfg = TrainerCfg::new("test")
+                .set_termination(Termination::FixedGenerations(5))
+                .set_print_valid(1)
+                .set_valid_sample(Some(10));
+            Trainer::new(cfg).train(Evolver::new(eval, EvolveCfg::new(10), || 0), &ValidSampler)
+        });
+        assert_eq!(r?.valid_fitness, Some(1.0));
+        assert_eq!(valid_calls.load(Ordering::Relaxed), 5 * 10 + 100);
+        let count = |prefix| records.iter().filter(|(_, v)| v.starts_with(prefix)).count();
+        assert_eq!((count("valid best (sampled)"), count("valid best (full)")), (5, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn slow_metric_sink() -> Result<()> {
+        const GENS: usize = 20;
+        let delay = Duration::from_millis(20);
+        let cfg = TrainerCfg::new("test")
+            .set_termination(Termination::FixedGenerations(GENS))
+            .set_report_gen(1)
+            .set_metric_queue(Some(2));
+        let time = |sink: MockSink| -> Result<_> {
+            let metrics = sink.written();
+            let mut trainer = Trainer::new(cfg.clone()).set_metric_sink(sink);
+            let start = Instant::now();
+            let evolver = Evolver::new(CountEvaluator, EvolveCfg::new(10), || 0);
+            let r = trainer.train(evolver, &EmptyDataSampler {})?;
+            let steps = tagged_scalars(&metrics.lock().unwrap(), "fitness");
+            let steps = steps.into_iter().map(|v| v.0).collect::<Vec<_>>();
+            Ok((start.elapsed(), r.dropped_metrics, steps))
+        };
+        let (base, _, _) = time(MockSink::new())?;
+
+        // Writing synchronously would add |delay| to every generation. Only
+        // the final flush waits, for at most the queue and one write in
+        // progress.
+        let (slow, dropped, steps) = time(MockSink::new().set_delay(delay))?;
+        assert!(slow < base + delay * 5, "base {base:?}, slow {slow:?}");
+        assert!(dropped > 0);
+        // Fitness reported for dropped generations is missing, but what was
+        // written is in order.
+        assert!(steps.windows(2).all(|v| v[0] < v[1]), "{steps:?}");
+        assert!(steps.iter().all(|&step| step < GENS), "{steps:?}");
+        Ok(())
+    }
+
+    // Training data is the generation, with a different batch id each time.
+    struct GenSampler;
+
+    impl DataSampler<f64> for GenSampler {
+        fn train(&self, gen: usize) -> Vec<f64> {
+            vec![gen as f64]
+        }
+
+        fn valid(&self, _: usize) -> Vec<f64> {
+            vec![2.0]
+        }
+
+        fn test(&self, _: usize) -> Vec<f64> {
+            vec![7.0]
+        }
+
+        fn batch_id(&self, gen: usize) -> u64 {
+            gen as u64 * 3 + 1
+        }
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn train_parallel_same_batches() -> Result<()> {
+        const GENS: usize = 5;
+        let cfg = TrainerCfg::new("test")
+            .set_termination(Termination::FixedGenerations(GENS))
+            .set_report_gen(1)
+            .set_metric_queue(None);
+        let sink = MockSink::new();
+        let metrics = sink.written();
+        let mut trainer = Trainer::new(cfg).set_metric_sink(sink);
+        let evolver =
+            |seed| Evolver::new(split(), EvolveCfg::new(10).set_fitness_seed(Some(seed)), || 0);
+        let evolvers = vec![("a".to_owned(), evolver(1)), ("b".to_owned(), evolver(2))];
+        let r = trainer.train_parallel(evolvers, &GenSampler)?;
+
+        let metrics = metrics.lock().unwrap();
+        let records = |tag: &str| tagged_scalars(&metrics, tag);
+        for kind in ["fitness", "data"] {
+            let (a, b) = (records(&format!("a/{kind}")), records(&format!("b/{kind}")));
+            assert_eq!(a.iter().map(|v| v.0).collect::<Vec<_>>(), (0..GENS).collect::<Vec<_>>());
+            assert_eq!(a, b, "{kind}");
+        }
+        let batches = records("a/data").iter().map(|v| v.1["batch"]).collect::<Vec<_>>();
+        assert_eq!(batches, [1.0, 4.0, 7.0, 10.0, 13.0]);
+        let train = records("a/fitness").iter().map(|v| v.1["train"]).collect::<Vec<_>>();
+        assert_eq!(train, [0.0, 1.0, 2.0, 3.0, 4.0]);
+        let finals = metrics
+            .iter()
+            .filter_map(|m| match m {
+                Metric::Scalar { tag, value, .. } => Some((tag.as_str(), *value)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(finals, [("a/test_fitness", 7.0), ("b/test_fitness", 7.0)]);
+        assert!(metrics.iter().all(|m| match m {
+            Metric::Scalars { tag, .. } | Metric::Scalar { tag, .. } => {
+                tag.starts_with("a/") || tag.starts_with("b/")
+            }
+            Metric::Line(_) => false,
+        }));
+
+        for label in ["a", "b"] {
+            let result = r.get(label).unwrap();
+            assert_eq!(result.data_fingerprint, Some(13));
+            assert_eq!(result.termination, Some(Termination::FixedGenerations(GENS)));
+            assert_eq!(result.test_fitness, Some(7.0));
+        }
+        assert!(r.get("c").is_none());
+        let rows = r.report.rows.iter().map(|v| (v.label.as_str(), v.best, v.best_gen));
+        assert_eq!(rows.collect::<Vec<_>>(), [("a", 4.0, 4), ("b", 4.0, 4)]);
+        Ok(())
+    }
+
+    #[test]
+    fn train_parallel_rejects_bad_labels() {
+        let evolver = || Evolver::new(CountEvaluator, EvolveCfg::new(4), || 0);
+        let mut trainer =
+            Trainer::new(TrainerCfg::new("test").set_termination(Termination::FixedGenerations(2)));
+        let evolvers = vec![("a".to_owned(), evolver()), ("a".to_owned(), evolver())];
+        assert!(trainer.train_parallel(evolvers, &EmptyDataSampler {}).is_err());
+        let none = Vec::<(String, Evolver<CountEvaluator>)>::new();
+        assert!(trainer.train_parallel(none, &EmptyDataSampler {}).is_err());
+        for label in ["", "a/b"] {
+            let evolvers = vec![(label.to_owned(), evolver())];
+            assert!(trainer.train_parallel(evolvers, &EmptyDataSampler {}).is_err(), "{label}");
         }
-        Ok(ret.unwrap())
+        let evolvers = vec![("a".to_owned(), evolver()), ("b".to_owned(), evolver())];
+        assert!(trainer.train_parallel(evolvers, &EmptyDataSampler {}).is_ok());
     }
 }
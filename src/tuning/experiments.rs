@@ -0,0 +1,249 @@
+use std::fmt;
+
+use eyre::{eyre, Result};
+
+use crate::eval::Evaluator;
+use crate::evolve::cfg::EvolveCfg;
+use crate::evolve::evolver::CreateEvolverFn;
+use crate::train::sampler::DataSampler;
+use crate::tuning::search::{run_cfg, MetricFn, MetricStats, SearchBudget};
+use crate::util::fmt::fmt_fitness;
+use crate::util::par::map_vec;
+
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd)]
+pub enum Winner {
+    A,   // Config A has the higher mean metric.
+    B,   // Config B has the higher mean metric.
+    Tie, // Both configs have the same mean metric.
+}
+
+/// Result of comparing two configs over repeated runs. Higher metric values
+/// are better.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct ComparisonReport {
+    pub metrics_a: Vec<f64>,
+    pub metrics_b: Vec<f64>,
+    pub a: MetricStats,
+    pub b: MetricStats,
+    /// Mann-Whitney U statistic, the smaller of `U_A` and `U_B`.
+    pub u: f64,
+    /// Two-sided p-value for the two configs giving the same distribution of
+    /// the metric.
+    pub p_value: f64,
+    pub winner: Winner,
+}
+
+impl ComparisonReport {
+    pub fn from_metrics(metrics_a: Vec<f64>, metrics_b: Vec<f64>) -> Self {
+        let a = MetricStats::from_values(&metrics_a);
+        let b = MetricStats::from_values(&metrics_b);
+        let (u_a, u_b) = mann_whitney_u(&metrics_a, &metrics_b);
+        let p_value = mann_whitney_p(u_a, &metrics_a, &metrics_b);
+        let winner = match a.mean.total_cmp(&b.mean) {
+            std::cmp::Ordering::Greater => Winner::A,
+            std::cmp::Ordering::Less => Winner::B,
+            std::cmp::Ordering::Equal => Winner::Tie,
+        };
+        Self { metrics_a, metrics_b, a, b, u: u_a.min(u_b), p_value, winner }
+    }
+
+    /// Whether the difference between the configs is significant at the given
+    /// level, e.g. 0.05.
+    #[must_use]
+    pub fn significant(&self, alpha: f64) -> bool {
+        self.p_value < alpha
+    }
+}
+
+impl fmt::Display for ComparisonReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, s) in [("a", &self.a), ("b", &self.b)] {
+            writeln!(
+                f,
+                "{name}: mean {} +- {}, median {} (min {}, max {})",
+                fmt_fitness(s.mean),
+                fmt_fitness(s.std),
+                fmt_fitness(s.median),
+                fmt_fitness(s.min),
+                fmt_fitness(s.max)
+            )?;
+        }
+        write!(f, "U: {}, p: {:.5}, winner: {:?}", self.u, self.p_value, self.winner)
+    }
+}
+
+// Ranks of |v|, starting from 1. Tied values get the average of their ranks.
+fn ranks(v: &[f64]) -> Vec<f64> {
+    let mut idxs = (0..v.len()).collect::<Vec<_>>();
+    idxs.sort_by(|&a, &b| v[a].total_cmp(&v[b]));
+    let mut ranks = vec![0.0; v.len()];
+    let mut st = 0;
+    while st < idxs.len() {
+        let mut en = st + 1;
+        while en < idxs.len() && v[idxs[en]].total_cmp(&v[idxs[st]]).is_eq() {
+            en += 1;
+        }
+        // Ranks st+1..=en averaged.
+        let rank = (st + en + 1) as f64 / 2.0;
+        for &idx in &idxs[st..en] {
+            ranks[idx] = rank;
+        }
+        st = en;
+    }
+    ranks
+}
+
+/// Mann-Whitney U statistics `(U_A, U_B)` for two samples. `U_A` counts the
+/// number of pairs where the value from |a| beats the value from |b|, with
+/// ties counting as half.
+#[must_use]
+pub fn mann_whitney_u(a: &[f64], b: &[f64]) -> (f64, f64) {
+    let combined = a.iter().chain(b).copied().collect::<Vec<_>>();
+    let r = ranks(&combined);
+    let n_a = a.len() as f64;
+    let rank_sum_a = r[..a.len()].iter().sum::<f64>();
+    let u_a = rank_sum_a - n_a * (n_a + 1.0) / 2.0;
+    (u_a, n_a * b.len() as f64 - u_a)
+}
+
+// Two-sided p-value for U using the normal approximation, with tie and
+// continuity corrections. Reasonable for samples of around 8 or more each.
+fn mann_whitney_p(u_a: f64, a: &[f64], b: &[f64]) -> f64 {
+    let (n_a, n_b) = (a.len() as f64, b.len() as f64);
+    let n = n_a + n_b;
+    let mut combined = a.iter().chain(b).copied().collect::<Vec<_>>();
+    combined.sort_by(f64::total_cmp);
+    let mut tie_sum = 0.0;
+    let mut st = 0;
+    while st < combined.len() {
+        let en =
+            st + combined[st..].iter().take_while(|v| v.total_cmp(&combined[st]).is_eq()).count();
+        let t = (en - st) as f64;
+        tie_sum += t * t * t - t;
+        st = en;
+    }
+    let var = n_a * n_b / 12.0 * ((n + 1.0) - tie_sum / (n * (n - 1.0)));
+    if var <= 0.0 || !var.is_finite() {
+        return 1.0;
+    }
+    let mean = n_a * n_b / 2.0;
+    let z = ((u_a - mean).abs() - 0.5).max(0.0) / var.sqrt();
+    erfc(z / std::f64::consts::SQRT_2).min(1.0)
+}
+
+// Complementary error function, with fractional error below 1.2e-7.
+// From Numerical Recipes in C, 2nd ed., section 6.2.
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -z * z - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let v = t * poly.exp();
+    if x >= 0.0 {
+        v
+    } else {
+        2.0 - v
+    }
+}
+
+/// Runs each config `budget.repeats` times for `budget.generations`
+/// generations and compares the resulting metric values.
+pub fn compare_cfgs<E: Evaluator>(
+    create_fn: &impl CreateEvolverFn<E>,
+    cfg_a: &EvolveCfg,
+    cfg_b: &EvolveCfg,
+    sampler: &(impl DataSampler<E::Data> + Sync),
+    budget: SearchBudget,
+    metric: &impl MetricFn,
+) -> Result<ComparisonReport> {
+    if budget.generations == 0 || budget.repeats == 0 {
+        return Err(eyre!("comparison budget must have at least one generation and repeat"));
+    }
+    let runs = (0..budget.repeats).flat_map(|_| [cfg_a, cfg_b]).collect::<Vec<_>>();
+    let metrics = map_vec(runs, budget.par, |cfg| -> Result<f64> {
+        Ok(metric(&run_cfg(cfg, create_fn, sampler, budget.generations)?))
+    })
+    .into_iter()
+    .collect::<Result<Vec<_>>>()?;
+    let metrics_a = metrics.iter().step_by(2).copied().collect();
+    let metrics_b = metrics.iter().skip(1).step_by(2).copied().collect();
+    Ok(ComparisonReport::from_metrics(metrics_a, metrics_b))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::evolve::cfg::Duplicates;
+    use crate::evolve::evolver::Evolver;
+    use crate::evolve::result::Stats;
+    use crate::train::sampler::EmptyDataSampler;
+    use crate::util::bench_utils::CountEvaluator;
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn u_statistic_textbook() {
+        // No ties.
+        let a = [19.0, 22.0, 16.0, 29.0, 24.0];
+        let b = [20.0, 11.0, 17.0, 12.0];
+        assert_eq!(mann_whitney_u(&a, &b), (17.0, 3.0));
+        assert_eq!(mann_whitney_u(&b, &a), (3.0, 17.0));
+
+        // Ties count as half a win.
+        assert_eq!(mann_whitney_u(&[1.0, 2.0, 2.0], &[2.0, 3.0]), (1.0, 5.0));
+
+        // Completely separated samples.
+        assert_eq!(mann_whitney_u(&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]), (0.0, 9.0));
+    }
+
+    #[test]
+    fn p_value() {
+        assert!((erfc(0.0) - 1.0).abs() < 1e-6);
+        // Two-sided p for z = 1.96 is 0.05.
+        assert!((erfc(1.96 / std::f64::consts::SQRT_2) - 0.05).abs() < 1e-4);
+
+        let same = ComparisonReport::from_metrics(vec![1.0; 10], vec![1.0; 10]);
+        assert!((same.p_value - 1.0).abs() < 1e-6);
+        assert_eq!(same.winner, Winner::Tie);
+
+        let a = (0..10).map(f64::from).collect::<Vec<_>>();
+        let b = (10..20).map(f64::from).collect::<Vec<_>>();
+        let separated = ComparisonReport::from_metrics(a, b);
+        assert!(separated.significant(0.001));
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn compare_rigged() -> Result<()> {
+        let create_fn = |cfg| Evolver::new(CountEvaluator, cfg, || 0);
+        let base = EvolveCfg::new(6).set_duplicates(Duplicates::AllowDuplicates);
+        let cfg_a = base.clone();
+        let cfg_b = base.set_pop_size(10);
+        // Metric is deterministic so B always wins.
+        let metric = |s: &Stats| s.pop_size as f64;
+        for par in [false, true] {
+            let budget = SearchBudget::new(2, 8).set_par(par);
+            let report =
+                compare_cfgs(&create_fn, &cfg_a, &cfg_b, &EmptyDataSampler {}, budget, &metric)?;
+            assert_eq!(report.metrics_a, vec![6.0; 8]);
+            assert_eq!(report.metrics_b, vec![10.0; 8]);
+            assert_eq!(report.winner, Winner::B);
+            assert_eq!(report.u, 0.0);
+            assert!(report.significant(0.01));
+
+            let report =
+                compare_cfgs(&create_fn, &cfg_b, &cfg_a, &EmptyDataSampler {}, budget, &metric)?;
+            assert_eq!(report.winner, Winner::A);
+        }
+        Ok(())
+    }
+}
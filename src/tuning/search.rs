@@ -0,0 +1,329 @@
+use eyre::{eyre, Result};
+use rand::prelude::SliceRandom;
+
+use crate::eval::Evaluator;
+use crate::evolve::cfg::{
+    Crossover, Duplicates, EvolveCfg, Mutation, Niching, Replacement, Selection, Species,
+    Stagnation, Survival,
+};
+use crate::evolve::evolver::CreateEvolverFn;
+use crate::evolve::result::Stats;
+use crate::train::sampler::DataSampler;
+use crate::util::par::map_vec;
+
+pub trait MetricFn = Fn(&Stats) -> f64 + Sync + Send;
+
+/// Values to try for each field of an `EvolveCfg`. Fields without any values
+/// keep the value from the base config.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct CfgSearchSpace {
+    base: EvolveCfg,
+    pop_size: Vec<usize>,
+    crossover: Vec<Crossover>,
+    mutation: Vec<Mutation>,
+    survival: Vec<Survival>,
+    selection: Vec<Selection>,
+    niching: Vec<Niching>,
+    species: Vec<Species>,
+    stagnation: Vec<Stagnation>,
+    replacement: Vec<Replacement>,
+    duplicates: Vec<Duplicates>,
+}
+
+// Each entry overrides one field of the config.
+type Setter = Box<dyn Fn(&mut EvolveCfg)>;
+
+fn setters<T: Clone + 'static>(v: &[T], f: fn(&mut EvolveCfg, T)) -> Vec<Setter> {
+    v.iter()
+        .cloned()
+        .map(|v| -> Setter { Box::new(move |cfg: &mut EvolveCfg| f(cfg, v.clone())) })
+        .collect()
+}
+
+impl CfgSearchSpace {
+    pub fn new(base: EvolveCfg) -> Self {
+        Self {
+            base,
+            pop_size: Vec::new(),
+            crossover: Vec::new(),
+            mutation: Vec::new(),
+            survival: Vec::new(),
+            selection: Vec::new(),
+            niching: Vec::new(),
+            species: Vec::new(),
+            stagnation: Vec::new(),
+            replacement: Vec::new(),
+            duplicates: Vec::new(),
+        }
+    }
+
+    pub fn set_pop_size(mut self, pop_size: &[usize]) -> Self {
+        self.pop_size = pop_size.to_vec();
+        self
+    }
+
+    pub fn set_crossover(mut self, crossover: &[Crossover]) -> Self {
+        self.crossover = crossover.to_vec();
+        self
+    }
+
+    pub fn set_mutation(mut self, mutation: &[Mutation]) -> Self {
+        self.mutation = mutation.to_vec();
+        self
+    }
+
+    pub fn set_survival(mut self, survival: &[Survival]) -> Self {
+        self.survival = survival.to_vec();
+        self
+    }
+
+    pub fn set_selection(mut self, selection: &[Selection]) -> Self {
+        self.selection = selection.to_vec();
+        self
+    }
+
+    pub fn set_niching(mut self, niching: &[Niching]) -> Self {
+        self.niching = niching.to_vec();
+        self
+    }
+
+    pub fn set_species(mut self, species: &[Species]) -> Self {
+        self.species = species.to_vec();
+        self
+    }
+
+    pub fn set_stagnation(mut self, stagnation: &[Stagnation]) -> Self {
+        self.stagnation = stagnation.to_vec();
+        self
+    }
+
+    pub fn set_replacement(mut self, replacement: &[Replacement]) -> Self {
+        self.replacement = replacement.to_vec();
+        self
+    }
+
+    pub fn set_duplicates(mut self, duplicates: &[Duplicates]) -> Self {
+        self.duplicates = duplicates.to_vec();
+        self
+    }
+
+    fn dims(&self) -> Vec<Vec<Setter>> {
+        let dims = vec![
+            setters(&self.pop_size, |cfg, v| cfg.pop_size = v),
+            setters(&self.crossover, |cfg, v| cfg.crossover = v),
+            setters(&self.mutation, |cfg, v| cfg.mutation = v),
+            setters(&self.survival, |cfg, v| cfg.survival = v),
+            setters(&self.selection, |cfg, v| cfg.selection = v),
+            setters(&self.niching, |cfg, v| cfg.niching = v),
+            setters(&self.species, |cfg, v| cfg.species = v),
+            setters(&self.stagnation, |cfg, v| cfg.stagnation = v),
+            setters(&self.replacement, |cfg, v| cfg.replacement = v),
+            setters(&self.duplicates, |cfg, v| cfg.duplicates = v),
+        ];
+        dims.into_iter().filter(|v| !v.is_empty()).collect()
+    }
+
+    /// Every combination of the given field values.
+    #[must_use]
+    pub fn grid(&self) -> Vec<EvolveCfg> {
+        let mut cfgs = vec![self.base.clone()];
+        for dim in self.dims() {
+            let mut next = Vec::with_capacity(cfgs.len() * dim.len());
+            for cfg in &cfgs {
+                for set in &dim {
+                    let mut cfg = cfg.clone();
+                    set(&mut cfg);
+                    next.push(cfg);
+                }
+            }
+            cfgs = next;
+        }
+        cfgs
+    }
+
+    /// Picks a random value for each field.
+    pub fn sample(&self) -> EvolveCfg {
+        let mut r = rand::thread_rng();
+        let mut cfg = self.base.clone();
+        for dim in self.dims() {
+            dim.choose(&mut r).unwrap()(&mut cfg);
+        }
+        cfg
+    }
+}
+
+/// How much work to do for each candidate config.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd)]
+pub struct SearchBudget {
+    /// Number of generations to run each candidate for.
+    pub generations: usize,
+    /// Number of independent runs for each candidate.
+    pub repeats: usize,
+    /// Run candidate configs in parallel.
+    pub par: bool,
+}
+
+impl SearchBudget {
+    pub fn new(generations: usize, repeats: usize) -> Self {
+        Self { generations, repeats, par: false }
+    }
+
+    pub fn set_par(self, par: bool) -> Self {
+        Self { par, ..self }
+    }
+}
+
+/// Summary of a metric over repeated runs.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub struct MetricStats {
+    pub mean: f64,
+    pub std: f64,
+    pub median: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl MetricStats {
+    pub fn from_values(v: &[f64]) -> Self {
+        let n = v.len() as f64;
+        let mean = v.iter().sum::<f64>() / n;
+        let var = v.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / n;
+        let min = v.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = v.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let mut sorted = v.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let mid = sorted.len() / 2;
+        let median = match sorted.len() {
+            0 => f64::NAN,
+            n if n % 2 == 0 => (sorted[mid - 1] + sorted[mid]) / 2.0,
+            _ => sorted[mid],
+        };
+        Self { mean, std: var.sqrt(), median, min, max }
+    }
+}
+
+#[must_use]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct SearchResult {
+    pub cfg: EvolveCfg,
+    /// Metric value for each repeat.
+    pub metrics: Vec<f64>,
+    pub stats: MetricStats,
+}
+
+pub(crate) fn run_cfg<E: Evaluator>(
+    cfg: &EvolveCfg,
+    create_fn: &impl CreateEvolverFn<E>,
+    sampler: &(impl DataSampler<E::Data> + Sync),
+    generations: usize,
+) -> Result<Stats> {
+    let mut evolver = create_fn(cfg.clone());
+    let mut r = None;
+    for _ in 0..generations {
+        r = Some(evolver.run_data(&sampler.train(evolver.generation()))?);
+    }
+    Ok(Stats::from_result(&r.unwrap()))
+}
+
+/// Runs each config and returns the results sorted by mean metric, highest
+/// first.
+pub fn search_cfgs<E: Evaluator>(
+    cfgs: Vec<EvolveCfg>,
+    create_fn: &impl CreateEvolverFn<E>,
+    sampler: &(impl DataSampler<E::Data> + Sync),
+    budget: SearchBudget,
+    metric: &impl MetricFn,
+) -> Result<Vec<SearchResult>> {
+    if budget.generations == 0 || budget.repeats == 0 {
+        return Err(eyre!("search budget must have at least one generation and repeat"));
+    }
+    let eval_cfg = |cfg: EvolveCfg| -> Result<SearchResult> {
+        let metrics = (0..budget.repeats)
+            .map(|_| Ok(metric(&run_cfg(&cfg, create_fn, sampler, budget.generations)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let stats = MetricStats::from_values(&metrics);
+        Ok(SearchResult { cfg, metrics, stats })
+    };
+    let mut results =
+        map_vec(cfgs, budget.par, eval_cfg).into_iter().collect::<Result<Vec<_>>>()?;
+    results.sort_by(|a, b| b.stats.mean.total_cmp(&a.stats.mean));
+    Ok(results)
+}
+
+/// Tries every config in the search space.
+pub fn grid_search<E: Evaluator>(
+    space: &CfgSearchSpace,
+    create_fn: &impl CreateEvolverFn<E>,
+    sampler: &(impl DataSampler<E::Data> + Sync),
+    budget: SearchBudget,
+    metric: &impl MetricFn,
+) -> Result<Vec<SearchResult>> {
+    search_cfgs(space.grid(), create_fn, sampler, budget, metric)
+}
+
+/// Tries `n_samples` randomly chosen configs from the search space.
+pub fn random_search<E: Evaluator>(
+    space: &CfgSearchSpace,
+    n_samples: usize,
+    create_fn: &impl CreateEvolverFn<E>,
+    sampler: &(impl DataSampler<E::Data> + Sync),
+    budget: SearchBudget,
+    metric: &impl MetricFn,
+) -> Result<Vec<SearchResult>> {
+    let cfgs = (0..n_samples).map(|_| space.sample()).collect();
+    search_cfgs(cfgs, create_fn, sampler, budget, metric)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::evolve::evolver::Evolver;
+    use crate::train::sampler::EmptyDataSampler;
+    use crate::util::bench_utils::CountEvaluator;
+
+    fn space() -> CfgSearchSpace {
+        CfgSearchSpace::new(EvolveCfg::new(10).set_duplicates(Duplicates::AllowDuplicates))
+            .set_pop_size(&[4, 8, 16])
+            .set_selection(&[Selection::Sus, Selection::Roulette])
+    }
+
+    #[test]
+    fn grid_is_cartesian_product() {
+        let grid = space().grid();
+        assert_eq!(grid.len(), 6);
+        for pop_size in [4, 8, 16] {
+            for selection in [Selection::Sus, Selection::Roulette] {
+                let count =
+                    grid.iter().filter(|c| c.pop_size == pop_size && c.selection == selection);
+                assert_eq!(count.count(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn search_sorted_by_metric() -> Result<()> {
+        let create_fn = |cfg| Evolver::new(CountEvaluator, cfg, || 0);
+        // Rank configs by population size so the order is known.
+        let metric = |s: &Stats| s.pop_size as f64;
+        let results = grid_search(
+            &space(),
+            &create_fn,
+            &EmptyDataSampler {},
+            SearchBudget::new(2, 2).set_par(true),
+            &metric,
+        )?;
+        assert_eq!(results.len(), 6);
+        for v in &results {
+            assert_eq!(v.metrics.len(), 2);
+            assert_eq!(v.stats, MetricStats::from_values(&v.metrics));
+        }
+        assert!(results.windows(2).all(|v| v[0].stats.mean >= v[1].stats.mean));
+        assert_eq!(results[0].cfg.pop_size, 16);
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+use eyre::Result;
+use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::eval::Evaluator;
+#[cfg(feature = "lgp")]
+use crate::evaluators::lgp::cfg::LgpEvaluatorCfg;
+#[cfg(feature = "lgp")]
+use crate::evaluators::lgp::vm::op::Op;
+
+/// Seed for benchmark setup, so inputs are the same from run to run.
+pub const BENCH_SEED: u64 = 1234;
+
+/// Deterministic rng for benchmark setup.
+#[must_use]
+pub fn bench_rng() -> StdRng {
+    StdRng::seed_from_u64(BENCH_SEED)
+}
+
+/// Random permutation of 0..n.
+#[must_use]
+pub fn rand_perm(n: usize, r: &mut StdRng) -> Vec<usize> {
+    let mut v = (0..n).collect::<Vec<_>>();
+    v.shuffle(r);
+    v
+}
+
+/// Random lgp program with |len| instructions.
+#[cfg(feature = "lgp")]
+#[must_use]
+pub fn rand_lgp_ops(cfg: &LgpEvaluatorCfg, len: usize, r: &mut StdRng) -> Vec<Op> {
+    (0..len).map(|_| cfg.rand_op_rng(r)).collect()
+}
+
+/// Trivially cheap evaluator, so benchmarks measure the library's overhead.
+/// Mutation counts up, so fitness is the number of mutations a member has
+/// had if it starts from zero. Distance is the difference in counts.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd)]
+pub struct CountEvaluator;
+
+impl Evaluator for CountEvaluator {
+    type State = usize;
+
+    fn crossover(&self, s1: &mut usize, s2: &mut usize, idx: usize) {
+        if idx != 0 {
+            std::mem::swap(s1, s2);
+        }
+    }
+
+    fn mutate(&self, s: &mut usize, _: f64, _: usize) {
+        *s += 1;
+    }
+
+    fn fitness(&self, s: &usize, _data: &()) -> Result<f64> {
+        Ok(*s as f64)
+    }
+
+    fn distance(&self, s1: &usize, s2: &usize) -> Result<f64> {
+        Ok(s1.abs_diff(*s2) as f64)
+    }
+}
@@ -0,0 +1,186 @@
+use std::cell::Cell;
+use std::time::Duration;
+
+// Number formatting shared by summaries, reports and CSV output, so the same
+// value is always written the same way.
+
+/// Significant digits `fmt_fitness` uses unless set otherwise.
+pub const DEFAULT_FITNESS_DIGITS: usize = 6;
+
+thread_local! {
+    static FITNESS_DIGITS: Cell<usize> = const { Cell::new(DEFAULT_FITNESS_DIGITS) };
+}
+
+/// Sets the significant digits `fmt_fitness` uses on this thread, at least
+/// one, until the returned guard is dropped. See `TrainerCfg::fitness_digits`.
+pub fn set_fitness_digits(digits: usize) -> FitnessDigitsGuard {
+    FitnessDigitsGuard { prev: FITNESS_DIGITS.replace(digits.max(1)) }
+}
+
+#[must_use]
+pub fn fitness_digits() -> usize {
+    FITNESS_DIGITS.get()
+}
+
+/// Restores the digits `fmt_fitness` used before `set_fitness_digits` when
+/// dropped.
+#[must_use = "the digits are restored when the guard is dropped"]
+#[derive(Debug)]
+pub struct FitnessDigitsGuard {
+    prev: usize,
+}
+
+impl Drop for FitnessDigitsGuard {
+    fn drop(&mut self) {
+        FITNESS_DIGITS.set(self.prev);
+    }
+}
+
+/// Formats |v| with `fitness_digits` significant digits. See
+/// `fmt_fitness_digits`.
+#[must_use]
+pub fn fmt_fitness(v: f64) -> String {
+    fmt_fitness_digits(v, fitness_digits())
+}
+
+/// Formats |v| with |digits| significant digits, keeping trailing zeros so
+/// values line up. Like `%g`, uses scientific notation for very small or
+/// large magnitudes. Zero is never negative, and non-finite values are
+/// written as `NaN`, `inf` and `-inf`.
+#[must_use]
+pub fn fmt_fitness_digits(v: f64, digits: usize) -> String {
+    if !v.is_finite() {
+        return v.to_string();
+    }
+    let digits = digits.max(1);
+    // Round in scientific notation first, so the exponent accounts for
+    // rounding up, e.g. 999999.5 to 1e6.
+    let v = if v == 0.0 { 0.0 } else { v };
+    let sci = format!("{:.*e}", digits - 1, v);
+    let exp = sci.split_once('e').and_then(|(_, exp)| exp.parse::<i64>().ok()).unwrap_or(0);
+    if exp < -4 || exp >= digits as i64 {
+        sci
+    } else {
+        format!("{:.*}", (digits as i64 - 1 - exp) as usize, v)
+    }
+}
+
+/// Formats |d| in the largest sensible unit: milliseconds below a second,
+/// seconds with two decimals below a minute, then minutes and seconds, then
+/// hours and minutes.
+#[must_use]
+pub fn fmt_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs == 0 {
+        format!("{}ms", d.as_millis())
+    } else if secs < 60 {
+        format!("{:.2}s", d.as_secs_f64())
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+/// Formats |n| with commas between groups of three digits.
+#[must_use]
+pub fn fmt_count(n: usize) -> String {
+    let digits = n.to_string();
+    let mut s = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            s.push(',');
+        }
+        s.push(c);
+    }
+    s
+}
+
+/// Quotes |v| if it has characters which are special in CSV, doubling any
+/// quotes in it.
+#[must_use]
+pub fn fmt_csv_str(v: &str) -> String {
+    if v.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", v.replace('"', "\"\""))
+    } else {
+        v.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn fitness() {
+        let cases = [
+            (0.0, "0.00000"),
+            (-0.0, "0.00000"),
+            (1e-12, "1.00000e-12"),
+            (0.5, "0.500000"),
+            (1.0, "1.00000"),
+            (0.000_123_456_7, "0.000123457"),
+            (123_456.789, "123457"),
+            (999_999.5, "1.00000e6"),
+            (-2.5, "-2.50000"),
+            (f64::NAN, "NaN"),
+            (f64::INFINITY, "inf"),
+            (f64::NEG_INFINITY, "-inf"),
+        ];
+        for (v, expected) in cases {
+            assert_eq!(fmt_fitness_digits(v, DEFAULT_FITNESS_DIGITS), expected, "{v}");
+        }
+        assert_eq!(fmt_fitness_digits(123_456.789, 9), "123456.789");
+        assert_eq!(fmt_fitness_digits(1e-12, 2), "1.0e-12");
+        assert_eq!(fmt_fitness_digits(0.5, 0), "0.5");
+    }
+
+    #[test]
+    fn fitness_digits_guard() {
+        {
+            let _outer = set_fitness_digits(2);
+            {
+                let _inner = set_fitness_digits(0);
+                assert_eq!(fmt_fitness(2.0 / 3.0), "0.7");
+            }
+            assert_eq!(fmt_fitness(2.0 / 3.0), "0.67");
+            // Other threads keep the default.
+            let other = std::thread::spawn(|| fmt_fitness(2.0 / 3.0)).join().unwrap();
+            assert_eq!(other, "0.666667");
+        }
+        assert_eq!(fitness_digits(), DEFAULT_FITNESS_DIGITS);
+    }
+
+    #[test]
+    fn duration() {
+        let cases = [
+            (Duration::ZERO, "0ms"),
+            (Duration::from_nanos(1), "0ms"),
+            (Duration::from_millis(250), "250ms"),
+            (Duration::from_millis(1500), "1.50s"),
+            (Duration::from_secs(125), "2m05s"),
+            (Duration::from_secs(3720), "1h02m"),
+            (Duration::from_secs(123_456_789), "34293h33m"),
+        ];
+        for (d, expected) in cases {
+            assert_eq!(fmt_duration(d), expected, "{d:?}");
+        }
+    }
+
+    #[test]
+    fn count() {
+        let cases = [(0, "0"), (999, "999"), (1000, "1,000"), (123_456_789, "123,456,789")];
+        for (n, expected) in cases {
+            assert_eq!(fmt_count(n), expected);
+        }
+    }
+
+    #[test]
+    fn csv_str() {
+        assert_eq!(fmt_csv_str("plain"), "plain");
+        assert_eq!(fmt_csv_str("a,\"b\""), "\"a,\"\"b\"\"\"");
+        assert_eq!(fmt_csv_str("a\nb"), "\"a\nb\"");
+    }
+}
@@ -1 +1,7 @@
+#[cfg(any(test, feature = "bench"))]
+pub mod bench_utils;
 pub mod distributions;
+pub mod fmt;
+pub mod par;
+#[cfg(test)]
+pub mod test_utils;
@@ -0,0 +1,66 @@
+use eyre::Result;
+
+// Helpers for work which can optionally run in parallel. Without the
+// `parallel` feature everything runs on the current thread.
+
+/// Splits |v| into chunks of the given size and calls |f| with the index and
+/// contents of each chunk, in parallel if |par| is set.
+pub fn try_for_each_chunk_mut<T: Send>(
+    v: &mut [T],
+    par: bool,
+    chunk_size: usize,
+    f: impl Fn(usize, &mut [T]) -> Result<()> + Sync + Send,
+) -> Result<()> {
+    #[cfg(feature = "parallel")]
+    if par {
+        use rayon::prelude::*;
+        return v.par_chunks_mut(chunk_size).enumerate().try_for_each(|(i, c)| f(i, c));
+    }
+    #[cfg(not(feature = "parallel"))]
+    let _ = par;
+    v.chunks_mut(chunk_size).enumerate().try_for_each(|(i, c)| f(i, c))
+}
+
+/// Maps |f| over |v|, in parallel if |par| is set.
+pub fn map_vec<T: Send, U: Send>(v: Vec<T>, par: bool, f: impl Fn(T) -> U + Sync + Send) -> Vec<U> {
+    #[cfg(feature = "parallel")]
+    if par {
+        use rayon::prelude::*;
+        return v.into_par_iter().map(f).collect();
+    }
+    #[cfg(not(feature = "parallel"))]
+    let _ = par;
+    v.into_iter().map(f).collect()
+}
+
+/// Whether |f| holds for any element of |v|, checking in parallel if |par| is
+/// set. Stops early once an element is found or |f| fails.
+pub fn try_any<T: Sync>(
+    v: &[T],
+    par: bool,
+    f: impl Fn(&T) -> Result<bool> + Sync + Send,
+) -> Result<bool> {
+    #[cfg(feature = "parallel")]
+    if par {
+        use rayon::prelude::*;
+        return v.par_iter().map(f).find_any(|r| !matches!(r, Ok(false))).unwrap_or(Ok(false));
+    }
+    #[cfg(not(feature = "parallel"))]
+    let _ = par;
+    for x in v {
+        if f(x)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Number of threads parallel work is spread over.
+#[must_use]
+pub fn num_threads() -> usize {
+    #[cfg(feature = "parallel")]
+    let n = rayon::current_num_threads();
+    #[cfg(not(feature = "parallel"))]
+    let n = 1;
+    n
+}
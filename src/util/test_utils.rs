@@ -0,0 +1,294 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use eyre::{eyre, Result};
+use rand::RngCore;
+
+use crate::eval::{Data, Evaluator, State};
+use crate::train::sink::{Metric, MetricSink};
+
+type CrossoverFn<S> = Box<dyn Fn(&mut S, &mut S, usize) + Send + Sync>;
+type MutateFn<S> = Box<dyn Fn(&mut S, f64, usize) + Send + Sync>;
+type DifferentialFn<S> = Box<dyn Fn(&mut S, [&S; 3], f64, f64) -> Result<()> + Send + Sync>;
+type FitnessRngFn<S, D> = Box<dyn Fn(&S, &D, &mut dyn RngCore) -> Result<f64> + Send + Sync>;
+type CompareFn<S, D> = Box<dyn Fn(&S, &S, &D) -> Result<Ordering> + Send + Sync>;
+type DistanceFn<S> = Box<dyn Fn(&S, &S) -> Result<f64> + Send + Sync>;
+type ViolationFn<S, D> = Box<dyn Fn(&S, &D) -> Result<f64> + Send + Sync>;
+type ValidateFn<S> = Box<dyn Fn(&S) -> Result<()> + Send + Sync>;
+type DescriptorFn<S, D> = Box<dyn Fn(&S, &D) -> Result<Vec<f64>> + Send + Sync>;
+type FingerprintFn<D> = Box<dyn Fn(&[D]) -> Option<u64> + Send + Sync>;
+
+/// Configurable evaluator for tests. Made with `new` or `default`, states are
+/// `usize`, fitness is the state, distance is the difference between states, and
+/// crossover and mutation do nothing. Each of these can be replaced, and
+/// hooks left unset behave like the `Evaluator` defaults. Calls to crossover,
+/// mutation, fitness and distance are counted. |C| and |M| are the numbers of
+/// crossover and mutation operators, and |NOOP| is `CROSSOVER_HAS_NOOP`, with
+/// the same defaults as `Evaluator`.
+#[must_use]
+pub struct MockEvaluator<
+    S = usize,
+    D = (),
+    const C: usize = 2,
+    const M: usize = 1,
+    const NOOP: bool = true,
+> {
+    pub crossover_calls: AtomicUsize,
+    pub mutate_calls: AtomicUsize,
+    pub fitness_calls: AtomicUsize,
+    pub distance_calls: AtomicUsize,
+    crossover: CrossoverFn<S>,
+    mutate: MutateFn<S>,
+    differential: Option<DifferentialFn<S>>,
+    fitness: FitnessRngFn<S, D>,
+    compare: Option<CompareFn<S, D>>,
+    distance: DistanceFn<S>,
+    violation: Option<ViolationFn<S, D>>,
+    validate: Option<ValidateFn<S>>,
+    descriptor: Option<DescriptorFn<S, D>>,
+    data_fingerprint: Option<FingerprintFn<D>>,
+}
+
+impl<D: Data, const C: usize, const M: usize, const NOOP: bool> Default
+    for MockEvaluator<usize, D, C, M, NOOP>
+{
+    fn default() -> Self {
+        Self::with(|s, _| Ok(*s as f64), |s1, s2| Ok(s1.abs_diff(*s2) as f64))
+    }
+}
+
+impl MockEvaluator {
+    // Only for the default parameters, which aren't used for inference. Use
+    // `default` for others.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<D: Data, const C: usize, const M: usize, const NOOP: bool>
+    MockEvaluator<usize, D, C, M, NOOP>
+{
+    /// Crossover swaps the states for operators other than 0, and mutation
+    /// counts up, like `CountEvaluator`.
+    pub fn counting(self) -> Self {
+        self.set_crossover(|s1, s2, idx| {
+            if idx != 0 {
+                std::mem::swap(s1, s2);
+            }
+        })
+        .set_mutate(|s, _, _| *s += 1)
+    }
+}
+
+impl<S: State, D: Data, const C: usize, const M: usize, const NOOP: bool>
+    MockEvaluator<S, D, C, M, NOOP>
+{
+    /// Evaluator over any state type, with the given fitness and distance.
+    pub fn with(
+        fitness: impl Fn(&S, &D) -> Result<f64> + Send + Sync + 'static,
+        distance: impl Fn(&S, &S) -> Result<f64> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            crossover_calls: AtomicUsize::new(0),
+            mutate_calls: AtomicUsize::new(0),
+            fitness_calls: AtomicUsize::new(0),
+            distance_calls: AtomicUsize::new(0),
+            crossover: Box::new(|_, _, _| {}),
+            mutate: Box::new(|_, _, _| {}),
+            differential: None,
+            fitness: Box::new(move |s, data, _| fitness(s, data)),
+            compare: None,
+            distance: Box::new(distance),
+            violation: None,
+            validate: None,
+            descriptor: None,
+            data_fingerprint: None,
+        }
+    }
+
+    pub fn set_crossover(self, f: impl Fn(&mut S, &mut S, usize) + Send + Sync + 'static) -> Self {
+        Self { crossover: Box::new(f), ..self }
+    }
+
+    pub fn set_mutate(self, f: impl Fn(&mut S, f64, usize) + Send + Sync + 'static) -> Self {
+        Self { mutate: Box::new(f), ..self }
+    }
+
+    /// Differential evolution variation of the target from the donors, given
+    /// |f| and |cr|.
+    pub fn set_differential(
+        self,
+        f: impl Fn(&mut S, [&S; 3], f64, f64) -> Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        Self { differential: Some(Box::new(f)), ..self }
+    }
+
+    pub fn set_fitness(self, f: impl Fn(&S, &D) -> Result<f64> + Send + Sync + 'static) -> Self {
+        Self { fitness: Box::new(move |s, data, _| f(s, data)), ..self }
+    }
+
+    pub fn set_fitness_rng(
+        self,
+        f: impl Fn(&S, &D, &mut dyn RngCore) -> Result<f64> + Send + Sync + 'static,
+    ) -> Self {
+        Self { fitness: Box::new(f), ..self }
+    }
+
+    pub fn set_compare(
+        self,
+        f: impl Fn(&S, &S, &D) -> Result<Ordering> + Send + Sync + 'static,
+    ) -> Self {
+        Self { compare: Some(Box::new(f)), ..self }
+    }
+
+    pub fn set_distance(self, f: impl Fn(&S, &S) -> Result<f64> + Send + Sync + 'static) -> Self {
+        Self { distance: Box::new(f), ..self }
+    }
+
+    pub fn set_violation(self, f: impl Fn(&S, &D) -> Result<f64> + Send + Sync + 'static) -> Self {
+        Self { violation: Some(Box::new(f)), ..self }
+    }
+
+    pub fn set_validate(self, f: impl Fn(&S) -> Result<()> + Send + Sync + 'static) -> Self {
+        Self { validate: Some(Box::new(f)), ..self }
+    }
+
+    pub fn set_descriptor(
+        self,
+        f: impl Fn(&S, &D) -> Result<Vec<f64>> + Send + Sync + 'static,
+    ) -> Self {
+        Self { descriptor: Some(Box::new(f)), ..self }
+    }
+
+    pub fn set_data_fingerprint(
+        self,
+        f: impl Fn(&[D]) -> Option<u64> + Send + Sync + 'static,
+    ) -> Self {
+        Self { data_fingerprint: Some(Box::new(f)), ..self }
+    }
+}
+
+impl<S: State, D: Data, const C: usize, const M: usize, const NOOP: bool> Evaluator
+    for MockEvaluator<S, D, C, M, NOOP>
+{
+    type State = S;
+    type Data = D;
+    const NUM_CROSSOVER: usize = C;
+    const CROSSOVER_HAS_NOOP: bool = NOOP;
+    const NUM_MUTATION: usize = M;
+
+    fn crossover(&self, s1: &mut S, s2: &mut S, idx: usize) {
+        let _ = self.crossover_calls.fetch_add(1, atomic::Ordering::Relaxed);
+        (self.crossover)(s1, s2, idx);
+    }
+
+    fn mutate(&self, s: &mut S, rate: f64, idx: usize) {
+        let _ = self.mutate_calls.fetch_add(1, atomic::Ordering::Relaxed);
+        (self.mutate)(s, rate, idx);
+    }
+
+    fn differential_rng(
+        &self,
+        target: &mut S,
+        donors: [&S; 3],
+        f: f64,
+        cr: f64,
+        _rng: &mut dyn RngCore,
+    ) -> Result<()> {
+        match &self.differential {
+            Some(differential) => differential(target, donors, f, cr),
+            None => Err(eyre!("differential crossover is not supported by this evaluator")),
+        }
+    }
+
+    fn fitness(&self, s: &S, data: &D) -> Result<f64> {
+        self.fitness_rng(s, data, &mut rand::thread_rng())
+    }
+
+    fn fitness_rng(&self, s: &S, data: &D, rng: &mut dyn RngCore) -> Result<f64> {
+        let _ = self.fitness_calls.fetch_add(1, atomic::Ordering::Relaxed);
+        (self.fitness)(s, data, rng)
+    }
+
+    fn compare(&self, a: &S, b: &S, data: &D) -> Result<Ordering> {
+        match &self.compare {
+            Some(compare) => compare(a, b, data),
+            None => Err(eyre!("comparative fitness needs Evaluator::compare")),
+        }
+    }
+
+    fn distance(&self, s1: &S, s2: &S) -> Result<f64> {
+        let _ = self.distance_calls.fetch_add(1, atomic::Ordering::Relaxed);
+        (self.distance)(s1, s2)
+    }
+
+    fn violation(&self, s: &S, data: &D) -> Result<f64> {
+        self.violation.as_ref().map_or(Ok(0.0), |violation| violation(s, data))
+    }
+
+    fn validate_state(&self, s: &S) -> Result<()> {
+        self.validate.as_ref().map_or(Ok(()), |validate| validate(s))
+    }
+
+    fn descriptor(&self, s: &S, data: &D) -> Result<Vec<f64>> {
+        self.descriptor.as_ref().map_or(Ok(vec![]), |descriptor| descriptor(s, data))
+    }
+
+    fn data_fingerprint(&self, inputs: &[D]) -> Option<u64> {
+        self.data_fingerprint.as_ref().and_then(|fingerprint| fingerprint(inputs))
+    }
+}
+
+/// Metric sink for tests which keeps every metric written. With a delay it
+/// sleeps on every write first, like a sink on slow storage.
+#[must_use]
+#[derive(Default)]
+pub struct MockSink {
+    delay: Duration,
+    written: Arc<Mutex<Vec<Metric>>>,
+}
+
+impl MockSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_delay(self, delay: Duration) -> Self {
+        Self { delay, ..self }
+    }
+
+    /// Metrics written so far, still readable once the sink has been handed
+    /// to an `AsyncSink` or `Trainer`.
+    #[must_use]
+    pub fn written(&self) -> Arc<Mutex<Vec<Metric>>> {
+        Arc::clone(&self.written)
+    }
+}
+
+impl MetricSink for MockSink {
+    fn write(&mut self, metric: Metric) -> Result<()> {
+        thread::sleep(self.delay);
+        self.written.lock().unwrap().push(metric);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Steps and values of the per-generation scalars in |metrics| tagged |tag|.
+#[must_use]
+pub fn tagged_scalars(metrics: &[Metric], tag: &str) -> Vec<(usize, HashMap<String, f32>)> {
+    metrics
+        .iter()
+        .filter_map(|m| match m {
+            Metric::Scalars { tag: t, values, step } if t == tag => Some((*step, values.clone())),
+            _ => None,
+        })
+        .collect()
+}
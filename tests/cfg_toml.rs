@@ -0,0 +1,47 @@
+#![cfg(feature = "serde")]
+
+use std::time::Duration;
+
+use eyre::Result;
+use memega::prelude::*;
+use pretty_assertions::assert_eq;
+
+#[test]
+fn toml_round_trip() -> Result<()> {
+    let cfg = EvolveCfg::new(30)
+        .set_crossover(Crossover::Fixed(vec![0.1, 0.9]))
+        .set_mutation(Mutation::Fixed(vec![1.0 / 3.0]))
+        .set_survival(Survival::Tournament(3))
+        .set_species(Species::TargetNumberBounded {
+            target: 4,
+            min_size: 2,
+            max_frac: PopFraction::new(0.5)?,
+        })
+        .set_fitness_pipeline(vec![
+            FitnessStage::Niching(Niching::SharedFitness(2.0)),
+            FitnessStage::Rank,
+            FitnessStage::Power(1.5),
+        ])
+        .set_warmup(Some(Warmup::new(3, false, Selection::Rank(RankPressure::new(1.5)?))))
+        .set_local_search(Some(LocalSearchCfg::new(0.1, 2, LocalSearchPolicy::BestParents)))
+        .set_fitness_seed(Some(7))
+        .set_generation_time_budget(Some(Duration::from_millis(1500)));
+    let text = cfg.to_toml()?;
+    assert_eq!(EvolveCfg::from_toml(&text)?, cfg);
+    // Rank pressure is checked when read.
+    assert!(text.contains("Rank = 1.5"), "{text}");
+    assert!(EvolveCfg::from_toml(&text.replace("Rank = 1.5", "Rank = 2.5")).is_err());
+    // So is the fraction of the population species are capped at.
+    assert!(text.contains("max_frac = 0.5"), "{text}");
+    assert!(EvolveCfg::from_toml(&text.replace("max_frac = 0.5", "max_frac = 0.0")).is_err());
+
+    // Unset options are left out, and read back as unset.
+    let cfg = EvolveCfg::new(10).set_crossover(Crossover::Differential { f: 0.5, cr: 0.9 });
+    let text = cfg.to_toml()?;
+    assert!(!text.contains("fitness_seed"), "{text}");
+    assert_eq!(EvolveCfg::from_toml(&text)?, cfg);
+
+    assert!(EvolveCfg::new(10).set_fitness_seed(Some(u64::MAX)).to_toml().is_err());
+    assert!(EvolveCfg::from_toml("pop_size = \"ten\"").is_err());
+    Ok(())
+}
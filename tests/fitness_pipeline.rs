@@ -0,0 +1,57 @@
+use eyre::Result;
+use memega::prelude::*;
+
+// A lone member with fitness 4, and a niche of four copies of a member with
+// fitness 6. States index into the table.
+const POP: [(f64, f64); 5] = [(0.0, 4.0), (10.0, 6.0), (10.0, 6.0), (10.0, 6.0), (10.0, 6.0)];
+
+// Members are points on a line, (position, fitness) in |POP|.
+struct LineEvaluator;
+
+impl Evaluator for LineEvaluator {
+    type State = usize;
+
+    fn crossover(&self, _: &mut usize, _: &mut usize, _: usize) {}
+
+    fn mutate(&self, _: &mut usize, _: f64, _: usize) {}
+
+    fn fitness(&self, s: &usize, _data: &()) -> Result<f64> {
+        Ok(POP[*s].1)
+    }
+
+    fn distance(&self, s1: &usize, s2: &usize) -> Result<f64> {
+        Ok((POP[*s1].0 - POP[*s2].0).abs())
+    }
+}
+
+// Selection fitness of the lone member and of one member of the niche.
+fn selection_fitness(pipeline: Vec<FitnessStage>) -> Result<(f64, f64)> {
+    let cfg = EvolveCfg::new(POP.len())
+        .set_duplicates(Duplicates::AllowDuplicates)
+        .set_fitness_pipeline(pipeline);
+    let mut evolver = Evolver::from_initial(LineEvaluator, cfg, (0..POP.len()).collect(), || 0);
+    let r = evolver.run()?;
+    let get = |state| r.gen.mems.iter().find(|v| *v.state == state).unwrap().selection_fitness;
+    Ok((get(0), get(1)))
+}
+
+#[test]
+#[allow(clippy::float_cmp)]
+fn scaling_and_sharing_order() -> Result<()> {
+    let share = FitnessStage::Niching(Niching::SharedFitness(1.0));
+    let square = FitnessStage::Power(2.0);
+
+    // Sharing divides fitness between the four copies.
+    assert_eq!(selection_fitness(vec![share])?, (4.0, 1.5));
+    // Scaling first shares the scaled fitness, so the niche as a whole gets
+    // more than twice the lone member's selection fitness.
+    assert_eq!(selection_fitness(vec![square, share])?, (16.0, 9.0));
+    // Scaling after sharing also scales the division, so the niche gets less
+    // than the lone member.
+    assert_eq!(selection_fitness(vec![share, square])?, (16.0, 2.25));
+
+    // The default pipeline comes from the niching setting.
+    let cfg = EvolveCfg::new(POP.len()).set_niching(Niching::SharedFitness(1.0));
+    assert_eq!(cfg.fitness_stages(), [share]);
+    Ok(())
+}
@@ -0,0 +1,27 @@
+#![cfg(feature = "lgp")]
+
+use eyre::Result;
+use memega::prelude::*;
+
+// Fitness rewards longer code, so programs bloat as fast as they can. With a
+// growth limit of 2, the longest program can only grow by 2 a generation.
+#[test]
+fn growth_limit_caps_bloat() -> Result<()> {
+    let lgpcfg = LgpEvaluatorCfg::new().set_max_code(500).set_growth_limit(2);
+    let cfg = EvolveCfg::new(30).set_duplicates(Duplicates::AllowDuplicates);
+    let f = |s: &LgpState, (): &()| Ok(s.ops_unopt().len() as f64);
+    let mut evolver = lgp_fitness_evolver(lgpcfg, cfg, f);
+    let mut prev_max = None;
+    for _ in 0..50 {
+        let stats = Stats::from_result(&evolver.run()?);
+        let lengths = stats.lengths.expect("lgp reports lengths");
+        assert!(lengths.min as f64 <= lengths.mean && lengths.mean <= lengths.max as f64);
+        assert!(lengths.effective_max <= lengths.max);
+        if let Some(prev_max) = prev_max {
+            assert!(lengths.max <= prev_max + 2, "grew from {prev_max} to {}", lengths.max);
+        }
+        prev_max = Some(lengths.max);
+        assert!(stats.to_string().contains("\nlength: min "), "{stats}");
+    }
+    Ok(())
+}
@@ -0,0 +1,34 @@
+#![cfg(feature = "lgp")]
+
+use eyre::Result;
+use memega::evaluators::lgp::vm::asm::lgp_asm;
+use memega::evaluators::lgp::vm::lgpvm::LgpVm;
+use memega::prelude::*;
+
+// Evolves programs computing x * 3 + 1 for a single input, starting from a
+// preamble which sets up scratch registers.
+#[test]
+fn preamble_survives_evolution() -> Result<()> {
+    let (lgpcfg, layout) = LgpEvaluatorCfg::for_problem(1, 1);
+    let preamble = lgp_asm("load r2, 3\nload r3, 1\n")?;
+    let lgpcfg = lgpcfg.set_max_code(16).set_preamble(&preamble);
+    let f = move |s: &LgpState, (): &()| {
+        let mut err = 0.0;
+        for x in 0..5 {
+            let x = f64::from(x);
+            let mut vm = LgpVm::new(&s.lgpvmcfg(&layout.regs(), &layout.constants(&[x])));
+            vm.run();
+            err += (layout.outputs(&vm)[0] - (x * 3.0 + 1.0)).abs();
+        }
+        Ok(1.0 / (1.0 + err))
+    };
+    let mut evolver = lgp_fitness_evolver(lgpcfg, EvolveCfg::new(30), f);
+    for _ in 0..300 {
+        let r = evolver.run()?;
+        for mem in &r.gen.mems {
+            assert!(mem.state.ops_unopt().starts_with(&preamble), "lost preamble:\n{}", mem.state);
+            assert!(mem.state.ops_unopt().len() <= 16);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,17 @@
+#![cfg(all(feature = "lgp", feature = "serde"))]
+
+use eyre::Result;
+use memega::evaluators::lgp::vm::program::LgpProgram;
+use pretty_assertions::assert_eq;
+
+#[test]
+fn serde_round_trip() -> Result<()> {
+    let program = LgpProgram::asm("load r1, 2.5\nmul r0, r1, r2\niflt r0, r2\n", 2, 1, &[0])?;
+    let json = serde_json::to_string(&program)?;
+    assert_eq!(serde_json::from_str::<LgpProgram>(&json)?, program);
+
+    // Deserialized programs are validated.
+    let invalid = json.replace("\"num_reg\":2", "\"num_reg\":1");
+    assert!(serde_json::from_str::<LgpProgram>(&invalid).is_err());
+    Ok(())
+}
@@ -0,0 +1,94 @@
+#![cfg(feature = "lgp")]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use memega::evaluators::lgp::vm::lgpvm::LgpVm;
+use memega::prelude::*;
+
+// Counts allocation calls for the whole test binary. This file only has one
+// test so nothing else allocates concurrently.
+struct CountingAlloc;
+
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::SeqCst);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+const POP: usize = 200;
+const POINTS: usize = 100;
+
+fn inputs(i: usize) -> [f64; 2] {
+    [i as f64 / 10.0, 1.0]
+}
+
+// Builds a vm per member per data point.
+fn fitness_fresh(pop: &[LgpState], layout: &LgpRegisterLayout) -> Vec<f64> {
+    let mut fitness = Vec::with_capacity(pop.len());
+    for s in pop {
+        let mut total = 0.0;
+        for i in 0..POINTS {
+            let cfg = s.lgpvmcfg(&layout.regs(), &layout.constants(&inputs(i)));
+            let mut vm = LgpVm::new(&cfg);
+            vm.run();
+            total += layout.outputs(&vm)[0];
+        }
+        fitness.push(total);
+    }
+    fitness
+}
+
+// Builds a vm per member and resets it per data point.
+fn fitness_reused(pop: &[LgpState], layout: &LgpRegisterLayout) -> Vec<f64> {
+    let regs = layout.regs();
+    let out = layout.output_indices[0];
+    let mut fitness = Vec::with_capacity(pop.len());
+    for s in pop {
+        let cfg = s.lgpvmcfg(&regs, &inputs(0));
+        let mut vm = LgpVm::borrowed(&cfg);
+        let mut total = 0.0;
+        for i in 0..POINTS {
+            vm.reset_with_constants(&regs, &inputs(i));
+            vm.run();
+            total += vm.mem(out);
+        }
+        fitness.push(total);
+    }
+    fitness
+}
+
+#[test]
+fn reused_vm_allocates_less() {
+    let (lgpcfg, layout) = LgpEvaluatorCfg::for_problem(2, 1);
+    let pop = (0..POP)
+        .map(|_| {
+            let ops = (0..20).map(|_| lgpcfg.rand_op()).collect();
+            LgpState::new(ops, lgpcfg.num_reg(), lgpcfg.num_const(), lgpcfg.output_regs())
+        })
+        .collect::<Vec<_>>();
+
+    let before = ALLOCS.load(Ordering::SeqCst);
+    let fresh = fitness_fresh(&pop, &layout);
+    let fresh_allocs = ALLOCS.load(Ordering::SeqCst) - before;
+
+    let before = ALLOCS.load(Ordering::SeqCst);
+    let reused = fitness_reused(&pop, &layout);
+    let reused_allocs = ALLOCS.load(Ordering::SeqCst) - before;
+
+    assert!(fresh.iter().zip(&reused).all(|(a, b)| a.to_bits() == b.to_bits()));
+    assert!(
+        reused_allocs * 10 < fresh_allocs,
+        "reused {reused_allocs} allocations vs fresh {fresh_allocs}"
+    );
+}
@@ -0,0 +1,133 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use eyre::Result;
+use memega::ops::distance::dist2;
+use memega::ops::mutation::{mutate_normal, mutate_rate};
+use memega::ops::util::rand_vec;
+use memega::prelude::*;
+use rand::Rng;
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+struct Point(Vec<f64>);
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+// Maximise closeness to the origin, with a hill climbing local search. Counts
+// every fitness call and local search.
+#[derive(Default)]
+struct SphereEvaluator {
+    fitness_calls: AtomicUsize,
+    searches: AtomicUsize,
+    // Local search moves states straight to the origin instead.
+    to_origin: bool,
+}
+
+impl Evaluator for SphereEvaluator {
+    type State = Point;
+
+    fn crossover(&self, s1: &mut Point, s2: &mut Point, _: usize) {
+        std::mem::swap(&mut s1.0[0], &mut s2.0[0]);
+    }
+
+    fn mutate(&self, s: &mut Point, rate: f64, _: usize) {
+        mutate_rate(&mut s.0, rate, |v| mutate_normal(v, 0.1));
+    }
+
+    fn fitness(&self, s: &Point, _data: &()) -> Result<f64> {
+        self.fitness_calls.fetch_add(1, Ordering::Relaxed);
+        Ok(1.0 / (1.0 + s.0.iter().map(|v| v * v).sum::<f64>()))
+    }
+
+    fn distance(&self, s1: &Point, s2: &Point) -> Result<f64> {
+        Ok(dist2(&s1.0, &s2.0))
+    }
+
+    fn local_search(
+        &self,
+        s: &mut Point,
+        data: &[()],
+        budget: usize,
+        evals: &FitnessEvals<'_, Point, ()>,
+    ) -> Result<()> {
+        self.searches.fetch_add(1, Ordering::Relaxed);
+        if self.to_origin {
+            s.0.fill(0.0);
+            return Ok(());
+        }
+        let mut r = rand::thread_rng();
+        let mean = |s: &Point| -> Result<f64> {
+            let total = data.iter().map(|v| evals.fitness(s, v)).sum::<Result<f64>>()?;
+            Ok(total / data.len() as f64)
+        };
+        let mut best = mean(s)?;
+        for _ in 0..budget {
+            let mut next = s.clone();
+            let i = r.gen_range(0..next.0.len());
+            next.0[i] = mutate_normal(next.0[i], 0.1);
+            let fitness = mean(&next)?;
+            if fitness > best {
+                (*s, best) = (next, fitness);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn local_search_fraction_and_evals() -> Result<()> {
+    // 4 survivors and 16 children each generation. Members start out equal,
+    // so copies must each be evaluated for the counts to be fixed.
+    const POP: usize = 20;
+    for policy in [LocalSearchPolicy::BestParents, LocalSearchPolicy::Random] {
+        let cfg = EvolveCfg::new(POP)
+            .set_survival(Survival::TopProportion(0.2))
+            .set_duplicates(Duplicates::AllowDuplicates)
+            .set_share_duplicate_fitness(false)
+            .set_local_search(Some(LocalSearchCfg::new(0.25, 5, policy)));
+        let mut evolver =
+            Evolver::new(SphereEvaluator::default(), cfg, || Point(rand_vec(3, || 1.0)));
+        for _ in 0..5 {
+            let r = evolver.run()?;
+            let searches = evolver.eval().searches.swap(0, Ordering::Relaxed);
+            let calls = evolver.eval().fitness_calls.swap(0, Ordering::Relaxed);
+            let stats = Stats::from_result(&r);
+            assert_eq!(searches, 4);
+            assert_eq!(stats.local_searched, 4);
+            // Evaluating the generation, then 1 + 5 evaluations per search.
+            assert_eq!(calls, POP + 4 * 6);
+            assert_eq!(stats.fitness_evals, calls);
+        }
+    }
+
+    // Without local search only the generation is evaluated.
+    let cfg = EvolveCfg::new(POP)
+        .set_duplicates(Duplicates::AllowDuplicates)
+        .set_share_duplicate_fitness(false);
+    let mut evolver = Evolver::new(SphereEvaluator::default(), cfg, || Point(vec![1.0; 3]));
+    let r = evolver.run()?;
+    assert_eq!(evolver.eval().searches.load(Ordering::Relaxed), 0);
+    assert_eq!(Stats::from_result(&r).fitness_evals, POP);
+    Ok(())
+}
+
+#[test]
+fn local_search_duplicates_removed() -> Result<()> {
+    // Local search makes every child equal, and they're removed like any
+    // other duplicates.
+    let cfg = EvolveCfg::new(20)
+        .set_duplicates(Duplicates::DisallowDuplicates)
+        .set_local_search(Some(LocalSearchCfg::new(1.0, 1, LocalSearchPolicy::Random)));
+    let eval = SphereEvaluator { to_origin: true, ..SphereEvaluator::default() };
+    let mut evolver = Evolver::new(eval, cfg, || Point(rand_vec(3, || rand::thread_rng().gen())));
+    for _ in 0..3 {
+        let r = evolver.run()?;
+        assert_eq!(r.num_dup(), 0);
+        assert!(r.dups_removed > 0);
+    }
+    Ok(())
+}
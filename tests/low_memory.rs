@@ -0,0 +1,109 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use eyre::Result;
+use memega::prelude::*;
+use rand::Rng;
+
+// Tracks current and peak heap usage for the whole test binary. This file
+// only has one test so nothing else allocates concurrently.
+struct CountingAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let cur = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        PEAK.fetch_max(cur, Ordering::SeqCst);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+        unsafe { System.dealloc(ptr, layout) };
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+const STATE_SIZE: usize = 1 << 20;
+const POP: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+struct BigState(Vec<u8>);
+
+impl fmt::Display for BigState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes", self.0.len())
+    }
+}
+
+struct BigEvaluator;
+
+impl Evaluator for BigEvaluator {
+    type State = BigState;
+
+    fn crossover(&self, _: &mut BigState, _: &mut BigState, _: usize) {}
+
+    fn mutate(&self, s: &mut BigState, _: f64, _: usize) {
+        let mut r = rand::thread_rng();
+        let idx = r.gen_range(0..s.0.len());
+        s.0[idx] = s.0[idx].wrapping_add(1);
+    }
+
+    fn fitness(&self, s: &BigState, _data: &()) -> Result<f64> {
+        Ok(s.0.iter().take(16).map(|&v| f64::from(v)).sum())
+    }
+
+    fn distance(&self, _: &BigState, _: &BigState) -> Result<f64> {
+        Ok(0.0)
+    }
+
+    fn state_bytes(&self, s: &BigState) -> usize {
+        s.0.len()
+    }
+}
+
+// Runs several generations, holding on to the previous result while the next
+// one runs as a trainer does, and returns the peak heap usage and the number
+// of members in the last result.
+fn peak_bytes(low_memory: Option<usize>) -> Result<(usize, usize)> {
+    let cfg = EvolveCfg::new(POP)
+        .set_survival(Survival::TopProportion(0.1))
+        .set_duplicates(Duplicates::AllowDuplicates)
+        .set_low_memory(low_memory);
+    let base = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(base, Ordering::SeqCst);
+    let mut evolver = Evolver::new(BigEvaluator, cfg, || BigState(vec![0; STATE_SIZE]));
+    let mut last = None;
+    for _ in 0..10 {
+        last = Some(evolver.run()?);
+    }
+    let pop = last.map_or(0, |r| Stats::from_result(&r).pop_size);
+    Ok((PEAK.load(Ordering::SeqCst) - base, pop))
+}
+
+#[test]
+fn low_memory_bounded() -> Result<()> {
+    // The evaluated generation and the children of the next one, plus a
+    // little slack. Without low-memory mode, the non-survivors of the held
+    // result take it over.
+    let bound = POP * STATE_SIZE * 12 / 5;
+
+    let (peak, pop) = peak_bytes(None)?;
+    assert_eq!(pop, POP);
+    assert!(peak > bound, "peak {peak} under bound {bound} without low-memory mode");
+
+    // Over the threshold, only survivors are kept in the result.
+    let (peak, pop) = peak_bytes(Some(POP * STATE_SIZE / 2))?;
+    assert!(pop < POP, "pop {pop}");
+    assert!(peak < bound, "peak {peak} over bound {bound}");
+
+    // Under it, everything is kept.
+    let (_, pop) = peak_bytes(Some(POP * STATE_SIZE))?;
+    assert_eq!(pop, POP);
+    Ok(())
+}
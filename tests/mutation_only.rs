@@ -0,0 +1,61 @@
+use eyre::Result;
+use memega::ops::mutation::mutate_rate;
+use memega::prelude::*;
+use rand::Rng;
+
+const BITS: usize = 32;
+
+// Maximise the number of set bits using only bit flips.
+struct OneMaxEvaluator;
+
+impl Evaluator for OneMaxEvaluator {
+    type State = String;
+    const NUM_CROSSOVER: usize = 0;
+    const NUM_MUTATION: usize = 1;
+
+    fn crossover(&self, _: &mut String, _: &mut String, idx: usize) {
+        panic!("crossover {idx} called on a mutation-only evaluator");
+    }
+
+    fn mutate(&self, s: &mut String, rate: f64, _: usize) {
+        let mut bits = s.chars().collect::<Vec<_>>();
+        mutate_rate(&mut bits, rate, |v| if v == '0' { '1' } else { '0' });
+        *s = bits.into_iter().collect();
+    }
+
+    fn fitness(&self, s: &String, _data: &()) -> Result<f64> {
+        Ok((s.chars().filter(|&v| v == '1').count() + 1) as f64)
+    }
+
+    fn distance(&self, s1: &String, s2: &String) -> Result<f64> {
+        Ok(s1.chars().zip(s2.chars()).filter(|(a, b)| a != b).count() as f64)
+    }
+}
+
+fn rand_bits() -> String {
+    let mut r = rand::thread_rng();
+    (0..BITS).map(|_| if r.gen::<bool>() { '1' } else { '0' }).collect()
+}
+
+#[test]
+fn mutation_only_runs() -> Result<()> {
+    let cfgs = [
+        EvolveCfg::new(50).set_crossover(Crossover::Adaptive).set_mutation(Mutation::Adaptive),
+        EvolveCfg::new(50)
+            .set_crossover(Crossover::Fixed(vec![]))
+            .set_crossover_probability(Some(0.9))
+            .set_mutation(Mutation::Fixed(vec![0.1])),
+    ];
+    for cfg in cfgs {
+        let mut evolver = Evolver::new(OneMaxEvaluator, cfg, rand_bits);
+        let first = evolver.run()?.nth(0).fitness;
+        let mut best = first;
+        for _ in 0..50 {
+            let r = evolver.run()?;
+            best = best.max(r.nth(0).fitness);
+            let _ = evolver.summary(&r);
+        }
+        assert!(best > first, "no improvement from {first}");
+    }
+    Ok(())
+}
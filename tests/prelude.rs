@@ -0,0 +1,126 @@
+// Uses every item in the prelude by name, so removing or renaming one fails to
+// compile.
+use std::time::Duration;
+
+use eyre::Result;
+use memega::prelude::*;
+
+struct SumEvaluator;
+
+impl Evaluator for SumEvaluator {
+    type State = String;
+
+    fn crossover(&self, s1: &mut String, s2: &mut String, _: usize) {
+        std::mem::swap(s1, s2);
+    }
+
+    fn mutate(&self, s: &mut String, _: f64, _: usize) {
+        s.push('1');
+    }
+
+    fn fitness(&self, s: &String, _data: &()) -> Result<f64> {
+        Ok(s.len() as f64 + 1.0)
+    }
+
+    fn distance(&self, s1: &String, s2: &String) -> Result<f64> {
+        Ok(s1.len().abs_diff(s2.len()) as f64)
+    }
+}
+
+fn traits<S: State, D: Data, F: FitnessFn<S, D>>(_: F) {}
+
+#[cfg(feature = "lgp")]
+fn lgp_traits<F: LgpFitnessFn>(_: F) {}
+
+struct OneSampler;
+
+impl DataSampler<()> for OneSampler {
+    fn train(&self, _gen: usize) -> Vec<()> {
+        vec![()]
+    }
+
+    fn valid(&self, _gen: usize) -> Vec<()> {
+        vec![()]
+    }
+
+    fn test(&self, _gen: usize) -> Vec<()> {
+        vec![]
+    }
+}
+
+#[test]
+fn prelude_items() -> Result<()> {
+    traits(|s: &String, (): &()| Ok(s.len() as f64));
+    let _: DataEpoch = 0;
+
+    let cfg = EvolveCfg::new(10)
+        .set_crossover(Crossover::Adaptive)
+        .set_mutation(Mutation::Adaptive)
+        .set_survival(Survival::TopProportion(0.2))
+        .set_survival_fitness(SurvivalFitness::Base)
+        .set_selection(Selection::Sus)
+        .set_niching(Niching::None)
+        .set_species(Species::None)
+        .set_stagnation(Stagnation::None)
+        .set_stagnation_condition(StagnationCondition::Default)
+        .set_stagnation_signal(StagnationSignal::TrainBest)
+        .set_replacement(Replacement::ReplaceChildren(0.2))
+        .set_params_crossover(ParamsCrossover::Inherit)
+        .set_duplicates(Duplicates::AllowDuplicates)
+        .set_fitness_reduction(FitnessReduction::ArithmeticMean)
+        .set_constraint_mode(ConstraintMode::None)
+        .set_comparison(Comparison::None)
+        .set_age_decay(None::<AgeDecay>)
+        .set_fitness_pipeline(vec![FitnessStage::Niching(Niching::None)])
+        .set_local_search(Some(LocalSearchCfg::new(0.1, 2, LocalSearchPolicy::Random)))
+        .set_validate_distance(true)
+        .set_warmup(Some(Warmup::new(2, false, Selection::Sus)))
+        .set_generation_time_budget(Some(Duration::from_secs(10)));
+    #[cfg(feature = "serde")]
+    assert_eq!(EvolveCfg::from_toml(&cfg.to_toml()?)?, cfg);
+    let _ = OptionalPhase::Distances;
+    validate_distance_metric(&SumEvaluator, &["a".into(), "bb".into(), String::new()], 8)?;
+    let _: Option<MetricError> = None;
+    let fitness = |s: &String, data: &()| SumEvaluator.fitness(s, data);
+    let evals = FitnessEvals::new(&fitness);
+    assert_eq!(evals.fitness(&"ab".into(), &())?, 3.0);
+    assert_eq!(evals.get(), 1);
+
+    let evolver = Evolver::new(SumEvaluator, cfg, String::new);
+    let trainer_cfg = TrainerCfg::new("prelude")
+        .set_termination(Termination::FixedGenerations(2))
+        .set_target_signal(TargetSignal::Train);
+    let mut trainer = Trainer::new(trainer_cfg);
+    let r: EvolveResult<String> = trainer.train(evolver, &OneSampler)?;
+    let best: &Member<String> = r.nth(0);
+    assert!(best.fitness >= 1.0);
+    let _: &Params = &best.params;
+    let _ = Stats::from_result(&r);
+    let _: PopulationSnapshot<'_> = r.snapshot();
+
+    let _ = crossover::crossover_kpx::<u8>;
+    let _ = distance::count_different::<u8>;
+    let _ = mutation::mutate_normal;
+    let _ = sampling::rws;
+    let _ = util::vec_to_str;
+    let _ = encoding::to_gray;
+    Ok(())
+}
+
+#[cfg(feature = "lgp")]
+#[test]
+fn prelude_lgp() -> Result<()> {
+    let lgpcfg = LgpEvaluatorCfg::new().set_layout(&LgpRegisterLayout::new(1, 1));
+    let _ = LgpState::try_from(LgpProgram::asm("load r0, 1", 1, 1, &[0])?)?;
+    let _ = lgp_fitness_evolver(lgpcfg.clone(), EvolveCfg::new(4), |s: &LgpState, (): &()| {
+        Ok(s.ops_opt().len() as f64 + 1.0)
+    });
+    let f = |_: &LgpState, (): &()| Ok(1.0);
+    let _ = lgp_fitness_reproducible_evolver(lgpcfg.clone(), EvolveCfg::new(4), f, 1);
+    let f = |_: &LgpState, (): &(), _: &std::sync::atomic::AtomicBool| Ok(1.0);
+    lgp_traits(f);
+    let _ = lgp_create_evolver(lgpcfg, EvolveCfg::new(4), |evaluator| {
+        LgpFitnessFnEvaluator::new(evaluator, f)
+    });
+    Ok(())
+}
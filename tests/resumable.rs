@@ -0,0 +1,217 @@
+#![cfg(feature = "serde")]
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use eyre::{eyre, Result};
+use memega::gen::snapshot::SpeciesSnapshot;
+use memega::ops::crossover::crossover_ux_rng;
+use memega::ops::mutation::mutate_normal_rng;
+use memega::prelude::*;
+use memega::train::sampler::EmptyDataSampler;
+use memega::train::sink::{Metric, MetricSink};
+use pretty_assertions::assert_eq;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+
+#[derive(Debug, Clone, PartialEq, PartialOrd, serde::Serialize, serde::Deserialize)]
+struct Point(Vec<f64>);
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+// Minimises the distance to the origin, making every random choice with the
+// evolver's rng so seeded runs are reproducible. Fails once it has made
+// |budget| fitness evaluations, to simulate a crash.
+struct SphereEvaluator {
+    budget: usize,
+    evals: AtomicUsize,
+}
+
+impl Evaluator for SphereEvaluator {
+    type State = Point;
+
+    fn crossover(&self, s1: &mut Point, s2: &mut Point, idx: usize) {
+        self.crossover_rng(s1, s2, idx, &mut rand::thread_rng());
+    }
+
+    fn mutate(&self, s: &mut Point, rate: f64, idx: usize) {
+        self.mutate_rng(s, rate, idx, &mut rand::thread_rng());
+    }
+
+    fn crossover_rng(&self, s1: &mut Point, s2: &mut Point, _: usize, r: &mut dyn RngCore) {
+        crossover_ux_rng(&mut s1.0, &mut s2.0, r);
+    }
+
+    fn mutate_rng(&self, s: &mut Point, rate: f64, _: usize, r: &mut dyn RngCore) {
+        for v in &mut s.0 {
+            if r.gen::<f64>() < rate {
+                *v = mutate_normal_rng(*v, 0.5, r);
+            }
+        }
+    }
+
+    fn fitness(&self, s: &Point, _data: &()) -> Result<f64> {
+        if self.evals.fetch_add(1, Ordering::Relaxed) >= self.budget {
+            return Err(eyre!("crashed"));
+        }
+        Ok(1.0 / (1.0 + s.0.iter().map(|v| v * v).sum::<f64>()))
+    }
+
+    fn distance(&self, s1: &Point, s2: &Point) -> Result<f64> {
+        Ok(s1.0.iter().zip(&s2.0).map(|(a, b)| (a - b) * (a - b)).sum::<f64>().sqrt())
+    }
+}
+
+fn evolver() -> Evolver<SphereEvaluator> {
+    crashing_evolver(usize::MAX)
+}
+
+fn crashing_evolver(budget: usize) -> Evolver<SphereEvaluator> {
+    let cfg = EvolveCfg::new(20)
+        .set_species(Species::TargetNumber(2))
+        .set_species_snapshots(true)
+        .set_par_fitness(false);
+    let mut r = StdRng::seed_from_u64(2);
+    let rand_state = move || Point((0..4).map(|_| r.gen_range(-5.0..5.0)).collect());
+    let eval = SphereEvaluator { budget, evals: AtomicUsize::new(0) };
+    Evolver::new_seeded(eval, cfg, rand_state, 1)
+}
+
+fn mems(r: &EvolveResult<Point>) -> Vec<(Point, f64)> {
+    r.gen.mems.iter().map(|v| ((*v.state).clone(), v.fitness)).collect()
+}
+
+#[test]
+fn resume_matches_uninterrupted() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("memega-resumable-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let checkpoint = dir.join("checkpoint.json");
+    let species = dir.join("species.jsonl");
+    let cfg = |gens| {
+        TrainerCfg::new("resumable")
+            .set_termination(Termination::FixedGenerations(gens))
+            .set_species_path(&species)
+    };
+    let sampler = EmptyDataSampler {};
+
+    // Train for 10 generations, then "crash" by dropping the trainer. Records
+    // written after the last checkpoint and a partly written checkpoint must
+    // be ignored on resuming.
+    let r = Trainer::new(cfg(10)).train_resumable(evolver, &sampler, &checkpoint, 4)?;
+    assert_eq!(r.unevaluated.gen_idx, 9);
+    OpenOptions::new().append(true).open(&species)?.write_all(b"{\"gen\":10,\"radi")?;
+    fs::write(dir.join("checkpoint.json.tmp"), "{\"evolver\":")?;
+
+    let resumed = Trainer::new(cfg(20)).train_resumable(evolver, &sampler, &checkpoint, 4)?;
+    assert_eq!(resumed.unevaluated.gen_idx, 19);
+    let gens = fs::read_to_string(&species)?
+        .lines()
+        .map(|v| Ok(serde_json::from_str::<SpeciesSnapshot>(v)?.gen))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(gens, (0..20).collect::<Vec<_>>());
+
+    let full = Trainer::new(cfg(20)).train(evolver(), &sampler)?;
+    assert_eq!(mems(&resumed), mems(&full));
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn parallel_resume_matches_uninterrupted() -> Result<()> {
+    let checkpoint =
+        std::env::temp_dir().join(format!("memega-resumable-parallel-{}.json", std::process::id()));
+    let _ = fs::remove_file(&checkpoint);
+    let cfg =
+        |gens| TrainerCfg::new("resumable").set_termination(Termination::FixedGenerations(gens));
+    let evolvers = || vec![("a".to_owned(), evolver()), ("b".to_owned(), evolver())];
+    let sampler = EmptyDataSampler {};
+
+    let r = Trainer::new(cfg(10)).train_parallel_resumable(evolvers, &sampler, &checkpoint, 4)?;
+    assert_eq!(r.get("a").unwrap().unevaluated.gen_idx, 9);
+    let resumed =
+        Trainer::new(cfg(20)).train_parallel_resumable(evolvers, &sampler, &checkpoint, 4)?;
+    let full = Trainer::new(cfg(20)).train_parallel(evolvers(), &sampler)?;
+    for label in ["a", "b"] {
+        assert_eq!(resumed.get(label).unwrap().unevaluated.gen_idx, 19);
+        assert_eq!(mems(resumed.get(label).unwrap()), mems(full.get(label).unwrap()));
+    }
+    assert_eq!(resumed.report, full.report);
+
+    // The evolvers must match the checkpoint's.
+    let renamed = || vec![("a".to_owned(), evolver()), ("c".to_owned(), evolver())];
+    assert!(Trainer::new(cfg(30))
+        .train_parallel_resumable(renamed, &sampler, &checkpoint, 4)
+        .is_err());
+    fs::remove_file(&checkpoint)?;
+    Ok(())
+}
+
+#[test]
+fn resume_keeps_evaluation_budget() -> Result<()> {
+    let checkpoint =
+        std::env::temp_dir().join(format!("memega-resumable-budget-{}.json", std::process::id()));
+    let _ = fs::remove_file(&checkpoint);
+    let budget = TrainerCfg::new("resumable").set_termination(Termination::FitnessEvaluations(300));
+    let sampler = EmptyDataSampler {};
+    let full = Trainer::new(budget.clone()).train(evolver(), &sampler)?;
+    assert!(full.unevaluated.gen_idx > 10);
+
+    // Evaluations made before the checkpoint count towards the budget after
+    // resuming, so training stops at the same generation.
+    let cfg = TrainerCfg::new("resumable").set_termination(Termination::FixedGenerations(10));
+    let _ = Trainer::new(cfg).train_resumable(evolver, &sampler, &checkpoint, 4)?;
+    let resumed = Trainer::new(budget).train_resumable(evolver, &sampler, &checkpoint, 4)?;
+    assert_eq!(resumed.unevaluated.gen_idx, full.unevaluated.gen_idx);
+    assert_eq!(mems(&resumed), mems(&full));
+    fs::remove_file(&checkpoint)?;
+    Ok(())
+}
+
+// Collects the steps training fitness is reported for.
+struct StepSink(Arc<Mutex<Vec<usize>>>);
+
+impl MetricSink for StepSink {
+    fn write(&mut self, metric: Metric) -> Result<()> {
+        if let Metric::Scalars { tag, step, .. } = metric && tag == "fitness" {
+            self.0.lock().unwrap().push(step);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn resume_reports_metrics_once() -> Result<()> {
+    let checkpoint =
+        std::env::temp_dir().join(format!("memega-resumable-metrics-{}.json", std::process::id()));
+    let _ = fs::remove_file(&checkpoint);
+    let steps = Arc::new(Mutex::new(Vec::new()));
+    let trainer = || {
+        let cfg = TrainerCfg::new("resumable")
+            .set_termination(Termination::FixedGenerations(20))
+            .set_report_gen(1)
+            .set_metric_queue(None);
+        Trainer::new(cfg).set_metric_sink(StepSink(Arc::clone(&steps)))
+    };
+
+    // Crash part way between two checkpoints, then resume. Generations run
+    // again after resuming must only be reported once.
+    let sampler = EmptyDataSampler {};
+    let crashed = trainer().train_resumable(|| crashing_evolver(210), &sampler, &checkpoint, 4);
+    assert!(crashed.is_err());
+    assert!(steps.lock().unwrap().len() % 4 == 0);
+    let _ = trainer().train_resumable(evolver, &sampler, &checkpoint, 4)?;
+    assert_eq!(*steps.lock().unwrap(), (0..20).collect::<Vec<_>>());
+    fs::remove_file(&checkpoint)?;
+    Ok(())
+}